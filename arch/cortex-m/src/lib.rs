@@ -474,6 +474,10 @@ pub unsafe extern "C" fn hard_fault_handler_arm_v7m() {
         ldr r2, [r1, #36]       /* BFAR */
         str r2, [r0, #16]
 
+        /* Clear the CFSR, so the next fault only reports its own causes. */
+        ldr r2, [r0, #4]
+        str r2, [r1, #20]
+
         ldr r0, =APP_HARD_FAULT /* Global variable address */
         mov r1, #1              /* r1 = 1 */
         str r1, [r0, #0]        /* APP_HARD_FAULT = 1 */
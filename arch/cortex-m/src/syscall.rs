@@ -3,6 +3,7 @@
 
 use core::fmt::Write;
 use core::ptr::{read_volatile, write_volatile};
+use kernel::crash_record::{Architecture, Registers};
 
 /// This is used in the syscall handler. When set to 1 this means the
 /// svc_handler was called. Marked `pub` because it is used in the cortex-m*
@@ -269,4 +270,56 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
             },
         ));
     }
+
+    unsafe fn store_context(
+        &self,
+        stack_pointer: *const usize,
+        state: &CortexMStoredState,
+    ) -> Registers {
+        let mut registers = Registers::new(Architecture::CortexM);
+        // r0-r3 were pushed by the hardware, r4-r11 are in the stored state.
+        for i in 0..4 {
+            registers.push(read_volatile(stack_pointer.offset(i)));
+        }
+        for &value in state.regs.iter() {
+            registers.push(value);
+        }
+        // r12, then sp, lr, pc and xpsr.
+        registers.push(read_volatile(stack_pointer.offset(4)));
+        registers.push(stack_pointer as usize);
+        for i in 5..8 {
+            registers.push(read_volatile(stack_pointer.offset(i)));
+        }
+        registers.push(state.yield_pc);
+        // CFSR, HFSR, MMFAR and BFAR, as saved by the hard fault handler.
+        for &value in SCB_REGISTERS[1..].iter() {
+            registers.push(value as usize);
+        }
+        registers
+    }
+
+    unsafe fn fault_address(
+        &self,
+        stack_pointer: *const usize,
+        _state: &CortexMStoredState,
+    ) -> Option<usize> {
+        let cfsr = SCB_REGISTERS[1];
+        let mmarvalid = (cfsr & 0x80) == 0x80;
+        let bfarvalid = ((cfsr >> 8) & 0x80) == 0x80;
+        let mstkerr = (cfsr & 0x10) == 0x10;
+        let stkerr = ((cfsr >> 8) & 0x10) == 0x10;
+
+        if mmarvalid {
+            Some(SCB_REGISTERS[3] as usize)
+        } else if bfarvalid {
+            Some(SCB_REGISTERS[4] as usize)
+        } else if mstkerr || stkerr {
+            // The hardware could not push the exception frame, and does not
+            // record the address for that. The stack pointer still moved to
+            // the start of the frame it tried to push.
+            Some(stack_pointer as usize)
+        } else {
+            None
+        }
+    }
 }
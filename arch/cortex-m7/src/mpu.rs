@@ -160,6 +160,10 @@ pub struct CortexMConfig {
 
 const APP_MEMORY_REGION_NUM: usize = 0;
 
+/// The guard region below the stack must override the app memory region, so
+/// it uses the highest numbered region every Cortex-M MPU has.
+const STACK_GUARD_REGION_NUM: usize = 7;
+
 impl Default for CortexMConfig {
     fn default() -> CortexMConfig {
         CortexMConfig {
@@ -243,7 +247,7 @@ impl fmt::Display for CortexMConfig {
 impl CortexMConfig {
     fn unused_region_number(&self) -> Option<usize> {
         for (number, region) in self.regions.iter().enumerate() {
-            if number == APP_MEMORY_REGION_NUM {
+            if number == APP_MEMORY_REGION_NUM || number == STACK_GUARD_REGION_NUM {
                 continue;
             }
             if let None = region.location() {
@@ -326,6 +330,26 @@ impl CortexMRegion {
         }
     }
 
+    /// A region that unprivileged code cannot read, write or execute.
+    /// `start` must be aligned to `size`, which must be a power of two of at
+    /// least 32 bytes.
+    fn guard(start: *const u8, size: usize, region_num: usize) -> CortexMRegion {
+        let base_address = RegionBaseAddress::ADDR.val((start as u32) >> 5)
+            + RegionBaseAddress::VALID::UseRBAR
+            + RegionBaseAddress::REGION.val(region_num as u32);
+
+        let attributes = RegionAttributes::ENABLE::SET
+            + RegionAttributes::SIZE.val(math::log_base_two(size as u32) - 1)
+            + RegionAttributes::AP::PrivilegedOnly
+            + RegionAttributes::XN::Disable;
+
+        CortexMRegion {
+            location: Some((start, size)),
+            base_address,
+            attributes,
+        }
+    }
+
     fn empty(region_num: usize) -> CortexMRegion {
         CortexMRegion {
             location: None,
@@ -687,6 +711,37 @@ impl kernel::mpu::MPU for MPU {
         Ok(())
     }
 
+    fn allocate_stack_guard(
+        &self,
+        stack_bottom: *const u8,
+        max_guard_size: usize,
+        config: &mut Self::MpuConfig,
+    ) -> Option<mpu::Region> {
+        let lowest_start = (stack_bottom as usize).checked_sub(max_guard_size)?;
+
+        // The guard cannot use subregions, as it has to override them in the
+        // app memory region. It therefore must be at least 32 bytes and
+        // aligned to its size, so it ends at the closest 32 byte boundary
+        // below the stack, and grows down as far as the alignment of that
+        // boundary and `max_guard_size` allow. A larger guard catches stack
+        // frames that skip over a small one.
+        let end = (stack_bottom as usize) & !31;
+        let mut size: usize = 32;
+        if end < lowest_start + size {
+            return None;
+        }
+        while size < (1 << 31) && end % (size * 2) == 0 && end - size * 2 >= lowest_start {
+            size *= 2;
+        }
+        let start = end - size;
+
+        config.regions[STACK_GUARD_REGION_NUM] =
+            CortexMRegion::guard(start as *const u8, size, STACK_GUARD_REGION_NUM);
+        config.is_dirty.set(true);
+
+        Some(mpu::Region::new(start as *const u8, size))
+    }
+
     fn configure_mpu(&self, config: &Self::MpuConfig, app_id: &AppId) {
         // If the hardware is already configured for this app and the app's MPU
         // configuration has not changed, then skip the hardware update.
@@ -17,13 +17,18 @@
 //! address must be aligned to the size, which results in wasted memory. To
 //! avoid this wasted memory we use TOR and each memory region uses two physical
 //! PMP regions.
+//!
+//! The first two PMP entries are reserved for the guard region below the
+//! process stack. PMP entries are matched in order, so the guard has to come
+//! before the app memory region it overlaps.
 
 /// Instantiate a PMP configuration.
 ///
 /// `$x` is the number of PMP entries the hardware supports.
 ///
 /// Since we use TOR, we will use two PMP entries for each region. So the actual
-/// number of regions we can protect is `$x/2`.
+/// number of regions we can protect is `$x/2`, one of which is the stack
+/// guard.
 #[macro_export]
 macro_rules! PMPConfigMacro {
     ( $x:expr $(,)? ) => {
@@ -102,6 +107,14 @@ impl fmt::Display for PMPRegion {
 }
 
 impl PMPRegion {
+    /// A region the process cannot read, write or execute.
+    fn guard(start: *const u8, size: usize) -> PMPRegion {
+        PMPRegion {
+            location: (start, size),
+            cfg: pmpcfg::r::CLEAR + pmpcfg::w::CLEAR + pmpcfg::x::CLEAR + pmpcfg::a::TOR,
+        }
+    }
+
     fn new(start: *const u8, size: usize, permissions: mpu::Permissions) -> PMPRegion {
         // Determine access and execute permissions
         let pmpcfg = match permissions {
@@ -161,6 +174,9 @@ pub struct PMPConfig {
     is_dirty: Cell<bool>,
     /// Which region index is used for app memory (if it has been configured).
     app_memory_region: OptionalCell<usize>,
+    /// The region below the process stack the process cannot access, if it
+    /// has been configured. This always uses the first two PMP entries.
+    stack_guard: Option<PMPRegion>,
 }
 
 impl Default for PMPConfig {
@@ -169,6 +185,7 @@ impl Default for PMPConfig {
             regions: [None; $x / 2],
             is_dirty: Cell::new(true),
             app_memory_region: OptionalCell::empty(),
+            stack_guard: None,
         }
     }
 }
@@ -176,6 +193,9 @@ impl Default for PMPConfig {
 impl fmt::Display for PMPConfig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, " PMP regions:\r\n")?;
+        if let Some(guard) = self.stack_guard {
+            write!(f, "  [stack guard]: {}\r\n", guard)?;
+        }
         for (n, region) in self.regions.iter().enumerate() {
             match region {
                 None => write!(f, "  <unset>\r\n")?,
@@ -188,7 +208,8 @@ impl fmt::Display for PMPConfig {
 
 impl PMPConfig {
     fn unused_region_number(&self) -> Option<usize> {
-        for (number, region) in self.regions.iter().enumerate() {
+        // The entries of the last region are taken by the stack guard.
+        for (number, region) in self.regions.iter().enumerate().take($x / 2 - 1) {
             if self.app_memory_region.contains(&number) {
                 continue;
             }
@@ -468,6 +489,28 @@ impl kernel::mpu::MPU for PMP {
         Ok(())
     }
 
+    fn allocate_stack_guard(
+        &self,
+        stack_bottom: *const u8,
+        max_guard_size: usize,
+        config: &mut Self::MpuConfig,
+    ) -> Option<mpu::Region> {
+        let lowest_start = (stack_bottom as usize).checked_sub(max_guard_size)?;
+
+        // TOR regions only need to be aligned to 4 bytes, so the guard can
+        // cover all of the memory it is allowed to.
+        let end = (stack_bottom as usize) & !3;
+        let start = (lowest_start + 3) & !3;
+        if start >= end {
+            return None;
+        }
+
+        config.stack_guard = Some(PMPRegion::guard(start as *const u8, end - start));
+        config.is_dirty.set(true);
+
+        Some(mpu::Region::new(start as *const u8, end - start))
+    }
+
     fn configure_mpu(&self, config: &Self::MpuConfig, app_id: &AppId) {
         // Is the PMP already configured for this app?
         let last_configured_for_this_app = self
@@ -477,7 +520,51 @@ impl kernel::mpu::MPU for PMP {
         // Skip PMP configuration if it is already configured for this app and the MPU
         // configuration of this app has not changed.
         if !last_configured_for_this_app || config.is_dirty.get() {
-            for (x, region) in config.regions.iter().enumerate() {
+            // The stack guard comes first so that it overrides the app memory
+            // region. Unlike for the other regions, its first entry is off
+            // and only marks where the guard starts, as the memory below the
+            // guard may belong to a later region.
+            match config.stack_guard {
+                Some(guard) => {
+                    let start = guard.location.0 as usize;
+                    let size = guard.location.1;
+
+                    csr::CSR.pmpcfg[0].modify(
+                        csr::pmpconfig::pmpcfg::r0::CLEAR
+                            + csr::pmpconfig::pmpcfg::w0::CLEAR
+                            + csr::pmpconfig::pmpcfg::x0::CLEAR
+                            + csr::pmpconfig::pmpcfg::a0::OFF
+                            + csr::pmpconfig::pmpcfg::r1::CLEAR
+                            + csr::pmpconfig::pmpcfg::w1::CLEAR
+                            + csr::pmpconfig::pmpcfg::x1::CLEAR
+                            + csr::pmpconfig::pmpcfg::a1::TOR,
+                    );
+                    csr::CSR.pmpaddr[0].set((start as u32) >> 2);
+                    csr::CSR.pmpaddr[1].set((start as u32 + size as u32) >> 2);
+                }
+                None => {
+                    csr::CSR.pmpcfg[0].modify(
+                        csr::pmpconfig::pmpcfg::r0::CLEAR
+                            + csr::pmpconfig::pmpcfg::w0::CLEAR
+                            + csr::pmpconfig::pmpcfg::x0::CLEAR
+                            + csr::pmpconfig::pmpcfg::a0::OFF
+                            + csr::pmpconfig::pmpcfg::r1::CLEAR
+                            + csr::pmpconfig::pmpcfg::w1::CLEAR
+                            + csr::pmpconfig::pmpcfg::x1::CLEAR
+                            + csr::pmpconfig::pmpcfg::a1::OFF,
+                    );
+                    csr::CSR.pmpaddr[0].set(0);
+                    csr::CSR.pmpaddr[1].set(0);
+                }
+            }
+
+            // The other regions follow the stack guard.
+            for (x, region) in config
+                .regions
+                .iter()
+                .enumerate()
+                .map(|(index, region)| (index + 1, region))
+            {
                 match region {
                     Some(r) => {
                         let cfg_val = r.cfg.value as u32;
@@ -1,6 +1,7 @@
 //! Kernel-userland system call interface for RISC-V architecture.
 
 use core::fmt::Write;
+use kernel::crash_record::{Architecture, Registers};
 
 use crate::csr::mcause;
 use kernel;
@@ -454,4 +455,32 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
             state.mtval,
         ));
     }
+
+    unsafe fn store_context(
+        &self,
+        _stack_pointer: *const usize,
+        state: &RiscvimacStoredState,
+    ) -> Registers {
+        let mut registers = Registers::new(Architecture::RiscV32);
+        for &value in state.regs.iter() {
+            registers.push(value);
+        }
+        registers.push(state.pc);
+        registers.push(state.mcause);
+        registers.push(state.mtval);
+        registers
+    }
+
+    unsafe fn fault_address(
+        &self,
+        _stack_pointer: *const usize,
+        state: &RiscvimacStoredState,
+    ) -> Option<usize> {
+        // For access faults mtval holds the address that was accessed.
+        match mcause::Trap::from(state.mcause as u32) {
+            mcause::Trap::Exception(mcause::Exception::LoadFault)
+            | mcause::Trap::Exception(mcause::Exception::StoreFault) => Some(state.mtval),
+            _ => None,
+        }
+    }
 }
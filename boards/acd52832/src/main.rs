@@ -455,6 +455,7 @@ pub unsafe fn reset_handler() {
     base_peripherals.clock.high_start();
     while !base_peripherals.clock.low_started() {}
     while !base_peripherals.clock.high_started() {}
+    nrf52_components::NrfSleepComponent::new(board_kernel, base_peripherals).finalize(());
 
     let platform = Platform {
         button: button,
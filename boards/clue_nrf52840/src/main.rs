@@ -507,6 +507,10 @@ pub unsafe fn reset_handler() {
     // Start all of the clocks. Low power operation will require a better
     // approach than this.
     nrf52_components::NrfClockComponent::new(&base_peripherals.clock).finalize(());
+    nrf52_components::NrfSleepComponent::new(board_kernel, base_peripherals).finalize(());
+    board_kernel
+        .power_manager()
+        .register(nrf52840_peripherals.usbd.sleep_constraint());
 
     let platform = Platform {
         ble_radio: ble_radio,
@@ -0,0 +1,175 @@
+//! Component to initialize the userland CoAP driver.
+//!
+//! This provides one Component, CoAPComponent. This component initializes a
+//! userspace CoAP driver that allows apps to act as CoAP clients and servers
+//! over the UDP stack set up by `UDPMuxComponent`. The driver sends its
+//! messages through a UDP sender of its own on the UDP send mux, and receives
+//! them with a UDP receiver on the UDP receive mux, both bound to the CoAP
+//! port.
+//!
+//! The port table only binds ports once the UDP driver is set up, so the UDP
+//! driver must be initialized first.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let coap_driver = CoAPComponent::new(
+//!        board_kernel,
+//!        udp_send_mux,
+//!        udp_recv_mux,
+//!        udp_port_table,
+//!        local_ip_ifaces,
+//!        mux_alarm,
+//!    )
+//!    .finalize(components::coap_component_helper!(sam4l::ast::Ast));
+//! ```
+
+use capsules::net::coap::{CoAPDriver, COAP_PORT};
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::udp::udp_port_table::UdpPortManager;
+use capsules::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+use kernel::{create_capability, static_init, static_init_half};
+
+const UDP_HDR_SIZE: usize = 8;
+const MAX_MESSAGE_LEN: usize = super::udp_mux::MAX_PAYLOAD_LEN - UDP_HDR_SIZE;
+
+static mut TX_BUF: [u8; MAX_MESSAGE_LEN] = [0; MAX_MESSAGE_LEN];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! coap_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::net::coap::CoAPDriver;
+        use capsules::net::ipv6::ipv6_send::IP6SendStruct;
+        use capsules::net::udp::udp_recv::UDPReceiver;
+        use capsules::net::udp::udp_send::UDPSendStruct;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, $A>>>,
+        > = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<UDPReceiver<'static>> = MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<CoAPDriver<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3)
+    };};
+}
+
+pub struct CoAPComponent<A: Alarm<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    udp_send_mux:
+        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    interface_list: &'static [IPAddr],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static> CoAPComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        interface_list: &'static [IPAddr],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            udp_send_mux,
+            udp_recv_mux,
+            port_table,
+            interface_list,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for CoAPComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<UDPReceiver<'static>>,
+        &'static mut MaybeUninit<CoAPDriver<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static CoAPDriver<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+
+        let coap_virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+        let udp_send = static_init_half!(
+            static_buffer.1,
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+            UDPSendStruct::new(self.udp_send_mux, udp_vis)
+        );
+        let udp_recv = static_init_half!(static_buffer.2, UDPReceiver<'static>, UDPReceiver::new());
+        self.udp_recv_mux.add_client(udp_recv);
+
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+        let socket = self
+            .port_table
+            .create_socket()
+            .expect("no UDP socket available for CoAP");
+        let (send_binding, recv_binding) = match self.port_table.bind(socket, COAP_PORT, net_cap) {
+            Ok(bindings) => bindings,
+            Err(_) => panic!("CoAP port already bound"),
+        };
+        udp_send.set_binding(send_binding);
+        udp_recv.set_binding(recv_binding);
+
+        // Message IDs and tokens must differ between devices
+        let seed = self.interface_list.iter().fold(0u32, |seed, addr| {
+            addr.0
+                .iter()
+                .fold(seed, |seed, byte| seed.rotate_left(5) ^ *byte as u32)
+        });
+
+        let coap_driver = static_init_half!(
+            static_buffer.3,
+            CoAPDriver<'static, VirtualMuxAlarm<'static, A>>,
+            CoAPDriver::new(
+                udp_send,
+                coap_virtual_alarm,
+                self.board_kernel.create_grant(&grant_cap),
+                kernel::common::leasable_buffer::LeasableBuffer::new(&mut TX_BUF),
+                seed,
+                net_cap,
+            )
+        );
+        udp_send.set_client(coap_driver);
+        udp_recv.set_client(coap_driver);
+        coap_virtual_alarm.set_alarm_client(coap_driver);
+
+        coap_driver
+    }
+}
@@ -0,0 +1,106 @@
+//! Component for keeping crash records in flash.
+//!
+//! This provides one component, CrashLogComponent, which stores a record of
+//! every kernel panic in a ring of flash pages, and provides a system call
+//! interface to read them out. The component also makes the log the crash
+//! store of the kernel, and starts looking for the records of earlier
+//! crashes.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! kernel::storage_volume!(CRASH_LOG, 8);
+//!
+//! let crash_log = components::crash_log::CrashLogComponent::new(
+//!     board_kernel,
+//!     &base_peripherals.nvmc,
+//!     CRASH_LOG.as_ptr() as usize / nrf52840::nvmc::PAGE_SIZE,
+//!     CRASH_LOG.len() / nrf52840::nvmc::PAGE_SIZE,
+//! )
+//! .finalize(components::crash_log_component_helper!(nrf52840::nvmc::Nvmc));
+//! pconsole.set_crash_log(crash_log);
+//! ```
+
+use capsules::crash_log::CrashLog;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! crash_log_component_helper {
+    ($F:ty $(,)?) => {{
+        use capsules::crash_log::CrashLog;
+        use core::mem::MaybeUninit;
+        use kernel::hil;
+        static mut BUF1: MaybeUninit<<$F as hil::flash::Flash>::Page> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<CrashLog<'static, $F>> = MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct CrashLogComponent<
+    F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, CrashLog<'static, F>>,
+> {
+    board_kernel: &'static kernel::Kernel,
+    flash: &'static F,
+    first_page: usize,
+    pages: usize,
+}
+
+impl<F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, CrashLog<'static, F>>>
+    CrashLogComponent<F>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        flash: &'static F,
+        first_page: usize,
+        pages: usize,
+    ) -> Self {
+        Self {
+            board_kernel,
+            flash,
+            first_page,
+            pages,
+        }
+    }
+}
+
+impl<F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, CrashLog<'static, F>>>
+    Component for CrashLogComponent<F>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<<F as hil::flash::Flash>::Page>,
+        &'static mut MaybeUninit<CrashLog<'static, F>>,
+    );
+    type Output = &'static CrashLog<'static, F>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let pagebuffer = static_init_half!(
+            static_buffer.0,
+            <F as hil::flash::Flash>::Page,
+            <F as hil::flash::Flash>::Page::default()
+        );
+
+        let crash_log = static_init_half!(
+            static_buffer.1,
+            CrashLog<'static, F>,
+            CrashLog::new(
+                self.flash,
+                pagebuffer,
+                self.first_page,
+                self.pages,
+                self.board_kernel.create_grant(&grant_cap)
+            )
+        );
+        hil::flash::HasClient::set_client(self.flash, crash_log);
+        kernel::crash_record::set_crash_store(crash_log);
+        crash_log.start();
+        crash_log
+    }
+}
@@ -0,0 +1,143 @@
+//! Components for the date and time.
+//!
+//! This provides two components:
+//!
+//! - `CounterDateTimeComponent` builds a software date and time clock on a
+//!   hardware counter, for chips without a calendar RTC.
+//! - `DateTimeComponent` provides the date and time driver to userspace,
+//!   on top of any `hil::date_time::DateTime` clock. The last argument of
+//!   `new` says whether processes may set the clock.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let date_time = components::date_time::CounterDateTimeComponent::new(&base_peripherals.rtc)
+//!     .finalize(components::counter_date_time_component_helper!(nrf52840::rtc::Rtc));
+//! let date_time_driver =
+//!     components::date_time::DateTimeComponent::new(board_kernel, mux_alarm, date_time, true)
+//!         .finalize(components::date_time_component_helper!(
+//!             capsules::counter_date_time::CounterDateTime<'static, nrf52840::rtc::Rtc>,
+//!             nrf52840::rtc::Rtc
+//!         ));
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::counter_date_time::CounterDateTime;
+use capsules::date_time::DateTimeDriver;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::date_time::DateTime;
+use kernel::hil::time::{self, Alarm};
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! counter_date_time_component_helper {
+    ($C:ty $(,)?) => {{
+        use capsules::counter_date_time::CounterDateTime;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<CounterDateTime<'static, $C>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+#[macro_export]
+macro_rules! date_time_component_helper {
+    ($D:ty, $A:ty $(,)?) => {{
+        use capsules::date_time::DateTimeDriver;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<DateTimeDriver<'static, $D, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct CounterDateTimeComponent<C: 'static + time::Counter<'static>> {
+    counter: &'static C,
+}
+
+impl<C: 'static + time::Counter<'static>> CounterDateTimeComponent<C> {
+    pub fn new(counter: &'static C) -> CounterDateTimeComponent<C> {
+        CounterDateTimeComponent { counter }
+    }
+}
+
+impl<C: 'static + time::Counter<'static>> Component for CounterDateTimeComponent<C> {
+    type StaticInput = &'static mut MaybeUninit<CounterDateTime<'static, C>>;
+    type Output = &'static CounterDateTime<'static, C>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let date_time = static_init_half!(
+            static_buffer,
+            CounterDateTime<'static, C>,
+            CounterDateTime::new(self.counter)
+        );
+
+        self.counter.set_overflow_client(date_time);
+        if !self.counter.is_running() {
+            self.counter.start();
+        }
+        date_time
+    }
+}
+
+pub struct DateTimeComponent<D: 'static + DateTime, A: 'static + time::Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    date_time: &'static D,
+    allow_set: bool,
+}
+
+impl<D: 'static + DateTime, A: 'static + time::Alarm<'static>> DateTimeComponent<D, A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        date_time: &'static D,
+        allow_set: bool,
+    ) -> DateTimeComponent<D, A> {
+        DateTimeComponent {
+            board_kernel,
+            alarm_mux,
+            date_time,
+            allow_set,
+        }
+    }
+}
+
+impl<D: 'static + DateTime, A: 'static + time::Alarm<'static>> Component
+    for DateTimeComponent<D, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<DateTimeDriver<'static, D, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static DateTimeDriver<'static, D, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let date_time_driver = static_init_half!(
+            static_buffer.1,
+            DateTimeDriver<'static, D, VirtualMuxAlarm<'static, A>>,
+            DateTimeDriver::new(
+                self.date_time,
+                virtual_alarm,
+                self.board_kernel.create_grant(&grant_cap),
+                self.allow_set
+            )
+        );
+
+        virtual_alarm.set_alarm_client(date_time_driver);
+        date_time_driver
+    }
+}
@@ -0,0 +1,67 @@
+//! Component for the FAT filesystem.
+//!
+//! This provides one Component, FatFsComponent, which mounts a FAT volume
+//! of a block storage device, such as an `SDCardBlockStorage` or a disk image
+//! of the posix chip, and provides a system call interface to its files.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let fat = components::fat::FatFsComponent::new(board_kernel, &peripherals.disk)
+//!     .finalize(components::fat_component_helper!(posix::disk::Disk<'static>));
+//! ```
+
+use capsules::fat::FatFs;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::block_storage::BlockStorage;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! fat_component_helper {
+    ($B:ty $(,)?) => {{
+        use capsules::fat::FatFs;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<FatFs<'static, $B>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+pub struct FatFsComponent<B: 'static + BlockStorage<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    device: &'static B,
+}
+
+impl<B: 'static + BlockStorage<'static>> FatFsComponent<B> {
+    pub fn new(board_kernel: &'static kernel::Kernel, device: &'static B) -> FatFsComponent<B> {
+        FatFsComponent {
+            board_kernel,
+            device,
+        }
+    }
+}
+
+impl<B: 'static + BlockStorage<'static>> Component for FatFsComponent<B> {
+    type StaticInput = &'static mut MaybeUninit<FatFs<'static, B>>;
+    type Output = &'static FatFs<'static, B>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let fat = static_init_half!(
+            s,
+            FatFs<'static, B>,
+            FatFs::new(
+                self.device,
+                &mut capsules::fat::CACHE_BUFFERS,
+                self.board_kernel.create_grant(&grant_cap)
+            )
+        );
+        self.device.set_client(fat);
+
+        fat
+    }
+}
@@ -0,0 +1,53 @@
+//! Component for the graphics capsule.
+//!
+//! The transfer buffer holds the tiles the screen is rendered in: a larger
+//! buffer takes fewer bus writes, but the graphics capsule renders well with
+//! a few rows of the screen. Its size is set with the
+//! `components::screen_buffer_size` macro.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let graphics = components::graphics::GraphicsComponent::new(board_kernel, tft)
+//!     .finalize(components::screen_buffer_size!(7680));
+//! ```
+use capsules::graphics::driver::Graphics;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::static_init;
+
+pub struct GraphicsComponent {
+    board_kernel: &'static kernel::Kernel,
+    screen: &'static dyn kernel::hil::screen::Screen,
+}
+
+impl GraphicsComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        screen: &'static dyn kernel::hil::screen::Screen,
+    ) -> GraphicsComponent {
+        GraphicsComponent {
+            board_kernel,
+            screen,
+        }
+    }
+}
+
+impl Component for GraphicsComponent {
+    type StaticInput = &'static mut [u8];
+    type Output = &'static Graphics<'static>;
+
+    unsafe fn finalize(self, static_input: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let grant_graphics = self.board_kernel.create_grant(&grant_cap);
+
+        let graphics = static_init!(
+            Graphics<'static>,
+            Graphics::new(self.screen, static_input, grant_graphics)
+        );
+        kernel::hil::screen::Screen::set_client(self.screen, Some(graphics));
+
+        graphics
+    }
+}
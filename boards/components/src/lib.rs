@@ -4,17 +4,23 @@ pub mod adc;
 pub mod alarm;
 pub mod analog_comparator;
 pub mod app_flash_driver;
+pub mod app_watchdog;
 pub mod bus;
 pub mod button;
 pub mod cdc;
+pub mod coap;
 pub mod console;
+pub mod crash_log;
 pub mod crc;
 pub mod ctap;
+pub mod date_time;
 pub mod debug_queue;
 pub mod debug_writer;
+pub mod fat;
 pub mod ft6x06;
 pub mod fxos8700;
 pub mod gpio;
+pub mod graphics;
 pub mod hd44780;
 pub mod hmac;
 pub mod humidity;
@@ -27,6 +33,7 @@ pub mod led_matrix;
 pub mod lldb;
 pub mod lsm303agr;
 pub mod lsm303dlhc;
+pub mod message_ipc;
 pub mod mlx90614;
 pub mod mx25r6435f;
 pub mod ninedof;
@@ -35,17 +42,23 @@ pub mod nrf51822;
 pub mod panic_button;
 pub mod process_console;
 pub mod rng;
+pub mod rpl;
 pub mod sched;
 pub mod screen;
 pub mod segger_rtt;
+pub mod sha_software;
 pub mod sht3x;
 pub mod si7021;
 pub mod spi;
 pub mod st77xx;
+pub mod tcp_driver;
 pub mod temperature;
 pub mod temperature_stm;
 pub mod test;
 pub mod text_screen;
+pub mod thread;
 pub mod touch;
 pub mod udp_driver;
 pub mod udp_mux;
+pub mod udp_mux_ethernet;
+pub mod usb_msc;
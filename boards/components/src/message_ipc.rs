@@ -0,0 +1,41 @@
+//! Component for message-passing IPC.
+//!
+//! This provides one Component, MessageIpcComponent, which provides the
+//! MessageIpc driver, through which processes register named services and
+//! exchange messages with them.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let message_ipc = MessageIpcComponent::new(board_kernel).finalize(());
+//! ```
+
+use capsules::message_ipc::MessageIpc;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::static_init;
+
+pub struct MessageIpcComponent {
+    board_kernel: &'static kernel::Kernel,
+}
+
+impl MessageIpcComponent {
+    pub fn new(board_kernel: &'static kernel::Kernel) -> MessageIpcComponent {
+        MessageIpcComponent { board_kernel }
+    }
+}
+
+impl Component for MessageIpcComponent {
+    type StaticInput = ();
+    type Output = &'static MessageIpc;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        static_init!(
+            MessageIpc,
+            MessageIpc::new(self.board_kernel.create_grant(&grant_cap))
+        )
+    }
+}
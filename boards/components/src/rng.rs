@@ -1,18 +1,29 @@
 //! Component for random number generator using `Entropy32ToRandom`.
 //!
-//! This provides one Component, RngComponent, which implements a
-//! userspace syscall interface to the RNG peripheral (TRNG).
+//! This provides three Components:
+//!
+//! - RngComponent implements a userspace syscall interface to the RNG
+//!   peripheral (TRNG), for boards where userspace is its only user.
+//! - RngMuxComponent shares the RNG between several users in the kernel.
+//! - VirtualRngComponent implements the userspace syscall interface on top of
+//!   an RngMuxComponent.
 //!
 //! Usage
 //! -----
 //! ```rust
 //! let rng = components::rng::RngComponent::new(board_kernel, &sam4l::trng::TRNG).finalize(());
 //! ```
+//!
+//! ```rust
+//! let rng_mux = components::rng::RngMuxComponent::new(&sam4l::trng::TRNG).finalize(());
+//! let rng = components::rng::VirtualRngComponent::new(board_kernel, rng_mux).finalize(());
+//! ```
 
 // Author: Hudson Ayers <hayers@cs.stanford.edu>
 // Last modified: 07/12/2019
 
 use capsules::rng;
+use capsules::virtual_rng::{MuxRngMaster, VirtualRngMasterDevice};
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::create_capability;
@@ -61,3 +72,64 @@ impl Component for RngComponent {
         rng
     }
 }
+
+pub struct RngMuxComponent {
+    trng: &'static dyn Entropy32<'static>,
+}
+
+impl RngMuxComponent {
+    pub fn new(trng: &'static dyn Entropy32<'static>) -> RngMuxComponent {
+        RngMuxComponent { trng }
+    }
+}
+
+impl Component for RngMuxComponent {
+    type StaticInput = ();
+    type Output = &'static MuxRngMaster<'static>;
+
+    unsafe fn finalize(self, _static_buffer: Self::StaticInput) -> Self::Output {
+        let entropy_to_random = static_init!(
+            rng::Entropy32ToRandom<'static>,
+            rng::Entropy32ToRandom::new(self.trng)
+        );
+        let mux = static_init!(MuxRngMaster<'static>, MuxRngMaster::new(entropy_to_random));
+        self.trng.set_client(entropy_to_random);
+
+        mux
+    }
+}
+
+pub struct VirtualRngComponent {
+    board_kernel: &'static kernel::Kernel,
+    mux: &'static MuxRngMaster<'static>,
+}
+
+impl VirtualRngComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        mux: &'static MuxRngMaster<'static>,
+    ) -> VirtualRngComponent {
+        VirtualRngComponent { board_kernel, mux }
+    }
+}
+
+impl Component for VirtualRngComponent {
+    type StaticInput = ();
+    type Output = &'static rng::RngDriver<'static>;
+
+    unsafe fn finalize(self, _static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let virtual_rng = static_init!(
+            VirtualRngMasterDevice<'static>,
+            VirtualRngMasterDevice::new(self.mux)
+        );
+        let rng = static_init!(
+            rng::RngDriver<'static>,
+            rng::RngDriver::new(virtual_rng, self.board_kernel.create_grant(&grant_cap))
+        );
+        virtual_rng.set_client(rng);
+
+        rng
+    }
+}
@@ -0,0 +1,263 @@
+//! Component to initialize the RPL mesh router.
+//!
+//! This provides one Component, RplComponent. This component initializes an
+//! RPL router for the 6LoWPAN stack set up by `UDPMuxComponent`. The router
+//! sends its control messages and forwards packets with an IP sender of its
+//! own, on a separate MAC user, and receives them from the IP receiver
+//! returned by `UDPMuxComponent`. The board must make the router the
+//! `IP6Router` of the other IP senders, and start it.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let rpl_router = RplComponent::new(
+//!        mux_mac,
+//!        ip_receive,
+//!        DEFAULT_CTX_PREFIX_LEN,
+//!        DEFAULT_CTX_PREFIX,
+//!        src_mac_from_serial_num,
+//!        local_ip_ifaces,
+//!        mux_alarm,
+//!    )
+//!    .finalize(components::rpl_component_helper!(sam4l::ast::Ast));
+//!    udp_send_mux.set_router(rpl_router);
+//!    rpl_router.start();
+//! ```
+
+use capsules::ieee802154::device::MacDevice;
+use capsules::net::icmpv6::icmpv6_send::{ICMP6SendStruct, ICMP6Sender};
+use capsules::net::icmpv6::{ICMP6Header, ICMP6Type};
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
+};
+use capsules::net::rpl::rpl_router::{RplRouter, RPL_BUF_LEN};
+use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil::radio;
+use kernel::hil::time::Alarm;
+use kernel::{create_capability, static_init, static_init_half};
+
+// The RPL router requires its own packet buffers, as it sends through its
+// own IP sender:
+//
+//   1. RADIO_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
+//   2. IP_PAYLOAD: The payload of the IP6_Packet, which holds control messages and
+//      forwarded packets before they are tx'd
+//   3. RPL_BUF: Buffer the router builds control messages in
+
+static mut RADIO_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+
+pub const MAX_FORWARDED_LEN: usize = 400; //The max size of a forwarded packet, after the IPv6 header
+static mut IP_PAYLOAD: [u8; MAX_FORWARDED_LEN] = [0; MAX_FORWARDED_LEN];
+static mut RPL_BUF: [u8; RPL_BUF_LEN] = [0; RPL_BUF_LEN];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! rpl_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules;
+        use capsules::net::icmpv6::icmpv6_send::ICMP6SendStruct;
+        use capsules::net::rpl::rpl_router::RplRouter;
+        use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>> =
+            MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, $A>,
+                sixlowpan_compression::Context,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, $A>>,
+        > = MaybeUninit::uninit();
+        static mut BUF5: MaybeUninit<
+            ICMP6SendStruct<
+                'static,
+                capsules::net::ipv6::ipv6_send::IP6SendStruct<
+                    'static,
+                    VirtualMuxAlarm<'static, $A>,
+                >,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF6: MaybeUninit<RplRouter<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (
+            &mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5, &mut BUF6,
+        )
+    };};
+}
+
+pub struct RplComponent<A: Alarm<'static> + 'static> {
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    ip_receive: &'static IP6RecvStruct<'static>,
+    ctx_pfix_len: u8,
+    ctx_pfix: [u8; 16],
+    src_mac_addr: MacAddress,
+    interface_list: &'static [IPAddr],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static> RplComponent<A> {
+    pub fn new(
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        ip_receive: &'static IP6RecvStruct<'static>,
+        ctx_pfix_len: u8,
+        ctx_pfix: [u8; 16],
+        src_mac_addr: MacAddress,
+        interface_list: &'static [IPAddr],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            mux_mac,
+            ip_receive,
+            ctx_pfix_len,
+            ctx_pfix,
+            src_mac_addr,
+            interface_list,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for RplComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>>,
+        &'static mut MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+        >,
+        &'static mut MaybeUninit<IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<
+            ICMP6SendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<RplRouter<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static RplRouter<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+
+        let ipsender_virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let rpl_virtual_alarm = static_init_half!(
+            static_buffer.1,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        // Packets are only sent with this MAC user; they are received by
+        // the MAC user of the UDP stack, which passes them to `ip_receive`.
+        let rpl_mac = static_init_half!(
+            static_buffer.2,
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(rpl_mac);
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+
+        let sixlowpan = static_init_half!(
+            static_buffer.3,
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+            sixlowpan_state::Sixlowpan::new(
+                sixlowpan_compression::Context {
+                    prefix: self.ctx_pfix,
+                    prefix_len: self.ctx_pfix_len,
+                    id: 0,
+                    compress: false,
+                },
+                ipsender_virtual_alarm, // OK to reuse bc only used to get time, not set alarms
+            )
+        );
+        let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
+        let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
+
+        let tr_hdr = TransportHeader::ICMP(ICMP6Header::new(ICMP6Type::Type155));
+        let ip_pyld: IPPayload = IPPayload {
+            header: tr_hdr,
+            payload: &mut IP_PAYLOAD,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        // Every packet is sent to the next hop chosen by the router, so the
+        // gateway is never used
+        let ip_send = static_init_half!(
+            static_buffer.4,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+            IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut RADIO_BUF,
+                sixlowpan_tx,
+                rpl_mac,
+                MacAddress::Short(0xffff),
+                self.src_mac_addr,
+                ip_vis,
+            )
+        );
+        ipsender_virtual_alarm.set_alarm_client(ip_send);
+        // Control messages are sent from the link local address
+        ip_send.set_addr(IPAddr::generate_from_mac(self.src_mac_addr));
+        rpl_mac.set_transmit_client(ip_send);
+
+        let icmp_send = static_init_half!(
+            static_buffer.5,
+            ICMP6SendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+            ICMP6SendStruct::new(ip_send)
+        );
+        ip_send.set_client(icmp_send);
+
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let rpl_router = static_init_half!(
+            static_buffer.6,
+            RplRouter<'static, VirtualMuxAlarm<'static, A>>,
+            RplRouter::new(
+                rpl_virtual_alarm,
+                icmp_send,
+                ip_send,
+                self.src_mac_addr,
+                self.interface_list,
+                &mut RPL_BUF,
+                net_cap,
+            )
+        );
+        icmp_send.set_client(rpl_router);
+        rpl_virtual_alarm.set_alarm_client(rpl_router);
+        ip_send.set_router(rpl_router);
+        self.ip_receive.set_icmp_client(rpl_router);
+        self.ip_receive.set_forwarder(rpl_router);
+
+        rpl_router
+    }
+}
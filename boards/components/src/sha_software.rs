@@ -0,0 +1,66 @@
+//! Component for the software implementation of `hil::digest`.
+//!
+//! This provides one Component, ShaSoftwareComponent, which creates a
+//! `ShaSoftware` engine for the digest type `T` and registers it for deferred
+//! calls. It can be used like a hardware digest peripheral, for example as
+//! the device underneath `HmacMuxComponent` on boards without hash hardware.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let sha = components::sha_software::ShaSoftwareComponent::new(dynamic_deferred_caller)
+//!        .finalize(components::sha_software_component_helper!([u8; 32]));
+//! ```
+
+use capsules::sha_software::ShaSoftware;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::component::Component;
+use kernel::hil::digest;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! sha_software_component_helper {
+    ($T:ty $(,)?) => {{
+        use capsules::sha_software::ShaSoftware;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<ShaSoftware<'static, $T>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+pub struct ShaSoftwareComponent<T: 'static + digest::DigestType> {
+    deferred_caller: &'static DynamicDeferredCall,
+    phantom: PhantomData<&'static T>,
+}
+
+impl<T: 'static + digest::DigestType> ShaSoftwareComponent<T> {
+    pub fn new(deferred_caller: &'static DynamicDeferredCall) -> ShaSoftwareComponent<T> {
+        ShaSoftwareComponent {
+            deferred_caller,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T: 'static + digest::DigestType> Component for ShaSoftwareComponent<T> {
+    type StaticInput = &'static mut MaybeUninit<ShaSoftware<'static, T>>;
+    type Output = &'static ShaSoftware<'static, T>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let sha = static_init_half!(
+            s,
+            ShaSoftware<'static, T>,
+            ShaSoftware::new(self.deferred_caller)
+        );
+        sha.initialize_callback_handle(
+            self.deferred_caller
+                .register(sha)
+                .expect("no deferred call slot available for sha"),
+        );
+
+        sha
+    }
+}
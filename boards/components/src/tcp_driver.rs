@@ -0,0 +1,259 @@
+//! Component to initialize the userland TCP driver.
+//!
+//! This provides one Component, TCPDriverComponent. This component
+//! initializes a userspace TCP driver that allows apps to open TCP
+//! connections over the 6LoWPAN stack set up by `UDPMuxComponent`. TCP
+//! segments are sent with an IP sender of their own, on a separate MAC user,
+//! and are received from the IP receiver returned by `UDPMuxComponent`. The
+//! driver takes the key for its initial sequence numbers from the RNG mux.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let tcp_driver = TCPDriverComponent::new(
+//!        board_kernel,
+//!        mux_mac,
+//!        ip_receive,
+//!        DEFAULT_CTX_PREFIX_LEN,
+//!        DEFAULT_CTX_PREFIX,
+//!        DST_MAC_ADDR,
+//!        src_mac_from_serial_num,
+//!        local_ip_ifaces,
+//!        mux_alarm,
+//!        rng_mux,
+//!    )
+//!    .finalize(components::tcp_driver_component_helper!(sam4l::ast::Ast));
+//! ```
+
+use capsules::ieee802154::device::MacDevice;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
+};
+use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules::net::tcp::{TCPDriver, TCPHeader, TCP_HDR_LEN};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules::virtual_rng::{MuxRngMaster, VirtualRngMasterDevice};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil::radio;
+use kernel::hil::rng::Rng;
+use kernel::hil::time::Alarm;
+use kernel::{create_capability, static_init, static_init_half};
+
+// The TCP driver requires its own packet buffers, as it sends through its
+// own IP sender:
+//
+//   1. RADIO_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
+//   2. TCP_SEGMENT: The payload of the IP6_Packet, which holds a segment before it is tx'd
+//   3. DRIVER_BUF: Buffer the driver copies segment payloads from apps into
+
+static mut RADIO_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+
+pub const MAX_SEGMENT_LEN: usize = 200; //The max size TCP segment, including its header
+static mut TCP_SEGMENT: [u8; MAX_SEGMENT_LEN - TCP_HDR_LEN] = [0; MAX_SEGMENT_LEN - TCP_HDR_LEN];
+static mut DRIVER_BUF: [u8; MAX_SEGMENT_LEN - TCP_HDR_LEN] = [0; MAX_SEGMENT_LEN - TCP_HDR_LEN];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! tcp_driver_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules;
+        use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+        use capsules::net::tcp::TCPDriver;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>> =
+            MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, $A>,
+                sixlowpan_compression::Context,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, $A>>,
+        > = MaybeUninit::uninit();
+        static mut BUF5: MaybeUninit<TCPDriver<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF6: MaybeUninit<capsules::virtual_rng::VirtualRngMasterDevice<'static>> =
+            MaybeUninit::uninit();
+        (
+            &mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5, &mut BUF6,
+        )
+    };};
+}
+
+pub struct TCPDriverComponent<A: Alarm<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    ip_receive: &'static IP6RecvStruct<'static>,
+    ctx_pfix_len: u8,
+    ctx_pfix: [u8; 16],
+    dst_mac_addr: MacAddress,
+    src_mac_addr: MacAddress,
+    interface_list: &'static [IPAddr],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    rng_mux: &'static MuxRngMaster<'static>,
+}
+
+impl<A: Alarm<'static> + 'static> TCPDriverComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        ip_receive: &'static IP6RecvStruct<'static>,
+        ctx_pfix_len: u8,
+        ctx_pfix: [u8; 16],
+        dst_mac_addr: MacAddress,
+        src_mac_addr: MacAddress,
+        interface_list: &'static [IPAddr],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        rng_mux: &'static MuxRngMaster<'static>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            mux_mac,
+            ip_receive,
+            ctx_pfix_len,
+            ctx_pfix,
+            dst_mac_addr,
+            src_mac_addr,
+            interface_list,
+            alarm_mux,
+            rng_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for TCPDriverComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>>,
+        &'static mut MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+        >,
+        &'static mut MaybeUninit<IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<TCPDriver<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<VirtualRngMasterDevice<'static>>,
+    );
+    type Output = &'static TCPDriver<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+
+        let ipsender_virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let tcp_virtual_alarm = static_init_half!(
+            static_buffer.1,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        // Segments are only sent with this MAC user; they are received by
+        // the MAC user of the UDP stack, which passes them to `ip_receive`.
+        let tcp_mac = static_init_half!(
+            static_buffer.2,
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(tcp_mac);
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+
+        let sixlowpan = static_init_half!(
+            static_buffer.3,
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+            sixlowpan_state::Sixlowpan::new(
+                sixlowpan_compression::Context {
+                    prefix: self.ctx_pfix,
+                    prefix_len: self.ctx_pfix_len,
+                    id: 0,
+                    compress: false,
+                },
+                ipsender_virtual_alarm, // OK to reuse bc only used to get time, not set alarms
+            )
+        );
+        let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
+        let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
+
+        let tr_hdr = TransportHeader::TCP(TCPHeader::new());
+        let ip_pyld: IPPayload = IPPayload {
+            header: tr_hdr,
+            payload: &mut TCP_SEGMENT,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let ip_send = static_init_half!(
+            static_buffer.4,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+            IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut RADIO_BUF,
+                sixlowpan_tx,
+                tcp_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr,
+                ip_vis,
+            )
+        );
+        ipsender_virtual_alarm.set_alarm_client(ip_send);
+        ip_send.set_addr(self.interface_list[0]);
+        tcp_mac.set_transmit_client(ip_send);
+
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let tcp_rng = static_init_half!(
+            static_buffer.6,
+            VirtualRngMasterDevice<'static>,
+            VirtualRngMasterDevice::new(self.rng_mux)
+        );
+
+        let tcp_driver = static_init_half!(
+            static_buffer.5,
+            TCPDriver<'static, VirtualMuxAlarm<'static, A>>,
+            TCPDriver::new(
+                ip_send,
+                tcp_virtual_alarm,
+                tcp_rng,
+                self.board_kernel.create_grant(&grant_cap),
+                self.interface_list,
+                kernel::common::leasable_buffer::LeasableBuffer::new(&mut DRIVER_BUF),
+                net_cap,
+            )
+        );
+        ip_send.set_client(tcp_driver);
+        tcp_virtual_alarm.set_alarm_client(tcp_driver);
+        self.ip_receive.set_tcp_client(tcp_driver);
+        tcp_rng.set_client(tcp_driver);
+        tcp_driver.start();
+
+        tcp_driver
+    }
+}
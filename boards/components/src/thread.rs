@@ -0,0 +1,396 @@
+//! Component to initialize a Thread child.
+//!
+//! This provides one Component, ThreadChildComponent. This component
+//! initializes a Thread sleepy end device for the 6LoWPAN stack set up by
+//! `UDPMuxComponent`. The child sends its MLE messages with an IP sender of
+//! its own, from the link local address derived from its extended address,
+//! and receives them from the UDP receive mux returned by `UDPMuxComponent`.
+//! It polls its parent with a separate MAC user, needs a dedicated
+//! HMAC-SHA256 engine to derive the keys of the network, and takes its
+//! challenges from the RNG mux.
+//!
+//! The child supplies the 802.15.4 keys and the frame counters of its parent,
+//! so the board must install it as the kernel key and device procedure of the
+//! radio driver. The outgoing frame counters of the child are those of the
+//! radio driver, which must be given frame counter storage so that they are
+//! never reused after a reboot. The board also routes the traffic of the UDP
+//! stack through the child, which secures it with the MAC key, sets the
+//! channel and the PAN ID of the network on the radio, and the master key
+//! before starting the child.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let sha = components::sha_software::ShaSoftwareComponent::new(dynamic_deferred_caller)
+//!        .finalize(components::sha_software_component_helper!([u8; 32]));
+//!    let thread_child = components::thread::ThreadChildComponent::new(
+//!        mux_mac,
+//!        aes_mux,
+//!        sha,
+//!        udp_recv_mux,
+//!        udp_port_table,
+//!        ext_addr,
+//!        DEFAULT_CTX_PREFIX_LEN,
+//!        DEFAULT_CTX_PREFIX,
+//!        mux_alarm,
+//!        rng_mux,
+//!    )
+//!    .finalize(components::thread_child_component_helper!(
+//!        nrf52840::rtc::Rtc,
+//!        nrf52840::aes::AesECB,
+//!        capsules::sha_software::ShaSoftware<'static, [u8; 32]>,
+//!    ));
+//!    radio_driver.set_kernel_key_procedure(thread_child);
+//!    radio_driver.set_kernel_device_procedure(thread_child);
+//!    thread_child.set_frame_counters(radio_driver);
+//!    udp_send_mux.set_router(thread_child);
+//!    udp_send_mux.set_link_security(thread_child);
+//!    thread_child.set_master_key(MASTER_KEY, 0);
+//!    thread_child.start();
+//! ```
+
+use capsules::ieee802154::device::MacDevice;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules::net::thread::mle::{KEY_INPUT_LEN, MLE_PORT};
+use capsules::net::thread::thread_child::{
+    ThreadChild, CRYPT_BUF_LEN, MAX_NETWORK_DATA_LEN, TX_BUF_LEN,
+};
+use capsules::net::udp::udp_port_table::UdpPortManager;
+use capsules::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules::net::udp::UDPHeader;
+use capsules::virtual_aes_ccm::{MuxAES128CCM, VirtualAES128CCM};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules::virtual_rng::{MuxRngMaster, VirtualRngMasterDevice};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil::digest;
+use kernel::hil::radio;
+use kernel::hil::rng::Rng;
+use kernel::hil::symmetric_encryption::{self, AES128Ctr, AES128, AES128CBC, AES128CCM};
+use kernel::hil::time::Alarm;
+use kernel::{create_capability, static_init, static_init_half};
+
+// The Thread child requires its own buffers:
+//
+//   1. RADIO_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
+//   2. UDP_DGRAM: The payload of the IP6_Packet, which holds MLE messages before they are tx'd
+//   3. TX_BUF: Buffer secured MLE messages are passed to the UDP sender in
+//   4. CRYPT_BUF: Buffer MLE messages are encrypted and decrypted in
+//   5. CCM_BUF: Buffer the AES-CCM virtualizer uses, which also holds padding
+//   6. POLL_BUF: Buffer the MAC Data Requests are sent from
+
+static mut RADIO_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut UDP_DGRAM: [u8; TX_BUF_LEN] = [0; TX_BUF_LEN];
+static mut TX_BUF: [u8; TX_BUF_LEN] = [0; TX_BUF_LEN];
+static mut CRYPT_BUF: [u8; CRYPT_BUF_LEN] = [0; CRYPT_BUF_LEN];
+const CCM_BUF_LEN: usize = 3 * symmetric_encryption::AES128_BLOCK_SIZE + CRYPT_BUF_LEN;
+static mut CCM_BUF: [u8; CCM_BUF_LEN] = [0; CCM_BUF_LEN];
+static mut POLL_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut KEY_INPUT: [u8; KEY_INPUT_LEN] = [0; KEY_INPUT_LEN];
+static mut DIGEST: [u8; 32] = [0; 32];
+static mut NETWORK_DATA: [u8; MAX_NETWORK_DATA_LEN] = [0; MAX_NETWORK_DATA_LEN];
+
+type ThreadChildType<A, AES, H> =
+    ThreadChild<'static, VirtualMuxAlarm<'static, A>, H, VirtualAES128CCM<'static, AES>>;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! thread_child_component_helper {
+    ($A:ty, $AES:ty, $H:ty $(,)?) => {{
+        use capsules;
+        use capsules::net::ipv6::ipv6_send::IP6SendStruct;
+        use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+        use capsules::net::thread::thread_child::ThreadChild;
+        use capsules::net::udp::udp_recv::UDPReceiver;
+        use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct};
+        use capsules::virtual_aes_ccm::VirtualAES128CCM;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>> =
+            MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>> =
+            MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, $A>,
+                sixlowpan_compression::Context,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF5: MaybeUninit<IP6SendStruct<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF6: MaybeUninit<
+            MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, $A>>>,
+        > = MaybeUninit::uninit();
+        static mut BUF7: MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, $A>>>,
+        > = MaybeUninit::uninit();
+        static mut BUF8: MaybeUninit<UDPReceiver<'static>> = MaybeUninit::uninit();
+        static mut BUF9: MaybeUninit<VirtualAES128CCM<'static, $AES>> = MaybeUninit::uninit();
+        static mut BUF10: MaybeUninit<
+            ThreadChild<'static, VirtualMuxAlarm<'static, $A>, $H, VirtualAES128CCM<'static, $AES>>,
+        > = MaybeUninit::uninit();
+        static mut BUF11: MaybeUninit<capsules::virtual_rng::VirtualRngMasterDevice<'static>> =
+            MaybeUninit::uninit();
+        (
+            &mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5, &mut BUF6, &mut BUF7,
+            &mut BUF8, &mut BUF9, &mut BUF10, &mut BUF11,
+        )
+    };};
+}
+
+pub struct ThreadChildComponent<
+    A: Alarm<'static> + 'static,
+    AES: AES128<'static> + AES128Ctr + AES128CBC + 'static,
+    H: digest::Digest<'static, [u8; 32]> + digest::HMACSha256 + 'static,
+> {
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    aes_mux: &'static MuxAES128CCM<'static, AES>,
+    hmac: &'static H,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    ext_addr: [u8; 8],
+    ctx_pfix_len: u8,
+    ctx_pfix: [u8; 16],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    rng_mux: &'static MuxRngMaster<'static>,
+}
+
+impl<
+        A: Alarm<'static> + 'static,
+        AES: AES128<'static> + AES128Ctr + AES128CBC + 'static,
+        H: digest::Digest<'static, [u8; 32]> + digest::HMACSha256 + 'static,
+    > ThreadChildComponent<A, AES, H>
+{
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        aes_mux: &'static MuxAES128CCM<'static, AES>,
+        hmac: &'static H,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        ext_addr: [u8; 8],
+        ctx_pfix_len: u8,
+        ctx_pfix: [u8; 16],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        rng_mux: &'static MuxRngMaster<'static>,
+    ) -> Self {
+        Self {
+            mux_mac,
+            aes_mux,
+            hmac,
+            udp_recv_mux,
+            port_table,
+            ext_addr,
+            ctx_pfix_len,
+            ctx_pfix,
+            alarm_mux,
+            rng_mux,
+        }
+    }
+}
+
+impl<
+        A: Alarm<'static> + 'static,
+        AES: AES128<'static> + AES128Ctr + AES128CBC + 'static,
+        H: digest::Digest<'static, [u8; 32]> + digest::HMACSha256 + 'static,
+    > Component for ThreadChildComponent<A, AES, H>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>>,
+        &'static mut MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>>,
+        &'static mut MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+        >,
+        &'static mut MaybeUninit<IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<
+            MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<UDPReceiver<'static>>,
+        &'static mut MaybeUninit<VirtualAES128CCM<'static, AES>>,
+        &'static mut MaybeUninit<ThreadChildType<A, AES, H>>,
+        &'static mut MaybeUninit<VirtualRngMasterDevice<'static>>,
+    );
+    type Output = &'static ThreadChildType<A, AES, H>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let src_mac_addr = MacAddress::Long(self.ext_addr);
+
+        let ipsender_virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let thread_virtual_alarm = static_init_half!(
+            static_buffer.1,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        // MLE messages are only sent with this MAC user; they are received by
+        // the MAC user of the UDP stack, which passes them to `udp_recv_mux`.
+        let thread_mac = static_init_half!(
+            static_buffer.2,
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(thread_mac);
+        let poll_mac = static_init_half!(
+            static_buffer.3,
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(poll_mac);
+
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+
+        let sixlowpan = static_init_half!(
+            static_buffer.4,
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+            sixlowpan_state::Sixlowpan::new(
+                sixlowpan_compression::Context {
+                    prefix: self.ctx_pfix,
+                    prefix_len: self.ctx_pfix_len,
+                    id: 0,
+                    compress: false,
+                },
+                ipsender_virtual_alarm, // OK to reuse bc only used to get time, not set alarms
+            )
+        );
+        let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
+        let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
+
+        let tr_hdr = TransportHeader::UDP(UDPHeader::new());
+        let ip_pyld: IPPayload = IPPayload {
+            header: tr_hdr,
+            payload: &mut UDP_DGRAM,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        // Every packet is sent to the next hop chosen by the child, so the
+        // gateway is never used
+        let ip_send = static_init_half!(
+            static_buffer.5,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+            IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut RADIO_BUF,
+                sixlowpan_tx,
+                thread_mac,
+                MacAddress::Short(0xffff),
+                src_mac_addr,
+                ip_vis,
+            )
+        );
+        ipsender_virtual_alarm.set_alarm_client(ip_send);
+        // MLE messages are sent from the link local address
+        ip_send.set_addr(IPAddr::generate_from_mac(src_mac_addr));
+        thread_mac.set_transmit_client(ip_send);
+
+        let udp_send_mux = static_init_half!(
+            static_buffer.6,
+            MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+            MuxUdpSender::new(ip_send)
+        );
+        ip_send.set_client(udp_send_mux);
+        let udp_send = static_init_half!(
+            static_buffer.7,
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+            UDPSendStruct::new(udp_send_mux, udp_vis)
+        );
+        let udp_recv = static_init_half!(static_buffer.8, UDPReceiver<'static>, UDPReceiver::new());
+        self.udp_recv_mux.add_client(udp_recv);
+
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+        let socket = self
+            .port_table
+            .create_socket()
+            .expect("no UDP socket available for MLE");
+        let (send_binding, recv_binding) = match self.port_table.bind(socket, MLE_PORT, net_cap) {
+            Ok(bindings) => bindings,
+            Err(_) => panic!("MLE port already bound"),
+        };
+        udp_send.set_binding(send_binding);
+        udp_recv.set_binding(recv_binding);
+
+        let aes_ccm = static_init_half!(
+            static_buffer.9,
+            VirtualAES128CCM<'static, AES>,
+            VirtualAES128CCM::new(self.aes_mux, &mut CCM_BUF)
+        );
+        aes_ccm.setup();
+
+        let thread_rng = static_init_half!(
+            static_buffer.11,
+            VirtualRngMasterDevice<'static>,
+            VirtualRngMasterDevice::new(self.rng_mux)
+        );
+
+        let thread_child = static_init_half!(
+            static_buffer.10,
+            ThreadChildType<A, AES, H>,
+            ThreadChild::new(
+                thread_virtual_alarm,
+                udp_send,
+                poll_mac,
+                self.hmac,
+                aes_ccm,
+                thread_rng,
+                self.ext_addr,
+                &mut TX_BUF,
+                &mut CRYPT_BUF,
+                &mut KEY_INPUT,
+                &mut DIGEST,
+                &mut POLL_BUF,
+                &mut NETWORK_DATA,
+                net_cap,
+            )
+        );
+        thread_virtual_alarm.set_alarm_client(thread_child);
+        ip_send.set_router(thread_child);
+        udp_send.set_client(thread_child);
+        udp_recv.set_client(thread_child);
+        poll_mac.set_transmit_client(thread_child);
+        aes_ccm.set_client(thread_child);
+        self.hmac.set_client(thread_child);
+        thread_rng.set_client(thread_child);
+
+        thread_child
+    }
+}
@@ -3,6 +3,11 @@
 //! This provides one Component, UDPDriverComponent. This component initializes a userspace
 //! UDP driver that allows apps to use the UDP stack.
 //!
+//! The driver can be used on top of the MuxUdpSender of either `UDPMuxComponent`
+//! (6LoWPAN) or `UDPMuxEthernetComponent` (Ethernet), using
+//! `udp_driver_component_helper` or `udp_driver_ethernet_component_helper`
+//! respectively.
+//!
 //! Usage
 //! -----
 //! ```rust
@@ -19,7 +24,7 @@
 
 use capsules;
 use capsules::net::ipv6::ip_utils::IPAddr;
-use capsules::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules::net::ipv6::ipv6_send::IP6Sender;
 use capsules::net::network_capabilities::{
     AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
 };
@@ -27,13 +32,11 @@ use capsules::net::udp::udp_port_table::UdpPortManager;
 use capsules::net::udp::udp_recv::MuxUdpReceiver;
 use capsules::net::udp::udp_recv::UDPReceiver;
 use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
-use capsules::virtual_alarm::VirtualMuxAlarm;
 use core::mem::MaybeUninit;
 use kernel;
 use kernel::capabilities;
 use kernel::capabilities::NetworkCapabilityCreationCapability;
 use kernel::component::Component;
-use kernel::hil::time::Alarm;
 use kernel::{create_capability, static_init, static_init_half};
 
 const UDP_HDR_SIZE: usize = 8;
@@ -60,22 +63,38 @@ macro_rules! udp_driver_component_helper {
     };};
 }
 
-pub struct UDPDriverComponent<A: Alarm<'static> + 'static> {
+// Setup static space for the objects, when the driver is used over Ethernet.
+#[macro_export]
+macro_rules! udp_driver_ethernet_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::net::udp::udp_send::UDPSendStruct;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<
+            UDPSendStruct<
+                'static,
+                capsules::net::ipv6::ipv6_ethernet::IP6EthernetStruct<
+                    'static,
+                    VirtualMuxAlarm<'static, $A>,
+                >,
+            >,
+        > = MaybeUninit::uninit();
+        (&mut BUF0,)
+    };};
+}
+
+pub struct UDPDriverComponent<T: IP6Sender<'static> + 'static> {
     board_kernel: &'static kernel::Kernel,
-    udp_send_mux:
-        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    udp_send_mux: &'static MuxUdpSender<'static, T>,
     udp_recv_mux: &'static MuxUdpReceiver<'static>,
     port_table: &'static UdpPortManager,
     interface_list: &'static [IPAddr],
 }
 
-impl<A: Alarm<'static>> UDPDriverComponent<A> {
+impl<T: IP6Sender<'static>> UDPDriverComponent<T> {
     pub fn new(
         board_kernel: &'static kernel::Kernel,
-        udp_send_mux: &'static MuxUdpSender<
-            'static,
-            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
-        >,
+        udp_send_mux: &'static MuxUdpSender<'static, T>,
         udp_recv_mux: &'static MuxUdpReceiver<'static>,
         port_table: &'static UdpPortManager,
         interface_list: &'static [IPAddr],
@@ -90,15 +109,8 @@ impl<A: Alarm<'static>> UDPDriverComponent<A> {
     }
 }
 
-impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
-    type StaticInput = (
-        &'static mut MaybeUninit<
-            UDPSendStruct<
-                'static,
-                capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
-            >,
-        >,
-    );
+impl<T: IP6Sender<'static>> Component for UDPDriverComponent<T> {
+    type StaticInput = (&'static mut MaybeUninit<UDPSendStruct<'static, T>>,);
     type Output = &'static capsules::net::udp::UDPDriver<'static>;
 
     unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
@@ -111,10 +123,7 @@ impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
         );
         let udp_send = static_init_half!(
             static_buffer.0,
-            UDPSendStruct<
-                'static,
-                capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
-            >,
+            UDPSendStruct<'static, T>,
             UDPSendStruct::new(self.udp_send_mux, udp_vis)
         );
 
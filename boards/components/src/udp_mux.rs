@@ -2,12 +2,14 @@
 //!
 //! This provides one Component, UDPMuxComponent. This component
 //! exposes a MuxUdpSender that other components can implement
-//! UDPSenders on top of to use the UDP/6Lowpan stack.
+//! UDPSenders on top of to use the UDP/6Lowpan stack. It also exposes
+//! the IP6 receiver, which other transport layers (such as TCP) can
+//! register as clients of.
 //!
 //! Usage
 //! -----
 //! ```rust
-//!    let (udp_mux, udp_recv) = UDPMuxComponent::new(
+//!    let (udp_mux, udp_recv, udp_port_table, ip_receive) = UDPMuxComponent::new(
 //!        mux_mac,
 //!        DEFAULT_CTX_PREFIX_LEN,
 //!        DEFAULT_CTX_PREFIX,
@@ -171,6 +173,7 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
         &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
         &'static MuxUdpReceiver<'static>,
         &'static UdpPortManager,
+        &'static capsules::net::ipv6::ipv6_recv::IP6RecvStruct<'static>,
     );
 
     unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
@@ -284,6 +287,6 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
             UdpPortManager::new(&create_table_cap, &mut USED_KERNEL_PORTS, udp_vis)
         );
 
-        (udp_send_mux, udp_recv_mux, udp_port_table)
+        (udp_send_mux, udp_recv_mux, udp_port_table, ip_receive)
     }
 }
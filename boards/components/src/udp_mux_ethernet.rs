@@ -0,0 +1,181 @@
+//! Component to initialize the udp/Ethernet interface.
+//!
+//! This provides one Component, UDPMuxEthernetComponent. Like
+//! `UDPMuxComponent`, this component exposes a MuxUdpSender that other
+//! components can implement UDPSenders on top of, but it sends and receives
+//! IPv6 packets over an Ethernet adapter rather than over 6LoWPAN. It also
+//! exposes the IP6 receiver, which other transport layers can register as
+//! clients of.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let (udp_send_mux, udp_recv_mux, udp_port_table, ip_receive) =
+//!        UDPMuxEthernetComponent::new(
+//!            ethmac0,
+//!            ETHERNET_MAC_ADDR,
+//!            local_ip_ifaces,
+//!            mux_alarm,
+//!        )
+//!        .finalize(components::udp_mux_ethernet_component_helper!(LiteXAlarm));
+//! ```
+
+use capsules::net::ethernet::{EthernetAddress, ETHERNET_HDR_LEN, ETHERNET_MTU};
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_ethernet::IP6EthernetStruct;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::network_capabilities::{IpVisibilityCapability, UdpVisibilityCapability};
+use capsules::net::udp::udp_port_table::{SocketBindingEntry, UdpPortManager, MAX_NUM_BOUND_PORTS};
+use capsules::net::udp::udp_recv::MuxUdpReceiver;
+use capsules::net::udp::udp_send::MuxUdpSender;
+use capsules::net::udp::UDPHeader;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::ethernet::EthernetAdapter;
+use kernel::hil::time::Alarm;
+use kernel::{static_init, static_init_half};
+
+// The UDP stack over Ethernet requires two packet buffers:
+//
+//   1. ETHERNET_TX_BUF: buffer the IP6_Sender uses to pass frames to the Ethernet adapter
+//   2. UDP_DGRAM: The payload of the IP6_Packet, which holds full IP Packets before they are tx'd.
+//
+//   Received frames are read directly from the buffer of the Ethernet adapter.
+
+static mut ETHERNET_TX_BUF: [u8; ETHERNET_HDR_LEN + ETHERNET_MTU] =
+    [0x00; ETHERNET_HDR_LEN + ETHERNET_MTU];
+
+const MAX_PAYLOAD_LEN: usize = super::udp_mux::MAX_PAYLOAD_LEN;
+const UDP_HDR_SIZE: usize = 8;
+static mut UDP_DGRAM: [u8; MAX_PAYLOAD_LEN - UDP_HDR_SIZE] = [0; MAX_PAYLOAD_LEN - UDP_HDR_SIZE];
+
+// See `udp_mux.rs` for a description of the port table.
+static mut USED_KERNEL_PORTS: [Option<SocketBindingEntry>; MAX_NUM_BOUND_PORTS] =
+    [None; MAX_NUM_BOUND_PORTS];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! udp_mux_ethernet_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::net::ipv6::ipv6_ethernet::IP6EthernetStruct;
+        use capsules::net::udp::udp_send::MuxUdpSender;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<IP6EthernetStruct<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<
+            MuxUdpSender<'static, IP6EthernetStruct<'static, VirtualMuxAlarm<'static, $A>>>,
+        > = MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct UDPMuxEthernetComponent<A: Alarm<'static> + 'static> {
+    adapter: &'static dyn EthernetAdapter<'static>,
+    mac_addr: EthernetAddress,
+    interface_list: &'static [IPAddr],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static> UDPMuxEthernetComponent<A> {
+    pub fn new(
+        adapter: &'static dyn EthernetAdapter<'static>,
+        mac_addr: EthernetAddress,
+        interface_list: &'static [IPAddr],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            adapter,
+            mac_addr,
+            interface_list,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for UDPMuxEthernetComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<IP6EthernetStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<
+            MuxUdpSender<'static, IP6EthernetStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+    );
+    type Output = (
+        &'static MuxUdpSender<'static, IP6EthernetStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static MuxUdpReceiver<'static>,
+        &'static UdpPortManager,
+        &'static IP6RecvStruct<'static>,
+    );
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let ndp_virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+
+        let tr_hdr = TransportHeader::UDP(UDPHeader::new());
+        let ip_pyld: IPPayload = IPPayload {
+            header: tr_hdr,
+            payload: &mut UDP_DGRAM,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let ip_receive = static_init!(IP6RecvStruct<'static>, IP6RecvStruct::new());
+        let udp_recv_mux = static_init!(MuxUdpReceiver<'static>, MuxUdpReceiver::new());
+        ip_receive.set_client(udp_recv_mux);
+
+        let ip_send = static_init_half!(
+            static_buffer.1,
+            IP6EthernetStruct<'static, VirtualMuxAlarm<'static, A>>,
+            IP6EthernetStruct::new(
+                ip6_dg,
+                ndp_virtual_alarm,
+                self.adapter,
+                &mut ETHERNET_TX_BUF,
+                self.mac_addr,
+                self.interface_list,
+                ip_receive,
+                ip_vis,
+            )
+        );
+        ndp_virtual_alarm.set_alarm_client(ip_send);
+        self.adapter.set_client(ip_send);
+
+        // Initially, set src IP of the sender to be the first IP in the Interface
+        // list, as for 6LoWPAN.
+        ip_send.set_addr(self.interface_list[0]);
+
+        let udp_send_mux = static_init_half!(
+            static_buffer.2,
+            MuxUdpSender<'static, IP6EthernetStruct<'static, VirtualMuxAlarm<'static, A>>>,
+            MuxUdpSender::new(ip_send)
+        );
+        ip_send.set_client(udp_send_mux);
+
+        let create_table_cap = create_capability!(capabilities::CreatePortTableCapability);
+        let udp_port_table = static_init!(
+            UdpPortManager,
+            UdpPortManager::new(&create_table_cap, &mut USED_KERNEL_PORTS, udp_vis)
+        );
+
+        (udp_send_mux, udp_recv_mux, udp_port_table, ip_receive)
+    }
+}
@@ -0,0 +1,112 @@
+//! Component for the USB Mass Storage class.
+//!
+//! This provides one Component, UsbMscComponent, which exposes a block
+//! storage device, such as an `SDCardBlockStorage` or a region of flash
+//! through `NonvolatileToBlocks`, as a USB flash drive.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! static STRINGS: &'static [&str; 3] = &[
+//!     "XYZ Corp.",      // Manufacturer
+//!     "The Zorpinator", // Product
+//!     "0123456789AB",   // Serial number
+//! ];
+//! let msc = components::usb_msc::UsbMscComponent::new(
+//!     &nrf52::usbd::USBD,
+//!     capsules::usb::usbc_client::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915,
+//!     0x503a,
+//!     STRINGS,
+//!     nv_to_blocks,
+//! )
+//! .finalize(components::usb_msc_component_helper!(
+//!     nrf52::usbd::Usbd,
+//!     capsules::nonvolatile_to_blocks::NonvolatileToBlocks<'static>
+//! ));
+//! msc.enable();
+//! msc.attach();
+//! ```
+
+use capsules::usb::msc::MassStorage;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::hil::block_storage::BlockStorage;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_msc_component_helper {
+    ($U:ty, $B:ty $(,)?) => {{
+        use capsules::usb::msc::{MassStorage, BUFFER_SIZE};
+        use core::mem::MaybeUninit;
+        static mut BUF0: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+        static mut BUF1: MaybeUninit<MassStorage<'static, $U, $B>> = MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1)
+    };};
+}
+
+pub struct UsbMscComponent<
+    U: 'static + hil::usb::UsbController<'static>,
+    B: 'static + BlockStorage<'static>,
+> {
+    usb: &'static U,
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 3],
+    storage: &'static B,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>, B: 'static + BlockStorage<'static>>
+    UsbMscComponent<U, B>
+{
+    pub fn new(
+        usb: &'static U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        storage: &'static B,
+    ) -> Self {
+        Self {
+            usb,
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+            storage,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>, B: 'static + BlockStorage<'static>> Component
+    for UsbMscComponent<U, B>
+{
+    type StaticInput = (
+        &'static mut [u8; capsules::usb::msc::BUFFER_SIZE],
+        &'static mut MaybeUninit<MassStorage<'static, U, B>>,
+    );
+    type Output = &'static MassStorage<'static, U, B>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let msc = static_init_half!(
+            s.1,
+            MassStorage<'static, U, B>,
+            MassStorage::new(
+                self.usb,
+                self.max_ctrl_packet_size,
+                self.vendor_id,
+                self.product_id,
+                self.strings,
+                self.storage,
+                s.0,
+            )
+        );
+        self.usb.set_client(msc);
+        self.storage.set_client(msc);
+
+        msc
+    }
+}
@@ -260,6 +260,8 @@ pub unsafe fn reset_handler() {
     let mux_alarm = components::alarm::AlarmMuxComponent::new(&peripherals.ast)
         .finalize(components::alarm_mux_component_helper!(sam4l::ast::Ast));
     peripherals.ast.configure(mux_alarm);
+    // Measure how long the chip sleeps in each state with the AST.
+    board_kernel.power_manager().set_clock(&peripherals.ast);
 
     let sensors_i2c = static_init!(
         MuxI2C<'static>,
@@ -255,6 +255,10 @@ pub unsafe fn reset_handler() {
         debug!("{:?}", err);
     });
 
+    // Uncomment this to have the hardware watchdog reset the chip if the
+    // kernel hangs.
+    // chip.enable_watchdog();
+
     let scheduler = components::sched::cooperative::CooperativeComponent::new(&PROCESSES)
         .finalize(components::coop_component_helper!(NUM_PROCS));
     board_kernel.kernel_loop(&hifive1, chip, None, scheduler, &main_loop_cap);
@@ -43,7 +43,7 @@ use components::isl29035::AmbientLightComponent;
 use components::led::LedsComponent;
 use components::nrf51822::Nrf51822Component;
 use components::process_console::ProcessConsoleComponent;
-use components::rng::RngComponent;
+use components::rng::{RngMuxComponent, VirtualRngComponent};
 use components::si7021::{HumidityComponent, SI7021Component};
 use components::spi::{SpiComponent, SpiSyscallComponent};
 use imix_components::adc::AdcComponent;
@@ -131,6 +131,14 @@ struct Imix {
     ninedof: &'static capsules::ninedof::NineDof<'static>,
     radio_driver: &'static capsules::ieee802154::RadioDriver<'static>,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
+    tcp_driver: &'static capsules::net::tcp::TCPDriver<
+        'static,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
+    coap_driver: &'static capsules::net::coap::CoAPDriver<
+        'static,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
     crc: &'static capsules::crc::Crc<'static, sam4l::crccu::Crccu<'static>>,
     usb_driver: &'static capsules::usb::usb_user::UsbSyscallDriver<
         'static,
@@ -153,6 +161,12 @@ static mut RF233_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
 static mut RF233_REG_WRITE: [u8; 2] = [0x00; 2];
 static mut RF233_REG_READ: [u8; 2] = [0x00; 2];
 
+// Flash volume persisting the IEEE 802.15.4 frame counters across reboots,
+// with a copy of the state in each of its two 512 byte flash pages.
+mod frame_counters {
+    kernel::storage_volume!(IEEE802154_FRAME_COUNTERS, 1);
+}
+
 impl kernel::Platform for Imix {
     fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
     where
@@ -175,6 +189,8 @@ impl kernel::Platform for Imix {
             capsules::usb::usb_user::DRIVER_NUM => f(Some(self.usb_driver)),
             capsules::ieee802154::DRIVER_NUM => f(Some(self.radio_driver)),
             capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
+            capsules::net::tcp::DRIVER_NUM => f(Some(self.tcp_driver)),
+            capsules::net::coap::DRIVER_NUM => f(Some(self.coap_driver)),
             capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
@@ -333,6 +349,8 @@ pub unsafe fn reset_handler() {
     let mux_alarm = AlarmMuxComponent::new(&peripherals.ast)
         .finalize(components::alarm_mux_component_helper!(sam4l::ast::Ast));
     peripherals.ast.configure(mux_alarm);
+    // Measure how long the chip sleeps in each state with the AST.
+    board_kernel.power_manager().set_clock(&peripherals.ast);
     let alarm = AlarmDriverComponent::new(board_kernel, mux_alarm)
         .finalize(components::alarm_component_helper!(sam4l::ast::Ast));
 
@@ -437,7 +455,8 @@ pub unsafe fn reset_handler() {
         ),
     )
     .finalize(components::acomp_component_buf!(sam4l::acifc::Acifc));
-    let rng = RngComponent::new(board_kernel, &peripherals.trng).finalize(());
+    let rng_mux = RngMuxComponent::new(&peripherals.trng).finalize(());
+    let rng = VirtualRngComponent::new(board_kernel, rng_mux).finalize(());
 
     // For now, assign the 802.15.4 MAC address on the device as
     // simply a 16-bit short address which represents the last 16 bits
@@ -495,6 +514,21 @@ pub unsafe fn reset_handler() {
         sam4l::flashcalw::FLASHCALW
     ));
 
+    let frame_counter_buf = static_init!(
+        [u8; capsules::ieee802154::STATE_SIZE],
+        [0; capsules::ieee802154::STATE_SIZE]
+    );
+    kernel::hil::nonvolatile_storage::NonvolatileStorage::set_client(
+        nonvolatile_storage,
+        radio_driver,
+    );
+    let frame_counters_start = frame_counters::IEEE802154_FRAME_COUNTERS.as_ptr() as usize;
+    radio_driver.set_frame_counter_storage(
+        nonvolatile_storage,
+        [frame_counters_start, frame_counters_start + 512],
+        frame_counter_buf,
+    );
+
     let local_ip_ifaces = static_init!(
         [IPAddr; 3],
         [
@@ -510,17 +544,18 @@ pub unsafe fn reset_handler() {
         ]
     );
 
-    let (udp_send_mux, udp_recv_mux, udp_port_table) = components::udp_mux::UDPMuxComponent::new(
-        mux_mac,
-        DEFAULT_CTX_PREFIX_LEN,
-        DEFAULT_CTX_PREFIX,
-        DST_MAC_ADDR,
-        src_mac_from_serial_num, //comment out for dual rx test only
-        //MacAddress::Short(49138), //comment in for dual rx test only
-        local_ip_ifaces,
-        mux_alarm,
-    )
-    .finalize(components::udp_mux_component_helper!(sam4l::ast::Ast));
+    let (udp_send_mux, udp_recv_mux, udp_port_table, ip_receive) =
+        components::udp_mux::UDPMuxComponent::new(
+            mux_mac,
+            DEFAULT_CTX_PREFIX_LEN,
+            DEFAULT_CTX_PREFIX,
+            DST_MAC_ADDR,
+            src_mac_from_serial_num, //comment out for dual rx test only
+            //MacAddress::Short(49138), //comment in for dual rx test only
+            local_ip_ifaces,
+            mux_alarm,
+        )
+        .finalize(components::udp_mux_component_helper!(sam4l::ast::Ast));
 
     // UDP driver initialization happens here
     let udp_driver = components::udp_driver::UDPDriverComponent::new(
@@ -532,6 +567,49 @@ pub unsafe fn reset_handler() {
     )
     .finalize(components::udp_driver_component_helper!(sam4l::ast::Ast));
 
+    // CoAP driver initialization happens here, once the UDP driver lets
+    // the port table bind ports
+    let coap_driver = components::coap::CoAPComponent::new(
+        board_kernel,
+        udp_send_mux,
+        udp_recv_mux,
+        udp_port_table,
+        local_ip_ifaces,
+        mux_alarm,
+    )
+    .finalize(components::coap_component_helper!(sam4l::ast::Ast));
+
+    // TCP driver initialization happens here
+    let tcp_driver = components::tcp_driver::TCPDriverComponent::new(
+        board_kernel,
+        mux_mac,
+        ip_receive,
+        DEFAULT_CTX_PREFIX_LEN,
+        DEFAULT_CTX_PREFIX,
+        DST_MAC_ADDR,
+        src_mac_from_serial_num,
+        local_ip_ifaces,
+        mux_alarm,
+        rng_mux,
+    )
+    .finalize(components::tcp_driver_component_helper!(sam4l::ast::Ast));
+
+    // RPL routing over the 6LoWPAN mesh. One node of the mesh must instead
+    // be started as the DODAG root, with `rpl_router.start_root(instance)`.
+    let rpl_router = components::rpl::RplComponent::new(
+        mux_mac,
+        ip_receive,
+        DEFAULT_CTX_PREFIX_LEN,
+        DEFAULT_CTX_PREFIX,
+        src_mac_from_serial_num,
+        local_ip_ifaces,
+        mux_alarm,
+    )
+    .finalize(components::rpl_component_helper!(sam4l::ast::Ast));
+    udp_send_mux.set_router(rpl_router);
+    tcp_driver.set_router(rpl_router);
+    rpl_router.start();
+
     let imix = Imix {
         pconsole,
         console,
@@ -551,6 +629,8 @@ pub unsafe fn reset_handler() {
         ninedof,
         radio_driver,
         udp_driver,
+        tcp_driver,
+        coap_driver,
         usb_driver,
         nrf51822: nrf_serialization,
         nonvolatile_storage,
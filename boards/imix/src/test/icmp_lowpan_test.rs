@@ -26,6 +26,7 @@ use capsules::net::sixlowpan::sixlowpan_state::{Sixlowpan, SixlowpanState, TxSta
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use core::cell::Cell;
 use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::common::leasable_buffer::LeasableBuffer;
 use kernel::create_capability;
 use kernel::debug;
 use kernel::hil::radio;
@@ -231,8 +232,12 @@ impl<'a, A: time::Alarm<'a>> LowpanICMPTest<'a, A> {
     fn send_next(&self) {
         let icmp_hdr = ICMP6Header::new(ICMP6Type::Type128); // Echo Request
         unsafe {
-            self.icmp_sender
-                .send(DST_ADDR, icmp_hdr, &mut ICMP_PAYLOAD, self.net_cap)
+            self.icmp_sender.send(
+                DST_ADDR,
+                icmp_hdr,
+                &LeasableBuffer::new(&mut ICMP_PAYLOAD),
+                self.net_cap,
+            )
         };
     }
 }
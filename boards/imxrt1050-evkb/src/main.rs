@@ -208,7 +208,7 @@ pub unsafe fn reset_handler() {
     );
     DynamicDeferredCall::set_global_instance(dynamic_deferred_caller);
 
-    let chip = static_init!(Chip, Chip::new(peripherals));
+    let chip = static_init!(Chip, Chip::new(peripherals.ccm, peripherals));
     CHIP = Some(chip);
 
     // LPUART1
@@ -446,6 +446,10 @@ pub unsafe fn reset_handler() {
         debug!("{:?}", err);
     });
 
+    // Uncomment this to have the hardware watchdog reset the chip if the
+    // kernel hangs. It cannot be stopped once started.
+    // chip.enable_watchdog();
+
     let scheduler = components::sched::round_robin::RoundRobinComponent::new(&PROCESSES)
         .finalize(components::rr_component_helper!(NUM_PROCS));
     board_kernel.kernel_loop(
@@ -5,6 +5,8 @@
 // https://github.com/rust-lang/rust/issues/62184.
 #![cfg_attr(not(doc), no_main)]
 
+use capsules::net::ethernet::EthernetAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use kernel::capabilities;
 use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
@@ -99,6 +101,10 @@ static mut PANIC_REFERENCES: LiteXSimPanicReferences = LiteXSimPanicReferences {
     uart: None,
 };
 
+// MAC address of the simulated Ethernet interface. This is a locally
+// administered address, as the simulation has no assigned one.
+const ETHERNET_MAC_ADDR: EthernetAddress = EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+
 // How should the kernel respond when a process faults.
 const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
 
@@ -127,6 +133,7 @@ struct LiteXSim {
             >,
         >,
     >,
+    udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
 }
 
 /// Mapping of integer syscalls to objects that implement syscalls.
@@ -139,6 +146,7 @@ impl Platform for LiteXSim {
             capsules::console::DRIVER_NUM => f(Some(self.console)),
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
             capsules::low_level_debug::DRIVER_NUM => f(Some(self.lldb)),
+            capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
             _ => f(None),
         }
     }
@@ -300,9 +308,6 @@ pub unsafe fn reset_handler() {
 
     // ---------- ETHERNET ----------
 
-    // Packet receive buffer
-    let ethmac0_rxbuf0 = static_init!([u8; 1522], [0; 1522]);
-
     // ETHMAC peripheral
     let ethmac0 = static_init!(
         litex_vexriscv::liteeth::LiteEth<socc::SoCRegisterFmt>,
@@ -316,13 +321,63 @@ pub unsafe fn reset_handler() {
             socc::ETHMAC_SLOT_SIZE,
             socc::ETHMAC_RX_SLOTS,
             socc::ETHMAC_TX_SLOTS,
-            ethmac0_rxbuf0,
         )
     );
 
     // Initialize the ETHMAC controller
     ethmac0.initialize();
 
+    // ---------- UDP over ETHERNET ----------
+
+    // The first address is the link-local address derived from the MAC
+    // address (modified EUI-64), which neighbors on the tap0 link can reach
+    // without any further configuration.
+    let local_ip_ifaces = static_init!(
+        [IPAddr; 2],
+        [
+            IPAddr([
+                0xfe, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xfe, 0x00,
+                0x00, 0x01,
+            ]),
+            IPAddr([
+                0xfd, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x02,
+            ]),
+        ]
+    );
+
+    let (udp_send_mux, udp_recv_mux, udp_port_table, _ip_receive) =
+        components::udp_mux_ethernet::UDPMuxEthernetComponent::new(
+            ethmac0,
+            ETHERNET_MAC_ADDR,
+            local_ip_ifaces,
+            mux_alarm,
+        )
+        .finalize(components::udp_mux_ethernet_component_helper!(
+            litex_vexriscv::timer::LiteXAlarm<
+                'static,
+                'static,
+                socc::SoCRegisterFmt,
+                socc::ClockFrequency,
+            >
+        ));
+
+    let udp_driver = components::udp_driver::UDPDriverComponent::new(
+        board_kernel,
+        udp_send_mux,
+        udp_recv_mux,
+        udp_port_table,
+        local_ip_ifaces,
+    )
+    .finalize(components::udp_driver_ethernet_component_helper!(
+        litex_vexriscv::timer::LiteXAlarm<
+            'static,
+            'static,
+            socc::SoCRegisterFmt,
+            socc::ClockFrequency,
+        >
+    ));
+
     // ---------- INITIALIZE CHIP, ENABLE INTERRUPTS ----------
 
     let interrupt_service = static_init!(
@@ -391,6 +446,7 @@ pub unsafe fn reset_handler() {
         console: console,
         alarm: alarm,
         lldb: lldb,
+        udp_driver,
     };
 
     kernel::procs::load_processes(
@@ -37,7 +37,7 @@ impl Write for Writer {
 
 impl IoWrite for Writer {
     fn write(&mut self, buf: &[u8]) {
-        let uart = nrf52833::uart::Uarte::new();
+        let uart = nrf52833::uart::Uarte::new("uarte0");
 
         use kernel::hil::uart::Configure;
 
@@ -28,7 +28,7 @@ impl IoWrite for Writer {
         // Here, we create a second instance of the Uarte struct.
         // This is okay because we only call this during a panic, and
         // we will never actually process the interrupts
-        let uart = nrf52840::uart::Uarte::new();
+        let uart = nrf52840::uart::Uarte::new("uarte0");
         if !self.initialized {
             self.initialized = true;
             uart.configure(uart::Parameters {
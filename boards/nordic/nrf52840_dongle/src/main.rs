@@ -312,6 +312,7 @@ pub unsafe fn reset_handler() {
     ));
 
     nrf52_components::NrfClockComponent::new(&base_peripherals.clock).finalize(());
+    nrf52_components::NrfSleepComponent::new(board_kernel, base_peripherals).finalize(());
 
     let platform = Platform {
         button,
@@ -136,6 +136,11 @@ static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; NUM_PROC
 
 static mut CHIP: Option<&'static nrf52840::chip::NRF52<Nrf52840DefaultPeripherals>> = None;
 
+// Internal flash pages keeping the records of kernel panics across reboots.
+mod crash_log {
+    kernel::storage_volume!(CRASH_LOG, 16);
+}
+
 /// Dummy buffer that causes the linker to reserve enough space for the stack.
 #[no_mangle]
 #[link_section = ".stack_buffer"]
@@ -148,6 +153,7 @@ pub struct Platform {
         nrf52840::ble_radio::Radio<'static>,
         VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
     >,
+    ble_gatt: &'static capsules::ble::driver::GattDriver<'static>,
     ieee802154_radio: &'static capsules::ieee802154::RadioDriver<'static>,
     button: &'static capsules::button::Button<'static, nrf52840::gpio::GPIOPin<'static>>,
     pconsole: &'static capsules::process_console::ProcessConsole<
@@ -163,6 +169,7 @@ pub struct Platform {
     rng: &'static capsules::rng::RngDriver<'static>,
     temp: &'static capsules::temperature::TemperatureSensor<'static>,
     ipc: kernel::ipc::IPC,
+    message_ipc: &'static capsules::message_ipc::MessageIpc,
     analog_comparator: &'static capsules::analog_comparator::AnalogComparator<
         'static,
         nrf52840::acomp::Comparator<'static>,
@@ -171,8 +178,28 @@ pub struct Platform {
         'static,
         capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
     >,
+    app_watchdog: &'static capsules::app_watchdog::AppWatchdog<
+        'static,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
+        components::app_watchdog::Capability,
+    >,
+    date_time: &'static capsules::date_time::DateTimeDriver<
+        'static,
+        capsules::counter_date_time::CounterDateTime<'static, nrf52840::rtc::Rtc<'static>>,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
+    >,
     nonvolatile_storage: &'static capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>,
+    crash_log: &'static capsules::crash_log::CrashLog<'static, nrf52840::nvmc::Nvmc>,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
+    hmac: &'static capsules::hmac::HmacDriver<
+        'static,
+        capsules::virtual_hmac::VirtualMuxHmac<
+            'static,
+            capsules::sha_software::ShaSoftware<'static, [u8; 32]>,
+            [u8; 32],
+        >,
+        [u8; 32],
+    >,
 }
 
 impl kernel::Platform for Platform {
@@ -184,16 +211,22 @@ impl kernel::Platform for Platform {
             capsules::console::DRIVER_NUM => f(Some(self.console)),
             capsules::gpio::DRIVER_NUM => f(Some(self.gpio)),
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
+            capsules::app_watchdog::DRIVER_NUM => f(Some(self.app_watchdog)),
+            capsules::date_time::DRIVER_NUM => f(Some(self.date_time)),
             capsules::led::DRIVER_NUM => f(Some(self.led)),
             capsules::button::DRIVER_NUM => f(Some(self.button)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
             capsules::ble_advertising_driver::DRIVER_NUM => f(Some(self.ble_radio)),
+            capsules::ble::driver::DRIVER_NUM => f(Some(self.ble_gatt)),
             capsules::ieee802154::DRIVER_NUM => f(Some(self.ieee802154_radio)),
             capsules::temperature::DRIVER_NUM => f(Some(self.temp)),
             capsules::analog_comparator::DRIVER_NUM => f(Some(self.analog_comparator)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
+            capsules::crash_log::DRIVER_NUM => f(Some(self.crash_log)),
             capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
+            capsules::hmac::DRIVER_NUM => f(Some(self.hmac)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
+            capsules::message_ipc::DRIVER_NUM => f(Some(self.message_ipc)),
             _ => f(None),
         }
     }
@@ -329,6 +362,25 @@ pub unsafe fn reset_handler() {
         .finalize(components::alarm_mux_component_helper!(nrf52840::rtc::Rtc));
     let alarm = components::alarm::AlarmDriverComponent::new(board_kernel, mux_alarm)
         .finalize(components::alarm_component_helper!(nrf52840::rtc::Rtc));
+    let app_watchdog = components::app_watchdog::AppWatchdogComponent::new(board_kernel, mux_alarm)
+        .finalize(components::app_watchdog_component_helper!(
+            nrf52840::rtc::Rtc
+        ));
+    // The RTC has no calendar and is cleared on reset, so an app has to set
+    // the date and time after every boot, and apps are allowed to.
+    let counter_date_time = components::date_time::CounterDateTimeComponent::new(rtc).finalize(
+        components::counter_date_time_component_helper!(nrf52840::rtc::Rtc),
+    );
+    let date_time = components::date_time::DateTimeComponent::new(
+        board_kernel,
+        mux_alarm,
+        counter_date_time,
+        true,
+    )
+    .finalize(components::date_time_component_helper!(
+        capsules::counter_date_time::CounterDateTime<'static, nrf52840::rtc::Rtc>,
+        nrf52840::rtc::Rtc
+    ));
 
     let channel = nrf52_components::UartChannelComponent::new(
         uart_channel,
@@ -338,7 +390,7 @@ pub unsafe fn reset_handler() {
     .finalize(());
 
     let dynamic_deferred_call_clients =
-        static_init!([DynamicDeferredCallClientState; 2], Default::default());
+        static_init!([DynamicDeferredCallClientState; 3], Default::default());
     let dynamic_deferred_caller = static_init!(
         DynamicDeferredCall,
         DynamicDeferredCall::new(dynamic_deferred_call_clients)
@@ -362,6 +414,13 @@ pub unsafe fn reset_handler() {
     let ble_radio =
         nrf52_components::BLEComponent::new(board_kernel, &base_peripherals.ble_radio, mux_alarm)
             .finalize(());
+    let ble_gatt = nrf52_components::BleGattComponent::new(
+        board_kernel,
+        &base_peripherals.ble_radio,
+        mux_alarm,
+        b"Tock",
+    )
+    .finalize(());
 
     let aes_mux = static_init!(
         MuxAES128CCM<'static, nrf52840::aes::AesECB>,
@@ -406,16 +465,17 @@ pub unsafe fn reset_handler() {
         ]
     );
 
-    let (udp_send_mux, udp_recv_mux, udp_port_table) = components::udp_mux::UDPMuxComponent::new(
-        mux_mac,
-        DEFAULT_CTX_PREFIX_LEN,
-        DEFAULT_CTX_PREFIX,
-        DST_MAC_ADDR,
-        src_mac_from_serial_num,
-        local_ip_ifaces,
-        mux_alarm,
-    )
-    .finalize(components::udp_mux_component_helper!(nrf52840::rtc::Rtc));
+    let (udp_send_mux, udp_recv_mux, udp_port_table, _ip_receive) =
+        components::udp_mux::UDPMuxComponent::new(
+            mux_mac,
+            DEFAULT_CTX_PREFIX_LEN,
+            DEFAULT_CTX_PREFIX,
+            DST_MAC_ADDR,
+            src_mac_from_serial_num,
+            local_ip_ifaces,
+            mux_alarm,
+        )
+        .finalize(components::udp_mux_component_helper!(nrf52840::rtc::Rtc));
 
     // UDP driver initialization happens here
     let udp_driver = components::udp_driver::UDPDriverComponent::new(
@@ -427,12 +487,34 @@ pub unsafe fn reset_handler() {
     )
     .finalize(components::udp_driver_component_helper!(nrf52840::rtc::Rtc));
 
+    // The nRF52840 has no hash peripheral, so HMAC is computed in software
+    let sha = components::sha_software::ShaSoftwareComponent::new(dynamic_deferred_caller)
+        .finalize(components::sha_software_component_helper!([u8; 32]));
+    let mux_hmac = components::hmac::HmacMuxComponent::new(sha).finalize(
+        components::hmac_mux_component_helper!(
+            capsules::sha_software::ShaSoftware<'static, [u8; 32]>,
+            [u8; 32]
+        ),
+    );
+    let hmac = components::hmac::HmacComponent::new(
+        board_kernel,
+        mux_hmac,
+        static_init!([u8; 64], [0; 64]),
+        static_init!([u8; 32], [0; 32]),
+    )
+    .finalize(components::hmac_component_helper!(
+        capsules::sha_software::ShaSoftware<'static, [u8; 32]>,
+        [u8; 32]
+    ));
+
     let temp =
         components::temperature::TemperatureComponent::new(board_kernel, &base_peripherals.temp)
             .finalize(());
 
     let rng = components::rng::RngComponent::new(board_kernel, &base_peripherals.trng).finalize(());
 
+    let message_ipc = components::message_ipc::MessageIpcComponent::new(board_kernel).finalize(());
+
     // SPI
     let mux_spi = components::spi::SpiMuxComponent::new(&base_peripherals.spim0)
         .finalize(components::spi_mux_component_helper!(nrf52840::spi::SPIM));
@@ -473,6 +555,17 @@ pub unsafe fn reset_handler() {
         >
     ));
 
+    let crash_log = components::crash_log::CrashLogComponent::new(
+        board_kernel,
+        &base_peripherals.nvmc,
+        crash_log::CRASH_LOG.as_ptr() as usize / nrf52840::nvmc::PAGE_SIZE,
+        crash_log::CRASH_LOG.len() / nrf52840::nvmc::PAGE_SIZE,
+    )
+    .finalize(components::crash_log_component_helper!(
+        nrf52840::nvmc::Nvmc
+    ));
+    pconsole.set_crash_log(crash_log);
+
     // Initialize AC using AIN5 (P0.29) as VIN+ and VIN- as AIN0 (P0.02)
     // These are hardcoded pin assignments specified in the driver
     let analog_comparator = components::analog_comparator::AcComponent::new(
@@ -487,6 +580,7 @@ pub unsafe fn reset_handler() {
     ));
 
     nrf52_components::NrfClockComponent::new(&base_peripherals.clock).finalize(());
+    nrf52_components::NrfSleepComponent::new(board_kernel, base_peripherals).finalize(());
 
     // let alarm_test_component =
     //     components::test::multi_alarm_test::MultiAlarmTestComponent::new(&mux_alarm).finalize(
@@ -528,6 +622,7 @@ pub unsafe fn reset_handler() {
     let platform = Platform {
         button,
         ble_radio,
+        ble_gatt,
         ieee802154_radio,
         pconsole,
         console,
@@ -536,10 +631,15 @@ pub unsafe fn reset_handler() {
         rng,
         temp,
         alarm,
+        app_watchdog,
+        date_time,
         analog_comparator,
         nonvolatile_storage,
+        crash_log,
         udp_driver,
+        hmac,
         ipc: kernel::ipc::IPC::new(board_kernel, &memory_allocation_capability),
+        message_ipc,
     };
 
     platform.pconsole.start();
@@ -580,6 +680,10 @@ pub unsafe fn reset_handler() {
         debug!("{:?}", err);
     });
 
+    // Uncomment this to have the hardware watchdog reset the chip if the
+    // kernel hangs. It cannot be stopped once started.
+    // chip.enable_watchdog();
+
     let scheduler = components::sched::round_robin::RoundRobinComponent::new(&PROCESSES)
         .finalize(components::rr_component_helper!(NUM_PROCS));
     board_kernel.kernel_loop(
@@ -1,4 +1,5 @@
-//! Component for BLE radio on nRF52 based platforms.
+//! Components for the BLE radio on nRF52 based platforms: the advertising
+//! driver, and the GATT server.
 //!
 //! Usage
 //! -----
@@ -7,12 +8,16 @@
 //! ```
 
 use capsules;
+use capsules::ble::att::AttServer;
+use capsules::ble::l2cap::L2cap;
+use capsules::ble::link_layer::{LinkLayer, MAX_ADVERTISING_DATA};
 use capsules::virtual_alarm::VirtualMuxAlarm;
 
 use nrf52::rtc::Rtc;
 
 use kernel::capabilities;
 use kernel::component::Component;
+use kernel::hil::ble_connection::LinkLayerRadio;
 use kernel::hil::time::Alarm;
 use kernel::{create_capability, static_init};
 
@@ -78,3 +83,87 @@ impl Component for BLEComponent {
         ble_radio
     }
 }
+
+/// Component for the BLE GATT server on nRF52 based platforms: the link
+/// layer, L2CAP, the ATT server and the userspace driver.
+///
+/// The device uses a static random address derived from the device address
+/// in the FICR, and advertises `name` as its complete local name.
+///
+/// ```rust
+/// let ble_gatt = BleGattComponent::new(
+///     board_kernel,
+///     &base_peripherals.ble_radio,
+///     mux_alarm,
+///     b"Tock",
+/// )
+/// .finalize(());
+/// ```
+pub struct BleGattComponent {
+    board_kernel: &'static kernel::Kernel,
+    radio: &'static nrf52::ble_radio::Radio<'static>,
+    mux_alarm: &'static capsules::virtual_alarm::MuxAlarm<'static, nrf52::rtc::Rtc<'static>>,
+    name: &'static [u8],
+}
+
+impl BleGattComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        radio: &'static nrf52::ble_radio::Radio,
+        mux_alarm: &'static capsules::virtual_alarm::MuxAlarm<'static, nrf52::rtc::Rtc>,
+        name: &'static [u8],
+    ) -> BleGattComponent {
+        BleGattComponent {
+            board_kernel,
+            radio,
+            mux_alarm,
+            name,
+        }
+    }
+}
+
+impl Component for BleGattComponent {
+    type StaticInput = ();
+    type Output = &'static capsules::ble::driver::GattDriver<'static>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let link_layer_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, Rtc>,
+            VirtualMuxAlarm::new(self.mux_alarm)
+        );
+
+        // A static random address: its two most significant bits are set
+        let mut address = nrf52::ficr::FICR_INSTANCE.address();
+        address[5] |= 0xc0;
+
+        let link_layer = static_init!(
+            LinkLayer<'static, nrf52::ble_radio::Radio, VirtualMuxAlarm<'static, Rtc>>,
+            LinkLayer::new(self.radio, link_layer_virtual_alarm, address)
+        );
+        self.radio.set_link_layer_client(link_layer);
+        link_layer_virtual_alarm.set_alarm_client(link_layer);
+
+        let advertising_data = static_init!([u8; MAX_ADVERTISING_DATA], [0; MAX_ADVERTISING_DATA]);
+        let len = capsules::ble::gatt::advertising_data(self.name, advertising_data);
+        link_layer.set_advertising_data(&advertising_data[..len]);
+
+        let att_server = static_init!(AttServer<'static>, AttServer::new());
+        let l2cap = static_init!(L2cap<'static>, L2cap::new(att_server));
+        link_layer.set_client(l2cap);
+
+        let gatt = static_init!(
+            capsules::ble::driver::GattDriver<'static>,
+            capsules::ble::driver::GattDriver::new(
+                att_server,
+                link_layer,
+                self.name,
+                self.board_kernel.create_grant(&grant_cap)
+            )
+        );
+        att_server.set_database(gatt);
+
+        gatt
+    }
+}
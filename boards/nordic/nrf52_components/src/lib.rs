@@ -3,7 +3,8 @@
 pub mod ble;
 pub mod startup;
 
-pub use self::ble::BLEComponent;
+pub use self::ble::{BLEComponent, BleGattComponent};
 pub use self::startup::{
-    NrfClockComponent, NrfStartupComponent, UartChannel, UartChannelComponent, UartPins,
+    NrfClockComponent, NrfSleepComponent, NrfStartupComponent, UartChannel, UartChannelComponent,
+    UartPins,
 };
@@ -1,7 +1,7 @@
 //! Component for starting up nrf52 platforms.
-//! Contains 3 components, NrfStartupComponent, NrfClockComponent,
-//! and UartChannelComponent, as well as two helper structs for
-//! intializing Uart on Nordic boards.
+//! Contains 4 components, NrfStartupComponent, NrfClockComponent,
+//! NrfSleepComponent and UartChannelComponent, as well as two helper
+//! structs for intializing Uart on Nordic boards.
 
 use capsules::virtual_alarm::MuxAlarm;
 use components;
@@ -115,8 +115,8 @@ impl<'a> Component for NrfClockComponent<'a> {
     type StaticInput = ();
     type Output = ();
     unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
-        // Start all of the clocks. Low power operation will require a better
-        // approach than this.
+        // Start all of the clocks. The chip stops the crystal oscillator in
+        // deep sleep, see `NrfSleepComponent`.
         self.clock.low_stop();
         self.clock.high_stop();
 
@@ -129,6 +129,42 @@ impl<'a> Component for NrfClockComponent<'a> {
     }
 }
 
+/// Registers the sleep constraints of the peripherals that need the crystal
+/// oscillator with the kernel, so that the chip only enters deep sleep while
+/// none of them is active, and measures the time spent sleeping with the RTC.
+///
+/// Every nrf52 board must use this: without the constraints the kernel puts
+/// the chip into deep sleep while the radios or UARTE depend on the crystal.
+/// Boards that use USB register the constraint of `usbd` as well.
+pub struct NrfSleepComponent {
+    board_kernel: &'static kernel::Kernel,
+    peripherals: &'static nrf52::chip::Nrf52DefaultPeripherals<'static>,
+}
+
+impl NrfSleepComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        peripherals: &'static nrf52::chip::Nrf52DefaultPeripherals<'static>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            peripherals,
+        }
+    }
+}
+
+impl Component for NrfSleepComponent {
+    type StaticInput = ();
+    type Output = ();
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let power_manager = self.board_kernel.power_manager();
+        power_manager.register(self.peripherals.uarte0.sleep_constraint());
+        power_manager.register(self.peripherals.ble_radio.sleep_constraint());
+        power_manager.register(self.peripherals.ieee802154_radio.sleep_constraint());
+        power_manager.set_clock(&self.peripherals.rtc);
+    }
+}
+
 /// Pins for the UART
 #[derive(Debug)]
 pub struct UartPins {
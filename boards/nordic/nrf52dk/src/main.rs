@@ -134,6 +134,7 @@ pub struct Platform {
         nrf52832::ble_radio::Radio<'static>,
         VirtualMuxAlarm<'static, Rtc<'static>>,
     >,
+    ble_gatt: &'static capsules::ble::driver::GattDriver<'static>,
     button: &'static capsules::button::Button<'static, nrf52832::gpio::GPIOPin<'static>>,
     pconsole: &'static capsules::process_console::ProcessConsole<
         'static,
@@ -171,6 +172,7 @@ impl kernel::Platform for Platform {
             capsules::button::DRIVER_NUM => f(Some(self.button)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
             capsules::ble_advertising_driver::DRIVER_NUM => f(Some(self.ble_radio)),
+            capsules::ble::driver::DRIVER_NUM => f(Some(self.ble_gatt)),
             capsules::temperature::DRIVER_NUM => f(Some(self.temp)),
             capsules::analog_comparator::DRIVER_NUM => f(Some(self.analog_comparator)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
@@ -327,6 +329,13 @@ pub unsafe fn reset_handler() {
     let ble_radio =
         nrf52_components::BLEComponent::new(board_kernel, &base_peripherals.ble_radio, mux_alarm)
             .finalize(());
+    let ble_gatt = nrf52_components::BleGattComponent::new(
+        board_kernel,
+        &base_peripherals.ble_radio,
+        mux_alarm,
+        b"Tock",
+    )
+    .finalize(());
 
     let temp =
         components::temperature::TemperatureComponent::new(board_kernel, &base_peripherals.temp)
@@ -348,10 +357,12 @@ pub unsafe fn reset_handler() {
     ));
 
     nrf52_components::NrfClockComponent::new(&base_peripherals.clock).finalize(());
+    nrf52_components::NrfSleepComponent::new(board_kernel, base_peripherals).finalize(());
 
     let platform = Platform {
         button,
         ble_radio,
+        ble_gatt,
         pconsole,
         console,
         led,
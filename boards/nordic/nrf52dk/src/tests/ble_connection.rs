@@ -0,0 +1,54 @@
+use capsules::ble::att::AttServer;
+use capsules::ble::l2cap::L2cap;
+use capsules::ble::link_layer::{LinkControl, LinkLayer};
+use capsules::test::ble_connection::{SimulatedCentral, TestDatabase};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::hil::ble_connection::LinkLayerRadio;
+use kernel::hil::time::Alarm;
+use kernel::static_init;
+use nrf52832::rtc::Rtc;
+
+type Central = SimulatedCentral<'static, VirtualMuxAlarm<'static, Rtc<'static>>>;
+
+/// To run the test add the following `main.rs::reset_handler` somewhere after that the
+/// alarm mux and the debug writer have been initialized:
+///
+/// ```rustc
+///     tests::ble_connection::run(mux_alarm);
+/// ```
+///
+/// The link layer runs against a simulated central instead of the radio, so the test needs no
+/// other device. It prints the steps of the test as they pass, and then
+/// `BLE connection test passed`.
+pub unsafe fn run(mux_alarm: &'static MuxAlarm<'static, Rtc<'static>>) {
+    let central_alarm = static_init!(
+        VirtualMuxAlarm<'static, Rtc>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let link_layer_alarm = static_init!(
+        VirtualMuxAlarm<'static, Rtc>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+
+    let att_server = static_init!(AttServer<'static>, AttServer::new());
+    let database = static_init!(TestDatabase<'static>, TestDatabase::new(att_server));
+    att_server.set_database(database);
+    let l2cap = static_init!(L2cap<'static>, L2cap::new(att_server));
+
+    let central = static_init!(Central, SimulatedCentral::new(central_alarm, database));
+    central_alarm.set_alarm_client(central);
+
+    let link_layer = static_init!(
+        LinkLayer<'static, Central, VirtualMuxAlarm<'static, Rtc>>,
+        LinkLayer::new(
+            central,
+            link_layer_alarm,
+            [0x11, 0x22, 0x33, 0x44, 0x55, 0xc6]
+        )
+    );
+    central.set_link_layer_client(link_layer);
+    link_layer_alarm.set_alarm_client(link_layer);
+    link_layer.set_client(l2cap);
+
+    link_layer.start_advertising();
+}
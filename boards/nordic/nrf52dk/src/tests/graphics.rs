@@ -0,0 +1,23 @@
+use capsules::test::graphics::{TestGraphics, HEIGHT, TILE_HEIGHT, TILE_WIDTH, WIDTH};
+use kernel::static_init;
+
+/// To run the test add the following `main.rs::reset_handler` somewhere after that the debug
+/// writer has been initialized:
+///
+/// ```rustc
+///     tests::graphics::run();
+/// ```
+///
+/// The test renders into memory, so the board needs no screen. It prints the steps of the test
+/// as they pass, and then `Graphics test passed`.
+pub unsafe fn run() {
+    let frame = static_init!([u8; WIDTH * HEIGHT], [0; WIDTH * HEIGHT]);
+    let tiled_frame = static_init!([u8; WIDTH * HEIGHT], [0; WIDTH * HEIGHT]);
+    let tile = static_init!(
+        [u8; TILE_WIDTH * TILE_HEIGHT],
+        [0; TILE_WIDTH * TILE_HEIGHT]
+    );
+
+    let test = static_init!(TestGraphics, TestGraphics::new(frame, tiled_frame, tile));
+    test.run();
+}
@@ -1,2 +1,4 @@
 pub mod aes;
+pub mod ble_connection;
+pub mod graphics;
 pub mod uart;
@@ -60,6 +60,11 @@ struct NucleoF429ZI {
         VirtualMuxAlarm<'static, stm32f429zi::tim2::Tim2<'static>>,
     >,
     temperature: &'static capsules::temperature::TemperatureSensor<'static>,
+    date_time: &'static capsules::date_time::DateTimeDriver<
+        'static,
+        stm32f429zi::rtc::Rtc<'static>,
+        VirtualMuxAlarm<'static, stm32f429zi::tim2::Tim2<'static>>,
+    >,
     gpio: &'static capsules::gpio::GPIO<'static, stm32f429zi::gpio::Pin<'static>>,
 }
 
@@ -76,6 +81,7 @@ impl Platform for NucleoF429ZI {
             capsules::adc::DRIVER_NUM => f(Some(self.adc)),
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
             capsules::temperature::DRIVER_NUM => f(Some(self.temperature)),
+            capsules::date_time::DRIVER_NUM => f(Some(self.date_time)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             capsules::gpio::DRIVER_NUM => f(Some(self.gpio)),
             _ => f(None),
@@ -351,6 +357,22 @@ pub unsafe fn reset_handler() {
     let alarm = components::alarm::AlarmDriverComponent::new(board_kernel, mux_alarm)
         .finalize(components::alarm_component_helper!(stm32f429zi::tim2::Tim2));
 
+    // DATE AND TIME
+
+    // The RTC counts on the LSE crystal, and keeps the date and time across
+    // resets as long as VBAT stays powered. Apps may set it.
+    base_peripherals.rtc.enable();
+    let date_time = components::date_time::DateTimeComponent::new(
+        board_kernel,
+        mux_alarm,
+        &base_peripherals.rtc,
+        true,
+    )
+    .finalize(components::date_time_component_helper!(
+        stm32f429zi::rtc::Rtc<'static>,
+        stm32f429zi::tim2::Tim2
+    ));
+
     // GPIO
     let gpio = GpioComponent::new(
         board_kernel,
@@ -517,6 +539,7 @@ pub unsafe fn reset_handler() {
         temperature: temp,
         button: button,
         alarm: alarm,
+        date_time: date_time,
         gpio: gpio,
     };
 
@@ -559,6 +582,11 @@ pub unsafe fn reset_handler() {
         debug!("{:?}", err);
     });
 
+    // Uncomment this to have the independent watchdog reset the chip if the
+    // kernel hangs. It cannot be stopped once started and keeps counting
+    // while the chip sleeps, so the kernel must wake up at least every 30 s.
+    // chip.enable_watchdog();
+
     let scheduler = components::sched::round_robin::RoundRobinComponent::new(&PROCESSES)
         .finalize(components::rr_component_helper!(NUM_PROCS));
 
@@ -0,0 +1,41 @@
+use core::fmt::Write;
+use kernel::debug;
+use kernel::debug::IoWrite;
+use std::io::{self, Write as _};
+use std::panic;
+use std::process;
+
+use crate::CHIP;
+use crate::PROCESSES;
+
+/// Writes panic output to stderr, so that it is not mixed with the output of
+/// the console.
+struct Writer {}
+
+static mut WRITER: Writer = Writer {};
+
+impl Write for Writer {
+    fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+impl IoWrite for Writer {
+    fn write(&mut self, buf: &[u8]) {
+        let _ = io::stderr().write_all(buf);
+    }
+}
+
+/// Installs a panic hook that prints the kernel and process state, like the
+/// panic handlers of other boards, and exits with a failure status.
+pub fn set_panic_hook() {
+    panic::set_hook(Box::new(|pi| unsafe {
+        let writer = &mut WRITER;
+        debug::panic_banner(writer, pi);
+        debug::flush(writer);
+        debug::panic_cpu_state(&CHIP, writer);
+        debug::panic_process_info(&PROCESSES, writer);
+        process::exit(1);
+    }));
+}
@@ -0,0 +1,280 @@
+//! Board file for running Tock as a process on a Linux host.
+//!
+//! The board uses the emulated chip of the `posix` crate, and loads apps from
+//! TBF files given on the command line:
+//!
+//! ```text
+//! posix_board [--flash FILE] [--disk IMAGE] [APP.tbf ...]
+//! ```
+//!
+//! Apps are placed at the addresses of the HiFive1 board, so RISC-V apps built
+//! for it run unmodified. The console is connected to stdin and stdout. With
+//! `--flash`, the flash that apps access through the nonvolatile storage
+//! driver is kept in `FILE` across runs. With `--disk`, apps can access the
+//! files of the FAT volume in the disk image `IMAGE`, for example one created
+//! with `mkfs.fat`, through the FAT filesystem driver.
+//!
+//! The kernel exits once nothing is left that could ever wake it up, which
+//! makes the board suitable for running capsule and app tests in CI.
+
+use capsules::virtual_alarm::VirtualMuxAlarm;
+use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+use kernel::Chip;
+use kernel::Platform;
+use kernel::{create_capability, debug, static_init};
+use posix::chip::PosixDefaultPeripherals;
+use posix::clock::Clock;
+use std::env;
+use std::fs;
+use std::process;
+
+pub mod io;
+
+pub const NUM_PROCS: usize = 4;
+
+// Actual memory for holding the active process structures.
+static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; NUM_PROCS] =
+    [None; NUM_PROCS];
+
+// Reference to the chip for panic dumps.
+static mut CHIP: Option<&'static posix::chip::Posix<VirtualMuxAlarm<'static, Clock<'static>>>> =
+    None;
+
+// How should the kernel respond when a process faults.
+const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
+
+/// Address of the app flash. This is where apps are placed on the HiFive1.
+const APP_FLASH_ADDRESS: usize = 0x2004_0000;
+const APP_FLASH_SIZE: usize = 0x10_0000;
+
+/// Address of the app memory. This is where apps are placed on the HiFive1.
+const APP_MEMORY_ADDRESS: usize = 0x8000_2800;
+const APP_MEMORY_SIZE: usize = 0x2_0000;
+
+/// Number of pages of the flash for the nonvolatile storage driver.
+const FLASH_PAGES: usize = 64;
+
+/// A structure representing this platform that holds references to all
+/// capsules for this platform.
+struct PosixBoard {
+    console: &'static capsules::console::Console<'static>,
+    lldb: &'static capsules::low_level_debug::LowLevelDebug<
+        'static,
+        capsules::virtual_uart::UartDevice<'static>,
+    >,
+    alarm: &'static capsules::alarm::AlarmDriver<'static, VirtualMuxAlarm<'static, Clock<'static>>>,
+    app_watchdog: &'static capsules::app_watchdog::AppWatchdog<
+        'static,
+        VirtualMuxAlarm<'static, Clock<'static>>,
+        components::app_watchdog::Capability,
+    >,
+    date_time: &'static capsules::date_time::DateTimeDriver<
+        'static,
+        capsules::counter_date_time::CounterDateTime<'static, Clock<'static>>,
+        VirtualMuxAlarm<'static, Clock<'static>>,
+    >,
+    nonvolatile_storage: &'static capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>,
+    fat: &'static capsules::fat::FatFs<'static, posix::disk::Disk<'static>>,
+}
+
+/// Mapping of integer syscalls to objects that implement syscalls.
+impl Platform for PosixBoard {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn kernel::Driver>) -> R,
+    {
+        match driver_num {
+            capsules::console::DRIVER_NUM => f(Some(self.console)),
+            capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
+            capsules::low_level_debug::DRIVER_NUM => f(Some(self.lldb)),
+            capsules::app_watchdog::DRIVER_NUM => f(Some(self.app_watchdog)),
+            capsules::date_time::DRIVER_NUM => f(Some(self.date_time)),
+            capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
+            capsules::fat::DRIVER_NUM => f(Some(self.fat)),
+            _ => f(None),
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: posix_board [--flash FILE] [--disk IMAGE] [APP.tbf ...]");
+    process::exit(2);
+}
+
+fn main() {
+    let mut flash_file = None;
+    let mut disk_image = None;
+    let mut apps = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--flash" => flash_file = Some(args.next().unwrap_or_else(|| usage())),
+            "--disk" => disk_image = Some(args.next().unwrap_or_else(|| usage())),
+            "-h" | "--help" => usage(),
+            _ => apps.push(arg),
+        }
+    }
+
+    // Concatenate the apps in flash. Each TBF file holds a single app, and
+    // its header records the size of the file.
+    let mut app_images = Vec::new();
+    for app in apps.iter() {
+        match fs::read(app) {
+            Ok(image) => app_images.extend(image),
+            Err(err) => {
+                eprintln!("posix_board: cannot read {}: {}", app, err);
+                process::exit(1);
+            }
+        }
+    }
+    if app_images.len() > APP_FLASH_SIZE {
+        eprintln!("posix_board: apps do not fit in {} bytes", APP_FLASH_SIZE);
+        process::exit(1);
+    }
+
+    io::set_panic_hook();
+    unsafe { run(&app_images, flash_file, disk_image) }
+}
+
+unsafe fn run(app_images: &[u8], flash_file: Option<String>, disk_image: Option<String>) -> ! {
+    let app_flash = posix::memory::map(APP_FLASH_ADDRESS, APP_FLASH_SIZE)
+        .unwrap_or_else(|err| panic!("cannot map app flash: {}", err));
+    app_flash[..app_images.len()].copy_from_slice(app_images);
+    let app_memory = posix::memory::map(APP_MEMORY_ADDRESS, APP_MEMORY_SIZE)
+        .unwrap_or_else(|err| panic!("cannot map app memory: {}", err));
+
+    let peripherals = static_init!(
+        PosixDefaultPeripherals,
+        PosixDefaultPeripherals::new(FLASH_PAGES)
+    );
+    if let Some(path) = flash_file {
+        peripherals
+            .flash
+            .attach_file(&path)
+            .unwrap_or_else(|err| panic!("cannot use {} as flash: {}", path, err));
+    }
+    if let Some(path) = disk_image {
+        peripherals
+            .disk
+            .attach_file(&path)
+            .unwrap_or_else(|err| panic!("cannot use {} as disk: {}", path, err));
+    }
+
+    // initialize capabilities
+    let process_mgmt_cap = create_capability!(capabilities::ProcessManagementCapability);
+    let main_loop_cap = create_capability!(capabilities::MainLoopCapability);
+
+    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+
+    let dynamic_deferred_call_clients =
+        static_init!([DynamicDeferredCallClientState; 2], Default::default());
+    let dynamic_deferred_caller = static_init!(
+        DynamicDeferredCall,
+        DynamicDeferredCall::new(dynamic_deferred_call_clients)
+    );
+    DynamicDeferredCall::set_global_instance(dynamic_deferred_caller);
+
+    // Create a shared UART channel for the console and for kernel debug.
+    let uart_mux = components::console::UartMuxComponent::new(
+        &peripherals.uart0,
+        115200,
+        dynamic_deferred_caller,
+    )
+    .finalize(());
+
+    // Create a shared virtualization mux layer on top of the simulated clock.
+    let mux_alarm = components::alarm::AlarmMuxComponent::new(&peripherals.clock)
+        .finalize(components::alarm_mux_component_helper!(Clock));
+    let alarm = components::alarm::AlarmDriverComponent::new(board_kernel, mux_alarm)
+        .finalize(components::alarm_component_helper!(Clock));
+    let app_watchdog = components::app_watchdog::AppWatchdogComponent::new(board_kernel, mux_alarm)
+        .finalize(components::app_watchdog_component_helper!(Clock));
+
+    // Simulated time starts at 0 on every run, so the date and time has to
+    // be set by an app before it can be read.
+    let counter_date_time =
+        components::date_time::CounterDateTimeComponent::new(&peripherals.clock)
+            .finalize(components::counter_date_time_component_helper!(Clock));
+    let date_time = components::date_time::DateTimeComponent::new(
+        board_kernel,
+        mux_alarm,
+        counter_date_time,
+        true,
+    )
+    .finalize(components::date_time_component_helper!(
+        capsules::counter_date_time::CounterDateTime<'static, Clock>,
+        Clock
+    ));
+
+    let systick_virtual_alarm = static_init!(
+        VirtualMuxAlarm<'static, Clock>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+
+    let chip = static_init!(
+        posix::chip::Posix<VirtualMuxAlarm<'static, Clock>>,
+        posix::chip::Posix::new(systick_virtual_alarm, peripherals)
+    );
+    systick_virtual_alarm.set_alarm_client(chip.scheduler_timer());
+    CHIP = Some(chip);
+
+    // Setup the console.
+    let console = components::console::ConsoleComponent::new(board_kernel, uart_mux).finalize(());
+    // Create the debugger object that handles calls to `debug!()`.
+    components::debug_writer::DebugWriterComponent::new(uart_mux).finalize(());
+
+    let lldb = components::lldb::LowLevelDebugComponent::new(board_kernel, uart_mux).finalize(());
+
+    // All of the flash is accessible to apps.
+    let flash_size = peripherals.flash.num_pages() * posix::flash::PAGE_SIZE;
+    let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageComponent::new(
+        board_kernel,
+        &peripherals.flash,
+        0,
+        flash_size,
+        flash_size,
+        0,
+    )
+    .finalize(components::nv_storage_component_helper!(
+        posix::flash::Flash
+    ));
+
+    // Without a disk image, the filesystem reports that there is no medium.
+    let fat = components::fat::FatFsComponent::new(board_kernel, &peripherals.disk).finalize(
+        components::fat_component_helper!(posix::disk::Disk<'static>),
+    );
+
+    debug!("POSIX initialization complete.");
+    debug!("Entering main loop.");
+
+    let board = PosixBoard {
+        console,
+        lldb,
+        alarm,
+        app_watchdog,
+        date_time,
+        nonvolatile_storage,
+        fat,
+    };
+
+    kernel::procs::load_processes(
+        board_kernel,
+        chip,
+        app_flash,
+        app_memory,
+        &mut PROCESSES,
+        FAULT_RESPONSE,
+        &process_mgmt_cap,
+    )
+    .unwrap_or_else(|err| {
+        debug!("Error loading processes!");
+        debug!("{:?}", err);
+    });
+
+    let scheduler = components::sched::round_robin::RoundRobinComponent::new(&PROCESSES)
+        .finalize(components::rr_component_helper!(NUM_PROCS));
+    board_kernel.kernel_loop(&board, chip, None, scheduler, &main_loop_cap);
+}
@@ -113,7 +113,7 @@ pub unsafe fn reset_handler() {
     cortexm7::nvic::Nvic::new(imxrt1060::nvic::GPT1).enable();
     cortexm7::nvic::Nvic::new(imxrt1060::nvic::LPUART2).enable();
 
-    let chip = static_init!(Chip, Chip::new(peripherals));
+    let chip = static_init!(Chip, Chip::new(peripherals.ccm, peripherals));
     CHIP = Some(chip);
 
     // Start loading the kernel
@@ -0,0 +1,74 @@
+//! Application credentials checker that verifies SHA-256 hashes.
+//!
+//! This checker accepts a process if one of its TBF footers contains a SHA-256
+//! credential that matches the hash of the process's TBF header and binary. A
+//! SHA-256 credential that does not match causes the process to be rejected.
+//! Credentials in any other format are passed on.
+//!
+//! A hash only protects against corrupted or truncated images, not against
+//! someone deliberately installing a different application. Boards that need
+//! the latter should use a checker that verifies signatures.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let checker = static_init!(
+//!     capsules::app_checker_sha256::AppCheckerSha256,
+//!     capsules::app_checker_sha256::AppCheckerSha256::new(true)
+//! );
+//! kernel::procs::load_processes_with_checker(
+//!     board_kernel,
+//!     chip,
+//!     app_flash,
+//!     app_memory,
+//!     &mut PROCESSES,
+//!     FAULT_RESPONSE,
+//!     checker,
+//!     &process_management_capability,
+//! );
+//! ```
+
+use crate::sha::sha256;
+use kernel::procs::{
+    AppCredentialsChecker, CheckResult, TbfFooterV2Credentials, TbfFooterV2CredentialsType,
+};
+
+pub struct AppCheckerSha256 {
+    require_credentials: bool,
+}
+
+impl AppCheckerSha256 {
+    /// Create a new checker. If `require_credentials` is `true`, processes
+    /// without a SHA-256 credential are not loaded.
+    pub const fn new(require_credentials: bool) -> AppCheckerSha256 {
+        AppCheckerSha256 {
+            require_credentials,
+        }
+    }
+}
+
+impl AppCredentialsChecker for AppCheckerSha256 {
+    fn require_credentials(&self) -> bool {
+        self.require_credentials
+    }
+
+    fn check_credentials(
+        &self,
+        credentials: TbfFooterV2Credentials,
+        binary: &'static [u8],
+    ) -> CheckResult {
+        match credentials.format() {
+            TbfFooterV2CredentialsType::SHA256 => {
+                if sha256(binary)[..] == credentials.data()[..] {
+                    CheckResult::Accept
+                } else {
+                    CheckResult::Reject
+                }
+            }
+            _ => CheckResult::Pass,
+        }
+    }
+}
@@ -0,0 +1,506 @@
+//! Loads new applications over a UART without reflashing the kernel.
+//!
+//! A host sends a TBF object (an app as built by elf2tab) to the board in
+//! chunks. `AppLoader` writes it to the free app flash after the apps that
+//! are already installed, and then has the kernel's `DynamicProcessLoader`
+//! create a process for it. The new app starts running right away, and is
+//! loaded again by `load_processes()` when the board next boots.
+//!
+//! The UART can be a real UART, a virtual UART on a shared mux, or the USB CDC
+//! serial device.
+//!
+//! Protocol
+//! --------
+//!
+//! Every message from the host is a three byte header followed by a payload:
+//!
+//! ```text
+//! +--------+-----------------------------+------------------+
+//! | opcode | payload length (u16 little) | payload ...      |
+//! +--------+-----------------------------+------------------+
+//! ```
+//!
+//! The payload must fit in the receive buffer the board gives `AppLoader`.
+//! The board answers every message with a single status byte, and the host
+//! must wait for it before sending the next message. The messages are:
+//!
+//! - `0x01` DATA: The next chunk of the TBF object. The first chunk of an app
+//!   must be at least 8 bytes long, as the start of the TBF header decides
+//!   where the app is placed.
+//! - `0x02` LOAD: All of the TBF object was sent. The app is finished in flash
+//!   and a process is created for it.
+//! - `0x03` ABORT: Forget about the app that is being sent.
+//!
+//! The status bytes are:
+//!
+//! - `0x00`: Success.
+//! - `0x01`: The message was not expected or had a bad length. Sending the
+//!   app must start again.
+//! - `0x02`: The TBF header of the app is not valid.
+//! - `0x03`: There is not enough app flash left, or no free process slot.
+//! - `0x04`: Writing flash failed.
+//! - `0x05`: The app was written but the kernel could not create a process
+//!   for it.
+//! - `0x06`: The app was written but its credentials were not accepted, so
+//!   the kernel did not create a process for it.
+//!
+//! Apps are written so that a partial app is never mistaken for a valid one:
+//! the first eight bytes of its TBF header are only written after the rest of
+//! the app is in flash.
+//!
+//! Usage
+//! -----
+//!
+//! The app flash region must start on a flash page boundary.
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let loader = static_init!(
+//!     kernel::procs::DynamicProcessLoader<nrf52840::chip::Chip>,
+//!     kernel::procs::DynamicProcessLoader::new(
+//!         board_kernel,
+//!         chip,
+//!         app_flash,
+//!         &mut DYNAMIC_APP_MEMORY,
+//!         &mut PROCESSES,
+//!         FAULT_RESPONSE,
+//!         None,
+//!     )
+//! );
+//! let app_loader = static_init!(
+//!     capsules::app_loader::AppLoader<'static, nrf52840::nvmc::Nvmc, nrf52840::chip::Chip, Capability>,
+//!     capsules::app_loader::AppLoader::new(
+//!         loader_uart,
+//!         &nrf52840::nvmc::NVMC,
+//!         loader,
+//!         &mut capsules::app_loader::TX_BUF,
+//!         &mut capsules::app_loader::RX_BUF,
+//!         page_buffer,
+//!         Capability,
+//!     )
+//! );
+//! hil::flash::HasClient::set_client(&nrf52840::nvmc::NVMC, app_loader);
+//! loader_uart.set_transmit_client(app_loader);
+//! loader_uart.set_receive_client(app_loader);
+//! app_loader.start();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::debug;
+use kernel::hil::flash::{self, Flash};
+use kernel::hil::uart;
+use kernel::procs::{AppPlacement, DynamicProcessLoader, ProcessLoadError};
+use kernel::{Chip, ReturnCode};
+
+pub static mut TX_BUF: [u8; 1] = [0; 1];
+pub static mut RX_BUF: [u8; 512] = [0; 512];
+
+const MESSAGE_HEADER_LEN: usize = 3;
+
+const OPCODE_DATA: u8 = 0x01;
+const OPCODE_LOAD: u8 = 0x02;
+const OPCODE_ABORT: u8 = 0x03;
+
+const RESPONSE_OK: u8 = 0x00;
+const RESPONSE_INVALID: u8 = 0x01;
+const RESPONSE_BAD_HEADER: u8 = 0x02;
+const RESPONSE_NO_SPACE: u8 = 0x03;
+const RESPONSE_FLASH_ERROR: u8 = 0x04;
+const RESPONSE_LOAD_FAILED: u8 = 0x05;
+const RESPONSE_CREDENTIALS_REJECTED: u8 = 0x06;
+
+/// Number of bytes at the start of the TBF header that are written last.
+const HEADER_START_LEN: usize = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// Waiting for the header of the next message.
+    MessageHeader,
+    /// Waiting for the payload of a message.
+    MessagePayload,
+    /// Writing a DATA chunk to flash.
+    WritingData,
+    /// Writing the rest of the last page of the app.
+    WritingTail,
+    /// Writing the page with the start of the TBF header.
+    WritingHeaderStart,
+}
+
+pub struct AppLoader<'a, F: Flash + 'static, C: Chip + 'static, P: ProcessManagementCapability> {
+    uart: &'a dyn uart::UartData<'a>,
+    flash: &'a F,
+    loader: &'a DynamicProcessLoader<C>,
+    capability: P,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    pagebuffer: TakeCell<'static, F::Page>,
+    page_size: usize,
+    state: Cell<State>,
+    /// Opcode of the message being received.
+    opcode: Cell<u8>,
+    /// Where the app that is being sent goes, once its first chunk arrived.
+    placement: OptionalCell<AppPlacement>,
+    /// Address of the next byte to put in the page buffer.
+    cursor: Cell<usize>,
+    /// How much of the received chunk was put in the page buffer.
+    rx_position: Cell<usize>,
+    rx_length: Cell<usize>,
+    /// Where writing the app ends, at a page boundary.
+    tail_end: Cell<usize>,
+    /// The start of the TBF header, held back until the app is in flash.
+    header_start: Cell<[u8; HEADER_START_LEN]>,
+}
+
+impl<'a, F: Flash + 'static, C: Chip + 'static, P: ProcessManagementCapability>
+    AppLoader<'a, F, C, P>
+{
+    pub fn new(
+        uart: &'a dyn uart::UartData<'a>,
+        flash: &'a F,
+        loader: &'a DynamicProcessLoader<C>,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+        pagebuffer: &'static mut F::Page,
+        capability: P,
+    ) -> AppLoader<'a, F, C, P> {
+        let page_size = pagebuffer.as_mut().len();
+        AppLoader {
+            uart,
+            flash,
+            loader,
+            capability,
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            pagebuffer: TakeCell::new(pagebuffer),
+            page_size,
+            state: Cell::new(State::MessageHeader),
+            opcode: Cell::new(0),
+            placement: OptionalCell::empty(),
+            cursor: Cell::new(0),
+            rx_position: Cell::new(0),
+            rx_length: Cell::new(0),
+            tail_end: Cell::new(0),
+            header_start: Cell::new([0xFF; HEADER_START_LEN]),
+        }
+    }
+
+    /// Start listening for messages from the host.
+    pub fn start(&self) -> ReturnCode {
+        self.receive_message_header()
+    }
+
+    fn receive_message_header(&self) -> ReturnCode {
+        self.rx_buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            self.state.set(State::MessageHeader);
+            let (rcode, buffer) = self.uart.receive_buffer(buffer, MESSAGE_HEADER_LEN);
+            buffer.map(|buffer| self.rx_buffer.replace(buffer));
+            rcode
+        })
+    }
+
+    /// Send a status byte to the host. The next message is received once it
+    /// is sent.
+    fn respond(&self, response: u8) {
+        if response != RESPONSE_OK {
+            self.placement.clear();
+        }
+        if let Some(buffer) = self.tx_buffer.take() {
+            buffer[0] = response;
+            if let (_, Some(buffer)) = self.uart.transmit_buffer(buffer, 1) {
+                self.tx_buffer.replace(buffer);
+                self.receive_message_header();
+            }
+        }
+    }
+
+    fn load_error_response(error: ProcessLoadError) -> u8 {
+        match error {
+            ProcessLoadError::TbfHeaderParseFailure(_) => RESPONSE_BAD_HEADER,
+            ProcessLoadError::NotEnoughFlash | ProcessLoadError::NoFreeProcessSlot => {
+                RESPONSE_NO_SPACE
+            }
+            ProcessLoadError::CredentialsNotAccepted => RESPONSE_CREDENTIALS_REJECTED,
+            _ => RESPONSE_LOAD_FAILED,
+        }
+    }
+
+    fn handle_message(&self, opcode: u8, length: usize) {
+        match opcode {
+            OPCODE_DATA => {
+                if self.placement.is_none() && !self.start_app(length) {
+                    return;
+                }
+                self.rx_position.set(0);
+                self.rx_length.set(length);
+                self.state.set(State::WritingData);
+                self.fill_pages();
+            }
+            OPCODE_LOAD => match self.placement.map(|placement| *placement) {
+                Some(placement) if length == 0 && self.cursor.get() == placement.end() => {
+                    self.tail_end.set(self.tail_end(placement));
+                    self.state.set(State::WritingTail);
+                    if self.cursor.get() < self.tail_end.get() {
+                        self.fill_pages();
+                    } else {
+                        // The app ended at a page boundary and that page was
+                        // already written.
+                        self.write_header_start(placement);
+                    }
+                }
+                _ => self.respond(RESPONSE_INVALID),
+            },
+            OPCODE_ABORT => {
+                self.placement.clear();
+                self.respond(RESPONSE_OK);
+            }
+            _ => self.respond(RESPONSE_INVALID),
+        }
+    }
+
+    /// Find where the app goes from the start of its header, which is in the
+    /// receive buffer. Returns `false`, after responding to the host, if the
+    /// app cannot be loaded.
+    fn start_app(&self, length: usize) -> bool {
+        let result = self.rx_buffer.map_or(Err(RESPONSE_INVALID), |buffer| {
+            self.loader
+                .check_header(&buffer[..length], &self.capability)
+                .map_err(Self::load_error_response)
+        });
+        let placement = match result {
+            Ok(placement) => placement,
+            Err(response) => {
+                self.respond(response);
+                return false;
+            }
+        };
+
+        // The bytes of the first page before the app are kept as they are.
+        let page_size = self.page_size;
+        let page_start = placement.write_start() / page_size * page_size;
+        let app_flash = self.loader.app_flash();
+        let flash_start = app_flash.as_ptr() as usize;
+        if page_start < flash_start {
+            self.respond(RESPONSE_INVALID);
+            return false;
+        }
+        self.pagebuffer.map(|pagebuffer| {
+            let before = placement.write_start() - page_start;
+            let offset = page_start - flash_start;
+            pagebuffer.as_mut()[..before].copy_from_slice(&app_flash[offset..offset + before]);
+        });
+
+        self.cursor.set(placement.write_start());
+        self.header_start.set([0xFF; HEADER_START_LEN]);
+        self.placement.set(placement);
+        true
+    }
+
+    /// Where to stop writing after the app. This is the end of the page the app
+    /// ends in. If the app ends at a page boundary and what follows it in
+    /// flash looks like another TBF header, perhaps from an app that failed to
+    /// load, one more page is erased so that the app list ends after this app.
+    fn tail_end(&self, placement: AppPlacement) -> usize {
+        let page_size = self.page_size;
+        let end = (placement.end() + page_size - 1) / page_size * page_size;
+        let app_flash = self.loader.app_flash();
+        let flash_start = app_flash.as_ptr() as usize;
+        match app_flash.get(end - flash_start..end - flash_start + 2) {
+            Some(&[0x02, 0x00]) => cmp::min(end + page_size, flash_start + app_flash.len()),
+            _ => end,
+        }
+    }
+
+    /// Returns the byte that goes at `address`, or `None` if it has not been
+    /// received yet.
+    fn next_byte(&self, placement: AppPlacement, address: usize) -> Option<u8> {
+        if address < placement.address() {
+            let padding = placement.padding_header().unwrap_or([0xFF; 16]);
+            let offset = address - placement.write_start();
+            Some(*padding.get(offset).unwrap_or(&0xFF))
+        } else if address < placement.end() {
+            if self.state.get() != State::WritingData
+                || self.rx_position.get() >= self.rx_length.get()
+            {
+                return None;
+            }
+            let byte = self
+                .rx_buffer
+                .map_or(0xFF, |buffer| buffer[self.rx_position.get()]);
+            self.rx_position.set(self.rx_position.get() + 1);
+
+            let offset = address - placement.address();
+            if offset < HEADER_START_LEN {
+                let mut header_start = self.header_start.get();
+                header_start[offset] = byte;
+                self.header_start.set(header_start);
+                Some(0xFF)
+            } else {
+                Some(byte)
+            }
+        } else if self.state.get() == State::WritingTail && address < self.tail_end.get() {
+            Some(0xFF)
+        } else {
+            None
+        }
+    }
+
+    /// Put as much of the app as is available in the page buffer, and write
+    /// the page to flash if it is full.
+    fn fill_pages(&self) {
+        let placement = match self.placement.map(|placement| *placement) {
+            Some(placement) => placement,
+            None => return self.respond(RESPONSE_INVALID),
+        };
+        let page_size = self.page_size;
+        let page_start = self.cursor.get() / page_size * page_size;
+
+        let full = self.pagebuffer.map_or(false, |pagebuffer| {
+            let page = pagebuffer.as_mut();
+            while self.cursor.get() < page_start + page_size {
+                match self.next_byte(placement, self.cursor.get()) {
+                    Some(byte) => {
+                        page[self.cursor.get() - page_start] = byte;
+                        self.cursor.set(self.cursor.get() + 1);
+                    }
+                    None => break,
+                }
+            }
+            self.cursor.get() == page_start + page_size
+        });
+
+        if full {
+            self.write_page(page_start / page_size);
+        } else if self.state.get() == State::WritingData
+            && self.rx_position.get() < self.rx_length.get()
+        {
+            // The host sent more than the length in the TBF header.
+            self.respond(RESPONSE_INVALID);
+        } else {
+            self.respond(RESPONSE_OK);
+        }
+    }
+
+    fn write_page(&self, page_number: usize) {
+        if let Some(pagebuffer) = self.pagebuffer.take() {
+            if let Err((_, pagebuffer)) = self.flash.write_page(page_number, pagebuffer) {
+                self.pagebuffer.replace(pagebuffer);
+                self.respond(RESPONSE_FLASH_ERROR);
+            }
+        }
+    }
+
+    /// Write the page with the start of the TBF header, now that the rest of
+    /// the app is in flash.
+    fn write_header_start(&self, placement: AppPlacement) {
+        let page_size = self.page_size;
+        let page_start = placement.address() / page_size * page_size;
+        let app_flash = self.loader.app_flash();
+        let offset = page_start - app_flash.as_ptr() as usize;
+        let header_offset = placement.address() - page_start;
+
+        self.pagebuffer.map(|pagebuffer| {
+            let page = pagebuffer.as_mut();
+            page.copy_from_slice(&app_flash[offset..offset + page_size]);
+            page[header_offset..header_offset + HEADER_START_LEN]
+                .copy_from_slice(&self.header_start.get());
+        });
+        self.state.set(State::WritingHeaderStart);
+        self.write_page(page_start / page_size);
+    }
+
+    fn finish_load(&self) {
+        self.placement.clear();
+        match self.loader.load(&self.capability) {
+            Ok(_) => self.respond(RESPONSE_OK),
+            Err(error) => {
+                debug!("AppLoader: loading app failed: {:?}", error);
+                self.respond(Self::load_error_response(error));
+            }
+        }
+    }
+}
+
+impl<'a, F: Flash + 'static, C: Chip + 'static, P: ProcessManagementCapability> uart::TransmitClient
+    for AppLoader<'a, F, C, P>
+{
+    fn transmitted_buffer(&self, buffer: &'static mut [u8], _tx_len: usize, _rcode: ReturnCode) {
+        self.tx_buffer.replace(buffer);
+        self.receive_message_header();
+    }
+}
+
+impl<'a, F: Flash + 'static, C: Chip + 'static, P: ProcessManagementCapability> uart::ReceiveClient
+    for AppLoader<'a, F, C, P>
+{
+    fn received_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        rx_len: usize,
+        _rcode: ReturnCode,
+        error: uart::Error,
+    ) {
+        if error != uart::Error::None {
+            self.rx_buffer.replace(buffer);
+            self.respond(RESPONSE_INVALID);
+            return;
+        }
+
+        match self.state.get() {
+            State::MessageHeader => {
+                let opcode = buffer[0];
+                let length = buffer[1] as usize | (buffer[2] as usize) << 8;
+                if length > buffer.len() {
+                    self.rx_buffer.replace(buffer);
+                    self.respond(RESPONSE_INVALID);
+                } else if length == 0 {
+                    self.rx_buffer.replace(buffer);
+                    self.handle_message(opcode, 0);
+                } else {
+                    self.opcode.set(opcode);
+                    self.state.set(State::MessagePayload);
+                    if let (_, Some(buffer)) = self.uart.receive_buffer(buffer, length) {
+                        self.rx_buffer.replace(buffer);
+                        self.respond(RESPONSE_INVALID);
+                    }
+                }
+            }
+            _ => {
+                self.rx_buffer.replace(buffer);
+                self.handle_message(self.opcode.get(), rx_len);
+            }
+        }
+    }
+}
+
+impl<'a, F: Flash + 'static, C: Chip + 'static, P: ProcessManagementCapability> flash::Client<F>
+    for AppLoader<'a, F, C, P>
+{
+    fn read_complete(&self, pagebuffer: &'static mut F::Page, _error: flash::Error) {
+        self.pagebuffer.replace(pagebuffer);
+    }
+
+    fn write_complete(&self, pagebuffer: &'static mut F::Page, error: flash::Error) {
+        self.pagebuffer.replace(pagebuffer);
+        if error != flash::Error::CommandComplete {
+            self.respond(RESPONSE_FLASH_ERROR);
+            return;
+        }
+
+        match self.state.get() {
+            State::WritingHeaderStart => self.finish_load(),
+            State::WritingTail if self.cursor.get() >= self.tail_end.get() => {
+                match self.placement.map(|placement| *placement) {
+                    Some(placement) => self.write_header_start(placement),
+                    None => self.respond(RESPONSE_INVALID),
+                }
+            }
+            _ => self.fill_pages(),
+        }
+    }
+
+    fn erase_complete(&self, _error: flash::Error) {}
+}
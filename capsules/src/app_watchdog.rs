@@ -0,0 +1,251 @@
+//! Watchdog for processes.
+//!
+//! A process registers a heartbeat interval, and then has to send a heartbeat
+//! at least once per interval. If it misses its deadline, the capsule makes
+//! the process fault with `FaultReason::MissedHeartbeat`, and the kernel then
+//! applies the process's `FaultResponse`: it restarts it, stops it, or
+//! panics. Processes that are stopped, for example from the process console,
+//! are not faulted; their deadline is pushed back by one interval instead.
+//!
+//! This only watches processes. To also reset the chip if the kernel itself
+//! hangs, the board enables the chip's hardware watchdog, which the kernel
+//! feeds from its main loop.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::{capabilities, create_capability, static_init};
+//! # use capsules::app_watchdog::AppWatchdog;
+//! # use capsules::virtual_alarm::VirtualMuxAlarm;
+//!
+//! struct ProcessMgmtCap;
+//! unsafe impl capabilities::ProcessManagementCapability for ProcessMgmtCap {}
+//!
+//! let watchdog_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let app_watchdog = static_init!(
+//!     AppWatchdog<'static, VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>, ProcessMgmtCap>,
+//!     AppWatchdog::new(
+//!         watchdog_alarm,
+//!         board_kernel,
+//!         ProcessMgmtCap,
+//!         board_kernel.create_grant(&memory_allocation_capability)
+//!     )
+//! );
+//! watchdog_alarm.set_alarm_client(app_watchdog);
+//! // Also reset the chip if the kernel hangs.
+//! chip.enable_watchdog();
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Command 0: check whether the driver exists.
+//! - Command 1: start watching the process, which must send a heartbeat at
+//!   least every `arg1` milliseconds from now on. Returns `EINVAL` if the
+//!   interval is 0 or too long for the alarm. Calling it again changes the
+//!   interval and restarts the deadline.
+//! - Command 2: heartbeat, which restarts the deadline. Returns `EOFF` if the
+//!   process is not being watched.
+//! - Command 3: stop watching the process.
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::debug;
+use kernel::hil::time::{self, Alarm, Ticks};
+use kernel::procs::{FaultReason, State};
+use kernel::{AppId, Driver, Grant, Kernel, ReturnCode};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::AppWatchdog as usize;
+
+#[derive(Copy, Clone)]
+struct Deadline<T: Ticks> {
+    /// When the interval started.
+    reference: T,
+    interval: T,
+}
+
+impl<T: Ticks> Deadline<T> {
+    fn expired(&self, now: T) -> bool {
+        !now.within_range(self.reference, self.reference.wrapping_add(self.interval))
+    }
+}
+
+pub struct App<T: Ticks> {
+    deadline: Option<Deadline<T>>,
+}
+
+impl<T: Ticks> Default for App<T> {
+    fn default() -> App<T> {
+        App { deadline: None }
+    }
+}
+
+pub struct AppWatchdog<'a, A: Alarm<'a>, C: ProcessManagementCapability> {
+    alarm: &'a A,
+    kernel: &'static Kernel,
+    capability: C,
+    apps: Grant<App<A::Ticks>>,
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> AppWatchdog<'a, A, C> {
+    pub fn new(
+        alarm: &'a A,
+        kernel: &'static Kernel,
+        capability: C,
+        grant: Grant<App<A::Ticks>>,
+    ) -> AppWatchdog<'a, A, C> {
+        AppWatchdog {
+            alarm,
+            kernel,
+            capability,
+            apps: grant,
+        }
+    }
+
+    /// Arm the alarm for the earliest deadline of all processes, or disarm
+    /// it if no process is being watched.
+    fn reset_alarm(&self) {
+        let now = self.alarm.now();
+        let mut earliest: Option<A::Ticks> = None;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if let Some(deadline) = app.deadline {
+                    let remaining = if deadline.expired(now) {
+                        A::Ticks::from(0)
+                    } else {
+                        deadline
+                            .reference
+                            .wrapping_add(deadline.interval)
+                            .wrapping_sub(now)
+                    };
+                    if earliest.map_or(true, |earliest| remaining < earliest) {
+                        earliest = Some(remaining);
+                    }
+                }
+            });
+        }
+        match earliest {
+            Some(remaining) => self.alarm.set_alarm(now, remaining),
+            None => {
+                self.alarm.disarm();
+            }
+        }
+    }
+
+    /// Handle a process that missed its deadline.
+    fn missed_deadline(&self, appid: AppId, now: A::Ticks) {
+        let interval = self
+            .apps
+            .enter(appid, |app, _| app.deadline.take().map(|d| d.interval));
+        let interval = match interval {
+            Ok(Some(interval)) => interval,
+            _ => return,
+        };
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.appid() != appid {
+                    return;
+                }
+                match process.get_state() {
+                    State::StoppedRunning | State::StoppedYielded => {
+                        let _ = self.apps.enter(appid, |app, _| {
+                            app.deadline = Some(Deadline {
+                                reference: now,
+                                interval,
+                            });
+                        });
+                    }
+                    _ => {
+                        debug!(
+                            "Process {} missed its watchdog deadline",
+                            process.get_process_name()
+                        );
+                        process.set_fault_state_with_reason(FaultReason::MissedHeartbeat);
+                    }
+                }
+            });
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> time::AlarmClient for AppWatchdog<'a, A, C> {
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        // Faulting a process can free its grant, so handle one process at a
+        // time and look for the next one afterwards.
+        loop {
+            let mut missed = None;
+            for app in self.apps.iter() {
+                app.enter(|app, _| {
+                    if missed.is_none() && app.deadline.map_or(false, |d| d.expired(now)) {
+                        missed = Some(app.appid());
+                    }
+                });
+            }
+            match missed {
+                Some(appid) => self.missed_deadline(appid, now),
+                None => break,
+            }
+        }
+        self.reset_alarm();
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> Driver for AppWatchdog<'a, A, C> {
+    /// Control the watchdog of the process.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Start watching the process, with a heartbeat interval of `data`
+    ///   milliseconds.
+    /// - `2`: Heartbeat.
+    /// - `3`: Stop watching the process.
+    fn command(&self, command_num: usize, data: usize, _: usize, appid: AppId) -> ReturnCode {
+        let now = self.alarm.now();
+        let result = self
+            .apps
+            .enter(appid, |app, _| match command_num {
+                0 => ReturnCode::SUCCESS,
+                1 => {
+                    let interval = A::ticks_from_ms(data as u32);
+                    // Keep deadlines well inside the range of the alarm, so
+                    // that a deadline is never mistaken for one in the past.
+                    if interval.into_u32() == 0
+                        || interval.into_u32() > A::Ticks::max_value().into_u32() / 2
+                    {
+                        ReturnCode::EINVAL
+                    } else {
+                        app.deadline = Some(Deadline {
+                            reference: now,
+                            interval,
+                        });
+                        ReturnCode::SUCCESS
+                    }
+                }
+                2 => match app.deadline.as_mut() {
+                    Some(deadline) => {
+                        deadline.reference = now;
+                        ReturnCode::SUCCESS
+                    }
+                    None => ReturnCode::EOFF,
+                },
+                3 => {
+                    app.deadline = None;
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::ENOSUPPORT,
+            })
+            .unwrap_or_else(|err| err.into());
+        // A heartbeat only moves a deadline later, so the alarm can stay
+        // armed for the old one and will be moved when it fires.
+        if result == ReturnCode::SUCCESS && (command_num == 1 || command_num == 3) {
+            self.reset_alarm();
+        }
+        result
+    }
+}
@@ -0,0 +1,491 @@
+//! Attribute Protocol (ATT) server.
+//!
+//! The server answers the requests of the client on the ATT channel of L2CAP,
+//! using an `AttributeDatabase` for the attributes, and sends the
+//! notifications and indications of attribute values the database asks for.
+//! The ATT MTU is the default, 23 bytes, so each ATT PDU fits in a single
+//! link layer PDU with its L2CAP header.
+//!
+//! Only one response or notification is pending at a time: requests that
+//! arrive before the response to the previous one has been sent are refused,
+//! which flow control turns into retransmissions of the link layer.
+//!
+//! Long writes (prepared writes), signed writes and Read Multiple are not
+//! supported.
+//!
+//! BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part F]
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{MapCell, OptionalCell};
+use kernel::ReturnCode;
+
+/// The ATT MTU.
+pub const ATT_MTU: usize = 23;
+
+// Opcodes
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part F], section 3.4.8
+const ERROR_RSP: u8 = 0x01;
+const EXCHANGE_MTU_REQ: u8 = 0x02;
+const EXCHANGE_MTU_RSP: u8 = 0x03;
+const FIND_INFORMATION_REQ: u8 = 0x04;
+const FIND_INFORMATION_RSP: u8 = 0x05;
+const FIND_BY_TYPE_VALUE_REQ: u8 = 0x06;
+const FIND_BY_TYPE_VALUE_RSP: u8 = 0x07;
+const READ_BY_TYPE_REQ: u8 = 0x08;
+const READ_BY_TYPE_RSP: u8 = 0x09;
+const READ_REQ: u8 = 0x0A;
+const READ_RSP: u8 = 0x0B;
+const READ_BLOB_REQ: u8 = 0x0C;
+const READ_BLOB_RSP: u8 = 0x0D;
+const READ_BY_GROUP_TYPE_REQ: u8 = 0x10;
+const READ_BY_GROUP_TYPE_RSP: u8 = 0x11;
+const WRITE_REQ: u8 = 0x12;
+const WRITE_RSP: u8 = 0x13;
+const HANDLE_VALUE_NTF: u8 = 0x1B;
+const HANDLE_VALUE_IND: u8 = 0x1D;
+const HANDLE_VALUE_CFM: u8 = 0x1E;
+const WRITE_CMD: u8 = 0x52;
+/// Set in the opcodes of commands, which have no response
+const COMMAND_FLAG: u8 = 0x40;
+
+/// Error codes of the Error Response.
+///
+/// BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part F], section 3.4.1.1
+pub mod error {
+    pub const INVALID_HANDLE: u8 = 0x01;
+    pub const READ_NOT_PERMITTED: u8 = 0x02;
+    pub const WRITE_NOT_PERMITTED: u8 = 0x03;
+    pub const INVALID_PDU: u8 = 0x04;
+    pub const REQUEST_NOT_SUPPORTED: u8 = 0x06;
+    pub const INVALID_OFFSET: u8 = 0x07;
+    pub const ATTRIBUTE_NOT_FOUND: u8 = 0x0A;
+    pub const INVALID_ATTRIBUTE_VALUE_LENGTH: u8 = 0x0D;
+    pub const UNSUPPORTED_GROUP_TYPE: u8 = 0x10;
+}
+
+/// The Bluetooth base UUID, 00000000-0000-1000-8000-00805F9B34FB, least
+/// significant byte first. 16-bit UUIDs replace bytes 12 and 13.
+const BASE_UUID: [u8; 16] = [
+    0xFB, 0x34, 0x9B, 0x5F, 0x80, 0x00, 0x00, 0x80, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// An attribute type, encoded least significant byte first.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Uuid {
+    Uuid16(u16),
+    Uuid128([u8; 16]),
+}
+
+impl Uuid {
+    /// Parses a 2 or 16 byte UUID.
+    pub fn from_slice(buf: &[u8]) -> Option<Uuid> {
+        match buf.len() {
+            2 => Some(Uuid::Uuid16(u16::from_le_bytes([buf[0], buf[1]]))),
+            16 => {
+                let mut uuid = [0; 16];
+                uuid.copy_from_slice(buf);
+                Some(Uuid::Uuid128(uuid))
+            }
+            _ => None,
+        }
+    }
+
+    /// The length of the encoded UUID.
+    pub fn size(&self) -> usize {
+        match self {
+            Uuid::Uuid16(_) => 2,
+            Uuid::Uuid128(_) => 16,
+        }
+    }
+
+    /// Writes the UUID to `buf`, and returns its length.
+    pub fn encode(&self, buf: &mut [u8]) -> usize {
+        match self {
+            Uuid::Uuid16(uuid) => buf[..2].copy_from_slice(&uuid.to_le_bytes()),
+            Uuid::Uuid128(uuid) => buf[..16].copy_from_slice(uuid),
+        }
+        self.size()
+    }
+
+    fn to_128(&self) -> [u8; 16] {
+        match self {
+            Uuid::Uuid16(uuid) => {
+                let mut full = BASE_UUID;
+                full[12..14].copy_from_slice(&uuid.to_le_bytes());
+                full
+            }
+            Uuid::Uuid128(uuid) => *uuid,
+        }
+    }
+
+    /// Whether both UUIDs are the same, in any form.
+    pub fn matches(&self, other: &Uuid) -> bool {
+        self.to_128() == other.to_128()
+    }
+}
+
+/// An attribute of the database.
+pub struct Attribute<'b> {
+    pub handle: u16,
+    pub uuid: Uuid,
+    pub value: &'b [u8],
+    pub readable: bool,
+    /// The last handle of the group the attribute starts (a service), or its
+    /// own handle.
+    pub group_end: u16,
+}
+
+/// The attributes the server exposes.
+pub trait AttributeDatabase {
+    /// Calls `f` with each attribute whose handle is between `start` and
+    /// `end` included, in increasing handle order, until `f` returns false.
+    fn for_each_attribute(&self, start: u16, end: u16, f: &mut dyn FnMut(&Attribute) -> bool);
+
+    /// Writes `value` to the attribute with the given handle, for a Write
+    /// Request if `with_response`, or a Write Command. Returns an error code
+    /// from `error` if it cannot be written.
+    fn write_attribute(&self, handle: u16, value: &[u8], with_response: bool) -> Result<(), u8>;
+
+    /// A client connected.
+    fn connected(&self);
+
+    /// The client disconnected.
+    fn disconnected(&self);
+
+    /// A notification of the attribute was sent, or an indication of it was
+    /// confirmed.
+    fn notification_done(&self, handle: u16);
+}
+
+pub struct AttServer<'a> {
+    database: OptionalCell<&'a dyn AttributeDatabase>,
+    connected: Cell<bool>,
+    response: MapCell<[u8; ATT_MTU]>,
+    response_len: Cell<usize>,
+    /// A notification to send: the handle, and whether it is an indication
+    notification: Cell<Option<(u16, bool)>>,
+    /// An indication waiting for its confirmation
+    indication: Cell<Option<u16>>,
+}
+
+impl Default for AttServer<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> AttServer<'a> {
+    pub fn new() -> AttServer<'a> {
+        AttServer {
+            database: OptionalCell::empty(),
+            connected: Cell::new(false),
+            response: MapCell::new([0; ATT_MTU]),
+            response_len: Cell::new(0),
+            notification: Cell::new(None),
+            indication: Cell::new(None),
+        }
+    }
+
+    pub fn set_database(&self, database: &'a dyn AttributeDatabase) {
+        self.database.set(database);
+    }
+
+    /// Sends a notification, or an indication, of the value of the attribute
+    /// with the given handle. The database is told with `notification_done`
+    /// when the notification is sent or the indication confirmed.
+    ///
+    /// Returns EOFF if no client is connected, and EBUSY if a notification is
+    /// pending or an indication not confirmed yet.
+    pub fn notify(&self, handle: u16, indicate: bool) -> ReturnCode {
+        if !self.connected.get() {
+            ReturnCode::EOFF
+        } else if self.notification.get().is_some() || self.indication.get().is_some() {
+            ReturnCode::EBUSY
+        } else {
+            self.notification.set(Some((handle, indicate)));
+            ReturnCode::SUCCESS
+        }
+    }
+
+    pub fn connected(&self) {
+        self.reset();
+        self.connected.set(true);
+        self.database.map(|database| database.connected());
+    }
+
+    pub fn disconnected(&self) {
+        self.reset();
+        self.connected.set(false);
+        self.database.map(|database| database.disconnected());
+    }
+
+    fn reset(&self) {
+        self.response_len.set(0);
+        self.notification.set(None);
+        self.indication.set(None);
+    }
+
+    /// Handles an ATT PDU from the client. Returns false if it is a request
+    /// and the response to the previous one has not been sent yet.
+    pub fn receive(&self, pdu: &[u8]) -> bool {
+        if pdu.is_empty() {
+            return true;
+        }
+        let opcode = pdu[0];
+        match opcode {
+            WRITE_CMD => {
+                if pdu.len() >= 3 {
+                    let handle = u16::from_le_bytes([pdu[1], pdu[2]]);
+                    self.database.map(|database| {
+                        let _ = database.write_attribute(handle, &pdu[3..], false);
+                    });
+                }
+                true
+            }
+            HANDLE_VALUE_CFM => {
+                if let Some(handle) = self.indication.take() {
+                    self.database
+                        .map(|database| database.notification_done(handle));
+                }
+                true
+            }
+            // Other commands are ignored, as are the responses a client
+            // cannot send
+            _ if opcode & COMMAND_FLAG != 0 || opcode & 1 != 0 => true,
+            _ => {
+                if self.response_len.get() != 0 {
+                    return false;
+                }
+                let len = self.response.map_or(0, |response| {
+                    self.handle_request(opcode, &pdu[1..], response)
+                        .unwrap_or_else(|(handle, code)| {
+                            response[0] = ERROR_RSP;
+                            response[1] = opcode;
+                            response[2..4].copy_from_slice(&handle.to_le_bytes());
+                            response[4] = code;
+                            5
+                        })
+                });
+                self.response_len.set(len);
+                true
+            }
+        }
+    }
+
+    /// Writes the next ATT PDU to send to `buf`, and returns its length.
+    pub fn next_pdu(&self, buf: &mut [u8]) -> Option<usize> {
+        let len = self.response_len.get();
+        if len != 0 {
+            self.response
+                .map(|response| buf[..len].copy_from_slice(&response[..len]));
+            self.response_len.set(0);
+            return Some(len);
+        }
+
+        let (handle, indicate) = self.notification.take()?;
+        buf[0] = if indicate {
+            HANDLE_VALUE_IND
+        } else {
+            HANDLE_VALUE_NTF
+        };
+        buf[1..3].copy_from_slice(&handle.to_le_bytes());
+        let mut len = 3;
+        self.for_each(handle, handle, &mut |attribute| {
+            len += copy_value(&mut buf[3..ATT_MTU], attribute.value);
+            false
+        });
+        if indicate {
+            self.indication.set(Some(handle));
+        } else {
+            self.database
+                .map(|database| database.notification_done(handle));
+        }
+        Some(len)
+    }
+
+    fn for_each(&self, start: u16, end: u16, f: &mut dyn FnMut(&Attribute) -> bool) {
+        self.database
+            .map(|database| database.for_each_attribute(start, end, f));
+    }
+
+    /// Reads the start and end handles of a request over a range.
+    fn handle_range(params: &[u8]) -> Result<(u16, u16), (u16, u8)> {
+        if params.len() < 4 {
+            return Err((0, error::INVALID_PDU));
+        }
+        let start = u16::from_le_bytes([params[0], params[1]]);
+        let end = u16::from_le_bytes([params[2], params[3]]);
+        if start == 0 || start > end {
+            return Err((start, error::INVALID_HANDLE));
+        }
+        Ok((start, end))
+    }
+
+    /// Writes the response to a request to `response`, and returns its
+    /// length, or the handle and the error code of an Error Response.
+    fn handle_request(
+        &self,
+        opcode: u8,
+        params: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, (u16, u8)> {
+        match opcode {
+            EXCHANGE_MTU_REQ => {
+                response[0] = EXCHANGE_MTU_RSP;
+                response[1..3].copy_from_slice(&(ATT_MTU as u16).to_le_bytes());
+                Ok(3)
+            }
+            FIND_INFORMATION_REQ => {
+                let (start, end) = Self::handle_range(params)?;
+                response[0] = FIND_INFORMATION_RSP;
+                let mut len = 2;
+                self.for_each(start, end, &mut |attribute| {
+                    let uuid_len = attribute.uuid.size();
+                    if len == 2 {
+                        // Format: 1 for 16-bit UUIDs, 2 for 128-bit ones
+                        response[1] = if uuid_len == 2 { 1 } else { 2 };
+                    } else if (uuid_len == 2) != (response[1] == 1) {
+                        return false;
+                    }
+                    if len + 2 + uuid_len > ATT_MTU {
+                        return false;
+                    }
+                    response[len..len + 2].copy_from_slice(&attribute.handle.to_le_bytes());
+                    len += 2 + attribute.uuid.encode(&mut response[len + 2..]);
+                    true
+                });
+                if len == 2 {
+                    return Err((start, error::ATTRIBUTE_NOT_FOUND));
+                }
+                Ok(len)
+            }
+            FIND_BY_TYPE_VALUE_REQ => {
+                let (start, end) = Self::handle_range(params)?;
+                if params.len() < 6 {
+                    return Err((0, error::INVALID_PDU));
+                }
+                let uuid = Uuid::Uuid16(u16::from_le_bytes([params[4], params[5]]));
+                let value = &params[6..];
+                response[0] = FIND_BY_TYPE_VALUE_RSP;
+                let mut len = 1;
+                self.for_each(start, end, &mut |attribute| {
+                    if attribute.uuid.matches(&uuid) && attribute.value == value {
+                        if len + 4 > ATT_MTU {
+                            return false;
+                        }
+                        response[len..len + 2].copy_from_slice(&attribute.handle.to_le_bytes());
+                        response[len + 2..len + 4]
+                            .copy_from_slice(&attribute.group_end.to_le_bytes());
+                        len += 4;
+                    }
+                    true
+                });
+                if len == 1 {
+                    return Err((start, error::ATTRIBUTE_NOT_FOUND));
+                }
+                Ok(len)
+            }
+            READ_BY_TYPE_REQ | READ_BY_GROUP_TYPE_REQ => {
+                let (start, end) = Self::handle_range(params)?;
+                let uuid = Uuid::from_slice(&params[4..]).ok_or((0, error::INVALID_PDU))?;
+                let group = opcode == READ_BY_GROUP_TYPE_REQ;
+                if group && !uuid.matches(&super::gatt::PRIMARY_SERVICE) {
+                    return Err((start, error::UNSUPPORTED_GROUP_TYPE));
+                }
+                response[0] = if group {
+                    READ_BY_GROUP_TYPE_RSP
+                } else {
+                    READ_BY_TYPE_RSP
+                };
+                // Entries: the handle, the group end handle for groups, and
+                // the value, all of the same length
+                let header_len = if group { 4 } else { 2 };
+                let mut len = 2;
+                let mut error = None;
+                self.for_each(start, end, &mut |attribute| {
+                    if !attribute.uuid.matches(&uuid) {
+                        return true;
+                    }
+                    if !attribute.readable {
+                        if len == 2 {
+                            error = Some((attribute.handle, error::READ_NOT_PERMITTED));
+                        }
+                        return false;
+                    }
+                    let value_len = cmp::min(attribute.value.len(), ATT_MTU - 2 - header_len);
+                    if len == 2 {
+                        response[1] = (header_len + value_len) as u8;
+                    } else if response[1] as usize != header_len + value_len {
+                        return false;
+                    }
+                    if len + header_len + value_len > ATT_MTU {
+                        return false;
+                    }
+                    response[len..len + 2].copy_from_slice(&attribute.handle.to_le_bytes());
+                    if group {
+                        response[len + 2..len + 4]
+                            .copy_from_slice(&attribute.group_end.to_le_bytes());
+                    }
+                    len += header_len;
+                    response[len..len + value_len].copy_from_slice(&attribute.value[..value_len]);
+                    len += value_len;
+                    true
+                });
+                if let Some(error) = error {
+                    return Err(error);
+                }
+                if len == 2 {
+                    return Err((start, error::ATTRIBUTE_NOT_FOUND));
+                }
+                Ok(len)
+            }
+            READ_REQ | READ_BLOB_REQ => {
+                let blob = opcode == READ_BLOB_REQ;
+                if params.len() < if blob { 4 } else { 2 } {
+                    return Err((0, error::INVALID_PDU));
+                }
+                let handle = u16::from_le_bytes([params[0], params[1]]);
+                let offset = if blob {
+                    u16::from_le_bytes([params[2], params[3]]) as usize
+                } else {
+                    0
+                };
+                response[0] = if blob { READ_BLOB_RSP } else { READ_RSP };
+                let mut result = Err((handle, error::INVALID_HANDLE));
+                self.for_each(handle, handle, &mut |attribute| {
+                    result = if !attribute.readable {
+                        Err((handle, error::READ_NOT_PERMITTED))
+                    } else if offset > attribute.value.len() {
+                        Err((handle, error::INVALID_OFFSET))
+                    } else {
+                        Ok(1 + copy_value(&mut response[1..], &attribute.value[offset..]))
+                    };
+                    false
+                });
+                result
+            }
+            WRITE_REQ => {
+                if params.len() < 2 {
+                    return Err((0, error::INVALID_PDU));
+                }
+                let handle = u16::from_le_bytes([params[0], params[1]]);
+                self.database
+                    .map_or(Err(error::INVALID_HANDLE), |database| {
+                        database.write_attribute(handle, &params[2..], true)
+                    })
+                    .map_err(|code| (handle, code))?;
+                response[0] = WRITE_RSP;
+                Ok(1)
+            }
+            _ => Err((0, error::REQUEST_NOT_SUPPORTED)),
+        }
+    }
+}
+
+/// Copies as much of `value` as fits in `buf`, and returns the length copied.
+fn copy_value(buf: &mut [u8], value: &[u8]) -> usize {
+    let len = cmp::min(buf.len(), value.len());
+    buf[..len].copy_from_slice(&value[..len]);
+    len
+}
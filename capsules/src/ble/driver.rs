@@ -0,0 +1,664 @@
+//! GATT server userspace interface.
+//!
+//! Processes register a primary service each, with up to
+//! `MAX_CHARACTERISTICS` characteristics, which the driver exposes to the
+//! connected central through the ATT server. The value of each
+//! characteristic is a buffer the process allows: the central reads it, and
+//! its writes are copied into it. Characteristics that can be notified or
+//! indicated have a Client Characteristic Configuration Descriptor, and the
+//! process sends their value to the central when it subscribed to them.
+//!
+//! The attribute handles are laid out in order: the Generic Access service
+//! (handles 1 to 5, with the device name and the appearance), the Generic
+//! Attribute service (handle 6), then the service of each process, in the
+//! order of the processes. A service declaration is followed, for each
+//! characteristic, by its declaration, its value and its CCCD. Services
+//! cannot be registered while connected, since clients expect the handles
+//! not to change during a connection; the Service Changed characteristic is
+//! not supported, so clients that cache handles should not be bonded with.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let gatt = static_init!(
+//!     capsules::ble::driver::GattDriver<'static>,
+//!     capsules::ble::driver::GattDriver::new(
+//!         att_server,
+//!         link_layer,
+//!         b"Tock",
+//!         board_kernel.create_grant(&memory_allocation_capability)
+//!     )
+//! );
+//! att_server.set_database(gatt);
+//! ```
+
+use super::att::{self, AttServer, Attribute, AttributeDatabase, Uuid};
+use super::gatt::{self, properties};
+use super::link_layer::LinkControl;
+use core::cell::Cell;
+use core::cmp;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::BleGatt as usize;
+
+/// Number of characteristics in the service of each process.
+pub const MAX_CHARACTERISTICS: usize = 4;
+
+/// Events of the callback.
+pub const EVENT_CONNECTED: usize = 0;
+pub const EVENT_DISCONNECTED: usize = 1;
+pub const EVENT_WRITTEN: usize = 2;
+pub const EVENT_NOTIFY_DONE: usize = 3;
+pub const EVENT_SUBSCRIBED: usize = 4;
+
+// Fixed handles
+const GAP_SERVICE_HANDLE: u16 = 1;
+const DEVICE_NAME_HANDLE: u16 = 3;
+const APPEARANCE_HANDLE: u16 = 5;
+const GATT_SERVICE_HANDLE: u16 = 6;
+const FIRST_APP_HANDLE: u16 = 7;
+
+/// Generic appearance
+const APPEARANCE_UNKNOWN: [u8; 2] = [0, 0];
+
+#[derive(Copy, Clone)]
+struct Characteristic {
+    uuid: Uuid,
+    properties: u8,
+    /// The length of the value, at the start of the value buffer
+    value_len: usize,
+    /// The value of the CCCD
+    cccd: u16,
+}
+
+impl Characteristic {
+    fn has_cccd(&self) -> bool {
+        self.properties & (properties::NOTIFY | properties::INDICATE) != 0
+    }
+
+    fn attribute_count(&self) -> u16 {
+        if self.has_cccd() {
+            3
+        } else {
+            2
+        }
+    }
+}
+
+/// The attribute of a process a handle refers to
+#[derive(Copy, Clone, PartialEq)]
+enum Target {
+    Declaration,
+    Value(usize),
+    Cccd(usize),
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    config: Option<AppSlice<Shared, u8>>,
+    values: [Option<AppSlice<Shared, u8>>; MAX_CHARACTERISTICS],
+    service: Option<Uuid>,
+    characteristics: [Option<Characteristic>; MAX_CHARACTERISTICS],
+}
+
+impl App {
+    fn attribute_count(&self) -> u16 {
+        if self.service.is_none() {
+            return 0;
+        }
+        1 + self
+            .characteristics
+            .iter()
+            .flatten()
+            .map(Characteristic::attribute_count)
+            .sum::<u16>()
+    }
+
+    /// The attribute at `offset` from the service declaration.
+    fn target(&self, offset: u16) -> Target {
+        if offset == 0 {
+            return Target::Declaration;
+        }
+        let mut handle = 1;
+        for (index, characteristic) in self.characteristics.iter().flatten().enumerate() {
+            match offset - handle {
+                0 => return Target::Declaration,
+                1 => return Target::Value(index),
+                2 if characteristic.has_cccd() => return Target::Cccd(index),
+                _ => handle += characteristic.attribute_count(),
+            }
+        }
+        Target::Declaration
+    }
+
+    /// The offset of the value of a characteristic from the service
+    /// declaration.
+    fn value_offset(&self, index: usize) -> u16 {
+        1 + self.characteristics[..index]
+            .iter()
+            .flatten()
+            .map(Characteristic::attribute_count)
+            .sum::<u16>()
+            + 1
+    }
+
+    fn value(&self, index: usize) -> &[u8] {
+        let value_len = self.characteristics[index].map_or(0, |c| c.value_len);
+        self.values[index].as_ref().map_or(&[], |value| {
+            let len = cmp::min(value_len, value.len());
+            &value.as_ref()[..len]
+        })
+    }
+
+    fn schedule(&mut self, event: usize, arg1: usize, arg2: usize) {
+        self.callback.map(|mut cb| cb.schedule(event, arg1, arg2));
+    }
+
+    /// Reads the UUID at the start of the config buffer.
+    fn config_uuid(&self, len: usize) -> Option<Uuid> {
+        self.config
+            .as_ref()
+            .and_then(|config| config.as_ref().get(..len).and_then(Uuid::from_slice))
+    }
+
+    /// Visits the attributes of the service of the process, whose
+    /// declaration has handle `base`. Returns false if `visit` did.
+    fn for_each_attribute(&self, base: u16, visit: &mut dyn FnMut(&Attribute) -> bool) -> bool {
+        let service = match self.service {
+            Some(service) => service,
+            None => return true,
+        };
+        let mut uuid = [0; 16];
+        let uuid_len = service.encode(&mut uuid);
+        let service_declaration = Attribute {
+            handle: base,
+            uuid: gatt::PRIMARY_SERVICE,
+            value: &uuid[..uuid_len],
+            readable: true,
+            group_end: base + self.attribute_count() - 1,
+        };
+        if !visit(&service_declaration) {
+            return false;
+        }
+
+        let mut handle = base + 1;
+        for (index, characteristic) in self.characteristics.iter().flatten().enumerate() {
+            let mut declaration = [0; 3 + 16];
+            let declaration_len = gatt::characteristic_declaration(
+                &mut declaration,
+                characteristic.properties,
+                handle + 1,
+                &characteristic.uuid,
+            );
+            let cccd = characteristic.cccd.to_le_bytes();
+            let attributes = [
+                Attribute {
+                    handle,
+                    uuid: gatt::CHARACTERISTIC,
+                    value: &declaration[..declaration_len],
+                    readable: true,
+                    group_end: handle,
+                },
+                Attribute {
+                    handle: handle + 1,
+                    uuid: characteristic.uuid,
+                    value: self.value(index),
+                    readable: characteristic.properties & properties::READ != 0,
+                    group_end: handle + 1,
+                },
+                Attribute {
+                    handle: handle + 2,
+                    uuid: gatt::CLIENT_CHARACTERISTIC_CONFIGURATION,
+                    value: &cccd,
+                    readable: true,
+                    group_end: handle + 2,
+                },
+            ];
+            let count = characteristic.attribute_count();
+            for attribute in attributes[..count as usize].iter() {
+                if !visit(attribute) {
+                    return false;
+                }
+            }
+            handle += count;
+        }
+        true
+    }
+}
+
+pub struct GattDriver<'a> {
+    att: &'a AttServer<'a>,
+    link: &'a dyn LinkControl,
+    name: &'a [u8],
+    apps: Grant<App>,
+    connected: Cell<bool>,
+}
+
+impl<'a> GattDriver<'a> {
+    pub fn new(
+        att: &'a AttServer<'a>,
+        link: &'a dyn LinkControl,
+        name: &'a [u8],
+        grant: Grant<App>,
+    ) -> GattDriver<'a> {
+        GattDriver {
+            att,
+            link,
+            name,
+            apps: grant,
+            connected: Cell::new(false),
+        }
+    }
+
+    /// Calls `f` with the process whose service contains `handle`, and the
+    /// attribute it refers to.
+    fn with_target<F, R>(&self, handle: u16, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut App, Target) -> R,
+    {
+        let mut f = Some(f);
+        let mut base = FIRST_APP_HANDLE;
+        let mut result = None;
+        for app in self.apps.iter() {
+            let found = app.enter(|app, _| {
+                let count = app.attribute_count();
+                if handle >= base && handle < base + count {
+                    let target = app.target(handle - base);
+                    result = f.take().map(|f| f(app, target));
+                    true
+                } else {
+                    base += count;
+                    false
+                }
+            });
+            if found {
+                break;
+            }
+        }
+        result
+    }
+
+    /// The handle of the service declaration of the process.
+    fn service_handle(&self, appid: AppId) -> Option<u16> {
+        let mut base = FIRST_APP_HANDLE;
+        for app in self.apps.iter() {
+            let (found, count) = app.enter(|app, _| (app.appid() == appid, app.attribute_count()));
+            if found {
+                return Some(base);
+            }
+            base += count;
+        }
+        None
+    }
+
+    /// Sends the value of a characteristic of the process to the central,
+    /// as it subscribed to it.
+    fn notify(&self, appid: AppId, index: usize) -> ReturnCode {
+        let base = match self.service_handle(appid) {
+            Some(base) => base,
+            None => return ReturnCode::FAIL,
+        };
+        let result = self.apps.enter(appid, |app, _| {
+            let characteristic = app
+                .characteristics
+                .get(index)
+                .and_then(|characteristic| *characteristic)
+                .ok_or(ReturnCode::EINVAL)?;
+            let indicate = characteristic.cccd & gatt::CCCD_INDICATIONS != 0
+                && characteristic.properties & properties::INDICATE != 0;
+            let notify = characteristic.cccd & gatt::CCCD_NOTIFICATIONS != 0
+                && characteristic.properties & properties::NOTIFY != 0;
+            if !indicate && !notify {
+                return Err(ReturnCode::EOFF);
+            }
+            Ok((base + app.value_offset(index), indicate))
+        });
+        match result {
+            Ok(Ok((handle, indicate))) => self.att.notify(handle, indicate),
+            Ok(Err(error)) => error,
+            Err(err) => err.into(),
+        }
+    }
+}
+
+impl AttributeDatabase for GattDriver<'_> {
+    fn for_each_attribute(&self, start: u16, end: u16, f: &mut dyn FnMut(&Attribute) -> bool) {
+        let mut visit = |attribute: &Attribute| {
+            if attribute.handle > end {
+                false
+            } else {
+                attribute.handle < start || f(attribute)
+            }
+        };
+
+        let mut device_name = [0; 5];
+        gatt::characteristic_declaration(
+            &mut device_name,
+            properties::READ,
+            DEVICE_NAME_HANDLE,
+            &gatt::DEVICE_NAME,
+        );
+        let mut appearance = [0; 5];
+        gatt::characteristic_declaration(
+            &mut appearance,
+            properties::READ,
+            APPEARANCE_HANDLE,
+            &gatt::APPEARANCE,
+        );
+        let gap_uuid = [0x00, 0x18];
+        let gatt_uuid = [0x01, 0x18];
+        let fixed = [
+            Attribute {
+                handle: GAP_SERVICE_HANDLE,
+                uuid: gatt::PRIMARY_SERVICE,
+                value: &gap_uuid,
+                readable: true,
+                group_end: APPEARANCE_HANDLE,
+            },
+            Attribute {
+                handle: DEVICE_NAME_HANDLE - 1,
+                uuid: gatt::CHARACTERISTIC,
+                value: &device_name,
+                readable: true,
+                group_end: DEVICE_NAME_HANDLE - 1,
+            },
+            Attribute {
+                handle: DEVICE_NAME_HANDLE,
+                uuid: gatt::DEVICE_NAME,
+                value: self.name,
+                readable: true,
+                group_end: DEVICE_NAME_HANDLE,
+            },
+            Attribute {
+                handle: APPEARANCE_HANDLE - 1,
+                uuid: gatt::CHARACTERISTIC,
+                value: &appearance,
+                readable: true,
+                group_end: APPEARANCE_HANDLE - 1,
+            },
+            Attribute {
+                handle: APPEARANCE_HANDLE,
+                uuid: gatt::APPEARANCE,
+                value: &APPEARANCE_UNKNOWN,
+                readable: true,
+                group_end: APPEARANCE_HANDLE,
+            },
+            Attribute {
+                handle: GATT_SERVICE_HANDLE,
+                uuid: gatt::PRIMARY_SERVICE,
+                value: &gatt_uuid,
+                readable: true,
+                group_end: GATT_SERVICE_HANDLE,
+            },
+        ];
+        for attribute in fixed.iter() {
+            if !visit(attribute) {
+                return;
+            }
+        }
+
+        let mut base = FIRST_APP_HANDLE;
+        for app in self.apps.iter() {
+            let more = app.enter(|app, _| {
+                let count = app.attribute_count();
+                let service = base;
+                base += count;
+                // Skip the services before the range
+                count == 0 || base <= start || app.for_each_attribute(service, &mut visit)
+            });
+            if !more || base > end {
+                return;
+            }
+        }
+    }
+
+    fn write_attribute(&self, handle: u16, value: &[u8], with_response: bool) -> Result<(), u8> {
+        if handle < FIRST_APP_HANDLE {
+            return Err(if handle == 0 {
+                att::error::INVALID_HANDLE
+            } else {
+                att::error::WRITE_NOT_PERMITTED
+            });
+        }
+        self.with_target(handle, |app, target| match target {
+            Target::Declaration => Err(att::error::WRITE_NOT_PERMITTED),
+            Target::Value(index) => {
+                let permission = if with_response {
+                    properties::WRITE
+                } else {
+                    properties::WRITE_WITHOUT_RESPONSE
+                };
+                let characteristic = app.characteristics[index].as_mut().unwrap();
+                if characteristic.properties & permission == 0 {
+                    return Err(att::error::WRITE_NOT_PERMITTED);
+                }
+                let written = app.values[index].as_mut().map_or(false, |buffer| {
+                    if value.len() > buffer.len() {
+                        return false;
+                    }
+                    buffer.as_mut()[..value.len()].copy_from_slice(value);
+                    true
+                });
+                if !written {
+                    return Err(att::error::INVALID_ATTRIBUTE_VALUE_LENGTH);
+                }
+                characteristic.value_len = value.len();
+                app.schedule(EVENT_WRITTEN, index, value.len());
+                Ok(())
+            }
+            Target::Cccd(index) => {
+                if value.len() != 2 {
+                    return Err(att::error::INVALID_ATTRIBUTE_VALUE_LENGTH);
+                }
+                let cccd = u16::from_le_bytes([value[0], value[1]])
+                    & (gatt::CCCD_NOTIFICATIONS | gatt::CCCD_INDICATIONS);
+                if let Some(characteristic) = app.characteristics[index].as_mut() {
+                    characteristic.cccd = cccd;
+                }
+                app.schedule(EVENT_SUBSCRIBED, index, cccd as usize);
+                Ok(())
+            }
+        })
+        .unwrap_or(Err(att::error::INVALID_HANDLE))
+    }
+
+    fn connected(&self) {
+        self.connected.set(true);
+        self.apps.each(|app| app.schedule(EVENT_CONNECTED, 0, 0));
+    }
+
+    fn disconnected(&self) {
+        self.connected.set(false);
+        // Subscriptions only last for the connection, without bonding
+        self.apps.each(|app| {
+            for characteristic in app.characteristics.iter_mut().flatten() {
+                characteristic.cccd = 0;
+            }
+            app.schedule(EVENT_DISCONNECTED, 0, 0);
+        });
+    }
+
+    fn notification_done(&self, handle: u16) {
+        self.with_target(handle, |app, target| {
+            if let Target::Value(index) = target {
+                app.schedule(EVENT_NOTIFY_DONE, index, 0);
+            }
+        });
+    }
+}
+
+impl Driver for GattDriver<'_> {
+    /// Setup buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Config buffer. Contains the UUID of the service or the
+    ///        characteristic to register, least significant byte first.
+    /// - `1` to `4`: The value of the characteristic with index
+    ///        `allow_num - 1`. Writes of the central are copied into it.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.config = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            1..=MAX_CHARACTERISTICS => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.values[allow_num - 1] = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Events of the connection. The callback receives the event and
+    ///        two arguments:
+    ///        - `EVENT_CONNECTED`: a central connected.
+    ///        - `EVENT_DISCONNECTED`: the central disconnected.
+    ///        - `EVENT_WRITTEN`: the central wrote the value of the
+    ///          characteristic with the index of the first argument, of the
+    ///          length of the second.
+    ///        - `EVENT_NOTIFY_DONE`: the notification of the characteristic
+    ///          was sent, or its indication confirmed.
+    ///        - `EVENT_SUBSCRIBED`: the central wrote the CCCD of the
+    ///          characteristic, whose value is the second argument.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// GATT server control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Register the service of the app, whose UUID is the first
+    ///        `arg1` bytes (2 or 16) of the config buffer. Returns EALREADY
+    ///        if the app registered one.
+    /// - `2`: Add a characteristic to the service, whose UUID is the first
+    ///        `arg1` bytes of the config buffer and whose properties are
+    ///        `arg2`. Returns the index of the characteristic, or ENOMEM if
+    ///        the service has `MAX_CHARACTERISTICS` characteristics.
+    /// - `3`: Set the length of the value of the characteristic with index
+    ///        `arg1` to `arg2`.
+    /// - `4`: Notify or indicate the value of the characteristic with index
+    ///        `arg1`, as the central subscribed to it. Returns EOFF if it did
+    ///        not, and EBUSY if a notification is in progress.
+    /// - `5`: Start advertising, and advertise again after each connection.
+    /// - `6`: Stop advertising.
+    /// - `7`: Disconnect.
+    ///
+    /// Commands `1` and `2` return EBUSY while connected.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 | 2 if self.connected.get() => ReturnCode::EBUSY,
+
+            1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if app.service.is_some() {
+                        return ReturnCode::EALREADY;
+                    }
+                    match app.config_uuid(arg1) {
+                        Some(uuid) => {
+                            app.service = Some(uuid);
+                            ReturnCode::SUCCESS
+                        }
+                        None => ReturnCode::EINVAL,
+                    }
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            2 => self
+                .apps
+                .enter(appid, |app, _| {
+                    let uuid = match app.config_uuid(arg1) {
+                        Some(uuid) if app.service.is_some() => uuid,
+                        _ => return ReturnCode::EINVAL,
+                    };
+                    let supported = properties::READ
+                        | properties::WRITE_WITHOUT_RESPONSE
+                        | properties::WRITE
+                        | properties::NOTIFY
+                        | properties::INDICATE;
+                    if arg2 > 0xff || arg2 as u8 & !supported != 0 {
+                        return ReturnCode::EINVAL;
+                    }
+                    match app.characteristics.iter().position(Option::is_none) {
+                        Some(index) => {
+                            app.characteristics[index] = Some(Characteristic {
+                                uuid,
+                                properties: arg2 as u8,
+                                value_len: 0,
+                                cccd: 0,
+                            });
+                            ReturnCode::SuccessWithValue { value: index }
+                        }
+                        None => ReturnCode::ENOMEM,
+                    }
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            3 => self
+                .apps
+                .enter(appid, |app, _| {
+                    let buffer_len = app
+                        .values
+                        .get(arg1)
+                        .map_or(0, |value| value.as_ref().map_or(0, |value| value.len()));
+                    match app.characteristics.get_mut(arg1) {
+                        Some(Some(characteristic)) if arg2 <= buffer_len => {
+                            characteristic.value_len = arg2;
+                            ReturnCode::SUCCESS
+                        }
+                        _ => ReturnCode::EINVAL,
+                    }
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            4 => self.notify(appid, arg1),
+
+            5 => self.link.start_advertising(),
+
+            6 => self.link.stop_advertising(),
+
+            7 => self.link.disconnect(),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! Generic Attribute Profile (GATT) definitions.
+//!
+//! GATT organizes the attributes of the ATT server into services, each
+//! started by a service declaration and made of characteristics. A
+//! characteristic is a declaration attribute, holding its properties, the
+//! handle and the type of its value, followed by the value attribute and, if
+//! it can be notified or indicated, a Client Characteristic Configuration
+//! Descriptor (CCCD) through which the client subscribes to it.
+//!
+//! BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part G]
+
+use super::att::Uuid;
+
+/// Declaration of a primary service
+pub const PRIMARY_SERVICE: Uuid = Uuid::Uuid16(0x2800);
+/// Declaration of a characteristic
+pub const CHARACTERISTIC: Uuid = Uuid::Uuid16(0x2803);
+/// Client Characteristic Configuration Descriptor
+pub const CLIENT_CHARACTERISTIC_CONFIGURATION: Uuid = Uuid::Uuid16(0x2902);
+
+/// The Generic Access service, which every server has
+pub const GENERIC_ACCESS_SERVICE: Uuid = Uuid::Uuid16(0x1800);
+pub const DEVICE_NAME: Uuid = Uuid::Uuid16(0x2A00);
+pub const APPEARANCE: Uuid = Uuid::Uuid16(0x2A01);
+/// The Generic Attribute service
+pub const GENERIC_ATTRIBUTE_SERVICE: Uuid = Uuid::Uuid16(0x1801);
+
+/// Characteristic properties.
+///
+/// BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part G], section 3.3.1.1
+pub mod properties {
+    pub const READ: u8 = 0x02;
+    pub const WRITE_WITHOUT_RESPONSE: u8 = 0x04;
+    pub const WRITE: u8 = 0x08;
+    pub const NOTIFY: u8 = 0x10;
+    pub const INDICATE: u8 = 0x20;
+}
+
+/// Bits of the value of a CCCD
+pub const CCCD_NOTIFICATIONS: u16 = 0x0001;
+pub const CCCD_INDICATIONS: u16 = 0x0002;
+
+/// Writes the value of the declaration of a characteristic to `buf`: its
+/// properties, the handle of its value and its type. Returns its length.
+pub fn characteristic_declaration(
+    buf: &mut [u8],
+    properties: u8,
+    value_handle: u16,
+    uuid: &Uuid,
+) -> usize {
+    buf[0] = properties;
+    buf[1..3].copy_from_slice(&value_handle.to_le_bytes());
+    3 + uuid.encode(&mut buf[3..])
+}
+
+// AD types
+// Bluetooth Core Specification Supplement, Part A, section 1
+const AD_FLAGS: u8 = 0x01;
+const AD_SHORTENED_LOCAL_NAME: u8 = 0x08;
+const AD_COMPLETE_LOCAL_NAME: u8 = 0x09;
+/// LE General Discoverable Mode, BR/EDR Not Supported
+const FLAGS_GENERAL_DISCOVERABLE: u8 = 0x06;
+
+/// Writes the advertising data of a connectable device named `name` to
+/// `buf`: the flags, and the name, shortened if it does not fit. Returns its
+/// length.
+pub fn advertising_data(name: &[u8], buf: &mut [u8]) -> usize {
+    buf[..3].copy_from_slice(&[2, AD_FLAGS, FLAGS_GENERAL_DISCOVERABLE]);
+    let room = buf.len().saturating_sub(5);
+    let (ad_type, name) = if name.len() <= room {
+        (AD_COMPLETE_LOCAL_NAME, name)
+    } else {
+        (AD_SHORTENED_LOCAL_NAME, &name[..room])
+    };
+    if name.is_empty() {
+        return 3;
+    }
+    buf[3] = 1 + name.len() as u8;
+    buf[4] = ad_type;
+    buf[5..5 + name.len()].copy_from_slice(name);
+    5 + name.len()
+}
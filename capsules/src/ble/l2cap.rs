@@ -0,0 +1,254 @@
+//! Logical Link Control and Adaptation Protocol (L2CAP) for Bluetooth Low
+//! Energy, on the fixed channels of a peripheral.
+//!
+//! L2CAP reassembles the PDUs of the central from the data PDUs of the link
+//! layer and dispatches them by channel: the Attribute Protocol channel goes
+//! to the ATT server, and the signaling and Security Manager channels are
+//! answered here. In the other direction, it sends the PDUs of the ATT
+//! server, fragmented to the payload of the link layer.
+//!
+//! Neither connection-oriented channels nor pairing are supported: LE credit
+//! based connection requests are refused, and pairing requests fail with
+//! "Pairing Not Supported".
+//!
+//! BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part A]
+
+use super::att::{AttServer, ATT_MTU};
+use super::link_layer::{ConnectionClient, MAX_DATA_PAYLOAD};
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::MapCell;
+
+// Fixed channel identifiers
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part A], section 2.1
+const CID_ATT: u16 = 0x0004;
+const CID_SIGNALING: u16 = 0x0005;
+const CID_SMP: u16 = 0x0006;
+
+// Signaling commands
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part A], section 4
+const COMMAND_REJECT: u8 = 0x01;
+const CONNECTION_PARAMETER_UPDATE_RSP: u8 = 0x13;
+const LE_CREDIT_BASED_CONNECTION_REQ: u8 = 0x14;
+const LE_CREDIT_BASED_CONNECTION_RSP: u8 = 0x15;
+const REJECT_NOT_UNDERSTOOD: u16 = 0x0000;
+const RESULT_LE_PSM_NOT_SUPPORTED: u16 = 0x0002;
+
+// Security Manager commands
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part H], section 3.3
+const SMP_PAIRING_REQUEST: u8 = 0x01;
+const SMP_PAIRING_FAILED: u8 = 0x05;
+const SMP_PAIRING_NOT_SUPPORTED: u8 = 0x05;
+
+/// The length of the basic L2CAP header: the length and the channel
+const HEADER_LEN: usize = 4;
+/// The largest PDU received or sent
+const MAX_PDU: usize = HEADER_LEN + ATT_MTU;
+
+pub struct L2cap<'a> {
+    att: &'a AttServer<'a>,
+    /// The PDU being reassembled
+    rx: MapCell<[u8; MAX_PDU]>,
+    rx_len: Cell<usize>,
+    /// The total length of the PDU being reassembled, or None if there is
+    /// none, or it is too long and its fragments are dropped
+    rx_expected: Cell<Option<usize>>,
+    /// A response on the signaling or SMP channel to send
+    control: MapCell<[u8; MAX_PDU]>,
+    control_len: Cell<usize>,
+    /// The PDU being sent
+    tx: MapCell<[u8; MAX_PDU]>,
+    tx_len: Cell<usize>,
+    tx_offset: Cell<usize>,
+}
+
+impl<'a> L2cap<'a> {
+    pub fn new(att: &'a AttServer<'a>) -> L2cap<'a> {
+        L2cap {
+            att,
+            rx: MapCell::new([0; MAX_PDU]),
+            rx_len: Cell::new(0),
+            rx_expected: Cell::new(None),
+            control: MapCell::new([0; MAX_PDU]),
+            control_len: Cell::new(0),
+            tx: MapCell::new([0; MAX_PDU]),
+            tx_len: Cell::new(0),
+            tx_offset: Cell::new(0),
+        }
+    }
+
+    fn reset(&self) {
+        self.rx_len.set(0);
+        self.rx_expected.set(None);
+        self.control_len.set(0);
+        self.tx_len.set(0);
+        self.tx_offset.set(0);
+    }
+
+    /// Handles a complete PDU. Returns false if it cannot be handled yet.
+    fn dispatch(&self, cid: u16, payload: &[u8]) -> bool {
+        match cid {
+            CID_ATT => self.att.receive(payload),
+            CID_SIGNALING | CID_SMP => {
+                if self.control_len.get() != 0 {
+                    return false;
+                }
+                let len = self.control.map_or(0, |control| {
+                    let len = if cid == CID_SIGNALING {
+                        Self::signaling(payload, &mut control[HEADER_LEN..])
+                    } else {
+                        Self::security_manager(payload, &mut control[HEADER_LEN..])
+                    };
+                    control[..2].copy_from_slice(&(len as u16).to_le_bytes());
+                    control[2..4].copy_from_slice(&cid.to_le_bytes());
+                    len
+                });
+                if len != 0 {
+                    self.control_len.set(HEADER_LEN + len);
+                }
+                true
+            }
+            // Unknown channels are ignored
+            _ => true,
+        }
+    }
+
+    /// Writes the response to a signaling command to `response`, and
+    /// returns its length, or 0 if there is none.
+    fn signaling(command: &[u8], response: &mut [u8]) -> usize {
+        if command.len() < 4 {
+            return 0;
+        }
+        let code = command[0];
+        let identifier = command[1];
+        match code {
+            COMMAND_REJECT | CONNECTION_PARAMETER_UPDATE_RSP => 0,
+            LE_CREDIT_BASED_CONNECTION_REQ => {
+                response[..4].copy_from_slice(&[LE_CREDIT_BASED_CONNECTION_RSP, identifier, 10, 0]);
+                // Destination CID, MTU, MPS and initial credits are unused
+                // when the connection is refused
+                for byte in response[4..12].iter_mut() {
+                    *byte = 0;
+                }
+                response[12..14].copy_from_slice(&RESULT_LE_PSM_NOT_SUPPORTED.to_le_bytes());
+                14
+            }
+            _ => {
+                response[..4].copy_from_slice(&[COMMAND_REJECT, identifier, 2, 0]);
+                response[4..6].copy_from_slice(&REJECT_NOT_UNDERSTOOD.to_le_bytes());
+                6
+            }
+        }
+    }
+
+    /// Writes the response to a Security Manager command to `response`, and
+    /// returns its length, or 0 if there is none.
+    fn security_manager(command: &[u8], response: &mut [u8]) -> usize {
+        match command.first() {
+            Some(&SMP_PAIRING_REQUEST) => {
+                response[..2].copy_from_slice(&[SMP_PAIRING_FAILED, SMP_PAIRING_NOT_SUPPORTED]);
+                2
+            }
+            _ => 0,
+        }
+    }
+
+    /// Prepares the next PDU to send in `tx`, and returns its length, or 0 if
+    /// there is nothing to send.
+    fn next_pdu(&self) -> usize {
+        self.tx.map_or(0, |tx| {
+            let control_len = self.control_len.get();
+            if control_len != 0 {
+                self.control
+                    .map(|control| tx[..control_len].copy_from_slice(&control[..control_len]));
+                self.control_len.set(0);
+                return control_len;
+            }
+            self.att.next_pdu(&mut tx[HEADER_LEN..]).map_or(0, |len| {
+                tx[..2].copy_from_slice(&(len as u16).to_le_bytes());
+                tx[2..4].copy_from_slice(&CID_ATT.to_le_bytes());
+                HEADER_LEN + len
+            })
+        })
+    }
+}
+
+impl ConnectionClient for L2cap<'_> {
+    fn connected(&self) {
+        self.reset();
+        self.att.connected();
+    }
+
+    fn disconnected(&self, _reason: u8) {
+        self.reset();
+        self.att.disconnected();
+    }
+
+    fn receive(&self, start: bool, payload: &[u8]) -> bool {
+        let previous_len = self.rx_len.get();
+        let previous_expected = self.rx_expected.get();
+        if start {
+            if payload.len() < HEADER_LEN {
+                return true;
+            }
+            let len = HEADER_LEN + u16::from_le_bytes([payload[0], payload[1]]) as usize;
+            self.rx_len.set(0);
+            self.rx_expected
+                .set(if len <= MAX_PDU { Some(len) } else { None });
+        }
+        let expected = match self.rx_expected.get() {
+            Some(expected) => expected,
+            None => return true,
+        };
+
+        let offset = self.rx_len.get();
+        if offset + payload.len() > expected {
+            // Longer than announced
+            self.rx_expected.set(None);
+            return true;
+        }
+        let complete = self.rx.map_or(None, |rx| {
+            rx[offset..offset + payload.len()].copy_from_slice(payload);
+            if offset + payload.len() == expected {
+                let cid = u16::from_le_bytes([rx[2], rx[3]]);
+                Some(self.dispatch(cid, &rx[HEADER_LEN..expected]))
+            } else {
+                None
+            }
+        });
+        match complete {
+            Some(false) => {
+                // Undo this fragment, for it to be received again
+                self.rx_len.set(previous_len);
+                self.rx_expected.set(previous_expected);
+                false
+            }
+            Some(true) => {
+                self.rx_len.set(0);
+                self.rx_expected.set(None);
+                true
+            }
+            None => {
+                self.rx_len.set(offset + payload.len());
+                true
+            }
+        }
+    }
+
+    fn next_payload(&self, buf: &mut [u8]) -> Option<(bool, usize)> {
+        let mut offset = self.tx_offset.get();
+        if offset >= self.tx_len.get() {
+            let len = self.next_pdu();
+            if len == 0 {
+                return None;
+            }
+            self.tx_len.set(len);
+            offset = 0;
+        }
+        let len = cmp::min(self.tx_len.get() - offset, MAX_DATA_PAYLOAD);
+        self.tx
+            .map(|tx| buf[..len].copy_from_slice(&tx[offset..offset + len]));
+        self.tx_offset.set(offset + len);
+        Some((offset == 0, len))
+    }
+}
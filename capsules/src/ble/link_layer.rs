@@ -0,0 +1,984 @@
+//! Bluetooth Low Energy link layer, in the peripheral (slave) role.
+//!
+//! The link layer sends connectable undirected advertisements (ADV_IND) on
+//! the three advertising channels, answers scan requests, and accepts the
+//! first connection request (CONNECT_IND) addressed to it. It then follows
+//! the connection: at each connection event it listens on the data channel
+//! picked by channel selection algorithm #1, acknowledges the packet of the
+//! central and responds with its own, which it retransmits until the central
+//! acknowledges it (the SN and NESN bits). The control procedures a
+//! peripheral has to answer (feature and version exchange, connection
+//! update, channel map update, termination, ...) are handled here; data PDUs
+//! go to the `ConnectionClient`, which is L2CAP.
+//!
+//! The radio turns around between a packet and its response (see
+//! `hil::ble_connection`), and the link layer wakes up for each advertising
+//! and connection event with an alarm. The receive window of a connection
+//! event is widened by the drift of both sleep clocks since the last packet
+//! received, plus a margin for the resolution of the alarm and for waking
+//! up.
+//!
+//! Flow control uses the acknowledgements: a PDU received while the previous
+//! one has not been taken by the client or answered yet is not
+//! acknowledged, so the central sends it again.
+//!
+//! Limitations:
+//!
+//! - One packet is exchanged per connection event: the MD bit is never set.
+//! - Encryption is not supported, and LL_ENC_REQ is rejected.
+//! - The peripheral listens at every connection event, whatever the slave
+//!   latency.
+//! - Only the 1M PHY and 27 byte payloads are supported.
+//!
+//! BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B]
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//! # use capsules::virtual_alarm::VirtualMuxAlarm;
+//!
+//! let link_layer = static_init!(
+//!     capsules::ble::link_layer::LinkLayer<
+//!         'static,
+//!         nrf52::ble_radio::Radio,
+//!         VirtualMuxAlarm<'static, Rtc>,
+//!     >,
+//!     capsules::ble::link_layer::LinkLayer::new(
+//!         &base_peripherals.ble_radio,
+//!         ble_virtual_alarm,
+//!         address,
+//!     )
+//! );
+//! base_peripherals.ble_radio.set_link_layer_client(link_layer);
+//! ble_virtual_alarm.set_alarm_client(link_layer);
+//! link_layer.set_client(l2cap);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{MapCell, OptionalCell};
+use kernel::hil::ble_advertising::RadioChannel;
+use kernel::hil::ble_connection::{self, LinkLayerClient, LinkLayerRadio};
+use kernel::hil::time::{self, Frequency, Ticks};
+use kernel::ReturnCode;
+
+/// The maximum payload of a data channel PDU.
+pub const MAX_DATA_PAYLOAD: usize = 27;
+
+/// The maximum length of the advertising data.
+pub const MAX_ADVERTISING_DATA: usize = 31;
+
+/// The default advertising interval, in milliseconds.
+pub const DEFAULT_ADVERTISING_INTERVAL_MS: u32 = 100;
+
+/// Error codes of disconnections, from the Bluetooth specification
+/// [Vol 2, Part D].
+pub mod reason {
+    pub const CONNECTION_TIMEOUT: u8 = 0x08;
+    pub const REMOTE_USER_TERMINATED: u8 = 0x13;
+    pub const LOCAL_HOST_TERMINATED: u8 = 0x16;
+    pub const UNSUPPORTED_REMOTE_FEATURE: u8 = 0x1A;
+    pub const INSTANT_PASSED: u8 = 0x28;
+    pub const FAILED_TO_ESTABLISH: u8 = 0x3E;
+}
+
+// Advertising channel PDU types
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.3
+const ADV_IND: u8 = 0b0000;
+const SCAN_REQ: u8 = 0b0011;
+const SCAN_RSP: u8 = 0b0100;
+const CONNECT_IND: u8 = 0b0101;
+const TXADD: u8 = 1 << 6;
+const RXADD: u8 = 1 << 7;
+
+// Data channel PDU header
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.4
+const LLID_CONTINUATION: u8 = 0b01;
+const LLID_START: u8 = 0b10;
+const LLID_CONTROL: u8 = 0b11;
+const NESN: u8 = 1 << 2;
+const SN: u8 = 1 << 3;
+
+// Control PDU opcodes
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.4.2
+const LL_CONNECTION_UPDATE_IND: u8 = 0x00;
+const LL_CHANNEL_MAP_IND: u8 = 0x01;
+const LL_TERMINATE_IND: u8 = 0x02;
+const LL_ENC_REQ: u8 = 0x03;
+const LL_UNKNOWN_RSP: u8 = 0x07;
+const LL_FEATURE_REQ: u8 = 0x08;
+const LL_FEATURE_RSP: u8 = 0x09;
+const LL_VERSION_IND: u8 = 0x0C;
+const LL_REJECT_IND: u8 = 0x0D;
+const LL_PING_REQ: u8 = 0x12;
+const LL_PING_RSP: u8 = 0x13;
+const LL_LENGTH_REQ: u8 = 0x14;
+const LL_LENGTH_RSP: u8 = 0x15;
+
+/// Bluetooth 4.2
+const VERSION: u8 = 0x08;
+/// No company identifier is assigned to Tock
+const COMPANY_ID: u16 = 0xFFFF;
+
+const NUM_DATA_CHANNELS: u8 = 37;
+const ADVERTISING_CHANNELS: [RadioChannel; 3] = [
+    RadioChannel::AdvertisingChannel37,
+    RadioChannel::AdvertisingChannel38,
+    RadioChannel::AdvertisingChannel39,
+];
+
+/// The accuracy of our sleep clock, in ppm
+const SLEEP_CLOCK_ACCURACY_PPM: u32 = 50;
+/// The accuracy of the sleep clock of the central, indexed by the SCA field of
+/// CONNECT_IND
+const MASTER_SCA_PPM: [u32; 8] = [500, 250, 150, 100, 75, 50, 30, 20];
+/// Time to wake up and start the radio before a receive window
+const WAKEUP_US: u32 = 250;
+/// Air time of the preamble and access address, after which a packet being
+/// received can no longer be interrupted
+const ADDRESS_US: u32 = 40;
+/// The unit of the timing parameters of connections
+const UNIT_US: u32 = 1250;
+
+/// Air time of a packet with the given PDU length: preamble (1), access
+/// address (4), PDU and CRC (3), at 1 µs per bit.
+fn air_time_us(pdu_len: usize) -> u32 {
+    (1 + 4 + pdu_len as u32 + 3) * 8
+}
+
+/// Receives the data PDUs of the connection, and provides those to send.
+pub trait ConnectionClient {
+    /// A central connected.
+    fn connected(&self);
+
+    /// The connection ended, with the given reason (an error code from
+    /// `reason`).
+    fn disconnected(&self, reason: u8);
+
+    /// Receives the payload of a data PDU. `start` is true if it starts an
+    /// L2CAP PDU, false if it continues one. Returns false if the payload
+    /// cannot be taken yet: it is offered again after the next connection
+    /// event, and the central retransmits it until then.
+    fn receive(&self, start: bool, payload: &[u8]) -> bool;
+
+    /// Writes the payload of the next data PDU to send to `buf`, which is
+    /// `MAX_DATA_PAYLOAD` bytes long. Returns whether it starts an L2CAP PDU
+    /// and its length, or None if there is nothing to send.
+    fn next_payload(&self, buf: &mut [u8]) -> Option<(bool, usize)>;
+}
+
+/// Controls advertising and the connection, for the host.
+pub trait LinkControl {
+    /// Starts advertising, and advertises again whenever a connection ends.
+    fn start_advertising(&self) -> ReturnCode;
+
+    /// Stops advertising. A connection in progress is not affected, but no
+    /// advertising follows it.
+    fn stop_advertising(&self) -> ReturnCode;
+
+    /// Terminates the connection.
+    fn disconnect(&self) -> ReturnCode;
+
+    fn is_connected(&self) -> bool;
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum State {
+    Standby,
+    /// Waiting for the next advertising event
+    AdvertisingIdle,
+    /// Advertising on the channel with the given index in
+    /// `ADVERTISING_CHANNELS`, then listening for requests
+    Advertising(usize),
+    /// Connected, waiting for the next connection event
+    Connected,
+    /// Listening in a connection event
+    ConnectionEvent,
+}
+
+/// A data channel PDU, without the acknowledgement bits.
+#[derive(Copy, Clone)]
+struct Pdu {
+    llid: u8,
+    len: u8,
+    payload: [u8; MAX_DATA_PAYLOAD],
+}
+
+impl Pdu {
+    fn empty() -> Pdu {
+        Pdu {
+            llid: LLID_CONTINUATION,
+            len: 0,
+            payload: [0; MAX_DATA_PAYLOAD],
+        }
+    }
+
+    fn control(opcode: u8, data: &[u8]) -> Pdu {
+        let mut pdu = Pdu {
+            llid: LLID_CONTROL,
+            len: 1 + data.len() as u8,
+            payload: [0; MAX_DATA_PAYLOAD],
+        };
+        pdu.payload[0] = opcode;
+        pdu.payload[1..=data.len()].copy_from_slice(data);
+        pdu
+    }
+
+    fn payload(&self) -> &[u8] {
+        &self.payload[..self.len as usize]
+    }
+
+    /// Writes the PDU to `buf` with the given acknowledgement bits, and returns
+    /// its length.
+    fn encode(&self, buf: &mut [u8], sn: bool, nesn: bool) -> usize {
+        let mut header = self.llid;
+        if sn {
+            header |= SN;
+        }
+        if nesn {
+            header |= NESN;
+        }
+        buf[0] = header;
+        buf[1] = self.len;
+        buf[2..2 + self.len as usize].copy_from_slice(self.payload());
+        2 + self.len as usize
+    }
+}
+
+/// Connection parameters taking effect at an instant.
+#[derive(Copy, Clone)]
+struct ConnectionUpdate {
+    instant: u16,
+    window_size_us: u32,
+    window_offset_us: u32,
+    interval_us: u32,
+    supervision_timeout_us: u32,
+}
+
+#[derive(Copy, Clone)]
+struct Connection {
+    access_address: u32,
+    crc_init: u32,
+    interval_us: u32,
+    supervision_timeout_us: u32,
+    master_sca_ppm: u32,
+    channel_map: [u8; 5],
+    hop: u8,
+    unmapped_channel: u8,
+    /// The data channel of the next connection event
+    channel: u8,
+    event_counter: u16,
+    /// Time from the last anchor point the link layer synchronized to (the
+    /// `sync` field of the link layer) to the start of the receive window of
+    /// the next connection event
+    anchor_us: u32,
+    /// Size of the receive window, if the next event starts with a transmit
+    /// window
+    window_us: u32,
+    /// Whether a packet was received since the connection was created
+    established: bool,
+    sn: bool,
+    nesn: bool,
+    version_sent: bool,
+    update: Option<ConnectionUpdate>,
+    channel_map_update: Option<(u16, [u8; 5])>,
+}
+
+impl Connection {
+    /// Parses the LLData field of a CONNECT_IND PDU.
+    fn from_connect_ind(data: &[u8]) -> Option<Connection> {
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let interval = u16_at(10) as u32;
+        let timeout = u16_at(14) as u32;
+        let mut channel_map = [0; 5];
+        channel_map.copy_from_slice(&data[16..21]);
+        channel_map[4] &= 0x1f;
+        let hop = data[21] & 0x1f;
+        let connection = Connection {
+            access_address: u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+            crc_init: u32::from_le_bytes([data[4], data[5], data[6], 0]),
+            interval_us: interval * UNIT_US,
+            supervision_timeout_us: timeout * 10_000,
+            master_sca_ppm: MASTER_SCA_PPM[(data[21] >> 5) as usize],
+            channel_map,
+            hop,
+            unmapped_channel: 0,
+            channel: 0,
+            event_counter: 0,
+            // The transmit window starts 1.25 ms plus the window offset after
+            // the end of CONNECT_IND
+            anchor_us: UNIT_US + u16_at(8) as u32 * UNIT_US,
+            window_us: data[7] as u32 * UNIT_US,
+            established: false,
+            sn: false,
+            nesn: false,
+            version_sent: false,
+            update: None,
+            channel_map_update: None,
+        };
+        let valid = (6..=3200).contains(&interval)
+            && (5..=16).contains(&hop)
+            && connection.num_used_channels() >= 2
+            && connection.supervision_timeout_us > 2 * connection.interval_us;
+        if valid {
+            Some(connection)
+        } else {
+            None
+        }
+    }
+
+    fn num_used_channels(&self) -> u8 {
+        self.channel_map.iter().map(|b| b.count_ones() as u8).sum()
+    }
+
+    fn is_used(&self, channel: u8) -> bool {
+        self.channel_map[channel as usize / 8] & (1 << (channel % 8)) != 0
+    }
+
+    /// Channel selection algorithm #1.
+    ///
+    /// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.5.8.2
+    fn next_channel(&mut self) {
+        self.unmapped_channel = (self.unmapped_channel + self.hop) % NUM_DATA_CHANNELS;
+        self.channel = if self.is_used(self.unmapped_channel) {
+            self.unmapped_channel
+        } else {
+            let remapping_index = self.unmapped_channel % self.num_used_channels();
+            (0..NUM_DATA_CHANNELS)
+                .filter(|&channel| self.is_used(channel))
+                .nth(remapping_index as usize)
+                .unwrap_or(0)
+        };
+    }
+
+    /// Moves to the next connection event, and applies the updates taking
+    /// effect at it.
+    fn next_event(&mut self) {
+        self.event_counter = self.event_counter.wrapping_add(1);
+        self.anchor_us += self.interval_us;
+        self.window_us = 0;
+        if let Some(update) = self.update {
+            if update.instant == self.event_counter {
+                // The transmit window starts at the window offset after the
+                // anchor point the event would have had
+                self.anchor_us += update.window_offset_us;
+                self.window_us = update.window_size_us;
+                self.interval_us = update.interval_us;
+                self.supervision_timeout_us = update.supervision_timeout_us;
+                self.update = None;
+            }
+        }
+        if let Some((instant, channel_map)) = self.channel_map_update {
+            if instant == self.event_counter {
+                self.channel_map = channel_map;
+                self.channel_map_update = None;
+            }
+        }
+        self.next_channel();
+    }
+
+    /// Whether the instant of a procedure is in the past.
+    fn has_passed(&self, instant: u16) -> bool {
+        instant.wrapping_sub(self.event_counter) >= 0x8000
+    }
+
+    /// The receive window is widened by the drift of both sleep clocks since
+    /// the last anchor point.
+    ///
+    /// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.5.7
+    fn window_widening_us(&self) -> u32 {
+        let ppm = (self.master_sca_ppm + SLEEP_CLOCK_ACCURACY_PPM) as u64;
+        (ppm * self.anchor_us as u64 / 1_000_000) as u32 + 16
+    }
+
+    /// Whether too much time passed since the last packet received.
+    fn timed_out(&self) -> bool {
+        if self.established {
+            self.anchor_us > self.supervision_timeout_us
+        } else {
+            self.anchor_us > 6 * self.interval_us + self.window_us
+        }
+    }
+}
+
+pub struct LinkLayer<'a, R: LinkLayerRadio<'a>, A: time::Alarm<'a>> {
+    radio: &'a R,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn ConnectionClient>,
+    address: [u8; 6],
+    advertising_data: Cell<&'a [u8]>,
+    advertising_interval_ms: Cell<u32>,
+    advertise: Cell<bool>,
+    state: Cell<State>,
+    connection: MapCell<Connection>,
+    /// The last anchor point the link layer synchronized to
+    sync: Cell<A::Ticks>,
+    /// The PDU sent and not acknowledged yet
+    tx: Cell<Option<Pdu>>,
+    /// The PDU to send once `tx` is acknowledged
+    tx_next: Cell<Option<Pdu>>,
+    /// A PDU received and not handled yet
+    rx: Cell<Option<Pdu>>,
+    /// A termination requested by the host, not sent yet
+    terminate: Cell<Option<u8>>,
+    /// Set when the connection ends at the end of the current event
+    disconnect_reason: Cell<Option<u8>>,
+    random_state: Cell<u32>,
+}
+
+impl<'a, R: LinkLayerRadio<'a>, A: time::Alarm<'a>> LinkLayer<'a, R, A> {
+    /// `address` is the static random device address, least significant byte
+    /// first. Its two most significant bits must be set.
+    pub fn new(radio: &'a R, alarm: &'a A, address: [u8; 6]) -> LinkLayer<'a, R, A> {
+        let seed = address.iter().fold(0x2545_f491u32, |seed, byte| {
+            seed.rotate_left(7) ^ *byte as u32
+        });
+        LinkLayer {
+            radio,
+            alarm,
+            client: OptionalCell::empty(),
+            address,
+            advertising_data: Cell::new(&[]),
+            advertising_interval_ms: Cell::new(DEFAULT_ADVERTISING_INTERVAL_MS),
+            advertise: Cell::new(false),
+            state: Cell::new(State::Standby),
+            connection: MapCell::empty(),
+            sync: Cell::new(A::Ticks::from(0)),
+            tx: Cell::new(None),
+            tx_next: Cell::new(None),
+            rx: Cell::new(None),
+            terminate: Cell::new(None),
+            disconnect_reason: Cell::new(None),
+            random_state: Cell::new(seed | 1),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn ConnectionClient) {
+        self.client.set(client);
+    }
+
+    /// Sets the data of advertisements, at most `MAX_ADVERTISING_DATA` bytes
+    /// of AD structures.
+    pub fn set_advertising_data(&self, data: &'a [u8]) -> ReturnCode {
+        if data.len() > MAX_ADVERTISING_DATA {
+            return ReturnCode::ESIZE;
+        }
+        self.advertising_data.set(data);
+        ReturnCode::SUCCESS
+    }
+
+    /// Sets the interval between advertising events, between 20 ms and
+    /// 10.24 s.
+    pub fn set_advertising_interval(&self, interval_ms: u32) -> ReturnCode {
+        if !(20..=10_240).contains(&interval_ms) {
+            return ReturnCode::EINVAL;
+        }
+        self.advertising_interval_ms.set(interval_ms);
+        ReturnCode::SUCCESS
+    }
+
+    pub fn address(&self) -> [u8; 6] {
+        self.address
+    }
+
+    fn ticks_from_us(us: u32) -> A::Ticks {
+        A::ticks_from_us(us)
+    }
+
+    /// The resolution of the alarm, in microseconds.
+    fn tick_us() -> u32 {
+        1_000_000 / A::Frequency::frequency() + 1
+    }
+
+    /// Returns a pseudo-random number (xorshift32).
+    fn random(&self) -> u32 {
+        let mut x = self.random_state.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random_state.set(x);
+        x
+    }
+
+    // Advertising
+
+    /// Schedules the next advertising event, after the advertising interval
+    /// and a random delay of up to 10 ms.
+    ///
+    /// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.4.2.2
+    fn schedule_advertising(&self) {
+        self.state.set(State::AdvertisingIdle);
+        let delay_us = self.advertising_interval_ms.get() * 1000 + self.random() % 10_000;
+        self.alarm
+            .set_alarm(self.alarm.now(), Self::ticks_from_us(delay_us));
+    }
+
+    fn advertise_on(&self, index: usize) {
+        let data = self.advertising_data.get();
+        let mut pdu = [0; 2 + 6 + MAX_ADVERTISING_DATA];
+        pdu[0] = ADV_IND | TXADD;
+        pdu[1] = (6 + data.len()) as u8;
+        pdu[2..8].copy_from_slice(&self.address);
+        pdu[8..8 + data.len()].copy_from_slice(data);
+        let len = 8 + data.len();
+
+        self.state.set(State::Advertising(index));
+        self.radio.set_access_address(
+            ble_connection::ADVERTISING_ACCESS_ADDRESS,
+            ble_connection::ADVERTISING_CRC_INIT,
+        );
+        if self
+            .radio
+            .transmit_and_listen(&pdu[..len], ADVERTISING_CHANNELS[index])
+            != ReturnCode::SUCCESS
+        {
+            // The radio is in use: skip this advertising event
+            self.schedule_advertising();
+            return;
+        }
+        // Listen for requests sent T_IFS after the advertisement
+        let listen_us =
+            WAKEUP_US + air_time_us(len) + ble_connection::T_IFS_US + ADDRESS_US + Self::tick_us();
+        self.alarm
+            .set_alarm(self.alarm.now(), Self::ticks_from_us(listen_us));
+    }
+
+    /// The advertising on a channel ended.
+    fn advertising_done(&self, index: usize) {
+        if self.connection.is_some() {
+            self.connect();
+        } else if !self.advertise.get() {
+            self.state.set(State::Standby);
+        } else if index + 1 < ADVERTISING_CHANNELS.len() {
+            self.advertise_on(index + 1);
+        } else {
+            self.schedule_advertising();
+        }
+    }
+
+    /// Writes the scan response, the only response to an advertisement, and
+    /// returns its length.
+    fn scan_response(&self, response: &mut [u8]) -> usize {
+        response[0] = SCAN_RSP | TXADD;
+        response[1] = 6;
+        response[2..8].copy_from_slice(&self.address);
+        8
+    }
+
+    /// Answers a request received after an advertisement.
+    fn respond_advertising(&self, pdu: &[u8], response: Option<&mut [u8]>) -> usize {
+        if pdu.len() < 2 || pdu[0] & RXADD == 0 {
+            return 0;
+        }
+        let len = pdu[1] as usize;
+        match pdu[0] & 0x0f {
+            SCAN_REQ if len == 12 && pdu.len() >= 14 && pdu[8..14] == self.address => {
+                response.map_or(0, |response| self.scan_response(response))
+            }
+            CONNECT_IND if len == 34 && pdu.len() >= 36 && pdu[8..14] == self.address => {
+                if let Some(mut connection) = Connection::from_connect_ind(&pdu[14..36]) {
+                    connection.next_channel();
+                    self.connection.put(connection);
+                    // The transmit window is timed from the end of
+                    // CONNECT_IND, which is now
+                    self.sync.set(self.alarm.now());
+                }
+                0
+            }
+            _ => 0,
+        }
+    }
+
+    // Connection
+
+    fn connect(&self) {
+        self.tx.set(None);
+        self.tx_next.set(None);
+        self.rx.set(None);
+        self.terminate.set(None);
+        self.disconnect_reason.set(None);
+        self.state.set(State::Connected);
+        self.client.map(|client| client.connected());
+        self.schedule_connection_event();
+    }
+
+    fn disconnect_now(&self, reason: u8) {
+        self.connection.take();
+        self.tx.set(None);
+        self.tx_next.set(None);
+        self.rx.set(None);
+        self.terminate.set(None);
+        self.disconnect_reason.set(None);
+        self.alarm.disarm();
+        self.client.map(|client| client.disconnected(reason));
+        if self.advertise.get() {
+            self.schedule_advertising();
+        } else {
+            self.state.set(State::Standby);
+        }
+    }
+
+    /// Wakes up before the receive window of the next connection event.
+    fn schedule_connection_event(&self) {
+        self.connection.map(|connection| {
+            let open_us = connection
+                .anchor_us
+                .saturating_sub(connection.window_widening_us() + WAKEUP_US + Self::tick_us());
+            self.state.set(State::Connected);
+            self.alarm
+                .set_alarm(self.sync.get(), Self::ticks_from_us(open_us));
+        });
+    }
+
+    fn start_connection_event(&self) {
+        let event = self.connection.map(|connection| {
+            let channel = RadioChannel::from_channel_index(connection.channel as u32)
+                .unwrap_or(RadioChannel::DataChannel0);
+            self.radio
+                .set_access_address(connection.access_address, connection.crc_init);
+            // Stop listening once the central had the time to start its
+            // packet
+            let close_us = connection.anchor_us
+                + connection.window_us
+                + connection.window_widening_us()
+                + ADDRESS_US
+                + WAKEUP_US
+                + Self::tick_us();
+            (channel, close_us)
+        });
+        // The radio asks for the prepared response when it starts listening,
+        // so the connection must not be borrowed then
+        self.state.set(State::ConnectionEvent);
+        match event {
+            Some((channel, close_us))
+                if self.radio.listen_and_respond(channel) == ReturnCode::SUCCESS =>
+            {
+                self.alarm
+                    .set_alarm(self.sync.get(), Self::ticks_from_us(close_us));
+            }
+            _ => self.connection_event_done(),
+        }
+    }
+
+    /// Writes the response transmitted if a packet of the central cannot be
+    /// answered in time: the PDU being sent, acknowledging nothing. This is
+    /// also the answer to a packet with a CRC error, so it is valid whatever
+    /// the central sent.
+    fn prepare_connection(&self, response: &mut [u8]) -> usize {
+        self.connection.map_or(0, |connection| {
+            self.tx.get().unwrap_or_else(Pdu::empty).encode(
+                response,
+                connection.sn,
+                connection.nesn,
+            )
+        })
+    }
+
+    /// Answers a packet of the central in a connection event: acknowledges
+    /// it, and responds with the PDU being sent. If `response` is `None`, the
+    /// prepared response was sent, so the packet is not acknowledged.
+    fn respond_connection(&self, pdu: &[u8], crc_ok: bool, response: Option<&mut [u8]>) -> usize {
+        let now = self.alarm.now();
+        self.connection.map_or(0, |connection| {
+            let mut acked = false;
+            if crc_ok && pdu.len() >= 2 {
+                let header = pdu[0];
+                // The anchor point is the start of the packet of the central
+                let air_time = air_time_us(pdu.len());
+                self.sync
+                    .set(now.wrapping_sub(Self::ticks_from_us(air_time)));
+                connection.anchor_us = 0;
+                connection.established = true;
+
+                if (header & NESN != 0) != connection.sn {
+                    // Our PDU was acknowledged
+                    acked = true;
+                    connection.sn = !connection.sn;
+                    if let Some(tx) = self.tx.take() {
+                        if tx.llid == LLID_CONTROL && tx.payload[0] == LL_TERMINATE_IND {
+                            self.disconnect_reason
+                                .set(Some(reason::LOCAL_HOST_TERMINATED));
+                        }
+                    }
+                }
+                if response.is_some() && (header & SN != 0) == connection.nesn {
+                    // A new PDU, which we acknowledge if we have room for it
+                    let llid = header & 0b11;
+                    let len = pdu[1] as usize;
+                    if llid == LLID_CONTINUATION && len == 0 {
+                        connection.nesn = !connection.nesn;
+                    } else if self.rx.get().is_none() && len <= MAX_DATA_PAYLOAD && len > 0 {
+                        let mut rx = Pdu::empty();
+                        rx.llid = llid;
+                        rx.len = len as u8;
+                        rx.payload[..len].copy_from_slice(&pdu[2..2 + len]);
+                        self.rx.set(Some(rx));
+                        connection.nesn = !connection.nesn;
+                    }
+                }
+            }
+            match response {
+                Some(response) => {
+                    let tx = self
+                        .tx
+                        .get()
+                        .or_else(|| self.tx_next.take())
+                        .unwrap_or_else(Pdu::empty);
+                    self.tx.set(Some(tx));
+                    tx.encode(response, connection.sn, connection.nesn)
+                }
+                None => {
+                    // Unless it repeated an acknowledged PDU, which the
+                    // central ignores, the prepared response was a new empty
+                    // PDU
+                    if !acked && self.tx.get().is_none() {
+                        self.tx.set(Some(Pdu::empty()));
+                    }
+                    0
+                }
+            }
+        })
+    }
+
+    /// Handles the PDU received in the last connection event, unless there is
+    /// no room for its response yet.
+    fn handle_received(&self) {
+        if let Some(rx) = self.rx.get() {
+            let handled = match rx.llid {
+                LLID_CONTROL => self.receive_control(&rx),
+                llid => self.client.map_or(true, |client| {
+                    client.receive(llid == LLID_START, rx.payload())
+                }),
+            };
+            if handled {
+                self.rx.set(None);
+            }
+        }
+    }
+
+    /// Queues a control PDU, if there is room for it.
+    fn queue_control(&self, opcode: u8, data: &[u8]) -> bool {
+        if self.tx_next.get().is_some() {
+            return false;
+        }
+        self.tx_next.set(Some(Pdu::control(opcode, data)));
+        true
+    }
+
+    /// Handles a control PDU. Returns false if there is no room for its
+    /// response yet.
+    ///
+    /// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 5.1
+    fn receive_control(&self, pdu: &Pdu) -> bool {
+        let data = &pdu.payload()[1..];
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        match pdu.payload[0] {
+            LL_CONNECTION_UPDATE_IND if data.len() == 11 => {
+                let update = ConnectionUpdate {
+                    window_size_us: data[0] as u32 * UNIT_US,
+                    window_offset_us: u16_at(1) as u32 * UNIT_US,
+                    interval_us: u16_at(3) as u32 * UNIT_US,
+                    supervision_timeout_us: u16_at(7) as u32 * 10_000,
+                    instant: u16_at(9),
+                };
+                self.connection.map(|connection| {
+                    if connection.has_passed(update.instant) {
+                        self.disconnect_reason.set(Some(reason::INSTANT_PASSED));
+                    } else {
+                        connection.update = Some(update);
+                    }
+                });
+                true
+            }
+            LL_CHANNEL_MAP_IND if data.len() == 7 => {
+                let mut channel_map = [0; 5];
+                channel_map.copy_from_slice(&data[..5]);
+                channel_map[4] &= 0x1f;
+                let instant = u16_at(5);
+                self.connection.map(|connection| {
+                    if connection.has_passed(instant) {
+                        self.disconnect_reason.set(Some(reason::INSTANT_PASSED));
+                    } else if channel_map.iter().map(|b| b.count_ones()).sum::<u32>() >= 2 {
+                        connection.channel_map_update = Some((instant, channel_map));
+                    }
+                });
+                true
+            }
+            LL_TERMINATE_IND if !data.is_empty() => {
+                self.disconnect_reason.set(Some(data[0]));
+                true
+            }
+            LL_ENC_REQ => self.queue_control(LL_REJECT_IND, &[reason::UNSUPPORTED_REMOTE_FEATURE]),
+            // No optional feature is supported
+            LL_FEATURE_REQ => self.queue_control(LL_FEATURE_RSP, &[0; 8]),
+            LL_VERSION_IND => {
+                let sent = self
+                    .connection
+                    .map_or(true, |connection| connection.version_sent);
+                if sent {
+                    return true;
+                }
+                let company = COMPANY_ID.to_le_bytes();
+                let queued =
+                    self.queue_control(LL_VERSION_IND, &[VERSION, company[0], company[1], 0, 0]);
+                if queued {
+                    self.connection
+                        .map(|connection| connection.version_sent = true);
+                }
+                queued
+            }
+            LL_PING_REQ => self.queue_control(LL_PING_RSP, &[]),
+            LL_LENGTH_REQ => {
+                // Keep the default lengths: 27 bytes, 328 µs
+                let octets = (MAX_DATA_PAYLOAD as u16).to_le_bytes();
+                let time = 328u16.to_le_bytes();
+                self.queue_control(
+                    LL_LENGTH_RSP,
+                    &[
+                        octets[0], octets[1], time[0], time[1], octets[0], octets[1], time[0],
+                        time[1],
+                    ],
+                )
+            }
+            // Responses to procedures we never start
+            LL_UNKNOWN_RSP | LL_FEATURE_RSP | LL_REJECT_IND | LL_PING_RSP | LL_LENGTH_RSP => true,
+            opcode => self.queue_control(LL_UNKNOWN_RSP, &[opcode]),
+        }
+    }
+
+    /// Prepares the PDU to send after the one in flight.
+    fn fill_tx_next(&self) {
+        if self.tx_next.get().is_some() {
+            return;
+        }
+        if let Some(reason) = self.terminate.take() {
+            self.tx_next
+                .set(Some(Pdu::control(LL_TERMINATE_IND, &[reason])));
+            return;
+        }
+        let mut pdu = Pdu::empty();
+        let next = self
+            .client
+            .map_or(None, |client| client.next_payload(&mut pdu.payload));
+        if let Some((start, len)) = next {
+            pdu.llid = if start { LLID_START } else { LLID_CONTINUATION };
+            pdu.len = cmp::min(len, MAX_DATA_PAYLOAD) as u8;
+            self.tx_next.set(Some(pdu));
+        }
+    }
+
+    /// The connection event ended, with or without a packet.
+    fn connection_event_done(&self) {
+        self.handle_received();
+        if let Some(reason) = self.disconnect_reason.take() {
+            self.disconnect_now(reason);
+            return;
+        }
+        self.fill_tx_next();
+
+        let timed_out = self.connection.map_or(None, |connection| {
+            connection.next_event();
+            if connection.timed_out() {
+                Some(if connection.established {
+                    reason::CONNECTION_TIMEOUT
+                } else {
+                    reason::FAILED_TO_ESTABLISH
+                })
+            } else {
+                None
+            }
+        });
+        match timed_out {
+            Some(reason) => self.disconnect_now(reason),
+            None => self.schedule_connection_event(),
+        }
+    }
+}
+
+impl<'a, R: LinkLayerRadio<'a>, A: time::Alarm<'a>> LinkControl for LinkLayer<'a, R, A> {
+    fn start_advertising(&self) -> ReturnCode {
+        if self.advertise.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.advertise.set(true);
+        if self.state.get() == State::Standby {
+            self.schedule_advertising();
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn stop_advertising(&self) -> ReturnCode {
+        if !self.advertise.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.advertise.set(false);
+        // An advertising event in progress ends on its own
+        if self.state.get() == State::AdvertisingIdle {
+            self.alarm.disarm();
+            self.state.set(State::Standby);
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn disconnect(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Connected | State::ConnectionEvent => {
+                self.terminate.set(Some(reason::REMOTE_USER_TERMINATED));
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::EOFF,
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        matches!(self.state.get(), State::Connected | State::ConnectionEvent)
+    }
+}
+
+impl<'a, R: LinkLayerRadio<'a>, A: time::Alarm<'a>> LinkLayerClient for LinkLayer<'a, R, A> {
+    fn prepare_response(&self, response: &mut [u8]) -> usize {
+        match self.state.get() {
+            State::Advertising(_) => self.scan_response(response),
+            State::ConnectionEvent => self.prepare_connection(response),
+            _ => 0,
+        }
+    }
+
+    fn respond(&self, pdu: &[u8], crc_ok: bool, response: Option<&mut [u8]>) -> usize {
+        match self.state.get() {
+            State::Advertising(_) if crc_ok => self.respond_advertising(pdu, response),
+            State::ConnectionEvent => self.respond_connection(pdu, crc_ok, response),
+            _ => 0,
+        }
+    }
+
+    fn operation_done(&self, _result: ReturnCode) {
+        match self.state.get() {
+            State::Advertising(index) => self.advertising_done(index),
+            State::ConnectionEvent => self.connection_event_done(),
+            _ => (),
+        }
+    }
+}
+
+impl<'a, R: LinkLayerRadio<'a>, A: time::Alarm<'a>> time::AlarmClient for LinkLayer<'a, R, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::AdvertisingIdle => self.advertise_on(0),
+            State::Advertising(index) => {
+                if self.radio.stop_listening() != ReturnCode::EBUSY {
+                    self.advertising_done(index);
+                }
+            }
+            State::Connected => self.start_connection_event(),
+            State::ConnectionEvent => {
+                if self.radio.stop_listening() != ReturnCode::EBUSY {
+                    self.connection_event_done();
+                }
+            }
+            State::Standby => (),
+        }
+    }
+}
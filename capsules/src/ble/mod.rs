@@ -0,0 +1,8 @@
+//! Bluetooth Low Energy peripheral stack: the link layer, L2CAP, and a GATT
+//! server over the Attribute Protocol.
+
+pub mod att;
+pub mod driver;
+pub mod gatt;
+pub mod l2cap;
+pub mod link_layer;
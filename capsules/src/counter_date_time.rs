@@ -0,0 +1,114 @@
+//! Software date and time clock built on a hardware counter.
+//!
+//! `CounterDateTime` implements `hil::date_time::DateTime` for chips whose
+//! RTC block is only a counter, such as the sam4l AST or the nrf5x RTC. It
+//! counts the overflows of the counter to extend it to 64 bits, and keeps the
+//! date and time it was last set to along with the counter value at that
+//! moment. The counter must keep running for the clock to stay correct, and
+//! the clock has to be set again after every reset, so it returns `EOFF`
+//! until it is set.
+//!
+//! The counter must be at most 32 bits wide.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//! # use kernel::hil::time::Counter;
+//! # use capsules::counter_date_time::CounterDateTime;
+//!
+//! let date_time = static_init!(
+//!     CounterDateTime<'static, nrf52840::rtc::Rtc>,
+//!     CounterDateTime::new(&base_peripherals.rtc)
+//! );
+//! base_peripherals.rtc.set_overflow_client(date_time);
+//! base_peripherals.rtc.start();
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::date_time::{DateTime, DateTimeValues};
+use kernel::hil::time::{self, Counter, Frequency, Ticks};
+use kernel::ReturnCode;
+
+/// The date and time the clock was set to, and when.
+#[derive(Copy, Clone)]
+struct Reference {
+    /// Seconds since the Unix epoch.
+    seconds: u64,
+    /// Extended counter value.
+    ticks: u64,
+}
+
+pub struct CounterDateTime<'a, C: Counter<'a>> {
+    counter: &'a C,
+    /// Number of times the counter wrapped around.
+    wraps: Cell<u64>,
+    /// Wrap-arounds that `ticks()` already counted before their overflow
+    /// callback ran.
+    early_wraps: Cell<u32>,
+    /// Counter value at the last call to `ticks()`.
+    last_now: Cell<u32>,
+    reference: Cell<Option<Reference>>,
+}
+
+impl<'a, C: Counter<'a>> CounterDateTime<'a, C> {
+    pub fn new(counter: &'a C) -> CounterDateTime<'a, C> {
+        CounterDateTime {
+            counter,
+            wraps: Cell::new(0),
+            early_wraps: Cell::new(0),
+            last_now: Cell::new(0),
+            reference: Cell::new(None),
+        }
+    }
+
+    /// The counter extended to 64 bits with the number of wrap-arounds.
+    fn ticks(&self) -> u64 {
+        let now = self.counter.now().into_u32();
+        // The overflow callback runs some time after the counter wrapped
+        // around, so a value smaller than the last one means that it wrapped
+        // around and the callback is still pending.
+        if now < self.last_now.get() {
+            self.wraps.set(self.wraps.get() + 1);
+            self.early_wraps.set(self.early_wraps.get() + 1);
+        }
+        self.last_now.set(now);
+        self.wraps.get() * (C::Ticks::max_value().into_u32() as u64 + 1) + now as u64
+    }
+}
+
+impl<'a, C: Counter<'a>> time::OverflowClient for CounterDateTime<'a, C> {
+    fn overflow(&self) {
+        if self.early_wraps.get() > 0 {
+            self.early_wraps.set(self.early_wraps.get() - 1);
+        } else {
+            self.wraps.set(self.wraps.get() + 1);
+            self.last_now.set(0);
+        }
+    }
+}
+
+impl<'a, C: Counter<'a>> DateTime for CounterDateTime<'a, C> {
+    fn get_date_time(&self) -> Result<DateTimeValues, ReturnCode> {
+        let reference = self.reference.get().ok_or(ReturnCode::EOFF)?;
+        let elapsed = (self.ticks() - reference.ticks) / C::Frequency::frequency() as u64;
+        DateTimeValues::from_unix_time(reference.seconds + elapsed).ok_or(ReturnCode::FAIL)
+    }
+
+    fn set_date_time(&self, date_time: DateTimeValues) -> ReturnCode {
+        if !self.counter.is_running() {
+            return ReturnCode::EOFF;
+        }
+        match date_time.unix_time() {
+            Some(seconds) => {
+                self.reference.set(Some(Reference {
+                    seconds,
+                    ticks: self.ticks(),
+                }));
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+}
@@ -0,0 +1,498 @@
+//! Keeps the crash records of the kernel in flash across reboots.
+//!
+//! `CrashLog` is a `kernel::crash_record::CrashStore`: the kernel hands it a
+//! record of every panic, including those caused by processes that fault with
+//! `FaultResponse::Panic`, and it writes the record to a ring of flash pages
+//! reserved for it, one record per page. Once every page holds a record, the
+//! oldest one is overwritten.
+//!
+//! At boot the log reads all of its pages to find the records, and prints a
+//! summary of the most recent one. Records are then read out:
+//!
+//! - with the `crash` command of the process console, which prints a record
+//!   as `crash:` lines of hex that `tools/crash_record_decode.py` decodes,
+//! - by processes, through the system call interface below.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//! # use capsules::crash_log::CrashLog;
+//!
+//! kernel::storage_volume!(CRASH_LOG, 8);
+//!
+//! let crash_log = static_init!(
+//!     CrashLog<'static, nrf52840::nvmc::Nvmc>,
+//!     CrashLog::new(
+//!         &base_peripherals.nvmc,
+//!         static_init!(nrf52840::nvmc::NrfPage, Default::default()),
+//!         CRASH_LOG.as_ptr() as usize / nrf52840::nvmc::PAGE_SIZE,
+//!         CRASH_LOG.len() / nrf52840::nvmc::PAGE_SIZE,
+//!         board_kernel.create_grant(&grant_cap),
+//!     )
+//! );
+//! hil::flash::HasClient::set_client(&base_peripherals.nvmc, crash_log);
+//! kernel::crash_record::set_crash_store(crash_log);
+//! crash_log.start();
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Allow 0: the buffer a record is read into, at least
+//!   `crash_record::RECORD_LEN` bytes long.
+//! - Subscribe 0: called with the result, and for a read the length of the
+//!   record, when a read or clear completes.
+//! - Command 0: check whether the driver exists.
+//! - Command 1: returns how many records are stored, or `EBUSY` while the
+//!   log is still reading its pages at boot.
+//! - Command 2: read the record with index `arg1`, 0 being the most recent,
+//!   into the allowed buffer.
+//! - Command 3: erase all records.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::TakeCell;
+use kernel::crash_record::{CrashKind, CrashRecord, CrashRecordReader, CrashStore, RECORD_LEN};
+use kernel::debug;
+use kernel::hil;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::CrashLog as usize;
+
+/// The most flash pages a crash log uses.
+pub const MAX_PAGES: usize = 8;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+/// How many bytes of a record each `crash:` line holds.
+const HEX_LINE_LEN: usize = 32;
+
+/// Who asked for the operation in progress.
+#[derive(Clone, Copy, PartialEq)]
+enum Requester {
+    /// The log itself, to print a summary of the newest record at boot
+    Boot,
+    Console,
+    App(AppId),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    /// Reading the page with this index to find the records
+    Scanning(usize),
+    /// Writing a record to the page with this index
+    Storing(usize),
+    Reading(Requester),
+    /// Erasing the page with this index
+    Clearing(usize, Requester),
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct CrashLog<'a, F: hil::flash::Flash + 'static> {
+    flash: &'a F,
+    buffer: TakeCell<'static, F::Page>,
+    /// The number of the first flash page of the log
+    first_page: usize,
+    pages: usize,
+    /// The sequence number of the record in each page, if it holds one
+    sequences: [Cell<Option<u32>>; MAX_PAGES],
+    state: Cell<State>,
+    /// Set when the kernel wants to store a record while another operation
+    /// is in progress, so that the log does not start another one.
+    halted: Cell<bool>,
+    /// Whether the last record given to `store()` is written
+    stored: Cell<bool>,
+    apps: Grant<App>,
+}
+
+impl<'a, F: hil::flash::Flash + 'static> CrashLog<'a, F> {
+    /// Create a log on `pages` flash pages, from `first_page` on. At most
+    /// `MAX_PAGES` are used.
+    pub fn new(
+        flash: &'a F,
+        buffer: &'static mut F::Page,
+        first_page: usize,
+        pages: usize,
+        grant: Grant<App>,
+    ) -> CrashLog<'a, F> {
+        CrashLog {
+            flash,
+            buffer: TakeCell::new(buffer),
+            first_page,
+            pages: cmp::min(pages, MAX_PAGES),
+            sequences: Default::default(),
+            state: Cell::new(State::Idle),
+            halted: Cell::new(false),
+            stored: Cell::new(false),
+            apps: grant,
+        }
+    }
+
+    /// Find the records in flash. The log can store records before this
+    /// completes, but does not read them out.
+    pub fn start(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.scan(0)
+    }
+
+    fn scan(&self, index: usize) -> ReturnCode {
+        if index == self.pages {
+            self.state.set(State::Idle);
+            if self.count() > 0 {
+                self.read(0, Requester::Boot)
+            } else {
+                ReturnCode::SUCCESS
+            }
+        } else {
+            self.state.set(State::Scanning(index));
+            let result = self.read_page(index);
+            if result != ReturnCode::SUCCESS {
+                self.state.set(State::Idle);
+            }
+            result
+        }
+    }
+
+    /// Returns the index in the log of the page holding the record with this
+    /// index, 0 being the most recent.
+    fn page_of(&self, index: usize) -> Option<usize> {
+        (0..self.pages).find(|&page| match self.sequences[page].get() {
+            Some(sequence) => {
+                let newer = self
+                    .sequences
+                    .iter()
+                    .filter(|other| other.get().map_or(false, |other| other > sequence))
+                    .count();
+                newer == index
+            }
+            None => false,
+        })
+    }
+
+    fn read(&self, index: usize, requester: Requester) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        match self.page_of(index) {
+            Some(page) => {
+                self.state.set(State::Reading(requester));
+                let result = self.read_page(page);
+                if result != ReturnCode::SUCCESS {
+                    self.state.set(State::Idle);
+                }
+                result
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    fn read_page(&self, page: usize) -> ReturnCode {
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            match self.flash.read_page(self.first_page + page, buffer) {
+                Ok(()) => ReturnCode::SUCCESS,
+                Err((result, buffer)) => {
+                    self.buffer.replace(buffer);
+                    result
+                }
+            }
+        })
+    }
+
+    fn clear_from(&self, page: usize, requester: Requester) -> ReturnCode {
+        if page == self.pages {
+            self.state.set(State::Idle);
+            if requester == Requester::Console {
+                debug!("Crash log cleared");
+            }
+            self.done(requester, ReturnCode::SUCCESS, 0);
+            return ReturnCode::SUCCESS;
+        }
+        self.state.set(State::Clearing(page, requester));
+        let result = self.flash.erase_page(self.first_page + page);
+        if result != ReturnCode::SUCCESS {
+            self.state.set(State::Idle);
+        }
+        result
+    }
+
+    /// Report the end of a read or clear.
+    fn done(&self, requester: Requester, result: ReturnCode, len: usize) {
+        match requester {
+            Requester::Boot => {}
+            Requester::Console => {
+                if result != ReturnCode::SUCCESS {
+                    debug!("Crash log error: {:?}", result);
+                }
+            }
+            Requester::App(appid) => {
+                let _ = self.apps.enter(appid, |app, _| {
+                    app.callback
+                        .map(|mut cb| cb.schedule(usize::from(result), len, 0));
+                });
+            }
+        }
+    }
+
+    /// Print a one line summary of a record.
+    fn print_summary(record: &CrashRecord) {
+        match record.kind() {
+            CrashKind::ProcessFault => debug!(
+                "Crash record {}: process {} faulted, kernel {}: {}",
+                record.sequence(),
+                record.process_name(),
+                record.kernel_version(),
+                record.message()
+            ),
+            CrashKind::KernelPanic => debug!(
+                "Crash record {}: kernel {} panicked: {}",
+                record.sequence(),
+                record.kernel_version(),
+                record.message()
+            ),
+        }
+    }
+
+    /// Print an encoded record as `crash:` lines of hex.
+    fn print_hex(bytes: &[u8]) {
+        for line in bytes.chunks(HEX_LINE_LEN) {
+            let mut hex = [0; 2 * HEX_LINE_LEN];
+            for (i, byte) in line.iter().enumerate() {
+                hex[2 * i] = HEX_DIGITS[(byte >> 4) as usize];
+                hex[2 * i + 1] = HEX_DIGITS[(byte & 0xf) as usize];
+            }
+            debug!(
+                "crash: {}",
+                core::str::from_utf8(&hex[..2 * line.len()]).unwrap_or("")
+            );
+        }
+    }
+}
+
+impl<F: hil::flash::Flash + 'static> CrashStore for CrashLog<'_, F> {
+    fn store(&self, record: &CrashRecord) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            self.halted.set(true);
+            return ReturnCode::EBUSY;
+        }
+        // Write after the most recent record, or at the start of the log.
+        let (page, sequence) = match self.page_of(0) {
+            Some(page) => (
+                (page + 1) % self.pages,
+                self.sequences[page].get().unwrap_or(0).wrapping_add(1),
+            ),
+            None => (0, 0),
+        };
+        let mut record = *record;
+        record.set_sequence(sequence);
+
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            let bytes = buffer.as_mut();
+            for byte in bytes.iter_mut() {
+                *byte = 0xff;
+            }
+            bytes[..RECORD_LEN].copy_from_slice(&record.to_bytes());
+            self.stored.set(false);
+            match self.flash.write_page(self.first_page + page, buffer) {
+                Ok(()) => {
+                    self.state.set(State::Storing(page));
+                    self.sequences[page].set(Some(sequence));
+                    ReturnCode::SUCCESS
+                }
+                Err((result, buffer)) => {
+                    self.buffer.replace(buffer);
+                    result
+                }
+            }
+        })
+    }
+
+    fn is_stored(&self) -> bool {
+        self.stored.get()
+    }
+}
+
+impl<F: hil::flash::Flash + 'static> CrashRecordReader for CrashLog<'_, F> {
+    fn count(&self) -> usize {
+        self.sequences[..self.pages]
+            .iter()
+            .filter(|sequence| sequence.get().is_some())
+            .count()
+    }
+
+    fn print(&self, index: usize) -> ReturnCode {
+        self.read(index, Requester::Console)
+    }
+
+    fn clear(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.clear_from(0, Requester::Console)
+    }
+}
+
+impl<F: hil::flash::Flash + 'static> hil::flash::Client<F> for CrashLog<'_, F> {
+    fn read_complete(&self, buffer: &'static mut F::Page, error: hil::flash::Error) {
+        let record = match error {
+            hil::flash::Error::CommandComplete => CrashRecord::from_bytes(buffer.as_mut()),
+            hil::flash::Error::FlashError => None,
+        };
+        let state = self.state.get();
+        match state {
+            State::Scanning(page) => {
+                self.sequences[page].set(record.map(|record| record.sequence()));
+            }
+            State::Reading(requester) => match record {
+                Some(record) => match requester {
+                    Requester::Boot => Self::print_summary(&record),
+                    Requester::Console => {
+                        Self::print_summary(&record);
+                        Self::print_hex(&buffer.as_mut()[..RECORD_LEN]);
+                    }
+                    Requester::App(appid) => {
+                        let len = self
+                            .apps
+                            .enter(appid, |app, _| {
+                                app.buffer.as_mut().map_or(0, |slice| {
+                                    let len = cmp::min(slice.len(), RECORD_LEN);
+                                    slice.as_mut()[..len].copy_from_slice(&buffer.as_mut()[..len]);
+                                    len
+                                })
+                            })
+                            .unwrap_or(0);
+                        self.done(requester, ReturnCode::SUCCESS, len);
+                    }
+                },
+                None => self.done(requester, ReturnCode::FAIL, 0),
+            },
+            _ => {}
+        }
+        self.buffer.replace(buffer);
+
+        if self.halted.get() {
+            self.state.set(State::Idle);
+            return;
+        }
+        match state {
+            State::Scanning(page) => {
+                self.scan(page + 1);
+            }
+            _ => self.state.set(State::Idle),
+        }
+    }
+
+    fn write_complete(&self, buffer: &'static mut F::Page, error: hil::flash::Error) {
+        self.buffer.replace(buffer);
+        if let State::Storing(page) = self.state.get() {
+            match error {
+                hil::flash::Error::CommandComplete => self.stored.set(true),
+                hil::flash::Error::FlashError => self.sequences[page].set(None),
+            }
+        }
+        self.state.set(State::Idle);
+    }
+
+    fn erase_complete(&self, error: hil::flash::Error) {
+        if let State::Clearing(page, requester) = self.state.get() {
+            self.sequences[page].set(None);
+            if self.halted.get() {
+                self.state.set(State::Idle);
+            } else if error == hil::flash::Error::FlashError {
+                self.state.set(State::Idle);
+                self.done(requester, ReturnCode::FAIL, 0);
+            } else {
+                let result = self.clear_from(page + 1, requester);
+                if result != ReturnCode::SUCCESS {
+                    self.done(requester, result, 0);
+                }
+            }
+        }
+    }
+}
+
+impl<F: hil::flash::Flash + 'static> Driver for CrashLog<'_, F> {
+    /// Setup the buffer a record is read into.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The buffer for reads.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup the callback.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Called when a read or clear completes.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Read and clear the records.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: The number of records.
+    /// - `2`: Read the record with index `arg1`, 0 being the most recent.
+    /// - `3`: Erase all records.
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => match self.state.get() {
+                State::Scanning(_) => ReturnCode::EBUSY,
+                _ => ReturnCode::SuccessWithValue {
+                    value: self.count(),
+                },
+            },
+            2 => self.read(arg1, Requester::App(appid)),
+            3 => {
+                if self.state.get() != State::Idle {
+                    return ReturnCode::EBUSY;
+                }
+                self.clear_from(0, Requester::App(appid))
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
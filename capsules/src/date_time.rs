@@ -0,0 +1,241 @@
+//! Provides userspace with the date and time, and with wakeups at a given
+//! date and time.
+//!
+//! Times are in seconds since the Unix epoch, 1970-01-01 00:00:00 UTC, which
+//! apps can convert to a calendar date and back with the usual libc
+//! functions. Setting the clock moves the timestamps and wakeups of every
+//! process, so processes can only set it when the board allows it with
+//! `allow_set`, for example so that an app can set it from a time it received
+//! over the network. Boards that allow it should restrict command 2 to the
+//! processes that need it with `Platform::filter_syscall()`, for example with
+//! the permissions in the TBF headers (`TbfHeaderFilterDefaultAllow`).
+//!
+//! A wakeup fires once the clock reaches the requested time, with a
+//! resolution of one second. Because the alarm can only wait for a limited
+//! time, and because the clock can be set in the meantime, the driver waits
+//! in steps and reads the clock again after each one, so a wakeup follows
+//! the clock even when it is set forwards or backwards.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//! # use capsules::counter_date_time::CounterDateTime;
+//! # use capsules::date_time::DateTimeDriver;
+//! # use capsules::virtual_alarm::VirtualMuxAlarm;
+//!
+//! let date_time_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let date_time_driver = static_init!(
+//!     DateTimeDriver<
+//!         'static,
+//!         CounterDateTime<'static, nrf52840::rtc::Rtc>,
+//!         VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>,
+//!     >,
+//!     DateTimeDriver::new(
+//!         date_time,
+//!         date_time_alarm,
+//!         board_kernel.create_grant(&memory_allocation_capability),
+//!         true,
+//!     )
+//! );
+//! date_time_alarm.set_alarm_client(date_time_driver);
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Subscribe 0: callback for wakeups, called with the current time.
+//! - Command 0: check whether the driver exists.
+//! - Command 1: get the current time. Returns `EOFF` if the clock was not set.
+//! - Command 2: set the clock to `arg1`. Returns `ENOSUPPORT` if the board
+//!   does not allow processes to set the clock.
+//! - Command 3: wake the process up at time `arg1`. Replaces any earlier
+//!   wakeup of the process, and fires right away if the time already passed.
+//! - Command 4: cancel the wakeup of the process.
+
+use kernel::hil::date_time::{DateTime, DateTimeValues};
+use kernel::hil::time::{self, Alarm, Frequency, Ticks};
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::DateTime as usize;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    /// Time of the wakeup, in seconds since the Unix epoch.
+    wakeup: Option<u64>,
+}
+
+pub struct DateTimeDriver<'a, D: DateTime, A: Alarm<'a>> {
+    date_time: &'a D,
+    alarm: &'a A,
+    apps: Grant<App>,
+    /// Whether processes may set the clock
+    allow_set: bool,
+}
+
+impl<'a, D: DateTime, A: Alarm<'a>> DateTimeDriver<'a, D, A> {
+    /// Creates the driver. Processes can only set the clock if `allow_set`
+    /// is true.
+    pub fn new(
+        date_time: &'a D,
+        alarm: &'a A,
+        grant: Grant<App>,
+        allow_set: bool,
+    ) -> DateTimeDriver<'a, D, A> {
+        DateTimeDriver {
+            date_time,
+            alarm,
+            apps: grant,
+            allow_set,
+        }
+    }
+
+    fn now(&self) -> Result<u64, ReturnCode> {
+        self.date_time
+            .get_date_time()?
+            .unix_time()
+            .ok_or(ReturnCode::FAIL)
+    }
+
+    /// Arm the alarm for the earliest wakeup, or for as long as it can wait
+    /// if that is further away. Disarms it if there is no wakeup, or if the
+    /// clock does not know the time.
+    fn reset_alarm(&self) {
+        let mut earliest: Option<u64> = None;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if let Some(wakeup) = app.wakeup {
+                    if earliest.map_or(true, |earliest| wakeup < earliest) {
+                        earliest = Some(wakeup);
+                    }
+                }
+            });
+        }
+        match (earliest, self.now()) {
+            (Some(wakeup), Ok(now)) => {
+                // Stay well inside the range of the alarm.
+                let max_seconds = (A::Ticks::max_value().into_u32() / 2 / A::Frequency::frequency())
+                    .max(1) as u64;
+                let seconds = wakeup.saturating_sub(now).min(max_seconds) as u32;
+                self.alarm
+                    .set_alarm(self.alarm.now(), A::ticks_from_seconds(seconds));
+            }
+            _ => {
+                self.alarm.disarm();
+            }
+        }
+    }
+}
+
+impl<'a, D: DateTime, A: Alarm<'a>> time::AlarmClient for DateTimeDriver<'a, D, A> {
+    fn alarm(&self) {
+        if let Ok(now) = self.now() {
+            for app in self.apps.iter() {
+                app.enter(|app, _| {
+                    if app.wakeup.map_or(false, |wakeup| wakeup <= now) {
+                        app.wakeup = None;
+                        app.callback
+                            .map(|mut callback| callback.schedule(now as usize, 0, 0));
+                    }
+                });
+            }
+        }
+        self.reset_alarm();
+    }
+}
+
+impl<'a, D: DateTime, A: Alarm<'a>> Driver for DateTimeDriver<'a, D, A> {
+    /// Subscribe to wakeups.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Wakeup callback, called with the current time.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Read and set the clock, and request wakeups.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Get the current time, in seconds since the Unix epoch.
+    /// - `2`: Set the clock to `data` seconds since the Unix epoch, if the
+    ///   board allows it.
+    /// - `3`: Wake the process up at `data` seconds since the Unix epoch.
+    /// - `4`: Cancel the wakeup.
+    fn command(&self, command_num: usize, data: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => match self.now() {
+                Ok(now) => ReturnCode::SuccessWithValue {
+                    value: now as usize,
+                },
+                Err(err) => err,
+            },
+            2 if !self.allow_set => ReturnCode::ENOSUPPORT,
+            2 => match DateTimeValues::from_unix_time(data as u64) {
+                Some(date_time) => {
+                    let result = self.date_time.set_date_time(date_time);
+                    if result == ReturnCode::SUCCESS {
+                        // Wakeups are due at a different moment now.
+                        self.reset_alarm();
+                    }
+                    result
+                }
+                None => ReturnCode::EINVAL,
+            },
+            3 => {
+                if let Err(err) = self.now() {
+                    return err;
+                }
+                let result = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.wakeup = Some(data as u64);
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into());
+                if result == ReturnCode::SUCCESS {
+                    self.reset_alarm();
+                }
+                result
+            }
+            4 => {
+                let result = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.wakeup = None;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into());
+                if result == ReturnCode::SUCCESS {
+                    self.reset_alarm();
+                }
+                result
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -19,6 +19,8 @@ pub enum NUM {
 
     // Kernel
     Ipc                   = 0x10000,
+    MessageIpc            = 0x10001,
+    AppWatchdog           = 0x10002,
 
     // HW Buses
     Spi                   = 0x20001,
@@ -31,6 +33,9 @@ pub enum NUM {
     BleAdvertising        = 0x30000,
     Ieee802154            = 0x30001,
     Udp                   = 0x30002,
+    Tcp                   = 0x30003,
+    Coap                  = 0x30004,
+    BleGatt               = 0x30005,
 
     // Cryptography
     Rng                   = 0x40001,
@@ -42,6 +47,9 @@ pub enum NUM {
     AppFlash              = 0x50000,
     NvmStorage            = 0x50001,
     SdCard                = 0x50002,
+    KVStore               = 0x50003,
+    FatFs                 = 0x50004,
+    CrashLog              = 0x50005,
 
     // Sensors
     Temperature           = 0x60000,
@@ -72,5 +80,7 @@ pub enum NUM {
     Screen                = 0x90001,
     Touch                 = 0x90002,
     TextScreen            = 0x90003,
+    Graphics              = 0x90004,
+    DateTime              = 0x90005,
 }
 }
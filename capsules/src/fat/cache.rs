@@ -0,0 +1,179 @@
+//! Write-back cache of device sectors.
+//!
+//! The cache holds a few sectors in memory. Modified sectors are marked dirty
+//! and only written to the device when their entry is needed for another
+//! sector, or when the cache is flushed. Entries are replaced least recently
+//! used first.
+//!
+//! The cache does not do any I/O itself: the filesystem moves the buffer of
+//! an entry to the device with `take_for_read()` or `take_for_write()`, and
+//! returns it with `read_done()` or `write_done()`.
+
+use core::cell::Cell;
+use kernel::common::cells::TakeCell;
+
+use super::format::SECTOR_SIZE;
+
+/// Number of sectors in the cache.
+pub const CACHE_SECTORS: usize = 4;
+
+struct Entry {
+    sector: Cell<Option<u32>>,
+    dirty: Cell<bool>,
+    last_use: Cell<u32>,
+    buffer: TakeCell<'static, [u8]>,
+}
+
+impl Entry {
+    fn new(buffer: &'static mut [u8; SECTOR_SIZE]) -> Entry {
+        Entry {
+            sector: Cell::new(None),
+            dirty: Cell::new(false),
+            last_use: Cell::new(0),
+            buffer: TakeCell::new(buffer),
+        }
+    }
+}
+
+pub struct SectorCache {
+    entries: [Entry; CACHE_SECTORS],
+    /// Counter for the least recently used order of entries.
+    uses: Cell<u32>,
+}
+
+impl SectorCache {
+    pub fn new(buffers: &'static mut [[u8; SECTOR_SIZE]; CACHE_SECTORS]) -> SectorCache {
+        let [b0, b1, b2, b3] = buffers;
+        SectorCache {
+            entries: [
+                Entry::new(b0),
+                Entry::new(b1),
+                Entry::new(b2),
+                Entry::new(b3),
+            ],
+            uses: Cell::new(0),
+        }
+    }
+
+    fn find(&self, sector: u32) -> Option<&Entry> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.sector.get() == Some(sector))?;
+        self.uses.set(self.uses.get().wrapping_add(1));
+        entry.last_use.set(self.uses.get());
+        Some(entry)
+    }
+
+    /// Calls `f` with the contents of `sector`, if it is cached.
+    pub fn get<F, R>(&self, sector: u32, f: F) -> Option<R>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        self.find(sector)
+            .and_then(|entry| entry.buffer.map(|buffer| f(buffer)))
+    }
+
+    /// Calls `f` to modify the contents of `sector`, if it is cached.
+    pub fn get_mut<F, R>(&self, sector: u32, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let entry = self.find(sector)?;
+        let result = entry.buffer.map(|buffer| f(buffer))?;
+        entry.dirty.set(true);
+        Some(result)
+    }
+
+    /// Makes room for `sector` without reading it from the device, and fills
+    /// it with zeroes. This is for sectors that will be overwritten. Returns
+    /// false if the entry for it must be written back first.
+    pub fn claim(&self, sector: u32) -> bool {
+        let index = match self.find(sector) {
+            Some(_) => return true,
+            None => self.victim(),
+        };
+        let entry = &self.entries[index];
+        if entry.dirty.get() {
+            return false;
+        }
+        entry.buffer.map(|buffer| {
+            for byte in buffer.iter_mut() {
+                *byte = 0;
+            }
+        });
+        entry.sector.set(Some(sector));
+        entry.dirty.set(true);
+        self.find(sector);
+        true
+    }
+
+    /// The entry to use for a sector that is not cached: an empty entry, or
+    /// the least recently used one.
+    pub fn victim(&self) -> usize {
+        let uses = self.uses.get();
+        let mut victim = 0;
+        let mut victim_age = 0;
+        for (index, entry) in self.entries.iter().enumerate() {
+            if entry.sector.get().is_none() {
+                return index;
+            }
+            let age = uses.wrapping_sub(entry.last_use.get());
+            if age > victim_age {
+                victim = index;
+                victim_age = age;
+            }
+        }
+        victim
+    }
+
+    /// Returns the sector of an entry if it is dirty.
+    pub fn dirty_sector(&self, index: usize) -> Option<u32> {
+        let entry = &self.entries[index];
+        if entry.dirty.get() {
+            entry.sector.get()
+        } else {
+            None
+        }
+    }
+
+    /// Returns a dirty entry, if there is one.
+    pub fn any_dirty(&self) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.dirty.get())
+    }
+
+    /// Takes the buffer of a clean entry to read `sector` into it. The entry
+    /// is empty until `read_done()`.
+    pub fn take_for_read(&self, index: usize) -> Option<&'static mut [u8]> {
+        let entry = &self.entries[index];
+        entry.sector.set(None);
+        entry.dirty.set(false);
+        entry.buffer.take()
+    }
+
+    /// Returns the buffer of an entry after reading `sector` into it, or
+    /// `None` as the sector if the read failed.
+    pub fn read_done(&self, index: usize, sector: Option<u32>, buffer: &'static mut [u8]) {
+        let entry = &self.entries[index];
+        entry.buffer.replace(buffer);
+        entry.sector.set(sector);
+        if let Some(sector) = sector {
+            self.find(sector);
+        }
+    }
+
+    /// Takes the buffer of a dirty entry to write it back to its sector.
+    pub fn take_for_write(&self, index: usize) -> Option<&'static mut [u8]> {
+        self.entries[index].buffer.take()
+    }
+
+    /// Returns the buffer of an entry after writing it back. The entry stays
+    /// dirty if the write failed.
+    pub fn write_done(&self, index: usize, success: bool, buffer: &'static mut [u8]) {
+        let entry = &self.entries[index];
+        entry.buffer.replace(buffer);
+        if success {
+            entry.dirty.set(false);
+        }
+    }
+}
@@ -0,0 +1,1507 @@
+//! FAT filesystem over a block device, with a file-descriptor-style syscall
+//! interface.
+//!
+//! Operations are executed one at a time as a sequence of steps. A step
+//! accesses sectors through the sector cache, and stops when it needs a
+//! sector that is not cached. The filesystem then reads the sector, or
+//! writes back the entry that makes room for it, and runs the step again
+//! once the device is done. The state of an operation is only advanced once
+//! an access succeeded, so repeating a step after a stop is safe.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::block_storage::{BlockStorage, BlockStorageClient};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use super::cache::{SectorCache, CACHE_SECTORS};
+use super::format::{
+    self, DirEntry, ShortName, Volume, DELETED, DIR_ENTRY_SIZE, END_OF_DIR, ENTRIES_PER_SECTOR,
+    SECTOR_SIZE,
+};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::FatFs as usize;
+
+/// Buffers for the sector cache, assigned in board `main.rs` files.
+pub static mut CACHE_BUFFERS: [[u8; SECTOR_SIZE]; CACHE_SECTORS] =
+    [[0; SECTOR_SIZE]; CACHE_SECTORS];
+
+/// Number of files each process can have open at once.
+pub const MAX_OPEN_FILES: usize = 4;
+
+/// Number of components of a path, including the file name.
+const MAX_DEPTH: usize = 4;
+
+// Flags of the open command.
+const OPEN_WRITE: usize = 1 << 0;
+const OPEN_CREATE: usize = 1 << 1;
+const OPEN_TRUNCATE: usize = 1 << 2;
+
+/// Why a step cannot continue.
+enum Stop {
+    /// The sector must be read into the cache.
+    Load(u32),
+    /// The cache entry must be written back to the device.
+    WriteBack(usize),
+    /// The operation failed.
+    Error(ReturnCode),
+}
+
+type StepResult<T> = Result<T, Stop>;
+
+/// The result of an operation and a value for the second callback argument.
+type Outcome = (ReturnCode, usize);
+
+/// Location of a directory entry.
+#[derive(Clone, Copy, PartialEq)]
+struct EntryLocation {
+    sector: u32,
+    index: usize,
+}
+
+impl EntryLocation {
+    fn offset(&self) -> usize {
+        self.index * DIR_ENTRY_SIZE
+    }
+}
+
+#[derive(Clone, Copy)]
+struct OpenFile {
+    /// The directory entry of the file.
+    entry: EntryLocation,
+    first_cluster: u32,
+    size: u32,
+    position: u32,
+    writable: bool,
+    /// Whether the first cluster or the size changed since the directory
+    /// entry was last updated.
+    modified: bool,
+    /// The cluster with index `cursor_index` in the chain of the file, or 0
+    /// if it is not known. This saves following the chain from its start for
+    /// every access.
+    cursor_cluster: u32,
+    cursor_index: u32,
+}
+
+impl OpenFile {
+    fn new(entry: EntryLocation, dir_entry: &DirEntry, writable: bool) -> OpenFile {
+        OpenFile {
+            entry,
+            first_cluster: dir_entry.cluster,
+            size: dir_entry.size,
+            position: 0,
+            writable,
+            modified: false,
+            cursor_cluster: 0,
+            cursor_index: 0,
+        }
+    }
+}
+
+/// A path split into short names.
+#[derive(Clone, Copy)]
+struct Path {
+    names: [ShortName; MAX_DEPTH],
+    len: usize,
+    /// Case flags of the last name.
+    case: u8,
+}
+
+impl Path {
+    fn parse(path: &[u8]) -> Option<Path> {
+        let mut parsed = Path {
+            names: [[b' '; format::NAME_LEN]; MAX_DEPTH],
+            len: 0,
+            case: 0,
+        };
+        for component in path.split(|&c| c == b'/').filter(|c| !c.is_empty()) {
+            if parsed.len == MAX_DEPTH {
+                return None;
+            }
+            let (name, case) = format::short_name(component)?;
+            parsed.names[parsed.len] = name;
+            parsed.case = case;
+            parsed.len += 1;
+        }
+        Some(parsed)
+    }
+
+    fn last(&self) -> ShortName {
+        self.names[self.len - 1]
+    }
+}
+
+/// Position in a directory.
+#[derive(Clone, Copy)]
+struct DirCursor {
+    /// The current cluster, or 0 in the fixed root directory of FAT16.
+    cluster: u32,
+    /// The sector in the cluster, or in the fixed root directory.
+    sector: u32,
+}
+
+impl DirCursor {
+    /// The start of the directory at `cluster`, where 0 is the root.
+    fn new(volume: &Volume, cluster: u32) -> DirCursor {
+        DirCursor {
+            cluster: if cluster == 0 {
+                volume.root_cluster
+            } else {
+                cluster
+            },
+            sector: 0,
+        }
+    }
+
+    /// The sector at the cursor, or `None` past the end of the fixed root
+    /// directory.
+    fn device_sector(&self, volume: &Volume) -> Option<u32> {
+        if self.cluster == 0 {
+            if self.sector < volume.root_sectors {
+                Some(volume.root_start + self.sector)
+            } else {
+                None
+            }
+        } else {
+            Some(volume.cluster_sector(self.cluster) + self.sector)
+        }
+    }
+}
+
+/// State of looking up a path.
+#[derive(Clone, Copy)]
+struct Walk {
+    /// Index of the path component looked up in the current directory.
+    depth: usize,
+    /// Position in the current directory, `None` before the root.
+    dir: Option<DirCursor>,
+    /// The first unused entry of the last directory, for creating a file.
+    free: Option<EntryLocation>,
+}
+
+impl Walk {
+    fn new() -> Walk {
+        Walk {
+            depth: 0,
+            dir: None,
+            free: None,
+        }
+    }
+}
+
+enum WalkResult {
+    Found(EntryLocation, DirEntry),
+    /// The last component is not in the directory. The walk is at the last
+    /// sector of the directory.
+    Missing,
+    /// The path is the root directory.
+    Root,
+}
+
+/// Entries found when scanning a directory sector.
+enum Scan {
+    Found(usize, DirEntry),
+    /// The end of the directory, with the first unused entry.
+    End(Option<usize>),
+    /// The entry is not in this sector, which has a deleted entry if any.
+    Next(Option<usize>),
+}
+
+/// State of freeing a cluster chain.
+#[derive(Clone, Copy)]
+struct Chain {
+    cluster: u32,
+    /// The cluster after `cluster`, once it was read.
+    next: Option<u32>,
+}
+
+impl Chain {
+    fn new(cluster: u32) -> Chain {
+        Chain {
+            cluster,
+            next: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum OpenPhase {
+    Walk,
+    /// Extending the directory by a cluster to have room for the entry.
+    ExtendDir {
+        last: u32,
+        cluster: Option<u32>,
+        zeroed: u32,
+    },
+    Create(EntryLocation),
+    Truncate(Chain),
+}
+
+#[derive(Clone, Copy)]
+enum ListPhase {
+    Walk,
+    /// Scanning the directory, with the number of entries in the sectors
+    /// before the cursor.
+    Scan(DirCursor, usize),
+}
+
+#[derive(Clone, Copy)]
+enum DeletePhase {
+    Walk,
+    Free(Chain),
+    Flush,
+}
+
+/// An operation requested by an application.
+#[derive(Clone, Copy)]
+enum Task {
+    Open {
+        path: Path,
+        writable: bool,
+        flags: usize,
+        fd: usize,
+        walk: Walk,
+        phase: OpenPhase,
+        file: Option<OpenFile>,
+    },
+    Read {
+        fd: usize,
+        file: OpenFile,
+        len: usize,
+        done: usize,
+    },
+    Write {
+        fd: usize,
+        file: OpenFile,
+        len: usize,
+        done: usize,
+    },
+    Close {
+        fd: usize,
+        file: OpenFile,
+    },
+    List {
+        path: Path,
+        index: usize,
+        walk: Walk,
+        phase: ListPhase,
+    },
+    Delete {
+        path: Path,
+        walk: Walk,
+        phase: DeletePhase,
+    },
+    /// Updating the entries of the open files from `fd` on.
+    Sync {
+        fd: usize,
+    },
+}
+
+impl Task {
+    /// The state of the file descriptor after the task finished with
+    /// `result`, if the task changes it.
+    fn file_state(&self, result: ReturnCode) -> Option<(usize, Option<OpenFile>)> {
+        match *self {
+            Task::Open { fd, file, .. } if succeeded(result) => file.map(|file| (fd, Some(file))),
+            Task::Read { fd, file, .. } | Task::Write { fd, file, .. } => Some((fd, Some(file))),
+            Task::Close { fd, file } => {
+                if succeeded(result) {
+                    Some((fd, None))
+                } else {
+                    Some((fd, Some(file)))
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn succeeded(result: ReturnCode) -> bool {
+    matches!(
+        result,
+        ReturnCode::SUCCESS | ReturnCode::SuccessWithValue { .. }
+    )
+}
+
+/// What the filesystem waits on the device for.
+#[derive(Clone, Copy)]
+enum Io {
+    Idle,
+    Read { index: usize, sector: u32 },
+    Write { index: usize },
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    path: Option<AppSlice<Shared, u8>>,
+    data: Option<AppSlice<Shared, u8>>,
+    files: [Option<OpenFile>; MAX_OPEN_FILES],
+    pending: Option<Task>,
+}
+
+pub struct FatFs<'a, B: BlockStorage<'a>> {
+    device: &'a B,
+    cache: SectorCache,
+    /// The layout of the volume, once it was mounted by the first operation.
+    volume: Cell<Option<Volume>>,
+    apps: Grant<App>,
+    current_app: OptionalCell<AppId>,
+    task: Cell<Option<Task>>,
+    io: Cell<Io>,
+    /// A free cluster picked for a chain that is not linked yet.
+    new_cluster: OptionalCell<u32>,
+    /// Where to continue looking for free clusters.
+    free_hint: Cell<u32>,
+    /// Number of clusters checked since the last free one was found.
+    free_scanned: Cell<u32>,
+    /// Whether the free cluster count in the FSInfo sector was invalidated.
+    fsinfo_invalidated: Cell<bool>,
+}
+
+impl<'a, B: BlockStorage<'a>> FatFs<'a, B> {
+    pub fn new(
+        device: &'a B,
+        cache_buffers: &'static mut [[u8; SECTOR_SIZE]; CACHE_SECTORS],
+        grant: Grant<App>,
+    ) -> FatFs<'a, B> {
+        FatFs {
+            device,
+            cache: SectorCache::new(cache_buffers),
+            volume: Cell::new(None),
+            apps: grant,
+            current_app: OptionalCell::empty(),
+            task: Cell::new(None),
+            io: Cell::new(Io::Idle),
+            new_cluster: OptionalCell::empty(),
+            free_hint: Cell::new(2),
+            free_scanned: Cell::new(0),
+            fsinfo_invalidated: Cell::new(false),
+        }
+    }
+
+    // Sector access.
+
+    fn read<F, R>(&self, sector: u32, f: F) -> StepResult<R>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        self.cache.get(sector, f).ok_or(Stop::Load(sector))
+    }
+
+    fn modify<F, R>(&self, sector: u32, f: F) -> StepResult<R>
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        self.cache.get_mut(sector, f).ok_or(Stop::Load(sector))
+    }
+
+    /// Modifies a sector without reading it first if it is not cached, in
+    /// which case it starts out with zeroes.
+    fn overwrite<F, R>(&self, sector: u32, f: F) -> StepResult<R>
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        if !self.cache.claim(sector) {
+            return Err(Stop::WriteBack(self.cache.victim()));
+        }
+        self.modify(sector, f)
+    }
+
+    /// Writes back all dirty sectors.
+    fn flush(&self) -> StepResult<()> {
+        match self.cache.any_dirty() {
+            Some(index) => Err(Stop::WriteBack(index)),
+            None => Ok(()),
+        }
+    }
+
+    fn mount(&self) -> StepResult<Volume> {
+        if self.device.block_size() != SECTOR_SIZE {
+            return Err(Stop::Error(ReturnCode::ENOSUPPORT));
+        }
+
+        // The volume is either the whole device, or its first FAT partition.
+        let start = self.read(0, |sector| match Volume::parse(sector, 0) {
+            Ok(_) => Ok(0),
+            Err(ReturnCode::FAIL) => format::mbr_partition(sector).ok_or(ReturnCode::FAIL),
+            Err(rcode) => Err(rcode),
+        })?;
+        let start = start.map_err(Stop::Error)?;
+        let volume = self
+            .read(start, |sector| Volume::parse(sector, start))?
+            .map_err(Stop::Error)?;
+
+        self.volume.set(Some(volume));
+        self.free_hint.set(2);
+        self.free_scanned.set(0);
+        self.fsinfo_invalidated.set(false);
+        Ok(volume)
+    }
+
+    // Cluster chains.
+
+    fn fat_get(&self, volume: &Volume, cluster: u32) -> StepResult<u32> {
+        let (sector, offset) = volume.fat_entry(cluster);
+        self.read(sector, |buf| volume.read_fat(buf, offset))
+    }
+
+    /// Sets the entry for `cluster` in all FATs. The sectors of all FATs are
+    /// loaded before any is modified, so that a set either changes all
+    /// copies or none.
+    fn fat_set(&self, volume: &Volume, cluster: u32, value: u32) -> StepResult<()> {
+        if volume.fsinfo_sector != 0 && !self.fsinfo_invalidated.get() {
+            self.modify(volume.fsinfo_sector, format::invalidate_fsinfo)?;
+            self.fsinfo_invalidated.set(true);
+        }
+
+        let (sector, offset) = volume.fat_entry(cluster);
+        for fat in 0..volume.num_fats {
+            self.read(sector + fat * volume.fat_sectors, |_| ())?;
+        }
+        for fat in 0..volume.num_fats {
+            self.modify(sector + fat * volume.fat_sectors, |buf| {
+                volume.write_fat(buf, offset, value)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Returns the cluster after `cluster` in its chain, or `None` if it is
+    /// the last one.
+    fn next_cluster(&self, volume: &Volume, cluster: u32) -> StepResult<Option<u32>> {
+        let next = self.fat_get(volume, cluster)?;
+        if volume.is_cluster(next) {
+            Ok(Some(next))
+        } else if next >= volume.end_of_chain() - 7 {
+            Ok(None)
+        } else {
+            // A free or bad cluster in a chain.
+            Err(Stop::Error(ReturnCode::FAIL))
+        }
+    }
+
+    fn find_free_cluster(&self, volume: &Volume) -> StepResult<u32> {
+        let per_sector = volume.entries_per_fat_sector();
+        loop {
+            if self.free_scanned.get() >= volume.num_clusters {
+                self.free_scanned.set(0);
+                return Err(Stop::Error(ReturnCode::ENOMEM));
+            }
+
+            let mut first = self.free_hint.get();
+            if !volume.is_cluster(first) {
+                first = 2;
+            }
+            let end = cmp::min(
+                first - first % per_sector + per_sector,
+                volume.num_clusters + 2,
+            );
+            let (sector, _) = volume.fat_entry(first);
+            let free = self.read(sector, |buf| {
+                (first..end).find(|&cluster| volume.read_fat(buf, volume.fat_entry(cluster).1) == 0)
+            })?;
+
+            match free {
+                Some(cluster) => {
+                    self.free_hint.set(cluster + 1);
+                    self.free_scanned.set(0);
+                    return Ok(cluster);
+                }
+                None => {
+                    self.free_hint.set(end);
+                    self.free_scanned.set(self.free_scanned.get() + end - first);
+                }
+            }
+        }
+    }
+
+    /// Allocates a cluster and appends it to the chain that ends with
+    /// `last`, or starts a new chain if `last` is 0. The new cluster is kept
+    /// until it is linked, so that repeating the step does not allocate
+    /// another one.
+    fn append_cluster(&self, volume: &Volume, last: u32) -> StepResult<u32> {
+        let cluster = match self.new_cluster.take() {
+            Some(cluster) => cluster,
+            None => self.find_free_cluster(volume)?,
+        };
+        self.new_cluster.set(cluster);
+
+        self.fat_set(volume, cluster, volume.end_of_chain())?;
+        if last != 0 {
+            self.fat_set(volume, last, cluster)?;
+        }
+        self.new_cluster.clear();
+        Ok(cluster)
+    }
+
+    fn free_chain(&self, volume: &Volume, chain: &mut Chain) -> StepResult<()> {
+        while volume.is_cluster(chain.cluster) {
+            let next = match chain.next {
+                Some(next) => next,
+                None => {
+                    let next = self.fat_get(volume, chain.cluster)?;
+                    chain.next = Some(next);
+                    next
+                }
+            };
+            self.fat_set(volume, chain.cluster, 0)?;
+            chain.cluster = next;
+            chain.next = None;
+        }
+        Ok(())
+    }
+
+    /// Returns the cluster of the file that holds the byte at its position,
+    /// or `None` if the chain of the file ends before it.
+    fn file_cluster(&self, volume: &Volume, file: &mut OpenFile) -> StepResult<Option<u32>> {
+        if file.first_cluster == 0 {
+            return Ok(None);
+        }
+        let index = file.position / volume.cluster_size();
+        if file.cursor_cluster == 0 || file.cursor_index > index {
+            file.cursor_cluster = file.first_cluster;
+            file.cursor_index = 0;
+        }
+        while file.cursor_index < index {
+            match self.next_cluster(volume, file.cursor_cluster)? {
+                Some(next) => {
+                    file.cursor_cluster = next;
+                    file.cursor_index += 1;
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(file.cursor_cluster))
+    }
+
+    /// Writes the first cluster and size of a file to its directory entry.
+    fn update_entry(&self, file: &mut OpenFile) -> StepResult<()> {
+        if file.modified {
+            let (cluster, size) = (file.first_cluster, file.size);
+            self.modify(file.entry.sector, |buf| {
+                DirEntry::write_location(buf, file.entry.offset(), cluster, size)
+            })?;
+            file.modified = false;
+        }
+        Ok(())
+    }
+
+    // Directories.
+
+    /// Moves the cursor to the next sector of the directory. Returns false
+    /// at the end of the directory, where the cursor stays.
+    fn advance(&self, volume: &Volume, cursor: &mut DirCursor) -> StepResult<bool> {
+        if cursor.cluster == 0 {
+            if cursor.sector + 1 >= volume.root_sectors {
+                return Ok(false);
+            }
+            cursor.sector += 1;
+        } else if cursor.sector + 1 < volume.sectors_per_cluster {
+            cursor.sector += 1;
+        } else {
+            match self.next_cluster(volume, cursor.cluster)? {
+                Some(next) => {
+                    cursor.cluster = next;
+                    cursor.sector = 0;
+                }
+                None => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+
+    fn walk(&self, volume: &Volume, path: &Path, walk: &mut Walk) -> StepResult<WalkResult> {
+        if path.len == 0 {
+            return Ok(WalkResult::Root);
+        }
+        loop {
+            // Only the last component can be missing, and only files are
+            // created.
+            let last = walk.depth + 1 == path.len;
+            let missing = if last {
+                Ok(WalkResult::Missing)
+            } else {
+                Err(Stop::Error(ReturnCode::FAIL))
+            };
+
+            let mut dir = walk.dir.unwrap_or_else(|| DirCursor::new(volume, 0));
+            walk.dir = Some(dir);
+            let sector = match dir.device_sector(volume) {
+                Some(sector) => sector,
+                None => return missing,
+            };
+
+            let name = path.names[walk.depth];
+            let scan = self.read(sector, |buf| {
+                let mut unused = None;
+                for index in 0..ENTRIES_PER_SECTOR {
+                    let first = buf[index * DIR_ENTRY_SIZE];
+                    if first == END_OF_DIR {
+                        return Scan::End(unused.or(Some(index)));
+                    }
+                    if first == DELETED && unused.is_none() {
+                        unused = Some(index);
+                    }
+                    if let Some(entry) = DirEntry::parse(buf, index * DIR_ENTRY_SIZE) {
+                        if entry.name == name {
+                            return Scan::Found(index, entry);
+                        }
+                    }
+                }
+                Scan::Next(unused)
+            })?;
+
+            let unused = match scan {
+                Scan::Found(index, entry) => {
+                    let location = EntryLocation { sector, index };
+                    if last {
+                        return Ok(WalkResult::Found(location, entry));
+                    } else if !entry.is_directory() {
+                        return Err(Stop::Error(ReturnCode::FAIL));
+                    }
+                    walk.depth += 1;
+                    walk.dir = Some(DirCursor::new(volume, entry.cluster));
+                    walk.free = None;
+                    continue;
+                }
+                Scan::End(unused) | Scan::Next(unused) => unused,
+            };
+            if last && walk.free.is_none() {
+                walk.free = unused.map(|index| EntryLocation { sector, index });
+            }
+            if let Scan::End(_) = scan {
+                return missing;
+            }
+
+            if !self.advance(volume, &mut dir)? {
+                return missing;
+            }
+            walk.dir = Some(dir);
+        }
+    }
+
+    /// Whether the file with the entry at `location` is open, or only
+    /// whether it is open for writing if `writers` is set.
+    fn is_open(&self, location: EntryLocation, writers: bool) -> bool {
+        self.apps.iter().any(|cntr| {
+            cntr.enter(|app, _| {
+                app.files.iter().any(|file| {
+                    file.map_or(false, |file| {
+                        file.entry == location && (file.writable || !writers)
+                    })
+                })
+            })
+        })
+    }
+
+    // Operations.
+
+    fn step(&self, appid: AppId, task: &mut Task) -> StepResult<Outcome> {
+        let volume = match self.volume.get() {
+            Some(volume) => volume,
+            None => self.mount()?,
+        };
+
+        match *task {
+            Task::Open {
+                ref path,
+                writable,
+                flags,
+                fd,
+                ref mut walk,
+                ref mut phase,
+                ref mut file,
+            } => self.open(&volume, path, writable, flags, fd, walk, phase, file),
+            Task::Read {
+                ref mut file,
+                len,
+                ref mut done,
+                ..
+            } => self.read_file(&volume, appid, file, len, done),
+            Task::Write {
+                ref mut file,
+                len,
+                ref mut done,
+                ..
+            } => self.write_file(&volume, appid, file, len, done),
+            Task::Close { ref mut file, .. } => {
+                self.update_entry(file)?;
+                self.flush()?;
+                Ok((ReturnCode::SUCCESS, 0))
+            }
+            Task::List {
+                ref path,
+                index,
+                ref mut walk,
+                ref mut phase,
+            } => self.list(&volume, appid, path, index, walk, phase),
+            Task::Delete {
+                ref path,
+                ref mut walk,
+                ref mut phase,
+            } => self.delete(&volume, path, walk, phase),
+            Task::Sync { ref mut fd } => {
+                while *fd < MAX_OPEN_FILES {
+                    let open = self
+                        .apps
+                        .enter(appid, |app, _| app.files[*fd])
+                        .map_err(|err| Stop::Error(err.into()))?;
+                    if let Some(mut file) = open {
+                        self.update_entry(&mut file)?;
+                        let _ = self.apps.enter(appid, |app, _| app.files[*fd] = Some(file));
+                    }
+                    *fd += 1;
+                }
+                self.flush()?;
+                Ok((ReturnCode::SUCCESS, 0))
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn open(
+        &self,
+        volume: &Volume,
+        path: &Path,
+        writable: bool,
+        flags: usize,
+        fd: usize,
+        walk: &mut Walk,
+        phase: &mut OpenPhase,
+        file: &mut Option<OpenFile>,
+    ) -> StepResult<Outcome> {
+        loop {
+            match *phase {
+                OpenPhase::Walk => match self.walk(volume, path, walk)? {
+                    WalkResult::Found(location, entry) => {
+                        if entry.is_directory() {
+                            return Err(Stop::Error(ReturnCode::EINVAL));
+                        } else if writable && entry.is_read_only() {
+                            return Err(Stop::Error(ReturnCode::EPERM));
+                        } else if self.is_open(location, !writable) {
+                            return Err(Stop::Error(ReturnCode::EBUSY));
+                        }
+
+                        let mut opened = OpenFile::new(location, &entry, writable);
+                        if writable && flags & OPEN_TRUNCATE != 0 && entry.cluster != 0 {
+                            // The entry no longer refers to the clusters once
+                            // they are freed.
+                            opened.first_cluster = 0;
+                            opened.size = 0;
+                            opened.modified = true;
+                            *file = Some(opened);
+                            *phase = OpenPhase::Truncate(Chain::new(entry.cluster));
+                        } else {
+                            *file = Some(opened);
+                            return Ok((ReturnCode::SuccessWithValue { value: fd }, 0));
+                        }
+                    }
+                    WalkResult::Missing => {
+                        if !writable || flags & OPEN_CREATE == 0 {
+                            return Err(Stop::Error(ReturnCode::FAIL));
+                        }
+                        let dir = walk.dir.unwrap_or_else(|| DirCursor::new(volume, 0));
+                        *phase = match walk.free {
+                            Some(location) => OpenPhase::Create(location),
+                            // The fixed root directory cannot be extended.
+                            None if dir.cluster == 0 => {
+                                return Err(Stop::Error(ReturnCode::ENOMEM))
+                            }
+                            None => OpenPhase::ExtendDir {
+                                last: dir.cluster,
+                                cluster: None,
+                                zeroed: 0,
+                            },
+                        };
+                    }
+                    WalkResult::Root => return Err(Stop::Error(ReturnCode::EINVAL)),
+                },
+                OpenPhase::ExtendDir {
+                    last,
+                    cluster,
+                    zeroed,
+                } => {
+                    let cluster = match cluster {
+                        Some(cluster) => cluster,
+                        None => {
+                            let cluster = self.append_cluster(volume, last)?;
+                            *phase = OpenPhase::ExtendDir {
+                                last,
+                                cluster: Some(cluster),
+                                zeroed,
+                            };
+                            cluster
+                        }
+                    };
+                    // The new cluster must not contain stale entries.
+                    let first = volume.cluster_sector(cluster);
+                    for sector in zeroed..volume.sectors_per_cluster {
+                        self.overwrite(first + sector, |buf| {
+                            for byte in buf.iter_mut() {
+                                *byte = 0;
+                            }
+                        })?;
+                        *phase = OpenPhase::ExtendDir {
+                            last,
+                            cluster: Some(cluster),
+                            zeroed: sector + 1,
+                        };
+                    }
+                    *phase = OpenPhase::Create(EntryLocation {
+                        sector: first,
+                        index: 0,
+                    });
+                }
+                OpenPhase::Create(location) => {
+                    let entry = DirEntry::new_file(path.last(), path.case);
+                    self.modify(location.sector, |buf| entry.write(buf, location.offset()))?;
+                    *file = Some(OpenFile::new(location, &entry, writable));
+                    return Ok((ReturnCode::SuccessWithValue { value: fd }, 0));
+                }
+                OpenPhase::Truncate(mut chain) => {
+                    if let Some(ref mut opened) = *file {
+                        self.update_entry(opened)?;
+                    }
+                    let result = self.free_chain(volume, &mut chain);
+                    *phase = OpenPhase::Truncate(chain);
+                    result?;
+                    return Ok((ReturnCode::SuccessWithValue { value: fd }, 0));
+                }
+            }
+        }
+    }
+
+    /// Copies the bytes of a file from its position to the data buffer of
+    /// the application.
+    fn read_file(
+        &self,
+        volume: &Volume,
+        appid: AppId,
+        file: &mut OpenFile,
+        len: usize,
+        done: &mut usize,
+    ) -> StepResult<Outcome> {
+        loop {
+            let remaining = cmp::min(len - *done, (file.size - file.position) as usize);
+            if remaining == 0 {
+                return Ok((ReturnCode::SuccessWithValue { value: *done }, 0));
+            }
+
+            let cluster = match self.file_cluster(volume, file)? {
+                Some(cluster) => cluster,
+                // The chain is shorter than the size of the file.
+                None => return Err(Stop::Error(ReturnCode::FAIL)),
+            };
+            let offset = file.position % volume.cluster_size();
+            let sector = volume.cluster_sector(cluster) + offset / SECTOR_SIZE as u32;
+            let start = (offset % SECTOR_SIZE as u32) as usize;
+            let count = cmp::min(SECTOR_SIZE - start, remaining);
+
+            let copied = self.read(sector, |buf| {
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.data.as_mut().map_or(false, |data| {
+                            let dest = data.as_mut();
+                            if dest.len() < *done + count {
+                                return false;
+                            }
+                            dest[*done..*done + count].copy_from_slice(&buf[start..start + count]);
+                            true
+                        })
+                    })
+                    .unwrap_or(false)
+            })?;
+            if !copied {
+                return Err(Stop::Error(ReturnCode::EINVAL));
+            }
+            file.position += count as u32;
+            *done += count;
+        }
+    }
+
+    /// Copies the data buffer of the application to a file at its position,
+    /// allocating clusters as the file grows.
+    fn write_file(
+        &self,
+        volume: &Volume,
+        appid: AppId,
+        file: &mut OpenFile,
+        len: usize,
+        done: &mut usize,
+    ) -> StepResult<Outcome> {
+        loop {
+            // Files cannot be larger than 4 GiB - 1.
+            let remaining = cmp::min(len - *done, (u32::MAX - file.position) as usize);
+            if remaining == 0 {
+                return if *done == 0 && len > 0 {
+                    Err(Stop::Error(ReturnCode::ESIZE))
+                } else {
+                    Ok((ReturnCode::SuccessWithValue { value: *done }, 0))
+                };
+            }
+
+            let cluster = match self.file_cluster(volume, file)? {
+                Some(cluster) => cluster,
+                None => {
+                    // The position is at the end of the chain, as it is not
+                    // past the end of the file.
+                    let index = file.position / volume.cluster_size();
+                    let last = if file.first_cluster == 0 {
+                        0
+                    } else if file.cursor_index + 1 == index {
+                        file.cursor_cluster
+                    } else {
+                        return Err(Stop::Error(ReturnCode::FAIL));
+                    };
+                    let cluster = match self.append_cluster(volume, last) {
+                        Ok(cluster) => cluster,
+                        // Report the bytes written before the volume filled
+                        // up.
+                        Err(Stop::Error(ReturnCode::ENOMEM)) if *done > 0 => {
+                            return Ok((ReturnCode::SuccessWithValue { value: *done }, 0));
+                        }
+                        Err(stop) => return Err(stop),
+                    };
+                    if last == 0 {
+                        file.first_cluster = cluster;
+                    }
+                    file.cursor_cluster = cluster;
+                    file.cursor_index = index;
+                    file.modified = true;
+                    cluster
+                }
+            };
+            let offset = file.position % volume.cluster_size();
+            let sector = volume.cluster_sector(cluster) + offset / SECTOR_SIZE as u32;
+            let start = (offset % SECTOR_SIZE as u32) as usize;
+            let count = cmp::min(SECTOR_SIZE - start, remaining);
+
+            let copy = |buf: &mut [u8]| {
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.data.as_ref().map_or(false, |data| {
+                            let src = data.as_ref();
+                            if src.len() < *done + count {
+                                return false;
+                            }
+                            buf[start..start + count].copy_from_slice(&src[*done..*done + count]);
+                            true
+                        })
+                    })
+                    .unwrap_or(false)
+            };
+            // Sectors that do not hold any bytes of the file yet do not need
+            // to be read.
+            let copied = if start == 0 && file.position >= file.size {
+                self.overwrite(sector, copy)?
+            } else {
+                self.modify(sector, copy)?
+            };
+            if !copied {
+                return Err(Stop::Error(ReturnCode::EINVAL));
+            }
+
+            file.position += count as u32;
+            *done += count;
+            if file.position > file.size {
+                file.size = file.position;
+                file.modified = true;
+            }
+        }
+    }
+
+    /// Writes the name of the entry at `index` of the directory at `path` to
+    /// the data buffer of the application.
+    fn list(
+        &self,
+        volume: &Volume,
+        appid: AppId,
+        path: &Path,
+        index: usize,
+        walk: &mut Walk,
+        phase: &mut ListPhase,
+    ) -> StepResult<Outcome> {
+        if let ListPhase::Walk = *phase {
+            let dir = match self.walk(volume, path, walk)? {
+                WalkResult::Root => DirCursor::new(volume, 0),
+                WalkResult::Found(_, ref entry) if entry.is_directory() => {
+                    DirCursor::new(volume, entry.cluster)
+                }
+                WalkResult::Found(..) => return Err(Stop::Error(ReturnCode::EINVAL)),
+                WalkResult::Missing => return Err(Stop::Error(ReturnCode::FAIL)),
+            };
+            *phase = ListPhase::Scan(dir, 0);
+        }
+
+        while let ListPhase::Scan(mut dir, seen) = *phase {
+            let sector = match dir.device_sector(volume) {
+                Some(sector) => sector,
+                None => return Err(Stop::Error(ReturnCode::FAIL)),
+            };
+            let scan = self.read(sector, |buf| {
+                let mut seen = seen;
+                for i in 0..ENTRIES_PER_SECTOR {
+                    if buf[i * DIR_ENTRY_SIZE] == END_OF_DIR {
+                        return Scan::End(None);
+                    }
+                    match DirEntry::parse(buf, i * DIR_ENTRY_SIZE) {
+                        Some(entry) if !entry.is_dot() => {
+                            if seen == index {
+                                return Scan::Found(i, entry);
+                            }
+                            seen += 1;
+                        }
+                        _ => {}
+                    }
+                }
+                Scan::Next(Some(seen))
+            })?;
+
+            match scan {
+                Scan::Found(_, entry) => {
+                    let mut name = [0; 13];
+                    let len = entry.format_name(&mut name);
+                    let copied = self
+                        .apps
+                        .enter(appid, |app, _| {
+                            app.data.as_mut().map_or(false, |data| {
+                                let dest = data.as_mut();
+                                if dest.len() < len {
+                                    return false;
+                                }
+                                dest[..len].copy_from_slice(&name[..len]);
+                                true
+                            })
+                        })
+                        .unwrap_or(false);
+                    if !copied {
+                        return Err(Stop::Error(ReturnCode::ESIZE));
+                    }
+                    let size = if entry.is_directory() { 0 } else { entry.size };
+                    return Ok((ReturnCode::SuccessWithValue { value: len }, size as usize));
+                }
+                Scan::End(_) => return Err(Stop::Error(ReturnCode::FAIL)),
+                Scan::Next(seen) => {
+                    if !self.advance(volume, &mut dir)? {
+                        return Err(Stop::Error(ReturnCode::FAIL));
+                    }
+                    *phase = ListPhase::Scan(dir, seen.unwrap_or(0));
+                }
+            }
+        }
+        Err(Stop::Error(ReturnCode::FAIL))
+    }
+
+    fn delete(
+        &self,
+        volume: &Volume,
+        path: &Path,
+        walk: &mut Walk,
+        phase: &mut DeletePhase,
+    ) -> StepResult<Outcome> {
+        loop {
+            match *phase {
+                DeletePhase::Walk => match self.walk(volume, path, walk)? {
+                    WalkResult::Found(location, entry) => {
+                        // Only files can be deleted.
+                        if entry.is_directory() {
+                            return Err(Stop::Error(ReturnCode::EINVAL));
+                        } else if entry.is_read_only() {
+                            return Err(Stop::Error(ReturnCode::EPERM));
+                        } else if self.is_open(location, false) {
+                            return Err(Stop::Error(ReturnCode::EBUSY));
+                        }
+
+                        // The entry is removed before its clusters are freed,
+                        // so that a failure can only lose clusters.
+                        self.modify(location.sector, |buf| {
+                            buf[location.offset()] = DELETED;
+                            // Long name entries precede the entry.
+                            for index in (0..location.index).rev() {
+                                let offset = index * DIR_ENTRY_SIZE;
+                                if buf[offset] == DELETED
+                                    || buf[offset + 11] & format::ATTR_LONG_NAME
+                                        != format::ATTR_LONG_NAME
+                                {
+                                    break;
+                                }
+                                buf[offset] = DELETED;
+                            }
+                        })?;
+                        *phase = DeletePhase::Free(Chain::new(entry.cluster));
+                    }
+                    WalkResult::Missing => return Err(Stop::Error(ReturnCode::FAIL)),
+                    WalkResult::Root => return Err(Stop::Error(ReturnCode::EINVAL)),
+                },
+                DeletePhase::Free(mut chain) => {
+                    let result = self.free_chain(volume, &mut chain);
+                    *phase = DeletePhase::Free(chain);
+                    result?;
+                    *phase = DeletePhase::Flush;
+                }
+                DeletePhase::Flush => {
+                    self.flush()?;
+                    return Ok((ReturnCode::SUCCESS, 0));
+                }
+            }
+        }
+    }
+
+    // Execution.
+
+    /// Runs the current task until it waits for the device or finishes.
+    fn run(&self) {
+        let appid = match self.current_app.map(|appid| *appid) {
+            Some(appid) => appid,
+            None => return,
+        };
+        let mut task = match self.task.get() {
+            Some(task) => task,
+            None => return,
+        };
+
+        let result = self.step(appid, &mut task);
+        self.task.set(Some(task));
+        let started = match result {
+            Ok((rcode, value)) => return self.finish(rcode, value),
+            Err(Stop::Error(rcode)) => return self.finish(rcode, 0),
+            Err(Stop::Load(sector)) => {
+                let index = self.cache.victim();
+                if self.cache.dirty_sector(index).is_some() {
+                    self.write_back(index)
+                } else {
+                    self.load(index, sector)
+                }
+            }
+            Err(Stop::WriteBack(index)) => self.write_back(index),
+        };
+        if let Err(rcode) = started {
+            self.finish(rcode, 0);
+        }
+    }
+
+    fn load(&self, index: usize, sector: u32) -> Result<(), ReturnCode> {
+        let buffer = self.cache.take_for_read(index).ok_or(ReturnCode::FAIL)?;
+        self.device
+            .read_blocks(buffer, sector, 1)
+            .map(|()| self.io.set(Io::Read { index, sector }))
+            .map_err(|(rcode, buffer)| {
+                self.cache.read_done(index, None, buffer);
+                rcode
+            })
+    }
+
+    fn write_back(&self, index: usize) -> Result<(), ReturnCode> {
+        let sector = self.cache.dirty_sector(index).ok_or(ReturnCode::FAIL)?;
+        let buffer = self.cache.take_for_write(index).ok_or(ReturnCode::FAIL)?;
+        self.device
+            .write_blocks(buffer, sector, 1)
+            .map(|()| self.io.set(Io::Write { index }))
+            .map_err(|(rcode, buffer)| {
+                self.cache.write_done(index, false, buffer);
+                rcode
+            })
+    }
+
+    /// Start executing a task for an application.
+    fn start(&self, appid: AppId, task: Task) {
+        self.current_app.set(appid);
+        self.task.set(Some(task));
+        self.run();
+    }
+
+    /// Finish the current task, notify the application, and start the next
+    /// queued task, if any.
+    fn finish(&self, result: ReturnCode, value: usize) {
+        self.new_cluster.clear();
+        let task = self.task.take();
+        if let Some(appid) = self.current_app.take() {
+            let _ = self.apps.enter(appid, |app, _| {
+                if let Some((fd, file)) = task.and_then(|task| task.file_state(result)) {
+                    app.files[fd] = file;
+                }
+                app.callback
+                    .map(|mut cb| cb.schedule(usize::from(result), value, 0));
+            });
+        }
+
+        for cntr in self.apps.iter() {
+            let next = cntr.enter(|app, _| app.pending.take().map(|task| (app.appid(), task)));
+            if let Some((appid, task)) = next {
+                self.start(appid, task);
+                break;
+            }
+        }
+    }
+
+    /// Queue a task built from the state of the application, or start it
+    /// right away if the filesystem is idle.
+    fn enqueue<F>(&self, appid: AppId, build: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App) -> Result<Task, ReturnCode>,
+    {
+        let busy = self.current_app.is_some();
+        let result = self
+            .apps
+            .enter(appid, |app, _| {
+                if app.pending.is_some() || self.current_app.contains(&appid) {
+                    return Err(ReturnCode::EBUSY);
+                }
+                let task = build(app)?;
+                if busy {
+                    app.pending = Some(task);
+                }
+                Ok(task)
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        match result {
+            Ok(task) => {
+                if !busy {
+                    self.start(appid, task);
+                }
+                ReturnCode::SUCCESS
+            }
+            Err(rcode) => rcode,
+        }
+    }
+
+    /// Checks whether a file descriptor can be used by a new command.
+    fn open_file(&self, appid: AppId, app: &App, fd: usize) -> Result<OpenFile, ReturnCode> {
+        if app.pending.is_some() || self.current_app.contains(&appid) {
+            return Err(ReturnCode::EBUSY);
+        }
+        app.files
+            .get(fd)
+            .and_then(|file| *file)
+            .ok_or(ReturnCode::EINVAL)
+    }
+}
+
+/// Parses the first `len` bytes of the path buffer of the application.
+fn app_path(app: &App, len: usize) -> Result<Path, ReturnCode> {
+    app.path
+        .as_ref()
+        .and_then(|path| path.as_ref().get(..len))
+        .and_then(Path::parse)
+        .ok_or(ReturnCode::EINVAL)
+}
+
+/// Checks that the data buffer of the application holds `len` bytes.
+fn check_data(app: &App, len: usize) -> Result<(), ReturnCode> {
+    match app.data {
+        Some(ref data) if data.len() >= len => Ok(()),
+        _ => Err(ReturnCode::EINVAL),
+    }
+}
+
+impl<'a, B: BlockStorage<'a>> BlockStorageClient for FatFs<'a, B> {
+    fn read_done(&self, result: ReturnCode, buffer: &'static mut [u8]) {
+        if let Io::Read { index, sector } = self.io.replace(Io::Idle) {
+            if result == ReturnCode::SUCCESS {
+                self.cache.read_done(index, Some(sector), buffer);
+                self.run();
+            } else {
+                self.cache.read_done(index, None, buffer);
+                self.finish(result, 0);
+            }
+        }
+    }
+
+    fn write_done(&self, result: ReturnCode, buffer: &'static mut [u8]) {
+        if let Io::Write { index } = self.io.replace(Io::Idle) {
+            // A sector that failed to be written stays dirty, so its data is
+            // not lost.
+            let success = result == ReturnCode::SUCCESS;
+            self.cache.write_done(index, success, buffer);
+            if success {
+                self.run();
+            } else {
+                self.finish(result, 0);
+            }
+        }
+    }
+}
+
+impl<'a, B: BlockStorage<'a>> Driver for FatFs<'a, B> {
+    /// Setup path and data buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Set the path buffer.
+    /// - `1`: Set the data buffer. Files are read into and written from this
+    ///   buffer, and directory entries are listed into it.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if allow_num == 0 {
+                        app.path = slice;
+                    } else {
+                        app.data = slice;
+                    }
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Set a callback for when an operation finishes.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Filesystem control.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Open the file at the path of length `arg1`, with the flags in
+    ///   `arg2`.
+    /// - `2`: Read up to `arg2` bytes from file descriptor `arg1`.
+    /// - `3`: Write `arg2` bytes to file descriptor `arg1`.
+    /// - `4`: Set the position of file descriptor `arg1` to `arg2`.
+    /// - `5`: Get the size of the file of file descriptor `arg1`.
+    /// - `6`: Close file descriptor `arg1`.
+    /// - `7`: List entry `arg2` of the directory at the path of length
+    ///   `arg1`.
+    /// - `8`: Delete the file at the path of length `arg1`.
+    /// - `9`: Write all modified data of the open files to the device.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => self.enqueue(appid, |app| {
+                let path = app_path(app, arg1)?;
+                if path.len == 0 {
+                    return Err(ReturnCode::EINVAL);
+                }
+                let fd = app
+                    .files
+                    .iter()
+                    .position(|file| file.is_none())
+                    .ok_or(ReturnCode::ENOMEM)?;
+                Ok(Task::Open {
+                    path,
+                    writable: arg2 & OPEN_WRITE != 0,
+                    flags: arg2,
+                    fd,
+                    walk: Walk::new(),
+                    phase: OpenPhase::Walk,
+                    file: None,
+                })
+            }),
+
+            2 => self.enqueue(appid, |app| {
+                let file = self.open_file(appid, app, arg1)?;
+                check_data(app, arg2)?;
+                Ok(Task::Read {
+                    fd: arg1,
+                    file,
+                    len: arg2,
+                    done: 0,
+                })
+            }),
+
+            3 => self.enqueue(appid, |app| {
+                let file = self.open_file(appid, app, arg1)?;
+                if !file.writable {
+                    return Err(ReturnCode::EPERM);
+                }
+                check_data(app, arg2)?;
+                Ok(Task::Write {
+                    fd: arg1,
+                    file,
+                    len: arg2,
+                    done: 0,
+                })
+            }),
+
+            4 => self
+                .apps
+                .enter(appid, |app, _| {
+                    let mut file = match self.open_file(appid, app, arg1) {
+                        Ok(file) => file,
+                        Err(rcode) => return rcode,
+                    };
+                    // Files cannot have holes, so the position cannot be past
+                    // the end of the file.
+                    if arg2 > file.size as usize {
+                        return ReturnCode::EINVAL;
+                    }
+                    file.position = arg2 as u32;
+                    app.files[arg1] = Some(file);
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            5 => self
+                .apps
+                .enter(appid, |app, _| match self.open_file(appid, app, arg1) {
+                    Ok(file) => ReturnCode::SuccessWithValue {
+                        value: file.size as usize,
+                    },
+                    Err(rcode) => rcode,
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            6 => self.enqueue(appid, |app| {
+                let file = self.open_file(appid, app, arg1)?;
+                Ok(Task::Close { fd: arg1, file })
+            }),
+
+            7 => self.enqueue(appid, |app| {
+                Ok(Task::List {
+                    path: app_path(app, arg1)?,
+                    index: arg2,
+                    walk: Walk::new(),
+                    phase: ListPhase::Walk,
+                })
+            }),
+
+            8 => self.enqueue(appid, |app| {
+                let path = app_path(app, arg1)?;
+                if path.len == 0 {
+                    return Err(ReturnCode::EINVAL);
+                }
+                Ok(Task::Delete {
+                    path,
+                    walk: Walk::new(),
+                    phase: DeletePhase::Walk,
+                })
+            }),
+
+            9 => self.enqueue(appid, |_| Ok(Task::Sync { fd: 0 })),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
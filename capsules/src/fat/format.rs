@@ -0,0 +1,452 @@
+//! On-disk structures of FAT16 and FAT32 volumes.
+//!
+//! Only 512-byte sectors and short (8.3) names are supported. Long file
+//! names are skipped when reading directories, so files that have one are
+//! found by their short alias.
+
+use kernel::ReturnCode;
+
+pub const SECTOR_SIZE: usize = 512;
+pub const DIR_ENTRY_SIZE: usize = 32;
+pub const ENTRIES_PER_SECTOR: usize = SECTOR_SIZE / DIR_ENTRY_SIZE;
+
+/// Length of a short name, without the dot.
+pub const NAME_LEN: usize = 11;
+pub type ShortName = [u8; NAME_LEN];
+
+/// Largest number of copies of the FAT that is supported.
+pub const MAX_FATS: u32 = 2;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+pub const ATTR_LONG_NAME: u8 = 0x0F;
+
+/// First name byte of a deleted entry.
+pub const DELETED: u8 = 0xE5;
+/// First name byte of the entry after the last one in a directory.
+pub const END_OF_DIR: u8 = 0x00;
+
+/// Flags in the reserved byte of an entry, as used by Windows and Linux to
+/// keep the case of names that are all lowercase.
+const CASE_LOWER_BASE: u8 = 0x08;
+const CASE_LOWER_EXT: u8 = 0x10;
+
+/// Date and time of entries the filesystem creates: 1980-01-01 00:00, the
+/// epoch of FAT timestamps, as there is no wall clock.
+const DEFAULT_DATE: u16 = (1 << 5) | 1;
+const DEFAULT_TIME: u16 = 0;
+
+/// Partition types of FAT16 and FAT32 volumes in an MBR partition table.
+const FAT_PARTITION_TYPES: [u8; 6] = [0x04, 0x06, 0x0B, 0x0C, 0x0E, 0x01];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FatType {
+    Fat16,
+    Fat32,
+}
+
+/// Layout of a mounted volume. Sector numbers are absolute sectors of the
+/// block device.
+#[derive(Clone, Copy, Debug)]
+pub struct Volume {
+    pub fat_type: FatType,
+    pub sectors_per_cluster: u32,
+    /// First sector of the first FAT.
+    pub fat_start: u32,
+    pub fat_sectors: u32,
+    pub num_fats: u32,
+    /// First sector of the fixed root directory of FAT16 volumes.
+    pub root_start: u32,
+    pub root_sectors: u32,
+    /// First cluster of the root directory of FAT32 volumes.
+    pub root_cluster: u32,
+    /// FSInfo sector of FAT32 volumes, or 0 if there is none.
+    pub fsinfo_sector: u32,
+    /// Sector of cluster 2, the first data cluster.
+    pub data_start: u32,
+    pub num_clusters: u32,
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn has_signature(sector: &[u8]) -> bool {
+    sector[510] == 0x55 && sector[511] == 0xAA
+}
+
+impl Volume {
+    /// Parses the boot sector of a volume starting at sector `start`.
+    /// Returns ENOSUPPORT for FAT12 volumes, sector sizes other than 512
+    /// bytes and more than two FATs, and FAIL if the sector is not a FAT
+    /// boot sector.
+    pub fn parse(sector: &[u8], start: u32) -> Result<Volume, ReturnCode> {
+        // All FAT boot sectors start with a jump instruction.
+        if !has_signature(sector) || (sector[0] != 0xEB && sector[0] != 0xE9) {
+            return Err(ReturnCode::FAIL);
+        }
+
+        let bytes_per_sector = read_u16(sector, 11) as u32;
+        let sectors_per_cluster = sector[13] as u32;
+        let reserved_sectors = read_u16(sector, 14) as u32;
+        let num_fats = sector[16] as u32;
+        let root_entries = read_u16(sector, 17) as u32;
+        let total_sectors = match read_u16(sector, 19) {
+            0 => read_u32(sector, 32),
+            n => n as u32,
+        };
+        let fat_sectors = match read_u16(sector, 22) {
+            0 => read_u32(sector, 36),
+            n => n as u32,
+        };
+
+        if !bytes_per_sector.is_power_of_two()
+            || bytes_per_sector < 512
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || num_fats == 0
+            || fat_sectors == 0
+        {
+            return Err(ReturnCode::FAIL);
+        }
+        // All copies of the FAT must fit in the sector cache together.
+        if bytes_per_sector != SECTOR_SIZE as u32 || num_fats > MAX_FATS {
+            return Err(ReturnCode::ENOSUPPORT);
+        }
+
+        let root_sectors =
+            (root_entries * DIR_ENTRY_SIZE as u32 + SECTOR_SIZE as u32 - 1) / SECTOR_SIZE as u32;
+        let meta_sectors =
+            reserved_sectors as u64 + num_fats as u64 * fat_sectors as u64 + root_sectors as u64;
+        if total_sectors as u64 <= meta_sectors {
+            return Err(ReturnCode::FAIL);
+        }
+        let num_clusters = (total_sectors - meta_sectors as u32) / sectors_per_cluster;
+
+        // The type of a volume is determined by its number of clusters only.
+        if num_clusters < 4085 {
+            return Err(ReturnCode::ENOSUPPORT);
+        } else if num_clusters > 0x0FFF_FFF5 {
+            return Err(ReturnCode::FAIL);
+        }
+        let (fat_type, entry_size) = if num_clusters < 65525 {
+            (FatType::Fat16, 2)
+        } else {
+            (FatType::Fat32, 4)
+        };
+
+        // A FAT must have an entry for every cluster.
+        if (num_clusters as u64 + 2) * entry_size > fat_sectors as u64 * SECTOR_SIZE as u64 {
+            return Err(ReturnCode::FAIL);
+        }
+
+        let (root_cluster, fsinfo_sector) = match fat_type {
+            FatType::Fat16 => (0, 0),
+            FatType::Fat32 => {
+                let fsinfo = read_u16(sector, 48) as u32;
+                let fsinfo_sector = if fsinfo > 0 && fsinfo < reserved_sectors {
+                    start + fsinfo
+                } else {
+                    0
+                };
+                (read_u32(sector, 44) & 0x0FFF_FFFF, fsinfo_sector)
+            }
+        };
+
+        let fat_start = start + reserved_sectors;
+        let root_start = fat_start + num_fats * fat_sectors;
+        let volume = Volume {
+            fat_type,
+            sectors_per_cluster,
+            fat_start,
+            fat_sectors,
+            num_fats,
+            root_start,
+            root_sectors,
+            root_cluster,
+            fsinfo_sector,
+            data_start: root_start + root_sectors,
+            num_clusters,
+        };
+        if fat_type == FatType::Fat32 && !volume.is_cluster(root_cluster) {
+            return Err(ReturnCode::FAIL);
+        }
+        Ok(volume)
+    }
+
+    /// Bytes in a cluster.
+    pub fn cluster_size(&self) -> u32 {
+        self.sectors_per_cluster * SECTOR_SIZE as u32
+    }
+
+    /// Whether `cluster` is the number of a data cluster.
+    pub fn is_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.num_clusters + 2
+    }
+
+    /// First sector of a data cluster.
+    pub fn cluster_sector(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.sectors_per_cluster
+    }
+
+    /// The sector of the first FAT and the offset in it of the entry for
+    /// `cluster`.
+    pub fn fat_entry(&self, cluster: u32) -> (u32, usize) {
+        let offset = match self.fat_type {
+            FatType::Fat16 => cluster * 2,
+            FatType::Fat32 => cluster * 4,
+        };
+        (
+            self.fat_start + offset / SECTOR_SIZE as u32,
+            (offset % SECTOR_SIZE as u32) as usize,
+        )
+    }
+
+    /// Number of FAT entries in a sector of the FAT.
+    pub fn entries_per_fat_sector(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat16 => SECTOR_SIZE as u32 / 2,
+            FatType::Fat32 => SECTOR_SIZE as u32 / 4,
+        }
+    }
+
+    /// Reads the FAT entry at `offset` of a FAT sector.
+    pub fn read_fat(&self, sector: &[u8], offset: usize) -> u32 {
+        match self.fat_type {
+            FatType::Fat16 => read_u16(sector, offset) as u32,
+            FatType::Fat32 => read_u32(sector, offset) & 0x0FFF_FFFF,
+        }
+    }
+
+    /// Writes the FAT entry at `offset` of a FAT sector. The reserved upper
+    /// bits of FAT32 entries are kept.
+    pub fn write_fat(&self, sector: &mut [u8], offset: usize, value: u32) {
+        match self.fat_type {
+            FatType::Fat16 => write_u16(sector, offset, value as u16),
+            FatType::Fat32 => {
+                let reserved = read_u32(sector, offset) & 0xF000_0000;
+                write_u32(sector, offset, reserved | (value & 0x0FFF_FFFF));
+            }
+        }
+    }
+
+    /// The FAT entry that marks the last cluster of a chain.
+    pub fn end_of_chain(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+}
+
+/// Returns the first sector of the first FAT partition in the MBR in
+/// `sector`, if there is one.
+pub fn mbr_partition(sector: &[u8]) -> Option<u32> {
+    if !has_signature(sector) {
+        return None;
+    }
+    (0..4)
+        .map(|i| &sector[446 + 16 * i..446 + 16 * (i + 1)])
+        .find(|entry| FAT_PARTITION_TYPES.contains(&entry[4]) && read_u32(entry, 8) != 0)
+        .map(|entry| read_u32(entry, 8))
+}
+
+/// Invalidates the free cluster count and next free cluster hint in a FAT32
+/// FSInfo sector, so that other systems recount them.
+pub fn invalidate_fsinfo(sector: &mut [u8]) {
+    if read_u32(sector, 0) == 0x4161_5252 && read_u32(sector, 484) == 0x6141_7272 {
+        write_u32(sector, 488, 0xFFFF_FFFF);
+        write_u32(sector, 492, 0xFFFF_FFFF);
+    }
+}
+
+/// A directory entry.
+#[derive(Clone, Copy, Debug)]
+pub struct DirEntry {
+    pub name: ShortName,
+    pub attributes: u8,
+    pub case: u8,
+    pub cluster: u32,
+    pub size: u32,
+}
+
+impl DirEntry {
+    /// Parses the entry at `offset` of a directory sector. Returns `None`
+    /// for entries that are deleted, long name parts, or volume labels.
+    pub fn parse(sector: &[u8], offset: usize) -> Option<DirEntry> {
+        let entry = &sector[offset..offset + DIR_ENTRY_SIZE];
+        let attributes = entry[11];
+        if entry[0] == DELETED
+            || entry[0] == END_OF_DIR
+            || attributes & ATTR_LONG_NAME == ATTR_LONG_NAME
+            || attributes & ATTR_VOLUME_ID != 0
+        {
+            return None;
+        }
+
+        let mut name = [0; NAME_LEN];
+        name.copy_from_slice(&entry[0..NAME_LEN]);
+        // 0x05 stands for a name starting with the byte 0xE5.
+        if name[0] == 0x05 {
+            name[0] = DELETED;
+        }
+        Some(DirEntry {
+            name,
+            attributes,
+            case: entry[12],
+            cluster: (read_u16(entry, 20) as u32) << 16 | read_u16(entry, 26) as u32,
+            size: read_u32(entry, 28),
+        })
+    }
+
+    /// A new, empty file.
+    pub fn new_file(name: ShortName, case: u8) -> DirEntry {
+        DirEntry {
+            name,
+            attributes: ATTR_ARCHIVE,
+            case,
+            cluster: 0,
+            size: 0,
+        }
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.attributes & ATTR_READ_ONLY != 0
+    }
+
+    /// Whether this is the `.` or `..` entry of a subdirectory.
+    pub fn is_dot(&self) -> bool {
+        self.name[0] == b'.'
+    }
+
+    /// Writes the whole entry at `offset` of a directory sector.
+    pub fn write(&self, sector: &mut [u8], offset: usize) {
+        let entry = &mut sector[offset..offset + DIR_ENTRY_SIZE];
+        for byte in entry.iter_mut() {
+            *byte = 0;
+        }
+        entry[0..NAME_LEN].copy_from_slice(&self.name);
+        if entry[0] == DELETED {
+            entry[0] = 0x05;
+        }
+        entry[11] = self.attributes;
+        entry[12] = self.case;
+        write_u16(entry, 14, DEFAULT_TIME);
+        write_u16(entry, 16, DEFAULT_DATE);
+        write_u16(entry, 18, DEFAULT_DATE);
+        write_u16(entry, 22, DEFAULT_TIME);
+        write_u16(entry, 24, DEFAULT_DATE);
+        Self::write_location(entry, 0, self.cluster, self.size);
+    }
+
+    /// Updates the first cluster and size of the entry at `offset` of a
+    /// directory sector.
+    pub fn write_location(sector: &mut [u8], offset: usize, cluster: u32, size: u32) {
+        let entry = &mut sector[offset..offset + DIR_ENTRY_SIZE];
+        write_u16(entry, 20, (cluster >> 16) as u16);
+        write_u16(entry, 26, cluster as u16);
+        write_u32(entry, 28, size);
+    }
+
+    /// Writes the name as `NAME.EXT` to `buf`, and returns its length.
+    /// Directories get a trailing `/`. `buf` must hold at least 13 bytes.
+    pub fn format_name(&self, buf: &mut [u8]) -> usize {
+        let base_len = self.name[..8]
+            .iter()
+            .rposition(|&c| c != b' ')
+            .map_or(0, |i| i + 1);
+        let ext_len = self.name[8..]
+            .iter()
+            .rposition(|&c| c != b' ')
+            .map_or(0, |i| i + 1);
+
+        let lower = |c: u8, flag: u8| {
+            if self.case & flag != 0 {
+                c.to_ascii_lowercase()
+            } else {
+                c
+            }
+        };
+        let mut len = 0;
+        for &c in self.name[..base_len].iter() {
+            buf[len] = lower(c, CASE_LOWER_BASE);
+            len += 1;
+        }
+        if ext_len > 0 {
+            buf[len] = b'.';
+            len += 1;
+            for &c in self.name[8..8 + ext_len].iter() {
+                buf[len] = lower(c, CASE_LOWER_EXT);
+                len += 1;
+            }
+        }
+        if self.is_directory() {
+            buf[len] = b'/';
+            len += 1;
+        }
+        len
+    }
+}
+
+/// Converts a path component to a short name and the case flags for it. Returns `None` if the component is not a valid
+/// 8.3 name.
+pub fn short_name(component: &[u8]) -> Option<(ShortName, u8)> {
+    let (base, ext) = match component.iter().rposition(|&c| c == b'.') {
+        Some(dot) => (&component[..dot], &component[dot + 1..]),
+        None => (component, &component[..0]),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return None;
+    }
+
+    let valid = |c: &u8| c.is_ascii_alphanumeric() || b"!#$%&'()-@^_`{}~".contains(c);
+    if !base.iter().all(valid) || !ext.iter().all(valid) {
+        return None;
+    }
+
+    // Names are matched regardless of case. The case of parts that are all
+    // lowercase is kept with flags, other names would need a long name for
+    // their case and are stored in uppercase.
+    let case_flag = |part: &[u8], flag: u8| {
+        if part.iter().any(|c| c.is_ascii_lowercase())
+            && !part.iter().any(|c| c.is_ascii_uppercase())
+        {
+            flag
+        } else {
+            0
+        }
+    };
+    let case = case_flag(base, CASE_LOWER_BASE) | case_flag(ext, CASE_LOWER_EXT);
+
+    let mut name = [b' '; NAME_LEN];
+    for (dest, c) in name[..8].iter_mut().zip(base.iter()) {
+        *dest = c.to_ascii_uppercase();
+    }
+    for (dest, c) in name[8..].iter_mut().zip(ext.iter()) {
+        *dest = c.to_ascii_uppercase();
+    }
+    Some((name, case))
+}
@@ -0,0 +1,85 @@
+//! FAT16/FAT32 filesystem for applications, over a block device such as an
+//! SD card.
+//!
+//! Applications open files by path and access them through file descriptors,
+//! so that they do not have to implement FAT themselves to share data with a
+//! computer. Paths consist of short (8.3) names separated by `/`, and are
+//! matched regardless of case. Long file names are not supported: files that
+//! have one are listed and opened by their short alias.
+//!
+//! Each process has its own table of open files in its grant. A file can be
+//! open for writing only once, and not at the same time as for reading.
+//!
+//! Sectors are kept in a small write-back cache. Data written to a file, and
+//! its size in the directory, only reach the device when the file is closed
+//! or synced, or when the cache needs room for other sectors. Volumes are
+//! either the whole device or the first FAT partition on it, and are mounted
+//! by the first operation.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let sdcard_block = static_init!(
+//!     capsules::sdcard::SDCardBlockStorage<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::sdcard::SDCardBlockStorage::new(sdcard));
+//! sdcard.set_client(sdcard_block);
+//!
+//! let fat = static_init!(
+//!     capsules::fat::FatFs<'static, capsules::sdcard::SDCardBlockStorage<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>>,
+//!     capsules::fat::FatFs::new(
+//!         sdcard_block,
+//!         &mut capsules::fat::CACHE_BUFFERS,
+//!         board_kernel.create_grant(&grant_cap)
+//!     )
+//! );
+//! kernel::hil::block_storage::BlockStorage::set_client(sdcard_block, fat);
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Allow 0: The path.
+//! - Allow 1: The data buffer. Files are read into and written from this
+//!   buffer, and directory entries are listed into it.
+//! - Subscribe 0: Called when an operation finishes, with the `ReturnCode` of
+//!   the operation as the first argument. Operations with a result return it
+//!   as the `ReturnCode` value.
+//! - Command 0: Check if the driver exists.
+//! - Command 1: Open the file at the path in the first `arg1` bytes of the
+//!   path buffer. `arg2` holds flags: bit 0 opens the file for writing, bit 1
+//!   creates it if it does not exist, and bit 2 truncates it. The result is
+//!   the file descriptor.
+//! - Command 2: Read up to `arg2` bytes from file descriptor `arg1` at its
+//!   position. The result is the number of bytes read, which is 0 at the end
+//!   of the file.
+//! - Command 3: Write `arg2` bytes to file descriptor `arg1` at its position.
+//!   The result is the number of bytes written, which is less than `arg2`
+//!   if the volume is full.
+//! - Command 4: Set the position of file descriptor `arg1` to `arg2`, which
+//!   can be at most the size of the file. Returns immediately.
+//! - Command 5: Get the size of the file of file descriptor `arg1`. Returns
+//!   immediately.
+//! - Command 6: Close file descriptor `arg1`, writing its data to the device.
+//! - Command 7: List entry `arg2` of the directory at the path in the first
+//!   `arg1` bytes of the path buffer. The name of the entry is written to the
+//!   data buffer, with a trailing `/` for directories. The result is the
+//!   length of the name, and the second callback argument is the size of the
+//!   file. Returns `FAIL` past the last entry.
+//! - Command 8: Delete the file at the path in the first `arg1` bytes of the
+//!   path buffer.
+//! - Command 9: Write the data of all open files to the device.
+//!
+//! Each process can have one operation in progress. Operations fail with
+//! `FAIL` if the path does not exist, `EBUSY` if the file is already open or
+//! another operation of the process is in progress, `ENOMEM` if the process
+//! has too many open files or the volume or directory is full, and `EPERM`
+//! for writing to read-only files.
+
+mod cache;
+mod filesystem;
+mod format;
+
+pub use self::filesystem::{App, FatFs, CACHE_BUFFERS, DRIVER_NUM, MAX_OPEN_FILES};
@@ -0,0 +1,515 @@
+//! Rendering of display lists into a part of the screen.
+//!
+//! The graphics capsule has no framebuffer: it renders the screen a tile at a
+//! time into its transfer buffer, and sends each tile to the screen before
+//! rendering the next one. A `Canvas` is the tile being rendered, seen
+//! through the viewport of an application. Drawing on it uses the
+//! coordinates of the viewport, after its rotation, and only touches the
+//! pixels that fall in the tile.
+//!
+//! Display list
+//! ------------
+//!
+//! Applications describe their viewport as a display list: a sequence of
+//! drawing operations, each an opcode byte followed by its arguments, in
+//! little endian. Coordinates are `i16` and may be outside the viewport,
+//! sizes are `u16`, and colors are `u32` as `0x00RRGGBB`.
+//!
+//! | Opcode | Operation   | Arguments                                            |
+//! |--------|-------------|------------------------------------------------------|
+//! | 0x00   | `END`       | Ends the list                                        |
+//! | 0x01   | `CLEAR`     | color                                                |
+//! | 0x02   | `PIXEL`     | x, y, color                                          |
+//! | 0x03   | `LINE`      | x0, y0, x1, y1, color                                |
+//! | 0x04   | `RECT`      | x, y, width, height, color                           |
+//! | 0x05   | `FILL_RECT` | x, y, width, height, color                           |
+//! | 0x06   | `TEXT`      | x, y, foreground, background, scale: u8, len: u8, text |
+//! | 0x07   | `BITMAP`    | x, y, width, height, foreground, background, scale: u8, rows |
+//!
+//! Text is drawn with the built-in 5x7 font, `\n` starting a new line. The
+//! rows of a bitmap are one bit per pixel, most significant bit first, each
+//! padded to a byte. A scale of 0 is the same as 1, and a background of
+//! `TRANSPARENT` leaves the pixels behind the text or the bitmap unchanged.
+//! The list ends at `END`, at its first malformed operation, or at the end of
+//! the buffer.
+
+use super::font::{Font, FONT_5X7};
+use core::cmp;
+use kernel::hil::screen::{ScreenPixelFormat, ScreenRotation};
+
+/// Display list opcodes
+pub mod op {
+    pub const END: u8 = 0x00;
+    pub const CLEAR: u8 = 0x01;
+    pub const PIXEL: u8 = 0x02;
+    pub const LINE: u8 = 0x03;
+    pub const RECT: u8 = 0x04;
+    pub const FILL_RECT: u8 = 0x05;
+    pub const TEXT: u8 = 0x06;
+    pub const BITMAP: u8 = 0x07;
+}
+
+/// The background color that leaves the pixels unchanged
+pub const TRANSPARENT: u32 = 0xffff_ffff;
+
+/// A rectangle of pixels.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub const fn new(x: i32, y: i32, width: i32, height: i32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    fn right(&self) -> i32 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> i32 {
+        self.y + self.height
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    /// The pixels in both rectangles.
+    pub fn intersection(&self, other: &Rect) -> Rect {
+        let x = cmp::max(self.x, other.x);
+        let y = cmp::max(self.y, other.y);
+        Rect::new(
+            x,
+            y,
+            cmp::max(cmp::min(self.right(), other.right()) - x, 0),
+            cmp::max(cmp::min(self.bottom(), other.bottom()) - y, 0),
+        )
+    }
+
+    pub fn overlaps(&self, other: &Rect) -> bool {
+        !self.intersection(other).is_empty()
+    }
+
+    /// The smallest rectangle holding both rectangles.
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let x = cmp::min(self.x, other.x);
+        let y = cmp::min(self.y, other.y);
+        Rect::new(
+            x,
+            y,
+            cmp::max(self.right(), other.right()) - x,
+            cmp::max(self.bottom(), other.bottom()) - y,
+        )
+    }
+}
+
+/// The size of a viewport once rotated.
+pub fn viewport_size(viewport: &Rect, rotation: ScreenRotation) -> (i32, i32) {
+    match rotation {
+        ScreenRotation::Normal | ScreenRotation::Rotated180 => (viewport.width, viewport.height),
+        ScreenRotation::Rotated90 | ScreenRotation::Rotated270 => (viewport.height, viewport.width),
+    }
+}
+
+/// Converts a point of a rotated viewport to the screen.
+fn point_to_screen(viewport: &Rect, rotation: ScreenRotation, x: i32, y: i32) -> (i32, i32) {
+    let (x, y) = match rotation {
+        ScreenRotation::Normal => (x, y),
+        ScreenRotation::Rotated90 => (viewport.width - 1 - y, x),
+        ScreenRotation::Rotated180 => (viewport.width - 1 - x, viewport.height - 1 - y),
+        ScreenRotation::Rotated270 => (y, viewport.height - 1 - x),
+    };
+    (viewport.x + x, viewport.y + y)
+}
+
+/// Converts a point of the screen to a rotated viewport.
+fn point_to_viewport(viewport: &Rect, rotation: ScreenRotation, x: i32, y: i32) -> (i32, i32) {
+    let (x, y) = (x - viewport.x, y - viewport.y);
+    match rotation {
+        ScreenRotation::Normal => (x, y),
+        ScreenRotation::Rotated90 => (y, viewport.width - 1 - x),
+        ScreenRotation::Rotated180 => (viewport.width - 1 - x, viewport.height - 1 - y),
+        ScreenRotation::Rotated270 => (viewport.height - 1 - y, x),
+    }
+}
+
+/// Converts a rectangle with `convert`, which maps its corners.
+fn convert_rect(rect: &Rect, convert: impl Fn(i32, i32) -> (i32, i32)) -> Rect {
+    if rect.is_empty() {
+        return Rect::new(0, 0, 0, 0);
+    }
+    let (x0, y0) = convert(rect.x, rect.y);
+    let (x1, y1) = convert(rect.right() - 1, rect.bottom() - 1);
+    Rect::new(
+        cmp::min(x0, x1),
+        cmp::min(y0, y1),
+        (x1 - x0).abs() + 1,
+        (y1 - y0).abs() + 1,
+    )
+}
+
+/// Converts a rectangle of a rotated viewport to the screen.
+pub fn rect_to_screen(viewport: &Rect, rotation: ScreenRotation, rect: &Rect) -> Rect {
+    convert_rect(rect, |x, y| point_to_screen(viewport, rotation, x, y))
+}
+
+/// Converts a rectangle of the screen to a rotated viewport.
+pub fn rect_to_viewport(viewport: &Rect, rotation: ScreenRotation, rect: &Rect) -> Rect {
+    convert_rect(rect, |x, y| point_to_viewport(viewport, rotation, x, y))
+}
+
+/// Converts a `0x00RRGGBB` color to a pixel of `format`.
+pub fn encode_color(color: u32, format: ScreenPixelFormat) -> u32 {
+    let r = (color >> 16) & 0xff;
+    let g = (color >> 8) & 0xff;
+    let b = color & 0xff;
+    match format {
+        ScreenPixelFormat::Mono => ((r * 77 + g * 150 + b * 29) >> 8 >= 0x80) as u32,
+        ScreenPixelFormat::RGB_233 => (r >> 6) << 6 | (g >> 5) << 3 | (b >> 5),
+        ScreenPixelFormat::RGB_565 => (r >> 3) << 11 | (g >> 2) << 5 | (b >> 3),
+        ScreenPixelFormat::RGB_888 => color & 0xff_ffff,
+        ScreenPixelFormat::ARGB_8888 => 0xff00_0000 | (color & 0xff_ffff),
+    }
+}
+
+/// The number of bytes holding `pixels` pixels of `format`.
+pub fn pixels_in_bytes(pixels: usize, format: ScreenPixelFormat) -> usize {
+    (pixels * format.get_bits_per_pixel() + 7) / 8
+}
+
+pub struct Canvas<'b> {
+    /// The pixels of the tile, row by row, packed
+    buffer: &'b mut [u8],
+    format: ScreenPixelFormat,
+    /// The area of the screen held by the buffer
+    tile: Rect,
+    viewport: Rect,
+    rotation: ScreenRotation,
+    /// The part of the tile in the viewport, in the coordinates of the
+    /// viewport
+    clip: Rect,
+}
+
+impl<'b> Canvas<'b> {
+    /// Creates a canvas drawing on `tile` through `viewport`. `buffer` must
+    /// hold the pixels of `tile`.
+    pub fn new(
+        buffer: &'b mut [u8],
+        format: ScreenPixelFormat,
+        tile: Rect,
+        viewport: Rect,
+        rotation: ScreenRotation,
+    ) -> Canvas<'b> {
+        let clip = rect_to_viewport(&viewport, rotation, &tile.intersection(&viewport));
+        Canvas {
+            buffer,
+            format,
+            tile,
+            viewport,
+            rotation,
+            clip,
+        }
+    }
+
+    /// The size of the viewport, in its coordinates.
+    pub fn size(&self) -> (i32, i32) {
+        viewport_size(&self.viewport, self.rotation)
+    }
+
+    /// Sets a pixel of the tile, in screen coordinates, to an encoded color.
+    fn set_pixel(&mut self, x: i32, y: i32, pixel: u32) {
+        let index = ((y - self.tile.y) * self.tile.width + (x - self.tile.x)) as usize;
+        let bits = self.format.get_bits_per_pixel();
+        if bits < 8 {
+            let bit = index * bits;
+            let shift = 8 - bits - bit % 8;
+            let mask = ((1 << bits) - 1) << shift;
+            let byte = &mut self.buffer[bit / 8];
+            *byte = (*byte & !mask) | ((pixel << shift) as u8 & mask);
+        } else {
+            let bytes = bits / 8;
+            for (i, byte) in self.buffer[index * bytes..(index + 1) * bytes]
+                .iter_mut()
+                .enumerate()
+            {
+                *byte = (pixel >> (8 * (bytes - 1 - i))) as u8;
+            }
+        }
+    }
+
+    fn fill_encoded(&mut self, rect: &Rect, pixel: u32) {
+        let rect = rect.intersection(&self.clip);
+        if rect.is_empty() {
+            return;
+        }
+        let screen = rect_to_screen(&self.viewport, self.rotation, &rect);
+        for y in screen.y..screen.bottom() {
+            for x in screen.x..screen.right() {
+                self.set_pixel(x, y, pixel);
+            }
+        }
+    }
+
+    fn plot(&mut self, x: i32, y: i32, pixel: u32) {
+        if self.clip.contains(x, y) {
+            let (x, y) = point_to_screen(&self.viewport, self.rotation, x, y);
+            self.set_pixel(x, y, pixel);
+        }
+    }
+
+    /// Fills the whole viewport.
+    pub fn clear(&mut self, color: u32) {
+        let clip = self.clip;
+        self.fill_rect(&clip, color);
+    }
+
+    pub fn pixel(&mut self, x: i32, y: i32, color: u32) {
+        let pixel = encode_color(color, self.format);
+        self.plot(x, y, pixel);
+    }
+
+    pub fn fill_rect(&mut self, rect: &Rect, color: u32) {
+        let pixel = encode_color(color, self.format);
+        self.fill_encoded(rect, pixel);
+    }
+
+    /// Draws the outline of a rectangle.
+    pub fn rect(&mut self, rect: &Rect, color: u32) {
+        if rect.is_empty() {
+            return;
+        }
+        let pixel = encode_color(color, self.format);
+        self.fill_encoded(&Rect::new(rect.x, rect.y, rect.width, 1), pixel);
+        self.fill_encoded(&Rect::new(rect.x, rect.bottom() - 1, rect.width, 1), pixel);
+        self.fill_encoded(&Rect::new(rect.x, rect.y, 1, rect.height), pixel);
+        self.fill_encoded(&Rect::new(rect.right() - 1, rect.y, 1, rect.height), pixel);
+    }
+
+    /// Draws a line from `(x0, y0)` to `(x1, y1)`, both included.
+    pub fn line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: u32) {
+        let pixel = encode_color(color, self.format);
+        // Bresenham's algorithm, for all octants
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+        loop {
+            self.plot(x, y, pixel);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let double_error = 2 * error;
+            if double_error >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if double_error <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Draws `text` with `font`, its top left corner at `(x, y)`, each pixel
+    /// of the font `scale` pixels wide.
+    #[allow(clippy::too_many_arguments)]
+    pub fn text(
+        &mut self,
+        x: i32,
+        y: i32,
+        font: &Font,
+        scale: i32,
+        foreground: u32,
+        background: u32,
+        text: &[u8],
+    ) {
+        let foreground = encode_color(foreground, self.format);
+        let background = if background == TRANSPARENT {
+            None
+        } else {
+            Some(encode_color(background, self.format))
+        };
+        let advance = font.advance() as i32 * scale;
+        let line_height = font.line_height() as i32 * scale;
+        let (mut cell_x, mut cell_y) = (x, y);
+        for &c in text {
+            if c == b'\n' {
+                cell_x = x;
+                cell_y += line_height;
+                continue;
+            }
+            let cell = Rect::new(cell_x, cell_y, advance, line_height);
+            cell_x += advance;
+            if !cell.overlaps(&self.clip) {
+                continue;
+            }
+            if let Some(background) = background {
+                self.fill_encoded(&cell, background);
+            }
+            for (column, &bits) in font.glyph(c).iter().enumerate() {
+                for row in 0..font.height {
+                    if bits & (1 << row) != 0 {
+                        self.fill_encoded(
+                            &Rect::new(
+                                cell.x + column as i32 * scale,
+                                cell.y + row as i32 * scale,
+                                scale,
+                                scale,
+                            ),
+                            foreground,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws a bitmap of one bit per pixel, with the position and the size
+    /// of `rect`, each of its pixels `scale` pixels wide.
+    pub fn bitmap(
+        &mut self,
+        rect: &Rect,
+        scale: i32,
+        foreground: u32,
+        background: u32,
+        rows: &[u8],
+    ) {
+        let foreground = encode_color(foreground, self.format);
+        let background = if background == TRANSPARENT {
+            None
+        } else {
+            Some(encode_color(background, self.format))
+        };
+        let stride = (rect.width as usize + 7) / 8;
+        for row in 0..rect.height {
+            for column in 0..rect.width {
+                let byte = rows[row as usize * stride + column as usize / 8];
+                let pixel = if byte & (0x80 >> (column % 8)) != 0 {
+                    Some(foreground)
+                } else {
+                    background
+                };
+                if let Some(pixel) = pixel {
+                    self.fill_encoded(
+                        &Rect::new(rect.x + column * scale, rect.y + row * scale, scale, scale),
+                        pixel,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Reads the arguments of display list operations.
+struct Reader<'l> {
+    list: &'l [u8],
+    position: usize,
+}
+
+impl<'l> Reader<'l> {
+    fn bytes(&mut self, len: usize) -> Option<&'l [u8]> {
+        let bytes = self.list.get(self.position..self.position + len)?;
+        self.position += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|bytes| bytes[0])
+    }
+
+    fn i16(&mut self) -> Option<i32> {
+        self.bytes(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]) as i32)
+    }
+
+    fn u16(&mut self) -> Option<i32> {
+        self.bytes(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as i32)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn scale(&mut self) -> Option<i32> {
+        self.u8().map(|scale| cmp::max(scale, 1) as i32)
+    }
+
+    fn rect(&mut self) -> Option<Rect> {
+        Some(Rect::new(
+            self.i16()?,
+            self.i16()?,
+            self.u16()?,
+            self.u16()?,
+        ))
+    }
+}
+
+/// Draws the operations of a display list on `canvas`.
+pub fn render(canvas: &mut Canvas, list: &[u8]) {
+    let mut reader = Reader { list, position: 0 };
+    while render_operation(canvas, &mut reader).is_some() {}
+}
+
+/// Draws the next operation of a display list, or returns `None` at its end.
+fn render_operation(canvas: &mut Canvas, reader: &mut Reader) -> Option<()> {
+    match reader.u8()? {
+        op::CLEAR => canvas.clear(reader.u32()?),
+        op::PIXEL => {
+            let (x, y) = (reader.i16()?, reader.i16()?);
+            canvas.pixel(x, y, reader.u32()?);
+        }
+        op::LINE => {
+            let (x0, y0) = (reader.i16()?, reader.i16()?);
+            let (x1, y1) = (reader.i16()?, reader.i16()?);
+            canvas.line(x0, y0, x1, y1, reader.u32()?);
+        }
+        op::RECT => {
+            let rect = reader.rect()?;
+            canvas.rect(&rect, reader.u32()?);
+        }
+        op::FILL_RECT => {
+            let rect = reader.rect()?;
+            canvas.fill_rect(&rect, reader.u32()?);
+        }
+        op::TEXT => {
+            let (x, y) = (reader.i16()?, reader.i16()?);
+            let (foreground, background) = (reader.u32()?, reader.u32()?);
+            let scale = reader.scale()?;
+            let len = reader.u8()? as usize;
+            let text = reader.bytes(len)?;
+            canvas.text(x, y, &FONT_5X7, scale, foreground, background, text);
+        }
+        op::BITMAP => {
+            let rect = reader.rect()?;
+            let (foreground, background) = (reader.u32()?, reader.u32()?);
+            let scale = reader.scale()?;
+            let rows = reader.bytes((rect.width as usize + 7) / 8 * rect.height as usize)?;
+            canvas.bitmap(&rect, scale, foreground, background, rows);
+        }
+        _ => return None,
+    }
+    Some(())
+}
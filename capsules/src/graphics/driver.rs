@@ -0,0 +1,499 @@
+//! Provides userspace with lines, rectangles, bitmaps and text on a screen
+//! shared by several applications.
+//!
+//! Each application claims a viewport, an area of the screen that does not
+//! overlap the viewport of any other application, and may rotate it. It
+//! describes its viewport as a display list in an allowed buffer (see
+//! `canvas` for the format), marks the areas that changed as damaged, and
+//! presents them: the capsule then renders the damaged area from the display
+//! list, a tile at a time through its transfer buffer, and sends the tiles to
+//! the screen. Drawing is clipped to the viewport, so an application cannot
+//! draw over the others. A viewport that is moved or released is not erased.
+//!
+//! This capsule and `capsules::screen` cannot share a screen, as the screen
+//! has a single client.
+//!
+//! Usage
+//! -----
+//!
+//! You need a screen that provides the `hil::screen::Screen` trait.
+//!
+//! ```rust
+//! let graphics = components::graphics::GraphicsComponent::new(board_kernel, tft)
+//!     .finalize(components::screen_buffer_size!(7680));
+//! ```
+
+use super::canvas::{self, Canvas, Rect};
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::hil::screen::{ScreenPixelFormat, ScreenRotation};
+use kernel::ReturnCode;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Graphics as usize;
+
+/// The color of a viewport before its display list is drawn
+const BACKGROUND: u32 = 0x00_0000;
+
+fn rotation_from(rotation: usize) -> Option<ScreenRotation> {
+    match rotation {
+        0 => Some(ScreenRotation::Normal),
+        1 => Some(ScreenRotation::Rotated90),
+        2 => Some(ScreenRotation::Rotated180),
+        3 => Some(ScreenRotation::Rotated270),
+        _ => None,
+    }
+}
+
+/// Unpacks a rectangle from the arguments of a command: `x << 16 | y` and
+/// `width << 16 | height`.
+fn rect_from(position: usize, size: usize) -> Rect {
+    Rect::new(
+        ((position >> 16) & 0xffff) as i32,
+        (position & 0xffff) as i32,
+        ((size >> 16) & 0xffff) as i32,
+        (size & 0xffff) as i32,
+    )
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    SettingFrame,
+    Writing,
+}
+
+pub struct App {
+    callback: Option<Callback>,
+    display_list: Option<AppSlice<Shared, u8>>,
+    /// The area of the screen of the application, if it has claimed one
+    viewport: Option<Rect>,
+    rotation: ScreenRotation,
+    /// The area changed since the viewport was last rendered, in the
+    /// coordinates of the viewport
+    damage: Rect,
+    pending_present: bool,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            callback: None,
+            display_list: None,
+            viewport: None,
+            rotation: ScreenRotation::Normal,
+            damage: Rect::new(0, 0, 0, 0),
+            pending_present: false,
+        }
+    }
+}
+
+impl App {
+    /// The whole viewport, in its coordinates.
+    fn bounds(&self) -> Option<Rect> {
+        self.viewport.map(|viewport| {
+            let (width, height) = canvas::viewport_size(&viewport, self.rotation);
+            Rect::new(0, 0, width, height)
+        })
+    }
+}
+
+pub struct Graphics<'a> {
+    screen: &'a dyn hil::screen::Screen,
+    apps: Grant<App>,
+    screen_ready: Cell<bool>,
+    state: Cell<State>,
+    /// The application whose viewport is being rendered
+    current_app: OptionalCell<AppId>,
+    /// The area of the screen being rendered
+    area: Cell<Rect>,
+    /// The part of `area` in the buffer
+    tile: Cell<Rect>,
+    pixel_format: Cell<ScreenPixelFormat>,
+    buffer: TakeCell<'static, [u8]>,
+    buffer_len: usize,
+}
+
+impl<'a> Graphics<'a> {
+    pub fn new(
+        screen: &'a dyn hil::screen::Screen,
+        buffer: &'static mut [u8],
+        grant: Grant<App>,
+    ) -> Graphics<'a> {
+        Graphics {
+            screen,
+            apps: grant,
+            screen_ready: Cell::new(false),
+            state: Cell::new(State::Idle),
+            current_app: OptionalCell::empty(),
+            area: Cell::new(Rect::new(0, 0, 0, 0)),
+            tile: Cell::new(Rect::new(0, 0, 0, 0)),
+            pixel_format: Cell::new(screen.get_pixel_format()),
+            buffer_len: buffer.len(),
+            buffer: TakeCell::new(buffer),
+        }
+    }
+
+    fn screen_bounds(&self) -> Rect {
+        let (width, height) = self.screen.get_resolution();
+        Rect::new(0, 0, width as i32, height as i32)
+    }
+
+    fn is_rendering(&self, appid: AppId) -> bool {
+        self.current_app.map_or(false, |current| *current == appid)
+    }
+
+    /// Claims `viewport` for `appid`, if it is on the screen and does not
+    /// overlap the viewport of another application.
+    fn set_viewport(&self, appid: AppId, viewport: Rect) -> ReturnCode {
+        if viewport.is_empty() || viewport.intersection(&self.screen_bounds()) != viewport {
+            return ReturnCode::EINVAL;
+        }
+        if self.is_rendering(appid) {
+            return ReturnCode::EBUSY;
+        }
+        for app in self.apps.iter() {
+            let overlaps = app.enter(|other, _| {
+                other.appid() != appid
+                    && other
+                        .viewport
+                        .map_or(false, |other| other.overlaps(&viewport))
+            });
+            if overlaps {
+                return ReturnCode::EBUSY;
+            }
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                app.viewport = Some(viewport);
+                app.damage = app.bounds().unwrap_or(app.damage);
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Rotates the viewport of `appid`, which damages all of it.
+    fn set_rotation(&self, appid: AppId, rotation: ScreenRotation) -> ReturnCode {
+        if self.is_rendering(appid) {
+            return ReturnCode::EBUSY;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                app.rotation = rotation;
+                app.damage = app.bounds().unwrap_or(app.damage);
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn release_viewport(&self, appid: AppId) -> ReturnCode {
+        if self.is_rendering(appid) {
+            return ReturnCode::EBUSY;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                app.viewport = None;
+                app.damage = Rect::new(0, 0, 0, 0);
+                app.pending_present = false;
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn present(&self, appid: AppId) -> ReturnCode {
+        let r = self
+            .apps
+            .enter(appid, |app, _| {
+                if app.viewport.is_none() {
+                    ReturnCode::ERESERVE
+                } else if app.pending_present {
+                    ReturnCode::EBUSY
+                } else {
+                    app.pending_present = true;
+                    ReturnCode::SUCCESS
+                }
+            })
+            .unwrap_or_else(|err| err.into());
+        if r == ReturnCode::SUCCESS && self.state.get() == State::Idle {
+            self.start_next_present();
+        }
+        r
+    }
+
+    /// Starts rendering the damaged area of the next application waiting for
+    /// it.
+    fn start_next_present(&self) {
+        if !self.screen_ready.get() || self.state.get() != State::Idle {
+            return;
+        }
+        let screen = self.screen_bounds();
+        for app in self.apps.iter() {
+            let area = app.enter(|app, _| {
+                if !app.pending_present {
+                    return None;
+                }
+                app.pending_present = false;
+                let area = app.viewport.map_or(Rect::new(0, 0, 0, 0), |viewport| {
+                    canvas::rect_to_screen(&viewport, app.rotation, &app.damage)
+                        .intersection(&viewport)
+                        .intersection(&screen)
+                });
+                app.damage = Rect::new(0, 0, 0, 0);
+                if area.is_empty() {
+                    // Nothing to render
+                    app.callback
+                        .map(|mut cb| cb.schedule(usize::from(ReturnCode::SUCCESS), 0, 0));
+                    None
+                } else {
+                    self.current_app.set(app.appid());
+                    Some(area)
+                }
+            });
+            if let Some(area) = area {
+                self.pixel_format.set(self.screen.get_pixel_format());
+                self.area.set(area);
+                self.set_frame(self.first_tile());
+                return;
+            }
+        }
+    }
+
+    fn first_tile(&self) -> Rect {
+        let area = self.area.get();
+        let capacity = cmp::max(
+            self.buffer_len * 8 / self.pixel_format.get().get_bits_per_pixel(),
+            1,
+        ) as i32;
+        let width = cmp::min(area.width, capacity);
+        let height = cmp::max(cmp::min(area.height, capacity / width), 1);
+        Rect::new(area.x, area.y, width, height)
+    }
+
+    /// The tile after `tile`, left to right then top to bottom, or `None`
+    /// once the area is rendered.
+    fn next_tile(&self, tile: Rect) -> Option<Rect> {
+        let area = self.area.get();
+        let first = self.first_tile();
+        let (x, y) = if tile.x + first.width < area.x + area.width {
+            (tile.x + first.width, tile.y)
+        } else {
+            (area.x, tile.y + first.height)
+        };
+        let next = Rect::new(x, y, first.width, first.height).intersection(&area);
+        if next.is_empty() {
+            None
+        } else {
+            Some(next)
+        }
+    }
+
+    fn set_frame(&self, tile: Rect) {
+        self.tile.set(tile);
+        self.state.set(State::SettingFrame);
+        let r = self.screen.set_write_frame(
+            tile.x as usize,
+            tile.y as usize,
+            tile.width as usize,
+            tile.height as usize,
+        );
+        if r != ReturnCode::SUCCESS {
+            self.finish(r);
+        }
+    }
+
+    /// Renders the current tile and sends it to the screen.
+    fn write_tile(&self) {
+        let tile = self.tile.get();
+        let format = self.pixel_format.get();
+        let len = canvas::pixels_in_bytes((tile.width * tile.height) as usize, format);
+        let r = self.buffer.take().map_or(ReturnCode::FAIL, |buffer| {
+            let rendered = self.current_app.map_or(false, |appid| {
+                self.apps
+                    .enter(*appid, |app, _| {
+                        app.viewport.map_or(false, |viewport| {
+                            let mut canvas = Canvas::new(
+                                &mut buffer[..len],
+                                format,
+                                tile,
+                                viewport,
+                                app.rotation,
+                            );
+                            canvas.clear(BACKGROUND);
+                            if let Some(ref list) = app.display_list {
+                                canvas::render(&mut canvas, list.as_ref());
+                            }
+                            true
+                        })
+                    })
+                    .unwrap_or(false)
+            });
+            if !rendered {
+                self.buffer.replace(buffer);
+                return ReturnCode::FAIL;
+            }
+            self.state.set(State::Writing);
+            self.screen.write(buffer, len)
+        });
+        if r != ReturnCode::SUCCESS {
+            self.finish(r);
+        }
+    }
+
+    /// Ends the rendering of the current application, and starts the next
+    /// one.
+    fn finish(&self, r: ReturnCode) {
+        self.state.set(State::Idle);
+        if let Some(appid) = self.current_app.take() {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback.map(|mut cb| cb.schedule(usize::from(r), 0, 0));
+            });
+        }
+        self.start_next_present();
+    }
+}
+
+impl<'a> hil::screen::ScreenClient for Graphics<'a> {
+    fn command_complete(&self, r: ReturnCode) {
+        if self.state.get() == State::SettingFrame {
+            if r == ReturnCode::SUCCESS {
+                self.write_tile();
+            } else {
+                self.finish(r);
+            }
+        }
+    }
+
+    fn write_complete(&self, buffer: &'static mut [u8], r: ReturnCode) {
+        self.buffer.replace(buffer);
+        if r != ReturnCode::SUCCESS {
+            self.finish(r);
+        } else if let Some(tile) = self.next_tile(self.tile.get()) {
+            self.set_frame(tile);
+        } else {
+            self.finish(ReturnCode::SUCCESS);
+        }
+    }
+
+    fn screen_is_ready(&self) {
+        self.screen_ready.set(true);
+        self.start_next_present();
+    }
+}
+
+impl<'a> Driver for Graphics<'a> {
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: The damaged area was presented. The first argument is the
+    ///   result.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Draw on the screen.
+    ///
+    /// Rectangles are passed as `x << 16 | y` and `width << 16 | height`.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Get the resolution of the screen, as `width << 16 | height`.
+    /// - `2`: Claim a viewport, in screen coordinates. Returns `EBUSY` if it
+    ///   overlaps the viewport of another application.
+    /// - `3`: Rotate the viewport. `data1` is the rotation: 0 for none, then
+    ///   1 to 3 for 90, 180 and 270 degrees clockwise.
+    /// - `4`: Get the size of the rotated viewport, as `width << 16 |
+    ///   height`.
+    /// - `5`: Mark an area of the viewport as damaged, in its coordinates.
+    /// - `6`: Present the damaged area: render it from the display list, and
+    ///   send it to the screen.
+    /// - `7`: Release the viewport.
+    fn command(&self, command_num: usize, data1: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => {
+                let (width, height) = self.screen.get_resolution();
+                ReturnCode::SuccessWithValue {
+                    value: width << 16 | height,
+                }
+            }
+
+            2 => self.set_viewport(appid, rect_from(data1, data2)),
+
+            3 => rotation_from(data1).map_or(ReturnCode::EINVAL, |rotation| {
+                self.set_rotation(appid, rotation)
+            }),
+
+            4 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.bounds().map_or(ReturnCode::ERESERVE, |bounds| {
+                        ReturnCode::SuccessWithValue {
+                            value: (bounds.width as usize) << 16 | bounds.height as usize,
+                        }
+                    })
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            5 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.bounds().map_or(ReturnCode::ERESERVE, |bounds| {
+                        let area = rect_from(data1, data2).intersection(&bounds);
+                        app.damage = app.damage.union(&area);
+                        ReturnCode::SUCCESS
+                    })
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            6 => self.present(appid),
+
+            7 => self.release_viewport(appid),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The display list.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.display_list = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,7 @@
+//! Graphics on a shared screen: lines, rectangles, bitmaps and text rendered
+//! in the kernel from the display lists of applications, each in its own
+//! viewport.
+
+pub mod canvas;
+pub mod driver;
+pub mod font;
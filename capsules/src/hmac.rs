@@ -28,7 +28,7 @@ use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::Hmac as usize;
 
 use core::cell::Cell;
-use core::convert::TryInto;
+use core::cmp;
 use core::marker::PhantomData;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::common::leasable_buffer::LeasableBuffer;
@@ -78,9 +78,9 @@ where
                 .enter(*appid, |app, _| {
                     match app.key.as_ref() {
                         Some(k) => {
-                            self.hmac
-                                .set_mode_hmacsha256(k.as_ref().try_into().unwrap())
-                                .unwrap();
+                            if let Err(e) = self.hmac.set_mode_hmacsha256(k.as_ref()) {
+                                return e;
+                            }
                         }
                         None => {
                             return ReturnCode::ERESERVE;
@@ -89,26 +89,21 @@ where
 
                     match app.data.as_ref() {
                         Some(d) => {
-                            self.data_buffer.map(|buf| {
-                                let data = d.as_ref();
-
-                                // Determine the size of the static buffer we have
-                                let static_buffer_len = buf.len();
+                            let data = d.as_ref();
 
-                                // If we have more data then the static buffer we set how much data we are going to copy
-                                if data.len() > static_buffer_len {
-                                    self.data_copied.set(static_buffer_len);
-                                }
-
-                                // Copy the data into the static buffer
-                                buf.copy_from_slice(&data[..static_buffer_len]);
+                            // Copy as much of the data as fits into the static buffer
+                            let copy_len = self.data_buffer.map_or(0, |buf| {
+                                let len = cmp::min(buf.len(), data.len());
+                                buf[..len].copy_from_slice(&data[..len]);
+                                len
                             });
+                            self.data_copied.set(copy_len);
 
                             // Add the data from the static buffer to the HMAC
-                            if let Err(e) = self
-                                .hmac
-                                .add_data(LeasableBuffer::new(self.data_buffer.take().unwrap()))
-                            {
+                            let mut lease_buf =
+                                LeasableBuffer::new(self.data_buffer.take().unwrap());
+                            lease_buf.slice(..copy_len);
+                            if let Err(e) = self.hmac.add_data(lease_buf) {
                                 self.data_buffer.replace(e.1);
                                 return e.0;
                             }
@@ -151,84 +146,64 @@ impl<'a, H: digest::Digest<'a, T> + digest::HMACSha256, T: DigestType> digest::C
     for HmacDriver<'a, H, T>
 {
     fn add_data_done(&'a self, _result: Result<(), ReturnCode>, data: &'static mut [u8]) {
+        self.data_buffer.replace(data);
+
         self.appid.map(move |id| {
             self.apps
                 .enter(*id, move |app, _| {
-                    let mut data_len = 0;
-                    let mut static_buffer_len = 0;
-
-                    self.data_buffer.replace(data);
-
-                    self.data_buffer.map(|buf| {
-                        match app.data.as_ref() {
-                            Some(d) => {
-                                let data = d.as_ref();
+                    let copied_data = self.data_copied.get();
 
-                                // Determine the size of the static buffer we have
-                                static_buffer_len = buf.len();
-                                // Determine how much data we have already copied
-                                let copied_data = self.data_copied.get();
-
-                                data_len = data.len();
-
-                                if data_len > copied_data {
-                                    let remaining_data = &d.as_ref()[copied_data..];
-                                    let remaining_len = data_len - copied_data;
-
-                                    if remaining_len < static_buffer_len {
-                                        buf[..remaining_len].copy_from_slice(remaining_data);
-                                    } else {
-                                        buf.copy_from_slice(&remaining_data[..static_buffer_len]);
-                                    }
-                                }
-                            }
-                            None => {
-                                // No data buffer, clear the appid and data
-                                self.hmac.clear_data();
-                                self.appid.clear();
-                                self.check_queue();
-                            }
+                    // Copy the next part of the data into the static buffer
+                    let next_len = match app.data.as_ref() {
+                        Some(d) => {
+                            let data = d.as_ref();
+                            self.data_buffer.map_or(0, |buf| {
+                                let remaining_len = data.len().saturating_sub(copied_data);
+                                let len = cmp::min(buf.len(), remaining_len);
+                                buf[..len].copy_from_slice(&data[copied_data..(copied_data + len)]);
+                                len
+                            })
                         }
-                    });
-
-                    if static_buffer_len > 0 {
-                        let copied_data = self.data_copied.get();
-
-                        if data_len > copied_data {
-                            // Update the amount of data copied
-                            self.data_copied.set(copied_data + static_buffer_len);
-
-                            let mut lease_buf =
-                                LeasableBuffer::new(self.data_buffer.take().unwrap());
+                        None => {
+                            // No data buffer, clear the appid and data
+                            self.hmac.clear_data();
+                            self.appid.clear();
+                            self.check_queue();
+                            return;
+                        }
+                    };
 
-                            // Add the data from the static buffer to the HMAC
-                            if data_len < (copied_data + static_buffer_len) {
-                                lease_buf.slice(..(data_len - copied_data))
-                            }
+                    if next_len > 0 {
+                        // Update the amount of data copied
+                        self.data_copied.set(copied_data + next_len);
 
-                            if self.hmac.add_data(lease_buf).is_err() {
-                                // Error, clear the appid and data
-                                self.hmac.clear_data();
-                                self.appid.clear();
-                                self.check_queue();
-                                return;
-                            }
+                        let mut lease_buf = LeasableBuffer::new(self.data_buffer.take().unwrap());
+                        lease_buf.slice(..next_len);
 
-                            // Return as we don't want to run the digest yet
-                            return;
+                        // Add the data from the static buffer to the HMAC
+                        if let Err(e) = self.hmac.add_data(lease_buf) {
+                            // Error, clear the appid and data
+                            self.data_buffer.replace(e.1);
+                            self.hmac.clear_data();
+                            self.appid.clear();
+                            self.check_queue();
                         }
+
+                        // Return as we don't want to run the digest yet
+                        return;
                     }
 
                     // If we get here we are ready to run the digest, reset the copied data
                     self.data_copied.set(0);
 
-                    if let Err(e) = self.hmac.run(self.dest_buffer.take().unwrap()) {
+                    if let Err((e, dest_buffer)) = self.hmac.run(self.dest_buffer.take().unwrap()) {
                         // Error, clear the appid and data
+                        self.dest_buffer.replace(dest_buffer);
                         self.hmac.clear_data();
                         self.appid.clear();
 
                         app.callback.map(|cb| {
-                            cb.schedule(usize::from(e.0), 0, 0);
+                            cb.schedule(usize::from(e), 0, 0);
                         });
 
                         self.check_queue();
@@ -256,7 +231,8 @@ impl<'a, H: digest::Digest<'a, T> + digest::HMACSha256, T: DigestType> digest::C
 
                     match app.dest.as_mut() {
                         Some(dest) => {
-                            dest.as_mut().copy_from_slice(digest.as_ref());
+                            let len = cmp::min(dest.len(), digest.as_ref().len());
+                            dest.as_mut()[..len].copy_from_slice(&digest.as_ref()[..len]);
                         }
                         None => {}
                     };
@@ -12,7 +12,7 @@
 //! procedure in hardware, as opposed to requiring a software implementation.
 
 use crate::ieee802154::framer::Frame;
-use crate::net::ieee802154::{Header, KeyId, MacAddress, PanID, SecurityLevel};
+use crate::net::ieee802154::{CommandId, Header, KeyId, MacAddress, PanID, SecurityLevel};
 use kernel::ReturnCode;
 
 pub trait MacDevice<'a> {
@@ -69,6 +69,22 @@ pub trait MacDevice<'a> {
         security_needed: Option<(SecurityLevel, KeyId)>,
     ) -> Result<Frame, &'static mut [u8]>;
 
+    /// Prepares a mutable buffer slice as an 802.15.4 MAC command frame, like
+    /// `prepare_data_frame`. The command identifier `command_id` is written
+    /// as the first byte of the payload; the command content, if any, can be
+    /// appended to the returned frame.
+    #[allow(clippy::too_many_arguments)]
+    fn prepare_command_frame(
+        &self,
+        buf: &'static mut [u8],
+        dst_pan: PanID,
+        dst_addr: MacAddress,
+        src_pan: PanID,
+        src_addr: MacAddress,
+        command_id: CommandId,
+        security_needed: Option<(SecurityLevel, KeyId)>,
+    ) -> Result<Frame, &'static mut [u8]>;
+
     /// Transmits a frame that has been prepared by the above process. If the
     /// transmission process fails, the buffer inside the frame is returned so
     /// that it can be re-used.
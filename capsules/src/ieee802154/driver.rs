@@ -3,18 +3,114 @@
 //! Implements a userspace interface for sending and receiving IEEE 802.15.4
 //! frames. Also provides a minimal list-based interface for managing keys and
 //! known link neighbors, which is needed for 802.15.4 security.
+//!
+//! Keys are looked up by their security level and key ID, for all four key ID
+//! modes. With the implicit key ID mode (0), there is at most one key per
+//! security level. Adding a key with the same level and key ID as an existing
+//! one replaces the existing key.
+//!
+//! Secured frames are only accepted from known neighbors, and each neighbor
+//! has an incoming frame counter: a frame whose counter is lower than the
+//! counter following the last frame accepted from that neighbor is dropped as
+//! a replay. The outgoing frame counter is incremented with each secured
+//! frame.
+//!
+//! Frame counters must never be reused with the same key, including across
+//! reboots. When the board gives this driver a region of nonvolatile storage
+//! with `set_frame_counter_storage`, the outgoing frame counter and the
+//! neighbors with their incoming frame counters are persisted there. Outgoing
+//! frame counters are reserved in blocks of `FRAME_COUNTER_RESERVATION`: the
+//! end of the current block is written before any counter of the block is
+//! used, and the counter resumes there after a reboot. Incoming frame counters
+//! are written every `FRAME_COUNTER_SAVE_INTERVAL` frames, so that at most
+//! that many frames of each neighbor could be replayed after a reboot. Until
+//! the persisted state is loaded, no secured frame can be sent. Without
+//! nonvolatile storage, the frame counters restart from 0 on each boot, so the
+//! keys must then be changed after a reboot.
+//!
+//! The state is kept in two copies, which are written alternately. Each copy
+//! has a sequence number and a CRC, and the valid copy with the highest
+//! sequence number is loaded at boot, so losing power while one copy is
+//! written leaves the other one intact. The copies should be in different
+//! flash pages, so that writing one never erases the other. If the storage
+//! holds no valid copy but was written before, the frame counters cannot be
+//! recovered, and no secured frame is sent until the storage is erased or the
+//! board is given a new one.
+//!
+//! A kernel capsule that manages its own keys and neighbors, such as a Thread
+//! child, can be installed with `set_kernel_key_procedure` and
+//! `set_kernel_device_procedure`. Keys and neighbors that userspace does not
+//! know are then looked up with it. The outgoing frame counter is always the
+//! one of this driver, and such a capsule can also take the frame counters of
+//! the messages it secures itself from this driver, with `next_frame_counter`.
 
 use crate::ieee802154::{device, framer};
 use crate::net::ieee802154::{AddressMode, Header, KeyId, MacAddress, PanID, SecurityLevel};
 use crate::net::stream::{decode_bytes, decode_u8, encode_bytes, encode_u8, SResult};
 use core::cell::Cell;
-use core::cmp::min;
+use core::cmp::{max, min};
 use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
 use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
 
 const MAX_NEIGHBORS: usize = 4;
 const MAX_KEYS: usize = 4;
 
+/// Number of outgoing frame counters reserved by each write of the persisted
+/// state.
+const FRAME_COUNTER_RESERVATION: u32 = 1024;
+/// Number of frames received from a neighbor after which its incoming frame
+/// counter is persisted again.
+const FRAME_COUNTER_SAVE_INTERVAL: u32 = 256;
+
+/// Magic number identifying the persisted state.
+const STATE_MAGIC: [u8; 4] = *b"154S";
+/// Size of a neighbor in the persisted state: short address, long address and
+/// incoming frame counter.
+const STATE_NEIGHBOR_SIZE: usize = 2 + 8 + 4;
+/// Offset of the neighbors in a copy of the persisted state.
+const STATE_NEIGHBORS: usize = 13;
+/// Offset of the CRC in a copy of the persisted state.
+const STATE_CRC: usize = STATE_NEIGHBORS + MAX_NEIGHBORS * STATE_NEIGHBOR_SIZE;
+/// Size of a copy of the persisted state: the magic number, the sequence
+/// number of the copy, the end of the outgoing frame counter reservation, the
+/// number of neighbors, the neighbors and a CRC-32 of all of these.
+pub const STATE_SIZE: usize = STATE_CRC + 4;
+
+/// CRC-32, as used by IEEE 802.3.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(0xffff_ffff, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xedb8_8320 & (!(crc & 1)).wrapping_add(1))
+        })
+    })
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&buf[..4]);
+    u32::from_le_bytes(bytes)
+}
+
+/// Returns the sequence number of the copy of the persisted state in `buf`,
+/// or `None` if it is not valid.
+fn state_sequence(buf: &[u8]) -> Option<u32> {
+    if buf.len() < STATE_SIZE
+        || buf[..4] != STATE_MAGIC
+        || read_u32(&buf[STATE_CRC..]) != crc32(&buf[..STATE_CRC])
+    {
+        return None;
+    }
+    Some(read_u32(&buf[4..]))
+}
+
+/// Whether the copy of the persisted state in `buf` was never written: it is
+/// still erased, or still holds the zeros of the board image.
+fn state_blank(buf: &[u8]) -> bool {
+    let buf = &buf[..min(buf.len(), STATE_SIZE)];
+    buf.iter().all(|byte| *byte == 0) || buf.iter().all(|byte| *byte == 0xff)
+}
+
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::Ieee802154 as usize;
 
@@ -22,6 +118,11 @@ pub const DRIVER_NUM: usize = driver::NUM::Ieee802154 as usize;
 struct DeviceDescriptor {
     short_addr: u16,
     long_addr: [u8; 8],
+    /// Lowest frame counter accepted in the next secured frame from this
+    /// device.
+    frame_counter: u32,
+    /// Value of `frame_counter` when the state was last persisted.
+    saved_frame_counter: u32,
 }
 
 impl Default for DeviceDescriptor {
@@ -29,10 +130,36 @@ impl Default for DeviceDescriptor {
         DeviceDescriptor {
             short_addr: 0,
             long_addr: [0; 8],
+            frame_counter: 0,
+            saved_frame_counter: 0,
         }
     }
 }
 
+impl DeviceDescriptor {
+    /// Whether both descriptors describe the same device.
+    fn same_device(&self, other: &DeviceDescriptor) -> bool {
+        self.short_addr == other.short_addr && self.long_addr == other.long_addr
+    }
+}
+
+/// State of the nonvolatile storage of frame counters.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum StorageState {
+    /// Frame counters are not persisted.
+    Disabled,
+    /// This copy of the persisted state is being read.
+    Loading(usize),
+    /// The newest valid copy is being read again to restore it.
+    Restoring,
+    /// No valid copy was found in storage that was written before. No
+    /// secured frame can be sent.
+    Lost,
+    Idle,
+    /// The state is being written.
+    Writing,
+}
+
 /// The Key ID mode mapping expected by the userland driver
 #[repr(u8)]
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
@@ -182,6 +309,11 @@ pub struct RadioDriver<'a> {
     /// Actual number of keys in the fixed size array of keys.
     num_keys: Cell<usize>,
 
+    /// Key and neighbor lookup procedures of a kernel capsule, used for the
+    /// keys and neighbors that userspace did not configure.
+    kernel_key_procedure: OptionalCell<&'a dyn framer::KeyProcedure>,
+    kernel_device_procedure: OptionalCell<&'a dyn framer::DeviceProcedure>,
+
     /// Grant of apps that use this radio driver.
     apps: Grant<App>,
     /// ID of app whose transmission request is being processed.
@@ -189,6 +321,33 @@ pub struct RadioDriver<'a> {
 
     /// Buffer that stores the IEEE 802.15.4 frame to be transmitted.
     kernel_tx: TakeCell<'static, [u8]>,
+
+    /// Frame counter of the next outgoing secured frame.
+    frame_counter: Cell<u32>,
+    /// Outgoing frame counters below this value may be used.
+    frame_counter_limit: Cell<u32>,
+    /// Value of `frame_counter_limit` once the state being written is
+    /// persisted.
+    pending_frame_counter_limit: Cell<u32>,
+
+    /// Nonvolatile storage persisting the frame counters.
+    storage: OptionalCell<&'a dyn NonvolatileStorage<'static>>,
+    /// Addresses of the two copies of the persisted state in the nonvolatile
+    /// storage.
+    storage_addresses: Cell<[usize; 2]>,
+    /// Buffer of `STATE_SIZE` bytes used to read and write the state.
+    storage_buffer: TakeCell<'static, [u8]>,
+    storage_state: Cell<StorageState>,
+    /// The copy that was last written, or loaded at boot, and its sequence
+    /// number. The next write goes to the other copy.
+    storage_copy: Cell<usize>,
+    storage_sequence: Cell<u32>,
+    /// The newest valid copy found while loading, and its sequence number.
+    storage_newest: Cell<Option<(usize, u32)>>,
+    /// Whether a copy that was read while loading had been written.
+    storage_written: Cell<bool>,
+    /// Whether the state changed while it was being written.
+    storage_write_pending: Cell<bool>,
 }
 
 impl<'a> RadioDriver<'a> {
@@ -203,12 +362,156 @@ impl<'a> RadioDriver<'a> {
             num_neighbors: Cell::new(0),
             keys: MapCell::new(Default::default()),
             num_keys: Cell::new(0),
+            kernel_key_procedure: OptionalCell::empty(),
+            kernel_device_procedure: OptionalCell::empty(),
             apps: grant,
             current_app: OptionalCell::empty(),
             kernel_tx: TakeCell::new(kernel_tx),
+            frame_counter: Cell::new(0),
+            frame_counter_limit: Cell::new(0xffffffff),
+            pending_frame_counter_limit: Cell::new(0),
+            storage: OptionalCell::empty(),
+            storage_addresses: Cell::new([0; 2]),
+            storage_buffer: TakeCell::empty(),
+            storage_state: Cell::new(StorageState::Disabled),
+            storage_copy: Cell::new(1),
+            storage_sequence: Cell::new(0),
+            storage_newest: Cell::new(None),
+            storage_written: Cell::new(false),
+            storage_write_pending: Cell::new(false),
         }
     }
 
+    /// Look up the keys that userspace did not configure with `procedure`.
+    pub fn set_kernel_key_procedure(&self, procedure: &'a dyn framer::KeyProcedure) {
+        self.kernel_key_procedure.set(procedure);
+    }
+
+    /// Look up the neighbors that userspace did not configure, and check and
+    /// update their frame counters, with `procedure`.
+    pub fn set_kernel_device_procedure(&self, procedure: &'a dyn framer::DeviceProcedure) {
+        self.kernel_device_procedure.set(procedure);
+    }
+
+    /// Persist the frame counters and the neighbors in `storage`, in two
+    /// copies of `STATE_SIZE` bytes at `addresses`, using `buffer` of at least
+    /// `STATE_SIZE` bytes. The copies should be in different flash pages. The
+    /// persisted state is loaded first, and no secured frame can be sent until
+    /// it is. This driver must be the client of `storage`.
+    pub fn set_frame_counter_storage(
+        &self,
+        storage: &'a dyn NonvolatileStorage<'static>,
+        addresses: [usize; 2],
+        buffer: &'static mut [u8],
+    ) {
+        self.storage.set(storage);
+        self.storage_addresses.set(addresses);
+        self.frame_counter_limit.set(0);
+        self.load_copy(StorageState::Loading(0), 0, buffer);
+    }
+
+    /// Read a copy of the persisted state into `buffer`.
+    fn load_copy(&self, state: StorageState, copy: usize, buffer: &'static mut [u8]) {
+        self.storage_state.set(state);
+        self.storage.map(move |storage| {
+            // If the read fails, the storage keeps the buffer: the state is
+            // never loaded and secured frames can never be sent.
+            storage.read(buffer, self.storage_addresses.get()[copy], STATE_SIZE);
+        });
+    }
+
+    /// Persist the state, now or once the ongoing write is done.
+    fn save_state(&self) {
+        match self.storage_state.get() {
+            StorageState::Idle => self.write_state(),
+            StorageState::Writing => self.storage_write_pending.set(true),
+            StorageState::Disabled
+            | StorageState::Loading(_)
+            | StorageState::Restoring
+            | StorageState::Lost => {}
+        }
+    }
+
+    /// Write the state, reserving the next block of outgoing frame counters.
+    fn write_state(&self) {
+        let buf = match self.storage_buffer.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        let limit = max(
+            self.frame_counter_limit.get(),
+            self.frame_counter
+                .get()
+                .saturating_add(FRAME_COUNTER_RESERVATION),
+        );
+        for byte in buf[..STATE_SIZE].iter_mut() {
+            *byte = 0;
+        }
+        buf[..4].copy_from_slice(&STATE_MAGIC);
+        buf[4..8].copy_from_slice(&self.storage_sequence.get().wrapping_add(1).to_le_bytes());
+        buf[8..12].copy_from_slice(&limit.to_le_bytes());
+        buf[12] = self.num_neighbors.get() as u8;
+        self.neighbors.map(|neighbors| {
+            let state = buf[STATE_NEIGHBORS..STATE_CRC].chunks_mut(STATE_NEIGHBOR_SIZE);
+            for (neighbor, entry) in neighbors.iter_mut().zip(state) {
+                entry[..2].copy_from_slice(&neighbor.short_addr.to_le_bytes());
+                entry[2..10].copy_from_slice(&neighbor.long_addr);
+                entry[10..].copy_from_slice(&neighbor.frame_counter.to_le_bytes());
+                neighbor.saved_frame_counter = neighbor.frame_counter;
+            }
+        });
+        let crc = crc32(&buf[..STATE_CRC]);
+        buf[STATE_CRC..STATE_SIZE].copy_from_slice(&crc.to_le_bytes());
+        self.pending_frame_counter_limit.set(limit);
+        self.storage.map(move |storage| {
+            let address = self.storage_addresses.get()[1 - self.storage_copy.get()];
+            // If the write fails, the storage keeps the buffer: no more
+            // frame counters are reserved, so secured frames can only be sent
+            // until the current block is exhausted.
+            if storage.write(buf, address, STATE_SIZE) == ReturnCode::SUCCESS {
+                self.storage_state.set(StorageState::Writing);
+            }
+        });
+    }
+
+    /// Restore the persisted state from the valid copy in `buf`, keeping the
+    /// neighbors that were added since boot.
+    fn load_state(&self, buf: &[u8]) {
+        self.frame_counter.set(read_u32(&buf[8..]));
+
+        let num_neighbors = min(buf[12] as usize, MAX_NEIGHBORS);
+        for entry in buf[STATE_NEIGHBORS..STATE_CRC]
+            .chunks(STATE_NEIGHBOR_SIZE)
+            .take(num_neighbors)
+        {
+            let mut neighbor = DeviceDescriptor::default();
+            neighbor.short_addr = u16::from_le_bytes([entry[0], entry[1]]);
+            neighbor.long_addr.copy_from_slice(&entry[2..10]);
+            let frame_counter = read_u32(&entry[10..]);
+            if let Some(index) = self.add_neighbor(neighbor) {
+                self.neighbors.map(|neighbors| {
+                    let neighbor = &mut neighbors[index];
+                    neighbor.frame_counter = max(neighbor.frame_counter, frame_counter);
+                });
+            }
+        }
+    }
+
+    /// Find the neighbor with the given long address and apply `closure` to
+    /// it.
+    fn with_neighbor_long<F, R>(&self, addr_long: [u8; 8], closure: F) -> Option<R>
+    where
+        F: FnOnce(&mut DeviceDescriptor) -> R,
+    {
+        let num_neighbors = self.num_neighbors.get();
+        self.neighbors.and_then(|neighbors| {
+            neighbors[..num_neighbors]
+                .iter_mut()
+                .find(|neighbor| neighbor.long_addr == addr_long)
+                .map(closure)
+        })
+    }
+
     // Neighbor management functions
 
     /// Add a new neighbor to the end of the list if there is still space
@@ -220,7 +523,7 @@ impl<'a> RadioDriver<'a> {
             let num_neighbors = self.num_neighbors.get();
             let position = neighbors[..num_neighbors]
                 .iter()
-                .position(|neighbor| *neighbor == new_neighbor);
+                .position(|neighbor| neighbor.same_device(&new_neighbor));
             match position {
                 Some(index) => Some(index),
                 None => {
@@ -229,6 +532,7 @@ impl<'a> RadioDriver<'a> {
                     } else {
                         neighbors[num_neighbors] = new_neighbor;
                         self.num_neighbors.set(num_neighbors + 1);
+                        self.save_state();
                         Some(num_neighbors)
                     }
                 }
@@ -249,6 +553,7 @@ impl<'a> RadioDriver<'a> {
                 }
             });
             self.num_neighbors.set(num_neighbors - 1);
+            self.save_state();
             ReturnCode::SUCCESS
         } else {
             ReturnCode::EINVAL
@@ -268,15 +573,20 @@ impl<'a> RadioDriver<'a> {
     // Key management functions
 
     /// Add a new key to the end of the list if there is still space
-    /// for one, returning its new index. If the key already exists,
-    /// returns the index of the existing key. Returns `None` if there
-    /// is no remaining space.
+    /// for one, returning its new index. If a key with the same security level
+    /// and key ID already exists, it is replaced and its index is returned.
+    /// Returns `None` if there is no remaining space.
     fn add_key(&self, new_key: KeyDescriptor) -> Option<usize> {
         self.keys.and_then(|keys| {
             let num_keys = self.num_keys.get();
-            let position = keys[..num_keys].iter().position(|key| *key == new_key);
+            let position = keys[..num_keys]
+                .iter()
+                .position(|key| key.level == new_key.level && key.key_id == new_key.key_id);
             match position {
-                Some(index) => Some(index),
+                Some(index) => {
+                    keys[index] = new_key;
+                    Some(index)
+                }
                 None => {
                     if num_keys == MAX_KEYS {
                         None
@@ -497,15 +807,65 @@ impl framer::DeviceProcedure for RadioDriver<'_> {
     /// Gets the long address corresponding to the neighbor that matches the given
     /// MAC address. If no such neighbor exists, returns `None`.
     fn lookup_addr_long(&self, addr: MacAddress) -> Option<[u8; 8]> {
-        self.neighbors.and_then(|neighbors| {
-            neighbors[..self.num_neighbors.get()]
-                .iter()
-                .find(|neighbor| match addr {
-                    MacAddress::Short(addr) => addr == neighbor.short_addr,
-                    MacAddress::Long(addr) => addr == neighbor.long_addr,
-                })
-                .map(|neighbor| neighbor.long_addr)
+        self.neighbors
+            .and_then(|neighbors| {
+                neighbors[..self.num_neighbors.get()]
+                    .iter()
+                    .find(|neighbor| match addr {
+                        MacAddress::Short(addr) => addr == neighbor.short_addr,
+                        MacAddress::Long(addr) => addr == neighbor.long_addr,
+                    })
+                    .map(|neighbor| neighbor.long_addr)
+            })
+            .or_else(|| {
+                self.kernel_device_procedure
+                    .and_then(|procedure| procedure.lookup_addr_long(addr))
+            })
+    }
+
+    /// Returns the next outgoing frame counter, unless the counters reserved
+    /// in the nonvolatile storage are exhausted. A new block of counters is
+    /// reserved when half of the current one is used.
+    fn next_frame_counter(&self) -> Option<u32> {
+        let frame_counter = self.frame_counter.get();
+        let limit = self.frame_counter_limit.get();
+        // 0xffffffff is never a valid frame counter.
+        if frame_counter >= limit || frame_counter == 0xffffffff {
+            return None;
+        }
+        self.frame_counter.set(frame_counter + 1);
+        if limit - frame_counter <= FRAME_COUNTER_RESERVATION / 2 {
+            self.save_state();
+        }
+        Some(frame_counter)
+    }
+
+    /// Accepts the frame counter if it is not lower than the counter following
+    /// the last frame accepted from the neighbor with this long address.
+    fn check_frame_counter(&self, addr_long: [u8; 8], frame_counter: u32) -> bool {
+        self.with_neighbor_long(addr_long, |neighbor| {
+            frame_counter >= neighbor.frame_counter
         })
+        .or_else(|| {
+            self.kernel_device_procedure
+                .map(|procedure| procedure.check_frame_counter(addr_long, frame_counter))
+        })
+        .unwrap_or(false)
+    }
+
+    fn update_frame_counter(&self, addr_long: [u8; 8], frame_counter: u32) {
+        let save = self.with_neighbor_long(addr_long, |neighbor| {
+            neighbor.frame_counter = max(neighbor.frame_counter, frame_counter.saturating_add(1));
+            neighbor.frame_counter - neighbor.saved_frame_counter >= FRAME_COUNTER_SAVE_INTERVAL
+        });
+        match save {
+            Some(true) => self.save_state(),
+            Some(false) => {}
+            None => {
+                self.kernel_device_procedure
+                    .map(|procedure| procedure.update_frame_counter(addr_long, frame_counter));
+            }
+        }
     }
 }
 
@@ -514,12 +874,17 @@ impl framer::KeyProcedure for RadioDriver<'_> {
     /// level `level` and key ID `key_id`. If no such key matches, returns
     /// `None`.
     fn lookup_key(&self, level: SecurityLevel, key_id: KeyId) -> Option<[u8; 16]> {
-        self.keys.and_then(|keys| {
-            keys[..self.num_keys.get()]
-                .iter()
-                .find(|key| key.level == level && key.key_id == key_id)
-                .map(|key| key.key)
-        })
+        self.keys
+            .and_then(|keys| {
+                keys[..self.num_keys.get()]
+                    .iter()
+                    .find(|key| key.level == level && key.key_id == key_id)
+                    .map(|key| key.key)
+            })
+            .or_else(|| {
+                self.kernel_key_procedure
+                    .and_then(|procedure| procedure.lookup_key(level, key_id))
+            })
     }
 }
 
@@ -627,6 +992,19 @@ impl Driver for RadioDriver<'_> {
     ///                      9 bytes: the key ID (might not use all bytes) +
     ///                      16 bytes: the key.
     /// - `25`: Remove the key at an index.
+    /// - `26`: Transmit a frame to the given short address.
+    ///        app_cfg (in): 1 byte: the security level +
+    ///                      1 byte: the key ID mode +
+    ///                      9 bytes: the key ID (might not use all bytes).
+    /// - `27`: Get the frame counter of the next outgoing secured frame.
+    ///        app_cfg (out): 4 bytes: the frame counter.
+    /// - `28`: Get the incoming frame counter of the neighbor at an index,
+    ///        which is the lowest frame counter accepted from it.
+    ///        app_cfg (out): 4 bytes: the frame counter.
+    /// - `29`: Raise the incoming frame counter of the neighbor at an index,
+    ///        for example to a value learned by a higher layer protocol.
+    ///        Lowering it is not allowed, as it would accept replays.
+    ///        app_cfg (in): 4 bytes: the frame counter.
     fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
         match command_num {
             0 => ReturnCode::SUCCESS,
@@ -792,6 +1170,32 @@ impl Driver for RadioDriver<'_> {
                     self.do_next_tx_sync(appid)
                 })
             }
+            27 => self.do_with_cfg_mut(appid, 4, |cfg| {
+                cfg.copy_from_slice(&self.frame_counter.get().to_ne_bytes());
+                ReturnCode::SUCCESS
+            }),
+            28 => self.do_with_cfg_mut(appid, 4, |cfg| {
+                self.get_neighbor(arg1)
+                    .map_or(ReturnCode::EINVAL, |neighbor| {
+                        cfg.copy_from_slice(&neighbor.frame_counter.to_ne_bytes());
+                        ReturnCode::SUCCESS
+                    })
+            }),
+            29 => self.do_with_cfg(appid, 4, |cfg| {
+                let mut frame_counter = [0; 4];
+                frame_counter.copy_from_slice(cfg);
+                let frame_counter = u32::from_ne_bytes(frame_counter);
+                match self.get_neighbor(arg1) {
+                    Some(neighbor) if frame_counter >= neighbor.frame_counter => {
+                        self.neighbors.map(|neighbors| {
+                            neighbors[arg1].frame_counter = frame_counter;
+                        });
+                        self.save_state();
+                        ReturnCode::SUCCESS
+                    }
+                    _ => ReturnCode::EINVAL,
+                }
+            }),
             _ => ReturnCode::ENOSUPPORT,
         }
     }
@@ -850,3 +1254,85 @@ impl device::RxClient for RadioDriver<'_> {
         });
     }
 }
+
+impl NonvolatileStorageClient<'static> for RadioDriver<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], _length: usize) {
+        match self.storage_state.get() {
+            StorageState::Loading(copy) => {
+                match state_sequence(buffer) {
+                    Some(sequence) => {
+                        self.storage_written.set(true);
+                        if self
+                            .storage_newest
+                            .get()
+                            .map_or(true, |(_, newest)| sequence > newest)
+                        {
+                            self.storage_newest.set(Some((copy, sequence)));
+                        }
+                    }
+                    None => {
+                        if !state_blank(buffer) {
+                            self.storage_written.set(true);
+                        }
+                    }
+                }
+                if copy == 0 {
+                    self.load_copy(StorageState::Loading(1), 1, buffer);
+                    return;
+                }
+                match self.storage_newest.get() {
+                    // The newest copy is the one that was just read.
+                    Some((1, sequence)) => {
+                        self.storage_copy.set(1);
+                        self.storage_sequence.set(sequence);
+                        self.load_state(buffer);
+                    }
+                    Some((_, _)) => {
+                        self.load_copy(StorageState::Restoring, 0, buffer);
+                        return;
+                    }
+                    None if self.storage_written.get() => {
+                        // The frame counters are lost, so any counter could
+                        // have been used already.
+                        self.storage_buffer.replace(buffer);
+                        self.storage_state.set(StorageState::Lost);
+                        return;
+                    }
+                    // Nothing was persisted yet.
+                    None => {}
+                }
+            }
+            StorageState::Restoring => match state_sequence(buffer) {
+                Some(sequence) => {
+                    self.storage_copy.set(0);
+                    self.storage_sequence.set(sequence);
+                    self.load_state(buffer);
+                }
+                None => {
+                    // The copy read differently the second time.
+                    self.storage_buffer.replace(buffer);
+                    self.storage_state.set(StorageState::Lost);
+                    return;
+                }
+            },
+            _ => {}
+        }
+        self.storage_buffer.replace(buffer);
+        self.storage_state.set(StorageState::Idle);
+        // Reserve the first block of frame counters of this boot.
+        self.write_state();
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.storage_buffer.replace(buffer);
+        self.storage_state.set(StorageState::Idle);
+        self.storage_copy.set(1 - self.storage_copy.get());
+        self.storage_sequence
+            .set(self.storage_sequence.get().wrapping_add(1));
+        self.frame_counter_limit
+            .set(self.pending_frame_counter_limit.get());
+        if self.storage_write_pending.take() {
+            self.write_state();
+        }
+    }
+}
@@ -1,6 +1,13 @@
 //! Implements IEEE 802.15.4 MAC device abstraction over a 802.15.4 MAC interface.
 //! Allows its users to prepare and send frames in plaintext, handling 802.15.4
-//! encoding and security procedures (in the future) transparently.
+//! encoding and security procedures transparently.
+//!
+//! Frames are secured according to IEEE 802.15.4-2015, chapter 9. The keys and
+//! the neighbors whose frames are accepted, along with their frame counters,
+//! are managed by an upper layer through the `KeyProcedure` and
+//! `DeviceProcedure` traits. Incoming secured frames are only delivered after
+//! their MIC is verified and their frame counter is checked against the last
+//! one received from the same device, so that replayed frames are dropped.
 //!
 //! However, certain IEEE 802.15.4 MAC device concepts are not implemented in
 //! this layer of abstraction and instead handled in hardware for performance
@@ -66,7 +73,6 @@
 //! ```
 
 //
-// TODO: Encryption/decryption
 // TODO: Sending beacon frames
 // TODO: Channel scanning
 //
@@ -74,7 +80,7 @@
 use crate::ieee802154::device::{MacDevice, RxClient, TxClient};
 use crate::ieee802154::mac::Mac;
 use crate::net::ieee802154::{
-    FrameType, FrameVersion, Header, KeyId, MacAddress, PanID, Security, SecurityLevel,
+    CommandId, FrameType, FrameVersion, Header, KeyId, MacAddress, PanID, Security, SecurityLevel,
 };
 use crate::net::stream::SResult;
 use crate::net::stream::{encode_bytes, encode_u32, encode_u8};
@@ -166,12 +172,14 @@ impl FrameInfo {
         // on the type of frame.
         let private_payload_offset = match self.frame_type {
             FrameType::Beacon => {
-                // Beginning of beacon payload field
+                // Beginning of beacon payload field. Secured beacons are
+                // neither sent nor accepted by the framer.
                 unimplemented!()
             }
             FrameType::MACCommand => {
-                // Beginning of MAC command content field
-                unimplemented!()
+                // Beginning of MAC command content field, after the command
+                // identifier
+                self.mac_payload_offset + 1
             }
             _ => {
                 // MAC payload field, which includes payload IEs
@@ -191,7 +199,7 @@ impl FrameInfo {
             // m data is the private payload field
             (
                 private_payload_offset,
-                self.unsecured_length() | private_payload_offset,
+                self.unsecured_length() - private_payload_offset,
             )
         }
     }
@@ -214,6 +222,18 @@ fn get_ccm_nonce(device_addr: &[u8; 8], frame_counter: u32, level: SecurityLevel
     }
 }
 
+/// Recovers the extended source address and the frame counter of a frame
+/// from its CCM* nonce.
+fn get_ccm_nonce_source(nonce: &[u8; 13]) -> ([u8; 8], u32) {
+    let mut device_addr = [0u8; 8];
+    device_addr.copy_from_slice(&nonce[..8]);
+    let frame_counter = (nonce[8] as u32) << 24
+        | (nonce[9] as u32) << 16
+        | (nonce[10] as u32) << 8
+        | nonce[11] as u32;
+    (device_addr, frame_counter)
+}
+
 /// The needed buffer size might be bigger than an MTU, because
 /// the CCM* authentication procedure
 ///
@@ -244,6 +264,22 @@ pub trait DeviceProcedure {
     /// address is already long, a long address should be returned only if the
     /// given address matches a known DeviceDescriptor.
     fn lookup_addr_long(&self, addr: MacAddress) -> Option<[u8; 8]>;
+
+    /// IEEE 802.15.4-2015, 9.2.1, step g. Return the frame counter to use for
+    /// the next outgoing secured frame (macFrameCounter) and increment it, or
+    /// `None` if no frame counter is available, in which case the frame must
+    /// not be sent.
+    fn next_frame_counter(&self) -> Option<u32>;
+
+    /// IEEE 802.15.4-2015, 9.2.3, step h. Return whether a frame with the
+    /// given frame counter from the device with the given extended address
+    /// may be accepted, i.e. whether it is not a replay of an earlier frame.
+    fn check_frame_counter(&self, addr_long: [u8; 8], frame_counter: u32) -> bool;
+
+    /// IEEE 802.15.4-2015, 9.2.3, step j. Called once a frame from the device
+    /// with the given extended address was authenticated, so that frames with
+    /// this frame counter or a lower one are rejected from now on.
+    fn update_frame_counter(&self, addr_long: [u8; 8], frame_counter: u32);
 }
 
 /// This state enum describes the state of the transmission pipeline.
@@ -357,6 +393,88 @@ impl<'a, M: Mac, A: AES128CCM<'a>> Framer<'a, M, A> {
         })
     }
 
+    /// Writes the header of a frame of the given type into `buf`, and
+    /// prepares the security parameters of the frame.
+    #[allow(clippy::too_many_arguments)]
+    fn prepare_frame(
+        &self,
+        frame_type: FrameType,
+        buf: &'static mut [u8],
+        dst_pan: PanID,
+        dst_addr: MacAddress,
+        src_pan: PanID,
+        src_addr: MacAddress,
+        security_needed: Option<(SecurityLevel, KeyId)>,
+    ) -> Result<Frame, &'static mut [u8]> {
+        // IEEE 802.15.4-2015: 9.2.1, outgoing frame security
+        // Steps a-e of the security procedure are implemented here.
+
+        // TODO: For Thread, in the case of `KeyIdMode::Source4Index`, the source
+        // address should instead be some constant defined in their
+        // specification.
+        let src_addr_long = self.get_address_long();
+        let security_desc = security_needed.and_then(|(level, key_id)| {
+            let key = self.lookup_key(level, key_id)?;
+            let frame_counter = self
+                .device_procedure
+                .and_then(|procedure| procedure.next_frame_counter())?;
+            let nonce = get_ccm_nonce(&src_addr_long, frame_counter, level);
+            Some((
+                Security {
+                    level: level,
+                    asn_in_nonce: false,
+                    frame_counter: Some(frame_counter),
+                    key_id: key_id,
+                },
+                key,
+                nonce,
+            ))
+        });
+        if security_needed.is_some() && security_desc.is_none() {
+            // If security was requested, fail when desired key was not found
+            // or the frame counters are exhausted.
+            return Err(buf);
+        }
+
+        // Construct MAC header
+        let security = security_desc.map(|(sec, _, _)| sec);
+        let mic_len = security.map_or(0, |sec| sec.level.mic_len());
+        let header = Header {
+            frame_type,
+            /* TODO: determine this by looking at queue, and also set it in
+             * hardware so that ACKs set this flag to the right value. */
+            frame_pending: false,
+            // Unicast data and command frames request acknowledgement
+            ack_requested: true,
+            version: FrameVersion::V2006,
+            seq: Some(self.data_sequence.get()),
+            dst_pan: Some(dst_pan),
+            dst_addr: Some(dst_addr),
+            src_pan: Some(src_pan),
+            src_addr: Some(src_addr),
+            security: security,
+            header_ies: Default::default(),
+            header_ies_len: 0,
+            payload_ies: Default::default(),
+            payload_ies_len: 0,
+        };
+
+        match header.encode(&mut buf[radio::PSDU_OFFSET..], true).done() {
+            Some((data_offset, mac_payload_offset)) => Ok(Frame {
+                buf: buf,
+                info: FrameInfo {
+                    frame_type,
+                    mac_payload_offset: mac_payload_offset,
+                    data_offset: data_offset,
+                    data_len: 0,
+                    mic_len: mic_len,
+                    security_params: security_desc.map(|(sec, key, nonce)| (sec.level, key, nonce)),
+                },
+            }),
+            None => Err(buf),
+        }
+    }
+
     /// IEEE 802.15.4-2015, 9.2.1, outgoing frame security procedure
     /// Performs the first checks in the security procedure. The rest of the
     /// steps are performed as part of the transmission pipeline.
@@ -404,7 +522,10 @@ impl<'a, M: Mac, A: AES128CCM<'a>> Framer<'a, M, A> {
                 if let Some(security) = header.security {
                     // IEEE 802.15.4-2015: 9.2.3, incoming frame security procedure
                     // for security-enabled headers
-                    if header.version == FrameVersion::V2003 {
+                    if header.version == FrameVersion::V2003
+                        || header.frame_type == FrameType::Beacon
+                    {
+                        // Secured beacons are not supported.
                         None
                     } else {
                         // Step e: Lookup the key.
@@ -434,7 +555,13 @@ impl<'a, M: Mac, A: AES128CCM<'a>> Framer<'a, M, A> {
                                     // Counter error
                                     return None;
                                 }
-                                // TODO: Check frame counter against source device
+                                let fresh = self.device_procedure.map_or(false, |procedure| {
+                                    procedure.check_frame_counter(device_addr, frame_counter)
+                                });
+                                if !fresh {
+                                    // Replayed frame
+                                    return None;
+                                }
                                 frame_counter
                             }
                             // TSCH mode, where ASN is used instead, not supported
@@ -585,7 +712,7 @@ impl<'a, M: Mac, A: AES128CCM<'a>> Framer<'a, M, A> {
                                     m_len,
                                     info.mic_len,
                                     level.encryption_needed(),
-                                    true,
+                                    false,
                                 );
                                 match res {
                                     ReturnCode::SUCCESS => (RxState::Decrypting(info), None),
@@ -697,72 +824,42 @@ impl<'a, M: Mac, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
         src_addr: MacAddress,
         security_needed: Option<(SecurityLevel, KeyId)>,
     ) -> Result<Frame, &'static mut [u8]> {
-        // IEEE 802.15.4-2015: 9.2.1, outgoing frame security
-        // Steps a-e of the security procedure are implemented here.
-
-        // TODO: For Thread, in the case of `KeyIdMode::Source4Index`, the source
-        // address should instead be some constant defined in their
-        // specification.
-        let src_addr_long = self.get_address_long();
-        let security_desc = security_needed.and_then(|(level, key_id)| {
-            self.lookup_key(level, key_id).map(|key| {
-                // TODO: lookup frame counter for device
-                let frame_counter = 0;
-                let nonce = get_ccm_nonce(&src_addr_long, frame_counter, level);
-                (
-                    Security {
-                        level: level,
-                        asn_in_nonce: false,
-                        frame_counter: Some(frame_counter),
-                        key_id: key_id,
-                    },
-                    key,
-                    nonce,
-                )
-            })
-        });
-        if security_needed.is_some() && security_desc.is_none() {
-            // If security was requested, fail when desired key was not found.
-            return Err(buf);
-        }
-
-        // Construct MAC header
-        let security = security_desc.map(|(sec, _, _)| sec);
-        let mic_len = security.map_or(0, |sec| sec.level.mic_len());
-        let header = Header {
-            frame_type: FrameType::Data,
-            /* TODO: determine this by looking at queue, and also set it in
-             * hardware so that ACKs set this flag to the right value. */
-            frame_pending: false,
-            // Unicast data frames request acknowledgement
-            ack_requested: true,
-            version: FrameVersion::V2006,
-            seq: Some(self.data_sequence.get()),
-            dst_pan: Some(dst_pan),
-            dst_addr: Some(dst_addr),
-            src_pan: Some(src_pan),
-            src_addr: Some(src_addr),
-            security: security,
-            header_ies: Default::default(),
-            header_ies_len: 0,
-            payload_ies: Default::default(),
-            payload_ies_len: 0,
-        };
+        self.prepare_frame(
+            FrameType::Data,
+            buf,
+            dst_pan,
+            dst_addr,
+            src_pan,
+            src_addr,
+            security_needed,
+        )
+    }
 
-        match header.encode(&mut buf[radio::PSDU_OFFSET..], true).done() {
-            Some((data_offset, mac_payload_offset)) => Ok(Frame {
-                buf: buf,
-                info: FrameInfo {
-                    frame_type: FrameType::Data,
-                    mac_payload_offset: mac_payload_offset,
-                    data_offset: data_offset,
-                    data_len: 0,
-                    mic_len: mic_len,
-                    security_params: security_desc.map(|(sec, key, nonce)| (sec.level, key, nonce)),
-                },
-            }),
-            None => Err(buf),
+    fn prepare_command_frame(
+        &self,
+        buf: &'static mut [u8],
+        dst_pan: PanID,
+        dst_addr: MacAddress,
+        src_pan: PanID,
+        src_addr: MacAddress,
+        command_id: CommandId,
+        security_needed: Option<(SecurityLevel, KeyId)>,
+    ) -> Result<Frame, &'static mut [u8]> {
+        let mut frame = self.prepare_frame(
+            FrameType::MACCommand,
+            buf,
+            dst_pan,
+            dst_addr,
+            src_pan,
+            src_addr,
+            security_needed,
+        )?;
+        // The command identifier is the first byte of the MAC payload, and
+        // is never encrypted.
+        if frame.append_payload(&[command_id as u8]) != ReturnCode::SUCCESS {
+            return Err(frame.into_buf());
         }
+        Ok(frame)
     }
 
     fn transmit(&self, frame: Frame) -> (ReturnCode, Option<&'static mut [u8]>) {
@@ -885,7 +982,14 @@ impl<'a, M: Mac, A: AES128CCM<'a>> CCMClient for Framer<'a, M, A> {
                 let buf = buf;
                 match state {
                     RxState::Decrypting(info) => {
-                        let next_state = if tag_is_valid {
+                        let next_state = if res == ReturnCode::SUCCESS && tag_is_valid {
+                            // Step j: reject this frame counter from now on.
+                            if let Some((_, _, nonce)) = info.security_params {
+                                let (device_addr, frame_counter) = get_ccm_nonce_source(&nonce);
+                                self.device_procedure.map(|procedure| {
+                                    procedure.update_frame_counter(device_addr, frame_counter)
+                                });
+                            }
                             RxState::ReadyToYield(info, buf)
                         } else {
                             RxState::ReadyToReturn(buf)
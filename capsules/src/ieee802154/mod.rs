@@ -10,3 +10,4 @@ mod driver;
 
 pub use self::driver::RadioDriver;
 pub use self::driver::DRIVER_NUM;
+pub use self::driver::STATE_SIZE;
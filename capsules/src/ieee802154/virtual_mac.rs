@@ -29,7 +29,7 @@
 //! ```
 
 use crate::ieee802154::{device, framer};
-use crate::net::ieee802154::{Header, KeyId, MacAddress, PanID, SecurityLevel};
+use crate::net::ieee802154::{CommandId, Header, KeyId, MacAddress, PanID, SecurityLevel};
 use core::cell::Cell;
 use kernel::common::cells::{MapCell, OptionalCell};
 use kernel::common::{List, ListLink, ListNode};
@@ -282,6 +282,27 @@ impl<'a> device::MacDevice<'a> for MacUser<'a> {
             .prepare_data_frame(buf, dst_pan, dst_addr, src_pan, src_addr, security_needed)
     }
 
+    fn prepare_command_frame(
+        &self,
+        buf: &'static mut [u8],
+        dst_pan: PanID,
+        dst_addr: MacAddress,
+        src_pan: PanID,
+        src_addr: MacAddress,
+        command_id: CommandId,
+        security_needed: Option<(SecurityLevel, KeyId)>,
+    ) -> Result<framer::Frame, &'static mut [u8]> {
+        self.mux.mac.prepare_command_frame(
+            buf,
+            dst_pan,
+            dst_addr,
+            src_pan,
+            src_addr,
+            command_id,
+            security_needed,
+        )
+    }
+
     fn transmit(&self, frame: framer::Frame) -> (ReturnCode, Option<&'static mut [u8]>) {
         // If the muxer is idle, immediately transmit the frame, otherwise
         // attempt to queue the transmission request. However, each MAC user can
@@ -0,0 +1,1008 @@
+//! Persistent key-value storage for applications.
+//!
+//! Applications can store small values under keys of their choosing, and read
+//! them back after the board reboots or the application is updated. Each
+//! application only sees its own keys: the store is namespaced by the package
+//! name in the application's TBF header. Applications without a package name
+//! cannot use the store, and applications that share a package name share a
+//! namespace.
+//!
+//! Storage format
+//! --------------
+//!
+//! The store is a log of records kept in a statically allocated storage volume
+//! (see `storage_volume!`). A record contains the namespace, key, and value (or
+//! a tombstone for a deleted key) and a checksum. The newest record for a key
+//! is the current value.
+//!
+//! Each page in use starts with a header holding a magic number, a sequence
+//! number that orders the pages, the end of the records in the page, and a
+//! checksum over the header and the records. The page with the highest
+//! sequence number is the head page. A page is never written twice: to add a
+//! record, the records of the head page and the new record are written to a
+//! freshly erased page with the next sequence number, which replaces the head
+//! page, and the old head page is erased afterwards. If the head page is full,
+//! the new record starts a new page instead. Free pages are taken in a circle
+//! after the head page, so all pages of the volume are written about equally
+//! often. The volume must have at least two pages, and at most 64 are used.
+//!
+//! When a new page is needed and only one free page remains, the oldest page
+//! is compacted: the records in it that are still current are copied to the
+//! head, and then the oldest page is erased. The last free page is kept in
+//! reserve so that updates and compaction always have a page to write to.
+//!
+//! Updates are safe against losing power. A page is only used if its checksum
+//! is valid, so a page that was partly written or partly erased is ignored,
+//! and records are only erased once a page holding their copies is complete.
+//! A page that replaces the head page is marked as such in its header, so if
+//! power is lost before the old head page is erased, the old page is
+//! recognised as replaced and erased before the next update.
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//! # use kernel::static_init;
+//!
+//! storage_volume!(KV_VOLUME, 8);
+//! static mut PAGEBUFFER: sam4l::flashcalw::Sam4lPage = sam4l::flashcalw::Sam4lPage::new();
+//!
+//! let kv_store = static_init!(
+//!     capsules::kv_store::KVStore<'static, sam4l::flashcalw::FLASHCALW>,
+//!     capsules::kv_store::KVStore::new(
+//!         &KV_VOLUME,
+//!         &sam4l::flashcalw::FLASH_CONTROLLER,
+//!         &mut PAGEBUFFER,
+//!         board_kernel.create_grant(&grant_cap)
+//!     )
+//! );
+//! kernel::hil::flash::HasClient::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, kv_store);
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - Allow 0: The key.
+//! - Allow 1: The value. Values are read into and written from this buffer.
+//! - Subscribe 0: Called when a set or delete finishes, with the `ReturnCode`
+//!   of the operation as the first argument.
+//! - Command 0: Check if the driver exists.
+//! - Command 1: Get the value of the key in the first `arg1` bytes of the key
+//!   buffer. Returns the length of the value, `FAIL` if the key does not
+//!   exist, or `ESIZE` if the value buffer is too small.
+//! - Command 2: Set the key in the first `arg1` bytes of the key buffer to
+//!   the first `arg2` bytes of the value buffer.
+//! - Command 3: Delete the key in the first `arg1` bytes of the key buffer.
+//!   Returns `FAIL` if the key does not exist.
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::flash::{self, Flash};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::KVStore as usize;
+
+/// Magic number at the start of a page that starts a new part of the log,
+/// "TKV1".
+const PAGE_MAGIC: u32 = 0x3156_4b54;
+/// Magic number at the start of a page that replaces the page with the
+/// previous sequence number, "TKV2".
+const REPLACE_MAGIC: u32 = 0x3256_4b54;
+/// Page header: magic number, sequence number, end of the records, and
+/// checksum.
+const PAGE_HEADER_SIZE: usize = 16;
+/// Most pages the store can use, as the pages in use are kept in a bitmask.
+const MAX_PAGES: usize = 64;
+/// Record header: marker, kind, namespace length, key length, value length,
+/// two reserved bytes, and checksum.
+const RECORD_HEADER_SIZE: usize = 12;
+/// First byte of every record.
+const RECORD_MARKER: u8 = 0xA5;
+/// Value of erased flash.
+const ERASED: u8 = 0xFF;
+
+const KIND_VALUE: u8 = 0x01;
+const KIND_TOMBSTONE: u8 = 0x02;
+
+/// Total length of a record, padded to a multiple of four bytes.
+fn record_len(namespace_len: usize, key_len: usize, value_len: usize) -> usize {
+    (RECORD_HEADER_SIZE + namespace_len + key_len + value_len + 3) & !3
+}
+
+/// FNV-1a hash, used as the page and record checksum.
+fn checksum(header: &[u8], payload: &[u8]) -> u32 {
+    header
+        .iter()
+        .chain(payload.iter())
+        .fold(0x811c_9dc5, |hash, byte| {
+            (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+        })
+}
+
+/// Encode a record into `dest`, which must be long enough and erased.
+fn encode_record(dest: &mut [u8], kind: u8, namespace: &[u8], key: &[u8], value: &[u8]) {
+    let payload_start = RECORD_HEADER_SIZE;
+    let key_start = payload_start + namespace.len();
+    let value_start = key_start + key.len();
+    let payload_end = value_start + value.len();
+
+    dest[key_start - namespace.len()..key_start].copy_from_slice(namespace);
+    dest[key_start..value_start].copy_from_slice(key);
+    dest[value_start..payload_end].copy_from_slice(value);
+
+    dest[0] = RECORD_MARKER;
+    dest[1] = kind;
+    dest[2] = namespace.len() as u8;
+    dest[3] = key.len() as u8;
+    dest[4..6].copy_from_slice(&(value.len() as u16).to_le_bytes());
+    dest[6] = 0;
+    dest[7] = 0;
+    let sum = checksum(&dest[0..8], &dest[payload_start..payload_end]);
+    dest[8..12].copy_from_slice(&sum.to_le_bytes());
+}
+
+/// The location of a valid record in the volume.
+#[derive(Clone, Copy)]
+struct Record {
+    /// Offset of the record from the start of the volume.
+    start: usize,
+    kind: u8,
+    namespace_len: usize,
+    key_len: usize,
+    value_len: usize,
+}
+
+impl Record {
+    fn len(&self) -> usize {
+        record_len(self.namespace_len, self.key_len, self.value_len)
+    }
+
+    fn namespace<'b>(&self, volume: &'b [u8]) -> &'b [u8] {
+        let start = self.start + RECORD_HEADER_SIZE;
+        &volume[start..start + self.namespace_len]
+    }
+
+    fn key<'b>(&self, volume: &'b [u8]) -> &'b [u8] {
+        let start = self.start + RECORD_HEADER_SIZE + self.namespace_len;
+        &volume[start..start + self.key_len]
+    }
+
+    fn value<'b>(&self, volume: &'b [u8]) -> &'b [u8] {
+        let start = self.start + RECORD_HEADER_SIZE + self.namespace_len + self.key_len;
+        &volume[start..start + self.value_len]
+    }
+}
+
+/// An operation requested by an application.
+#[derive(Clone, Copy)]
+enum Operation {
+    Set { key_len: usize, value_len: usize },
+    Delete { key_len: usize },
+}
+
+/// What the store is waiting on the flash for.
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    /// Erasing a page that is no longer in use.
+    EraseStale,
+    /// Erasing the page that will become the new head page.
+    EraseHead,
+    /// Writing the new head page.
+    WriteHead,
+    /// Erasing the oldest page after compacting it.
+    EraseOldest,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    key: Option<AppSlice<Shared, u8>>,
+    value: Option<AppSlice<Shared, u8>>,
+    pending: Option<Operation>,
+}
+
+pub struct KVStore<'a, F: Flash + 'static> {
+    /// Underlying storage volume.
+    volume: &'static [u8],
+    /// Flash interface.
+    driver: &'a F,
+    /// Buffer for a flash page.
+    pagebuffer: TakeCell<'static, F::Page>,
+    /// Size of a flash page.
+    page_size: usize,
+    /// Number of pages in the volume.
+    num_pages: usize,
+    apps: Grant<App>,
+
+    /// Current operation being executed, if any.
+    state: Cell<State>,
+    /// The application whose operation is being executed.
+    current_app: OptionalCell<AppId>,
+    /// The operation being executed.
+    operation: OptionalCell<Operation>,
+
+    /// Pages in use, as a bitmask of page indices.
+    used_pages: Cell<u64>,
+    /// A page that is no longer in use but has not been erased yet.
+    stale_page: Cell<Option<usize>>,
+    /// Index of the head page, if any page is in use.
+    head_page: Cell<Option<usize>>,
+    /// Sequence number of the last page written.
+    head_sequence: Cell<u32>,
+    /// End of the records in the head page.
+    head_offset: Cell<usize>,
+
+    /// Page being erased and written to become the new head page.
+    new_page: Cell<usize>,
+    /// Whether the new head page replaces the current one.
+    new_replaces_head: Cell<bool>,
+    /// End of the records in the new head page.
+    new_offset: Cell<usize>,
+    /// Whether the record for the current operation has been written.
+    committed: Cell<bool>,
+
+    /// Whether the oldest page is being compacted.
+    compacting: Cell<bool>,
+    /// Number of pages compacted for the current operation.
+    compacted_pages: Cell<usize>,
+}
+
+impl<'a, F: Flash + 'static> KVStore<'a, F> {
+    pub fn new(
+        volume: &'static [u8],
+        driver: &'a F,
+        pagebuffer: &'static mut F::Page,
+        grant: Grant<App>,
+    ) -> KVStore<'a, F> {
+        let page_size = pagebuffer.as_mut().len();
+
+        let kv_store = KVStore {
+            volume,
+            driver,
+            pagebuffer: TakeCell::new(pagebuffer),
+            page_size,
+            num_pages: core::cmp::min(volume.len() / page_size, MAX_PAGES),
+            apps: grant,
+            state: Cell::new(State::Idle),
+            current_app: OptionalCell::empty(),
+            operation: OptionalCell::empty(),
+            used_pages: Cell::new(0),
+            stale_page: Cell::new(None),
+            head_page: Cell::new(None),
+            head_sequence: Cell::new(0),
+            head_offset: Cell::new(0),
+            new_page: Cell::new(0),
+            new_replaces_head: Cell::new(false),
+            new_offset: Cell::new(0),
+            committed: Cell::new(false),
+            compacting: Cell::new(false),
+            compacted_pages: Cell::new(0),
+        };
+
+        kv_store.reconstruct();
+        kv_store
+    }
+
+    /// Returns the flash page number of the page containing the given offset
+    /// into the volume.
+    fn page_number(&self, offset: usize) -> usize {
+        (self.volume.as_ptr() as usize + offset) / self.page_size
+    }
+
+    fn page_start(&self, page: usize) -> usize {
+        page * self.page_size
+    }
+
+    fn is_used(&self, page: usize) -> bool {
+        self.used_pages.get() & (1 << page) != 0
+    }
+
+    fn set_used(&self, page: usize, used: bool) {
+        if used {
+            self.used_pages.set(self.used_pages.get() | (1 << page));
+        } else {
+            self.used_pages.set(self.used_pages.get() & !(1 << page));
+        }
+    }
+
+    /// Reads a little-endian word at `offset` into the volume.
+    fn read_u32(&self, offset: usize) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.volume[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn page_sequence(&self, page: usize) -> u32 {
+        self.read_u32(self.page_start(page) + 4)
+    }
+
+    /// Returns the end of the records in a page.
+    fn page_end(&self, page: usize) -> usize {
+        self.read_u32(self.page_start(page) + 8) as usize
+    }
+
+    /// Returns the magic number of a page, or `None` if the page does not
+    /// have a valid header and checksum.
+    fn valid_page(&self, page: usize) -> Option<u32> {
+        let start = self.page_start(page);
+        let magic = self.read_u32(start);
+        if magic != PAGE_MAGIC && magic != REPLACE_MAGIC {
+            return None;
+        }
+        let end = self.page_end(page);
+        if end < PAGE_HEADER_SIZE || end > self.page_size {
+            return None;
+        }
+        let header = &self.volume[start..start + PAGE_HEADER_SIZE];
+        let sum = checksum(
+            &header[0..12],
+            &self.volume[start + PAGE_HEADER_SIZE..start + end],
+        );
+        if sum.to_le_bytes() == header[12..16] {
+            Some(magic)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the page in use with the lowest sequence number after
+    /// `sequence`, or the oldest page in use if `sequence` is `None`.
+    fn next_used_page(&self, sequence: Option<u32>) -> Option<usize> {
+        let mut next: Option<(usize, u32)> = None;
+        for page in (0..self.num_pages).filter(|page| self.is_used(*page)) {
+            let page_sequence = self.page_sequence(page);
+            if sequence.map_or(true, |s| page_sequence > s)
+                && next.map_or(true, |(_, s)| page_sequence < s)
+            {
+                next = Some((page, page_sequence));
+            }
+        }
+        next.map(|(page, _)| page)
+    }
+
+    /// Returns the number of pages that are neither in use nor waiting to be
+    /// erased.
+    fn free_pages(&self) -> usize {
+        let stale = if self.stale_page.get().is_some() {
+            1
+        } else {
+            0
+        };
+        self.num_pages - self.used_pages.get().count_ones() as usize - stale
+    }
+
+    /// Returns the first free page after the head page.
+    fn free_page(&self) -> Option<usize> {
+        let first = self.head_page.get().map_or(0, |page| page + 1);
+        (0..self.num_pages)
+            .map(|n| (first + n) % self.num_pages)
+            .find(|page| !self.is_used(*page) && self.stale_page.get() != Some(*page))
+    }
+
+    /// Returns the record at `offset` within `page`, if there is a valid one
+    /// that ends before `end`.
+    fn record_at(&self, page: usize, offset: usize, end: usize) -> Option<Record> {
+        if offset + RECORD_HEADER_SIZE > end {
+            return None;
+        }
+        let start = page * self.page_size + offset;
+        let header = &self.volume[start..start + RECORD_HEADER_SIZE];
+        if header[0] != RECORD_MARKER || (header[1] != KIND_VALUE && header[1] != KIND_TOMBSTONE) {
+            return None;
+        }
+
+        let record = Record {
+            start,
+            kind: header[1],
+            namespace_len: header[2] as usize,
+            key_len: header[3] as usize,
+            value_len: u16::from_le_bytes([header[4], header[5]]) as usize,
+        };
+        if offset + record.len() > end {
+            return None;
+        }
+
+        let payload_start = start + RECORD_HEADER_SIZE;
+        let payload_end = payload_start + record.namespace_len + record.key_len + record.value_len;
+        let sum = checksum(&header[0..8], &self.volume[payload_start..payload_end]);
+        if sum.to_le_bytes() == header[8..12] {
+            Some(record)
+        } else {
+            None
+        }
+    }
+
+    /// Call `f` on every valid record in `page`.
+    fn for_each_record_in<G: FnMut(&Record)>(&self, page: usize, mut f: G) {
+        let end = self.page_end(page);
+        let mut offset = PAGE_HEADER_SIZE;
+        while let Some(record) = self.record_at(page, offset, end) {
+            f(&record);
+            offset += record.len();
+        }
+    }
+
+    /// Call `f` on every valid record in the store, oldest first.
+    fn for_each_record<G: FnMut(&Record)>(&self, mut f: G) {
+        let mut sequence = None;
+        while let Some(page) = self.next_used_page(sequence) {
+            sequence = Some(self.page_sequence(page));
+            self.for_each_record_in(page, &mut f);
+        }
+    }
+
+    /// Returns the newest record for `key` in `namespace`. This may be a
+    /// tombstone.
+    fn lookup(&self, namespace: &[u8], key: &[u8]) -> Option<Record> {
+        let mut newest = None;
+        self.for_each_record(|record| {
+            if record.key(self.volume) == key && record.namespace(self.volume) == namespace {
+                newest = Some(*record);
+            }
+        });
+        newest
+    }
+
+    /// Find the pages in use and the head page from the contents of flash.
+    fn reconstruct(&self) {
+        let mut head: Option<(usize, u32, u32)> = None;
+        for page in 0..self.num_pages {
+            if let Some(magic) = self.valid_page(page) {
+                self.set_used(page, true);
+                let sequence = self.page_sequence(page);
+                if head.map_or(true, |(_, s, _)| sequence > s) {
+                    head = Some((page, sequence, magic));
+                }
+            }
+        }
+
+        if let Some((head_page, head_sequence, magic)) = head {
+            self.head_page.set(Some(head_page));
+            self.head_sequence.set(head_sequence);
+            self.head_offset.set(self.page_end(head_page));
+
+            // Power may have been lost after the head page was written but
+            // before the page it replaces was erased.
+            if magic == REPLACE_MAGIC {
+                let replaced = (0..self.num_pages).find(|page| {
+                    self.is_used(*page)
+                        && self.page_sequence(*page) == head_sequence.wrapping_sub(1)
+                });
+                if let Some(page) = replaced {
+                    self.set_used(page, false);
+                    self.stale_page.set(Some(page));
+                }
+            }
+        }
+    }
+
+    /// Returns whether a record in the oldest page is still current and so
+    /// must be copied before the page can be erased. Tombstones in the oldest
+    /// page are not needed, as there are no older records for them to hide.
+    fn is_live(&self, record: &Record) -> bool {
+        record.kind == KIND_VALUE
+            && self
+                .lookup(record.namespace(self.volume), record.key(self.volume))
+                .map_or(false, |newest| newest.start == record.start)
+    }
+
+    /// Returns the total length of the records in `page` that are still
+    /// current.
+    fn live_len(&self, page: usize) -> usize {
+        let mut len = 0;
+        self.for_each_record_in(page, |record| {
+            if self.is_live(record) {
+                len += record.len();
+            }
+        });
+        len
+    }
+
+    /// Returns the length of the record for the current operation.
+    fn operation_len(&self) -> usize {
+        let namespace_len = self
+            .current_app
+            .map_or(0, |appid| appid.get_package_name().len());
+        self.operation.map_or(0, |operation| match *operation {
+            Operation::Set { key_len, value_len } => record_len(namespace_len, key_len, value_len),
+            Operation::Delete { key_len } => record_len(namespace_len, key_len, 0),
+        })
+    }
+
+    /// Start the next flash operation needed to finish the current operation.
+    fn advance(&self) -> ReturnCode {
+        // A page that is no longer in use must be erased first, so that it
+        // is never mistaken for a page in use.
+        if let Some(page) = self.stale_page.get() {
+            self.state.set(State::EraseStale);
+            return self
+                .driver
+                .erase_page(self.page_number(self.page_start(page)));
+        }
+
+        loop {
+            if self.compacting.get() {
+                let oldest = match self.next_used_page(None) {
+                    Some(page) => page,
+                    None => return ReturnCode::FAIL,
+                };
+                let len = self.live_len(oldest);
+                if len == 0 {
+                    self.state.set(State::EraseOldest);
+                    return self
+                        .driver
+                        .erase_page(self.page_number(self.page_start(oldest)));
+                }
+
+                // Copy the live records to the head page if they fit, or to a
+                // new page, which may be the last free one.
+                return self.start_head(self.head_offset.get() + len <= self.page_size);
+            }
+
+            let len = self.operation_len();
+            if self.head_page.get().is_some() && self.head_offset.get() + len <= self.page_size {
+                return self.start_head(true);
+            }
+
+            // A new page is needed. The last free page is kept for replacing
+            // the head page and for compaction.
+            if self.free_pages() >= 2 {
+                return self.start_head(false);
+            }
+
+            // Compact the oldest page to make room. Give up once every page
+            // has been compacted without making enough room.
+            if self.used_pages.get().count_ones() < 2
+                || self.compacted_pages.get() >= self.num_pages
+            {
+                return ReturnCode::ENOMEM;
+            }
+            self.compacted_pages.set(self.compacted_pages.get() + 1);
+            self.compacting.set(true);
+        }
+    }
+
+    /// Erase a free page to write the next head page to. If `replace_head`
+    /// is set, the new page holds the records of the head page followed by
+    /// the new ones, and replaces it.
+    fn start_head(&self, replace_head: bool) -> ReturnCode {
+        match self.free_page() {
+            Some(page) => {
+                self.new_page.set(page);
+                self.new_replaces_head.set(replace_head);
+                self.state.set(State::EraseHead);
+                self.driver
+                    .erase_page(self.page_number(self.page_start(page)))
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
+
+    /// Build the new head page and write it to flash. The new records are
+    /// either the live records of the oldest page during compaction, or the
+    /// record for the current operation.
+    fn write_head(&self) -> ReturnCode {
+        let page_start = self.page_start(self.new_page.get());
+        let replaced = if self.new_replaces_head.get() {
+            self.head_page.get()
+        } else {
+            None
+        };
+
+        self.pagebuffer
+            .take()
+            .map_or(ReturnCode::ERESERVE, move |pagebuffer| {
+                let buffer = pagebuffer.as_mut();
+                for byte in buffer.iter_mut() {
+                    *byte = ERASED;
+                }
+
+                let mut offset = PAGE_HEADER_SIZE;
+                if let Some(head) = replaced {
+                    let head_start = self.page_start(head);
+                    offset = self.head_offset.get();
+                    buffer[PAGE_HEADER_SIZE..offset].copy_from_slice(
+                        &self.volume[head_start + PAGE_HEADER_SIZE..head_start + offset],
+                    );
+                }
+
+                let result = if self.compacting.get() {
+                    if let Some(oldest) = self.next_used_page(None) {
+                        self.for_each_record_in(oldest, |record| {
+                            if self.is_live(record) {
+                                let len = record.len();
+                                buffer[offset..offset + len].copy_from_slice(
+                                    &self.volume[record.start..record.start + len],
+                                );
+                                offset += len;
+                            }
+                        });
+                    }
+                    ReturnCode::SUCCESS
+                } else {
+                    let result = self.encode_operation(&mut buffer[offset..]);
+                    offset += self.operation_len();
+                    result
+                };
+                if result != ReturnCode::SUCCESS {
+                    self.pagebuffer.replace(pagebuffer);
+                    return result;
+                }
+
+                let magic = if replaced.is_some() {
+                    REPLACE_MAGIC
+                } else {
+                    PAGE_MAGIC
+                };
+                let sequence = self.head_sequence.get().wrapping_add(1);
+                buffer[0..4].copy_from_slice(&magic.to_le_bytes());
+                buffer[4..8].copy_from_slice(&sequence.to_le_bytes());
+                buffer[8..12].copy_from_slice(&(offset as u32).to_le_bytes());
+                let sum = checksum(&buffer[0..12], &buffer[PAGE_HEADER_SIZE..offset]);
+                buffer[12..16].copy_from_slice(&sum.to_le_bytes());
+                self.new_offset.set(offset);
+
+                self.state.set(State::WriteHead);
+                match self
+                    .driver
+                    .write_page(self.page_number(page_start), pagebuffer)
+                {
+                    Ok(()) => ReturnCode::SUCCESS,
+                    Err((return_code, pagebuffer)) => {
+                        self.pagebuffer.replace(pagebuffer);
+                        return_code
+                    }
+                }
+            })
+    }
+
+    /// Build the record for the current operation from the application's
+    /// buffers.
+    fn encode_operation(&self, dest: &mut [u8]) -> ReturnCode {
+        self.current_app.map_or(ReturnCode::FAIL, |appid| {
+            let namespace = appid.get_package_name().as_bytes();
+            self.apps
+                .enter(*appid, |app, _| {
+                    let (key_len, value_len, kind) =
+                        match self.operation.map(|operation| *operation) {
+                            Some(Operation::Set { key_len, value_len }) => {
+                                (key_len, value_len, KIND_VALUE)
+                            }
+                            Some(Operation::Delete { key_len }) => (key_len, 0, KIND_TOMBSTONE),
+                            None => return ReturnCode::FAIL,
+                        };
+                    let key = match app.key {
+                        Some(ref key) if key.len() >= key_len => &key.as_ref()[..key_len],
+                        _ => return ReturnCode::ERESERVE,
+                    };
+                    let value: &[u8] = match app.value {
+                        Some(ref value) if value.len() >= value_len => &value.as_ref()[..value_len],
+                        _ if value_len == 0 => &[],
+                        _ => return ReturnCode::ERESERVE,
+                    };
+                    encode_record(dest, kind, namespace, key, value);
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into())
+        })
+    }
+
+    /// Check that an operation is valid for an application and start it, or
+    /// queue it if the store is busy.
+    fn enqueue(&self, appid: AppId, operation: Operation) -> ReturnCode {
+        let namespace = appid.get_package_name().as_bytes();
+        if namespace.is_empty() {
+            return ReturnCode::ENOSUPPORT;
+        }
+
+        let (key_len, value_len) = match operation {
+            Operation::Set { key_len, value_len } => (key_len, value_len),
+            Operation::Delete { key_len } => (key_len, 0),
+        };
+        if namespace.len() > u8::MAX as usize
+            || key_len == 0
+            || key_len > u8::MAX as usize
+            || value_len > u16::MAX as usize
+            || record_len(namespace.len(), key_len, value_len) > self.page_size - PAGE_HEADER_SIZE
+        {
+            return ReturnCode::ESIZE;
+        }
+
+        let result = self
+            .apps
+            .enter(appid, |app, _| {
+                let key = match app.key {
+                    Some(ref key) if key.len() >= key_len => &key.as_ref()[..key_len],
+                    _ => return ReturnCode::EINVAL,
+                };
+                match operation {
+                    Operation::Set { .. } => {
+                        if value_len > 0 && app.value.as_ref().map_or(0, |v| v.len()) < value_len {
+                            return ReturnCode::EINVAL;
+                        }
+                    }
+                    Operation::Delete { .. } => {
+                        let exists = self
+                            .lookup(namespace, key)
+                            .map_or(false, |record| record.kind == KIND_VALUE);
+                        if !exists {
+                            return ReturnCode::FAIL;
+                        }
+                    }
+                }
+
+                // If the store is busy, queue the operation to be started
+                // when the current one finishes.
+                if self.current_app.is_some() {
+                    if app.pending.is_some() {
+                        return ReturnCode::EBUSY;
+                    }
+                    app.pending = Some(operation);
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into());
+
+        if result == ReturnCode::SUCCESS && self.current_app.is_none() {
+            self.start(appid, operation)
+        } else {
+            result
+        }
+    }
+
+    /// Start executing an operation for an application.
+    fn start(&self, appid: AppId, operation: Operation) -> ReturnCode {
+        self.current_app.set(appid);
+        self.operation.set(operation);
+        self.compacted_pages.set(0);
+        let result = self.advance();
+        if result != ReturnCode::SUCCESS {
+            self.reset();
+        }
+        result
+    }
+
+    fn reset(&self) {
+        self.state.set(State::Idle);
+        self.current_app.clear();
+        self.operation.clear();
+        self.compacting.set(false);
+        self.committed.set(false);
+    }
+
+    /// Continue the current operation after a flash operation finished. Once
+    /// the record is written and the page it replaced is erased, the
+    /// operation is done.
+    fn resume(&self) {
+        if self.committed.get() && self.stale_page.get().is_none() {
+            self.finish(ReturnCode::SUCCESS);
+            return;
+        }
+        let result = self.advance();
+        if result != ReturnCode::SUCCESS {
+            self.finish(result);
+        }
+    }
+
+    /// Finish the current operation, notify the application, and start the
+    /// next queued operation, if any.
+    fn finish(&self, result: ReturnCode) {
+        self.current_app.map(|appid| {
+            let _ = self.apps.enter(*appid, |app, _| {
+                app.callback
+                    .map(|mut cb| cb.schedule(usize::from(result), 0, 0));
+            });
+        });
+        self.reset();
+
+        for cntr in self.apps.iter() {
+            let next =
+                cntr.enter(|app, _| app.pending.take().map(|operation| (app.appid(), operation)));
+            if let Some((appid, operation)) = next {
+                let result = self.start(appid, operation);
+                if result == ReturnCode::SUCCESS {
+                    break;
+                }
+                let _ = self.apps.enter(appid, |app, _| {
+                    app.callback
+                        .map(|mut cb| cb.schedule(usize::from(result), 0, 0))
+                });
+            }
+        }
+    }
+
+    /// Copy the current value of a key into the application's value buffer.
+    fn get(&self, appid: AppId, key_len: usize) -> ReturnCode {
+        let namespace = appid.get_package_name().as_bytes();
+        if namespace.is_empty() {
+            return ReturnCode::ENOSUPPORT;
+        }
+
+        self.apps
+            .enter(appid, |app, _| {
+                let key = match app.key {
+                    Some(ref key) if key_len > 0 && key.len() >= key_len => {
+                        &key.as_ref()[..key_len]
+                    }
+                    _ => return ReturnCode::EINVAL,
+                };
+                let record = match self.lookup(namespace, key) {
+                    Some(record) if record.kind == KIND_VALUE => record,
+                    _ => return ReturnCode::FAIL,
+                };
+
+                let value = record.value(self.volume);
+                match app.value {
+                    Some(ref mut buffer) if buffer.len() >= value.len() => {
+                        buffer.as_mut()[..value.len()].copy_from_slice(value);
+                        ReturnCode::SuccessWithValue { value: value.len() }
+                    }
+                    _ => ReturnCode::ESIZE,
+                }
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+}
+
+impl<'a, F: Flash + 'static> flash::Client<F> for KVStore<'a, F> {
+    fn read_complete(&self, _read_buffer: &'static mut F::Page, _error: flash::Error) {}
+
+    fn write_complete(&self, pagebuffer: &'static mut F::Page, error: flash::Error) {
+        self.pagebuffer.replace(pagebuffer);
+
+        let page = self.new_page.get();
+        if error != flash::Error::CommandComplete {
+            // The head page is still intact. The new page may have been
+            // written anyway, so erase it before its sequence number is used
+            // again.
+            self.stale_page.set(Some(page));
+            self.finish(ReturnCode::FAIL);
+            return;
+        }
+
+        self.head_sequence
+            .set(self.head_sequence.get().wrapping_add(1));
+
+        if self.new_replaces_head.get() {
+            if let Some(head) = self.head_page.get() {
+                self.set_used(head, false);
+                self.stale_page.set(Some(head));
+            }
+        }
+        self.set_used(page, true);
+        self.head_page.set(Some(page));
+        self.head_offset.set(self.new_offset.get());
+        if !self.compacting.get() {
+            self.committed.set(true);
+        }
+
+        self.resume();
+    }
+
+    fn erase_complete(&self, error: flash::Error) {
+        if error != flash::Error::CommandComplete {
+            // The live records of the oldest page have already been copied,
+            // but the page may now be partly erased.
+            if self.state.get() == State::EraseOldest {
+                if let Some(oldest) = self.next_used_page(None) {
+                    self.set_used(oldest, false);
+                    self.stale_page.set(Some(oldest));
+                }
+            }
+            // A committed record is kept even if the page it replaced could
+            // not be erased; that page is erased before the next update.
+            if self.committed.get() {
+                self.finish(ReturnCode::SUCCESS);
+            } else {
+                self.finish(ReturnCode::FAIL);
+            }
+            return;
+        }
+
+        match self.state.get() {
+            State::EraseStale => {
+                self.stale_page.set(None);
+            }
+            State::EraseHead => {
+                let result = self.write_head();
+                if result != ReturnCode::SUCCESS {
+                    self.finish(result);
+                }
+                return;
+            }
+            State::EraseOldest => {
+                if let Some(oldest) = self.next_used_page(None) {
+                    self.set_used(oldest, false);
+                }
+                self.compacting.set(false);
+            }
+            _ => {}
+        }
+
+        self.resume();
+    }
+}
+
+impl<'a, F: Flash + 'static> Driver for KVStore<'a, F> {
+    /// Setup key and value buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Set the key buffer.
+    /// - `1`: Set the value buffer.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if allow_num == 0 {
+                        app.key = slice;
+                    } else {
+                        app.value = slice;
+                    }
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Set a callback for when a set or delete finishes.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Key-value store control.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Get the value of the key of length `arg1`.
+    /// - `2`: Set the key of length `arg1` to the value of length `arg2`.
+    /// - `3`: Delete the key of length `arg1`.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => self.get(appid, arg1),
+
+            2 => self.enqueue(
+                appid,
+                Operation::Set {
+                    key_len: arg1,
+                    value_len: arg2,
+                },
+            ),
+
+            3 => self.enqueue(appid, Operation::Delete { key_len: arg1 }),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -13,22 +13,31 @@ pub mod ambient_light;
 pub mod analog_comparator;
 pub mod analog_sensor;
 pub mod apds9960;
+pub mod app_checker_sha256;
 pub mod app_flash_driver;
+pub mod app_loader;
+pub mod app_watchdog;
+pub mod ble;
 pub mod ble_advertising_driver;
 pub mod bus;
 pub mod button;
 pub mod buzzer_driver;
 pub mod console;
+pub mod counter_date_time;
+pub mod crash_log;
 pub mod crc;
 pub mod ctap;
 pub mod dac;
+pub mod date_time;
 pub mod debug_process_restart;
 pub mod driver;
+pub mod fat;
 pub mod fm25cl;
 pub mod ft6x06;
 pub mod fxos8700cq;
 pub mod gpio;
 pub mod gpio_async;
+pub mod graphics;
 pub mod hd44780;
 pub mod hmac;
 pub mod humidity;
@@ -36,6 +45,7 @@ pub mod i2c_master;
 pub mod i2c_master_slave_driver;
 pub mod ieee802154;
 pub mod isl29035;
+pub mod kv_store;
 pub mod l3gd20;
 pub mod led;
 pub mod led_matrix;
@@ -48,10 +58,12 @@ pub mod lsm303xx;
 pub mod ltc294x;
 pub mod max17205;
 pub mod mcp230xx;
+pub mod message_ipc;
 pub mod mlx90614;
 pub mod mx25r6435f;
 pub mod ninedof;
 pub mod nonvolatile_storage_driver;
+pub mod nonvolatile_to_blocks;
 pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
 pub mod panic_button;
@@ -64,6 +76,8 @@ pub mod rng;
 pub mod screen;
 pub mod sdcard;
 pub mod segger_rtt;
+pub mod sha;
+pub mod sha_software;
 pub mod sht3x;
 pub mod si7021;
 pub mod spi_controller;
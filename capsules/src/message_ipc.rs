@@ -0,0 +1,341 @@
+//! Message-passing inter-process communication.
+//!
+//! This driver complements the shared-memory IPC mechanism in `kernel::ipc`.
+//! Instead of sharing buffers, processes exchange bounded messages, which the
+//! kernel copies from the buffer of the sender into the buffer of the
+//! receiver:
+//!
+//! - A service registers itself under a name of its choosing.
+//! - Clients look up the service by that name, and get a handle for it.
+//! - Clients send requests to the service handle, and the service replies to
+//!   the handle of the client, which is reported with every message. Handles
+//!   are the identifiers of the processes, which cannot be forged, so a
+//!   service can use them to tell its callers apart.
+//!
+//! Messages are queued in the grant region of the receiving process until it
+//! asks for the next one, so a process only gets a new message once it has
+//! dealt with the previous one. When the queue of a process is full, senders
+//! get `ENOMEM` and have to try again later.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let message_ipc = static_init!(
+//!     capsules::message_ipc::MessageIpc,
+//!     capsules::message_ipc::MessageIpc::new(board_kernel.create_grant(&grant_cap))
+//! );
+//! ```
+
+use core::cmp;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::MessageIpc as usize;
+
+/// Largest payload of a message in bytes.
+pub const MAX_MESSAGE_LEN: usize = 64;
+
+/// Number of messages that can be queued for each process.
+pub const INBOX_LEN: usize = 4;
+
+/// Longest name a service can register.
+pub const MAX_NAME_LEN: usize = 32;
+
+/// Whether a message was sent to a service, or by a service in reply.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MessageKind {
+    Request = 0,
+    Reply = 1,
+}
+
+#[derive(Copy, Clone)]
+struct Message {
+    kind: MessageKind,
+    sender: AppId,
+    len: usize,
+    data: [u8; MAX_MESSAGE_LEN],
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    rx_buffer: Option<AppSlice<Shared, u8>>,
+    tx_buffer: Option<AppSlice<Shared, u8>>,
+    name_buffer: Option<AppSlice<Shared, u8>>,
+    /// The name of the service this process registered, if any.
+    service_name: [u8; MAX_NAME_LEN],
+    service_name_len: usize,
+    /// Whether the process asked for the next message.
+    receiving: bool,
+    /// Messages waiting to be received, oldest first.
+    inbox: [Option<Message>; INBOX_LEN],
+}
+
+impl App {
+    fn is_service(&self) -> bool {
+        self.service_name_len > 0
+    }
+
+    fn service_name(&self) -> &[u8] {
+        &self.service_name[..self.service_name_len]
+    }
+
+    /// Copy the oldest queued message into the receive buffer and notify
+    /// the process, if it is waiting for a message.
+    fn deliver(&mut self) {
+        if !self.receiving {
+            return;
+        }
+        let message = match self.inbox[0].take() {
+            Some(message) => message,
+            None => return,
+        };
+        self.inbox.rotate_left(1);
+        self.receiving = false;
+
+        if let Some(ref mut rx_buffer) = self.rx_buffer {
+            let len = cmp::min(rx_buffer.len(), message.len);
+            rx_buffer.as_mut()[..len].copy_from_slice(&message.data[..len]);
+        }
+        if let Some(mut cb) = self.callback {
+            cb.schedule(message.kind as usize, message.sender.id(), message.len);
+        }
+    }
+}
+
+pub struct MessageIpc {
+    apps: Grant<App>,
+}
+
+impl MessageIpc {
+    pub fn new(grant: Grant<App>) -> MessageIpc {
+        MessageIpc { apps: grant }
+    }
+
+    /// Returns the process that a handle passed in by userspace refers to.
+    fn lookup_handle(&self, handle: usize) -> Option<AppId> {
+        self.apps
+            .iter()
+            .map(|app| app.enter(|app, _| app.appid()))
+            .find(|appid| appid.id() == handle)
+    }
+
+    /// Returns the handle of the process that registered the service named
+    /// `name`, other than `except`.
+    fn lookup_service(&self, name: &[u8], except: Option<AppId>) -> Option<usize> {
+        self.apps
+            .iter()
+            .filter_map(|app| {
+                app.enter(|app, _| {
+                    let appid = app.appid();
+                    if Some(appid) != except && app.is_service() && app.service_name() == name {
+                        Some(appid.id())
+                    } else {
+                        None
+                    }
+                })
+            })
+            .next()
+    }
+
+    /// Copies the name buffer of `appid` into `name`, returning its length.
+    fn read_name(&self, appid: AppId, name: &mut [u8; MAX_NAME_LEN]) -> Result<usize, ReturnCode> {
+        self.apps
+            .enter(appid, |app, _| match app.name_buffer {
+                Some(ref buffer) => {
+                    if buffer.len() == 0 || buffer.len() > MAX_NAME_LEN {
+                        Err(ReturnCode::EINVAL)
+                    } else {
+                        name[..buffer.len()].copy_from_slice(buffer.as_ref());
+                        Ok(buffer.len())
+                    }
+                }
+                None => Err(ReturnCode::ERESERVE),
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    fn register(&self, appid: AppId) -> ReturnCode {
+        let mut name = [0; MAX_NAME_LEN];
+        let len = match self.read_name(appid, &mut name) {
+            Ok(len) => len,
+            Err(e) => return e,
+        };
+        if self.lookup_service(&name[..len], Some(appid)).is_some() {
+            // Another process already provides this service
+            return ReturnCode::EBUSY;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                app.service_name = name;
+                app.service_name_len = len;
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn discover(&self, appid: AppId) -> ReturnCode {
+        let mut name = [0; MAX_NAME_LEN];
+        let len = match self.read_name(appid, &mut name) {
+            Ok(len) => len,
+            Err(e) => return e,
+        };
+        self.lookup_service(&name[..len], None)
+            .map_or(ReturnCode::ENODEVICE, |handle| {
+                ReturnCode::SuccessWithValue { value: handle }
+            })
+    }
+
+    /// Copies `len` bytes of the transmit buffer of `appid` into a message
+    /// and queues it for the process with the handle `target`.
+    fn send(&self, appid: AppId, kind: MessageKind, target: usize, len: usize) -> ReturnCode {
+        if len > MAX_MESSAGE_LEN {
+            return ReturnCode::ESIZE;
+        }
+        let target = match self.lookup_handle(target) {
+            Some(target) => target,
+            None => return ReturnCode::EINVAL,
+        };
+
+        let mut message = Message {
+            kind,
+            sender: appid,
+            len,
+            data: [0; MAX_MESSAGE_LEN],
+        };
+        let result = self
+            .apps
+            .enter(appid, |app, _| {
+                if kind == MessageKind::Reply && !app.is_service() {
+                    return ReturnCode::EINVAL;
+                }
+                match app.tx_buffer {
+                    Some(ref buffer) if buffer.len() >= len => {
+                        message.data[..len].copy_from_slice(&buffer.as_ref()[..len]);
+                        ReturnCode::SUCCESS
+                    }
+                    Some(_) => ReturnCode::ESIZE,
+                    None => ReturnCode::ERESERVE,
+                }
+            })
+            .unwrap_or_else(|err| err.into());
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+
+        // The sender's grant is no longer entered here, so this also works
+        // for a service that sends a message to itself.
+        self.apps
+            .enter(target, |app, _| {
+                if kind == MessageKind::Request && !app.is_service() {
+                    return ReturnCode::EINVAL;
+                }
+                match app.inbox.iter_mut().find(|slot| slot.is_none()) {
+                    Some(slot) => {
+                        *slot = Some(message);
+                        app.deliver();
+                        ReturnCode::SUCCESS
+                    }
+                    None => ReturnCode::ENOMEM,
+                }
+            })
+            .unwrap_or(ReturnCode::EINVAL)
+    }
+}
+
+impl Driver for MessageIpc {
+    /// Setup buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Receive buffer. Received messages are copied here.
+    /// - `1`: Transmit buffer. Requests and replies are copied from here.
+    /// - `2`: Name buffer. Holds the service name to register or look up.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| match allow_num {
+                0 => {
+                    app.rx_buffer = slice;
+                    ReturnCode::SUCCESS
+                }
+                1 => {
+                    app.tx_buffer = slice;
+                    ReturnCode::SUCCESS
+                }
+                2 => {
+                    app.name_buffer = slice;
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::ENOSUPPORT,
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A message was received. The callback signature is
+    ///        `fn(kind: usize, sender: usize, len: usize)`, where `kind` is
+    ///        `0` for requests and `1` for replies, `sender` is the handle of
+    ///        the sending process and `len` is the length of the message.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Register services and exchange messages.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Register as the service named in the name buffer.
+    /// - `2`: Look up the service named in the name buffer, returning its
+    ///        handle.
+    /// - `3`: Send the first `data2` bytes of the transmit buffer as a
+    ///        request to the service with handle `data1`.
+    /// - `4`: Send the first `data2` bytes of the transmit buffer as a reply
+    ///        to the process with handle `data1`. Only services can reply.
+    /// - `5`: Receive the next message, as soon as there is one.
+    fn command(&self, command_num: usize, data1: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.register(appid),
+            2 => self.discover(appid),
+            3 => self.send(appid, MessageKind::Request, data1, data2),
+            4 => self.send(appid, MessageKind::Reply, data1, data2),
+            5 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if app.receiving {
+                        return ReturnCode::EALREADY;
+                    }
+                    app.receiving = true;
+                    app.deliver();
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
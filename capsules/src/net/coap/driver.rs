@@ -0,0 +1,1578 @@
+//! CoAP userspace interface.
+//!
+//! Implements a userspace interface for the Constrained Application Protocol
+//! (RFC 7252) over the UDP stack. A process can act as a server, by
+//! registering resources that remote clients send requests to, and as a
+//! client, by sending requests to remote servers. The driver is bound to the
+//! CoAP port (5683), and handles the message layer of the protocol for the
+//! processes:
+//!
+//! - Confirmable messages are retransmitted with exponential backoff until
+//!   they are acknowledged, as described in Section 4.2 of RFC 7252. A
+//!   single virtual alarm drives the retransmissions of all processes.
+//! - Payloads that do not fit in a message are sent and received in blocks
+//!   (RFC 7959). A process always sees whole payloads: the driver collects
+//!   the blocks of requests and responses in the read buffers of the
+//!   process, and sends the blocks of requests and responses from its write
+//!   buffers. Requests for the later blocks of a response are passed to the
+//!   process like any other request, and it answers them with the whole
+//!   representation again.
+//! - Clients can observe resources (RFC 7641). Servers keep a list of the
+//!   observers of their resources, and notify all of them with a single
+//!   command.
+//!
+//! Messages are not buffered in the kernel: they are rebuilt from the
+//! buffers of the process each time they are sent, so the buffers must not
+//! be changed while a message is being sent. Responses are piggybacked on the
+//! acknowledgement of the request, so a server must respond quickly enough
+//! that the client does not give up on the request. Retransmissions of a
+//! request that is being handled are ignored, and the response to the last
+//! confirmable request is sent again if that request is retransmitted.
+//!
+//! Endpoints in the config buffer are encoded as a 16 byte IPv6 address
+//! followed by a port in host byte order, like those of the UDP driver.
+//! Paths are the segments of the Uri-Path separated by '/', without a
+//! leading '/'.
+
+use crate::net::coap::message::{code, encode_message, BlockOption, CoAPHeader, CoAPOptions};
+use crate::net::coap::message::{MessageType, Token, MAX_PATH_LEN};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use crate::net::util::host_slice_to_u16;
+use core::cell::Cell;
+use core::{cmp, mem};
+use kernel::common::cells::{MapCell, OptionalCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::time::{self, Frequency, Ticks};
+use kernel::{debug, AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Coap as usize;
+
+/// Length of an endpoint in the config buffer.
+const ENDPOINT_LEN: usize = mem::size_of::<IPAddr>() + mem::size_of::<u16>();
+
+/// Number of resources each process can register.
+pub const MAX_RESOURCES: usize = 4;
+/// Number of observers of the resources of each process.
+pub const MAX_OBSERVERS: usize = 4;
+
+/// Size exponent of the blocks the driver sends: 64 byte blocks, which fit
+/// in a UDP datagram with the header and options of the message.
+pub const BLOCK_SZX: u8 = 2;
+
+/// Flags of the resources registered with command `1`.
+pub const RESOURCE_OBSERVABLE: usize = 0x1;
+pub const RESOURCE_CONFIRMABLE_NOTIFY: usize = 0x2;
+
+/// Flags of the requests sent with command `5`, above the method.
+pub const REQUEST_CONFIRMABLE: usize = 0x100;
+pub const REQUEST_OBSERVE: usize = 0x200;
+
+// Transmission parameters (RFC 7252, Section 4.8)
+const ACK_TIMEOUT_MS: u32 = 2000;
+const MAX_RETRANSMIT: u8 = 4;
+/// How long a response is waited for once the request was acknowledged, or
+/// sent non-confirmable. Blockwise transfers of requests are given up after
+/// the same time without a block.
+const MAX_TRANSMIT_WAIT_MS: u64 = 93_000;
+
+/// Longest time the alarm is set for while exchanges are in progress, so
+/// that the uptime keeps up with the alarm ticks.
+const MAX_SLEEP_MS: u64 = 60_000;
+
+/// Values of the Observe option are 24 bits long.
+const OBSERVE_MODULUS: u32 = 1 << 24;
+/// A notification that arrives this long after the last one is newer,
+/// whatever its Observe value (RFC 7641, Section 3.4).
+const OBSERVE_FRESHNESS_MS: u64 = 128_000;
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct Endpoint {
+    addr: IPAddr,
+    port: u16,
+}
+
+fn parse_endpoint(buf: &[u8]) -> Endpoint {
+    let (a, p) = buf[..ENDPOINT_LEN].split_at(mem::size_of::<IPAddr>());
+    let mut addr = IPAddr::new();
+    addr.0.copy_from_slice(a);
+    Endpoint {
+        addr,
+        port: host_slice_to_u16(p),
+    }
+}
+
+/// Parses the config buffer of a request: the remote endpoint, followed by
+/// the length of the path and the path.
+fn parse_request_config(cfg: &[u8]) -> Option<(Endpoint, CoAPOptions)> {
+    if cfg.len() <= ENDPOINT_LEN {
+        return None;
+    }
+    let path_len = cfg[ENDPOINT_LEN] as usize;
+    let path = cfg.get(ENDPOINT_LEN + 1..ENDPOINT_LEN + 1 + path_len)?;
+    let mut options = CoAPOptions::default();
+    if !options.set_path(path) {
+        return None;
+    }
+    Some((parse_endpoint(cfg), options))
+}
+
+/// Returns the part of the first `len` bytes of `data` that a message with
+/// the given block option carries, or all of them.
+fn block_data(data: &[u8], len: usize, block: Option<BlockOption>) -> &[u8] {
+    let len = cmp::min(len, data.len());
+    match block {
+        Some(block) => {
+            let start = cmp::min(block.offset(), len);
+            let end = cmp::min(start + block.size(), len);
+            &data[start..end]
+        }
+        None => &data[..len],
+    }
+}
+
+/// The first block of a payload of `len` bytes, if it does not fit in one
+/// message.
+fn first_block(len: usize) -> Option<BlockOption> {
+    let block = BlockOption::new(0, true, BLOCK_SZX);
+    if len > block.size() {
+        Some(block)
+    } else {
+        None
+    }
+}
+
+/// Returns whether the Observe value `new` is newer than `old`.
+fn observe_newer(old: u32, new: u32) -> bool {
+    (old < new && new - old < OBSERVE_MODULUS / 2) || (old > new && old - new > OBSERVE_MODULUS / 2)
+}
+
+/// Transmission state of a message sent on behalf of a process, which is
+/// rebuilt from the buffers of the process each time it is sent.
+#[derive(Copy, Clone)]
+struct Transmission {
+    message_id: u16,
+    confirmable: bool,
+    /// Whether the message waits to be sent
+    pending: bool,
+    acknowledged: bool,
+    retransmissions: u8,
+    timeout_ms: u32,
+    /// When the message is retransmitted if it is not acknowledged
+    deadline: Option<u64>,
+}
+
+impl Transmission {
+    fn new(message_id: u16, confirmable: bool) -> Transmission {
+        Transmission {
+            message_id,
+            confirmable,
+            pending: true,
+            acknowledged: false,
+            retransmissions: 0,
+            timeout_ms: 0,
+            deadline: None,
+        }
+    }
+
+    fn awaiting_ack(&self) -> bool {
+        self.confirmable && !self.acknowledged
+    }
+
+    /// Called when the message was sent. The timeout of a confirmable
+    /// message starts at a random time between ACK_TIMEOUT and ACK_TIMEOUT
+    /// times ACK_RANDOM_FACTOR (1.5), and doubles with each retransmission.
+    fn sent(&mut self, now: u64, random: u32) {
+        self.pending = false;
+        if self.awaiting_ack() {
+            self.timeout_ms = if self.retransmissions == 0 {
+                ACK_TIMEOUT_MS + random % (ACK_TIMEOUT_MS / 2)
+            } else {
+                self.timeout_ms * 2
+            };
+            self.deadline = Some(now + self.timeout_ms as u64);
+        }
+    }
+
+    fn acknowledge(&mut self) {
+        self.acknowledged = true;
+        self.pending = false;
+        self.deadline = None;
+    }
+
+    /// Called when the deadline passed. Returns false if the message was
+    /// already retransmitted MAX_RETRANSMIT times, and is given up.
+    fn expired(&mut self) -> bool {
+        self.deadline = None;
+        if self.retransmissions >= MAX_RETRANSMIT {
+            return false;
+        }
+        self.retransmissions += 1;
+        self.pending = true;
+        true
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Resource {
+    path: [u8; MAX_PATH_LEN],
+    path_len: usize,
+    observable: bool,
+    confirmable_notify: bool,
+    /// Observe value of the current state of the resource
+    observe: u32,
+}
+
+impl Resource {
+    fn path(&self) -> &[u8] {
+        &self.path[..self.path_len]
+    }
+}
+
+/// A remote client observing a resource.
+#[derive(Copy, Clone)]
+struct Observer {
+    resource: usize,
+    remote: Endpoint,
+    token: Token,
+    /// Whether the current notification still has to be delivered
+    notifying: bool,
+    tx: Transmission,
+}
+
+/// A notification being sent to the observers of a resource.
+#[derive(Copy, Clone)]
+struct Notification {
+    resource: usize,
+    len: usize,
+    observe: u32,
+    /// Number of observers the notification was delivered to
+    delivered: usize,
+}
+
+#[derive(Copy, Clone)]
+enum ServerState {
+    /// The blocks of the request payload are being received
+    Receiving { next_block: u32 },
+    /// The process is handling the request
+    Handling,
+    /// The response waits to be sent
+    Responding { code: u8, len: usize },
+}
+
+/// A request received for a resource of a process.
+#[derive(Copy, Clone)]
+struct ServerExchange {
+    remote: Endpoint,
+    token: Token,
+    message_id: u16,
+    confirmable: bool,
+    resource: usize,
+    /// Last block of the request payload, which the response acknowledges
+    block1: Option<BlockOption>,
+    /// Block of the response, requested or needed
+    block2: Option<BlockOption>,
+    /// Whether the request registered an observer
+    observe: bool,
+    state: ServerState,
+    /// When the exchange is given up
+    deadline: Option<u64>,
+}
+
+/// A request sent by a process.
+#[derive(Copy, Clone)]
+struct ClientExchange {
+    remote: Endpoint,
+    token: Token,
+    method: u8,
+    /// Options holding the path of the request
+    options: CoAPOptions,
+    /// Length of the request payload
+    payload_len: usize,
+    /// Block of the request payload being sent
+    block1: Option<BlockOption>,
+    /// Block of the response being requested, after the first one
+    block2: Option<BlockOption>,
+    /// Whether the request registers an observation
+    observe: bool,
+    tx: Transmission,
+    /// When the exchange is given up if no response arrives
+    deadline: Option<u64>,
+    /// Observe value and arrival time of the last notification, once the
+    /// observation is established
+    last_notification: Option<(u32, u64)>,
+}
+
+impl ClientExchange {
+    /// Builds the message of the request, with its payload taken from
+    /// `write`.
+    fn message<'b>(&self, write: &'b [u8]) -> (CoAPHeader, CoAPOptions, &'b [u8]) {
+        let header = CoAPHeader {
+            msg_type: if self.tx.confirmable {
+                MessageType::Confirmable
+            } else {
+                MessageType::NonConfirmable
+            },
+            code: self.method,
+            message_id: self.tx.message_id,
+            token: self.token,
+        };
+        let mut options = self.options;
+        if self.block2.is_some() {
+            // Requests for the later blocks of the response carry no
+            // payload, and do not register again
+            options.block2 = self.block2;
+            (header, options, &[])
+        } else {
+            if self.observe {
+                options.observe = Some(0);
+            }
+            options.block1 = self.block1;
+            let payload = block_data(write, self.payload_len, self.block1);
+            (header, options, payload)
+        }
+    }
+
+    /// Starts sending the next message of the exchange.
+    fn next_message(&mut self, message_id: u16) {
+        self.tx = Transmission::new(message_id, self.tx.confirmable);
+        self.deadline = None;
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    request_callback: Option<Callback>,
+    response_callback: Option<Callback>,
+    notify_callback: Option<Callback>,
+    server_read: Option<AppSlice<Shared, u8>>,
+    server_write: Option<AppSlice<Shared, u8>>,
+    app_cfg: Option<AppSlice<Shared, u8>>,
+    client_write: Option<AppSlice<Shared, u8>>,
+    client_read: Option<AppSlice<Shared, u8>>,
+    resources: [Option<Resource>; MAX_RESOURCES],
+    observers: [Option<Observer>; MAX_OBSERVERS],
+    notification: Option<Notification>,
+    server: Option<ServerExchange>,
+    client: Option<ClientExchange>,
+}
+
+impl App {
+    fn find_resource(&self, path: &[u8]) -> Option<usize> {
+        self.resources
+            .iter()
+            .position(|resource| resource.map_or(false, |resource| resource.path() == path))
+    }
+
+    /// Adds an observer of a resource, replacing any other observation of
+    /// the resource by the same endpoint. Returns false if the list of
+    /// observers is full.
+    fn add_observer(&mut self, resource: usize, remote: Endpoint, token: Token) -> bool {
+        self.remove_observer(resource, remote);
+        match self.observers.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(Observer {
+                    resource,
+                    remote,
+                    token,
+                    notifying: false,
+                    tx: Transmission::new(0, false),
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove_observer(&mut self, resource: usize, remote: Endpoint) {
+        for slot in self.observers.iter_mut() {
+            if slot.map_or(false, |o| o.resource == resource && o.remote == remote) {
+                *slot = None;
+            }
+        }
+    }
+
+    fn response_done(&self, result: ReturnCode, code: u8, len: usize) {
+        self.response_callback
+            .map(|mut cb| cb.schedule(usize::from(result), code as usize, len));
+    }
+
+    /// Tells the process that its notification was sent once no observer
+    /// waits for it anymore.
+    fn notification_progress(&mut self) {
+        let notification = match self.notification {
+            Some(notification) => notification,
+            None => return,
+        };
+        let waiting = self
+            .observers
+            .iter()
+            .flatten()
+            .any(|observer| observer.resource == notification.resource && observer.notifying);
+        if !waiting {
+            self.notification = None;
+            self.notify_callback
+                .map(|mut cb| cb.schedule(notification.resource, notification.delivered, 0));
+        }
+    }
+
+    /// Called when the delivery of the current notification to an observer
+    /// ended.
+    fn notification_ended(&mut self, delivered: bool) {
+        if delivered {
+            if let Some(notification) = self.notification.as_mut() {
+                notification.delivered += 1;
+            }
+        }
+        self.notification_progress();
+    }
+}
+
+pub struct CoAPDriver<'a, A: time::Alarm<'a>> {
+    /// UDP sender bound to the CoAP port
+    sender: &'a dyn UDPSender<'a>,
+
+    /// Alarm used for the retransmissions of all processes
+    alarm: &'a A,
+
+    /// Grant of apps that use this driver.
+    apps: Grant<App>,
+
+    /// Whether a message is being sent.
+    busy: Cell<bool>,
+
+    /// Buffer messages are built in before they are sent
+    tx_buffer: MapCell<LeasableBuffer<'static, u8>>,
+
+    /// An acknowledgement, reset or error response generated by the driver
+    /// itself, and the endpoint to send it to
+    pending_control: OptionalCell<(Endpoint, CoAPHeader, CoAPOptions)>,
+
+    /// Endpoint, message ID and length of the last piggybacked response,
+    /// which stays in `tx_buffer` until another message is built
+    cached_response: OptionalCell<(Endpoint, u16, usize)>,
+    /// Whether the cached response is sent again
+    resend_cached: Cell<bool>,
+
+    next_message_id: Cell<u16>,
+    next_token: Cell<u32>,
+    random_state: Cell<u32>,
+    last_ticks: Cell<A::Ticks>,
+    uptime_ms: Cell<u64>,
+
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> CoAPDriver<'a, A> {
+    /// Creates the driver. `seed` seeds the random numbers used for message
+    /// IDs, tokens and retransmission timeouts, and should differ between
+    /// devices.
+    pub fn new(
+        sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        grant: Grant<App>,
+        tx_buffer: LeasableBuffer<'static, u8>,
+        seed: u32,
+        net_cap: &'static NetworkCapability,
+    ) -> CoAPDriver<'a, A> {
+        let seed = seed ^ alarm.now().into_u32();
+        CoAPDriver {
+            sender,
+            alarm,
+            apps: grant,
+            busy: Cell::new(false),
+            tx_buffer: MapCell::new(tx_buffer),
+            pending_control: OptionalCell::empty(),
+            cached_response: OptionalCell::empty(),
+            resend_cached: Cell::new(false),
+            next_message_id: Cell::new(seed as u16),
+            next_token: Cell::new(seed.rotate_left(16)),
+            random_state: Cell::new(seed | 1),
+            last_ticks: Cell::new(alarm.now()),
+            uptime_ms: Cell::new(0),
+            net_cap,
+        }
+    }
+
+    /// Utility function to perform an action on an app in a system call.
+    #[inline]
+    fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| closure(app))
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Milliseconds since the driver was created.
+    fn now(&self) -> u64 {
+        let freq = A::Frequency::frequency() as u64;
+        let now = self.alarm.now();
+        let elapsed = now.wrapping_sub(self.last_ticks.get()).into_u32() as u64;
+        let ms = elapsed * 1000 / freq;
+        let consumed = (ms * freq / 1000) as u32;
+        self.last_ticks
+            .set(self.last_ticks.get().wrapping_add(A::Ticks::from(consumed)));
+        self.uptime_ms.set(self.uptime_ms.get() + ms);
+        self.uptime_ms.get()
+    }
+
+    /// Returns a pseudo-random number (xorshift32).
+    fn random(&self) -> u32 {
+        let mut x = self.random_state.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random_state.set(x);
+        x
+    }
+
+    fn message_id(&self) -> u16 {
+        let id = self.next_message_id.get();
+        self.next_message_id.set(id.wrapping_add(1));
+        id
+    }
+
+    fn token(&self) -> Token {
+        let token = self.next_token.get();
+        self.next_token.set(token.wrapping_add(1));
+        // Four bytes are always a valid token
+        Token::new(&token.to_be_bytes()).unwrap_or_default()
+    }
+
+    /// Queues a message generated by the driver. If one is already waiting
+    /// to be sent, the new one is dropped; the peer retransmits confirmable
+    /// messages.
+    fn queue_control(&self, remote: Endpoint, header: CoAPHeader, options: CoAPOptions) {
+        if self.pending_control.is_none() {
+            self.pending_control.set((remote, header, options));
+        }
+    }
+
+    /// Queues a response generated by the driver to a request.
+    fn queue_reply(
+        &self,
+        remote: Endpoint,
+        request: &CoAPHeader,
+        code: u8,
+        block1: Option<BlockOption>,
+    ) {
+        let (msg_type, message_id) = if request.msg_type == MessageType::Confirmable {
+            (MessageType::Acknowledgement, request.message_id)
+        } else {
+            (MessageType::NonConfirmable, self.message_id())
+        };
+        let mut options = CoAPOptions::default();
+        options.block1 = block1;
+        let header = CoAPHeader {
+            msg_type,
+            code,
+            message_id,
+            token: request.token,
+        };
+        self.queue_control(remote, header, options);
+    }
+
+    /// Queues an empty acknowledgement or reset of a message.
+    fn queue_empty(&self, remote: Endpoint, msg_type: MessageType, message_id: u16) {
+        let header = CoAPHeader {
+            msg_type,
+            code: code::EMPTY,
+            message_id,
+            token: Token::default(),
+        };
+        self.queue_control(remote, header, CoAPOptions::default());
+    }
+
+    /// Send messages until the UDP sender is busy or nothing is left to
+    /// send.
+    fn send_next(&self) {
+        while !self.busy.get() {
+            if !self.send_one() {
+                break;
+            }
+        }
+    }
+
+    /// Send the next message that is waiting, if there is one. A
+    /// retransmitted response goes first, then messages generated by the
+    /// driver, then messages of the processes in app order. Returns false if
+    /// there was nothing to send.
+    fn send_one(&self) -> bool {
+        let mut buf = match self.tx_buffer.take() {
+            Some(buf) => buf,
+            None => return false,
+        };
+
+        let mut next = None;
+        if self.resend_cached.take() {
+            next = self
+                .cached_response
+                .map(|&mut (remote, _, len)| (remote, len, None));
+        }
+        if next.is_none() {
+            if let Some((remote, header, options)) = self.pending_control.take() {
+                self.cached_response.clear();
+                next = encode_message(&mut buf[..], &header, &options, &[])
+                    .done()
+                    .map(|(len, _)| (remote, len, None));
+            }
+        }
+        if next.is_none() {
+            let now = self.now();
+            for app in self.apps.iter() {
+                app.enter(|app, _| {
+                    next = self.app_message(app, &mut buf[..], now);
+                });
+                if next.is_some() {
+                    break;
+                }
+            }
+        }
+
+        match next {
+            Some((remote, len, cache)) => {
+                buf.slice(0..len);
+                self.busy.set(true);
+                match self
+                    .sender
+                    .send_to(remote.addr, remote.port, buf, self.net_cap)
+                {
+                    Ok(()) => {
+                        if let Some(message_id) = cache {
+                            self.cached_response.set((remote, message_id, len));
+                        }
+                    }
+                    Err(mut buf) => {
+                        // The message is lost; confirmable messages are
+                        // retransmitted like any other lost message.
+                        debug!("[CoAP] Error sending message");
+                        buf.reset();
+                        self.tx_buffer.replace(buf);
+                        self.busy.set(false);
+                    }
+                }
+                true
+            }
+            None => {
+                self.tx_buffer.replace(buf);
+                false
+            }
+        }
+    }
+
+    /// Builds the next message of an app in `buf`: its response, its
+    /// request, or a notification. Returns the endpoint to send it to, its
+    /// length and, for a piggybacked response, its message ID.
+    fn app_message(
+        &self,
+        app: &mut App,
+        buf: &mut [u8],
+        now: u64,
+    ) -> Option<(Endpoint, usize, Option<u16>)> {
+        if let Some(exchange) = app.server {
+            if let ServerState::Responding { code, len } = exchange.state {
+                app.server = None;
+                self.cached_response.clear();
+                let (msg_type, message_id) = if exchange.confirmable {
+                    (MessageType::Acknowledgement, exchange.message_id)
+                } else {
+                    (MessageType::NonConfirmable, self.message_id())
+                };
+                let header = CoAPHeader {
+                    msg_type,
+                    code,
+                    message_id,
+                    token: exchange.token,
+                };
+                let mut options = CoAPOptions::default();
+                options.block1 = exchange.block1;
+                options.block2 = exchange.block2;
+                if exchange.observe {
+                    options.observe = app.resources[exchange.resource].map(|r| r.observe);
+                }
+                let write = app.server_write.as_ref().map_or(&[][..], |w| w.as_ref());
+                let payload = block_data(write, len, exchange.block2);
+                match encode_message(buf, &header, &options, payload).done() {
+                    Some((len, _)) => {
+                        let cache = if exchange.confirmable {
+                            Some(message_id)
+                        } else {
+                            None
+                        };
+                        return Some((exchange.remote, len, cache));
+                    }
+                    None => debug!("[CoAP] Response does not fit in a message"),
+                }
+            }
+        }
+
+        if let Some(mut client) = app.client {
+            if client.tx.pending {
+                self.cached_response.clear();
+                let write = app.client_write.as_ref().map_or(&[][..], |w| w.as_ref());
+                let (header, options, payload) = client.message(write);
+                match encode_message(buf, &header, &options, payload).done() {
+                    Some((len, _)) => {
+                        client.tx.sent(now, self.random());
+                        if !client.tx.confirmable {
+                            client.deadline = Some(now + MAX_TRANSMIT_WAIT_MS);
+                        }
+                        app.client = Some(client);
+                        return Some((client.remote, len, None));
+                    }
+                    None => {
+                        app.client = None;
+                        app.response_done(ReturnCode::ESIZE, 0, 0);
+                    }
+                }
+            }
+        }
+
+        let notification = app.notification?;
+        let mut next = None;
+        let mut ended = None;
+        for observer in app.observers.iter_mut().flatten() {
+            if observer.resource != notification.resource
+                || !observer.notifying
+                || !observer.tx.pending
+            {
+                continue;
+            }
+            self.cached_response.clear();
+            let header = CoAPHeader {
+                msg_type: if observer.tx.confirmable {
+                    MessageType::Confirmable
+                } else {
+                    MessageType::NonConfirmable
+                },
+                code: code::CONTENT,
+                message_id: observer.tx.message_id,
+                token: observer.token,
+            };
+            let mut options = CoAPOptions::default();
+            options.observe = Some(notification.observe);
+            options.block2 = first_block(notification.len);
+            let write = app.server_write.as_ref().map_or(&[][..], |w| w.as_ref());
+            let payload = block_data(write, notification.len, options.block2);
+            match encode_message(buf, &header, &options, payload).done() {
+                Some((len, _)) => {
+                    observer.tx.sent(now, self.random());
+                    if !observer.tx.confirmable {
+                        observer.notifying = false;
+                        ended = Some(true);
+                    }
+                    next = Some((observer.remote, len, None));
+                    break;
+                }
+                None => {
+                    debug!("[CoAP] Notification does not fit in a message");
+                    observer.notifying = false;
+                    ended = Some(false);
+                }
+            }
+        }
+        if let Some(delivered) = ended {
+            app.notification_ended(delivered);
+        }
+        next
+    }
+
+    /// Set the alarm for the earliest deadline of all exchanges, and keep it
+    /// running while any exchange or observation is in progress.
+    fn schedule_alarm(&self) {
+        let now = self.now();
+        let mut active = false;
+        let mut next = now + MAX_SLEEP_MS;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                let mut deadlines = [None; 3 + MAX_OBSERVERS];
+                if let Some(exchange) = app.server {
+                    active = true;
+                    deadlines[0] = exchange.deadline;
+                }
+                if let Some(client) = app.client {
+                    active = true;
+                    deadlines[1] = client.tx.deadline;
+                    deadlines[2] = client.deadline;
+                }
+                for (i, observer) in app.observers.iter().enumerate() {
+                    if let Some(observer) = observer {
+                        active = true;
+                        deadlines[3 + i] = observer.tx.deadline;
+                    }
+                }
+                for deadline in deadlines.iter().flatten() {
+                    next = next.min(*deadline);
+                }
+            });
+        }
+        if active {
+            let dt = next.saturating_sub(now).max(1);
+            self.alarm
+                .set_alarm(self.alarm.now(), A::ticks_from_ms(dt as u32));
+        } else {
+            let _ = self.alarm.disarm();
+        }
+    }
+
+    /// Send any messages that are waiting and update the alarm, after the
+    /// state of an exchange changed.
+    fn exchanges_changed(&self) {
+        self.send_next();
+        self.schedule_alarm();
+    }
+
+    /// Handles an acknowledgement or reset of a confirmable message.
+    fn receive_reply(&self, remote: Endpoint, header: &CoAPHeader) {
+        let reset = header.msg_type == MessageType::Reset;
+        let now = self.now();
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if let Some(mut client) = app.client {
+                    if client.remote == remote
+                        && client.tx.message_id == header.message_id
+                        && client.tx.awaiting_ack()
+                    {
+                        if reset {
+                            app.client = None;
+                            app.response_done(ReturnCode::ECANCEL, 0, 0);
+                        } else {
+                            // The response is sent separately
+                            client.tx.acknowledge();
+                            client.deadline = Some(now + MAX_TRANSMIT_WAIT_MS);
+                            app.client = Some(client);
+                        }
+                    }
+                }
+
+                let mut ended = None;
+                for slot in app.observers.iter_mut() {
+                    let observer = match slot {
+                        Some(observer)
+                            if observer.remote == remote
+                                && observer.tx.message_id == header.message_id =>
+                        {
+                            observer
+                        }
+                        _ => continue,
+                    };
+                    if reset {
+                        // The observer is no longer interested
+                        if observer.notifying {
+                            ended = Some(false);
+                        }
+                        *slot = None;
+                    } else if observer.notifying && observer.tx.awaiting_ack() {
+                        observer.tx.acknowledge();
+                        observer.notifying = false;
+                        ended = Some(true);
+                    }
+                }
+                if let Some(delivered) = ended {
+                    app.notification_ended(delivered);
+                }
+            });
+        }
+    }
+
+    /// Handles a response: piggybacked on an acknowledgement, sent
+    /// separately, or a notification of an observation.
+    fn receive_response(&self, remote: Endpoint, header: &CoAPHeader, rest: &[u8]) {
+        let (offset, options) = match CoAPOptions::decode(rest).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        let data = &rest[offset..];
+        let now = self.now();
+        let mut matched = false;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                let client = match app.client {
+                    Some(client) if client.remote == remote && client.token == header.token => {
+                        client
+                    }
+                    _ => return,
+                };
+                if header.msg_type == MessageType::Acknowledgement
+                    && header.message_id != client.tx.message_id
+                {
+                    return;
+                }
+                matched = true;
+                self.client_response(app, client, header, &options, data, now);
+            });
+            if matched {
+                break;
+            }
+        }
+
+        match header.msg_type {
+            MessageType::Confirmable if matched => {
+                self.queue_empty(remote, MessageType::Acknowledgement, header.message_id);
+            }
+            // Responses nobody waits for are rejected, which also cancels
+            // observations that were given up
+            MessageType::Confirmable | MessageType::NonConfirmable if !matched => {
+                self.queue_empty(remote, MessageType::Reset, header.message_id);
+            }
+            _ => {}
+        }
+    }
+
+    /// Handles a response to the request of an app.
+    fn client_response(
+        &self,
+        app: &mut App,
+        mut client: ClientExchange,
+        header: &CoAPHeader,
+        options: &CoAPOptions,
+        data: &[u8],
+        now: u64,
+    ) {
+        client.tx.acknowledge();
+        client.deadline = None;
+
+        // The server wants the next block of the request payload
+        if header.code == code::CONTINUE {
+            if let Some(block) = client.block1.filter(|block| block.more) {
+                let szx = options
+                    .block1
+                    .map_or(block.szx, |b| cmp::min(b.szx, block.szx));
+                let offset = block.offset() + block.size();
+                let mut next = BlockOption::new(0, false, szx);
+                next.num = (offset / next.size()) as u32;
+                next.more = offset + next.size() < client.payload_len;
+                client.block1 = Some(next);
+                client.next_message(self.message_id());
+                app.client = Some(client);
+                return;
+            }
+        }
+
+        let observe = if client.observe {
+            options.observe
+        } else {
+            None
+        };
+        if let Some(observe) = observe {
+            if let Some((last, at)) = client.last_notification {
+                if !observe_newer(last, observe) && now < at + OBSERVE_FRESHNESS_MS {
+                    // A notification older than the last one
+                    app.client = Some(client);
+                    return;
+                }
+            }
+            if code::class(header.code) == 2 {
+                client.last_notification = Some((observe, now));
+            }
+        }
+
+        let offset = options.block2.map_or(0, |block| block.offset());
+        let mut result = ReturnCode::SUCCESS;
+        let mut received = offset;
+        if let Some(read) = app.client_read.as_mut() {
+            let read = read.as_mut();
+            let start = cmp::min(offset, read.len());
+            let len = cmp::min(data.len(), read.len() - start);
+            read[start..start + len].copy_from_slice(&data[..len]);
+            received = start + len;
+        }
+        if received < offset + data.len() {
+            result = ReturnCode::ESIZE;
+        }
+
+        if let Some(block) = options.block2 {
+            if block.more && result == ReturnCode::SUCCESS {
+                client.block2 = Some(BlockOption::new(block.num + 1, false, block.szx));
+                client.block1 = None;
+                client.next_message(self.message_id());
+                app.client = Some(client);
+                return;
+            }
+        }
+
+        client.block2 = None;
+        if client.last_notification.is_some() && code::class(header.code) == 2 {
+            // Notifications keep arriving with the same token
+            app.client = Some(client);
+        } else {
+            app.client = None;
+        }
+        app.response_done(result, header.code, received);
+    }
+
+    /// Handles a request to one of the resources of the processes.
+    fn receive_request(&self, remote: Endpoint, header: &CoAPHeader, rest: &[u8]) {
+        // The last response is sent again if its request is retransmitted
+        if header.msg_type == MessageType::Confirmable {
+            let retransmitted = self
+                .cached_response
+                .map_or(false, |&mut (dst, message_id, _)| {
+                    dst == remote && message_id == header.message_id
+                });
+            if retransmitted {
+                self.resend_cached.set(true);
+                return;
+            }
+        }
+
+        let (offset, options) = match CoAPOptions::decode(rest).done() {
+            Some(decoded) => decoded,
+            None => {
+                self.queue_reply(remote, header, code::BAD_REQUEST, None);
+                return;
+            }
+        };
+        if options.unknown_critical {
+            self.queue_reply(remote, header, code::BAD_OPTION, None);
+            return;
+        }
+
+        let mut target = None;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if let Some(resource) = app.find_resource(options.path()) {
+                    target = Some((app.appid(), resource));
+                }
+            });
+            if target.is_some() {
+                break;
+            }
+        }
+        let (appid, resource) = match target {
+            Some(target) => target,
+            None => {
+                self.queue_reply(remote, header, code::NOT_FOUND, None);
+                return;
+            }
+        };
+
+        let now = self.now();
+        let mut reply = None;
+        let _ = self.apps.enter(appid, |app, _| {
+            reply = self.server_request(
+                app,
+                resource,
+                remote,
+                header,
+                &options,
+                &rest[offset..],
+                now,
+            );
+        });
+        if let Some((code, block1)) = reply {
+            self.queue_reply(remote, header, code, block1);
+        }
+    }
+
+    /// Passes a request to the app that registered its resource. Returns
+    /// the code and Block1 option of the reply if the driver replies itself.
+    #[allow(clippy::too_many_arguments)]
+    fn server_request(
+        &self,
+        app: &mut App,
+        resource: usize,
+        remote: Endpoint,
+        header: &CoAPHeader,
+        options: &CoAPOptions,
+        data: &[u8],
+        now: u64,
+    ) -> Option<(u8, Option<BlockOption>)> {
+        let mut next_block = 0;
+        if let Some(exchange) = app.server {
+            if exchange.remote == remote && exchange.message_id == header.message_id {
+                // A retransmission of the request being handled
+                return None;
+            }
+            match exchange.state {
+                ServerState::Receiving { next_block: next }
+                    if exchange.remote == remote
+                        && exchange.resource == resource
+                        && options.block1.is_some() =>
+                {
+                    next_block = next;
+                }
+                _ => return Some((code::SERVICE_UNAVAILABLE, None)),
+            }
+        }
+
+        let offset = match options.block1 {
+            Some(block) if block.num != 0 && block.num != next_block => {
+                app.server = None;
+                return Some((code::REQUEST_ENTITY_INCOMPLETE, None));
+            }
+            Some(block) => block.offset(),
+            None => 0,
+        };
+        let read_len = app.server_read.as_ref().map_or(0, |read| read.len());
+        if offset + data.len() > read_len {
+            app.server = None;
+            return Some((code::REQUEST_ENTITY_TOO_LARGE, None));
+        }
+        if let Some(read) = app.server_read.as_mut() {
+            read.as_mut()[offset..offset + data.len()].copy_from_slice(data);
+        }
+        let received = offset + data.len();
+
+        let mut exchange = ServerExchange {
+            remote,
+            token: header.token,
+            message_id: header.message_id,
+            confirmable: header.msg_type == MessageType::Confirmable,
+            resource,
+            block1: options
+                .block1
+                .map(|block| BlockOption::new(block.num, false, block.szx)),
+            block2: options.block2,
+            observe: false,
+            state: ServerState::Handling,
+            deadline: Some(now + MAX_TRANSMIT_WAIT_MS),
+        };
+        if let Some(block) = options.block1.filter(|block| block.more) {
+            exchange.state = ServerState::Receiving {
+                next_block: block.num + 1,
+            };
+            app.server = Some(exchange);
+            return Some((code::CONTINUE, Some(block)));
+        }
+
+        if header.code == code::GET {
+            let observable = app.resources[resource].map_or(false, |r| r.observable);
+            match options.observe {
+                Some(0) if observable => {
+                    exchange.observe = app.add_observer(resource, remote, header.token);
+                }
+                Some(1) => app.remove_observer(resource, remote),
+                _ => {}
+            }
+        }
+        app.server = Some(exchange);
+        app.request_callback
+            .map(|mut cb| cb.schedule(resource, header.code as usize, received));
+        None
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> Driver for CoAPDriver<'a, A> {
+    /// Setup buffers to read/write from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Request read buffer. The payload of requests to the resources
+    ///        of the app is written to it.
+    /// - `1`: Response write buffer. Contains the payload of responses and
+    ///        notifications. It must not be changed until the response is
+    ///        sent, or until the notification callback.
+    /// - `2`: Config buffer. Contains the path of a resource to register, or
+    ///        the remote endpoint of a request followed by the length of its
+    ///        path (1 byte) and the path.
+    /// - `3`: Request write buffer. Contains the payload of requests. It
+    ///        cannot be changed while a request is in progress.
+    /// - `4`: Response read buffer. The payload of responses and
+    ///        notifications is written to it.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self.do_with_app(appid, |app| {
+                app.server_read = slice;
+                ReturnCode::SUCCESS
+            }),
+            1 => self.do_with_app(appid, |app| {
+                app.server_write = slice;
+                ReturnCode::SUCCESS
+            }),
+            2 => self.do_with_app(appid, |app| {
+                app.app_cfg = slice;
+                ReturnCode::SUCCESS
+            }),
+            3 => self.do_with_app(appid, |app| {
+                if app.client.is_some() {
+                    return ReturnCode::EBUSY;
+                }
+                app.client_write = slice;
+                ReturnCode::SUCCESS
+            }),
+            4 => self.do_with_app(appid, |app| {
+                app.client_read = slice;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A request to a resource of the app was received. The callback
+    ///        receives the index of the resource, the method of the request
+    ///        and the length of its payload. The app answers with command
+    ///        `3`.
+    /// - `1`: A response to the request of the app, or a notification of
+    ///        the resource it observes, was received. The callback receives
+    ///        `SUCCESS`, `ESIZE` if the payload did not fit in the response
+    ///        read buffer, or the error that ended the request: `ENOACK` if
+    ///        the server did not respond and `ECANCEL` if it rejected the
+    ///        request. It then receives the code of the response and the
+    ///        length of its payload.
+    /// - `2`: A notification sent with command `4` was delivered. The
+    ///        callback receives the index of the resource and the number of
+    ///        observers that the notification was delivered to.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self.do_with_app(app_id, |app| {
+                app.request_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            1 => self.do_with_app(app_id, |app| {
+                app.response_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            2 => self.do_with_app(app_id, |app| {
+                app.notify_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// CoAP control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Register a resource, whose path is the first `arg1` bytes of
+    ///        the config buffer. `arg2` holds the `RESOURCE_*` flags.
+    ///        Returns the index of the resource, EINVAL if the path is empty
+    ///        or too long, EBUSY if a resource with the same path exists and
+    ///        ENOMEM if the app registered `MAX_RESOURCES` resources.
+    /// - `2`: Unregister the resource with index `arg1`, and forget its
+    ///        observers.
+    /// - `3`: Respond to the request being handled, with code `arg1` and
+    ///        the first `arg2` bytes of the response write buffer as
+    ///        payload. Returns EOFF if no request is being handled and
+    ///        EINVAL if the code is not a response code or the buffer is too
+    ///        short.
+    /// - `4`: Notify the observers of the resource with index `arg1`, with
+    ///        the first `arg2` bytes of the response write buffer as
+    ///        payload. Returns EINVAL if the resource is not observable and
+    ///        EBUSY if a notification is being delivered.
+    /// - `5`: Send a request with the method in the low 8 bits of `arg1`,
+    ///        and the `REQUEST_*` flags above them, to the endpoint and path
+    ///        in the config buffer. Its payload is the first `arg2` bytes of
+    ///        the request write buffer. Returns EBUSY if a request is in
+    ///        progress and EINVAL if the method, the config buffer or the
+    ///        length is invalid.
+    /// - `6`: Cancel the request in progress. An observation ends once the
+    ///        next notification is rejected.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        let result = match command_num {
+            0 => return ReturnCode::SUCCESS,
+
+            1 => {
+                let path = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.app_cfg.as_ref().and_then(|cfg| {
+                            if arg1 == 0 || arg1 > MAX_PATH_LEN || arg1 > cfg.len() {
+                                return None;
+                            }
+                            let mut path = [0; MAX_PATH_LEN];
+                            path[..arg1].copy_from_slice(&cfg.as_ref()[..arg1]);
+                            Some(path)
+                        })
+                    })
+                    .unwrap_or(None);
+                let path = match path {
+                    Some(path) => path,
+                    None => return ReturnCode::EINVAL,
+                };
+                let mut in_use = false;
+                for app in self.apps.iter() {
+                    app.enter(|app, _| {
+                        if app.find_resource(&path[..arg1]).is_some() {
+                            in_use = true;
+                        }
+                    });
+                }
+                if in_use {
+                    return ReturnCode::EBUSY;
+                }
+                return self.do_with_app(appid, |app| {
+                    match app.resources.iter().position(|slot| slot.is_none()) {
+                        Some(index) => {
+                            app.resources[index] = Some(Resource {
+                                path,
+                                path_len: arg1,
+                                observable: arg2 & RESOURCE_OBSERVABLE != 0,
+                                confirmable_notify: arg2 & RESOURCE_CONFIRMABLE_NOTIFY != 0,
+                                observe: 0,
+                            });
+                            ReturnCode::SuccessWithValue { value: index }
+                        }
+                        None => ReturnCode::ENOMEM,
+                    }
+                });
+            }
+
+            2 => self.do_with_app(appid, |app| {
+                match app.resources.get_mut(arg1) {
+                    Some(slot) if slot.is_some() => *slot = None,
+                    _ => return ReturnCode::EINVAL,
+                }
+                for slot in app.observers.iter_mut() {
+                    if slot.map_or(false, |observer| observer.resource == arg1) {
+                        *slot = None;
+                    }
+                }
+                if app
+                    .server
+                    .map_or(false, |exchange| exchange.resource == arg1)
+                {
+                    app.server = None;
+                }
+                if app
+                    .notification
+                    .map_or(false, |notification| notification.resource == arg1)
+                {
+                    app.notification = None;
+                }
+                ReturnCode::SUCCESS
+            }),
+
+            3 => self.do_with_app(appid, |app| {
+                let write_len = app.server_write.as_ref().map_or(0, |write| write.len());
+                if arg1 > 0xff || !code::is_response(arg1 as u8) || arg2 > write_len {
+                    return ReturnCode::EINVAL;
+                }
+                let mut exchange = match app.server {
+                    Some(exchange) if matches!(exchange.state, ServerState::Handling) => exchange,
+                    _ => return ReturnCode::EOFF,
+                };
+                let mut code = arg1 as u8;
+                let mut len = arg2;
+                let block2 = match exchange.block2 {
+                    // The block requested, in blocks no larger than ours
+                    Some(requested) => {
+                        let mut block =
+                            BlockOption::new(0, false, cmp::min(requested.szx, BLOCK_SZX));
+                        block.num = (requested.offset() / block.size()) as u32;
+                        Some(block)
+                    }
+                    None => first_block(len),
+                };
+                exchange.block2 = block2.map(|mut block| {
+                    block.more = block.offset() + block.size() < len;
+                    block
+                });
+                if block2.map_or(false, |block| block.offset() > len) {
+                    code = code::BAD_OPTION;
+                    len = 0;
+                    exchange.block2 = None;
+                }
+                // An error response ends the observation
+                if exchange.observe && code::class(code) != 2 {
+                    app.remove_observer(exchange.resource, exchange.remote);
+                    exchange.observe = false;
+                }
+                exchange.state = ServerState::Responding { code, len };
+                app.server = Some(exchange);
+                ReturnCode::SUCCESS
+            }),
+
+            4 => self.do_with_app(appid, |app| {
+                let write_len = app.server_write.as_ref().map_or(0, |write| write.len());
+                if app.notification.is_some() {
+                    return ReturnCode::EBUSY;
+                }
+                if arg2 > write_len {
+                    return ReturnCode::EINVAL;
+                }
+                let resource = match app.resources.get_mut(arg1) {
+                    Some(Some(resource)) if resource.observable => resource,
+                    _ => return ReturnCode::EINVAL,
+                };
+                resource.observe = (resource.observe + 1) % OBSERVE_MODULUS;
+                let observe = resource.observe;
+                let confirmable = resource.confirmable_notify;
+                for observer in app.observers.iter_mut().flatten() {
+                    if observer.resource == arg1 {
+                        observer.tx = Transmission::new(self.message_id(), confirmable);
+                        observer.notifying = true;
+                    }
+                }
+                app.notification = Some(Notification {
+                    resource: arg1,
+                    len: arg2,
+                    observe,
+                    delivered: 0,
+                });
+                // Reports right away if the resource has no observers
+                app.notification_progress();
+                ReturnCode::SUCCESS
+            }),
+
+            5 => {
+                let method = (arg1 & 0xff) as u8;
+                if !code::is_request(method) {
+                    return ReturnCode::EINVAL;
+                }
+                let token = self.token();
+                let message_id = self.message_id();
+                self.do_with_app(appid, |app| {
+                    if app.client.is_some() {
+                        return ReturnCode::EBUSY;
+                    }
+                    let write_len = app.client_write.as_ref().map_or(0, |write| write.len());
+                    if arg2 > write_len {
+                        return ReturnCode::EINVAL;
+                    }
+                    let config = app
+                        .app_cfg
+                        .as_ref()
+                        .and_then(|cfg| parse_request_config(cfg.as_ref()));
+                    let (remote, options) = match config {
+                        Some(config) => config,
+                        None => return ReturnCode::EINVAL,
+                    };
+                    app.client = Some(ClientExchange {
+                        remote,
+                        token,
+                        method,
+                        options,
+                        payload_len: arg2,
+                        block1: first_block(arg2),
+                        block2: None,
+                        observe: arg1 & REQUEST_OBSERVE != 0 && method == code::GET,
+                        tx: Transmission::new(message_id, arg1 & REQUEST_CONFIRMABLE != 0),
+                        deadline: None,
+                        last_notification: None,
+                    });
+                    ReturnCode::SUCCESS
+                })
+            }
+
+            6 => self.do_with_app(appid, |app| match app.client.take() {
+                Some(_) => ReturnCode::SUCCESS,
+                None => ReturnCode::EALREADY,
+            }),
+
+            _ => return ReturnCode::ENOSUPPORT,
+        };
+        self.exchanges_changed();
+        result
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> UDPSendClient for CoAPDriver<'a, A> {
+    fn send_done(&self, result: ReturnCode, mut dgram: LeasableBuffer<'static, u8>) {
+        if result != ReturnCode::SUCCESS {
+            debug!("[CoAP] Message not sent: {:?}", result);
+        }
+        dgram.reset();
+        self.tx_buffer.replace(dgram);
+        self.busy.set(false);
+        self.exchanges_changed();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> UDPRecvClient for CoAPDriver<'a, A> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        let (offset, header) = match CoAPHeader::decode(payload).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        let remote = Endpoint {
+            addr: src_addr,
+            port: src_port,
+        };
+        match header.msg_type {
+            MessageType::Acknowledgement | MessageType::Reset if header.code == code::EMPTY => {
+                self.receive_reply(remote, &header);
+            }
+            // Resets are always empty
+            MessageType::Reset => {}
+            // An empty confirmable message is a ping, answered with a reset
+            _ if header.code == code::EMPTY => {
+                if header.msg_type == MessageType::Confirmable {
+                    self.queue_empty(remote, MessageType::Reset, header.message_id);
+                }
+            }
+            _ if code::is_request(header.code) => {
+                if header.msg_type != MessageType::Acknowledgement {
+                    self.receive_request(remote, &header, &payload[offset..]);
+                }
+            }
+            _ => self.receive_response(remote, &header, &payload[offset..]),
+        }
+        self.exchanges_changed();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for CoAPDriver<'a, A> {
+    fn alarm(&self) {
+        let now = self.now();
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                let server_expired = app.server.map_or(false, |exchange| {
+                    exchange.deadline.map_or(false, |d| now >= d)
+                });
+                if server_expired {
+                    app.server = None;
+                }
+
+                if let Some(mut client) = app.client {
+                    let mut failed = client.deadline.map_or(false, |d| now >= d);
+                    if client.tx.deadline.map_or(false, |d| now >= d) && !client.tx.expired() {
+                        failed = true;
+                    }
+                    if failed {
+                        app.client = None;
+                        app.response_done(ReturnCode::ENOACK, 0, 0);
+                    } else {
+                        app.client = Some(client);
+                    }
+                }
+
+                // An observer that stops acknowledging notifications is
+                // removed
+                let mut ended = false;
+                for slot in app.observers.iter_mut() {
+                    if let Some(observer) = slot {
+                        if observer.tx.deadline.map_or(false, |d| now >= d)
+                            && !observer.tx.expired()
+                        {
+                            ended |= observer.notifying;
+                            *slot = None;
+                        }
+                    }
+                }
+                if ended {
+                    app.notification_ended(false);
+                }
+            });
+        }
+        self.exchanges_changed();
+    }
+}
@@ -0,0 +1,424 @@
+//! This file contains the encoding and decoding of CoAP messages (RFC 7252).
+//!
+//! A message is a fixed 4 byte header, followed by a token of up to 8 bytes,
+//! a list of options and, after a payload marker, the payload:
+//!
+//! ```text
+//!  0                   1                   2                   3
+//!  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |Ver| T |  TKL  |      Code     |          Message ID           |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |   Token (if any, TKL bytes) ...
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |   Options (if any) ...
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |1 1 1 1 1 1 1 1|    Payload (if any) ...
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! ```
+//!
+//! Options are sorted by number, and each one only encodes the difference
+//! between its number and the number of the option before it. Only the
+//! options used by the CoAP driver are decoded into `CoAPOptions`: Uri-Path,
+//! Content-Format, Observe (RFC 7641), and Block1 and Block2 (RFC 7959).
+//! Other elective options are skipped, and other critical options are
+//! reported so that the message can be rejected.
+
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_u16, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
+
+/// UDP port of CoAP servers.
+pub const COAP_PORT: u16 = 5683;
+
+const COAP_VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 4;
+pub const MAX_TOKEN_LEN: usize = 8;
+/// Maximum length of a Uri-Path, with its segments separated by '/'.
+pub const MAX_PATH_LEN: usize = 32;
+const PAYLOAD_MARKER: u8 = 0xff;
+
+/// Message codes, written as `class << 5 | detail`.
+pub mod code {
+    pub const EMPTY: u8 = 0x00;
+
+    pub const GET: u8 = 0x01;
+    pub const POST: u8 = 0x02;
+    pub const PUT: u8 = 0x03;
+    pub const DELETE: u8 = 0x04;
+
+    pub const CREATED: u8 = 0x41;
+    pub const DELETED: u8 = 0x42;
+    pub const VALID: u8 = 0x43;
+    pub const CHANGED: u8 = 0x44;
+    pub const CONTENT: u8 = 0x45;
+    pub const CONTINUE: u8 = 0x5f;
+
+    pub const BAD_REQUEST: u8 = 0x80;
+    pub const BAD_OPTION: u8 = 0x82;
+    pub const NOT_FOUND: u8 = 0x84;
+    pub const METHOD_NOT_ALLOWED: u8 = 0x85;
+    pub const REQUEST_ENTITY_INCOMPLETE: u8 = 0x88;
+    pub const REQUEST_ENTITY_TOO_LARGE: u8 = 0x8d;
+
+    pub const INTERNAL_SERVER_ERROR: u8 = 0xa0;
+    pub const SERVICE_UNAVAILABLE: u8 = 0xa3;
+
+    pub fn class(code: u8) -> u8 {
+        code >> 5
+    }
+
+    pub fn is_request(code: u8) -> bool {
+        class(code) == 0 && code != EMPTY
+    }
+
+    pub fn is_response(code: u8) -> bool {
+        class(code) >= 2
+    }
+}
+
+/// Option numbers.
+pub mod option {
+    pub const URI_HOST: u16 = 3;
+    pub const OBSERVE: u16 = 6;
+    pub const URI_PORT: u16 = 7;
+    pub const URI_PATH: u16 = 11;
+    pub const CONTENT_FORMAT: u16 = 12;
+    pub const URI_QUERY: u16 = 15;
+    pub const ACCEPT: u16 = 17;
+    pub const BLOCK2: u16 = 23;
+    pub const BLOCK1: u16 = 27;
+
+    /// Options with odd numbers are critical: a message with a critical
+    /// option that is not understood must be rejected.
+    pub fn is_critical(number: u16) -> bool {
+        number & 1 == 1
+    }
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MessageType {
+    Confirmable = 0,
+    NonConfirmable = 1,
+    Acknowledgement = 2,
+    Reset = 3,
+}
+
+impl From<u8> for MessageType {
+    fn from(msg_type: u8) -> Self {
+        match msg_type & 0x3 {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            _ => MessageType::Reset,
+        }
+    }
+}
+
+/// The token of a message, which matches responses to requests.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Token {
+    len: u8,
+    bytes: [u8; MAX_TOKEN_LEN],
+}
+
+impl Token {
+    /// Returns `None` if `bytes` is longer than `MAX_TOKEN_LEN`.
+    pub fn new(bytes: &[u8]) -> Option<Token> {
+        if bytes.len() > MAX_TOKEN_LEN {
+            return None;
+        }
+        let mut token = Token {
+            len: bytes.len() as u8,
+            bytes: [0; MAX_TOKEN_LEN],
+        };
+        token.bytes[..bytes.len()].copy_from_slice(bytes);
+        Some(token)
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+/// The header and token of a message.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct CoAPHeader {
+    pub msg_type: MessageType,
+    pub code: u8,
+    pub message_id: u16,
+    pub token: Token,
+}
+
+impl CoAPHeader {
+    pub fn encode(&self, buf: &mut [u8]) -> SResult {
+        let token = self.token.as_slice();
+        let first = COAP_VERSION << 6 | (self.msg_type as u8) << 4 | token.len() as u8;
+        let mut offset = enc_consume!(buf; encode_u8, first);
+        offset = enc_consume!(buf, offset; encode_u8, self.code);
+        offset = enc_consume!(buf, offset; encode_u16, self.message_id);
+        offset = enc_consume!(buf, offset; encode_bytes, token);
+        stream_done!(offset)
+    }
+
+    /// Decodes the header and token, which must be of version 1. Empty
+    /// messages must not have a token.
+    pub fn decode(buf: &[u8]) -> SResult<CoAPHeader> {
+        let (offset, first) = dec_try!(buf; decode_u8);
+        stream_cond!(first >> 6 == COAP_VERSION);
+        let token_len = (first & 0xf) as usize;
+        stream_cond!(token_len <= MAX_TOKEN_LEN);
+        let (offset, code) = dec_try!(buf, offset; decode_u8);
+        stream_cond!(code != code::EMPTY || token_len == 0);
+        let (offset, message_id) = dec_try!(buf, offset; decode_u16);
+        stream_len_cond!(buf, offset + token_len);
+        let token = stream_from_option!(Token::new(&buf[offset..offset + token_len]));
+        stream_done!(
+            offset + token_len,
+            CoAPHeader {
+                msg_type: MessageType::from(first >> 4),
+                code,
+                message_id,
+                token,
+            }
+        )
+    }
+}
+
+/// Value of a Block1 or Block2 option, which describes the block of a
+/// payload carried by a message, or the block requested.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BlockOption {
+    /// Number of the block
+    pub num: u32,
+    /// Whether more blocks follow this one
+    pub more: bool,
+    /// Size exponent: blocks are `2^(szx + 4)` bytes long
+    pub szx: u8,
+}
+
+/// Largest valid size exponent of a block, for 1024 byte blocks.
+pub const MAX_BLOCK_SZX: u8 = 6;
+
+impl BlockOption {
+    pub fn new(num: u32, more: bool, szx: u8) -> BlockOption {
+        BlockOption { num, more, szx }
+    }
+
+    pub fn size(&self) -> usize {
+        1 << (self.szx + 4)
+    }
+
+    /// Offset of the block in the payload.
+    pub fn offset(&self) -> usize {
+        self.num as usize * self.size()
+    }
+
+    fn value(&self) -> u32 {
+        self.num << 4 | (self.more as u32) << 3 | self.szx as u32
+    }
+
+    fn from_value(value: u32) -> Option<BlockOption> {
+        let szx = (value & 0x7) as u8;
+        if szx > MAX_BLOCK_SZX || value >> 24 != 0 {
+            return None;
+        }
+        Some(BlockOption {
+            num: value >> 4,
+            more: value & 0x8 != 0,
+            szx,
+        })
+    }
+}
+
+/// The options of a message that are understood.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct CoAPOptions {
+    pub observe: Option<u32>,
+    /// Segments of the Uri-Path, separated by '/'
+    uri_path: [u8; MAX_PATH_LEN],
+    uri_path_len: usize,
+    pub content_format: Option<u16>,
+    pub block2: Option<BlockOption>,
+    pub block1: Option<BlockOption>,
+    /// Whether the message has a critical option that is not understood.
+    /// It is not encoded.
+    pub unknown_critical: bool,
+}
+
+impl CoAPOptions {
+    pub fn path(&self) -> &[u8] {
+        &self.uri_path[..self.uri_path_len]
+    }
+
+    /// Sets the Uri-Path, whose segments are separated by '/'. Returns false
+    /// if it is longer than `MAX_PATH_LEN`.
+    pub fn set_path(&mut self, path: &[u8]) -> bool {
+        if path.len() > MAX_PATH_LEN {
+            return false;
+        }
+        self.uri_path[..path.len()].copy_from_slice(path);
+        self.uri_path_len = path.len();
+        true
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) -> SResult {
+        let mut last = 0;
+        let mut offset = 0;
+        if let Some(observe) = self.observe {
+            offset =
+                enc_consume!(buf, offset; encode_uint_option, &mut last, option::OBSERVE, observe);
+        }
+        if self.uri_path_len > 0 {
+            for segment in self.path().split(|b| *b == b'/') {
+                offset =
+                    enc_consume!(buf, offset; encode_option, &mut last, option::URI_PATH, segment);
+            }
+        }
+        if let Some(format) = self.content_format {
+            offset = enc_consume!(buf, offset; encode_uint_option, &mut last, option::CONTENT_FORMAT, format as u32);
+        }
+        if let Some(block2) = self.block2 {
+            offset = enc_consume!(buf, offset; encode_uint_option, &mut last, option::BLOCK2, block2.value());
+        }
+        if let Some(block1) = self.block1 {
+            offset = enc_consume!(buf, offset; encode_uint_option, &mut last, option::BLOCK1, block1.value());
+        }
+        stream_done!(offset)
+    }
+
+    /// Decodes the options of a message, up to the end of the message or
+    /// the payload marker. The returned offset is the start of the payload.
+    pub fn decode(buf: &[u8]) -> SResult<CoAPOptions> {
+        let mut options = CoAPOptions::default();
+        let mut number: u16 = 0;
+        let mut offset = 0;
+        while offset < buf.len() {
+            let first = buf[offset];
+            offset += 1;
+            if first == PAYLOAD_MARKER {
+                // A payload marker must be followed by a payload
+                stream_cond!(offset < buf.len());
+                break;
+            }
+            let (next, delta) = dec_try!(buf, offset; decode_extended, first >> 4);
+            let (next, len) = dec_try!(buf, next; decode_extended, first & 0xf);
+            offset = next;
+            let len = len as usize;
+            stream_len_cond!(buf, offset + len);
+            number = stream_from_option!(number.checked_add(delta));
+            let value = &buf[offset..offset + len];
+            offset += len;
+
+            match number {
+                option::OBSERVE => {
+                    options.observe = Some(stream_from_option!(decode_uint(value)));
+                }
+                option::URI_PATH => {
+                    let mut path_len = options.uri_path_len;
+                    if path_len > 0 {
+                        stream_cond!(path_len < MAX_PATH_LEN);
+                        options.uri_path[path_len] = b'/';
+                        path_len += 1;
+                    }
+                    stream_cond!(path_len + value.len() <= MAX_PATH_LEN);
+                    options.uri_path[path_len..path_len + value.len()].copy_from_slice(value);
+                    options.uri_path_len = path_len + value.len();
+                }
+                option::CONTENT_FORMAT => {
+                    let format = stream_from_option!(decode_uint(value));
+                    options.content_format = Some(format as u16);
+                }
+                option::BLOCK2 => {
+                    let value = stream_from_option!(decode_uint(value));
+                    options.block2 = Some(stream_from_option!(BlockOption::from_value(value)));
+                }
+                option::BLOCK1 => {
+                    let value = stream_from_option!(decode_uint(value));
+                    options.block1 = Some(stream_from_option!(BlockOption::from_value(value)));
+                }
+                // Understood, but they do not change how a request to one of
+                // our own resources is handled
+                option::URI_HOST | option::URI_PORT | option::URI_QUERY | option::ACCEPT => {}
+                _ => {
+                    if option::is_critical(number) {
+                        options.unknown_critical = true;
+                    }
+                }
+            }
+        }
+        stream_done!(offset, options)
+    }
+}
+
+/// Encodes a complete message: its header, options and payload.
+pub fn encode_message(
+    buf: &mut [u8],
+    header: &CoAPHeader,
+    options: &CoAPOptions,
+    payload: &[u8],
+) -> SResult {
+    let mut offset = enc_consume!(buf; header; encode);
+    offset = enc_consume!(buf, offset; options; encode);
+    if !payload.is_empty() {
+        offset = enc_consume!(buf, offset; encode_u8, PAYLOAD_MARKER);
+        offset = enc_consume!(buf, offset; encode_bytes, payload);
+    }
+    stream_done!(offset)
+}
+
+/// Decodes the 4-bit delta or length of an option, and the extended value
+/// that follows the first byte of the option if it needs one.
+fn decode_extended(buf: &[u8], nibble: u8) -> SResult<u16> {
+    match nibble {
+        13 => {
+            let (offset, value) = dec_try!(buf; decode_u8);
+            stream_done!(offset, value as u16 + 13);
+        }
+        14 => {
+            let (offset, value) = dec_try!(buf; decode_u16);
+            stream_done!(offset, stream_from_option!(value.checked_add(269)));
+        }
+        15 => stream_err!(),
+        _ => stream_done!(0, nibble as u16),
+    }
+}
+
+/// Splits a delta or length into its 4-bit nibble and extended value.
+fn extended(value: u16) -> (u8, [u8; 2], usize) {
+    if value < 13 {
+        (value as u8, [0; 2], 0)
+    } else if value < 269 {
+        (13, [(value - 13) as u8, 0], 1)
+    } else {
+        (14, (value - 269).to_be_bytes(), 2)
+    }
+}
+
+/// Encodes an option after the option numbered `last`, and updates `last`.
+fn encode_option(buf: &mut [u8], last: &mut u16, number: u16, value: &[u8]) -> SResult {
+    let (delta, delta_ext, delta_ext_len) = extended(number - *last);
+    let (len, len_ext, len_ext_len) = extended(value.len() as u16);
+    let mut offset = enc_consume!(buf; encode_u8, delta << 4 | len);
+    offset = enc_consume!(buf, offset; encode_bytes, &delta_ext[..delta_ext_len]);
+    offset = enc_consume!(buf, offset; encode_bytes, &len_ext[..len_ext_len]);
+    offset = enc_consume!(buf, offset; encode_bytes, value);
+    *last = number;
+    stream_done!(offset)
+}
+
+/// Encodes an option with an unsigned integer value, which is sent in as
+/// few bytes as possible.
+fn encode_uint_option(buf: &mut [u8], last: &mut u16, number: u16, value: u32) -> SResult {
+    let bytes = value.to_be_bytes();
+    let skip = (value.leading_zeros() / 8) as usize;
+    encode_option(buf, last, number, &bytes[skip..])
+}
+
+fn decode_uint(value: &[u8]) -> Option<u32> {
+    if value.len() > 4 {
+        return None;
+    }
+    Some(value.iter().fold(0, |uint, byte| uint << 8 | *byte as u32))
+}
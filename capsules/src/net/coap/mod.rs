@@ -0,0 +1,6 @@
+pub mod driver;
+pub mod message;
+
+pub use self::driver::CoAPDriver;
+pub use self::driver::DRIVER_NUM;
+pub use self::message::COAP_PORT;
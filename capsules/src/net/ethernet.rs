@@ -0,0 +1,81 @@
+//! Implements Ethernet II frame header encoding and decoding, as used to
+//! carry IPv6 packets over an `EthernetAdapter`.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u16};
+use crate::net::stream::{encode_bytes, encode_u16};
+
+/// Length of an Ethernet II header, without VLAN tags.
+pub const ETHERNET_HDR_LEN: usize = 14;
+
+/// Largest payload of a standard Ethernet frame.
+pub const ETHERNET_MTU: usize = 1500;
+
+/// EtherType of IPv6 packets.
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
+
+/// A 48 bit Ethernet MAC address, in transmission order.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct EthernetAddress(pub [u8; 6]);
+
+impl EthernetAddress {
+    pub const BROADCAST: EthernetAddress = EthernetAddress([0xff; 6]);
+
+    /// Multicast (and broadcast) addresses have the group bit set.
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+
+    /// Maps an IPv6 multicast address to an Ethernet multicast address, as
+    /// described in section 7 of RFC 2464.
+    pub fn from_ipv6_multicast(addr: &IPAddr) -> EthernetAddress {
+        EthernetAddress([0x33, 0x33, addr.0[12], addr.0[13], addr.0[14], addr.0[15]])
+    }
+}
+
+/// The `EthernetHeader` struct follows the layout of the Ethernet II frame
+/// header. The ethertype is stored in host byte order.
+#[derive(Copy, Clone, Debug)]
+pub struct EthernetHeader {
+    pub dst_addr: EthernetAddress,
+    pub src_addr: EthernetAddress,
+    pub ethertype: u16,
+}
+
+impl EthernetHeader {
+    pub fn new(
+        dst_addr: EthernetAddress,
+        src_addr: EthernetAddress,
+        ethertype: u16,
+    ) -> EthernetHeader {
+        EthernetHeader {
+            dst_addr,
+            src_addr,
+            ethertype,
+        }
+    }
+
+    /// Serializes the header into `buf`, returning the offset of the first
+    /// byte after the header.
+    pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
+        stream_len_cond!(buf, ETHERNET_HDR_LEN);
+
+        let mut off = enc_consume!(buf, 0; encode_bytes, &self.dst_addr.0);
+        off = enc_consume!(buf, off; encode_bytes, &self.src_addr.0);
+        off = enc_consume!(buf, off; encode_u16, self.ethertype);
+        stream_done!(off, off);
+    }
+
+    /// Deserializes the header from the start of `buf`.
+    pub fn decode(buf: &[u8]) -> SResult<EthernetHeader> {
+        stream_len_cond!(buf, ETHERNET_HDR_LEN);
+
+        let mut dst_addr = EthernetAddress::default();
+        let mut src_addr = EthernetAddress::default();
+        let off = dec_consume!(buf, 0; decode_bytes, &mut dst_addr.0);
+        let off = dec_consume!(buf, off; decode_bytes, &mut src_addr.0);
+        let (off, ethertype) = dec_try!(buf, off; decode_u16);
+        stream_done!(off, EthernetHeader::new(dst_addr, src_addr, ethertype));
+    }
+}
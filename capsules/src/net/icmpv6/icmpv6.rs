@@ -23,6 +23,9 @@ pub enum ICMP6HeaderOptions {
     Type3 { unused: u32 },
     Type128 { id: u16, seqno: u16 },
     Type129 { id: u16, seqno: u16 },
+    Type135 { reserved: u32 },
+    Type136 { flags: u32 },
+    Type155 { base: u32 }, // First word of the RPL message base
 }
 
 #[derive(Copy, Clone)]
@@ -31,6 +34,9 @@ pub enum ICMP6Type {
     Type3,   // Time Exceeded
     Type128, // Echo Request
     Type129, // Echo Reply
+    Type135, // Neighbor Solicitation
+    Type136, // Neighbor Advertisement
+    Type155, // RPL Control Message
 }
 
 impl ICMP6Header {
@@ -40,6 +46,9 @@ impl ICMP6Header {
             ICMP6Type::Type3 => ICMP6HeaderOptions::Type3 { unused: 0 },
             ICMP6Type::Type128 => ICMP6HeaderOptions::Type128 { id: 0, seqno: 0 },
             ICMP6Type::Type129 => ICMP6HeaderOptions::Type129 { id: 0, seqno: 0 },
+            ICMP6Type::Type135 => ICMP6HeaderOptions::Type135 { reserved: 0 },
+            ICMP6Type::Type136 => ICMP6HeaderOptions::Type136 { flags: 0 },
+            ICMP6Type::Type155 => ICMP6HeaderOptions::Type155 { base: 0 },
         };
 
         ICMP6Header {
@@ -56,6 +65,9 @@ impl ICMP6Header {
             ICMP6Type::Type3 => self.set_options(ICMP6HeaderOptions::Type3 { unused: 0 }),
             ICMP6Type::Type128 => self.set_options(ICMP6HeaderOptions::Type128 { id: 0, seqno: 0 }),
             ICMP6Type::Type129 => self.set_options(ICMP6HeaderOptions::Type129 { id: 0, seqno: 0 }),
+            ICMP6Type::Type135 => self.set_options(ICMP6HeaderOptions::Type135 { reserved: 0 }),
+            ICMP6Type::Type136 => self.set_options(ICMP6HeaderOptions::Type136 { flags: 0 }),
+            ICMP6Type::Type155 => self.set_options(ICMP6HeaderOptions::Type155 { base: 0 }),
         }
     }
 
@@ -81,6 +93,9 @@ impl ICMP6Header {
             ICMP6HeaderOptions::Type3 { .. } => ICMP6Type::Type3,
             ICMP6HeaderOptions::Type128 { .. } => ICMP6Type::Type128,
             ICMP6HeaderOptions::Type129 { .. } => ICMP6Type::Type129,
+            ICMP6HeaderOptions::Type135 { .. } => ICMP6Type::Type135,
+            ICMP6HeaderOptions::Type136 { .. } => ICMP6Type::Type136,
+            ICMP6HeaderOptions::Type155 { .. } => ICMP6Type::Type155,
         }
     }
 
@@ -90,6 +105,9 @@ impl ICMP6Header {
             ICMP6Type::Type3 => 3,
             ICMP6Type::Type128 => 128,
             ICMP6Type::Type129 => 129,
+            ICMP6Type::Type135 => 135,
+            ICMP6Type::Type136 => 136,
+            ICMP6Type::Type155 => 155,
         }
     }
 
@@ -135,6 +153,15 @@ impl ICMP6Header {
             ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
                 off = enc_consume!(buf, off; encode_u32, unused);
             }
+            ICMP6HeaderOptions::Type135 { reserved } => {
+                off = enc_consume!(buf, off; encode_u32, reserved);
+            }
+            ICMP6HeaderOptions::Type136 { flags } => {
+                off = enc_consume!(buf, off; encode_u32, flags);
+            }
+            ICMP6HeaderOptions::Type155 { base } => {
+                off = enc_consume!(buf, off; encode_u32, base);
+            }
             ICMP6HeaderOptions::Type128 { id, seqno }
             | ICMP6HeaderOptions::Type129 { id, seqno } => {
                 off = enc_consume!(buf, off; encode_u16, id);
@@ -163,6 +190,9 @@ impl ICMP6Header {
             3 => ICMP6Type::Type3,
             128 => ICMP6Type::Type128,
             129 => ICMP6Type::Type129,
+            135 => ICMP6Type::Type135,
+            136 => ICMP6Type::Type136,
+            155 => ICMP6Type::Type155,
             _ => return SResult::Error(()),
         };
 
@@ -171,34 +201,47 @@ impl ICMP6Header {
         let (off, code) = dec_try!(buf, off; decode_u8);
         icmp_header.set_code(code);
         let (off, cksum) = dec_try!(buf, off; decode_u16);
-        icmp_header.set_cksum(u16::from_be(cksum));
+        icmp_header.set_cksum(cksum);
 
-        match icmp_type {
+        let off = match icmp_type {
             ICMP6Type::Type1 => {
-                let (_off, unused) = dec_try!(buf, off; decode_u32);
-                let unused = u32::from_be(unused);
+                let (off, unused) = dec_try!(buf, off; decode_u32);
                 icmp_header.set_options(ICMP6HeaderOptions::Type1 { unused });
+                off
             }
             ICMP6Type::Type3 => {
-                let (_off, unused) = dec_try!(buf, off; decode_u32);
-                let unused = u32::from_be(unused);
+                let (off, unused) = dec_try!(buf, off; decode_u32);
                 icmp_header.set_options(ICMP6HeaderOptions::Type3 { unused });
+                off
             }
             ICMP6Type::Type128 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
-                let id = u16::from_be(id);
-                let (_off, seqno) = dec_try!(buf, off; decode_u16);
-                let seqno = u16::from_be(seqno);
+                let (off, id) = dec_try!(buf, off; decode_u16);
+                let (off, seqno) = dec_try!(buf, off; decode_u16);
                 icmp_header.set_options(ICMP6HeaderOptions::Type128 { id, seqno });
+                off
             }
             ICMP6Type::Type129 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
-                let id = u16::from_be(id);
-                let (_off, seqno) = dec_try!(buf, off; decode_u16);
-                let seqno = u16::from_be(seqno);
+                let (off, id) = dec_try!(buf, off; decode_u16);
+                let (off, seqno) = dec_try!(buf, off; decode_u16);
                 icmp_header.set_options(ICMP6HeaderOptions::Type129 { id, seqno });
+                off
             }
-        }
+            ICMP6Type::Type135 => {
+                let (off, reserved) = dec_try!(buf, off; decode_u32);
+                icmp_header.set_options(ICMP6HeaderOptions::Type135 { reserved });
+                off
+            }
+            ICMP6Type::Type136 => {
+                let (off, flags) = dec_try!(buf, off; decode_u32);
+                icmp_header.set_options(ICMP6HeaderOptions::Type136 { flags });
+                off
+            }
+            ICMP6Type::Type155 => {
+                let (off, base) = dec_try!(buf, off; decode_u32);
+                icmp_header.set_options(ICMP6HeaderOptions::Type155 { base });
+                off
+            }
+        };
 
         stream_done!(off, icmp_header);
     }
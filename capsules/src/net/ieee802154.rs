@@ -81,6 +81,21 @@ impl FrameType {
     }
 }
 
+/// IEEE 802.15.4-2015, 7.5.1, MAC command identifiers, which are the first
+/// byte of the payload of MAC command frames.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum CommandId {
+    AssociationRequest = 0x01,
+    AssociationResponse = 0x02,
+    DisassociationNotification = 0x03,
+    DataRequest = 0x04,
+    PanIdConflictNotification = 0x05,
+    OrphanNotification = 0x06,
+    BeaconRequest = 0x07,
+    CoordinatorRealignment = 0x08,
+}
+
 #[repr(u16)]
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum FrameVersion {
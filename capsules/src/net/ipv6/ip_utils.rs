@@ -5,7 +5,9 @@
 use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions};
 use crate::net::ieee802154::MacAddress;
 use crate::net::ipv6::IP6Header;
+use crate::net::tcp::{TCPHeader, TCP_HDR_LEN};
 use crate::net::udp::UDPHeader;
+use core::cmp;
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum MacAddr {
@@ -74,6 +76,25 @@ impl IPAddr {
         ip_addr
     }
 
+    /// Returns the 15.4 MAC address a link local address was generated from
+    /// with `generate_from_mac`, or `None` if this is not a link local
+    /// address.
+    pub fn link_local_mac(&self) -> Option<MacAddress> {
+        if !self.is_unicast_link_local() {
+            return None;
+        }
+        if self.0[8..14] == [0x00, 0x00, 0x00, 0xff, 0xfe, 0x00] {
+            Some(MacAddress::Short(
+                (self.0[14] as u16) << 8 | self.0[15] as u16,
+            ))
+        } else {
+            let mut long_addr = [0; 8];
+            long_addr.copy_from_slice(&self.0[8..16]);
+            long_addr[0] ^= 0b00000010;
+            Some(MacAddress::Long(long_addr))
+        }
+    }
+
     pub fn is_unspecified(&self) -> bool {
         self.0.iter().all(|&b| b == 0)
     }
@@ -201,6 +222,12 @@ pub fn compute_icmp_checksum(
             sum += unused >> 16; // upper 16 bits
             sum += unused & 0xffff; // lower 16 bits
         }
+        ICMP6HeaderOptions::Type135 { reserved: word }
+        | ICMP6HeaderOptions::Type136 { flags: word }
+        | ICMP6HeaderOptions::Type155 { base: word } => {
+            sum += word >> 16; // upper 16 bits
+            sum += word & 0xffff; // lower 16 bits
+        }
         ICMP6HeaderOptions::Type128 { id, seqno } | ICMP6HeaderOptions::Type129 { id, seqno } => {
             sum += id as u32;
             sum += seqno as u32;
@@ -215,7 +242,7 @@ pub fn compute_icmp_checksum(
     while sum > 0xffff {
         let sum_upper = sum >> 16;
         let sum_lower = sum & 0xffff;
-        sum += sum_upper + sum_lower;
+        sum = sum_upper + sum_lower;
     }
 
     sum = !sum;
@@ -224,6 +251,65 @@ pub fn compute_icmp_checksum(
     sum as u16
 }
 
+/// Computes the checksum of a TCP segment. `tcp_length` is the length of the
+/// whole segment, and `data` holds everything after the first `TCP_HDR_LEN`
+/// bytes of the header: any options, followed by the payload. The checksum
+/// field of `tcp_header` is included in the sum, so this returns 0 for a
+/// received segment with a valid checksum. The result is in host byte order.
+pub fn compute_tcp_checksum(
+    ip6_header: &IP6Header,
+    tcp_header: &TCPHeader,
+    tcp_length: u16,
+    data: &[u8],
+) -> u16 {
+    let mut sum: u32 = 0;
+
+    // The pseudo-header holds both addresses, the upper-layer packet length
+    // and the next header value
+    let mut i = 0;
+    while i < 16 {
+        sum += (ip6_header.src_addr.0[i] as u32) << 8 | ip6_header.src_addr.0[i + 1] as u32;
+        sum += (ip6_header.dst_addr.0[i] as u32) << 8 | ip6_header.dst_addr.0[i + 1] as u32;
+        i += 2;
+    }
+    sum += tcp_length as u32;
+    sum += ip6_nh::TCP as u32;
+
+    sum += tcp_header.src_port as u32;
+    sum += tcp_header.dst_port as u32;
+    sum += tcp_header.seq_num >> 16;
+    sum += tcp_header.seq_num & 0xffff;
+    sum += tcp_header.ack_num >> 16;
+    sum += tcp_header.ack_num & 0xffff;
+    sum += tcp_header.offset_and_control as u32;
+    sum += tcp_header.window as u32;
+    sum += tcp_header.cksum as u32;
+    sum += tcp_header.urg_ptr as u32;
+
+    // An odd final byte is padded with a zero byte
+    let data_len = cmp::min(
+        data.len(),
+        (tcp_length as usize).saturating_sub(TCP_HDR_LEN),
+    );
+    let mut i = 0;
+    while i < data_len {
+        let msb = (data[i] as u32) << 8;
+        let lsb = if i + 1 < data_len {
+            data[i + 1] as u32
+        } else {
+            0
+        };
+        sum += msb + lsb;
+        i += 2;
+    }
+
+    while sum > 0xffff {
+        sum = (sum >> 16) + (sum & 0xffff);
+    }
+
+    !sum as u16
+}
+
 pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
     let mut sum: u32 = 0;
 
@@ -241,7 +327,7 @@ pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
         i += 2;
     }
 
-    sum += ip6_header.payload_len as u32;
+    sum += ip6_header.get_payload_len() as u32;
     sum += ip6_header.next_header as u32;
 
     sum
@@ -253,7 +339,12 @@ pub fn compute_sum(buf: &[u8], len: u16) -> u32 {
     let mut i: usize = 0;
     while i < (len as usize) {
         let msb = (buf[i] as u32) << 8;
-        let lsb = buf[i + 1] as u32;
+        // An odd final byte is padded with zero
+        let lsb = if i + 1 < len as usize {
+            buf[i + 1] as u32
+        } else {
+            0
+        };
         sum += msb + lsb;
         i += 2;
     }
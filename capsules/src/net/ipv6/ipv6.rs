@@ -67,11 +67,13 @@
 // (as required by 6LoWPAN) difficult.
 
 use crate::net::icmpv6::ICMP6Header;
-use crate::net::ipv6::ip_utils::{compute_icmp_checksum, compute_udp_checksum, ip6_nh, IPAddr};
+use crate::net::ipv6::ip_utils::{
+    compute_icmp_checksum, compute_tcp_checksum, compute_udp_checksum, ip6_nh, IPAddr,
+};
 use crate::net::stream::SResult;
 use crate::net::stream::{decode_bytes, decode_u16, decode_u8};
 use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
-use crate::net::tcp::TCPHeader;
+use crate::net::tcp::{TCPHeader, TCP_HDR_LEN};
 use crate::net::udp::UDPHeader;
 use kernel::common::leasable_buffer::LeasableBuffer;
 use kernel::ReturnCode;
@@ -280,18 +282,29 @@ impl IP6Header {
                 }
                 ReturnCode::SUCCESS
             }
+            ip6_nh::TCP => {
+                let checksum = match TCPHeader::decode(buf).done() {
+                    Some((_offset, hdr)) => {
+                        compute_tcp_checksum(&self, &hdr, buf.len() as u16, &buf[TCP_HDR_LEN..])
+                    }
+                    None => 0xffff, //Will be dropped, as ones comp -0 checksum is invalid
+                };
+                if checksum != 0 {
+                    return ReturnCode::FAIL; //Incorrect cksum
+                }
+                ReturnCode::SUCCESS
+            }
             ip6_nh::ICMP => {
-                // Untested (10/5/18)
-                let mut icmp_header: [u8; ICMP_HDR_LEN] = [0; ICMP_HDR_LEN];
-                icmp_header.copy_from_slice(&buf[..ICMP_HDR_LEN]);
-                let checksum = match ICMP6Header::decode(&icmp_header).done() {
+                // The computed checksum does not include the checksum field,
+                // so it is compared to the received one
+                let valid = match ICMP6Header::decode(buf).done() {
                     Some((_offset, mut hdr)) => {
                         hdr.set_len(buf.len() as u16);
-                        u16::from_be(compute_icmp_checksum(&self, &hdr, &buf[ICMP_HDR_LEN..]))
+                        compute_icmp_checksum(&self, &hdr, &buf[ICMP_HDR_LEN..]) == hdr.get_cksum()
                     }
-                    None => 0xffff, //Will be dropped, as ones comp -0 checksum is invalid
+                    None => false,
                 };
-                if checksum != 0 {
+                if !valid {
                     return ReturnCode::FAIL; //Incorrect cksum
                 }
                 ReturnCode::SUCCESS
@@ -363,13 +376,18 @@ impl<'a> IPPayload<'a> {
                 self.header = transport_header;
                 (ip6_nh::UDP, length)
             }
+            TransportHeader::TCP(mut tcp_header) => {
+                let length = (payload.len() + tcp_header.get_hdr_size()) as u16;
+                tcp_header.set_len(length);
+                self.header = TransportHeader::TCP(tcp_header);
+                (ip6_nh::TCP, length)
+            }
             TransportHeader::ICMP(mut icmp_header) => {
                 let length = (payload.len() + icmp_header.get_hdr_size()) as u16;
                 icmp_header.set_len(length);
                 self.header = transport_header;
                 (ip6_nh::ICMP, length)
             }
-            _ => (ip6_nh::NO_NEXT, payload.len() as u16),
         }
     }
 
@@ -387,10 +405,8 @@ impl<'a> IPPayload<'a> {
     pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
         let (offset, _) = match self.header {
             TransportHeader::UDP(udp_header) => udp_header.encode(buf, offset).done().unwrap(),
+            TransportHeader::TCP(tcp_header) => tcp_header.encode(buf, offset).done().unwrap(),
             TransportHeader::ICMP(icmp_header) => icmp_header.encode(buf, offset).done().unwrap(),
-            _ => {
-                unimplemented!();
-            }
         };
         let payload_length = self.get_payload_length();
         let offset = enc_consume!(buf, offset; encode_bytes, &self.payload[..payload_length]);
@@ -402,12 +418,12 @@ impl<'a> IPPayload<'a> {
             TransportHeader::UDP(udp_header) => {
                 udp_header.get_len() as usize - udp_header.get_hdr_size()
             }
+            TransportHeader::TCP(tcp_header) => {
+                tcp_header.get_len() as usize - tcp_header.get_hdr_size()
+            }
             TransportHeader::ICMP(icmp_header) => {
                 icmp_header.get_len() as usize - icmp_header.get_hdr_size()
             }
-            _ => {
-                unimplemented!();
-            }
         }
     }
 }
@@ -453,8 +469,8 @@ impl<'a> IP6Packet<'a> {
     pub fn get_total_hdr_size(&self) -> usize {
         let transport_hdr_size = match self.payload.header {
             TransportHeader::UDP(udp_hdr) => udp_hdr.get_hdr_size(),
+            TransportHeader::TCP(tcp_header) => tcp_header.get_hdr_size(),
             TransportHeader::ICMP(icmp_header) => icmp_header.get_hdr_size(),
-            _ => unimplemented!(),
         };
         40 + transport_hdr_size
     }
@@ -476,13 +492,20 @@ impl<'a> IP6Packet<'a> {
                 );
                 udp_header.set_cksum(cksum);
             }
+            TransportHeader::TCP(ref mut tcp_header) => {
+                tcp_header.set_cksum(0);
+                let cksum = compute_tcp_checksum(
+                    &self.header,
+                    &tcp_header,
+                    tcp_header.get_len(),
+                    self.payload.payload,
+                );
+                tcp_header.set_cksum(cksum);
+            }
             TransportHeader::ICMP(ref mut icmp_header) => {
                 let cksum = compute_icmp_checksum(&self.header, &icmp_header, self.payload.payload);
                 icmp_header.set_cksum(cksum);
             }
-            _ => {
-                unimplemented!();
-            }
         }
     }
 
@@ -510,7 +533,55 @@ impl<'a> IP6Packet<'a> {
         self.header.set_payload_len(payload_len);
     }
 
-    // TODO: Do we need a decode equivalent? I don't think so, but we might
+    /// This function sets the header and payload of the `IP6Packet` to those
+    /// of a received packet, so that it can be forwarded unchanged (apart
+    /// from the header fields the caller modifies before or after). The
+    /// transport header is decoded from `buf` and the remainder of `buf` is
+    /// copied into the transport payload; TCP options are kept as part of
+    /// the payload. The transport checksum is left as received.
+    ///
+    /// # Arguments
+    ///
+    /// `header` - The `IP6Header` of the received packet
+    /// `buf` - The received packet after the IPv6 header
+    ///
+    /// # Return Value
+    ///
+    /// `ENOSUPPORT` if the packet carries extension headers or a transport
+    /// header that cannot be decoded, `ESIZE` if it does not fit in the
+    /// payload buffer, and `SUCCESS` otherwise.
+    pub fn set_received_payload(&mut self, header: IP6Header, buf: &[u8]) -> ReturnCode {
+        let (transport_header, hdr_len) = match header.get_next_header() {
+            ip6_nh::UDP => match UDPHeader::decode(buf).done() {
+                Some((_offset, mut udp_header)) => {
+                    udp_header.set_len(buf.len() as u16);
+                    (TransportHeader::UDP(udp_header), UDP_HDR_LEN)
+                }
+                None => return ReturnCode::ENOSUPPORT,
+            },
+            ip6_nh::TCP => match TCPHeader::decode(buf).done() {
+                Some((_offset, tcp_header)) => (TransportHeader::TCP(tcp_header), TCP_HDR_LEN),
+                None => return ReturnCode::ENOSUPPORT,
+            },
+            ip6_nh::ICMP => match ICMP6Header::decode(buf).done() {
+                Some((_offset, mut icmp_header)) => {
+                    icmp_header.set_len(buf.len() as u16);
+                    (TransportHeader::ICMP(icmp_header), ICMP_HDR_LEN)
+                }
+                None => return ReturnCode::ENOSUPPORT,
+            },
+            _ => return ReturnCode::ENOSUPPORT,
+        };
+        let payload_len = buf.len() - hdr_len;
+        if payload_len > self.payload.payload.len() {
+            return ReturnCode::ESIZE;
+        }
+        self.payload.payload[..payload_len].copy_from_slice(&buf[hdr_len..]);
+        self.payload.header = transport_header;
+        self.header = header;
+        self.header.set_payload_len(buf.len() as u16);
+        ReturnCode::SUCCESS
+    }
 
     pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
         let ip6_header = self.header;
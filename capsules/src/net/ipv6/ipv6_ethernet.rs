@@ -0,0 +1,456 @@
+//! This file contains an implementation of the `IP6Sender` trait that sends
+//! IPv6 packets over Ethernet (RFC 2464), using an `EthernetAdapter`. It is
+//! also the client of that adapter, and passes the IPv6 packets it receives
+//! to an `IP6RecvStruct`, so that the UDP layer can be used over Ethernet in
+//! the same way as over 6LoWPAN.
+//!
+//! The link-layer address of the destination of a packet is resolved with
+//! Neighbor Discovery (see `ndp.rs`): if it is not in the neighbor cache, a
+//! Neighbor Solicitation is sent to its solicited-node multicast address, and
+//! the packet is held until a Neighbor Advertisement arrives. Neighbor
+//! Solicitations for any address in the interface list are answered.
+//!
+//! All destinations are assumed to be on-link, as there is no router
+//! discovery. Only one packet and one frame are handled at a time; the
+//! `send_done` callback is issued once the adapter has transmitted the frame
+//! carrying the packet.
+
+use crate::net::ethernet::{EthernetAddress, EthernetHeader, ETHERNET_HDR_LEN, ETHERTYPE_IPV6};
+use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::{compute_icmp_checksum, ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6_recv::IP6RecvStruct;
+use crate::net::ipv6::ipv6_send::{IP6LinkSecurity, IP6Router, IP6SendClient, IP6Sender};
+use crate::net::ipv6::ndp::{self, ndp_option, NeighborCache};
+use crate::net::ipv6::{IP6Header, IP6Packet, TransportHeader, ICMP_HDR_LEN};
+use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::debug;
+use kernel::hil::ethernet::{EthernetAdapter, EthernetAdapterClient};
+use kernel::hil::time;
+use kernel::ReturnCode;
+
+const IP6_HDR_LEN: usize = 40;
+
+/// Length of the frames carrying Neighbor Solicitations and Advertisements.
+const NDP_FRAME_LEN: usize = ETHERNET_HDR_LEN + IP6_HDR_LEN + ICMP_HDR_LEN + ndp::NDP_BODY_LEN;
+
+#[derive(Copy, Clone, PartialEq)]
+enum PacketState {
+    /// No packet is being sent.
+    Idle,
+    /// Waiting for a Neighbor Advertisement from the destination.
+    Resolving,
+    /// The link-layer address of the destination is known, and the packet
+    /// is waiting for the adapter.
+    Ready(EthernetAddress),
+    /// The frame carrying the packet is being transmitted.
+    Transmitting,
+}
+
+/// A Neighbor Advertisement to send in reply to a Neighbor Solicitation.
+#[derive(Copy, Clone)]
+struct Advertisement {
+    dst: IPAddr,
+    dst_mac: EthernetAddress,
+    target: IPAddr,
+}
+
+pub struct IP6EthernetStruct<'a, A: time::Alarm<'a>> {
+    ip6_packet: TakeCell<'static, IP6Packet<'static>>,
+    alarm: &'a A, // Alarm used to retransmit Neighbor Solicitations
+    adapter: &'a dyn EthernetAdapter<'a>,
+    tx_buf: TakeCell<'static, [u8]>,
+    mac_addr: EthernetAddress,
+    src_addr: Cell<IPAddr>,
+    interface_list: &'static [IPAddr],
+    ip_receive: &'a IP6RecvStruct<'a>,
+    neighbors: NeighborCache,
+    state: Cell<PacketState>,
+    dst_addr: Cell<IPAddr>,
+    solicit_pending: Cell<bool>,
+    solicits_sent: Cell<u8>,
+    advert_pending: OptionalCell<Advertisement>,
+    client: OptionalCell<&'a dyn IP6SendClient>,
+    ip_vis: &'static IpVisibilityCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6EthernetStruct<'a, A> {
+    fn set_client(&self, client: &'a dyn IP6SendClient) {
+        self.client.set(client);
+    }
+
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.src_addr.set(src_addr);
+    }
+
+    /// The next hop of a packet over Ethernet is resolved with Neighbor
+    /// Discovery, so the 802.15.4 gateway address is ignored.
+    fn set_gateway(&self, _gateway: MacAddress) {}
+
+    /// Packets sent over Ethernet always go to the on-link destination, so
+    /// the router is ignored.
+    fn set_router(&self, _router: &'a dyn IP6Router) {}
+
+    /// Ethernet frames are not secured, so the link security is ignored.
+    fn set_link_security(&self, _link_security: &'a dyn IP6LinkSecurity) {}
+
+    fn set_header(&mut self, ip6_header: IP6Header) {
+        self.ip6_packet
+            .map(|ip6_packet| ip6_packet.header = ip6_header);
+    }
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> ReturnCode {
+        if !net_cap.remote_addr_valid(dst, self.ip_vis) {
+            return ReturnCode::FAIL;
+        }
+        if self.state.get() != PacketState::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.init_packet(dst, transport_header, payload);
+
+        if dst.is_multicast() {
+            self.state
+                .set(PacketState::Ready(EthernetAddress::from_ipv6_multicast(
+                    &dst,
+                )));
+        } else {
+            match self.neighbors.lookup(&dst) {
+                Some(mac) => self.state.set(PacketState::Ready(mac)),
+                None => {
+                    self.dst_addr.set(dst);
+                    self.state.set(PacketState::Resolving);
+                    self.solicit_pending.set(true);
+                    self.solicits_sent.set(1);
+                    self.alarm
+                        .set_alarm(self.alarm.now(), A::ticks_from_ms(ndp::RETRANS_TIMER_MS));
+                }
+            }
+        }
+        self.send_next();
+        ReturnCode::SUCCESS
+    }
+
+    /// Forwarding between links is not supported over Ethernet.
+    fn forward(&self, _header: IP6Header, _payload: &[u8]) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6EthernetStruct<'a, A> {
+    pub fn new(
+        ip6_packet: &'static mut IP6Packet<'static>,
+        alarm: &'a A,
+        adapter: &'a dyn EthernetAdapter<'a>,
+        tx_buf: &'static mut [u8],
+        mac_addr: EthernetAddress,
+        interface_list: &'static [IPAddr],
+        ip_receive: &'a IP6RecvStruct<'a>,
+        ip_vis: &'static IpVisibilityCapability,
+    ) -> IP6EthernetStruct<'a, A> {
+        IP6EthernetStruct {
+            ip6_packet: TakeCell::new(ip6_packet),
+            alarm,
+            adapter,
+            tx_buf: TakeCell::new(tx_buf),
+            mac_addr,
+            src_addr: Cell::new(IPAddr::new()),
+            interface_list,
+            ip_receive,
+            neighbors: NeighborCache::new(),
+            state: Cell::new(PacketState::Idle),
+            dst_addr: Cell::new(IPAddr::new()),
+            solicit_pending: Cell::new(false),
+            solicits_sent: Cell::new(0),
+            advert_pending: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+            ip_vis,
+        }
+    }
+
+    fn init_packet(
+        &self,
+        dst_addr: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+    ) {
+        self.ip6_packet.map_or_else(
+            || {
+                debug!("init packet failed.");
+            },
+            |ip6_packet| {
+                ip6_packet.header = IP6Header::default();
+                ip6_packet.header.src_addr = self.src_addr.get();
+                ip6_packet.header.dst_addr = dst_addr;
+                ip6_packet.set_payload(transport_header, payload);
+                ip6_packet.set_transport_checksum();
+            },
+        );
+    }
+
+    /// Transmit the next pending frame, if the adapter is not busy. Replies
+    /// to solicitations go first, then solicitations, then the packet.
+    fn send_next(&self) {
+        let tx_buf = match self.tx_buf.take() {
+            Some(tx_buf) => tx_buf,
+            None => return,
+        };
+
+        let mut is_packet = false;
+        let frame_len = if let Some(advert) = self.advert_pending.take() {
+            self.encode_ndp(
+                tx_buf,
+                advert.dst_mac,
+                advert.target,
+                advert.dst,
+                ICMP6HeaderOptions::Type136 {
+                    flags: ndp::na_flags::SOLICITED | ndp::na_flags::OVERRIDE,
+                },
+                ndp_option::TARGET_LL_ADDR,
+            )
+        } else if self.solicit_pending.get() {
+            self.solicit_pending.set(false);
+            let dst_addr = self.dst_addr.get();
+            let mcast = ndp::solicited_node_multicast(&dst_addr);
+            self.encode_ndp(
+                tx_buf,
+                EthernetAddress::from_ipv6_multicast(&mcast),
+                self.src_addr.get(),
+                mcast,
+                ICMP6HeaderOptions::Type135 { reserved: 0 },
+                ndp_option::SOURCE_LL_ADDR,
+            )
+        } else if let PacketState::Ready(dst_mac) = self.state.get() {
+            is_packet = true;
+            self.encode_packet(tx_buf, dst_mac)
+        } else {
+            self.tx_buf.replace(tx_buf);
+            return;
+        };
+
+        let result = match frame_len {
+            Some(len) => match self.adapter.transmit(tx_buf, len) {
+                Ok(()) => ReturnCode::SUCCESS,
+                Err((result, tx_buf)) => {
+                    self.tx_buf.replace(tx_buf);
+                    result
+                }
+            },
+            None => {
+                self.tx_buf.replace(tx_buf);
+                ReturnCode::ESIZE
+            }
+        };
+        if is_packet {
+            if result == ReturnCode::SUCCESS {
+                self.state.set(PacketState::Transmitting);
+            } else {
+                self.send_completed(result);
+            }
+        }
+        // Neighbor Discovery messages that could not be sent are dropped;
+        // solicitations are retried when the alarm fires.
+    }
+
+    fn send_completed(&self, result: ReturnCode) {
+        self.state.set(PacketState::Idle);
+        self.client.map(move |client| {
+            client.send_done(result);
+        });
+    }
+
+    /// Writes the frame carrying the packet to `buf`, returning its length.
+    fn encode_packet(&self, buf: &mut [u8], dst_mac: EthernetAddress) -> Option<usize> {
+        let eth_header = EthernetHeader::new(dst_mac, self.mac_addr, ETHERTYPE_IPV6);
+        let (off, _) = eth_header.encode(buf).done()?;
+        self.ip6_packet.map_or(None, |ip6_packet| {
+            let len = off + ip6_packet.get_total_len() as usize;
+            if len > buf.len() {
+                return None;
+            }
+            ip6_packet.encode(&mut buf[off..]).done()?;
+            Some(len)
+        })
+    }
+
+    /// Writes a frame carrying a Neighbor Solicitation or Advertisement to
+    /// `buf`, returning its length. `options` selects the message type, and
+    /// the link-layer address option of type `option_type` carries the
+    /// address of this interface.
+    fn encode_ndp(
+        &self,
+        buf: &mut [u8],
+        dst_mac: EthernetAddress,
+        src: IPAddr,
+        dst: IPAddr,
+        options: ICMP6HeaderOptions,
+        option_type: u8,
+    ) -> Option<usize> {
+        if buf.len() < NDP_FRAME_LEN {
+            return None;
+        }
+
+        // The target of a solicitation is the address being resolved, and
+        // that of an advertisement is the address it is sent from.
+        let target = match options {
+            ICMP6HeaderOptions::Type135 { .. } => self.dst_addr.get(),
+            _ => src,
+        };
+        let mut body = [0; ndp::NDP_BODY_LEN];
+        ndp::encode_body(&mut body, &target, option_type, &self.mac_addr);
+
+        let mut ip6_header = IP6Header::new();
+        ip6_header.src_addr = src;
+        ip6_header.dst_addr = dst;
+        ip6_header.set_next_header(ip6_nh::ICMP);
+        ip6_header.set_payload_len((ICMP_HDR_LEN + ndp::NDP_BODY_LEN) as u16);
+        ip6_header.set_hop_limit(ndp::NDP_HOP_LIMIT);
+
+        let mut icmp_header = ICMP6Header::new(ICMP6Type::Type135);
+        icmp_header.set_options(options);
+        icmp_header.set_len((ICMP_HDR_LEN + ndp::NDP_BODY_LEN) as u16);
+        icmp_header.set_cksum(compute_icmp_checksum(&ip6_header, &icmp_header, &body));
+
+        let eth_header = EthernetHeader::new(dst_mac, self.mac_addr, ETHERTYPE_IPV6);
+        let (off, _) = eth_header.encode(buf).done()?;
+        let (len, _) = ip6_header.encode(&mut buf[off..]).done()?;
+        let (off, _) = icmp_header.encode(buf, off + len).done()?;
+        buf[off..off + ndp::NDP_BODY_LEN].copy_from_slice(&body);
+        Some(NDP_FRAME_LEN)
+    }
+
+    /// Handles a received Neighbor Discovery message. Returns false if the
+    /// ICMPv6 message in `icmp` is not a Neighbor Solicitation or
+    /// Advertisement, in which case it is passed on like any other packet.
+    fn receive_ndp(
+        &self,
+        ip6_header: &IP6Header,
+        icmp: &[u8],
+        eth_header: &EthernetHeader,
+    ) -> bool {
+        let icmp_header = match ICMP6Header::decode(icmp).done() {
+            Some((_, icmp_header)) => icmp_header,
+            None => return false,
+        };
+        let option_type = match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type135 { .. } => ndp_option::SOURCE_LL_ADDR,
+            ICMP6HeaderOptions::Type136 { .. } => ndp_option::TARGET_LL_ADDR,
+            _ => return false,
+        };
+
+        // Validate the message as required by section 7.1 of RFC 4861
+        if ip6_header.get_hop_limit() != ndp::NDP_HOP_LIMIT
+            || icmp_header.get_code() != 0
+            || ip6_header.check_transport_checksum(icmp) != ReturnCode::SUCCESS
+        {
+            return true;
+        }
+        let (target, ll_addr) = match ndp::decode_body(&icmp[ICMP_HDR_LEN..], option_type) {
+            Some(body) => body,
+            None => return true,
+        };
+
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type135 { .. } => {
+                let src = ip6_header.get_src_addr();
+                // Solicitations from the unspecified address are part of
+                // duplicate address detection, which is not supported.
+                if !self.interface_list.contains(&target) || src.is_unspecified() {
+                    return true;
+                }
+                let src_mac = ll_addr.unwrap_or(eth_header.src_addr);
+                self.neighbors.update(src, src_mac);
+                self.neighbor_resolved(src, src_mac);
+                self.advert_pending.set(Advertisement {
+                    dst: src,
+                    dst_mac: src_mac,
+                    target,
+                });
+            }
+            _ => {
+                let target_mac = ll_addr.unwrap_or(eth_header.src_addr);
+                self.neighbors.update(target, target_mac);
+                self.neighbor_resolved(target, target_mac);
+            }
+        }
+        self.send_next();
+        true
+    }
+
+    /// Sends the packet waiting for `addr` to be resolved, if any.
+    fn neighbor_resolved(&self, addr: IPAddr, mac: EthernetAddress) {
+        if self.state.get() == PacketState::Resolving && self.dst_addr.get() == addr {
+            let _ = self.alarm.disarm();
+            self.solicit_pending.set(false);
+            self.state.set(PacketState::Ready(mac));
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for IP6EthernetStruct<'a, A> {
+    fn alarm(&self) {
+        if self.state.get() != PacketState::Resolving {
+            return;
+        }
+        if self.solicits_sent.get() >= ndp::MAX_MULTICAST_SOLICIT {
+            // The destination did not answer
+            self.solicit_pending.set(false);
+            self.send_completed(ReturnCode::ENOACK);
+        } else {
+            self.solicits_sent.set(self.solicits_sent.get() + 1);
+            self.solicit_pending.set(true);
+            self.alarm
+                .set_alarm(self.alarm.now(), A::ticks_from_ms(ndp::RETRANS_TIMER_MS));
+            self.send_next();
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> EthernetAdapterClient for IP6EthernetStruct<'a, A> {
+    fn tx_done(&self, result: ReturnCode, packet: &'static mut [u8], _len: usize) {
+        self.tx_buf.replace(packet);
+        if self.state.get() == PacketState::Transmitting {
+            self.send_completed(result);
+        }
+        self.send_next();
+    }
+
+    fn rx_packet(&self, packet: &[u8]) {
+        let (off, eth_header) = match EthernetHeader::decode(packet).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        if eth_header.ethertype != ETHERTYPE_IPV6
+            || (eth_header.dst_addr != self.mac_addr && !eth_header.dst_addr.is_multicast())
+        {
+            return;
+        }
+
+        let ip6_packet = &packet[off..];
+        let ip6_header = match IP6Header::decode(ip6_packet).done() {
+            Some((_, ip6_header)) => ip6_header,
+            None => return,
+        };
+        // Short frames are padded, so the packet may not fill the payload
+        let len = ip6_header.get_total_len() as usize;
+        if len > ip6_packet.len() {
+            return;
+        }
+        let ip6_packet = &ip6_packet[..len];
+
+        if ip6_header.get_next_header() == ip6_nh::ICMP
+            && self.receive_ndp(&ip6_header, &ip6_packet[IP6_HDR_LEN..], &eth_header)
+        {
+            return;
+        }
+        self.ip_receive.receive_packet(ip6_packet);
+    }
+}
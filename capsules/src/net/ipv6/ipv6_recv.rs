@@ -1,3 +1,4 @@
+use crate::net::ipv6::ip_utils::ip6_nh;
 use crate::net::ipv6::IP6Header;
 use crate::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
 use kernel::common::cells::OptionalCell;
@@ -25,57 +26,113 @@ use kernel::ReturnCode;
   udp_recv, a `UDPReceive` struct.
 - The UDPReceive struct is a field of the UDPDriver, which ultimately passes the
   packets up to userland.
+- TCP segments are instead passed to the tcp client of the `ip_receive` struct,
+  which is the TCPDriver, and ICMPv6 messages to its icmp client (the RPL
+  router), if one is set.
+- Before any of these, packets are offered to the forwarder of the
+  `ip_receive` struct (also the RPL router), which sends packets that are not
+  addressed to this node on towards their destination.
+- Over Ethernet, the `IP6EthernetStruct` receives frames from the
+  `EthernetAdapter` and passes the IPv6 packets they carry to `ip_receive`
+  directly.
 */
 
 pub trait IP6RecvClient {
     fn receive(&self, header: IP6Header, payload: &[u8]);
 }
 
+/// This trait is implemented by routing protocols that forward packets which
+/// are not addressed to this node.
+pub trait IP6Forwarder {
+    /// Called with every received packet before it is passed to a client.
+    /// Returns `true` if the packet is not addressed to this node, in which
+    /// case it is forwarded or dropped by the forwarder, and is not passed
+    /// to any client.
+    fn forward(&self, header: IP6Header, payload: &[u8]) -> bool;
+}
+
 /// Currently only one implementation of this trait should exist,
 /// as we do not multiplex received packets based on the address.
 /// The receiver receives IP packets destined for any local address.
 /// The receiver should drop any packets with destination addresses
 /// that are not among the local addresses of this device.
 pub trait IP6Receiver<'a> {
+    /// Set the client that receives all packets other than TCP segments.
     fn set_client(&self, client: &'a dyn IP6RecvClient);
+
+    /// Set the client that receives TCP segments. If no TCP client is set,
+    /// TCP segments are passed to the client set with `set_client`.
+    fn set_tcp_client(&self, client: &'a dyn IP6RecvClient);
+
+    /// Set the client that receives ICMPv6 messages. If no ICMPv6 client is
+    /// set, they are passed to the client set with `set_client`.
+    fn set_icmp_client(&self, client: &'a dyn IP6RecvClient);
+
+    /// Set the forwarder that is offered every packet before the clients.
+    fn set_forwarder(&self, forwarder: &'a dyn IP6Forwarder);
 }
 
 pub struct IP6RecvStruct<'a> {
     client: OptionalCell<&'a dyn IP6RecvClient>,
+    tcp_client: OptionalCell<&'a dyn IP6RecvClient>,
+    icmp_client: OptionalCell<&'a dyn IP6RecvClient>,
+    forwarder: OptionalCell<&'a dyn IP6Forwarder>,
 }
 
 impl<'a> IP6Receiver<'a> for IP6RecvStruct<'a> {
     fn set_client(&self, client: &'a dyn IP6RecvClient) {
         self.client.set(client);
     }
+
+    fn set_tcp_client(&self, client: &'a dyn IP6RecvClient) {
+        self.tcp_client.set(client);
+    }
+
+    fn set_icmp_client(&self, client: &'a dyn IP6RecvClient) {
+        self.icmp_client.set(client);
+    }
+
+    fn set_forwarder(&self, forwarder: &'a dyn IP6Forwarder) {
+        self.forwarder.set(forwarder);
+    }
 }
 
 impl<'a> IP6RecvStruct<'a> {
     pub fn new() -> IP6RecvStruct<'a> {
         IP6RecvStruct {
             client: OptionalCell::empty(),
+            tcp_client: OptionalCell::empty(),
+            icmp_client: OptionalCell::empty(),
+            forwarder: OptionalCell::empty(),
         }
     }
-}
 
-impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
-    fn receive(&self, buf: &[u8], len: usize, result: ReturnCode) {
-        // TODO: Drop here?
-        if len > buf.len() || result != ReturnCode::SUCCESS {
-            return;
-        }
+    /// Pass a received IPv6 packet, which fills all of `buf`, to the
+    /// clients. This is called by the link layer the packet was received on.
+    pub fn receive_packet(&self, buf: &[u8]) {
         match IP6Header::decode(buf).done() {
             Some((offset, ip6_header)) => {
-                let checksum_result = ip6_header.check_transport_checksum(&buf[offset..len]);
+                let checksum_result = ip6_header.check_transport_checksum(&buf[offset..]);
                 if checksum_result == ReturnCode::FAIL {
                     debug!("cksum fail!: {:?}", checksum_result);
                     return; //Dropped.
                 }
-                // Note: Protocols for which checksum verification is not implemented (TCP, etc.)
+                // Note: Protocols for which checksum verification is not implemented
                 // are automatically assumed as fine, rather than dropped
 
-                self.client
-                    .map(|client| client.receive(ip6_header, &buf[offset..len]));
+                let forwarded = self.forwarder.map_or(false, |forwarder| {
+                    forwarder.forward(ip6_header, &buf[offset..])
+                });
+                if forwarded {
+                    return;
+                }
+
+                let client = match ip6_header.get_next_header() {
+                    ip6_nh::TCP if self.tcp_client.is_some() => &self.tcp_client,
+                    ip6_nh::ICMP if self.icmp_client.is_some() => &self.icmp_client,
+                    _ => &self.client,
+                };
+                client.map(|client| client.receive(ip6_header, &buf[offset..]));
             }
             None => {
                 debug!("failed to decode ipv6 header");
@@ -84,3 +141,13 @@ impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
         }
     }
 }
+
+impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
+    fn receive(&self, buf: &[u8], len: usize, result: ReturnCode) {
+        // TODO: Drop here?
+        if len > buf.len() || result != ReturnCode::SUCCESS {
+            return;
+        }
+        self.receive_packet(&buf[..len]);
+    }
+}
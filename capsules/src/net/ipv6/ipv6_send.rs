@@ -5,7 +5,12 @@
 //! when a transmission has completed.
 //!
 //! This file also includes an implementation of the `IP6Sender` trait, which
-//! sends an IPv6 packet using 6LoWPAN.
+//! sends an IPv6 packet using 6LoWPAN. The link-layer next hop of each packet
+//! is chosen by an [IP6Router](trait.IP6Router.html) if one is set (e.g. the
+//! RPL router in `net::rpl`), and is the gateway address otherwise. Frames
+//! are sent without MAC security, unless an
+//! [IP6LinkSecurity](trait.IP6LinkSecurity.html) is set (e.g. a Thread child)
+//! to choose the security level and the key of each frame.
 
 // Additional Work and Known Problems
 // ----------------------------------
@@ -17,7 +22,7 @@
 // interface.
 
 use crate::ieee802154::device::{MacDevice, TxClient};
-use crate::net::ieee802154::MacAddress;
+use crate::net::ieee802154::{KeyId, MacAddress, SecurityLevel};
 use crate::net::ipv6::ip_utils::IPAddr;
 use crate::net::ipv6::{IP6Header, IP6Packet, TransportHeader};
 use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
@@ -37,6 +42,23 @@ pub trait IP6SendClient {
     fn send_done(&self, result: ReturnCode);
 }
 
+/// This trait is implemented by routing protocols that choose the
+/// link-layer next hop of the packets an `IP6Sender` sends.
+pub trait IP6Router {
+    /// Returns the MAC address of the next hop towards `dst`, or `None` if
+    /// the router has no route to it, in which case the gateway is used.
+    fn next_hop(&self, dst: IPAddr) -> Option<MacAddress>;
+}
+
+/// This trait is implemented by layers that manage the 802.15.4 keys, to
+/// choose the MAC security of the frames an `IP6Sender` sends.
+pub trait IP6LinkSecurity {
+    /// Returns the security level and the key ID of the frames sent to
+    /// `next_hop`, or `None` to send them without MAC security. The key itself
+    /// is looked up by the `KeyProcedure` of the MAC device.
+    fn link_security(&self, next_hop: MacAddress) -> Option<(SecurityLevel, KeyId)>;
+}
+
 /// This trait provides a basic IPv6 sending interface. It exposes basic
 /// configuration information for the IPv6 layer (setting the source address,
 /// setting the gateway MAC address), as well as a way to send an IPv6
@@ -65,6 +87,23 @@ pub trait IP6Sender<'a> {
     /// `gateway` - MAC address to send the constructed packet to
     fn set_gateway(&self, gateway: MacAddress);
 
+    /// This method sets the router that chooses the next hop of the packets
+    /// sent from this `IP6Sender` instance. Without a router, all packets
+    /// are sent to the gateway.
+    ///
+    /// # Arguments
+    /// `router` - The `IP6Router` queried for the next hop of each packet
+    fn set_router(&self, router: &'a dyn IP6Router);
+
+    /// This method sets the layer that chooses the MAC security of the
+    /// frames sent from this `IP6Sender` instance. Without it, frames are
+    /// sent without MAC security.
+    ///
+    /// # Arguments
+    /// `link_security` - The `IP6LinkSecurity` queried for the security of
+    /// each packet
+    fn set_link_security(&self, link_security: &'a dyn IP6LinkSecurity);
+
     /// This method sets the `IP6Header` for the `IP6Sender` instance
     ///
     /// # Arguments
@@ -86,6 +125,16 @@ pub trait IP6Sender<'a> {
         payload: &LeasableBuffer<'static, u8>,
         net_cap: &'static NetworkCapability,
     ) -> ReturnCode;
+
+    /// This method sends a received packet that is not addressed to this
+    /// node on towards its destination. The packet is sent with the given
+    /// header, so the caller is responsible for decrementing the hop limit.
+    /// The `send_done` callback is issued as for `send_to`.
+    ///
+    /// # Arguments
+    /// `header` - The `IP6Header` to send the packet with
+    /// `payload` - The received packet following the IPv6 header
+    fn forward(&self, header: IP6Header, payload: &[u8]) -> ReturnCode;
 }
 
 /// This struct is a specific implementation of the `IP6Sender` trait. This
@@ -101,9 +150,10 @@ pub struct IP6SendStruct<'a, A: time::Alarm<'a>> {
     tx_buf: TakeCell<'static, [u8]>,
     sixlowpan: TxState<'a>,
     radio: &'a dyn MacDevice<'a>,
-    dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
     client: OptionalCell<&'a dyn IP6SendClient>,
+    router: OptionalCell<&'a dyn IP6Router>,
+    link_security: OptionalCell<&'a dyn IP6LinkSecurity>,
     ip_vis: &'static IpVisibilityCapability,
 }
 
@@ -120,6 +170,14 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         self.gateway.set(gateway);
     }
 
+    fn set_router(&self, router: &'a dyn IP6Router) {
+        self.router.set(router);
+    }
+
+    fn set_link_security(&self, link_security: &'a dyn IP6LinkSecurity) {
+        self.link_security.set(link_security);
+    }
+
     fn set_header(&mut self, ip6_header: IP6Header) {
         self.ip6_packet
             .map(|ip6_packet| ip6_packet.header = ip6_header);
@@ -135,16 +193,34 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         if !net_cap.remote_addr_valid(dst, self.ip_vis) {
             return ReturnCode::FAIL;
         }
+        let next_hop = self.next_hop(dst);
         self.sixlowpan.init(
             self.src_mac_addr,
-            self.dst_mac_addr,
+            next_hop,
             self.radio.get_pan(),
-            None,
+            self.security(next_hop),
         );
         self.init_packet(dst, transport_header, payload);
         let ret = self.send_next_fragment();
         ret
     }
+
+    fn forward(&self, header: IP6Header, payload: &[u8]) -> ReturnCode {
+        let result = self.ip6_packet.map_or(ReturnCode::EBUSY, |ip6_packet| {
+            ip6_packet.set_received_payload(header, payload)
+        });
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        let next_hop = self.next_hop(header.dst_addr);
+        self.sixlowpan.init(
+            self.src_mac_addr,
+            next_hop,
+            self.radio.get_pan(),
+            self.security(next_hop),
+        );
+        self.send_next_fragment()
+    }
 }
 
 impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
@@ -166,13 +242,25 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
             tx_buf: TakeCell::new(tx_buf),
             sixlowpan: sixlowpan,
             radio: radio,
-            dst_mac_addr: dst_mac_addr,
             src_mac_addr: src_mac_addr,
             client: OptionalCell::empty(),
+            router: OptionalCell::empty(),
+            link_security: OptionalCell::empty(),
             ip_vis: ip_vis,
         }
     }
 
+    fn next_hop(&self, dst: IPAddr) -> MacAddress {
+        self.router
+            .and_then(|router| router.next_hop(dst))
+            .unwrap_or_else(|| self.gateway.get())
+    }
+
+    fn security(&self, next_hop: MacAddress) -> Option<(SecurityLevel, KeyId)> {
+        self.link_security
+            .and_then(|link_security| link_security.link_security(next_hop))
+    }
+
     fn init_packet(
         &self,
         dst_addr: IPAddr,
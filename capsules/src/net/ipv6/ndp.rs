@@ -0,0 +1,141 @@
+//! This file implements the parts of IPv6 Neighbor Discovery (RFC 4861) that
+//! are needed to resolve the link-layer addresses of neighbors on an Ethernet
+//! link: a small neighbor cache, and the encoding and decoding of the bodies
+//! of Neighbor Solicitation and Neighbor Advertisement messages.
+//!
+//! The messages themselves are ICMPv6 messages of type 135 and 136, whose
+//! first word (reserved or flags) is part of the `ICMP6Header`. The body
+//! handled here follows that header, and consists of the target address and
+//! a single link-layer address option.
+//!
+//! Router discovery, redirects, duplicate address detection and neighbor
+//! unreachability detection are not implemented: cache entries are never
+//! expired, and are only replaced when the cache is full.
+
+use crate::net::ethernet::EthernetAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use core::cell::Cell;
+
+/// Number of neighbors whose link-layer address is remembered.
+pub const NEIGHBOR_CACHE_SIZE: usize = 8;
+
+/// Time to wait for a Neighbor Advertisement before soliciting again.
+pub const RETRANS_TIMER_MS: u32 = 1000;
+
+/// Number of Neighbor Solicitations sent before giving up on a neighbor.
+pub const MAX_MULTICAST_SOLICIT: u8 = 3;
+
+/// Length of the body of the Neighbor Solicitations and Advertisements sent
+/// by this implementation: the target address and a link-layer address option.
+pub const NDP_BODY_LEN: usize = 16 + 8;
+
+/// Hop limit that all Neighbor Discovery messages are sent and received with.
+pub const NDP_HOP_LIMIT: u8 = 255;
+
+/// Flags in the first word of a Neighbor Advertisement.
+pub mod na_flags {
+    pub const ROUTER: u32 = 0x8000_0000;
+    pub const SOLICITED: u32 = 0x4000_0000;
+    pub const OVERRIDE: u32 = 0x2000_0000;
+}
+
+/// Types of the Neighbor Discovery options that carry a link-layer address.
+pub mod ndp_option {
+    pub const SOURCE_LL_ADDR: u8 = 1;
+    pub const TARGET_LL_ADDR: u8 = 2;
+}
+
+/// Returns the solicited-node multicast address of `addr`, which Neighbor
+/// Solicitations for `addr` are sent to.
+pub fn solicited_node_multicast(addr: &IPAddr) -> IPAddr {
+    let mut mcast = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff, 0, 0, 0]);
+    mcast.0[13..].copy_from_slice(&addr.0[13..]);
+    mcast
+}
+
+/// Writes a message body with the given target address and link-layer
+/// address option to `buf`, which must hold at least `NDP_BODY_LEN` bytes.
+/// Returns the length of the body.
+pub fn encode_body(
+    buf: &mut [u8],
+    target: &IPAddr,
+    option_type: u8,
+    ll_addr: &EthernetAddress,
+) -> usize {
+    buf[..16].copy_from_slice(&target.0);
+    buf[16] = option_type;
+    buf[17] = 1; // In units of 8 bytes
+    buf[18..NDP_BODY_LEN].copy_from_slice(&ll_addr.0);
+    NDP_BODY_LEN
+}
+
+/// Parses a message body, returning the target address and the link-layer
+/// address in the first option of type `option_type`, if any. Returns `None`
+/// if the body is malformed.
+pub fn decode_body(buf: &[u8], option_type: u8) -> Option<(IPAddr, Option<EthernetAddress>)> {
+    if buf.len() < 16 {
+        return None;
+    }
+    let mut target = IPAddr::new();
+    target.0.copy_from_slice(&buf[..16]);
+
+    let mut ll_addr = None;
+    let mut off = 16;
+    while off + 2 <= buf.len() {
+        let len = buf[off + 1] as usize * 8;
+        if len == 0 || off + len > buf.len() {
+            // Options with a length of zero must cause the message to be
+            // discarded
+            return None;
+        }
+        if buf[off] == option_type && len >= 8 && ll_addr.is_none() {
+            let mut addr = EthernetAddress::default();
+            addr.0.copy_from_slice(&buf[off + 2..off + 8]);
+            ll_addr = Some(addr);
+        }
+        off += len;
+    }
+    Some((target, ll_addr))
+}
+
+/// A fixed-size cache of the link-layer addresses of neighbors. When the
+/// cache is full, entries are replaced in round-robin order.
+pub struct NeighborCache {
+    entries: Cell<[Option<(IPAddr, EthernetAddress)>; NEIGHBOR_CACHE_SIZE]>,
+    next_victim: Cell<usize>,
+}
+
+impl NeighborCache {
+    pub fn new() -> NeighborCache {
+        NeighborCache {
+            entries: Cell::new([None; NEIGHBOR_CACHE_SIZE]),
+            next_victim: Cell::new(0),
+        }
+    }
+
+    /// Returns the link-layer address of `addr`, if it is known.
+    pub fn lookup(&self, addr: &IPAddr) -> Option<EthernetAddress> {
+        self.entries
+            .get()
+            .iter()
+            .filter_map(|entry| *entry)
+            .find(|(ip, _)| ip == addr)
+            .map(|(_, mac)| mac)
+    }
+
+    /// Records `mac` as the link-layer address of `addr`.
+    pub fn update(&self, addr: IPAddr, mac: EthernetAddress) {
+        let mut entries = self.entries.get();
+        let index = entries
+            .iter()
+            .position(|entry| entry.map_or(false, |(ip, _)| ip == addr))
+            .or_else(|| entries.iter().position(|entry| entry.is_none()))
+            .unwrap_or_else(|| {
+                let victim = self.next_victim.get();
+                self.next_victim.set((victim + 1) % NEIGHBOR_CACHE_SIZE);
+                victim
+            });
+        entries[index] = Some((addr, mac));
+        self.entries.set(entries);
+    }
+}
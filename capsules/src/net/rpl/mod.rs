@@ -0,0 +1,7 @@
+pub mod rpl_router;
+pub mod trickle;
+
+// Reexport the exports of the [`rpl`] module, to avoid redundant
+// module paths (e.g. `capsules::net::rpl::rpl::Dio`)
+mod rpl;
+pub use rpl::*;
@@ -0,0 +1,427 @@
+//! This file implements the encoding and decoding of the RPL (RFC 6550)
+//! control messages used by storing mode routing: DODAG Information
+//! Solicitations (DIS), DODAG Information Objects (DIO), Destination
+//! Advertisement Objects (DAO) and their acknowledgements (DAO-ACK).
+//!
+//! RPL control messages are ICMPv6 messages of type 155, whose code selects
+//! the message. The first word of each message base is part of the
+//! `ICMP6Header` (as `ICMP6HeaderOptions::Type155`); the functions here
+//! build and parse that word and the body that follows it. Secure RPL
+//! messages are not supported.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+
+/// Rank advertised by nodes that are not part of a DODAG, and used to poison
+/// routes through a node that has left one.
+pub const INFINITE_RANK: u16 = 0xffff;
+
+/// Default value of the MinHopRankIncrease parameter, and the rank of the
+/// DODAG root.
+pub const DEFAULT_MIN_HOP_RANK_INCREASE: u16 = 256;
+
+/// Mode of operation of the DODAGs this implementation joins and builds:
+/// storing mode without multicast support.
+pub const MOP_STORING: u8 = 2;
+
+/// Objective Function 0 (RFC 6552), the only objective function supported.
+pub const OCP_OF0: u16 = 0;
+
+/// The all-RPL-nodes multicast address, ff02::1a, which DIOs and DISs are
+/// multicast to.
+pub const ALL_RPL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x1a]);
+
+/// Maximum number of targets in a DAO handled by this implementation.
+pub const MAX_DAO_TARGETS: usize = 12;
+
+/// Length of the body of a DIO sent by this implementation: the rest of the
+/// message base and a DODAG configuration option.
+pub const DIO_BODY_LEN: usize = 20 + 16;
+
+/// Length of the body of a DIS: the message base is padded into the first
+/// word, so there is nothing after it.
+pub const DIS_BODY_LEN: usize = 0;
+
+/// Length of the body of a DAO-ACK sent by this implementation, which
+/// always includes the DODAGID.
+pub const DAO_ACK_BODY_LEN: usize = 16;
+
+/// Length of a DAO sent by this implementation with `n` targets: the
+/// DODAGID, a target option per target and a transit information option.
+pub const fn dao_body_len(n: usize) -> usize {
+    16 + n * TARGET_OPTION_LEN + TRANSIT_OPTION_LEN
+}
+
+const TARGET_OPTION_LEN: usize = 2 + 2 + 16;
+const TRANSIT_OPTION_LEN: usize = 2 + 4;
+
+/// The codes of the RPL control messages.
+pub mod rpl_code {
+    pub const DIS: u8 = 0x00;
+    pub const DIO: u8 = 0x01;
+    pub const DAO: u8 = 0x02;
+    pub const DAO_ACK: u8 = 0x03;
+}
+
+/// The types of the RPL control message options.
+pub mod rpl_option {
+    pub const PAD1: u8 = 0x00;
+    pub const PADN: u8 = 0x01;
+    pub const DODAG_CONFIG: u8 = 0x04;
+    pub const TARGET: u8 = 0x05;
+    pub const TRANSIT_INFO: u8 = 0x06;
+}
+
+const DIO_GROUNDED: u8 = 0x80;
+const DAO_ACK_REQUESTED: u8 = 0x80;
+const DAO_DODAGID_PRESENT: u8 = 0x40;
+const DAO_ACK_DODAGID_PRESENT: u8 = 0x80;
+
+/// Initial value of lollipop counters: the DODAG version and DAO sequence
+/// numbers.
+pub const LOLLIPOP_INIT: u8 = 240;
+
+const SEQUENCE_WINDOW: u8 = 16;
+
+/// Returns the DAGRank of `rank`, the integer part used when comparing the
+/// ranks of nodes.
+pub fn dag_rank(rank: u16, min_hop_rank_increase: u16) -> u16 {
+    rank / min_hop_rank_increase
+}
+
+/// Returns whether the lollipop counter value `a` is newer than `b`
+/// (RFC 6550, Section 7.2), for the version numbers of DODAGs and the DAO
+/// sequence numbers.
+pub fn lollipop_newer(a: u8, b: u8) -> bool {
+    if a == b {
+        return false;
+    }
+    if a >= 128 && b < 128 {
+        // a is in the linear part of the counter, b in the circular one
+        return 256 + b as usize - a as usize > SEQUENCE_WINDOW as usize;
+    }
+    if b >= 128 && a < 128 {
+        return 256 + a as usize - b as usize <= SEQUENCE_WINDOW as usize;
+    }
+    if a >= 128 {
+        return a > b;
+    }
+    // Both are in the circular part of the counter. Values too far apart to
+    // be compared are treated as newer, so that the change is acted upon.
+    let diff = a.wrapping_sub(b) & 0x7f;
+    diff <= SEQUENCE_WINDOW || 128 - diff > SEQUENCE_WINDOW
+}
+
+/// Returns the value following `a` in a lollipop counter: the linear part
+/// (128 to 255) leads into the circular part (0 to 127).
+pub fn lollipop_increment(a: u8) -> u8 {
+    if a == 127 {
+        0
+    } else {
+        a.wrapping_add(1)
+    }
+}
+
+/// The parameters of a DODAG that are carried in the DODAG configuration
+/// option of a DIO.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DodagConfig {
+    /// Number of times the DIO Trickle interval is doubled
+    pub dio_interval_doublings: u8,
+    /// Minimum DIO Trickle interval, as the base 2 logarithm of milliseconds
+    pub dio_interval_min: u8,
+    /// DIO Trickle redundancy constant
+    pub dio_redundancy: u8,
+    pub max_rank_increase: u16,
+    pub min_hop_rank_increase: u16,
+    /// Objective Code Point of the objective function of the DODAG
+    pub ocp: u16,
+    /// Lifetime of routes, in units of `lifetime_unit`
+    pub default_lifetime: u8,
+    /// Unit of route lifetimes, in seconds
+    pub lifetime_unit: u16,
+}
+
+impl Default for DodagConfig {
+    fn default() -> DodagConfig {
+        DodagConfig {
+            dio_interval_doublings: 8,
+            dio_interval_min: 12,
+            dio_redundancy: 10,
+            max_rank_increase: 7 * DEFAULT_MIN_HOP_RANK_INCREASE,
+            min_hop_rank_increase: DEFAULT_MIN_HOP_RANK_INCREASE,
+            ocp: OCP_OF0,
+            default_lifetime: 30,
+            lifetime_unit: 60,
+        }
+    }
+}
+
+impl DodagConfig {
+    /// Returns the lifetime of routes in milliseconds.
+    pub fn route_lifetime_ms(&self) -> u64 {
+        self.default_lifetime as u64 * self.lifetime_unit as u64 * 1000
+    }
+}
+
+/// A DODAG Information Object, which advertises a DODAG and the rank of its
+/// sender in it.
+#[derive(Copy, Clone, Debug)]
+pub struct Dio {
+    pub instance_id: u8,
+    pub version: u8,
+    pub rank: u16,
+    pub grounded: bool,
+    pub mop: u8,
+    pub preference: u8,
+    /// Destination Advertisement Trigger Sequence Number
+    pub dtsn: u8,
+    pub dodag_id: IPAddr,
+    pub config: Option<DodagConfig>,
+}
+
+impl Dio {
+    /// Returns the first word of the DIO base.
+    pub fn base(&self) -> u32 {
+        (self.instance_id as u32) << 24 | (self.version as u32) << 16 | self.rank as u32
+    }
+
+    /// Writes the body of the DIO, including a DODAG configuration option
+    /// if `config` is set, to `buf`. Returns the length of the body.
+    pub fn encode_body(&self, buf: &mut [u8]) -> usize {
+        buf[0] = if self.grounded { DIO_GROUNDED } else { 0 }
+            | (self.mop & 0x7) << 3
+            | (self.preference & 0x7);
+        buf[1] = self.dtsn;
+        buf[2] = 0; // Flags
+        buf[3] = 0; // Reserved
+        buf[4..20].copy_from_slice(&self.dodag_id.0);
+        match self.config {
+            Some(config) => {
+                let opt = &mut buf[20..DIO_BODY_LEN];
+                opt[0] = rpl_option::DODAG_CONFIG;
+                opt[1] = 14;
+                opt[2] = 0; // Flags, A and PCS
+                opt[3] = config.dio_interval_doublings;
+                opt[4] = config.dio_interval_min;
+                opt[5] = config.dio_redundancy;
+                opt[6..8].copy_from_slice(&config.max_rank_increase.to_be_bytes());
+                opt[8..10].copy_from_slice(&config.min_hop_rank_increase.to_be_bytes());
+                opt[10..12].copy_from_slice(&config.ocp.to_be_bytes());
+                opt[12] = 0; // Reserved
+                opt[13] = config.default_lifetime;
+                opt[14..16].copy_from_slice(&config.lifetime_unit.to_be_bytes());
+                DIO_BODY_LEN
+            }
+            None => 20,
+        }
+    }
+
+    /// Parses a DIO from the first word of its base and its body. Returns
+    /// `None` if the body is malformed.
+    pub fn decode(base: u32, buf: &[u8]) -> Option<Dio> {
+        if buf.len() < 20 {
+            return None;
+        }
+        let mut dodag_id = IPAddr::new();
+        dodag_id.0.copy_from_slice(&buf[4..20]);
+        let mut config = None;
+        for_each_option(&buf[20..], |opt_type, opt| {
+            if opt_type == rpl_option::DODAG_CONFIG && opt.len() >= 14 {
+                config = Some(DodagConfig {
+                    dio_interval_doublings: opt[1],
+                    dio_interval_min: opt[2],
+                    dio_redundancy: opt[3],
+                    max_rank_increase: u16::from_be_bytes([opt[4], opt[5]]),
+                    min_hop_rank_increase: u16::from_be_bytes([opt[6], opt[7]]),
+                    ocp: u16::from_be_bytes([opt[8], opt[9]]),
+                    default_lifetime: opt[11],
+                    lifetime_unit: u16::from_be_bytes([opt[12], opt[13]]),
+                });
+            }
+        })?;
+        Some(Dio {
+            instance_id: (base >> 24) as u8,
+            version: (base >> 16) as u8,
+            rank: base as u16,
+            grounded: buf[0] & DIO_GROUNDED != 0,
+            mop: (buf[0] >> 3) & 0x7,
+            preference: buf[0] & 0x7,
+            dtsn: buf[1],
+            dodag_id,
+            config,
+        })
+    }
+}
+
+/// Returns the first word of a DIS, which holds its empty flags and reserved
+/// fields followed by an empty PadN option.
+pub fn dis_base() -> u32 {
+    (rpl_option::PADN as u32) << 8
+}
+
+/// A target of a DAO, and the length of its prefix.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DaoTarget {
+    pub prefix: IPAddr,
+    pub prefix_len: u8,
+}
+
+/// A Destination Advertisement Object, which advertises routes to a set of
+/// targets through its sender. All targets share the lifetime given in the
+/// transit information option; a lifetime of zero removes the routes (a
+/// No-Path DAO).
+#[derive(Copy, Clone, Debug)]
+pub struct Dao {
+    pub instance_id: u8,
+    pub ack_requested: bool,
+    pub sequence: u8,
+    pub dodag_id: Option<IPAddr>,
+    pub path_sequence: u8,
+    /// Lifetime of the routes, in units of the lifetime unit of the DODAG
+    pub lifetime: u8,
+    pub targets: [Option<DaoTarget>; MAX_DAO_TARGETS],
+}
+
+impl Dao {
+    /// Returns the first word of the DAO base.
+    pub fn base(&self) -> u32 {
+        let mut flags = 0;
+        if self.ack_requested {
+            flags |= DAO_ACK_REQUESTED;
+        }
+        if self.dodag_id.is_some() {
+            flags |= DAO_DODAGID_PRESENT;
+        }
+        (self.instance_id as u32) << 24 | (flags as u32) << 16 | self.sequence as u32
+    }
+
+    /// Returns the targets of the DAO.
+    pub fn targets(&self) -> impl Iterator<Item = &DaoTarget> {
+        self.targets.iter().filter_map(|target| target.as_ref())
+    }
+
+    /// Writes the body of the DAO to `buf`, which must hold at least
+    /// `dao_body_len` bytes for the number of targets. Returns the length of
+    /// the body.
+    pub fn encode_body(&self, buf: &mut [u8]) -> usize {
+        let mut off = 0;
+        if let Some(dodag_id) = self.dodag_id {
+            buf[..16].copy_from_slice(&dodag_id.0);
+            off += 16;
+        }
+        for target in self.targets() {
+            let opt = &mut buf[off..off + TARGET_OPTION_LEN];
+            opt[0] = rpl_option::TARGET;
+            opt[1] = (TARGET_OPTION_LEN - 2) as u8;
+            opt[2] = 0; // Flags
+            opt[3] = target.prefix_len;
+            opt[4..].copy_from_slice(&target.prefix.0);
+            off += TARGET_OPTION_LEN;
+        }
+        let opt = &mut buf[off..off + TRANSIT_OPTION_LEN];
+        opt[0] = rpl_option::TRANSIT_INFO;
+        opt[1] = (TRANSIT_OPTION_LEN - 2) as u8;
+        opt[2] = 0; // Flags
+        opt[3] = 0; // Path control
+        opt[4] = self.path_sequence;
+        opt[5] = self.lifetime;
+        off + TRANSIT_OPTION_LEN
+    }
+
+    /// Parses a DAO from the first word of its base and its body. Targets
+    /// beyond `MAX_DAO_TARGETS` are ignored. Returns `None` if the body is
+    /// malformed or has no transit information option.
+    pub fn decode(base: u32, buf: &[u8]) -> Option<Dao> {
+        let flags = (base >> 16) as u8;
+        let mut dodag_id = None;
+        let mut off = 0;
+        if flags & DAO_DODAGID_PRESENT != 0 {
+            if buf.len() < 16 {
+                return None;
+            }
+            let mut addr = IPAddr::new();
+            addr.0.copy_from_slice(&buf[..16]);
+            dodag_id = Some(addr);
+            off = 16;
+        }
+
+        let mut targets = [None; MAX_DAO_TARGETS];
+        let mut num_targets = 0;
+        let mut transit = None;
+        for_each_option(&buf[off..], |opt_type, opt| match opt_type {
+            rpl_option::TARGET if opt.len() >= 2 => {
+                let prefix_len = opt[1];
+                let prefix_bytes = (prefix_len as usize + 7) / 8;
+                if prefix_len > 128 || opt.len() < 2 + prefix_bytes {
+                    return;
+                }
+                if num_targets < MAX_DAO_TARGETS {
+                    let mut prefix = IPAddr::new();
+                    prefix.0[..prefix_bytes].copy_from_slice(&opt[2..2 + prefix_bytes]);
+                    targets[num_targets] = Some(DaoTarget { prefix, prefix_len });
+                    num_targets += 1;
+                }
+            }
+            rpl_option::TRANSIT_INFO if opt.len() >= 4 => {
+                transit = Some((opt[2], opt[3]));
+            }
+            _ => {}
+        })?;
+        let (path_sequence, lifetime) = transit?;
+
+        Some(Dao {
+            instance_id: (base >> 24) as u8,
+            ack_requested: flags & DAO_ACK_REQUESTED != 0,
+            sequence: base as u8,
+            dodag_id,
+            path_sequence,
+            lifetime,
+            targets,
+        })
+    }
+}
+
+/// Status of a DAO-ACK accepting the DAO.
+pub const DAO_ACK_ACCEPTED: u8 = 0;
+
+/// Status of a DAO-ACK rejecting the DAO because the routing table of the
+/// parent is full. Statuses of 128 and above are rejections.
+pub const DAO_ACK_NO_ROUTE_SPACE: u8 = 128;
+
+/// Returns the first word of a DAO-ACK for the DAO with the given instance
+/// and sequence number; the DODAGID is always included in the body.
+pub fn dao_ack_base(instance_id: u8, sequence: u8, status: u8) -> u32 {
+    (instance_id as u32) << 24
+        | (DAO_ACK_DODAGID_PRESENT as u32) << 16
+        | (sequence as u32) << 8
+        | status as u32
+}
+
+/// Parses the first word of a DAO-ACK, returning its instance, sequence
+/// number and status.
+pub fn dao_ack_decode(base: u32) -> (u8, u8, u8) {
+    ((base >> 24) as u8, (base >> 8) as u8, base as u8)
+}
+
+/// Calls `f` with the type and the contents (after the length field) of
+/// each option in `buf`. Returns `None` if an option overruns the buffer.
+fn for_each_option<F: FnMut(u8, &[u8])>(buf: &[u8], mut f: F) -> Option<()> {
+    let mut off = 0;
+    while off < buf.len() {
+        let opt_type = buf[off];
+        if opt_type == rpl_option::PAD1 {
+            off += 1;
+            continue;
+        }
+        if off + 2 > buf.len() {
+            return None;
+        }
+        let len = buf[off + 1] as usize;
+        if off + 2 + len > buf.len() {
+            return None;
+        }
+        f(opt_type, &buf[off + 2..off + 2 + len]);
+        off += 2 + len;
+    }
+    Some(())
+}
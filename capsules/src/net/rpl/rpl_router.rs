@@ -0,0 +1,1020 @@
+//! This file implements an RPL (RFC 6550) router for a single RPL instance
+//! in storing mode, using Objective Function 0 (RFC 6552).
+//!
+//! The router joins the first DODAG it hears a DIO for (or builds one when
+//! started as the root), keeps a small set of candidate parents, and
+//! advertises its own addresses and the routes it stores to its preferred
+//! parent with DAOs. Nodes that are not part of a DODAG solicit DIOs with
+//! DISs. DIOs are scheduled with a Trickle timer.
+//!
+//! The router is used by the IPv6 layer in three ways:
+//!
+//! - As the ICMPv6 client of an `IP6Receiver`, to receive RPL control
+//!   messages. Other ICMPv6 messages are ignored.
+//! - As the `IP6Forwarder` of that `IP6Receiver`, to forward packets that are
+//!   not addressed to this node: down along a stored route if there is one,
+//!   and up to the preferred parent otherwise. Packets are dropped when their
+//!   hop limit runs out, or when a control message or another packet is
+//!   being sent.
+//! - As the `IP6Router` of the `IP6Sender`s of this node, to choose the MAC
+//!   address of the next hop of the packets they send.
+//!
+//! Control messages are sent from the link local address of the node, with
+//! an `ICMP6Sender` whose IP sender is also used to forward packets.
+//!
+//! Limitations
+//! -----------
+//!
+//! - Only one instance and one DODAG are joined at a time; DIOs for other
+//!   DODAGs are ignored.
+//! - The RPL packet information (RFC 6553) and source routing (RFC 6554)
+//!   headers are not supported, so loops are not detected; the hop limit
+//!   bounds the time a packet can spend in one.
+//! - Prefix Information options are neither sent nor used for address
+//!   autoconfiguration, so the addresses of a node are set by the board.
+//! - Next hops are identified by their link local address, which must be
+//!   derived from their MAC address.
+
+use crate::net::icmpv6::icmpv6_send::{ICMP6SendClient, ICMP6Sender};
+use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6_recv::{IP6Forwarder, IP6RecvClient};
+use crate::net::ipv6::ipv6_send::{IP6Router, IP6Sender};
+use crate::net::ipv6::{IP6Header, ICMP_HDR_LEN};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::rpl::trickle::Trickle;
+use crate::net::rpl::{
+    dag_rank, dao_ack_base, dao_ack_decode, dao_body_len, dis_base, lollipop_increment,
+    lollipop_newer, rpl_code, Dao, DaoTarget, Dio, DodagConfig, ALL_RPL_NODES, DAO_ACK_ACCEPTED,
+    DAO_ACK_BODY_LEN, DAO_ACK_NO_ROUTE_SPACE, DIS_BODY_LEN, INFINITE_RANK, LOLLIPOP_INIT,
+    MAX_DAO_TARGETS, MOP_STORING, OCP_OF0,
+};
+use core::cell::Cell;
+use kernel::common::cells::TakeCell;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::time::{self, Frequency, Ticks};
+use kernel::ReturnCode;
+
+/// Number of candidate parents that are remembered.
+pub const MAX_PARENTS: usize = 3;
+
+/// Number of downward routes that are stored.
+pub const MAX_ROUTES: usize = 8;
+
+/// Size of the buffer the router needs to build control messages.
+pub const RPL_BUF_LEN: usize = dao_body_len(MAX_DAO_TARGETS);
+
+/// Delay between a change of the routes of a node and the DAO announcing it,
+/// so that several changes are announced together.
+const DAO_DELAY_MS: u64 = 1000;
+
+/// Time to wait for a DAO-ACK before resending a DAO.
+const DAO_ACK_TIMEOUT_MS: u64 = 2000;
+
+/// Number of times a DAO is sent before the preferred parent is considered
+/// unreachable.
+const DAO_MAX_ATTEMPTS: u8 = 3;
+
+/// Interval between the DISs of a node that is not part of a DODAG.
+const DIS_INTERVAL_MS: u64 = 10_000;
+
+/// Longest time the alarm is set for, so that the uptime is kept even if
+/// nothing is scheduled.
+const MAX_SLEEP_MS: u64 = 60_000;
+
+/// Rank increase of each hop with OF0: the default step of rank (3) times
+/// MinHopRankIncrease.
+const OF0_STEP_OF_RANK: u16 = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RplState {
+    /// The router has not been started.
+    Idle,
+    /// The node is looking for a DODAG to join.
+    Detached,
+    /// The node is part of a DODAG and has a preferred parent.
+    Joined,
+    /// The node is the root of a DODAG.
+    Root,
+}
+
+#[derive(Copy, Clone)]
+struct Parent {
+    addr: IPAddr,
+    rank: u16,
+    dtsn: u8,
+}
+
+#[derive(Copy, Clone)]
+struct Route {
+    target: DaoTarget,
+    next_hop: IPAddr,
+    expires: u64,
+}
+
+impl Route {
+    fn matches(&self, addr: &IPAddr) -> bool {
+        let full_bytes = (self.target.prefix_len / 8) as usize;
+        let remaining = self.target.prefix_len % 8;
+        if self.target.prefix.0[..full_bytes] != addr.0[..full_bytes] {
+            return false;
+        }
+        if remaining == 0 {
+            return true;
+        }
+        let mask = 0xff_u8 << (8 - remaining);
+        self.target.prefix.0[full_bytes] & mask == addr.0[full_bytes] & mask
+    }
+}
+
+pub struct RplRouter<'a, A: time::Alarm<'a>> {
+    alarm: &'a A,
+    icmp_sender: &'a dyn ICMP6Sender<'a>,
+    ip_sender: &'a dyn IP6Sender<'a>,
+    net_cap: &'static NetworkCapability,
+    interface_list: &'static [IPAddr],
+    buffer: TakeCell<'static, [u8]>,
+
+    /// Milliseconds since the router was started, updated from the alarm
+    uptime_ms: Cell<u64>,
+    last_ticks: Cell<A::Ticks>,
+    random_state: Cell<u32>,
+
+    state: Cell<RplState>,
+    instance_id: Cell<u8>,
+    version: Cell<u8>,
+    dodag_id: Cell<IPAddr>,
+    rank: Cell<u16>,
+    dtsn: Cell<u8>,
+    config: Cell<DodagConfig>,
+    parents: Cell<[Option<Parent>; MAX_PARENTS]>,
+    preferred_parent: Cell<Option<IPAddr>>,
+    routes: Cell<[Option<Route>; MAX_ROUTES]>,
+    trickle: Trickle,
+
+    dao_sequence: Cell<u8>,
+    path_sequence: Cell<u8>,
+    /// When to send the next DAO, or give up on the preferred parent if the
+    /// last one was not acknowledged
+    dao_deadline: Cell<Option<u64>>,
+    dao_attempts: Cell<u8>,
+    /// Sequence number of the DAO that is waiting for an acknowledgement
+    dao_awaiting_ack: Cell<Option<u8>>,
+    dis_deadline: Cell<Option<u64>>,
+
+    /// Whether a control message or a forwarded packet is being sent
+    busy: Cell<bool>,
+    dio_pending: Cell<Option<IPAddr>>,
+    dis_pending: Cell<bool>,
+    dao_pending: Cell<bool>,
+    no_path_pending: Cell<Option<IPAddr>>,
+    dao_ack_pending: Cell<Option<(IPAddr, u8, u8)>>,
+}
+
+impl<'a, A: time::Alarm<'a>> RplRouter<'a, A> {
+    /// Creates a router. `mac_addr` seeds the random numbers used by the
+    /// Trickle timer, and `interface_list` holds the addresses of the node:
+    /// the non link local ones are advertised in DAOs, and the first one is
+    /// the DODAGID when the node is the root.
+    pub fn new(
+        alarm: &'a A,
+        icmp_sender: &'a dyn ICMP6Sender<'a>,
+        ip_sender: &'a dyn IP6Sender<'a>,
+        mac_addr: MacAddress,
+        interface_list: &'static [IPAddr],
+        buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> RplRouter<'a, A> {
+        let seed = match mac_addr {
+            MacAddress::Short(addr) => addr as u32,
+            MacAddress::Long(addr) => {
+                u32::from_be_bytes([addr[4], addr[5], addr[6], addr[7]])
+                    ^ u32::from_be_bytes([addr[0], addr[1], addr[2], addr[3]])
+            }
+        }
+        .wrapping_mul(0x9e37_79b9);
+        RplRouter {
+            alarm,
+            icmp_sender,
+            ip_sender,
+            net_cap,
+            interface_list,
+            buffer: TakeCell::new(buffer),
+            uptime_ms: Cell::new(0),
+            last_ticks: Cell::new(A::Ticks::from(0)),
+            random_state: Cell::new(seed | 1),
+            state: Cell::new(RplState::Idle),
+            instance_id: Cell::new(0),
+            version: Cell::new(LOLLIPOP_INIT),
+            dodag_id: Cell::new(IPAddr::new()),
+            rank: Cell::new(INFINITE_RANK),
+            dtsn: Cell::new(LOLLIPOP_INIT),
+            config: Cell::new(DodagConfig::default()),
+            parents: Cell::new([None; MAX_PARENTS]),
+            preferred_parent: Cell::new(None),
+            routes: Cell::new([None; MAX_ROUTES]),
+            trickle: Trickle::new(),
+            dao_sequence: Cell::new(LOLLIPOP_INIT),
+            path_sequence: Cell::new(LOLLIPOP_INIT),
+            dao_deadline: Cell::new(None),
+            dao_attempts: Cell::new(0),
+            dao_awaiting_ack: Cell::new(None),
+            dis_deadline: Cell::new(None),
+            busy: Cell::new(false),
+            dio_pending: Cell::new(None),
+            dis_pending: Cell::new(false),
+            dao_pending: Cell::new(false),
+            no_path_pending: Cell::new(None),
+            dao_ack_pending: Cell::new(None),
+        }
+    }
+
+    /// Starts looking for a DODAG to join.
+    pub fn start(&self) -> ReturnCode {
+        if self.state.get() != RplState::Idle {
+            return ReturnCode::EALREADY;
+        }
+        self.last_ticks.set(self.alarm.now());
+        let now = self.now();
+        self.state.set(RplState::Detached);
+        self.dis_pending.set(true);
+        self.dis_deadline.set(Some(now + DIS_INTERVAL_MS));
+        self.send_next();
+        self.schedule_alarm();
+        ReturnCode::SUCCESS
+    }
+
+    /// Starts as the root of a new DODAG of the given instance, whose
+    /// DODAGID is the first address of the interface list.
+    pub fn start_root(&self, instance_id: u8) -> ReturnCode {
+        if self.state.get() != RplState::Idle {
+            return ReturnCode::EALREADY;
+        }
+        let dodag_id = match self.interface_list.first() {
+            Some(addr) if !addr.is_unicast_link_local() && !addr.is_multicast() => *addr,
+            _ => return ReturnCode::EINVAL,
+        };
+        self.last_ticks.set(self.alarm.now());
+        let now = self.now();
+        let config = DodagConfig::default();
+        self.instance_id.set(instance_id);
+        self.dodag_id.set(dodag_id);
+        self.config.set(config);
+        self.rank.set(config.min_hop_rank_increase);
+        self.state.set(RplState::Root);
+        self.configure_trickle();
+        self.trickle.start(now, self.random());
+        self.schedule_alarm();
+        ReturnCode::SUCCESS
+    }
+
+    /// Rebuilds the DODAG by incrementing its version, which makes all nodes
+    /// select their parents again. Only the root can do this.
+    pub fn global_repair(&self) -> ReturnCode {
+        if self.state.get() != RplState::Root {
+            return ReturnCode::EINVAL;
+        }
+        let now = self.now();
+        self.version.set(lollipop_increment(self.version.get()));
+        self.dtsn.set(lollipop_increment(self.dtsn.get()));
+        self.routes.set([None; MAX_ROUTES]);
+        self.trickle.start(now, self.random());
+        self.schedule_alarm();
+        ReturnCode::SUCCESS
+    }
+
+    pub fn get_state(&self) -> RplState {
+        self.state.get()
+    }
+
+    pub fn get_rank(&self) -> u16 {
+        self.rank.get()
+    }
+
+    /// Returns the link local address of the preferred parent.
+    pub fn get_preferred_parent(&self) -> Option<IPAddr> {
+        self.preferred_parent.get()
+    }
+
+    /// Returns the number of downward routes that are stored.
+    pub fn num_routes(&self) -> usize {
+        self.routes
+            .get()
+            .iter()
+            .filter(|route| route.is_some())
+            .count()
+    }
+
+    /// Returns the uptime in milliseconds, advancing it by the whole
+    /// milliseconds that passed since it was last updated.
+    fn now(&self) -> u64 {
+        let freq = A::Frequency::frequency() as u64;
+        let now = self.alarm.now();
+        let elapsed = now.wrapping_sub(self.last_ticks.get()).into_u32() as u64;
+        let ms = elapsed * 1000 / freq;
+        let consumed = (ms * freq / 1000) as u32;
+        self.last_ticks
+            .set(self.last_ticks.get().wrapping_add(A::Ticks::from(consumed)));
+        self.uptime_ms.set(self.uptime_ms.get() + ms);
+        self.uptime_ms.get()
+    }
+
+    /// Returns a pseudo-random number (xorshift32), used to pick Trickle
+    /// transmission times.
+    fn random(&self) -> u32 {
+        let mut x = self.random_state.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random_state.set(x);
+        x
+    }
+
+    fn rank_increase(&self) -> u16 {
+        OF0_STEP_OF_RANK * self.config.get().min_hop_rank_increase
+    }
+
+    fn dag_rank(&self, rank: u16) -> u16 {
+        dag_rank(rank, self.config.get().min_hop_rank_increase)
+    }
+
+    fn configure_trickle(&self) {
+        let config = self.config.get();
+        self.trickle.configure(
+            1 << config.dio_interval_min.min(24),
+            config.dio_interval_doublings,
+            config.dio_redundancy,
+        );
+    }
+
+    fn schedule_alarm(&self) {
+        if self.state.get() == RplState::Idle {
+            return;
+        }
+        let now = self.now();
+        let mut next = now + MAX_SLEEP_MS;
+        let deadlines = [
+            self.trickle.next_event(),
+            self.dao_deadline.get(),
+            self.dis_deadline.get(),
+        ];
+        for deadline in deadlines.iter().flatten() {
+            next = next.min(*deadline);
+        }
+        let dt = next.saturating_sub(now).max(1);
+        self.alarm
+            .set_alarm(self.alarm.now(), A::ticks_from_ms(dt as u32));
+    }
+
+    // Parent set and DODAG membership
+
+    fn find_parent(&self, addr: IPAddr) -> Option<Parent> {
+        self.parents
+            .get()
+            .iter()
+            .filter_map(|parent| *parent)
+            .find(|parent| parent.addr == addr)
+    }
+
+    /// Records a DIO from a candidate parent. Returns whether its DTSN is
+    /// newer than the one previously heard from it.
+    fn update_parent(&self, addr: IPAddr, rank: u16, dtsn: u8) -> bool {
+        let mut parents = self.parents.get();
+        if let Some(parent) = parents
+            .iter_mut()
+            .filter_map(|parent| parent.as_mut())
+            .find(|parent| parent.addr == addr)
+        {
+            let dtsn_newer = lollipop_newer(dtsn, parent.dtsn);
+            parent.rank = rank;
+            parent.dtsn = dtsn;
+            self.parents.set(parents);
+            return dtsn_newer;
+        }
+
+        // A new parent takes a free slot, or replaces the candidate with the
+        // highest rank if that is worse than its own
+        let index = parents
+            .iter()
+            .position(|parent| parent.is_none())
+            .or_else(|| {
+                let (index, worst) = parents
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, parent)| parent.map(|p| (i, p)))
+                    .filter(|(_, parent)| Some(parent.addr) != self.preferred_parent.get())
+                    .max_by_key(|(_, parent)| parent.rank)?;
+                if worst.rank > rank {
+                    Some(index)
+                } else {
+                    None
+                }
+            });
+        if let Some(index) = index {
+            parents[index] = Some(Parent { addr, rank, dtsn });
+            self.parents.set(parents);
+        }
+        false
+    }
+
+    /// Removes a candidate parent. Returns whether it was in the parent set.
+    fn remove_parent(&self, addr: IPAddr) -> bool {
+        let mut parents = self.parents.get();
+        let mut removed = false;
+        for parent in parents.iter_mut() {
+            if parent.map_or(false, |p| p.addr == addr) {
+                *parent = None;
+                removed = true;
+            }
+        }
+        self.parents.set(parents);
+        removed
+    }
+
+    /// Chooses the preferred parent with OF0 and updates the rank of the
+    /// node. The preferred parent is only replaced by a candidate whose rank
+    /// is lower by at least MinHopRankIncrease. Detaches from the DODAG if
+    /// no candidate is left. Returns whether the preferred parent or the
+    /// rank changed.
+    fn select_parent(&self, now: u64) -> bool {
+        let increase = self.rank_increase();
+        let min_hop_rank_increase = self.config.get().min_hop_rank_increase;
+        let parents = self.parents.get();
+        let candidates = || {
+            parents
+                .iter()
+                .filter_map(|parent| *parent)
+                .filter(|parent| parent.rank < INFINITE_RANK - increase)
+        };
+        let best = candidates().min_by_key(|parent| parent.rank);
+        let current = self
+            .preferred_parent
+            .get()
+            .and_then(|addr| candidates().find(|parent| parent.addr == addr));
+        let selected = match (current, best) {
+            (Some(current), Some(best))
+                if best.rank.saturating_add(min_hop_rank_increase) > current.rank =>
+            {
+                current
+            }
+            (_, Some(best)) => best,
+            (_, None) => {
+                self.detach(now);
+                return true;
+            }
+        };
+
+        let old_parent = self.preferred_parent.get();
+        let old_rank = self.rank.get();
+        let rank = selected.rank + increase;
+        self.preferred_parent.set(Some(selected.addr));
+        self.rank.set(rank);
+
+        // Candidates that are not above the node in the DODAG could create
+        // loops
+        let mut parents = parents;
+        for parent in parents.iter_mut() {
+            if parent.map_or(false, |p| self.dag_rank(p.rank) >= self.dag_rank(rank)) {
+                *parent = None;
+            }
+        }
+        self.parents.set(parents);
+
+        let parent_changed = old_parent != Some(selected.addr);
+        if parent_changed {
+            // Routes through the old parent are removed with a No-Path DAO,
+            // if it is still a candidate (and therefore reachable)
+            if let Some(old) = old_parent {
+                if self.find_parent(old).is_some() {
+                    self.no_path_pending.set(Some(old));
+                }
+            }
+            self.schedule_dao(now, DAO_DELAY_MS);
+        }
+        let changed = parent_changed || rank != old_rank;
+        if !self.trickle.is_running() {
+            self.trickle.start(now, self.random());
+        } else if changed {
+            self.trickle.reset(now, self.random());
+        }
+        changed
+    }
+
+    /// Joins the DODAG advertised by `dio`, forgetting any previous parents
+    /// and routes. The parent must then be added and selected.
+    fn join(&self, dio: &Dio) {
+        self.instance_id.set(dio.instance_id);
+        self.version.set(dio.version);
+        self.dodag_id.set(dio.dodag_id);
+        self.config.set(dio.config.unwrap_or_default());
+        self.parents.set([None; MAX_PARENTS]);
+        self.preferred_parent.set(None);
+        self.routes.set([None; MAX_ROUTES]);
+        self.rank.set(INFINITE_RANK);
+        self.state.set(RplState::Joined);
+        self.dis_deadline.set(None);
+        self.dis_pending.set(false);
+        self.configure_trickle();
+        self.trickle.stop();
+    }
+
+    /// Leaves the DODAG after losing all parents. A DIO with an infinite
+    /// rank is sent so that children select other parents, and the node
+    /// starts looking for a DODAG again.
+    fn detach(&self, now: u64) {
+        self.state.set(RplState::Detached);
+        self.rank.set(INFINITE_RANK);
+        self.parents.set([None; MAX_PARENTS]);
+        self.preferred_parent.set(None);
+        self.routes.set([None; MAX_ROUTES]);
+        self.trickle.stop();
+        self.dao_deadline.set(None);
+        self.dao_awaiting_ack.set(None);
+        self.dao_pending.set(false);
+        self.dio_pending.set(Some(ALL_RPL_NODES));
+        self.dis_pending.set(true);
+        self.dis_deadline.set(Some(now + DIS_INTERVAL_MS));
+    }
+
+    /// Schedules a DAO to the preferred parent after `delay` milliseconds.
+    fn schedule_dao(&self, now: u64, delay: u64) {
+        if self.state.get() != RplState::Joined {
+            return;
+        }
+        self.dao_attempts.set(0);
+        self.dao_awaiting_ack.set(None);
+        let deadline = now + delay;
+        let deadline = self
+            .dao_deadline
+            .get()
+            .map_or(deadline, |current| current.min(deadline));
+        self.dao_deadline.set(Some(deadline));
+    }
+
+    fn dao_timeout(&self, now: u64) {
+        self.dao_deadline.set(None);
+        if self.state.get() != RplState::Joined {
+            return;
+        }
+        if self.dao_awaiting_ack.get().is_some() && self.dao_attempts.get() >= DAO_MAX_ATTEMPTS {
+            // The preferred parent did not acknowledge any of the DAOs
+            self.dao_awaiting_ack.set(None);
+            if let Some(parent) = self.preferred_parent.get() {
+                self.remove_parent(parent);
+                self.preferred_parent.set(None);
+                self.select_parent(now);
+            }
+        } else {
+            self.dao_pending.set(true);
+        }
+    }
+
+    // Routing table
+
+    /// Stores or refreshes a route. Returns `Some(true)` if the route is new
+    /// or its next hop changed, `Some(false)` if it was only refreshed, and
+    /// `None` if the table is full.
+    fn add_route(&self, target: DaoTarget, next_hop: IPAddr, expires: u64) -> Option<bool> {
+        let mut routes = self.routes.get();
+        let index = routes
+            .iter()
+            .position(|route| route.map_or(false, |r| r.target == target));
+        let (index, changed) = match index {
+            Some(index) => (
+                index,
+                routes[index].map_or(true, |r| r.next_hop != next_hop),
+            ),
+            None => (routes.iter().position(|route| route.is_none())?, true),
+        };
+        routes[index] = Some(Route {
+            target,
+            next_hop,
+            expires,
+        });
+        self.routes.set(routes);
+        Some(changed)
+    }
+
+    /// Removes the route to `target` through `next_hop`. Returns whether
+    /// there was one.
+    fn remove_route(&self, target: DaoTarget, next_hop: IPAddr) -> bool {
+        let mut routes = self.routes.get();
+        let mut removed = false;
+        for route in routes.iter_mut() {
+            if route.map_or(false, |r| r.target == target && r.next_hop == next_hop) {
+                *route = None;
+                removed = true;
+            }
+        }
+        self.routes.set(routes);
+        removed
+    }
+
+    fn purge_routes(&self, now: u64) {
+        let mut routes = self.routes.get();
+        for route in routes.iter_mut() {
+            if route.map_or(false, |r| r.expires <= now) {
+                *route = None;
+            }
+        }
+        self.routes.set(routes);
+    }
+
+    /// Returns the link local address of the next hop towards `dst`: the
+    /// longest matching route, or the preferred parent.
+    fn route_next_hop(&self, dst: IPAddr) -> Option<IPAddr> {
+        let now = self.uptime_ms.get();
+        self.routes
+            .get()
+            .iter()
+            .filter_map(|route| *route)
+            .filter(|route| route.expires > now && route.matches(&dst))
+            .max_by_key(|route| route.target.prefix_len)
+            .map(|route| route.next_hop)
+            .or_else(|| self.preferred_parent.get())
+    }
+
+    fn is_local(&self, addr: &IPAddr) -> bool {
+        addr.is_multicast() || addr.is_unicast_link_local() || self.interface_list.contains(addr)
+    }
+
+    fn is_member(&self) -> bool {
+        matches!(self.state.get(), RplState::Joined | RplState::Root)
+    }
+
+    // Received control messages
+
+    fn receive_dis(&self, now: u64, src: IPAddr, dst: IPAddr) {
+        if !self.is_member() {
+            return;
+        }
+        if dst.is_multicast() {
+            self.trickle.reset(now, self.random());
+        } else if self.dio_pending.get().is_none() {
+            self.dio_pending.set(Some(src));
+        }
+    }
+
+    fn receive_dio(&self, now: u64, src: IPAddr, dio: Dio) {
+        if dio.mop != MOP_STORING || dio.config.map_or(false, |c| c.ocp != OCP_OF0) {
+            return;
+        }
+        match self.state.get() {
+            RplState::Idle => {}
+            RplState::Root => {
+                if dio.instance_id == self.instance_id.get()
+                    && dio.dodag_id == self.dodag_id.get()
+                    && dio.version == self.version.get()
+                {
+                    self.trickle.hear_consistent();
+                }
+            }
+            RplState::Detached => {
+                if dio.rank == INFINITE_RANK {
+                    return;
+                }
+                self.join(&dio);
+                self.update_parent(src, dio.rank, dio.dtsn);
+                self.select_parent(now);
+            }
+            RplState::Joined => {
+                if dio.instance_id != self.instance_id.get() || dio.dodag_id != self.dodag_id.get()
+                {
+                    return;
+                }
+                if lollipop_newer(dio.version, self.version.get()) {
+                    // Global repair: the DODAG is rebuilt from scratch
+                    if dio.rank != INFINITE_RANK {
+                        self.join(&dio);
+                        self.update_parent(src, dio.rank, dio.dtsn);
+                        self.select_parent(now);
+                    }
+                    return;
+                }
+                if dio.version != self.version.get() {
+                    // The sender has not heard of the current version yet
+                    self.trickle.reset(now, self.random());
+                    return;
+                }
+                if dio.rank == INFINITE_RANK {
+                    if self.remove_parent(src) {
+                        self.select_parent(now);
+                    }
+                    return;
+                }
+                let is_parent = self.find_parent(src).is_some();
+                if !is_parent && self.dag_rank(dio.rank) >= self.dag_rank(self.rank.get()) {
+                    self.trickle.hear_consistent();
+                    return;
+                }
+                let dtsn_newer = self.update_parent(src, dio.rank, dio.dtsn);
+                if dtsn_newer && self.preferred_parent.get() == Some(src) {
+                    self.schedule_dao(now, DAO_DELAY_MS);
+                }
+                if !self.select_parent(now) {
+                    self.trickle.hear_consistent();
+                }
+            }
+        }
+    }
+
+    fn receive_dao(&self, now: u64, src: IPAddr, dao: Dao) {
+        if !self.is_member() || dao.instance_id != self.instance_id.get() {
+            return;
+        }
+        if dao.dodag_id.map_or(false, |id| id != self.dodag_id.get()) {
+            return;
+        }
+        if self.preferred_parent.get() == Some(src) {
+            // Storing a route through the preferred parent would create a
+            // loop
+            return;
+        }
+
+        let lifetime = dao.lifetime as u64 * self.config.get().lifetime_unit as u64 * 1000;
+        let mut status = DAO_ACK_ACCEPTED;
+        let mut changed = false;
+        for target in dao.targets() {
+            let own = target.prefix_len == 128 && self.interface_list.contains(&target.prefix);
+            if own {
+                continue;
+            }
+            if dao.lifetime == 0 {
+                changed |= self.remove_route(*target, src);
+            } else {
+                match self.add_route(*target, src, now + lifetime) {
+                    Some(new) => changed |= new,
+                    None => status = DAO_ACK_NO_ROUTE_SPACE,
+                }
+            }
+        }
+        if dao.ack_requested {
+            self.dao_ack_pending.set(Some((src, dao.sequence, status)));
+        }
+        if changed {
+            self.schedule_dao(now, DAO_DELAY_MS);
+        }
+    }
+
+    fn receive_dao_ack(&self, now: u64, src: IPAddr, base: u32) {
+        let (instance_id, sequence, status) = dao_ack_decode(base);
+        if self.state.get() != RplState::Joined
+            || instance_id != self.instance_id.get()
+            || self.preferred_parent.get() != Some(src)
+            || self.dao_awaiting_ack.get() != Some(sequence)
+        {
+            return;
+        }
+        self.dao_awaiting_ack.set(None);
+        self.dao_attempts.set(0);
+        if status < DAO_ACK_NO_ROUTE_SPACE {
+            // Refresh the routes halfway through their lifetime
+            self.dao_deadline
+                .set(Some(now + self.config.get().route_lifetime_ms() / 2));
+        } else {
+            self.dao_deadline.set(None);
+            self.remove_parent(src);
+            self.preferred_parent.set(None);
+            self.select_parent(now);
+        }
+    }
+
+    // Sent control messages
+
+    /// Writes the DAO advertising the addresses of the node and its routes
+    /// to `buf`, returning the first word of its base and its length.
+    fn build_dao(&self, buf: &mut [u8], lifetime: u8, ack_requested: bool) -> (u32, usize) {
+        let mut dao = Dao {
+            instance_id: self.instance_id.get(),
+            ack_requested,
+            sequence: self.dao_sequence.get(),
+            dodag_id: Some(self.dodag_id.get()),
+            path_sequence: self.path_sequence.get(),
+            lifetime,
+            targets: [None; MAX_DAO_TARGETS],
+        };
+        let own = self
+            .interface_list
+            .iter()
+            .filter(|addr| {
+                !addr.is_unicast_link_local() && !addr.is_multicast() && !addr.is_unspecified()
+            })
+            .map(|addr| DaoTarget {
+                prefix: *addr,
+                prefix_len: 128,
+            });
+        let routes = self.routes.get();
+        let stored = routes.iter().filter_map(|route| route.map(|r| r.target));
+        for (slot, target) in dao.targets.iter_mut().zip(own.chain(stored)) {
+            *slot = Some(target);
+        }
+        (dao.base(), dao.encode_body(buf))
+    }
+
+    /// Writes the next pending control message to `buf`. Returns its
+    /// destination, code, the first word of its base and the length of its
+    /// body, or `None` if nothing is pending.
+    fn next_message(&self, buf: &mut [u8]) -> Option<(IPAddr, u8, u32, usize)> {
+        if let Some((dst, sequence, status)) = self.dao_ack_pending.take() {
+            buf[..DAO_ACK_BODY_LEN].copy_from_slice(&self.dodag_id.get().0);
+            let base = dao_ack_base(self.instance_id.get(), sequence, status);
+            return Some((dst, rpl_code::DAO_ACK, base, DAO_ACK_BODY_LEN));
+        }
+        if let Some(dst) = self.no_path_pending.take() {
+            self.dao_sequence
+                .set(lollipop_increment(self.dao_sequence.get()));
+            let (base, len) = self.build_dao(buf, 0, false);
+            return Some((dst, rpl_code::DAO, base, len));
+        }
+        if self.dao_pending.take() {
+            if let (RplState::Joined, Some(parent)) =
+                (self.state.get(), self.preferred_parent.get())
+            {
+                let now = self.now();
+                self.dao_sequence
+                    .set(lollipop_increment(self.dao_sequence.get()));
+                self.path_sequence
+                    .set(lollipop_increment(self.path_sequence.get()));
+                self.dao_awaiting_ack.set(Some(self.dao_sequence.get()));
+                self.dao_attempts.set(self.dao_attempts.get() + 1);
+                self.dao_deadline.set(Some(now + DAO_ACK_TIMEOUT_MS));
+                let lifetime = self.config.get().default_lifetime;
+                let (base, len) = self.build_dao(buf, lifetime, true);
+                return Some((parent, rpl_code::DAO, base, len));
+            }
+        }
+        if let Some(dst) = self.dio_pending.take() {
+            let dio = Dio {
+                instance_id: self.instance_id.get(),
+                version: self.version.get(),
+                rank: self.rank.get(),
+                grounded: false,
+                mop: MOP_STORING,
+                preference: 0,
+                dtsn: self.dtsn.get(),
+                dodag_id: self.dodag_id.get(),
+                config: Some(self.config.get()),
+            };
+            return Some((dst, rpl_code::DIO, dio.base(), dio.encode_body(buf)));
+        }
+        if self.dis_pending.take() {
+            return Some((ALL_RPL_NODES, rpl_code::DIS, dis_base(), DIS_BODY_LEN));
+        }
+        None
+    }
+
+    /// Sends the next pending control message, unless a message is being
+    /// sent.
+    fn send_next(&self) {
+        if self.busy.get() {
+            return;
+        }
+        let buf = match self.buffer.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        let (dst, code, base, len) = match self.next_message(buf) {
+            Some(message) => message,
+            None => {
+                self.buffer.replace(buf);
+                return;
+            }
+        };
+
+        let mut icmp_header = ICMP6Header::new(ICMP6Type::Type155);
+        icmp_header.set_code(code);
+        icmp_header.set_options(ICMP6HeaderOptions::Type155 { base });
+        let mut payload = LeasableBuffer::new(buf);
+        payload.slice(..len);
+        self.busy.set(true);
+        let result = self
+            .icmp_sender
+            .send(dst, icmp_header, &payload, self.net_cap);
+        self.buffer.replace(payload.take());
+        if result != ReturnCode::SUCCESS {
+            // The message is dropped; DAOs are resent when their
+            // acknowledgement times out, and DIOs by the Trickle timer
+            self.busy.set(false);
+        }
+        if !self.busy.get() {
+            self.send_next();
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for RplRouter<'a, A> {
+    fn alarm(&self) {
+        let now = self.now();
+        match self.state.get() {
+            RplState::Idle => return,
+            RplState::Joined | RplState::Root => {
+                if self.trickle.poll(now, self.random()) && self.dio_pending.get().is_none() {
+                    self.dio_pending.set(Some(ALL_RPL_NODES));
+                }
+            }
+            RplState::Detached => {
+                if self
+                    .dis_deadline
+                    .get()
+                    .map_or(false, |deadline| now >= deadline)
+                {
+                    self.dis_pending.set(true);
+                    self.dis_deadline.set(Some(now + DIS_INTERVAL_MS));
+                }
+            }
+        }
+        if self
+            .dao_deadline
+            .get()
+            .map_or(false, |deadline| now >= deadline)
+        {
+            self.dao_timeout(now);
+        }
+        self.purge_routes(now);
+        self.send_next();
+        self.schedule_alarm();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6RecvClient for RplRouter<'a, A> {
+    fn receive(&self, header: IP6Header, payload: &[u8]) {
+        if header.get_next_header() != ip6_nh::ICMP || self.state.get() == RplState::Idle {
+            return;
+        }
+        let icmp_header = match ICMP6Header::decode(payload).done() {
+            Some((_offset, icmp_header)) => icmp_header,
+            None => return,
+        };
+        let base = match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type155 { base } => base,
+            _ => return,
+        };
+        // RPL control messages are only exchanged between neighbors
+        let src = header.get_src_addr();
+        if !src.is_unicast_link_local() {
+            return;
+        }
+        let body = &payload[ICMP_HDR_LEN..];
+        let now = self.now();
+        match icmp_header.get_code() {
+            rpl_code::DIS => self.receive_dis(now, src, header.get_dst_addr()),
+            rpl_code::DIO => {
+                if let Some(dio) = Dio::decode(base, body) {
+                    self.receive_dio(now, src, dio);
+                }
+            }
+            rpl_code::DAO => {
+                if let Some(dao) = Dao::decode(base, body) {
+                    self.receive_dao(now, src, dao);
+                }
+            }
+            rpl_code::DAO_ACK => self.receive_dao_ack(now, src, base),
+            _ => return,
+        }
+        self.send_next();
+        self.schedule_alarm();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6Forwarder for RplRouter<'a, A> {
+    fn forward(&self, mut header: IP6Header, payload: &[u8]) -> bool {
+        if !self.is_member() || self.is_local(&header.get_dst_addr()) {
+            return false;
+        }
+        // Packets that cannot be forwarded are dropped
+        let hop_limit = header.get_hop_limit();
+        if hop_limit <= 1 || self.busy.get() || self.route_next_hop(header.dst_addr).is_none() {
+            return true;
+        }
+        header.set_hop_limit(hop_limit - 1);
+        self.busy.set(true);
+        if self.ip_sender.forward(header, payload) != ReturnCode::SUCCESS {
+            self.busy.set(false);
+        }
+        true
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6Router for RplRouter<'a, A> {
+    fn next_hop(&self, dst: IPAddr) -> Option<MacAddress> {
+        if dst.is_multicast() {
+            return Some(MacAddress::Short(0xffff));
+        }
+        if dst.is_unicast_link_local() {
+            return dst.link_local_mac();
+        }
+        if !self.is_member() {
+            return None;
+        }
+        self.route_next_hop(dst)
+            .and_then(|next_hop| next_hop.link_local_mac())
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> ICMP6SendClient for RplRouter<'a, A> {
+    fn send_done(&self, _result: ReturnCode) {
+        self.busy.set(false);
+        self.send_next();
+    }
+}
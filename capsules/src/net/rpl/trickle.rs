@@ -0,0 +1,133 @@
+//! This file implements the Trickle algorithm (RFC 6206), which RPL uses to
+//! schedule the transmission of DIOs: they are sent often while the DODAG is
+//! changing, and less and less often while it is consistent.
+//!
+//! The timer does not own an alarm. Its user passes the current time (in
+//! milliseconds) and a random number to each call, sets its alarm for the
+//! time returned by `next_event`, and calls `poll` when it fires.
+
+use core::cell::Cell;
+
+pub struct Trickle {
+    /// Minimum interval length, Imin, in milliseconds
+    imin_ms: Cell<u32>,
+    /// Number of times Imin is doubled to get the maximum interval length
+    doublings: Cell<u8>,
+    /// Redundancy constant, k. Zero disables suppression.
+    redundancy: Cell<u8>,
+
+    running: Cell<bool>,
+    /// Length of the current interval, I
+    interval_ms: Cell<u32>,
+    /// Start of the current interval
+    interval_start: Cell<u64>,
+    /// Time of the transmission in the current interval, t
+    transmit_at: Cell<u64>,
+    /// Whether the transmission time of the current interval has passed
+    transmit_done: Cell<bool>,
+    /// Number of consistent transmissions heard in the current interval, c
+    counter: Cell<u8>,
+}
+
+impl Default for Trickle {
+    fn default() -> Trickle {
+        Trickle::new()
+    }
+}
+
+impl Trickle {
+    pub fn new() -> Trickle {
+        Trickle {
+            imin_ms: Cell::new(1),
+            doublings: Cell::new(0),
+            redundancy: Cell::new(0),
+            running: Cell::new(false),
+            interval_ms: Cell::new(1),
+            interval_start: Cell::new(0),
+            transmit_at: Cell::new(0),
+            transmit_done: Cell::new(false),
+            counter: Cell::new(0),
+        }
+    }
+
+    /// Sets the parameters of the timer, which take effect at the next
+    /// interval.
+    pub fn configure(&self, imin_ms: u32, doublings: u8, redundancy: u8) {
+        self.imin_ms.set(imin_ms.max(1));
+        self.doublings.set(doublings.min(31));
+        self.redundancy.set(redundancy);
+    }
+
+    /// Starts the timer with an interval of Imin.
+    pub fn start(&self, now: u64, random: u32) {
+        self.running.set(true);
+        self.begin_interval(now, self.imin_ms.get(), random);
+    }
+
+    pub fn stop(&self) {
+        self.running.set(false);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.get()
+    }
+
+    /// Called when an inconsistency is detected: restarts the timer with an
+    /// interval of Imin, unless the current interval already is that short.
+    pub fn reset(&self, now: u64, random: u32) {
+        if !self.running.get() {
+            return;
+        }
+        if self.interval_ms.get() != self.imin_ms.get() {
+            self.begin_interval(now, self.imin_ms.get(), random);
+        }
+    }
+
+    /// Called when a consistent transmission is heard.
+    pub fn hear_consistent(&self) {
+        self.counter.set(self.counter.get().saturating_add(1));
+    }
+
+    /// Returns the time at which `poll` must next be called, or `None` if
+    /// the timer is stopped.
+    pub fn next_event(&self) -> Option<u64> {
+        if !self.running.get() {
+            None
+        } else if self.transmit_done.get() {
+            Some(self.interval_start.get() + self.interval_ms.get() as u64)
+        } else {
+            Some(self.transmit_at.get())
+        }
+    }
+
+    /// Advances the timer to `now`. Returns `true` if a transmission is due
+    /// and has not been suppressed, in which case the caller transmits.
+    pub fn poll(&self, now: u64, random: u32) -> bool {
+        if !self.running.get() {
+            return false;
+        }
+        let mut transmit = false;
+        if !self.transmit_done.get() && now >= self.transmit_at.get() {
+            self.transmit_done.set(true);
+            let k = self.redundancy.get();
+            transmit = k == 0 || self.counter.get() < k;
+        }
+        if now >= self.interval_start.get() + self.interval_ms.get() as u64 {
+            let imax = self.imin_ms.get().saturating_mul(1 << self.doublings.get());
+            let interval = self.interval_ms.get().saturating_mul(2).min(imax);
+            self.begin_interval(now, interval, random);
+        }
+        transmit
+    }
+
+    fn begin_interval(&self, now: u64, interval_ms: u32, random: u32) {
+        // t is chosen uniformly from [I/2, I)
+        let half = interval_ms / 2;
+        let offset = half + random % (interval_ms - half).max(1);
+        self.interval_ms.set(interval_ms);
+        self.interval_start.set(now);
+        self.transmit_at.set(now + offset as u64);
+        self.transmit_done.set(false);
+        self.counter.set(0);
+    }
+}
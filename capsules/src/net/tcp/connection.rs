@@ -0,0 +1,756 @@
+//! This file contains the state machine of a single TCP connection.
+//!
+//! `TCPConnection` follows the connection states of RFC 793, but keeps as
+//! little state as possible so that a connection fits in the grant region of
+//! the process that owns it:
+//!
+//! - No data is buffered by the connection. Data to send stays in a buffer
+//!   owned by the user of the connection until it has been acknowledged, and
+//!   is copied out of it whenever a segment is sent, including
+//!   retransmissions. Received data is copied straight to a buffer of the
+//!   user, and the window advertised to the peer is the free space left in
+//!   that buffer.
+//! - At most one segment that occupies sequence space (SYN, data or FIN) is
+//!   unacknowledged at any time, so the send window is effectively one
+//!   segment. This makes retransmission simple, at the cost of throughput.
+//! - Segments that arrive out of order are dropped and acknowledged with the
+//!   next sequence number expected, so that the peer retransmits them.
+//!
+//! The connection does not send segments or keep time itself. The user of a
+//! connection asks it for the next segment to send with `next_segment()`
+//! whenever the network is free and `wants_to_send()` returns true, passes
+//! received segments to `receive_segment()`, and calls `timer_fired()` once
+//! the time returned by `timer()` has passed. Events that the owner of the
+//! connection should be told about are returned in a `TCPEvents`.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::tcp::{tcp_flags, TCPHeader};
+use core::cmp;
+use kernel::ReturnCode;
+
+/// Initial retransmission timeout, in milliseconds. It is doubled after every
+/// retransmission of the same segment.
+pub const INITIAL_RTO_MS: u32 = 1000;
+
+/// Number of times a segment is retransmitted before the connection is
+/// aborted.
+pub const MAX_RETRANSMISSIONS: u8 = 5;
+
+/// Time spent in the TIME-WAIT state before a connection is closed, in
+/// milliseconds. RFC 793 asks for twice the maximum segment lifetime, which is
+/// far longer than a small device can afford to keep a connection around.
+pub const TIME_WAIT_MS: u32 = 2000;
+
+/// The states of a TCP connection, as defined by RFC 793.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TCPState {
+    Closed = 0,
+    Listen = 1,
+    SynSent = 2,
+    SynReceived = 3,
+    Established = 4,
+    FinWait1 = 5,
+    FinWait2 = 6,
+    CloseWait = 7,
+    Closing = 8,
+    LastAck = 9,
+    TimeWait = 10,
+}
+
+impl Default for TCPState {
+    fn default() -> TCPState {
+        TCPState::Closed
+    }
+}
+
+/// An IPv6 address and TCP port.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TCPEndpoint {
+    pub addr: IPAddr,
+    pub port: u16,
+}
+
+impl Default for TCPEndpoint {
+    fn default() -> TCPEndpoint {
+        TCPEndpoint {
+            addr: IPAddr::new(),
+            port: 0,
+        }
+    }
+}
+
+/// Things that happened to a connection that its owner should know about.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TCPEvents {
+    /// The connection was established.
+    pub connected: bool,
+    /// Number of bytes at the start of the payload of the received segment
+    /// that were accepted, and must be appended to the receive buffer.
+    pub received: usize,
+    /// All data queued with `send()` was acknowledged.
+    pub sent: bool,
+    /// The peer closed its side of the connection; no more data will be
+    /// received.
+    pub remote_closed: bool,
+    /// The connection is closed. `SUCCESS` if it was closed normally,
+    /// `ECANCEL` if it was reset by the peer and `ENOACK` if the peer stopped
+    /// acknowledging segments.
+    pub closed: Option<ReturnCode>,
+    /// A reset segment should be sent in reply.
+    pub reset: Option<TCPHeader>,
+}
+
+/// A segment to be sent on a connection.
+#[derive(Copy, Clone, Debug)]
+pub struct TCPSegment {
+    /// The header of the segment. The checksum is filled in by the IP layer.
+    pub header: TCPHeader,
+    /// Offset of the payload in the buffer passed to `send()`.
+    pub data_offset: usize,
+    /// Length of the payload.
+    pub data_len: usize,
+}
+
+/// Returns true if sequence number `a` comes before `b`.
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// Returns true if sequence number `a` comes before or is equal to `b`.
+fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+/// Build the reset segment sent in reply to a segment that does not belong to
+/// any connection, as described in RFC 793 section 3.4. Returns `None` if
+/// `header` is itself a reset, which is never answered.
+pub fn reset_reply(header: &TCPHeader, data_len: usize) -> Option<TCPHeader> {
+    if header.has_flags(tcp_flags::RST) {
+        return None;
+    }
+    let mut reply = TCPHeader::new();
+    reply.set_src_port(header.get_dst_port());
+    reply.set_dst_port(header.get_src_port());
+    if header.has_flags(tcp_flags::ACK) {
+        reply.set_seq_num(header.get_ack_num());
+        reply.set_flags(tcp_flags::RST);
+    } else {
+        let mut seg_len = data_len as u32;
+        if header.has_flags(tcp_flags::SYN) {
+            seg_len += 1;
+        }
+        if header.has_flags(tcp_flags::FIN) {
+            seg_len += 1;
+        }
+        reply.set_ack_num(header.get_seq_num().wrapping_add(seg_len));
+        reply.set_flags(tcp_flags::RST | tcp_flags::ACK);
+    }
+    Some(reply)
+}
+
+/// The state of a single TCP connection.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TCPConnection {
+    state: TCPState,
+    local: TCPEndpoint,
+    remote: TCPEndpoint,
+    /// Whether the connection was opened by `listen()`.
+    passive: bool,
+
+    /// Initial send sequence number.
+    iss: u32,
+    /// Oldest unacknowledged sequence number.
+    snd_una: u32,
+    /// Next sequence number to send.
+    snd_nxt: u32,
+    /// Window last advertised by the peer.
+    snd_wnd: u16,
+    /// Next sequence number expected from the peer.
+    rcv_nxt: u32,
+
+    /// Length of the data queued by `send()`.
+    tx_len: usize,
+    /// Number of bytes of the queued data that were acknowledged.
+    tx_acked: usize,
+    /// `close()` was called, so a FIN is sent once all data is acknowledged.
+    fin_queued: bool,
+    /// A FIN was sent.
+    fin_sent: bool,
+
+    /// An acknowledgment should be sent even if there is nothing else to
+    /// send.
+    ack_pending: bool,
+    /// The unacknowledged segment should be sent again.
+    retransmit: bool,
+    /// A window probe should be sent, because the peer's window is closed.
+    probe: bool,
+    /// Number of times the unacknowledged segment was retransmitted.
+    retries: u8,
+    /// Time the running timer was started at, and its length in
+    /// milliseconds.
+    timer: Option<(u32, u32)>,
+}
+
+impl TCPConnection {
+    pub fn new() -> TCPConnection {
+        TCPConnection::default()
+    }
+
+    pub fn get_state(&self) -> TCPState {
+        self.state
+    }
+
+    pub fn get_local(&self) -> TCPEndpoint {
+        self.local
+    }
+
+    pub fn get_remote(&self) -> TCPEndpoint {
+        self.remote
+    }
+
+    /// Returns true if the connection uses local port `port`, in any state
+    /// other than closed.
+    pub fn uses_port(&self, port: u16) -> bool {
+        self.state != TCPState::Closed && self.local.port == port
+    }
+
+    /// Returns true if a segment from `src` to `dst` belongs to this
+    /// connection. A listening connection is not matched; see
+    /// `is_listening_on()`.
+    pub fn matches(&self, src: TCPEndpoint, dst: TCPEndpoint) -> bool {
+        match self.state {
+            TCPState::Closed | TCPState::Listen => false,
+            _ => self.local == dst && self.remote == src,
+        }
+    }
+
+    /// Returns true if the connection is listening for segments sent to
+    /// `dst` from any remote endpoint.
+    pub fn is_listening_on(&self, dst: TCPEndpoint) -> bool {
+        self.state == TCPState::Listen && self.local == dst
+    }
+
+    /// Returns true if data queued with `send()` has not been fully
+    /// acknowledged yet.
+    pub fn is_sending(&self) -> bool {
+        self.tx_len > 0
+    }
+
+    /// The running timer, as the time it was started at and its length in
+    /// milliseconds.
+    pub fn timer(&self) -> Option<(u32, u32)> {
+        self.timer
+    }
+
+    /// Number of sequence numbers sent but not acknowledged.
+    fn in_flight(&self) -> u32 {
+        self.snd_nxt.wrapping_sub(self.snd_una)
+    }
+
+    /// Returns true if data may be sent in the current state.
+    fn can_send_data(&self) -> bool {
+        self.state == TCPState::Established || self.state == TCPState::CloseWait
+    }
+
+    fn data_remaining(&self) -> usize {
+        self.tx_len - self.tx_acked
+    }
+
+    fn start_timer(&mut self, now: u32, length_ms: u32) {
+        self.timer = Some((now, length_ms));
+    }
+
+    fn start_retransmit_timer(&mut self, now: u32) {
+        let rto = INITIAL_RTO_MS << cmp::min(self.retries, MAX_RETRANSMISSIONS);
+        self.start_timer(now, rto);
+    }
+
+    /// Return the connection to the closed state, clearing all of its state.
+    fn reset(&mut self) {
+        *self = TCPConnection::new();
+    }
+
+    /// Start opening a connection from `local` to `remote`, using `iss` as
+    /// the initial sequence number.
+    pub fn connect(&mut self, local: TCPEndpoint, remote: TCPEndpoint, iss: u32) -> ReturnCode {
+        if self.state != TCPState::Closed {
+            return ReturnCode::EALREADY;
+        }
+        self.reset();
+        self.state = TCPState::SynSent;
+        self.local = local;
+        self.remote = remote;
+        self.iss = iss;
+        self.snd_una = iss;
+        self.snd_nxt = iss;
+        ReturnCode::SUCCESS
+    }
+
+    /// Wait for a connection from any remote endpoint to `local`.
+    pub fn listen(&mut self, local: TCPEndpoint) -> ReturnCode {
+        if self.state != TCPState::Closed {
+            return ReturnCode::EALREADY;
+        }
+        self.reset();
+        self.state = TCPState::Listen;
+        self.local = local;
+        self.passive = true;
+        ReturnCode::SUCCESS
+    }
+
+    /// Queue `len` bytes of data to be sent. The data must stay in the buffer
+    /// segments are built from until the `sent` event.
+    pub fn send(&mut self, len: usize) -> ReturnCode {
+        if !self.can_send_data() || self.fin_queued {
+            return ReturnCode::EOFF;
+        }
+        if self.tx_len > 0 {
+            return ReturnCode::EBUSY;
+        }
+        if len == 0 {
+            return ReturnCode::EINVAL;
+        }
+        self.tx_len = len;
+        self.tx_acked = 0;
+        ReturnCode::SUCCESS
+    }
+
+    /// Close the sending side of the connection once all queued data has been
+    /// acknowledged. Returns `Some` with the events if this closed the
+    /// connection immediately.
+    pub fn close(&mut self) -> Result<Option<TCPEvents>, ReturnCode> {
+        match self.state {
+            TCPState::Closed => Err(ReturnCode::EALREADY),
+            TCPState::Listen | TCPState::SynSent => {
+                self.reset();
+                Ok(Some(TCPEvents {
+                    closed: Some(ReturnCode::SUCCESS),
+                    ..TCPEvents::default()
+                }))
+            }
+            TCPState::SynReceived | TCPState::Established | TCPState::CloseWait => {
+                if self.fin_queued {
+                    return Err(ReturnCode::EALREADY);
+                }
+                self.fin_queued = true;
+                Ok(None)
+            }
+            _ => Err(ReturnCode::EALREADY),
+        }
+    }
+
+    /// Close the connection immediately. Returns the reset segment that
+    /// should be sent to the peer, if any.
+    pub fn abort(&mut self) -> Option<TCPHeader> {
+        let reset = match self.state {
+            TCPState::Closed | TCPState::Listen | TCPState::SynSent | TCPState::TimeWait => None,
+            _ => Some(self.header(tcp_flags::RST | tcp_flags::ACK, 0)),
+        };
+        self.reset();
+        reset
+    }
+
+    /// Tell the connection that space was freed in the receive buffer, so
+    /// that the larger window is advertised to the peer.
+    pub fn window_opened(&mut self) {
+        match self.state {
+            TCPState::Established | TCPState::FinWait1 | TCPState::FinWait2 => {
+                self.ack_pending = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns true if `next_segment()` would return a segment.
+    pub fn wants_to_send(&self) -> bool {
+        match self.state {
+            TCPState::Closed | TCPState::Listen | TCPState::TimeWait => self.ack_pending,
+            _ => {
+                self.ack_pending
+                    || self.retransmit
+                    || self.probe
+                    || (self.in_flight() == 0 && self.has_new_segment())
+            }
+        }
+    }
+
+    /// Returns true if there is a SYN, data or FIN that has not been sent
+    /// yet and may be sent now.
+    fn has_new_segment(&self) -> bool {
+        match self.state {
+            TCPState::SynSent | TCPState::SynReceived if self.snd_nxt == self.iss => true,
+            _ => {
+                if self.can_send_data() && self.data_remaining() > 0 {
+                    self.snd_wnd > 0
+                } else {
+                    self.fin_queued && !self.fin_sent && self.data_remaining() == 0
+                }
+            }
+        }
+    }
+
+    fn header(&self, flags: u16, window: u16) -> TCPHeader {
+        let mut header = TCPHeader::new();
+        header.set_src_port(self.local.port);
+        header.set_dst_port(self.remote.port);
+        header.set_seq_num(self.snd_nxt);
+        header.set_ack_num(if flags & tcp_flags::ACK != 0 {
+            self.rcv_nxt
+        } else {
+            0
+        });
+        header.set_flags(flags);
+        header.set_window(window);
+        header
+    }
+
+    /// Build the next segment to send, and update the connection as if it
+    /// was sent.
+    ///
+    /// # Arguments
+    /// `now` - Current time, which is used as the start time of any timer
+    /// started
+    /// `rx_window` - Free space in the receive buffer, advertised as the
+    /// window
+    /// `max_data` - Largest payload that may be sent
+    pub fn next_segment(
+        &mut self,
+        now: u32,
+        rx_window: usize,
+        max_data: usize,
+    ) -> Option<TCPSegment> {
+        if !self.wants_to_send() {
+            return None;
+        }
+        let window = cmp::min(rx_window, u16::max_value() as usize) as u16;
+        let mut data_offset = self.tx_acked;
+        let mut data_len = 0;
+
+        let header = if self.retransmit || (self.in_flight() == 0 && self.has_new_segment()) {
+            // Send the unacknowledged segment again, or the next new one.
+            let new_segment = !self.retransmit;
+            self.retransmit = false;
+            self.snd_nxt = self.snd_una;
+
+            let header = if self.snd_una == self.iss
+                && (self.state == TCPState::SynSent || self.state == TCPState::SynReceived)
+            {
+                self.snd_nxt = self.iss.wrapping_add(1);
+                if self.state == TCPState::SynSent {
+                    self.header(tcp_flags::SYN, window)
+                } else {
+                    self.header(tcp_flags::SYN | tcp_flags::ACK, window)
+                }
+            } else if self.can_send_data() && self.data_remaining() > 0 {
+                data_len = cmp::min(
+                    cmp::min(self.data_remaining(), max_data),
+                    self.snd_wnd as usize,
+                );
+                let header = self.header(tcp_flags::ACK | tcp_flags::PSH, window);
+                self.snd_nxt = self.snd_nxt.wrapping_add(data_len as u32);
+                header
+            } else if self.fin_queued && (self.fin_sent || new_segment) {
+                let header = self.header(tcp_flags::FIN | tcp_flags::ACK, window);
+                self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                if !self.fin_sent {
+                    self.fin_sent = true;
+                    self.state = match self.state {
+                        TCPState::CloseWait => TCPState::LastAck,
+                        _ => TCPState::FinWait1,
+                    };
+                }
+                header
+            } else {
+                self.header(tcp_flags::ACK, window)
+            };
+            if self.in_flight() > 0 {
+                self.start_retransmit_timer(now);
+            }
+            header
+        } else if self.probe {
+            // The peer's window is closed: send one byte beyond it to learn
+            // when it opens again.
+            self.probe = false;
+            data_offset = self.tx_acked;
+            data_len = cmp::min(1, self.data_remaining());
+            let header = self.header(tcp_flags::ACK, window);
+            self.snd_nxt = self.snd_nxt.wrapping_add(data_len as u32);
+            if self.in_flight() > 0 {
+                self.start_retransmit_timer(now);
+            }
+            header
+        } else {
+            self.header(tcp_flags::ACK, window)
+        };
+
+        // Every segment carries an acknowledgment, except for the first SYN.
+        self.ack_pending = false;
+
+        // Wait for the peer's window to open if there is data left to send.
+        if self.in_flight() == 0
+            && self.timer.is_none()
+            && self.can_send_data()
+            && self.data_remaining() > 0
+            && self.snd_wnd == 0
+        {
+            self.start_retransmit_timer(now);
+        }
+
+        Some(TCPSegment {
+            header,
+            data_offset,
+            data_len,
+        })
+    }
+
+    /// Handle the expiry of the timer returned by `timer()`.
+    pub fn timer_fired(&mut self) -> TCPEvents {
+        let mut events = TCPEvents::default();
+        self.timer = None;
+        if self.state == TCPState::TimeWait {
+            self.reset();
+            events.closed = Some(ReturnCode::SUCCESS);
+        } else if self.can_send_data() && self.data_remaining() > 0 && self.snd_wnd == 0 {
+            // Nothing is lost while the peer's window is closed, so probe it
+            // without counting this as a retransmission.
+            self.snd_nxt = self.snd_una;
+            self.probe = true;
+        } else if self.in_flight() > 0 {
+            if self.retries >= MAX_RETRANSMISSIONS {
+                let reset = self.abort();
+                events.closed = Some(ReturnCode::ENOACK);
+                events.reset = reset;
+            } else {
+                self.retries += 1;
+                self.retransmit = true;
+            }
+        }
+        events
+    }
+
+    /// Process a segment received for this connection.
+    ///
+    /// # Arguments
+    /// `src` - Endpoint the segment was sent from
+    /// `header` - Header of the segment
+    /// `data_len` - Length of the payload of the segment
+    /// `rx_space` - Free space in the receive buffer
+    /// `iss` - Initial sequence number to use if this segment opens a
+    /// connection to a listening socket
+    /// `now` - Current time, which is used as the start time of any timer
+    /// started
+    pub fn receive_segment(
+        &mut self,
+        src: TCPEndpoint,
+        header: &TCPHeader,
+        data_len: usize,
+        rx_space: usize,
+        iss: u32,
+        now: u32,
+    ) -> TCPEvents {
+        let mut events = TCPEvents::default();
+        let seq = header.get_seq_num();
+        let ack = header.get_ack_num();
+        let rst = header.has_flags(tcp_flags::RST);
+        let syn = header.has_flags(tcp_flags::SYN);
+        let has_ack = header.has_flags(tcp_flags::ACK);
+        let fin = header.has_flags(tcp_flags::FIN);
+
+        match self.state {
+            TCPState::Closed => {
+                events.reset = reset_reply(header, data_len);
+                return events;
+            }
+            TCPState::Listen => {
+                if rst {
+                    return events;
+                }
+                if has_ack {
+                    events.reset = reset_reply(header, data_len);
+                    return events;
+                }
+                if syn {
+                    // Any data sent with the SYN is dropped, and will be
+                    // retransmitted by the peer.
+                    self.remote = src;
+                    self.rcv_nxt = seq.wrapping_add(1);
+                    self.iss = iss;
+                    self.snd_una = iss;
+                    self.snd_nxt = iss;
+                    self.snd_wnd = header.get_window();
+                    self.state = TCPState::SynReceived;
+                }
+                return events;
+            }
+            TCPState::SynSent => {
+                let ack_ok = has_ack && seq_lt(self.iss, ack) && seq_le(ack, self.snd_nxt);
+                if has_ack && !ack_ok {
+                    events.reset = reset_reply(header, data_len);
+                    return events;
+                }
+                if rst {
+                    if ack_ok {
+                        self.reset();
+                        events.closed = Some(ReturnCode::ECANCEL);
+                    }
+                    return events;
+                }
+                if syn {
+                    self.rcv_nxt = seq.wrapping_add(1);
+                    self.snd_wnd = header.get_window();
+                    if ack_ok {
+                        self.ack_pending = true;
+                        self.snd_una = ack;
+                        self.state = TCPState::Established;
+                        self.retries = 0;
+                        self.timer = None;
+                        events.connected = true;
+                    } else {
+                        // Both ends opened the connection at the same time:
+                        // acknowledge the peer's SYN with our own.
+                        self.state = TCPState::SynReceived;
+                        self.retransmit = true;
+                    }
+                }
+                return events;
+            }
+            _ => {}
+        }
+
+        // Only segments starting at the next expected sequence number are
+        // accepted; anything else is answered with an acknowledgment.
+        if seq != self.rcv_nxt {
+            if !rst {
+                self.ack_pending = true;
+            }
+            return events;
+        }
+
+        if rst {
+            if self.state == TCPState::SynReceived && self.passive {
+                let local = self.local;
+                self.reset();
+                self.state = TCPState::Listen;
+                self.local = local;
+                self.passive = true;
+            } else {
+                self.reset();
+                events.closed = Some(ReturnCode::ECANCEL);
+            }
+            return events;
+        }
+
+        if syn {
+            // A SYN inside the window of a synchronized connection is an
+            // error, and resets the connection.
+            events.reset = self.abort();
+            events.closed = Some(ReturnCode::ECANCEL);
+            return events;
+        }
+
+        if !has_ack {
+            return events;
+        }
+
+        if self.state == TCPState::SynReceived {
+            if seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt) {
+                self.state = TCPState::Established;
+                events.connected = true;
+            } else {
+                events.reset = reset_reply(header, data_len);
+                return events;
+            }
+        }
+
+        if seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt) {
+            let mut acked = ack.wrapping_sub(self.snd_una) as usize;
+            if self.snd_una == self.iss {
+                // The SYN is acknowledged
+                acked -= 1;
+            }
+            let fin_acked = self.fin_sent && ack == self.snd_nxt;
+            if fin_acked {
+                acked -= 1;
+            }
+            self.tx_acked = cmp::min(self.tx_acked + acked, self.tx_len);
+            self.snd_una = ack;
+            self.retries = 0;
+            self.retransmit = false;
+            self.timer = None;
+            if self.tx_len > 0 && self.tx_acked == self.tx_len {
+                self.tx_len = 0;
+                self.tx_acked = 0;
+                events.sent = true;
+            }
+
+            if fin_acked {
+                match self.state {
+                    TCPState::FinWait1 => self.state = TCPState::FinWait2,
+                    TCPState::Closing => {
+                        self.state = TCPState::TimeWait;
+                        self.start_timer(now, TIME_WAIT_MS);
+                    }
+                    TCPState::LastAck => {
+                        self.reset();
+                        events.closed = Some(ReturnCode::SUCCESS);
+                        return events;
+                    }
+                    _ => {}
+                }
+            }
+        } else if seq_lt(self.snd_nxt, ack) {
+            // Acknowledges something that was not sent
+            self.ack_pending = true;
+            return events;
+        }
+        if seq_le(self.snd_una, ack) {
+            self.snd_wnd = header.get_window();
+            if self.snd_wnd > 0 {
+                self.probe = false;
+            }
+        }
+
+        let mut all_data_accepted = true;
+        if data_len > 0 {
+            match self.state {
+                TCPState::Established | TCPState::FinWait1 | TCPState::FinWait2 => {
+                    events.received = cmp::min(data_len, rx_space);
+                    self.rcv_nxt = self.rcv_nxt.wrapping_add(events.received as u32);
+                    all_data_accepted = events.received == data_len;
+                }
+                _ => {}
+            }
+            self.ack_pending = true;
+        }
+
+        if fin && all_data_accepted {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.ack_pending = true;
+            match self.state {
+                TCPState::Established => {
+                    self.state = TCPState::CloseWait;
+                    events.remote_closed = true;
+                }
+                TCPState::FinWait1 => {
+                    // Our FIN was not acknowledged yet, or the state would be
+                    // FIN-WAIT-2.
+                    self.state = TCPState::Closing;
+                    events.remote_closed = true;
+                }
+                TCPState::FinWait2 => {
+                    self.state = TCPState::TimeWait;
+                    self.start_timer(now, TIME_WAIT_MS);
+                    events.remote_closed = true;
+                }
+                TCPState::TimeWait => {
+                    self.start_timer(now, TIME_WAIT_MS);
+                }
+                _ => {
+                    // A retransmitted FIN was already counted.
+                    self.rcv_nxt = self.rcv_nxt.wrapping_sub(1);
+                }
+            }
+        }
+        events
+    }
+}
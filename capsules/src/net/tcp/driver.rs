@@ -0,0 +1,781 @@
+//! TCP userspace interface.
+//!
+//! Implements a userspace interface for opening TCP connections and sending
+//! and receiving data on them. Each process can have a single connection at
+//! a time, which is opened either actively, by connecting to a remote
+//! endpoint, or passively, by listening on a local port. The state of the
+//! connection is kept in the grant region of the process, and the connection
+//! is closed with it if the process dies.
+//!
+//! The connection does not buffer data in the kernel: data to send is read
+//! from the write buffer of the process each time a segment is sent, and
+//! received data is copied into the read buffer of the process, whose free
+//! space is the window advertised to the peer. See `connection.rs` for the
+//! details of the state machine.
+//!
+//! Segments are sent through an `IP6Sender` of their own, and received from
+//! the TCP client of the `IP6Receiver`. A single virtual alarm drives the
+//! retransmission and TIME-WAIT timers of all connections.
+//!
+//! Initial sequence numbers are chosen as in RFC 6528: the time from the
+//! alarm plus a keyed hash (SipHash-2-4) of the local and remote endpoints.
+//! The key is taken from an RNG when the driver starts, and connections cannot
+//! be opened until it has arrived. An attacker that cannot see the segments
+//! of a connection therefore cannot guess its sequence numbers.
+//!
+//! Endpoints in the config buffer are encoded as a 16 byte IPv6 address
+//! followed by a port in host byte order, like those of the UDP driver.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6LinkSecurity, IP6Router, IP6SendClient, IP6Sender};
+use crate::net::ipv6::{IP6Header, TransportHeader};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::tcp::connection::{self, TCPConnection, TCPEndpoint, TCPEvents};
+use crate::net::tcp::TCPHeader;
+use crate::net::util::host_slice_to_u16;
+use core::cell::Cell;
+use core::{cmp, mem};
+use kernel::common::cells::{MapCell, OptionalCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::rng;
+use kernel::hil::time::{self, Ticks};
+use kernel::{debug, AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Tcp as usize;
+
+/// Length of an endpoint in the config buffer.
+const ENDPOINT_LEN: usize = mem::size_of::<IPAddr>() + mem::size_of::<u16>();
+
+/// Values passed as the first argument of the event callback.
+pub const EVENT_CONNECTED: usize = 0;
+pub const EVENT_REMOTE_CLOSED: usize = 1;
+pub const EVENT_CLOSED: usize = 2;
+
+#[derive(Default)]
+pub struct App {
+    rx_callback: Option<Callback>,
+    tx_callback: Option<Callback>,
+    event_callback: Option<Callback>,
+    app_read: Option<AppSlice<Shared, u8>>,
+    app_write: Option<AppSlice<Shared, u8>>,
+    app_cfg: Option<AppSlice<Shared, u8>>,
+    connection: TCPConnection,
+    /// Number of received bytes in `app_read` that the app has not consumed.
+    rx_len: usize,
+    /// Whether the app is waiting for the send callback.
+    tx_pending: bool,
+}
+
+impl App {
+    fn rx_space(&self) -> usize {
+        self.app_read
+            .as_ref()
+            .map_or(0, |read| read.len().saturating_sub(self.rx_len))
+    }
+
+    /// Tell the app about what happened to its connection.
+    fn report(&mut self, events: &TCPEvents) {
+        if events.connected {
+            let remote = self.connection.get_remote();
+            if let Some(cfg) = self.app_cfg.as_mut() {
+                if cfg.len() >= 2 * ENDPOINT_LEN {
+                    encode_endpoint(&remote, &mut cfg.as_mut()[ENDPOINT_LEN..]);
+                }
+            }
+            self.event_callback
+                .map(|mut cb| cb.schedule(EVENT_CONNECTED, 0, 0));
+        }
+        if events.received > 0 {
+            let rx_len = self.rx_len;
+            self.rx_callback.map(|mut cb| cb.schedule(rx_len, 0, 0));
+        }
+        if events.sent {
+            self.tx_pending = false;
+            self.tx_callback
+                .map(|mut cb| cb.schedule(usize::from(ReturnCode::SUCCESS), 0, 0));
+        }
+        if events.remote_closed {
+            self.event_callback
+                .map(|mut cb| cb.schedule(EVENT_REMOTE_CLOSED, 0, 0));
+        }
+        if let Some(result) = events.closed {
+            if self.tx_pending {
+                self.tx_pending = false;
+                self.tx_callback
+                    .map(|mut cb| cb.schedule(usize::from(result), 0, 0));
+            }
+            self.rx_len = 0;
+            self.event_callback
+                .map(|mut cb| cb.schedule(EVENT_CLOSED, usize::from(result), 0));
+        }
+    }
+}
+
+fn encode_endpoint(endpoint: &TCPEndpoint, buf: &mut [u8]) {
+    buf[..mem::size_of::<IPAddr>()].copy_from_slice(&endpoint.addr.0);
+    buf[mem::size_of::<IPAddr>()..ENDPOINT_LEN].copy_from_slice(&endpoint.port.to_le_bytes());
+}
+
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13) ^ v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16) ^ v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21) ^ v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17) ^ v[2];
+    v[2] = v[2].rotate_left(32);
+}
+
+fn sip_compress(v: &mut [u64; 4], word: u64) {
+    v[3] ^= word;
+    sip_round(v);
+    sip_round(v);
+    v[0] ^= word;
+}
+
+/// SipHash-2-4 of `data` with `key`.
+fn siphash(key: [u64; 2], data: &[u8]) -> u64 {
+    let mut v = [
+        key[0] ^ 0x736f_6d65_7073_6575,
+        key[1] ^ 0x646f_7261_6e64_6f6d,
+        key[0] ^ 0x6c79_6765_6e65_7261,
+        key[1] ^ 0x7465_6462_7974_6573,
+    ];
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut word = [0; 8];
+        word.copy_from_slice(chunk);
+        sip_compress(&mut v, u64::from_le_bytes(word));
+    }
+    let mut last = [0; 8];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    last[7] = data.len() as u8;
+    sip_compress(&mut v, u64::from_le_bytes(last));
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        sip_round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+fn parse_endpoint(buf: &[u8]) -> TCPEndpoint {
+    let (a, p) = buf[..ENDPOINT_LEN].split_at(mem::size_of::<IPAddr>());
+    let mut addr = IPAddr::new();
+    addr.0.copy_from_slice(a);
+    TCPEndpoint {
+        addr,
+        port: host_slice_to_u16(p),
+    }
+}
+
+pub struct TCPDriver<'a, A: time::Alarm<'a>> {
+    /// IPv6 sender the segments are sent with
+    sender: &'a dyn IP6Sender<'a>,
+
+    /// Alarm used for the timers of all connections
+    alarm: &'a A,
+
+    /// RNG the key for initial sequence numbers is taken from
+    rng: &'a dyn rng::Rng<'a>,
+
+    /// Key for initial sequence numbers, once enough randomness arrived
+    iss_key: OptionalCell<[u64; 2]>,
+
+    /// Random words received for the key so far
+    key_words: Cell<[u32; 4]>,
+    key_len: Cell<usize>,
+
+    /// Grant of apps that use this driver.
+    apps: Grant<App>,
+
+    /// Whether a segment is being sent.
+    busy: Cell<bool>,
+
+    /// List of IP Addresses of the interfaces on the device
+    interface_list: &'static [IPAddr],
+
+    /// Buffer the payload of a segment is copied to before it is sent
+    kernel_buffer: MapCell<LeasableBuffer<'static, u8>>,
+
+    /// A reset segment to send, and the address to send it to
+    pending_reset: OptionalCell<(IPAddr, TCPHeader)>,
+
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> TCPDriver<'a, A> {
+    pub fn new(
+        sender: &'a dyn IP6Sender<'a>,
+        alarm: &'a A,
+        rng: &'a dyn rng::Rng<'a>,
+        grant: Grant<App>,
+        interface_list: &'static [IPAddr],
+        kernel_buffer: LeasableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> TCPDriver<'a, A> {
+        TCPDriver {
+            sender,
+            alarm,
+            rng,
+            iss_key: OptionalCell::empty(),
+            key_words: Cell::new([0; 4]),
+            key_len: Cell::new(0),
+            apps: grant,
+            busy: Cell::new(false),
+            interface_list,
+            kernel_buffer: MapCell::new(kernel_buffer),
+            pending_reset: OptionalCell::empty(),
+            net_cap,
+        }
+    }
+
+    /// Request the key for initial sequence numbers from the RNG. Connections
+    /// cannot be opened until it has arrived.
+    pub fn start(&self) -> ReturnCode {
+        self.rng.get()
+    }
+
+    /// Sets the router that chooses the next hop of the segments sent.
+    pub fn set_router(&self, router: &'a dyn IP6Router) {
+        self.sender.set_router(router);
+    }
+
+    /// Sets the layer that chooses the MAC security of the segments sent.
+    pub fn set_link_security(&self, link_security: &'a dyn IP6LinkSecurity) {
+        self.sender.set_link_security(link_security);
+    }
+
+    /// Utility function to perform an action on an app in a system call.
+    #[inline]
+    fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| closure(app))
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn now(&self) -> u32 {
+        self.alarm.now().into_u32()
+    }
+
+    /// Initial sequence number for a connection from `local` to `remote`, as
+    /// in RFC 6528, or `None` if the key has not arrived yet.
+    fn iss(&self, local: &TCPEndpoint, remote: &TCPEndpoint) -> Option<u32> {
+        self.iss_key.map(|key| {
+            let mut tuple = [0; 2 * ENDPOINT_LEN];
+            encode_endpoint(local, &mut tuple);
+            encode_endpoint(remote, &mut tuple[ENDPOINT_LEN..]);
+            self.now().wrapping_add(siphash(*key, &tuple) as u32)
+        })
+    }
+
+    /// Queue a reset segment. If one is already waiting to be sent, the new
+    /// one is dropped; the peer will retransmit and be reset again.
+    fn queue_reset(&self, dst: IPAddr, header: Option<TCPHeader>) {
+        if let Some(header) = header {
+            if self.pending_reset.is_none() {
+                self.pending_reset.set((dst, header));
+            }
+        }
+    }
+
+    /// Check that `endpoint` can be used as the local endpoint of a new
+    /// connection of `appid`: its address is one of the interfaces, and no
+    /// other connection uses its port.
+    fn check_local_endpoint(&self, appid: AppId, endpoint: &TCPEndpoint) -> ReturnCode {
+        if endpoint.port == 0 || !self.interface_list.contains(&endpoint.addr) {
+            return ReturnCode::EINVAL;
+        }
+        let mut in_use = false;
+        for app in self.apps.iter() {
+            app.enter(|other_app, _| {
+                if other_app.appid() != appid && other_app.connection.uses_port(endpoint.port) {
+                    in_use = true;
+                }
+            });
+        }
+        if in_use {
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    /// Send segments until the IP sender is busy or nothing is left to send.
+    fn send_next(&self) {
+        while !self.busy.get() {
+            if !self.send_one() {
+                break;
+            }
+        }
+    }
+
+    /// Send the next segment that is waiting, if there is one. Resets are
+    /// sent first, then segments of the connections in app order. Returns
+    /// false if there was nothing to send.
+    fn send_one(&self) -> bool {
+        let mut buf = match self.kernel_buffer.take() {
+            Some(buf) => buf,
+            None => return false,
+        };
+
+        let mut next = self
+            .pending_reset
+            .take()
+            .map(|(dst, header)| (dst, header, 0));
+        if next.is_none() {
+            let now = self.now();
+            let max_data = buf.len();
+            for app in self.apps.iter() {
+                app.enter(|app, _| {
+                    if !app.connection.wants_to_send() {
+                        return;
+                    }
+                    let rx_space = app.rx_space();
+                    let remote = app.connection.get_remote();
+                    if let Some(segment) = app.connection.next_segment(now, rx_space, max_data) {
+                        let mut len = 0;
+                        if let Some(write) = app.app_write.as_ref() {
+                            let write = write.as_ref();
+                            let start = cmp::min(segment.data_offset, write.len());
+                            let end = cmp::min(start + segment.data_len, write.len());
+                            len = end - start;
+                            buf[..len].copy_from_slice(&write[start..end]);
+                        }
+                        next = Some((remote.addr, segment.header, len));
+                    }
+                });
+                if next.is_some() {
+                    break;
+                }
+            }
+        }
+
+        match next {
+            Some((dst, header, len)) => {
+                buf.slice(0..len);
+                self.busy.set(true);
+                let result =
+                    self.sender
+                        .send_to(dst, TransportHeader::TCP(header), &buf, self.net_cap);
+                buf.reset();
+                self.kernel_buffer.replace(buf);
+                if result != ReturnCode::SUCCESS {
+                    // The segment is lost; retransmission recovers from this
+                    // like from any other lost segment.
+                    debug!("[TCP] Error sending segment: {:?}", result);
+                    self.busy.set(false);
+                }
+                true
+            }
+            None => {
+                self.kernel_buffer.replace(buf);
+                false
+            }
+        }
+    }
+
+    /// Set the alarm for the connection timer that expires first, if any is
+    /// running.
+    fn update_alarm(&self) {
+        let now = self.alarm.now();
+        let mut earliest: Option<A::Ticks> = None;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if let Some((start, length_ms)) = app.connection.timer() {
+                    let elapsed = now.wrapping_sub(A::Ticks::from(start));
+                    let length = A::ticks_from_ms(length_ms);
+                    let remaining = if elapsed >= length {
+                        A::Ticks::from(0)
+                    } else {
+                        length.wrapping_sub(elapsed)
+                    };
+                    if earliest.map_or(true, |earliest| remaining < earliest) {
+                        earliest = Some(remaining);
+                    }
+                }
+            });
+        }
+        match earliest {
+            Some(remaining) => {
+                self.alarm
+                    .set_alarm(now, cmp::max(remaining, self.alarm.minimum_dt()));
+            }
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+
+    /// Send any segments that are waiting and update the alarm, after the
+    /// state of a connection changed.
+    fn connections_changed(&self) {
+        self.send_next();
+        self.update_alarm();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> Driver for TCPDriver<'a, A> {
+    /// Setup buffers to read/write from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Read buffer. Received data is appended to it, and the free
+    ///        space left in it is the window advertised to the peer.
+    /// - `1`: Write buffer. Contains the data to send. It cannot be changed
+    ///        while data is being sent.
+    /// - `2`: Config buffer. Contains the local endpoint, followed by the
+    ///        remote endpoint, of the connection.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self.do_with_app(appid, |app| {
+                app.app_read = slice;
+                app.rx_len = 0;
+                app.connection.window_opened();
+                ReturnCode::SUCCESS
+            }),
+            1 => self.do_with_app(appid, |app| {
+                if app.connection.is_sending() {
+                    return ReturnCode::EBUSY;
+                }
+                app.app_write = slice;
+                ReturnCode::SUCCESS
+            }),
+            2 => self.do_with_app(appid, |app| {
+                app.app_cfg = slice;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Data was received. The callback receives the number of bytes
+    ///        in the read buffer that have not been consumed.
+    /// - `1`: All data queued with command `3` was acknowledged. The
+    ///        callback receives `SUCCESS`, or the error that closed the
+    ///        connection before the data was acknowledged.
+    /// - `2`: Connection events. The callback receives the event and, for
+    ///        `EVENT_CLOSED`, the reason the connection was closed: `SUCCESS`
+    ///        if it was closed normally, `ECANCEL` if it was reset by the
+    ///        peer and `ENOACK` if the peer stopped responding.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self.do_with_app(app_id, |app| {
+                app.rx_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            1 => self.do_with_app(app_id, |app| {
+                app.tx_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            2 => self.do_with_app(app_id, |app| {
+                app.event_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// TCP control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Connect from the local endpoint in the config buffer to the
+    ///        remote endpoint following it. Returns EINVAL if the config
+    ///        buffer is too short or the local address is not an interface
+    ///        of this device, EBUSY if another app uses the local port or
+    ///        the driver has not received its key for initial sequence
+    ///        numbers yet, and EALREADY if the app already has a connection.
+    ///        The event callback is called with `EVENT_CONNECTED` once the
+    ///        connection is established.
+    /// - `2`: Listen for a connection to the local endpoint in the config
+    ///        buffer. Returns the same errors as `1`. Once a connection is
+    ///        established, the remote endpoint is written to the config
+    ///        buffer and the event callback is called with
+    ///        `EVENT_CONNECTED`.
+    /// - `3`: Send the first `arg1` bytes of the write buffer. Returns EOFF
+    ///        if the connection is not established or is being closed, EBUSY
+    ///        if data is already being sent and EINVAL if the write buffer is
+    ///        shorter than `arg1`. The write buffer must not be changed until
+    ///        the send callback.
+    /// - `4`: The app consumed the data in the read buffer. Received data is
+    ///        written to the start of the read buffer again.
+    /// - `5`: Close the connection once all data has been sent.
+    /// - `6`: Abort the connection, resetting it.
+    /// - `7`: Returns the state of the connection, as a `TCPState` value.
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        let result = match command_num {
+            0 => return ReturnCode::SUCCESS,
+
+            1 | 2 => {
+                let endpoints = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.app_cfg.as_ref().and_then(|cfg| {
+                            if command_num == 1 && cfg.len() >= 2 * ENDPOINT_LEN {
+                                Some((
+                                    parse_endpoint(cfg.as_ref()),
+                                    parse_endpoint(&cfg.as_ref()[ENDPOINT_LEN..]),
+                                ))
+                            } else if command_num == 2 && cfg.len() >= ENDPOINT_LEN {
+                                Some((parse_endpoint(cfg.as_ref()), TCPEndpoint::default()))
+                            } else {
+                                None
+                            }
+                        })
+                    })
+                    .unwrap_or(None);
+                let (local, remote) = match endpoints {
+                    Some(endpoints) => endpoints,
+                    None => return ReturnCode::EINVAL,
+                };
+                let result = self.check_local_endpoint(appid, &local);
+                if result != ReturnCode::SUCCESS {
+                    return result;
+                }
+                // A listening connection gets its initial sequence number
+                // when it receives a SYN, but it cannot until the key is here.
+                let iss = match self.iss(&local, &remote) {
+                    Some(iss) => iss,
+                    None => return ReturnCode::EBUSY,
+                };
+                self.do_with_app(appid, |app| {
+                    if command_num == 1 {
+                        app.connection.connect(local, remote, iss)
+                    } else {
+                        app.connection.listen(local)
+                    }
+                })
+            }
+
+            3 => self.do_with_app(appid, |app| {
+                let write_len = app.app_write.as_ref().map_or(0, |write| write.len());
+                if arg1 > write_len {
+                    return ReturnCode::EINVAL;
+                }
+                let result = app.connection.send(arg1);
+                if result == ReturnCode::SUCCESS {
+                    app.tx_pending = true;
+                }
+                result
+            }),
+
+            4 => self.do_with_app(appid, |app| {
+                app.rx_len = 0;
+                app.connection.window_opened();
+                ReturnCode::SUCCESS
+            }),
+
+            5 => self.do_with_app(appid, |app| match app.connection.close() {
+                Ok(Some(events)) => {
+                    app.report(&events);
+                    ReturnCode::SUCCESS
+                }
+                Ok(None) => ReturnCode::SUCCESS,
+                Err(err) => err,
+            }),
+
+            6 => {
+                let mut reset = None;
+                let result = self.do_with_app(appid, |app| {
+                    let remote = app.connection.get_remote();
+                    reset = app.connection.abort().map(|header| (remote.addr, header));
+                    app.rx_len = 0;
+                    app.tx_pending = false;
+                    ReturnCode::SUCCESS
+                });
+                if let Some((dst, header)) = reset {
+                    self.queue_reset(dst, Some(header));
+                }
+                result
+            }
+
+            7 => {
+                let mut state = 0;
+                let result = self.do_with_app(appid, |app| {
+                    state = app.connection.get_state() as usize;
+                    ReturnCode::SUCCESS
+                });
+                if result == ReturnCode::SUCCESS {
+                    return ReturnCode::SuccessWithValue { value: state };
+                }
+                result
+            }
+
+            _ => return ReturnCode::ENOSUPPORT,
+        };
+        self.connections_changed();
+        result
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6SendClient for TCPDriver<'a, A> {
+    fn send_done(&self, result: ReturnCode) {
+        if result != ReturnCode::SUCCESS {
+            debug!("[TCP] Segment not sent: {:?}", result);
+        }
+        self.busy.set(false);
+        self.connections_changed();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6RecvClient for TCPDriver<'a, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        let (offset, header) = match TCPHeader::decode(payload).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        let data = &payload[offset..];
+        let src = TCPEndpoint {
+            addr: ip_header.get_src_addr(),
+            port: header.get_src_port(),
+        };
+        let dst = TCPEndpoint {
+            addr: ip_header.get_dst_addr(),
+            port: header.get_dst_port(),
+        };
+
+        // Segments go to the connection they belong to, and otherwise to a
+        // connection listening on their destination.
+        let mut target = None;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if app.connection.matches(src, dst) {
+                    target = Some(app.appid());
+                }
+            });
+            if target.is_some() {
+                break;
+            }
+        }
+        if target.is_none() {
+            for app in self.apps.iter() {
+                app.enter(|app, _| {
+                    if app.connection.is_listening_on(dst) {
+                        target = Some(app.appid());
+                    }
+                });
+                if target.is_some() {
+                    break;
+                }
+            }
+        }
+
+        match target {
+            Some(appid) => {
+                let now = self.now();
+                // Connections can only be opened once the key has arrived.
+                let iss = match self.iss(&dst, &src) {
+                    Some(iss) => iss,
+                    None => return,
+                };
+                let mut reset = None;
+                let _ = self.apps.enter(appid, |app, _| {
+                    let rx_space = app.rx_space();
+                    let events = app.connection.receive_segment(
+                        src,
+                        &header,
+                        data.len(),
+                        rx_space,
+                        iss,
+                        now,
+                    );
+                    if events.received > 0 {
+                        let rx_len = app.rx_len;
+                        if let Some(read) = app.app_read.as_mut() {
+                            read.as_mut()[rx_len..rx_len + events.received]
+                                .copy_from_slice(&data[..events.received]);
+                        }
+                        app.rx_len += events.received;
+                    }
+                    app.report(&events);
+                    reset = events.reset;
+                });
+                self.queue_reset(src.addr, reset);
+            }
+            None => {
+                self.queue_reset(src.addr, connection::reset_reply(&header, data.len()));
+            }
+        }
+        self.connections_changed();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> rng::Client for TCPDriver<'a, A> {
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        error: ReturnCode,
+    ) -> rng::Continue {
+        if error != ReturnCode::SUCCESS {
+            return rng::Continue::More;
+        }
+        let mut words = self.key_words.get();
+        let mut len = self.key_len.get();
+        while len < words.len() {
+            match randomness.next() {
+                Some(word) => {
+                    words[len] = word;
+                    len += 1;
+                }
+                None => break,
+            }
+        }
+        self.key_words.set(words);
+        self.key_len.set(len);
+        if len < words.len() {
+            return rng::Continue::More;
+        }
+        self.iss_key.set([
+            (words[0] as u64) << 32 | words[1] as u64,
+            (words[2] as u64) << 32 | words[3] as u64,
+        ]);
+        rng::Continue::Done
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for TCPDriver<'a, A> {
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        for app in self.apps.iter() {
+            let mut reset = None;
+            app.enter(|app, _| {
+                if let Some((start, length_ms)) = app.connection.timer() {
+                    let elapsed = now.wrapping_sub(A::Ticks::from(start));
+                    if elapsed >= A::ticks_from_ms(length_ms) {
+                        let remote = app.connection.get_remote();
+                        let events = app.connection.timer_fired();
+                        app.report(&events);
+                        reset = events.reset.map(|header| (remote.addr, header));
+                    }
+                }
+            });
+            if let Some((dst, header)) = reset {
+                self.queue_reset(dst, Some(header));
+            }
+        }
+        self.connections_changed();
+    }
+}
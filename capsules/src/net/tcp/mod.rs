@@ -0,0 +1,12 @@
+pub mod connection;
+pub mod driver;
+
+pub use self::driver::TCPDriver;
+pub use self::driver::DRIVER_NUM;
+
+// Reexport the exports of the [`tcp`] module, to avoid redundant
+// module paths (e.g. `capsules::net::tcp::tcp::TCPHeader`)
+mod tcp;
+pub use tcp::tcp_flags;
+pub use tcp::TCPHeader;
+pub use tcp::TCP_HDR_LEN;
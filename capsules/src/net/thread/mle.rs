@@ -0,0 +1,291 @@
+//! Implements the message format and the security of Mesh Link
+//! Establishment (MLE), as outlined in Chapter 4 of the Thread 1.1.1
+//! Specification. The parameters of MLE messages are encoded as TLVs, which
+//! are implemented in the `tlv` module.
+//!
+//! MLE for network attaching comprises a four-step handshake that works
+//! as follows:
+//!
+//! 1. A child device multicasts a Parent Request MLE command.
+//! 2. Each potential parent device on the network unicasts a Parent
+//!    Response MLE command.
+//! 3. The child device selects a parent based on a hierarchy of
+//!    connectivity metrics and unicasts a Child ID Request MLE
+//!    command.
+//! 4. The selected parent unicasts a Child ID Response MLE command.
+//!
+//! MLE messages are sent over UDP, on port 19788, from the link local
+//! address of the sender. Except for discovery messages, which are not
+//! supported here, they are secured with AES-CCM at security level 5 (a
+//! 4-byte MIC, with encryption), using the auxiliary security header of IEEE
+//! 802.15.4 with key identifier mode 2:
+//!
+//! ```text
+//! +-------+--------------------------------+---------+------+-----+
+//! | Suite | Aux security header (10 bytes) | Command | TLVs | MIC |
+//! +-------+--------------------------------+---------+------+-----+
+//!                                          \__ encrypted ___/
+//! ```
+//!
+//! - The security suite is 0 for secured messages.
+//! - The auxiliary security header holds the security control field, the
+//!   frame counter of the sender (little endian), and the key identifier: the
+//!   key sequence counter (big endian) followed by the key index.
+//! - The nonce is the extended MAC address of the sender, the frame counter
+//!   (big endian) and the security level.
+//! - The authenticated data is the IPv6 source address, the IPv6
+//!   destination address and the auxiliary security header.
+//!
+//! The MLE key and the MAC key are derived from the network master key and
+//! the key sequence counter (Section 7.1.4): HMAC-SHA256 is computed over
+//! the key sequence counter (big endian) followed by the string "Thread",
+//! with the master key as its key. The first half of the result is the MLE
+//! key, and the second half the IEEE 802.15.4 key.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u32, encode_u8};
+use crate::net::thread::tlv::{Tlv, TlvType};
+use kernel::hil::symmetric_encryption::CCM_NONCE_LENGTH;
+
+/// UDP port of MLE messages.
+pub const MLE_PORT: u16 = 19788;
+
+/// Security suite of messages secured by MLE.
+pub const SECURITY_SUITE_ENABLED: u8 = 0;
+/// Security suite of unsecured messages.
+pub const SECURITY_SUITE_DISABLED: u8 = 255;
+
+/// Security level of MLE messages: encryption with a 4-byte MIC.
+pub const SECURITY_LEVEL: u8 = 5;
+/// Security control field of the auxiliary security header: security level
+/// 5 and key identifier mode 2.
+const SECURITY_CONTROL: u8 = SECURITY_LEVEL | 0x10;
+
+pub const AUX_HEADER_LEN: usize = 10;
+pub const MIC_LEN: usize = 4;
+/// Length of the security suite and the auxiliary security header.
+pub const SECURITY_HEADER_LEN: usize = 1 + AUX_HEADER_LEN;
+/// Length of the authenticated data: both IPv6 addresses and the auxiliary
+/// security header.
+pub const AUTH_DATA_LEN: usize = 16 + 16 + AUX_HEADER_LEN;
+
+/// Length of the data from which the keys are derived.
+pub const KEY_INPUT_LEN: usize = 4 + 6;
+const KEY_INPUT_STRING: &[u8] = b"Thread";
+
+/// Value of the Version TLV for Thread 1.1.
+pub const THREAD_VERSION: u16 = 2;
+
+/// Link local scope multicast address of all nodes, ff02::1.
+pub const LINK_LOCAL_ALL_NODES: IPAddr =
+    IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]);
+/// Link local scope multicast address of all routers, ff02::2.
+pub const LINK_LOCAL_ALL_ROUTERS: IPAddr =
+    IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02]);
+
+/// MLE command types (Section 4.4).
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MleCommand {
+    LinkRequest = 0,
+    LinkAccept = 1,
+    LinkAcceptAndRequest = 2,
+    LinkReject = 3,
+    Advertisement = 4,
+    Update = 5,
+    UpdateRequest = 6,
+    DataRequest = 7,
+    DataResponse = 8,
+    ParentRequest = 9,
+    ParentResponse = 10,
+    ChildIdRequest = 11,
+    ChildIdResponse = 12,
+    ChildUpdateRequest = 13,
+    ChildUpdateResponse = 14,
+    Announce = 15,
+    DiscoveryRequest = 16,
+    DiscoveryResponse = 17,
+    NotPresent,
+}
+
+impl From<u8> for MleCommand {
+    fn from(command: u8) -> Self {
+        match command {
+            0 => MleCommand::LinkRequest,
+            1 => MleCommand::LinkAccept,
+            2 => MleCommand::LinkAcceptAndRequest,
+            3 => MleCommand::LinkReject,
+            4 => MleCommand::Advertisement,
+            5 => MleCommand::Update,
+            6 => MleCommand::UpdateRequest,
+            7 => MleCommand::DataRequest,
+            8 => MleCommand::DataResponse,
+            9 => MleCommand::ParentRequest,
+            10 => MleCommand::ParentResponse,
+            11 => MleCommand::ChildIdRequest,
+            12 => MleCommand::ChildIdResponse,
+            13 => MleCommand::ChildUpdateRequest,
+            14 => MleCommand::ChildUpdateResponse,
+            15 => MleCommand::Announce,
+            16 => MleCommand::DiscoveryRequest,
+            17 => MleCommand::DiscoveryResponse,
+            _ => MleCommand::NotPresent,
+        }
+    }
+}
+
+/// Auxiliary security header of a secured MLE message.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct AuxHeader {
+    pub frame_counter: u32,
+    pub key_sequence: u32,
+}
+
+impl AuxHeader {
+    pub fn encode(&self, buf: &mut [u8]) -> SResult {
+        let mut offset = enc_consume!(buf; encode_u8, SECURITY_CONTROL);
+        offset = enc_consume!(buf, offset; encode_bytes, &self.frame_counter.to_le_bytes());
+        offset = enc_consume!(buf, offset; encode_u32, self.key_sequence);
+        offset = enc_consume!(buf, offset; encode_u8, key_index(self.key_sequence));
+        stream_done!(offset)
+    }
+
+    /// Decodes the header, which must use the security level and key
+    /// identifier mode of MLE, and a key index matching its key sequence.
+    pub fn decode(buf: &[u8]) -> SResult<AuxHeader> {
+        let (offset, security_control) = dec_try!(buf; decode_u8);
+        stream_cond!(security_control == SECURITY_CONTROL);
+        let mut frame_counter = [0u8; 4];
+        let offset = dec_consume!(buf, offset; decode_bytes, &mut frame_counter);
+        let (offset, key_sequence) = dec_try!(buf, offset; decode_u32);
+        let (offset, index) = dec_try!(buf, offset; decode_u8);
+        stream_cond!(index == key_index(key_sequence));
+        stream_done!(
+            offset,
+            AuxHeader {
+                frame_counter: u32::from_le_bytes(frame_counter),
+                key_sequence,
+            }
+        )
+    }
+}
+
+/// Key index of the keys derived with a key sequence counter.
+pub fn key_index(key_sequence: u32) -> u8 {
+    (key_sequence & 0x7f) as u8 + 1
+}
+
+/// Data over which the HMAC is computed to derive the keys of a key
+/// sequence counter.
+pub fn key_input(key_sequence: u32) -> [u8; KEY_INPUT_LEN] {
+    let mut input = [0; KEY_INPUT_LEN];
+    input[..4].copy_from_slice(&key_sequence.to_be_bytes());
+    input[4..].copy_from_slice(KEY_INPUT_STRING);
+    input
+}
+
+/// CCM nonce of a message sent by the device with the extended address
+/// `ext_addr`.
+pub fn nonce(ext_addr: &[u8; 8], frame_counter: u32) -> [u8; CCM_NONCE_LENGTH] {
+    let mut nonce = [0; CCM_NONCE_LENGTH];
+    nonce[..8].copy_from_slice(ext_addr);
+    nonce[8..12].copy_from_slice(&frame_counter.to_be_bytes());
+    nonce[12] = SECURITY_LEVEL;
+    nonce
+}
+
+/// Writes the authenticated data of a message into `buf`.
+pub fn encode_auth_data(buf: &mut [u8], src: &IPAddr, dst: &IPAddr, aux_header: &AuxHeader) {
+    buf[..16].copy_from_slice(&src.0);
+    buf[16..32].copy_from_slice(&dst.0);
+    // The buffer is always large enough
+    let _ = aux_header.encode(&mut buf[32..AUTH_DATA_LEN]);
+}
+
+/// Link quality (0 to 3) of a link with the given link margin in dB
+/// (Section 4.5.13).
+pub fn link_quality(link_margin: u8) -> u8 {
+    match link_margin {
+        0..=2 => 0,
+        3..=10 => 1,
+        11..=20 => 2,
+        _ => 3,
+    }
+}
+
+/// Appends `tlv` to the message in `buf`, which is `offset` bytes long.
+/// Returns the new length of the message, or `None` if it does not fit.
+pub fn append_tlv(buf: &mut [u8], offset: usize, tlv: Tlv) -> Option<usize> {
+    let (len, _) = tlv.encode(buf.get_mut(offset..)?).done()?;
+    Some(offset + len)
+}
+
+/// Finds the first TLV of the given type in the TLVs of a message, and
+/// decodes it. Returns `None` if there is no such TLV, or if it or a TLV
+/// before it is malformed.
+pub fn find_tlv(tlvs: &[u8], tlv_type: TlvType) -> Option<Tlv> {
+    let tlv_type = tlv_type as u8;
+    let mut offset = 0;
+    while offset + 2 <= tlvs.len() {
+        let end = offset + 2 + tlvs[offset + 1] as usize;
+        if end > tlvs.len() {
+            return None;
+        }
+        if tlvs[offset] == tlv_type {
+            return Tlv::decode(&tlvs[offset..end]).done().map(|(_, tlv)| tlv);
+        }
+        offset = end;
+    }
+    None
+}
+
+/// Contents of a Leader Data TLV, which describes the partition a node is
+/// part of and the version of its network data.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct LeaderData {
+    pub partition_id: u32,
+    pub weighting: u8,
+    pub data_version: u8,
+    pub stable_data_version: u8,
+    pub leader_router_id: u8,
+}
+
+impl LeaderData {
+    /// Finds and decodes the Leader Data TLV of a message.
+    pub fn find(tlvs: &[u8]) -> Option<LeaderData> {
+        match find_tlv(tlvs, TlvType::LeaderData)? {
+            Tlv::LeaderData {
+                partition_id,
+                weighting,
+                data_version,
+                stable_data_version,
+                leader_router_id,
+            } => Some(LeaderData {
+                partition_id,
+                weighting,
+                data_version,
+                stable_data_version,
+                leader_router_id,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn tlv(&self) -> Tlv<'static> {
+        Tlv::LeaderData {
+            partition_id: self.partition_id,
+            weighting: self.weighting,
+            data_version: self.data_version,
+            stable_data_version: self.stable_data_version,
+            leader_router_id: self.leader_router_id,
+        }
+    }
+}
+
+/// Returns whether the data version `version` is newer than `current`.
+/// Versions are compared with serial number arithmetic.
+pub fn version_newer(version: u8, current: u8) -> bool {
+    (version.wrapping_sub(current) as i8) > 0
+}
@@ -1 +1,3 @@
+pub mod mle;
+pub mod thread_child;
 pub mod tlv;
@@ -0,0 +1,1502 @@
+//! This file implements the child side of the Thread attach procedure, so
+//! that a node can join an existing Thread network as a sleepy end device
+//! (SED), a child that keeps its receiver off and polls its parent for the
+//! frames buffered for it.
+//!
+//! Once started with the network master key, the child derives the MLE and
+//! MAC keys with an HMAC-SHA256 engine, and attaches (Section 4.7.1):
+//!
+//! 1. It multicasts a Parent Request to the routers, and collects the Parent
+//!    Responses for 750 ms. If no router answers, it sends a second Parent
+//!    Request that router-eligible end devices (REEDs) also answer, and
+//!    waits 1250 ms.
+//! 2. It selects the best parent by link quality, parent priority and the
+//!    number of good links of the parent, and sends it a Child ID Request.
+//! 3. It polls the parent until it receives a Child ID Response, which
+//!    assigns the RLOC16 of the child, that is set as its MAC short address.
+//!
+//! If no parent answers, the child tries again after an exponential backoff.
+//! Once attached, it polls its parent every `poll_period` milliseconds with
+//! MAC Data Requests, answers the Child Update Requests of its parent, and
+//! tracks the partition and the stable network data it belongs to: it
+//! reattaches when the parent reports an error, when the parent moves to
+//! another partition, or when polls stop being acknowledged, and sends an MLE
+//! Data Request when the leader advertises newer network data.
+//!
+//! MLE messages are sent over UDP from the link local address derived from
+//! the extended address of the node, and MLE security is applied here (see
+//! the `mle` module). The child also provides the 802.15.4 keys and the frame
+//! counters of its parent to the MAC layer, by implementing the
+//! `KeyProcedure` and `DeviceProcedure` of the framer, chooses the next hop of
+//! the packets of its IP sender as an `IP6Router`, and has the other IPv6
+//! traffic secured with the MAC key as an `IP6LinkSecurity`.
+//!
+//! Frame counters must never be reused with the same key, including across
+//! reboots, and the key sequence is set by the leader, so the child cannot
+//! move to new keys after a reboot. MLE and MAC frames therefore share one
+//! outgoing frame counter, which the child takes from the radio driver, that
+//! persists it with `set_frame_counter_storage`. The challenges of the child
+//! are taken from a random number generator.
+//!
+//! Usage
+//! -----
+//! The child is created by `ThreadChildComponent` in the `thread` component,
+//! which sets up the IP sender and the UDP socket it uses. The channel and the
+//! PAN ID of the network are those of the radio, and are set by the board.
+//!
+//! Limitations
+//! -----------
+//!
+//! - Only the child role is implemented, and the child never becomes a router.
+//! - Address Registration is not supported, so the parent does not know the
+//!   non link local addresses of the child.
+//! - Operational datasets are ignored: the channel, the PAN ID and the master
+//!   key must be known in advance.
+//! - One MLE message is processed at a time; messages received while another
+//!   is being encrypted, decrypted or sent are dropped.
+//! - The link quality of a parent is the one it reports in its Parent
+//!   Response, as the UDP layer does not provide the link margin of received
+//!   packets.
+
+use crate::ieee802154::device::{MacDevice, TxClient};
+use crate::ieee802154::framer::{DeviceProcedure, KeyProcedure};
+use crate::net::ieee802154::{CommandId, KeyId, MacAddress, SecurityLevel};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_send::{IP6LinkSecurity, IP6Router};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::thread::mle::{
+    append_tlv, encode_auth_data, find_tlv, key_index, key_input, link_quality, nonce,
+    version_newer, AuxHeader, LeaderData, MleCommand, AUTH_DATA_LEN, AUX_HEADER_LEN, KEY_INPUT_LEN,
+    LINK_LOCAL_ALL_ROUTERS, MIC_LEN, MLE_PORT, SECURITY_HEADER_LEN, SECURITY_SUITE_ENABLED,
+    THREAD_VERSION,
+};
+use crate::net::thread::tlv::{LinkMode, MulticastResponder, Tlv, TlvType};
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::digest;
+use kernel::hil::rng;
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM};
+use kernel::hil::time::{self, Frequency, Ticks};
+use kernel::ReturnCode;
+
+/// Longest MLE message (command and TLVs) that can be received.
+pub const MAX_MLE_LEN: usize = 400;
+
+/// Longest MLE message this capsule sends.
+const MAX_TX_MLE_LEN: usize = 64;
+
+/// Size of the buffer MLE messages are encrypted and decrypted in.
+pub const CRYPT_BUF_LEN: usize = AUTH_DATA_LEN + MAX_MLE_LEN + MIC_LEN;
+
+/// Size of the buffer secured MLE messages are sent from.
+pub const TX_BUF_LEN: usize = SECURITY_HEADER_LEN + MAX_TX_MLE_LEN + MIC_LEN;
+
+/// Size of the buffer the stable network data is kept in.
+pub const MAX_NETWORK_DATA_LEN: usize = 255;
+
+/// Mode of a sleepy end device: the receiver is off when idle, data
+/// requests are secured, and only the stable network data is needed.
+const MODE: u8 = LinkMode::SecureDataRequests as u8;
+
+/// Time during which Parent Responses from routers are collected.
+const ROUTER_RESPONSE_WINDOW_MS: u64 = 750;
+
+/// Time during which Parent Responses from routers and REEDs are collected.
+const REED_RESPONSE_WINDOW_MS: u64 = 1250;
+
+/// Time to wait for a Child ID Response.
+const CHILD_ID_RESPONSE_TIMEOUT_MS: u64 = 1250;
+
+/// Delay before the second attach attempt. It doubles after each failed
+/// attempt, up to `MAX_ATTACH_BACKOFF_MS`.
+const ATTACH_BACKOFF_MS: u64 = 2000;
+const MAX_ATTACH_BACKOFF_MS: u64 = 60_000;
+/// Largest random delay added to the attach backoff.
+const ATTACH_JITTER_MS: u32 = 1000;
+
+/// Number of random words kept for challenges and the attach backoff. An
+/// attach needs two words for its challenge and one for its backoff.
+const RANDOM_POOL_LEN: usize = 4;
+
+/// Poll period while waiting for a Child ID Response or a Data Response.
+const FAST_POLL_MS: u64 = 100;
+
+/// Time during which the parent is polled quickly after an MLE Data Request.
+const DATA_RESPONSE_TIMEOUT_MS: u64 = 1000;
+
+pub const DEFAULT_POLL_PERIOD_MS: u32 = 10_000;
+pub const DEFAULT_TIMEOUT_S: u32 = 240;
+
+/// Number of consecutive polls the parent does not acknowledge before the
+/// child considers it lost and reattaches.
+const MAX_POLL_FAILURES: u8 = 4;
+
+/// Longest time the alarm is set for, so that the uptime is kept even if
+/// nothing is scheduled.
+const MAX_SLEEP_MS: u64 = 60_000;
+
+/// Short address of a device that has none.
+const NO_SHORT_ADDR: u16 = 0xfffe;
+
+/// The router ID is the upper 6 bits of an RLOC16, and the child ID its
+/// lower 9 bits.
+const ROUTER_ID_SHIFT: u16 = 10;
+const CHILD_ID_MASK: u16 = 0x1ff;
+
+/// Value of the Status TLV that tells a child to reattach.
+const STATUS_ERROR: u8 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ChildState {
+    /// The child has not been started.
+    Disabled,
+    /// The child is waiting before trying to attach again.
+    Detached,
+    /// The child is collecting Parent Responses.
+    ParentRequest,
+    /// The child is waiting for the Child ID Response of its parent.
+    ChildIdRequest,
+    /// The child is attached to its parent.
+    Child,
+}
+
+#[derive(Copy, Clone)]
+struct Keys {
+    sequence: u32,
+    mle: [u8; 16],
+    mac: [u8; 16],
+}
+
+/// A candidate parent, or the parent of the child.
+#[derive(Copy, Clone)]
+struct Parent {
+    ext_addr: [u8; 8],
+    rloc16: u16,
+    /// Lowest MAC frame counter accepted from the parent
+    link_frame_counter: u32,
+    /// Lowest MLE frame counter accepted from the parent
+    mle_frame_counter: u32,
+    /// Challenge of the Parent Response, echoed in the Child ID Request
+    challenge: [u8; 8],
+    /// Link quality, priority, and number of links of quality 3, 2 and 1
+    score: (u8, i8, u8, u8, u8),
+}
+
+/// The sender of the MLE message being decrypted.
+#[derive(Copy, Clone)]
+struct RxInfo {
+    ext_addr: [u8; 8],
+    aux_header: AuxHeader,
+    len: usize,
+}
+
+#[derive(Copy, Clone)]
+enum Op {
+    Idle,
+    /// Deriving the keys of a key sequence, either for the child, or to
+    /// decrypt a received message that uses a newer key sequence.
+    DerivingKeys {
+        sequence: u32,
+        for_rx: bool,
+    },
+    Encrypting {
+        dst: IPAddr,
+        len: usize,
+    },
+    Sending,
+    Decrypting,
+}
+
+pub struct ThreadChild<'a, A, H, C>
+where
+    A: time::Alarm<'a>,
+    H: digest::Digest<'a, [u8; 32]> + digest::HMACSha256,
+    C: AES128CCM<'a>,
+{
+    alarm: &'a A,
+    udp_sender: &'a dyn UDPSender<'a>,
+    mac: &'a dyn MacDevice<'a>,
+    hmac: &'a H,
+    ccm: &'a C,
+    rng: &'a dyn rng::Rng<'a>,
+    frame_counters: OptionalCell<&'a dyn DeviceProcedure>,
+    net_cap: &'static NetworkCapability,
+    ext_addr: [u8; 8],
+
+    uptime_ms: Cell<u64>,
+    last_ticks: Cell<A::Ticks>,
+    /// Random words received from the RNG and not used yet
+    random_pool: Cell<[u32; RANDOM_POOL_LEN]>,
+    random_len: Cell<usize>,
+
+    master_key: Cell<Option<[u8; 16]>>,
+    key_sequence: Cell<u32>,
+    keys: Cell<Option<Keys>>,
+    timeout_s: Cell<u32>,
+    poll_period_ms: Cell<u32>,
+
+    tx_buf: TakeCell<'static, [u8]>,
+    crypt_buf: TakeCell<'static, [u8]>,
+    key_input: TakeCell<'static, [u8]>,
+    digest: TakeCell<'static, [u8; 32]>,
+    poll_buf: TakeCell<'static, [u8]>,
+    network_data: TakeCell<'static, [u8]>,
+    network_data_len: Cell<usize>,
+
+    state: Cell<ChildState>,
+    op: Cell<Op>,
+    challenge: Cell<[u8; 8]>,
+    scan_reeds: Cell<bool>,
+    candidate: Cell<Option<Parent>>,
+    parent: Cell<Option<Parent>>,
+    rloc16: Cell<Option<u16>>,
+    leader_data: Cell<Option<LeaderData>>,
+    attach_attempts: Cell<u8>,
+
+    /// Time of the next step of the attach procedure
+    deadline: Cell<Option<u64>>,
+    poll_deadline: Cell<Option<u64>>,
+    fast_poll_until: Cell<u64>,
+    poll_failures: Cell<u8>,
+
+    parent_request_pending: Cell<bool>,
+    child_id_request_pending: Cell<bool>,
+    data_request_pending: Cell<bool>,
+    child_update_response_pending: Cell<bool>,
+    /// Challenge of the Child Update Request being answered, if it had one
+    response_challenge: Cell<Option<[u8; 8]>>,
+
+    rx_info: Cell<Option<RxInfo>>,
+    rx_keys: Cell<Option<Keys>>,
+}
+
+impl<'a, A, H, C> ThreadChild<'a, A, H, C>
+where
+    A: time::Alarm<'a>,
+    H: digest::Digest<'a, [u8; 32]> + digest::HMACSha256,
+    C: AES128CCM<'a>,
+{
+    /// Creates a child. `udp_sender` must be bound to the MLE port and send
+    /// from the link local address derived from `ext_addr`, and `mac` is used
+    /// to poll the parent. The challenges are taken from `rng`. `tx_buf` must
+    /// hold `TX_BUF_LEN` bytes and `crypt_buf` `CRYPT_BUF_LEN` bytes.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        alarm: &'a A,
+        udp_sender: &'a dyn UDPSender<'a>,
+        mac: &'a dyn MacDevice<'a>,
+        hmac: &'a H,
+        ccm: &'a C,
+        rng: &'a dyn rng::Rng<'a>,
+        ext_addr: [u8; 8],
+        tx_buf: &'static mut [u8],
+        crypt_buf: &'static mut [u8],
+        key_input: &'static mut [u8; KEY_INPUT_LEN],
+        digest: &'static mut [u8; 32],
+        poll_buf: &'static mut [u8],
+        network_data: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> ThreadChild<'a, A, H, C> {
+        ThreadChild {
+            alarm,
+            udp_sender,
+            mac,
+            hmac,
+            ccm,
+            rng,
+            frame_counters: OptionalCell::empty(),
+            net_cap,
+            ext_addr,
+            uptime_ms: Cell::new(0),
+            last_ticks: Cell::new(A::Ticks::from(0)),
+            random_pool: Cell::new([0; RANDOM_POOL_LEN]),
+            random_len: Cell::new(0),
+            master_key: Cell::new(None),
+            key_sequence: Cell::new(0),
+            keys: Cell::new(None),
+            timeout_s: Cell::new(DEFAULT_TIMEOUT_S),
+            poll_period_ms: Cell::new(DEFAULT_POLL_PERIOD_MS),
+            tx_buf: TakeCell::new(tx_buf),
+            crypt_buf: TakeCell::new(crypt_buf),
+            key_input: TakeCell::new(key_input),
+            digest: TakeCell::new(digest),
+            poll_buf: TakeCell::new(poll_buf),
+            network_data: TakeCell::new(network_data),
+            network_data_len: Cell::new(0),
+            state: Cell::new(ChildState::Disabled),
+            op: Cell::new(Op::Idle),
+            challenge: Cell::new([0; 8]),
+            scan_reeds: Cell::new(false),
+            candidate: Cell::new(None),
+            parent: Cell::new(None),
+            rloc16: Cell::new(None),
+            leader_data: Cell::new(None),
+            attach_attempts: Cell::new(0),
+            deadline: Cell::new(None),
+            poll_deadline: Cell::new(None),
+            fast_poll_until: Cell::new(0),
+            poll_failures: Cell::new(0),
+            parent_request_pending: Cell::new(false),
+            child_id_request_pending: Cell::new(false),
+            data_request_pending: Cell::new(false),
+            child_update_response_pending: Cell::new(false),
+            response_challenge: Cell::new(None),
+            rx_info: Cell::new(None),
+            rx_keys: Cell::new(None),
+        }
+    }
+
+    /// Sets the network master key and the current key sequence counter.
+    /// This can only be done before the child is started.
+    pub fn set_master_key(&self, master_key: [u8; 16], key_sequence: u32) -> ReturnCode {
+        if self.state.get() != ChildState::Disabled {
+            return ReturnCode::EBUSY;
+        }
+        self.master_key.set(Some(master_key));
+        self.key_sequence.set(key_sequence);
+        ReturnCode::SUCCESS
+    }
+
+    /// Takes the outgoing frame counters of MLE and MAC frames from
+    /// `frame_counters`, which is usually the radio driver with its frame
+    /// counter storage. This must be done before the child is started.
+    pub fn set_frame_counters(&self, frame_counters: &'a dyn DeviceProcedure) {
+        self.frame_counters.set(frame_counters);
+    }
+
+    /// Sets the timeout, in seconds, after which the parent removes the child
+    /// if it has not heard from it. It is sent in the next Child ID Request,
+    /// and must be longer than the poll period.
+    pub fn set_timeout(&self, timeout_s: u32) {
+        self.timeout_s.set(timeout_s);
+    }
+
+    /// Sets the interval between the polls of an attached child.
+    pub fn set_poll_period(&self, poll_period_ms: u32) {
+        self.poll_period_ms
+            .set(poll_period_ms.max(FAST_POLL_MS as u32));
+    }
+
+    /// Derives the keys of the network, and starts attaching to it.
+    pub fn start(&self) -> ReturnCode {
+        if self.state.get() != ChildState::Disabled {
+            return ReturnCode::EALREADY;
+        }
+        if self.master_key.get().is_none() || self.frame_counters.is_none() {
+            return ReturnCode::EINVAL;
+        }
+        if !matches!(self.op.get(), Op::Idle) {
+            return ReturnCode::EBUSY;
+        }
+        self.last_ticks.set(self.alarm.now());
+        self.mac.set_address_long(self.ext_addr);
+        self.mac.set_address(NO_SHORT_ADDR);
+        self.mac.config_commit();
+        self.state.set(ChildState::Detached);
+        self.rng.get();
+        let result = self.derive_keys(self.key_sequence.get(), false);
+        if result != ReturnCode::SUCCESS {
+            self.state.set(ChildState::Disabled);
+        }
+        result
+    }
+
+    pub fn get_state(&self) -> ChildState {
+        self.state.get()
+    }
+
+    /// Returns the RLOC16 of the child, once it is attached.
+    pub fn get_rloc16(&self) -> Option<u16> {
+        match self.state.get() {
+            ChildState::Child => self.rloc16.get(),
+            _ => None,
+        }
+    }
+
+    /// Returns the link local address of the parent, once the child is
+    /// attached.
+    pub fn get_parent(&self) -> Option<IPAddr> {
+        match self.state.get() {
+            ChildState::Child => self
+                .parent
+                .get()
+                .map(|parent| IPAddr::generate_from_mac(MacAddress::Long(parent.ext_addr))),
+            _ => None,
+        }
+    }
+
+    /// Returns the leader data of the partition the child is attached to.
+    pub fn get_leader_data(&self) -> Option<LeaderData> {
+        match self.state.get() {
+            ChildState::Child => self.leader_data.get(),
+            _ => None,
+        }
+    }
+
+    /// Copies the stable network data of the partition into `buf`, and
+    /// returns its length, or 0 if it does not fit.
+    pub fn get_network_data(&self, buf: &mut [u8]) -> usize {
+        let len = self.network_data_len.get();
+        if len > buf.len() {
+            return 0;
+        }
+        self.network_data.map_or(0, |data| {
+            buf[..len].copy_from_slice(&data[..len]);
+            len
+        })
+    }
+
+    /// Returns the uptime in milliseconds, advancing it by the whole
+    /// milliseconds that passed since it was last updated.
+    fn now(&self) -> u64 {
+        let freq = A::Frequency::frequency() as u64;
+        let now = self.alarm.now();
+        let elapsed = now.wrapping_sub(self.last_ticks.get()).into_u32() as u64;
+        let ms = elapsed * 1000 / freq;
+        let consumed = (ms * freq / 1000) as u32;
+        self.last_ticks
+            .set(self.last_ticks.get().wrapping_add(A::Ticks::from(consumed)));
+        self.uptime_ms.set(self.uptime_ms.get() + ms);
+        self.uptime_ms.get()
+    }
+
+    /// Returns a random word from the pool, and asks the RNG to refill it.
+    fn random(&self) -> Option<u32> {
+        let len = self.random_len.get();
+        if len == 0 {
+            return None;
+        }
+        self.random_len.set(len - 1);
+        self.rng.get();
+        Some(self.random_pool.get()[len - 1])
+    }
+
+    /// Returns the next outgoing frame counter, which MLE and MAC frames
+    /// share, or `None` if no frame counter is available.
+    fn take_frame_counter(&self) -> Option<u32> {
+        self.frame_counters
+            .and_then(|frame_counters| frame_counters.next_frame_counter())
+    }
+
+    fn link_local(&self) -> IPAddr {
+        IPAddr::generate_from_mac(MacAddress::Long(self.ext_addr))
+    }
+
+    fn poll_interval(&self, now: u64) -> u64 {
+        if self.state.get() == ChildState::ChildIdRequest || now < self.fast_poll_until.get() {
+            FAST_POLL_MS
+        } else {
+            self.poll_period_ms.get() as u64
+        }
+    }
+
+    fn schedule_alarm(&self) {
+        if self.state.get() == ChildState::Disabled {
+            return;
+        }
+        let now = self.now();
+        let mut next = now + MAX_SLEEP_MS;
+        let deadlines = [self.deadline.get(), self.poll_deadline.get()];
+        for deadline in deadlines.iter().flatten() {
+            next = next.min(*deadline);
+        }
+        let dt = next.saturating_sub(now).max(1);
+        self.alarm
+            .set_alarm(self.alarm.now(), A::ticks_from_ms(dt as u32));
+    }
+
+    // Keys
+
+    /// Starts computing the keys of a key sequence. When `for_rx` is set,
+    /// the received message in the crypt buffer is decrypted with them once
+    /// they are derived.
+    fn derive_keys(&self, sequence: u32, for_rx: bool) -> ReturnCode {
+        let master_key = match self.master_key.get() {
+            Some(master_key) => master_key,
+            None => return ReturnCode::EINVAL,
+        };
+        let buf = match self.key_input.take() {
+            Some(buf) => buf,
+            None => return ReturnCode::EBUSY,
+        };
+        buf.copy_from_slice(&key_input(sequence));
+        if let Err(code) = self.hmac.set_mode_hmacsha256(&master_key) {
+            self.key_input.replace(buf);
+            return code;
+        }
+        match self.hmac.add_data(LeasableBuffer::new(buf)) {
+            Ok(_) => {
+                self.op.set(Op::DerivingKeys { sequence, for_rx });
+                ReturnCode::SUCCESS
+            }
+            Err((code, buf)) => {
+                self.key_input.replace(buf);
+                self.hmac.clear_data();
+                code
+            }
+        }
+    }
+
+    /// Called when deriving keys failed.
+    fn keys_failed(&self, for_rx: bool) {
+        self.hmac.clear_data();
+        self.op.set(Op::Idle);
+        if for_rx {
+            self.rx_info.set(None);
+        } else if self.keys.get().is_none() {
+            self.state.set(ChildState::Disabled);
+        }
+    }
+
+    fn keys_derived(&self, keys: Keys, for_rx: bool) {
+        self.op.set(Op::Idle);
+        if for_rx {
+            let rx_info = match self.rx_info.get() {
+                Some(rx_info) => rx_info,
+                None => return,
+            };
+            let buf = match self.crypt_buf.take() {
+                Some(buf) => buf,
+                None => return,
+            };
+            self.rx_keys.set(Some(keys));
+            self.decrypt(buf, &keys, rx_info);
+        } else {
+            self.adopt_keys(keys);
+            if self.state.get() == ChildState::Detached {
+                self.attach();
+            }
+        }
+    }
+
+    /// Switches to new keys. The frame counters of the parent restart from 0
+    /// with each key sequence, while the outgoing frame counter keeps
+    /// increasing.
+    fn adopt_keys(&self, keys: Keys) {
+        self.keys.set(Some(keys));
+        self.key_sequence.set(keys.sequence);
+        let reset = |parent: Option<Parent>| {
+            parent.map(|parent| Parent {
+                link_frame_counter: 0,
+                mle_frame_counter: 0,
+                ..parent
+            })
+        };
+        self.parent.set(reset(self.parent.get()));
+        self.candidate.set(reset(self.candidate.get()));
+    }
+
+    // Attaching
+
+    /// Starts attaching with a Parent Request. Without enough randomness for
+    /// its challenge, the child stays detached until the RNG refills the
+    /// pool.
+    fn attach(&self) {
+        let now = self.now();
+        self.candidate.set(None);
+        self.parent.set(None);
+        self.poll_deadline.set(None);
+        self.deadline.set(None);
+        let challenge = match (self.random(), self.random()) {
+            (Some(high), Some(low)) => [high.to_be_bytes(), low.to_be_bytes()],
+            _ => {
+                self.state.set(ChildState::Detached);
+                return;
+            }
+        };
+        let mut bytes = [0; 8];
+        bytes[..4].copy_from_slice(&challenge[0]);
+        bytes[4..].copy_from_slice(&challenge[1]);
+        self.challenge.set(bytes);
+        self.state.set(ChildState::ParentRequest);
+        self.scan_reeds.set(false);
+        self.parent_request_pending.set(true);
+        self.deadline.set(Some(now + ROUTER_RESPONSE_WINDOW_MS));
+    }
+
+    /// Leaves the parent and attaches again.
+    fn reattach(&self) {
+        self.rloc16.set(None);
+        self.leader_data.set(None);
+        self.network_data_len.set(0);
+        self.attach_attempts.set(0);
+        self.data_request_pending.set(false);
+        self.child_update_response_pending.set(false);
+        self.mac.set_address(NO_SHORT_ADDR);
+        self.mac.config_commit();
+        self.attach();
+    }
+
+    fn attach_failed(&self) {
+        let now = self.now();
+        let attempts = self.attach_attempts.get().saturating_add(1);
+        self.attach_attempts.set(attempts);
+        let backoff = ATTACH_BACKOFF_MS
+            .checked_shl(attempts as u32 - 1)
+            .unwrap_or(MAX_ATTACH_BACKOFF_MS)
+            .min(MAX_ATTACH_BACKOFF_MS);
+        let jitter = self.random().map_or(0, |random| random % ATTACH_JITTER_MS) as u64;
+        self.state.set(ChildState::Detached);
+        self.candidate.set(None);
+        self.parent.set(None);
+        self.poll_deadline.set(None);
+        self.deadline.set(Some(now + backoff + jitter));
+    }
+
+    /// Advances the attach procedure when its deadline passes.
+    fn attach_timeout(&self) {
+        let now = self.now();
+        match self.state.get() {
+            ChildState::ParentRequest => {
+                if let Some(candidate) = self.candidate.get() {
+                    self.parent.set(Some(candidate));
+                    self.candidate.set(None);
+                    self.state.set(ChildState::ChildIdRequest);
+                    self.child_id_request_pending.set(true);
+                    self.deadline.set(Some(now + CHILD_ID_RESPONSE_TIMEOUT_MS));
+                    self.poll_deadline.set(Some(now + FAST_POLL_MS));
+                } else if !self.scan_reeds.get() {
+                    self.scan_reeds.set(true);
+                    self.parent_request_pending.set(true);
+                    self.deadline.set(Some(now + REED_RESPONSE_WINDOW_MS));
+                } else {
+                    self.attach_failed();
+                }
+            }
+            ChildState::ChildIdRequest => self.attach_failed(),
+            ChildState::Detached => self.attach(),
+            ChildState::Disabled | ChildState::Child => {}
+        }
+    }
+
+    // Received messages
+
+    fn handle_message(&self, rx_info: RxInfo, message: &[u8]) {
+        if message.is_empty() {
+            return;
+        }
+        let tlvs = &message[1..];
+        match (self.state.get(), MleCommand::from(message[0])) {
+            (ChildState::ParentRequest, MleCommand::ParentResponse) => {
+                self.parent_response(rx_info, tlvs);
+            }
+            (ChildState::ChildIdRequest, MleCommand::ChildIdResponse) => {
+                self.child_id_response(rx_info, tlvs);
+            }
+            (ChildState::Child, command) => {
+                self.parent_message(rx_info, command, tlvs);
+            }
+            _ => {}
+        }
+    }
+
+    fn parent_response(&self, rx_info: RxInfo, tlvs: &[u8]) -> Option<()> {
+        match find_tlv(tlvs, TlvType::Response)? {
+            Tlv::Response(response) if response == self.challenge.get() => {}
+            _ => return None,
+        }
+        let rloc16 = match find_tlv(tlvs, TlvType::SourceAddress)? {
+            Tlv::SourceAddress(rloc16) => rloc16,
+            _ => return None,
+        };
+        LeaderData::find(tlvs)?;
+        let link_frame_counter = match find_tlv(tlvs, TlvType::LinkLayerFrameCounter)? {
+            Tlv::LinkLayerFrameCounter(frame_counter) => frame_counter,
+            _ => return None,
+        };
+        let mle_frame_counter = match find_tlv(tlvs, TlvType::MleFrameCounter) {
+            Some(Tlv::MleFrameCounter(frame_counter)) => frame_counter,
+            _ => rx_info.aux_header.frame_counter,
+        };
+        let challenge = match find_tlv(tlvs, TlvType::Challenge)? {
+            Tlv::Challenge(challenge) => challenge,
+            _ => return None,
+        };
+        let link_margin = match find_tlv(tlvs, TlvType::LinkMargin)? {
+            Tlv::LinkMargin(link_margin) => link_margin,
+            _ => return None,
+        };
+        let score = match find_tlv(tlvs, TlvType::Connectivity)? {
+            Tlv::Connectivity {
+                parent_priority,
+                link_quality_3,
+                link_quality_2,
+                link_quality_1,
+                ..
+            } => (
+                link_quality(link_margin),
+                // The priority is a signed 2-bit field in the top bits
+                (parent_priority as i8) >> 6,
+                link_quality_3,
+                link_quality_2,
+                link_quality_1,
+            ),
+            _ => return None,
+        };
+        if self
+            .candidate
+            .get()
+            .map_or(true, |candidate| score > candidate.score)
+        {
+            self.candidate.set(Some(Parent {
+                ext_addr: rx_info.ext_addr,
+                rloc16,
+                link_frame_counter,
+                mle_frame_counter: mle_frame_counter.saturating_add(1),
+                challenge,
+                score,
+            }));
+        }
+        Some(())
+    }
+
+    fn child_id_response(&self, rx_info: RxInfo, tlvs: &[u8]) -> Option<()> {
+        let parent = self.check_parent(rx_info)?;
+        let parent_rloc16 = match find_tlv(tlvs, TlvType::SourceAddress)? {
+            Tlv::SourceAddress(rloc16) => rloc16,
+            _ => return None,
+        };
+        let rloc16 = match find_tlv(tlvs, TlvType::Address16)? {
+            Tlv::Address16(rloc16) => rloc16,
+            _ => return None,
+        };
+        // The parent may have become a router with a new RLOC16 if it was a
+        // REED, so the address of the child is checked against the one the
+        // parent uses now.
+        if rloc16 >> ROUTER_ID_SHIFT != parent_rloc16 >> ROUTER_ID_SHIFT
+            || rloc16 & CHILD_ID_MASK == 0
+        {
+            return None;
+        }
+        let leader_data = LeaderData::find(tlvs)?;
+
+        let now = self.now();
+        self.parent.set(Some(Parent {
+            rloc16: parent_rloc16,
+            ..parent
+        }));
+        self.rloc16.set(Some(rloc16));
+        self.mac.set_address(rloc16);
+        self.mac.config_commit();
+        self.state.set(ChildState::Child);
+        self.attach_attempts.set(0);
+        self.poll_failures.set(0);
+        self.deadline.set(None);
+        self.poll_deadline.set(Some(now + self.poll_interval(now)));
+        self.leader_data.set(None);
+        self.update_leader_data(leader_data, tlvs);
+        Some(())
+    }
+
+    /// Handles the messages of the parent of an attached child.
+    fn parent_message(&self, rx_info: RxInfo, command: MleCommand, tlvs: &[u8]) -> Option<()> {
+        match command {
+            MleCommand::ChildUpdateRequest
+            | MleCommand::ChildUpdateResponse
+            | MleCommand::DataResponse
+            | MleCommand::Advertisement => {}
+            _ => return None,
+        }
+        self.check_parent(rx_info)?;
+        if let Some(Tlv::Status(STATUS_ERROR)) = find_tlv(tlvs, TlvType::Status) {
+            self.reattach();
+            return Some(());
+        }
+        if let Some(leader_data) = LeaderData::find(tlvs) {
+            self.update_leader_data(leader_data, tlvs);
+        }
+        if command == MleCommand::ChildUpdateRequest && self.state.get() == ChildState::Child {
+            let challenge = match find_tlv(tlvs, TlvType::Challenge) {
+                Some(Tlv::Challenge(challenge)) => Some(challenge),
+                _ => None,
+            };
+            self.response_challenge.set(challenge);
+            self.child_update_response_pending.set(true);
+        }
+        Some(())
+    }
+
+    /// Checks that a message comes from the parent and is not a replay, and
+    /// records its frame counter. Returns the parent.
+    fn check_parent(&self, rx_info: RxInfo) -> Option<Parent> {
+        let parent = self.parent.get()?;
+        let frame_counter = rx_info.aux_header.frame_counter;
+        if parent.ext_addr != rx_info.ext_addr || frame_counter < parent.mle_frame_counter {
+            return None;
+        }
+        let parent = Parent {
+            mle_frame_counter: frame_counter.saturating_add(1),
+            ..parent
+        };
+        self.parent.set(Some(parent));
+        Some(parent)
+    }
+
+    /// Tracks the leader data and the stable network data of the partition,
+    /// requesting the network data when it is newer than the one the child
+    /// has.
+    fn update_leader_data(&self, leader_data: LeaderData, tlvs: &[u8]) {
+        let current = self.leader_data.get();
+        if let Some(current) = current {
+            if current.partition_id != leader_data.partition_id {
+                self.reattach();
+                return;
+            }
+        }
+        if let Some(Tlv::NetworkData(data)) = find_tlv(tlvs, TlvType::NetworkData) {
+            let stored = self.network_data.map_or(false, |buf| {
+                if data.len() > buf.len() {
+                    return false;
+                }
+                buf[..data.len()].copy_from_slice(data);
+                self.network_data_len.set(data.len());
+                true
+            });
+            if stored {
+                self.leader_data.set(Some(leader_data));
+                self.fast_poll_until.set(0);
+            }
+            return;
+        }
+        let newer = current.map_or(true, |current| {
+            version_newer(leader_data.stable_data_version, current.stable_data_version)
+        });
+        if newer {
+            let now = self.now();
+            self.data_request_pending.set(true);
+            self.fast_poll_until.set(now + DATA_RESPONSE_TIMEOUT_MS);
+            let poll_at = now + FAST_POLL_MS;
+            self.poll_deadline.set(Some(
+                self.poll_deadline
+                    .get()
+                    .map_or(poll_at, |deadline| deadline.min(poll_at)),
+            ));
+        } else {
+            self.leader_data.set(Some(leader_data));
+        }
+    }
+
+    // Sending
+
+    /// Builds the next pending MLE message into `buf`. Returns its
+    /// destination and length.
+    fn next_message(&self, buf: &mut [u8]) -> Option<(IPAddr, usize)> {
+        let parent = self.parent.get();
+        let parent_addr =
+            parent.map(|parent| IPAddr::generate_from_mac(MacAddress::Long(parent.ext_addr)));
+        if self.parent_request_pending.take() {
+            let mut scan_mask = MulticastResponder::Router as u8;
+            if self.scan_reeds.get() {
+                scan_mask |= MulticastResponder::EndDevice as u8;
+            }
+            buf[0] = MleCommand::ParentRequest as u8;
+            let mut len = append_tlv(buf, 1, Tlv::Mode(MODE))?;
+            len = append_tlv(buf, len, Tlv::Challenge(self.challenge.get()))?;
+            len = append_tlv(buf, len, Tlv::ScanMask(scan_mask))?;
+            len = append_tlv(buf, len, Tlv::Version(THREAD_VERSION))?;
+            return Some((LINK_LOCAL_ALL_ROUTERS, len));
+        }
+        if self.child_id_request_pending.take() {
+            let parent = parent?;
+            let requested = [TlvType::Address16 as u8, TlvType::NetworkData as u8];
+            buf[0] = MleCommand::ChildIdRequest as u8;
+            // Frames of the child only use frame counters above this one.
+            let frame_counter = self.take_frame_counter()?;
+            let mut len = append_tlv(buf, 1, Tlv::Response(parent.challenge))?;
+            len = append_tlv(buf, len, Tlv::LinkLayerFrameCounter(frame_counter))?;
+            len = append_tlv(buf, len, Tlv::MleFrameCounter(frame_counter))?;
+            len = append_tlv(buf, len, Tlv::Mode(MODE))?;
+            len = append_tlv(buf, len, Tlv::Timeout(self.timeout_s.get()))?;
+            len = append_tlv(buf, len, Tlv::Version(THREAD_VERSION))?;
+            len = append_tlv(buf, len, Tlv::TlvRequest(&requested))?;
+            return Some((parent_addr?, len));
+        }
+        if self.child_update_response_pending.take() {
+            let challenge = self.response_challenge.take();
+            buf[0] = MleCommand::ChildUpdateResponse as u8;
+            let mut len = append_tlv(buf, 1, Tlv::SourceAddress(self.rloc16.get()?))?;
+            len = append_tlv(buf, len, Tlv::Mode(MODE))?;
+            len = append_tlv(buf, len, Tlv::Timeout(self.timeout_s.get()))?;
+            if let Some(leader_data) = self.leader_data.get() {
+                len = append_tlv(buf, len, leader_data.tlv())?;
+            }
+            if let Some(challenge) = challenge {
+                let frame_counter = self.take_frame_counter()?;
+                len = append_tlv(buf, len, Tlv::Response(challenge))?;
+                len = append_tlv(buf, len, Tlv::LinkLayerFrameCounter(frame_counter))?;
+                len = append_tlv(buf, len, Tlv::MleFrameCounter(frame_counter))?;
+            }
+            return Some((parent_addr?, len));
+        }
+        if self.data_request_pending.take() {
+            let requested = [TlvType::NetworkData as u8];
+            buf[0] = MleCommand::DataRequest as u8;
+            let len = append_tlv(buf, 1, Tlv::TlvRequest(&requested))?;
+            return Some((parent_addr?, len));
+        }
+        None
+    }
+
+    /// Encrypts and sends the next pending MLE message, unless another
+    /// message is being processed. The message is dropped if no frame counter
+    /// is available.
+    fn send_next(&self) {
+        if !matches!(self.op.get(), Op::Idle) {
+            return;
+        }
+        let keys = match self.keys.get() {
+            Some(keys) => keys,
+            None => return,
+        };
+        let buf = match self.crypt_buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        let message = self.next_message(&mut buf[AUTH_DATA_LEN..AUTH_DATA_LEN + MAX_TX_MLE_LEN]);
+        let (dst, len, frame_counter) = match message {
+            Some((dst, len)) => match self.take_frame_counter() {
+                Some(frame_counter) => (dst, len, frame_counter),
+                None => {
+                    self.crypt_buf.replace(buf);
+                    return;
+                }
+            },
+            None => {
+                self.crypt_buf.replace(buf);
+                return;
+            }
+        };
+        let aux_header = AuxHeader {
+            frame_counter,
+            key_sequence: keys.sequence,
+        };
+        encode_auth_data(buf, &self.link_local(), &dst, &aux_header);
+        self.op.set(Op::Encrypting { dst, len });
+        self.crypt(buf, &keys.mle, &self.ext_addr, frame_counter, len, true);
+    }
+
+    fn decrypt(&self, buf: &'static mut [u8], keys: &Keys, rx_info: RxInfo) {
+        self.op.set(Op::Decrypting);
+        self.crypt(
+            buf,
+            &keys.mle,
+            &rx_info.ext_addr,
+            rx_info.aux_header.frame_counter,
+            rx_info.len,
+            false,
+        );
+    }
+
+    /// Encrypts or decrypts the message in `buf`, whose authenticated data
+    /// must already be in place.
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        key: &[u8; 16],
+        ext_addr: &[u8; 8],
+        frame_counter: u32,
+        len: usize,
+        encrypting: bool,
+    ) {
+        let nonce = nonce(ext_addr, frame_counter);
+        if self.ccm.set_key(key) != ReturnCode::SUCCESS
+            || self.ccm.set_nonce(&nonce) != ReturnCode::SUCCESS
+        {
+            self.crypt_buf.replace(buf);
+            self.op.set(Op::Idle);
+            return;
+        }
+        let (result, buf) = self
+            .ccm
+            .crypt(buf, 0, AUTH_DATA_LEN, len, MIC_LEN, true, encrypting);
+        if result != ReturnCode::SUCCESS {
+            if let Some(buf) = buf {
+                self.crypt_buf.replace(buf);
+            }
+            self.rx_info.set(None);
+            self.op.set(Op::Idle);
+        }
+    }
+
+    /// Sends the encrypted message in `buf` to `dst`.
+    fn send_encrypted(&self, buf: &[u8], dst: IPAddr, len: usize) {
+        let tx_buf = match self.tx_buf.take() {
+            Some(tx_buf) => tx_buf,
+            None => {
+                self.op.set(Op::Idle);
+                return;
+            }
+        };
+        let secured_len = len + MIC_LEN;
+        tx_buf[0] = SECURITY_SUITE_ENABLED;
+        tx_buf[1..SECURITY_HEADER_LEN]
+            .copy_from_slice(&buf[AUTH_DATA_LEN - AUX_HEADER_LEN..AUTH_DATA_LEN]);
+        tx_buf[SECURITY_HEADER_LEN..SECURITY_HEADER_LEN + secured_len]
+            .copy_from_slice(&buf[AUTH_DATA_LEN..AUTH_DATA_LEN + secured_len]);
+        let mut payload = LeasableBuffer::new(tx_buf);
+        payload.slice(..SECURITY_HEADER_LEN + secured_len);
+        match self
+            .udp_sender
+            .send_to(dst, MLE_PORT, payload, self.net_cap)
+        {
+            Ok(()) => self.op.set(Op::Sending),
+            Err(payload) => {
+                self.tx_buf.replace(payload.take());
+                self.op.set(Op::Idle);
+            }
+        }
+    }
+
+    /// Sends a MAC Data Request to the parent, and schedules the next one.
+    fn send_poll(&self) {
+        let now = self.now();
+        let state = self.state.get();
+        let (parent, keys) = match (self.parent.get(), self.keys.get()) {
+            (Some(parent), Some(keys))
+                if state == ChildState::ChildIdRequest || state == ChildState::Child =>
+            {
+                (parent, keys)
+            }
+            _ => return,
+        };
+        self.poll_deadline.set(Some(now + self.poll_interval(now)));
+        let (dst_addr, src_addr) = match (state, self.rloc16.get()) {
+            (ChildState::Child, Some(rloc16)) => {
+                (MacAddress::Short(parent.rloc16), MacAddress::Short(rloc16))
+            }
+            _ => (
+                MacAddress::Long(parent.ext_addr),
+                MacAddress::Long(self.ext_addr),
+            ),
+        };
+        let buf = match self.poll_buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        let pan = self.mac.get_pan();
+        let security = Some((
+            SecurityLevel::EncMic32,
+            KeyId::Index(key_index(keys.sequence)),
+        ));
+        match self.mac.prepare_command_frame(
+            buf,
+            pan,
+            dst_addr,
+            pan,
+            src_addr,
+            CommandId::DataRequest,
+            security,
+        ) {
+            Ok(frame) => {
+                let (result, buf) = self.mac.transmit(frame);
+                if result != ReturnCode::SUCCESS {
+                    if let Some(buf) = buf {
+                        self.poll_buf.replace(buf);
+                    }
+                }
+            }
+            Err(buf) => {
+                self.poll_buf.replace(buf);
+            }
+        }
+    }
+}
+
+impl<'a, A, H, C> time::AlarmClient for ThreadChild<'a, A, H, C>
+where
+    A: time::Alarm<'a>,
+    H: digest::Digest<'a, [u8; 32]> + digest::HMACSha256,
+    C: AES128CCM<'a>,
+{
+    fn alarm(&self) {
+        let now = self.now();
+        if self
+            .deadline
+            .get()
+            .map_or(false, |deadline| now >= deadline)
+        {
+            self.deadline.set(None);
+            self.attach_timeout();
+        }
+        if self
+            .poll_deadline
+            .get()
+            .map_or(false, |deadline| now >= deadline)
+        {
+            self.poll_deadline.set(None);
+            self.send_poll();
+        }
+        self.send_next();
+        self.schedule_alarm();
+    }
+}
+
+impl<'a, A, H, C> digest::Client<'a, [u8; 32]> for ThreadChild<'a, A, H, C>
+where
+    A: time::Alarm<'a>,
+    H: digest::Digest<'a, [u8; 32]> + digest::HMACSha256,
+    C: AES128CCM<'a>,
+{
+    fn add_data_done(&'a self, result: Result<(), ReturnCode>, data: &'static mut [u8]) {
+        self.key_input.replace(data);
+        let for_rx = match self.op.get() {
+            Op::DerivingKeys { for_rx, .. } => for_rx,
+            _ => return,
+        };
+        let digest = match (result, self.digest.take()) {
+            (Ok(()), Some(digest)) => digest,
+            (_, digest) => {
+                if let Some(digest) = digest {
+                    self.digest.replace(digest);
+                }
+                self.keys_failed(for_rx);
+                self.schedule_alarm();
+                return;
+            }
+        };
+        if let Err((_, digest)) = self.hmac.run(digest) {
+            self.digest.replace(digest);
+            self.keys_failed(for_rx);
+            self.schedule_alarm();
+        }
+    }
+
+    fn hash_done(&'a self, result: Result<(), ReturnCode>, digest: &'static mut [u8; 32]) {
+        let (sequence, for_rx) = match self.op.get() {
+            Op::DerivingKeys { sequence, for_rx } => (sequence, for_rx),
+            _ => {
+                self.digest.replace(digest);
+                return;
+            }
+        };
+        if result.is_err() {
+            self.digest.replace(digest);
+            self.keys_failed(for_rx);
+            self.schedule_alarm();
+            return;
+        }
+        // The first half of the digest is the MLE key, and the second half
+        // the MAC key.
+        let mut keys = Keys {
+            sequence,
+            mle: [0; 16],
+            mac: [0; 16],
+        };
+        keys.mle.copy_from_slice(&digest[..16]);
+        keys.mac.copy_from_slice(&digest[16..]);
+        for byte in digest.iter_mut() {
+            *byte = 0;
+        }
+        self.digest.replace(digest);
+        self.hmac.clear_data();
+        self.keys_derived(keys, for_rx);
+        self.send_next();
+        self.schedule_alarm();
+    }
+}
+
+impl<'a, A, H, C> CCMClient for ThreadChild<'a, A, H, C>
+where
+    A: time::Alarm<'a>,
+    H: digest::Digest<'a, [u8; 32]> + digest::HMACSha256,
+    C: AES128CCM<'a>,
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        match self.op.get() {
+            Op::Encrypting { dst, len } => {
+                if res == ReturnCode::SUCCESS {
+                    self.send_encrypted(buf, dst, len);
+                } else {
+                    self.op.set(Op::Idle);
+                }
+                self.crypt_buf.replace(buf);
+            }
+            Op::Decrypting => {
+                self.op.set(Op::Idle);
+                let rx_keys = self.rx_keys.take();
+                if let Some(rx_info) = self.rx_info.take() {
+                    if res == ReturnCode::SUCCESS && tag_is_valid {
+                        // A newer key sequence is used once a message secured
+                        // with it is authenticated.
+                        if let Some(keys) = rx_keys {
+                            self.adopt_keys(keys);
+                        }
+                        self.handle_message(
+                            rx_info,
+                            &buf[AUTH_DATA_LEN..AUTH_DATA_LEN + rx_info.len],
+                        );
+                    }
+                }
+                self.crypt_buf.replace(buf);
+            }
+            _ => {
+                self.crypt_buf.replace(buf);
+                return;
+            }
+        }
+        self.send_next();
+        self.schedule_alarm();
+    }
+}
+
+impl<'a, A, H, C> UDPSendClient for ThreadChild<'a, A, H, C>
+where
+    A: time::Alarm<'a>,
+    H: digest::Digest<'a, [u8; 32]> + digest::HMACSha256,
+    C: AES128CCM<'a>,
+{
+    fn send_done(&self, _result: ReturnCode, dgram: LeasableBuffer<'static, u8>) {
+        self.tx_buf.replace(dgram.take());
+        if matches!(self.op.get(), Op::Sending) {
+            self.op.set(Op::Idle);
+        }
+        self.send_next();
+        self.schedule_alarm();
+    }
+}
+
+impl<'a, A, H, C> UDPRecvClient for ThreadChild<'a, A, H, C>
+where
+    A: time::Alarm<'a>,
+    H: digest::Digest<'a, [u8; 32]> + digest::HMACSha256,
+    C: AES128CCM<'a>,
+{
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        dst_addr: IPAddr,
+        src_port: u16,
+        dst_port: u16,
+        payload: &[u8],
+    ) {
+        if src_port != MLE_PORT
+            || dst_port != MLE_PORT
+            || self.state.get() == ChildState::Disabled
+            || !matches!(self.op.get(), Op::Idle)
+        {
+            return;
+        }
+        let keys = match self.keys.get() {
+            Some(keys) => keys,
+            None => return,
+        };
+        if payload.len() < SECURITY_HEADER_LEN + 1 + MIC_LEN
+            || payload.len() > SECURITY_HEADER_LEN + MAX_MLE_LEN + MIC_LEN
+            || payload[0] != SECURITY_SUITE_ENABLED
+        {
+            return;
+        }
+        let aux_header = match AuxHeader::decode(&payload[1..SECURITY_HEADER_LEN]).done() {
+            Some((_, aux_header)) => aux_header,
+            None => return,
+        };
+        let ext_addr = match src_addr.link_local_mac() {
+            Some(MacAddress::Long(ext_addr)) => ext_addr,
+            _ => return,
+        };
+        // Once attached, only the parent can switch to a newer key sequence.
+        let sequence = aux_header.key_sequence;
+        let switch_keys = sequence > keys.sequence
+            && (self.state.get() != ChildState::Child
+                || self
+                    .parent
+                    .get()
+                    .map_or(false, |parent| parent.ext_addr == ext_addr));
+        if sequence != keys.sequence && !switch_keys {
+            return;
+        }
+        let buf = match self.crypt_buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        let len = payload.len() - SECURITY_HEADER_LEN - MIC_LEN;
+        encode_auth_data(buf, &src_addr, &dst_addr, &aux_header);
+        buf[AUTH_DATA_LEN..AUTH_DATA_LEN + len + MIC_LEN]
+            .copy_from_slice(&payload[SECURITY_HEADER_LEN..]);
+        let rx_info = RxInfo {
+            ext_addr,
+            aux_header,
+            len,
+        };
+        self.rx_info.set(Some(rx_info));
+        if switch_keys {
+            self.crypt_buf.replace(buf);
+            if self.derive_keys(sequence, true) != ReturnCode::SUCCESS {
+                self.rx_info.set(None);
+            }
+        } else {
+            self.decrypt(buf, &keys, rx_info);
+        }
+    }
+}
+
+impl<'a, A, H, C> TxClient for ThreadChild<'a, A, H, C>
+where
+    A: time::Alarm<'a>,
+    H: digest::Digest<'a, [u8; 32]> + digest::HMACSha256,
+    C: AES128CCM<'a>,
+{
+    fn send_done(&self, spi_buf: &'static mut [u8], acked: bool, result: ReturnCode) {
+        self.poll_buf.replace(spi_buf);
+        if self.state.get() != ChildState::Child {
+            return;
+        }
+        if acked && result == ReturnCode::SUCCESS {
+            self.poll_failures.set(0);
+            return;
+        }
+        let failures = self.poll_failures.get() + 1;
+        self.poll_failures.set(failures);
+        if failures >= MAX_POLL_FAILURES {
+            self.poll_failures.set(0);
+            self.reattach();
+            self.send_next();
+            self.schedule_alarm();
+        }
+    }
+}
+
+impl<'a, A, H, C> KeyProcedure for ThreadChild<'a, A, H, C>
+where
+    A: time::Alarm<'a>,
+    H: digest::Digest<'a, [u8; 32]> + digest::HMACSha256,
+    C: AES128CCM<'a>,
+{
+    /// Returns the MAC key of the current key sequence, which is identified
+    /// by its key index with key identifier mode 1.
+    fn lookup_key(&self, level: SecurityLevel, key_id: KeyId) -> Option<[u8; 16]> {
+        let keys = self.keys.get()?;
+        if level == SecurityLevel::None || key_id != KeyId::Index(key_index(keys.sequence)) {
+            return None;
+        }
+        Some(keys.mac)
+    }
+}
+
+impl<'a, A, H, C> DeviceProcedure for ThreadChild<'a, A, H, C>
+where
+    A: time::Alarm<'a>,
+    H: digest::Digest<'a, [u8; 32]> + digest::HMACSha256,
+    C: AES128CCM<'a>,
+{
+    /// The only device known to a child is its parent.
+    fn lookup_addr_long(&self, addr: MacAddress) -> Option<[u8; 8]> {
+        let parent = self.parent.get()?;
+        match addr {
+            MacAddress::Long(ext_addr) if ext_addr == parent.ext_addr => Some(ext_addr),
+            MacAddress::Short(rloc16)
+                if rloc16 == parent.rloc16 && self.state.get() == ChildState::Child =>
+            {
+                Some(parent.ext_addr)
+            }
+            _ => None,
+        }
+    }
+
+    /// MAC frames use the same frame counters as MLE messages.
+    fn next_frame_counter(&self) -> Option<u32> {
+        self.take_frame_counter()
+    }
+
+    fn check_frame_counter(&self, addr_long: [u8; 8], frame_counter: u32) -> bool {
+        self.parent.get().map_or(false, |parent| {
+            parent.ext_addr == addr_long && frame_counter >= parent.link_frame_counter
+        })
+    }
+
+    fn update_frame_counter(&self, addr_long: [u8; 8], frame_counter: u32) {
+        if let Some(parent) = self.parent.get() {
+            if parent.ext_addr == addr_long {
+                self.parent.set(Some(Parent {
+                    link_frame_counter: frame_counter.saturating_add(1),
+                    ..parent
+                }));
+            }
+        }
+    }
+}
+
+impl<'a, A, H, C> IP6Router for ThreadChild<'a, A, H, C>
+where
+    A: time::Alarm<'a>,
+    H: digest::Digest<'a, [u8; 32]> + digest::HMACSha256,
+    C: AES128CCM<'a>,
+{
+    /// Multicast packets are broadcast, link local packets are sent to the
+    /// MAC address of their destination, and all other packets to the parent.
+    fn next_hop(&self, dst: IPAddr) -> Option<MacAddress> {
+        if dst.is_multicast() {
+            return Some(MacAddress::Short(0xffff));
+        }
+        if dst.is_unicast_link_local() {
+            return dst.link_local_mac();
+        }
+        match (self.state.get(), self.parent.get()) {
+            (ChildState::Child, Some(parent)) => Some(MacAddress::Short(parent.rloc16)),
+            _ => None,
+        }
+    }
+}
+
+impl<'a, A, H, C> IP6LinkSecurity for ThreadChild<'a, A, H, C>
+where
+    A: time::Alarm<'a>,
+    H: digest::Digest<'a, [u8; 32]> + digest::HMACSha256,
+    C: AES128CCM<'a>,
+{
+    /// Packets other than MLE messages are secured with the MAC key of the
+    /// current key sequence.
+    fn link_security(&self, _next_hop: MacAddress) -> Option<(SecurityLevel, KeyId)> {
+        let keys = self.keys.get()?;
+        Some((
+            SecurityLevel::EncMic32,
+            KeyId::Index(key_index(keys.sequence)),
+        ))
+    }
+}
+
+impl<'a, A, H, C> rng::Client for ThreadChild<'a, A, H, C>
+where
+    A: time::Alarm<'a>,
+    H: digest::Digest<'a, [u8; 32]> + digest::HMACSha256,
+    C: AES128CCM<'a>,
+{
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        error: ReturnCode,
+    ) -> rng::Continue {
+        if error != ReturnCode::SUCCESS {
+            return rng::Continue::More;
+        }
+        let mut pool = self.random_pool.get();
+        let mut len = self.random_len.get();
+        while len < pool.len() {
+            match randomness.next() {
+                Some(word) => {
+                    pool[len] = word;
+                    len += 1;
+                }
+                None => break,
+            }
+        }
+        self.random_pool.set(pool);
+        self.random_len.set(len);
+        if len < pool.len() {
+            return rng::Continue::More;
+        }
+        // An attach that waited for its challenge can start now.
+        if self.state.get() == ChildState::Detached
+            && self.deadline.get().is_none()
+            && self.keys.get().is_some()
+        {
+            self.attach();
+            self.send_next();
+            self.schedule_alarm();
+        }
+        rng::Continue::Done
+    }
+}
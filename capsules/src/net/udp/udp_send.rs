@@ -16,7 +16,7 @@
 //! packet to the MuxUdpSender queue at a time.
 
 use crate::net::ipv6::ip_utils::IPAddr;
-use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::ipv6_send::{IP6LinkSecurity, IP6Router, IP6SendClient, IP6Sender};
 use crate::net::ipv6::TransportHeader;
 use crate::net::network_capabilities::{NetworkCapability, UdpVisibilityCapability};
 use crate::net::udp::udp_port_table::UdpPortBindingTx;
@@ -43,6 +43,18 @@ impl<'a, T: IP6Sender<'a>> MuxUdpSender<'a, T> {
         }
     }
 
+    /// Sets the router that chooses the next hop of the datagrams sent by
+    /// all senders of this mux.
+    pub fn set_router(&self, router: &'a dyn IP6Router) {
+        self.ip_sender.set_router(router);
+    }
+
+    /// Sets the layer that chooses the MAC security of the datagrams sent by
+    /// all senders of this mux.
+    pub fn set_link_security(&self, link_security: &'a dyn IP6LinkSecurity) {
+        self.ip_sender.set_link_security(link_security);
+    }
+
     fn send_to(
         &self,
         dest: IPAddr,
@@ -0,0 +1,231 @@
+//! Map block reads and writes to a region of nonvolatile storage.
+//!
+//! This exposes `length` bytes of a `NonvolatileStorage`, starting at address
+//! `start`, as a block device with 512 byte blocks, so that capsules written
+//! for SD cards, such as the USB Mass Storage class, can also use flash. The
+//! region should be a multiple of the block size: bytes after the last whole
+//! block are not accessible.
+//!
+//! Blocks go through a buffer of one block owned by this module, so that the
+//! buffer of the client can always be given back to it, even when the
+//! nonvolatile storage rejects an operation. This module must be the only
+//! client of the nonvolatile storage.
+//!
+//! ```plain
+//!     hil::block_storage::BlockStorage
+//!                ┌─────────────┐
+//!                │             │
+//!                │ This module │
+//!                │             │
+//!                └─────────────┘
+//! hil::nonvolatile_storage::NonvolatileStorage
+//! ```
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::{hil, static_init};
+//!
+//! let block_buffer = static_init!([u8; 512], [0; 512]);
+//! let nv_to_blocks = static_init!(
+//!     capsules::nonvolatile_to_blocks::NonvolatileToBlocks<'static>,
+//!     capsules::nonvolatile_to_blocks::NonvolatileToBlocks::new(
+//!         nv_to_page,
+//!         block_buffer,
+//!         0x60000,
+//!         0x20000));
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(nv_to_page, nv_to_blocks);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::block_storage::{BlockStorage, BlockStorageClient};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::ReturnCode;
+
+pub const BLOCK_SIZE: usize = 512;
+
+/// This module is either waiting to do something, or handling a read/write.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    Read,
+    Write,
+}
+
+pub struct NonvolatileToBlocks<'a> {
+    /// The module providing a `NonvolatileStorage` interface.
+    storage: &'a dyn NonvolatileStorage<'static>,
+    /// Callback to the user of this capsule.
+    client: OptionalCell<&'a dyn BlockStorageClient>,
+    /// Buffer of one block, passed to the nonvolatile storage.
+    block_buffer: TakeCell<'static, [u8]>,
+    /// Address of the first block in the nonvolatile storage.
+    start: usize,
+    /// Number of blocks of the region.
+    num_blocks: u32,
+    /// Current state of this capsule.
+    state: Cell<State>,
+    /// Temporary holding place for the user's buffer.
+    buffer: TakeCell<'static, [u8]>,
+    /// Block being read or written.
+    block: Cell<u32>,
+    /// Index of that block in the user's buffer.
+    index: Cell<u32>,
+    /// Number of blocks of the operation.
+    count: Cell<u32>,
+}
+
+impl<'a> NonvolatileToBlocks<'a> {
+    pub fn new(
+        storage: &'a dyn NonvolatileStorage<'static>,
+        block_buffer: &'static mut [u8; BLOCK_SIZE],
+        start: usize,
+        length: usize,
+    ) -> NonvolatileToBlocks<'a> {
+        NonvolatileToBlocks {
+            storage,
+            client: OptionalCell::empty(),
+            block_buffer: TakeCell::new(block_buffer),
+            start,
+            num_blocks: (length / BLOCK_SIZE) as u32,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::empty(),
+            block: Cell::new(0),
+            index: Cell::new(0),
+            count: Cell::new(0),
+        }
+    }
+
+    /// Checks and starts an operation on `count` blocks at `block`.
+    fn start(
+        &self,
+        state: State,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        if self.state.get() != State::Idle {
+            return Err((ReturnCode::EBUSY, buffer));
+        }
+        if count == 0
+            || buffer.len() < count as usize * BLOCK_SIZE
+            || block as u64 + count as u64 > self.num_blocks as u64
+        {
+            return Err((ReturnCode::EINVAL, buffer));
+        }
+
+        self.state.set(state);
+        self.buffer.replace(buffer);
+        self.block.set(block);
+        self.index.set(0);
+        self.count.set(count);
+        match self.next_block() {
+            ReturnCode::SUCCESS => Ok(()),
+            rcode => {
+                self.state.set(State::Idle);
+                Err((rcode, self.buffer.take().unwrap()))
+            }
+        }
+    }
+
+    /// Starts reading or writing the current block. A failure leaves the
+    /// operation in progress, to be completed by the caller.
+    fn next_block(&self) -> ReturnCode {
+        let block_buffer = match self.block_buffer.take() {
+            Some(block_buffer) => block_buffer,
+            // The nonvolatile storage did not give back the buffer of a
+            // rejected operation.
+            None => return ReturnCode::FAIL,
+        };
+        let address = self.start + self.block.get() as usize * BLOCK_SIZE;
+        let offset = self.index.get() as usize * BLOCK_SIZE;
+        match self.state.get() {
+            State::Read => self.storage.read(block_buffer, address, BLOCK_SIZE),
+            State::Write => {
+                self.buffer.map(|buffer| {
+                    block_buffer.copy_from_slice(&buffer[offset..offset + BLOCK_SIZE]);
+                });
+                self.storage.write(block_buffer, address, BLOCK_SIZE)
+            }
+            State::Idle => {
+                self.block_buffer.replace(block_buffer);
+                ReturnCode::FAIL
+            }
+        }
+    }
+
+    /// Called when the current block was read or written.
+    fn block_done(&self, block_buffer: &'static mut [u8]) {
+        if self.state.get() == State::Read {
+            let offset = self.index.get() as usize * BLOCK_SIZE;
+            self.buffer.map(|buffer| {
+                buffer[offset..offset + BLOCK_SIZE].copy_from_slice(block_buffer);
+            });
+        }
+        self.block_buffer.replace(block_buffer);
+
+        self.block.set(self.block.get() + 1);
+        self.index.set(self.index.get() + 1);
+        let result = if self.index.get() < self.count.get() {
+            match self.next_block() {
+                ReturnCode::SUCCESS => return,
+                rcode => rcode,
+            }
+        } else {
+            ReturnCode::SUCCESS
+        };
+
+        let state = self.state.replace(State::Idle);
+        if let Some(buffer) = self.buffer.take() {
+            self.client.map(move |client| match state {
+                State::Read => client.read_done(result, buffer),
+                State::Write => client.write_done(result, buffer),
+                State::Idle => {}
+            });
+        }
+    }
+}
+
+impl<'a> BlockStorage<'a> for NonvolatileToBlocks<'a> {
+    fn set_client(&self, client: &'a dyn BlockStorageClient) {
+        self.client.set(client);
+    }
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn num_blocks(&self) -> u32 {
+        self.num_blocks
+    }
+
+    fn read_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        self.start(State::Read, buffer, block, count)
+    }
+
+    fn write_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        self.start(State::Write, buffer, block, count)
+    }
+}
+
+impl<'a> NonvolatileStorageClient<'static> for NonvolatileToBlocks<'a> {
+    fn read_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.block_done(buffer);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.block_done(buffer);
+    }
+}
@@ -5,13 +5,41 @@
 //! --------
 //!
 //! This module provides a simple text-based console to inspect and control
-//! which processes are running. The console has five commands:
+//! which processes are running. The console has these commands:
 //!  - 'help' prints the available commands and arguments
 //!  - 'status' prints the current system status
 //!  - 'list' lists the current processes with their IDs and running state
 //!  - 'stop n' stops the process with name n
 //!  - 'start n' starts the stopped process with name n
 //!  - 'fault n' forces the process with name n into a fault state
+//!  - 'memory n' prints the memory map of the process with name n
+//!  - 'grants n' prints how much memory each grant of the process with name n
+//!    uses
+//!  - 'mpu n' prints the MPU (or PMP) configuration the process with name n
+//!    runs under
+//!  - 'callbacks n' lists the callbacks the process with name n has subscribed
+//!  - 'syscalls n' lists the last syscalls of the process with name n, the
+//!    most recent first
+//!  - 'trace' controls and reads out the syscall trace, if the board set one
+//!    with `set_syscall_trace()`:
+//!    - 'trace on' and 'trace off' start and stop recording
+//!    - 'trace app n' only records syscalls of the process with name n, and
+//!      'trace app all' records all processes again
+//!    - 'trace driver d' only records syscalls to driver number d (decimal or
+//!      0x hex), and 'trace driver all' records all drivers again
+//!    - 'trace dump' prints the oldest recorded syscalls and removes them from
+//!      the trace. Each one is printed as a `trace:` line holding the record
+//!      in hex; `tools/syscall_trace_decode.py` turns them into a timeline.
+//!  - 'crash' reads out the crash records kept in flash, if the board set a
+//!    crash log with `set_crash_log()`:
+//!    - 'crash' prints how many records there are
+//!    - 'crash n' prints the record with index n, 0 being the most recent, as
+//!      a summary and `crash:` lines holding the record in hex, which
+//!      `tools/crash_record_decode.py` decodes
+//!    - 'crash clear' erases all records
+//!
+//! The inspection commands work on running processes, so a process does not
+//! have to crash before its memory layout can be looked at.
 //!
 //! ### `list` Command Fields:
 //!
@@ -28,6 +56,9 @@
 //! - `State`: The state the process is in.
 //! - `Grants`: The number of grants that have been initialized for the process
 //!   out of the total number of grants defined by the kernel.
+//! - `Stack`: The most stack the process has used, in bytes, out of the stack
+//!   size declared in its TBF header (`?` if it did not declare one). This is
+//!   `?` if the process has not told the kernel where its stack starts.
 //!
 //! Setup
 //! -----
@@ -90,9 +121,9 @@
 //! Initialization complete. Entering main loop
 //! Hello World!
 //! list
-//! PID    Name    Quanta  Syscalls  Dropped Callbacks  Restarts    State  Grants
-//! 00     blink        0       113                  0         0  Yielded    1/12
-//! 01     c_hello      0         8                  0         0  Yielded    3/12
+//! PID    Name    Quanta  Syscalls  Dropped Callbacks  Restarts    State  Grants  Stack
+//! 00     blink        0       113                  0         0  Yielded    1/12     416/2048
+//! 01     c_hello      0         8                  0         0  Yielded    3/12     220/?
 //! ```
 //!
 //! To get a general view of the system, use the status command:
@@ -102,23 +133,56 @@
 //! Total processes: 2
 //! Active processes: 2
 //! Timeslice expirations: 0
+//! Deadline misses: 0
+//! Stack overflows: 0
+//! Sleep idle: 12 times, 35 ms
+//! Sleep sleep: 0 times, 0 ms
+//! Sleep deep sleep: 1520 times, 58124 ms
 //! ```
 //!
+//! Stack overflows counts the faults the kernel recognized as a process
+//! overflowing its stack. The sleep lines show how often and how long the chip slept in each sleep
+//! state (see `kernel::power`), followed by the driver that keeps the chip out
+//! of deeper states, if any.
+//!
 //! and you can control processes with the `start` and `stop` commands:
 //!
 //! ```text
 //! stop blink
 //! Process blink stopped
 //! ```
+//!
+//! To look at how a process uses its memory, use the `memory` command:
+//!
+//! ```text
+//! memory blink
+//! Memory map of process blink:
+//!  Flash        0x00030000-0x00030800   2048 bytes
+//!   Protected   0x00030000-0x00030048     72 bytes
+//!   App         0x00030048-0x00030800   1976 bytes
+//!  RAM          0x20004000-0x20006000   8192 bytes
+//!   Kernel      0x20005D7C-0x20006000    644 bytes
+//!   Grants      0x20005CFC-0x20005D7C    128 bytes
+//!   Unused      0x20004C00-0x20005CFC   4348 bytes
+//!   Heap        0x20004BF8-0x20004C00      8 bytes
+//!   Data        0x20004800-0x20004BF8   1016 bytes
+//!   Stack       0x20004000-0x20004800   2048 bytes, 416 used
+//! ```
 
 use core::cell::Cell;
 use core::cmp;
+use core::fmt;
 use core::str;
 use kernel::capabilities::ProcessManagementCapability;
-use kernel::common::cells::TakeCell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::crash_record::CrashRecordReader;
 use kernel::debug;
 use kernel::hil::uart;
 use kernel::introspection::KernelInfo;
+use kernel::power::SleepState;
+use kernel::procs::{ProcessType, SUBSCRIBED_CALLBACKS_LEN, SYSCALL_HISTORY_LEN};
+use kernel::syscall::Syscall;
+use kernel::syscall_trace::{SyscallTraceReader, RECORD_LEN};
 use kernel::Kernel;
 use kernel::ReturnCode;
 
@@ -132,6 +196,11 @@ pub static mut READ_BUF: [u8; 4] = [0; 4];
 // characters, limiting arguments to 25 bytes or so seems fine for now.
 pub static mut COMMAND_BUF: [u8; 32] = [0; 32];
 
+// How many trace records one 'trace dump' prints, so that the output fits in
+// the debug buffer.
+const TRACE_DUMP_RECORDS: usize = 8;
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
 pub struct ProcessConsole<'a, C: ProcessManagementCapability> {
     uart: &'a dyn uart::UartData<'a>,
     tx_in_progress: Cell<bool>,
@@ -148,6 +217,8 @@ pub struct ProcessConsole<'a, C: ProcessManagementCapability> {
     /// Internal flag that the process console should parse the command it just
     /// received after finishing echoing the last newline character.
     execute: Cell<bool>,
+    syscall_trace: OptionalCell<&'a dyn SyscallTraceReader>,
+    crash_log: OptionalCell<&'a dyn CrashRecordReader>,
     kernel: &'static Kernel,
     capability: C,
 }
@@ -171,11 +242,23 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
             command_index: Cell::new(0),
             running: Cell::new(false),
             execute: Cell::new(false),
+            syscall_trace: OptionalCell::empty(),
+            crash_log: OptionalCell::empty(),
             kernel: kernel,
             capability: capability,
         }
     }
 
+    /// Give the console a syscall trace to control with the `trace` command.
+    pub fn set_syscall_trace(&self, syscall_trace: &'a dyn SyscallTraceReader) {
+        self.syscall_trace.set(syscall_trace);
+    }
+
+    /// Give the console a crash log to read out with the `crash` command.
+    pub fn set_crash_log(&self, crash_log: &'a dyn CrashRecordReader) {
+        self.crash_log.set(crash_log);
+    }
+
     pub fn start(&self) -> ReturnCode {
         if self.running.get() == false {
             self.rx_buffer.take().map(|buffer| {
@@ -210,7 +293,7 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                         let clean_str = s.trim();
                         if clean_str.starts_with("help") {
                             debug!("Welcome to the process console.");
-                            debug!("Valid commands are: help status list stop start fault");
+                            debug!("Valid commands are: help status list stop start fault memory grants mpu callbacks syscalls trace crash");
                         } else if clean_str.starts_with("start") {
                             let argument = clean_str.split_whitespace().nth(1);
                             argument.map(|name| {
@@ -254,7 +337,7 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                                 );
                             });
                         } else if clean_str.starts_with("list") {
-                            debug!(" PID    Name                Quanta  Syscalls  Dropped Callbacks  Restarts    State  Grants");
+                            debug!(" PID    Name                Quanta  Syscalls  Dropped Callbacks  Restarts    State  Grants  Stack");
                             self.kernel
                                 .process_each_capability(&self.capability, |proc| {
                                     let info: KernelInfo = KernelInfo::new(self.kernel);
@@ -263,8 +346,10 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                                     let appid = proc.appid();
                                     let (grants_used, grants_total) = info.number_app_grant_uses(appid, &self.capability);
 
+                                    let stack_usage = StackUsage(info.app_stack_usage(appid, &self.capability));
+
                                     debug!(
-                                        "  {:?}\t{:<20}{:6}{:10}{:19}{:10}  {:?}{:5}/{}",
+                                        "  {:?}\t{:<20}{:6}{:10}{:19}{:10}  {:?}{:5}/{:<4}{}",
                                         appid,
                                         pname,
                                         proc.debug_timeslice_expiration_count(),
@@ -273,7 +358,8 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                                         proc.get_restart_count(),
                                         proc.get_state(),
                                         grants_used,
-                                        grants_total
+                                        grants_total,
+                                        stack_usage
                                     );
                                 });
                         } else if clean_str.starts_with("status") {
@@ -290,8 +376,61 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                                 "Timeslice expirations: {}",
                                 info.timeslice_expirations(&self.capability)
                             );
+                            debug!(
+                                "Deadline misses: {}",
+                                info.deadline_misses(&self.capability)
+                            );
+                            debug!(
+                                "Stack overflows: {}",
+                                info.stack_overflows(&self.capability)
+                            );
+                            for state in SleepState::ALL.iter() {
+                                let residency = info.sleep_residency(*state, &self.capability);
+                                debug!(
+                                    "Sleep {}: {} times, {} ms",
+                                    state.name(),
+                                    residency.entries,
+                                    residency.time_us / 1000
+                                );
+                            }
+                            if let Some(constraint) =
+                                self.kernel.power_manager().limiting_constraint()
+                            {
+                                debug!(
+                                    "Sleep limited to {} by {}",
+                                    constraint.deepest().name(),
+                                    constraint.name()
+                                );
+                            }
+                        } else if clean_str.starts_with("memory") {
+                            self.with_process(clean_str.split_whitespace().nth(1), |proc| {
+ self.print_memory_map(proc)
+ });
+                        } else if clean_str.starts_with("grants") {
+                            self.with_process(clean_str.split_whitespace().nth(1), |proc| {
+ self.print_grants(proc)
+ });
+                        } else if clean_str.starts_with("mpu") {
+                            self.with_process(clean_str.split_whitespace().nth(1), |proc| {
+                                debug!("MPU configuration of process {}:", proc.get_process_name());
+                                let mut writer = DebugLineWriter::new();
+                                proc.print_mpu_config(&mut writer);
+                                writer.flush();
+                            });
+                        } else if clean_str.starts_with("callbacks") {
+                            self.with_process(clean_str.split_whitespace().nth(1), |proc| {
+ self.print_callbacks(proc)
+ });
+                        } else if clean_str.starts_with("syscalls") {
+                            self.with_process(clean_str.split_whitespace().nth(1), |proc| {
+ self.print_syscalls(proc)
+ });
+                        } else if clean_str.starts_with("trace") {
+                            self.trace_command(clean_str);
+                        } else if clean_str.starts_with("crash") {
+                            self.crash_command(clean_str);
                         } else {
-                            debug!("Valid commands are: help status list stop start fault");
+                            debug!("Valid commands are: help status list stop start fault memory grants mpu callbacks syscalls trace crash");
                         }
                     }
                     Err(_e) => debug!("Invalid command: {:?}", command),
@@ -304,6 +443,265 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
         self.command_index.set(0);
     }
 
+    // Run `f` on the process with this name.
+    fn with_process<F: Fn(&dyn ProcessType)>(&self, name: Option<&str>, f: F) {
+        match name {
+            Some(name) => {
+                let found = Cell::new(false);
+                self.kernel
+                    .process_each_capability(&self.capability, |proc| {
+                        if proc.get_process_name() == name {
+                            found.set(true);
+                            f(proc);
+                        }
+                    });
+                if !found.get() {
+                    debug!("No process named {}", name);
+                }
+            }
+            None => debug!("Missing process name"),
+        }
+    }
+
+    fn print_memory_map(&self, proc: &dyn ProcessType) {
+        let addresses = proc.get_addresses();
+        let region = |name: &str, start: usize, end: usize| {
+            debug!(
+                "{:13}{:#010X}-{:#010X} {:6} bytes",
+                name,
+                start,
+                end,
+                end.saturating_sub(start)
+            );
+        };
+
+        debug!("Memory map of process {}:", proc.get_process_name());
+        region(" Flash", addresses.flash_start, addresses.flash_end);
+        region(
+            "  Protected",
+            addresses.flash_start,
+            addresses.flash_non_protected_start,
+        );
+        region(
+            "  App",
+            addresses.flash_non_protected_start,
+            addresses.flash_end,
+        );
+        region(" RAM", addresses.sram_start, addresses.sram_end);
+        region("  Kernel", addresses.sram_kernel_start, addresses.sram_end);
+        region(
+            "  Grants",
+            addresses.sram_grant_start,
+            addresses.sram_kernel_start,
+        );
+        region(
+            "  Unused",
+            addresses.sram_app_brk,
+            addresses.sram_grant_start,
+        );
+        match (addresses.sram_heap_start, addresses.sram_stack_top) {
+            (Some(heap_start), Some(stack_top)) => {
+                region("  Heap", heap_start, addresses.sram_app_brk);
+                region("  Data", stack_top, heap_start);
+                let stack_start = match proc.debug_stack_size() {
+                    Some(size) => cmp::max(stack_top.saturating_sub(size), addresses.sram_start),
+                    None => addresses.sram_start,
+                };
+                debug!(
+                    "  Stack      {:#010X}-{:#010X} {:6} bytes, {} used",
+                    stack_start,
+                    stack_top,
+                    stack_top - stack_start,
+                    proc.debug_stack_high_water_mark()
+                        .unwrap_or_else(|| stack_top.saturating_sub(addresses.sram_stack_bottom))
+                );
+            }
+            _ => {
+                // The process has not told the kernel where its stack and
+                // heap are.
+                region("  App RAM", addresses.sram_start, addresses.sram_app_brk);
+            }
+        }
+    }
+
+    fn print_grants(&self, proc: &dyn ProcessType) {
+        let info: KernelInfo = KernelInfo::new(self.kernel);
+        let (grants_used, grants_total) =
+            info.number_app_grant_uses(proc.appid(), &self.capability);
+
+        debug!(
+            "Grants of process {}: {}/{} allocated",
+            proc.get_process_name(),
+            grants_used,
+            grants_total
+        );
+        let mut total_size = 0;
+        for grant_num in 0..grants_total {
+            if let Some(size) = proc.get_grant_size(grant_num) {
+                debug!("  Grant {:2}: {:6} bytes", grant_num, size);
+                total_size += size;
+            }
+        }
+        debug!("  Total:    {:6} bytes", total_size);
+    }
+
+    fn print_callbacks(&self, proc: &dyn ProcessType) {
+        debug!(
+            "Callbacks subscribed by process {}:",
+            proc.get_process_name()
+        );
+        let mut count = 0;
+        while let Some((callback_id, callback_ptr)) = proc.debug_subscribed_callback(count) {
+            debug!(
+                "  driver {:#x} subscribe {} -> {:#010X}",
+                callback_id.driver_num, callback_id.subscribe_num, callback_ptr
+            );
+            count += 1;
+        }
+        if count == 0 {
+            debug!("  None");
+        } else if count == SUBSCRIBED_CALLBACKS_LEN {
+            debug!("  Only the first {} callbacks are recorded.", count);
+        }
+    }
+
+    fn print_syscalls(&self, proc: &dyn ProcessType) {
+        debug!(
+            "Last syscalls of process {} ({} in total):",
+            proc.get_process_name(),
+            proc.debug_syscall_count()
+        );
+        for index in 0..SYSCALL_HISTORY_LEN {
+            match proc.debug_syscall_history(index) {
+                Some(Syscall::YIELD) => debug!("  yield"),
+                Some(Syscall::SUBSCRIBE {
+                    driver_number,
+                    subdriver_number,
+                    callback_ptr,
+                    appdata,
+                }) => debug!(
+                    "  subscribe({:#x}, {}, @{:#x}, {:#x})",
+                    driver_number, subdriver_number, callback_ptr as usize, appdata
+                ),
+                Some(Syscall::COMMAND {
+                    driver_number,
+                    subdriver_number,
+                    arg0,
+                    arg1,
+                }) => debug!(
+                    "  command({:#x}, {}, {:#x}, {:#x})",
+                    driver_number, subdriver_number, arg0, arg1
+                ),
+                Some(Syscall::ALLOW {
+                    driver_number,
+                    subdriver_number,
+                    allow_address,
+                    allow_size,
+                }) => debug!(
+                    "  allow({:#x}, {}, @{:#x}, {})",
+                    driver_number, subdriver_number, allow_address as usize, allow_size
+                ),
+                Some(Syscall::MEMOP { operand, arg0 }) => {
+                    debug!("  memop({}, {:#x})", operand, arg0)
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn trace_command(&self, command: &str) {
+        let trace = match self.syscall_trace.map(|trace| *trace) {
+            Some(trace) => trace,
+            None => {
+                debug!("No syscall trace on this board");
+                return;
+            }
+        };
+
+        let mut arguments = command.split_whitespace().skip(1);
+        match (arguments.next(), arguments.next()) {
+            (None, _) => {
+                debug!(
+                    "Syscall trace {}, {} records",
+                    if trace.is_enabled() { "on" } else { "off" },
+                    trace.len()
+                );
+            }
+            (Some("on"), _) => trace.set_enabled(true),
+            (Some("off"), _) => trace.set_enabled(false),
+            (Some("app"), Some("all")) => trace.set_process_filter(None),
+            (Some("app"), Some(_)) => {
+                self.with_process(command.split_whitespace().nth(2), |proc| {
+                    trace.set_process_filter(Some(proc.appid()))
+                });
+            }
+            (Some("driver"), Some("all")) => trace.set_driver_filter(None),
+            (Some("driver"), Some(number)) => {
+                let parsed = match number.strip_prefix("0x") {
+                    Some(hex) => usize::from_str_radix(hex, 16),
+                    None => number.parse::<usize>(),
+                };
+                match parsed {
+                    Ok(driver_number) => trace.set_driver_filter(Some(driver_number)),
+                    Err(_) => debug!("Invalid driver number {}", number),
+                }
+            }
+            (Some("dump"), _) => {
+                debug!(
+                    "trace-start frequency={} dropped={}",
+                    trace.frequency(),
+                    trace.take_dropped_count()
+                );
+                for _ in 0..TRACE_DUMP_RECORDS {
+                    match trace.pop() {
+                        Some(record) => {
+                            let mut hex = [0; 2 * RECORD_LEN];
+                            for (i, byte) in record.to_bytes().iter().enumerate() {
+                                hex[2 * i] = HEX_DIGITS[(byte >> 4) as usize];
+                                hex[2 * i + 1] = HEX_DIGITS[(byte & 0xf) as usize];
+                            }
+                            debug!("trace: {}", str::from_utf8(&hex).unwrap_or(""));
+                        }
+                        None => break,
+                    }
+                }
+                debug!("trace-end remaining={}", trace.len());
+            }
+            _ => debug!("Usage: trace [on|off|app <name>|app all|driver <number>|driver all|dump]"),
+        }
+    }
+
+    fn crash_command(&self, command: &str) {
+        let crash_log = match self.crash_log.map(|crash_log| *crash_log) {
+            Some(crash_log) => crash_log,
+            None => {
+                debug!("No crash log on this board");
+                return;
+            }
+        };
+
+        let result = match command.split_whitespace().nth(1) {
+            None => {
+                debug!("{} crash records", crash_log.count());
+                ReturnCode::SUCCESS
+            }
+            Some("clear") => crash_log.clear(),
+            Some(index) => match index.parse::<usize>() {
+                Ok(index) => crash_log.print(index),
+                Err(_) => {
+                    debug!("Usage: crash [<index>|clear]");
+                    ReturnCode::SUCCESS
+                }
+            },
+        };
+        match result {
+            ReturnCode::SUCCESS => {}
+            ReturnCode::EINVAL => debug!("No such crash record"),
+            ReturnCode::EBUSY => debug!("Crash log busy, try again"),
+            error => debug!("Crash log error: {:?}", error),
+        }
+    }
+
     fn write_byte(&self, byte: u8) -> ReturnCode {
         if self.tx_in_progress.get() {
             ReturnCode::EBUSY
@@ -391,3 +789,67 @@ impl<'a, C: ProcessManagementCapability> uart::ReceiveClient for ProcessConsole<
         self.uart.receive_buffer(read_buf, 1);
     }
 }
+
+/// The `Stack` column of the `list` command: the most stack a process used out
+/// of its declared stack size.
+struct StackUsage(Option<(usize, Option<usize>)>);
+
+impl fmt::Display for StackUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some((used, Some(size))) => write!(f, "{:6}/{}", used, size),
+            Some((used, None)) => write!(f, "{:6}/?", used),
+            None => write!(f, "{:>6}", "?"),
+        }
+    }
+}
+
+/// Passes formatted text on to `debug!()` one line at a time, as `debug!()`
+/// ends every message with a newline.
+struct DebugLineWriter {
+    line: [u8; 80],
+    len: usize,
+}
+
+impl DebugLineWriter {
+    fn new() -> DebugLineWriter {
+        DebugLineWriter {
+            line: [0; 80],
+            len: 0,
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.len > 0 {
+            debug!("{}", str::from_utf8(&self.line[..self.len]).unwrap_or(""));
+            self.len = 0;
+        }
+    }
+}
+
+impl fmt::Write for DebugLineWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '\r' => {}
+                '\n' => {
+                    // Keep empty lines, which separate parts of the output.
+                    if self.len == 0 {
+                        debug!("");
+                    }
+                    self.flush();
+                }
+                c => {
+                    let mut encoded = [0; 4];
+                    let encoded = c.encode_utf8(&mut encoded).as_bytes();
+                    if self.len + encoded.len() > self.line.len() {
+                        self.flush();
+                    }
+                    self.line[self.len..self.len + encoded.len()].copy_from_slice(encoded);
+                    self.len += encoded.len();
+                }
+            }
+        }
+        Ok(())
+    }
+}
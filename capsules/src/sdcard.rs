@@ -1,6 +1,8 @@
 //! Provides driver for accessing an SD Card and a userspace Driver.
 //!
 //! This allows initialization and block reads or writes on top of SPI.
+//! `SDCardBlockStorage` provides the blocks of the card to other capsules
+//! through the `BlockStorage` HIL.
 //!
 //! Usage
 //! -----
@@ -1575,3 +1577,190 @@ impl<'a, A: hil::time::Alarm<'a>> Driver for SDCardDriver<'a, A> {
         }
     }
 }
+
+/// Block size of SD cards accessed over SPI.
+const SD_BLOCK_SIZE: usize = 512;
+
+/// Operations of `SDCardBlockStorage`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BlockOperation {
+    Idle,
+    Read { block: u32, count: u32 },
+    Write { block: u32 },
+}
+
+/// Block storage interface for SD cards, for capsules such as filesystems
+/// that build off of the SDCard. The card is initialized by the first
+/// operation, and again after it was removed or replaced.
+pub struct SDCardBlockStorage<'a, A: hil::time::Alarm<'a>> {
+    sdcard: &'a SDCard<'a, A>,
+    client: OptionalCell<&'a dyn hil::block_storage::BlockStorageClient>,
+    operation: Cell<BlockOperation>,
+    /// The buffer of an operation that waits for initialization to finish.
+    buffer: TakeCell<'static, [u8]>,
+    num_blocks: Cell<u32>,
+}
+
+impl<'a, A: hil::time::Alarm<'a>> SDCardBlockStorage<'a, A> {
+    pub fn new(sdcard: &'a SDCard<'a, A>) -> SDCardBlockStorage<'a, A> {
+        SDCardBlockStorage {
+            sdcard,
+            client: OptionalCell::empty(),
+            operation: Cell::new(BlockOperation::Idle),
+            buffer: TakeCell::empty(),
+            num_blocks: Cell::new(0),
+        }
+    }
+
+    fn start(
+        &self,
+        operation: BlockOperation,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        if self.operation.get() != BlockOperation::Idle {
+            return Err((ReturnCode::EBUSY, buffer));
+        }
+        if !self.sdcard.is_installed() {
+            return Err((ReturnCode::EUNINSTALLED, buffer));
+        }
+
+        if self.sdcard.is_initialized() {
+            self.operation.set(operation);
+            self.issue(operation, buffer);
+            Ok(())
+        } else {
+            match self.sdcard.initialize() {
+                ReturnCode::SUCCESS => {
+                    self.operation.set(operation);
+                    self.buffer.replace(buffer);
+                    Ok(())
+                }
+                rcode => Err((rcode, buffer)),
+            }
+        }
+    }
+
+    /// Issues `operation` to the initialized card. The card is idle, so this
+    /// cannot fail.
+    fn issue(&self, operation: BlockOperation, buffer: &'static mut [u8]) {
+        match operation {
+            BlockOperation::Read { block, count } => {
+                self.sdcard.read_blocks(buffer, block, count);
+            }
+            BlockOperation::Write { block } => {
+                self.sdcard.write_blocks(buffer, block, 1);
+            }
+            BlockOperation::Idle => {}
+        }
+    }
+
+    /// Finishes the current operation, returning the buffer to the client.
+    fn complete(&self, result: ReturnCode, buffer: &'static mut [u8]) {
+        let operation = self.operation.replace(BlockOperation::Idle);
+        self.client.map(move |client| match operation {
+            BlockOperation::Read { .. } => client.read_done(result, buffer),
+            BlockOperation::Write { .. } => client.write_done(result, buffer),
+            BlockOperation::Idle => {}
+        });
+    }
+
+    fn check_range(&self, buffer: &[u8], block: u32, count: u32) -> bool {
+        let num_blocks = self.num_blocks.get();
+        buffer.len() >= count as usize * SD_BLOCK_SIZE
+            && (num_blocks == 0 || block.saturating_add(count) <= num_blocks)
+    }
+}
+
+impl<'a, A: hil::time::Alarm<'a>> hil::block_storage::BlockStorage<'a>
+    for SDCardBlockStorage<'a, A>
+{
+    fn set_client(&self, client: &'a dyn hil::block_storage::BlockStorageClient) {
+        self.client.set(client);
+    }
+
+    fn block_size(&self) -> usize {
+        SD_BLOCK_SIZE
+    }
+
+    fn num_blocks(&self) -> u32 {
+        self.num_blocks.get()
+    }
+
+    fn read_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        if count == 0 || !self.check_range(buffer, block, count) {
+            return Err((ReturnCode::EINVAL, buffer));
+        }
+        self.start(BlockOperation::Read { block, count }, buffer)
+    }
+
+    fn write_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        if count != 1 {
+            // the SD card can't write multiple blocks yet
+            return Err((ReturnCode::ENOSUPPORT, buffer));
+        }
+        if !self.check_range(buffer, block, count) {
+            return Err((ReturnCode::EINVAL, buffer));
+        }
+        self.start(BlockOperation::Write { block }, buffer)
+    }
+}
+
+/// Handle callbacks from SDCard
+impl<'a, A: hil::time::Alarm<'a>> SDCardClient for SDCardBlockStorage<'a, A> {
+    fn card_detection_changed(&self, _installed: bool) {
+        // the next operation initializes the new card, if there is one
+        self.num_blocks.set(0);
+    }
+
+    fn init_done(&self, block_size: u32, total_size: u64) {
+        let num_blocks = total_size / cmp::max(block_size, 1) as u64;
+        self.num_blocks
+            .set(cmp::min(num_blocks, u32::MAX as u64) as u32);
+
+        // now that the size is known, the blocks can be checked
+        if let Some(buffer) = self.buffer.take() {
+            let operation = self.operation.get();
+            let in_range = match operation {
+                BlockOperation::Read { block, count } => self.check_range(buffer, block, count),
+                BlockOperation::Write { block } => self.check_range(buffer, block, 1),
+                BlockOperation::Idle => true,
+            };
+            if in_range {
+                self.issue(operation, buffer);
+            } else {
+                self.complete(ReturnCode::EINVAL, buffer);
+            }
+        }
+    }
+
+    fn read_done(&self, data: &'static mut [u8], _len: usize) {
+        self.complete(ReturnCode::SUCCESS, data);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8]) {
+        self.complete(ReturnCode::SUCCESS, buffer);
+    }
+
+    fn error(&self, _error: u32) {
+        // the buffer is either still waiting for initialization, or was kept
+        // by the SDCard for the failed transaction
+        let buffer = self
+            .buffer
+            .take()
+            .or_else(|| self.sdcard.client_buffer.take());
+        match buffer {
+            Some(buffer) => self.complete(ReturnCode::FAIL, buffer),
+            None => self.operation.set(BlockOperation::Idle),
+        }
+    }
+}
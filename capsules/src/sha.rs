@@ -0,0 +1,883 @@
+//! Software implementations of the SHA-1 and SHA-2 hash functions and of
+//! HMAC.
+//!
+//! These are synchronous building blocks: `Sha` hashes data incrementally
+//! with any of the supported algorithms, and `Hmac` computes a keyed MAC on
+//! top of it as described in RFC 2104. `ShaSoftware` in `sha_software.rs`
+//! wraps them in the asynchronous `hil::digest` interface, and
+//! `app_checker_sha256.rs` uses them directly.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mut sha = Sha::new(Algorithm::Sha256);
+//! sha.update(b"abc");
+//! let mut digest = [0; 32];
+//! sha.finish(&mut digest);
+//! ```
+
+use core::convert::TryInto;
+
+/// The hash functions implemented by `Sha`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Algorithm {
+    Sha1,
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Algorithm {
+    /// Length of the digest in bytes.
+    pub fn output_len(self) -> usize {
+        match self {
+            Algorithm::Sha1 => 20,
+            Algorithm::Sha224 => 28,
+            Algorithm::Sha256 => 32,
+            Algorithm::Sha384 => 48,
+            Algorithm::Sha512 => 64,
+        }
+    }
+
+    /// Length of the blocks the input is processed in, in bytes.
+    pub fn block_len(self) -> usize {
+        match self {
+            Algorithm::Sha1 | Algorithm::Sha224 | Algorithm::Sha256 => 64,
+            Algorithm::Sha384 | Algorithm::Sha512 => 128,
+        }
+    }
+
+    /// Returns the algorithm whose digest is `len` bytes long, if any.
+    pub fn from_output_len(len: usize) -> Option<Algorithm> {
+        match len {
+            20 => Some(Algorithm::Sha1),
+            28 => Some(Algorithm::Sha224),
+            32 => Some(Algorithm::Sha256),
+            48 => Some(Algorithm::Sha384),
+            64 => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+}
+
+/// Largest block length of all algorithms.
+pub const MAX_BLOCK_LEN: usize = 128;
+
+/// Largest digest length of all algorithms.
+pub const MAX_OUTPUT_LEN: usize = 64;
+
+const SHA1_H0: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+const SHA224_H0: [u32; 8] = [
+    0xc1059ed8, 0x367cd507, 0x3070dd17, 0xf70e5939, 0xffc00b31, 0x68581511, 0x64f98fa7, 0xbefa4fa4,
+];
+
+const SHA256_H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const SHA384_H0: [u64; 8] = [
+    0xcbbb9d5dc1059ed8,
+    0x629a292a367cd507,
+    0x9159015a3070dd17,
+    0x152fecd8f70e5939,
+    0x67332667ffc00b31,
+    0x8eb44a8768581511,
+    0xdb0c2e0d64f98fa7,
+    0x47b5481dbefa4fa4,
+];
+
+const SHA512_H0: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const SHA512_K: [u64; 80] = [
+    0x428a2f98d728ae22,
+    0x7137449123ef65cd,
+    0xb5c0fbcfec4d3b2f,
+    0xe9b5dba58189dbbc,
+    0x3956c25bf348b538,
+    0x59f111f1b605d019,
+    0x923f82a4af194f9b,
+    0xab1c5ed5da6d8118,
+    0xd807aa98a3030242,
+    0x12835b0145706fbe,
+    0x243185be4ee4b28c,
+    0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f,
+    0x80deb1fe3b1696b1,
+    0x9bdc06a725c71235,
+    0xc19bf174cf692694,
+    0xe49b69c19ef14ad2,
+    0xefbe4786384f25e3,
+    0x0fc19dc68b8cd5b5,
+    0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275,
+    0x4a7484aa6ea6e483,
+    0x5cb0a9dcbd41fbd4,
+    0x76f988da831153b5,
+    0x983e5152ee66dfab,
+    0xa831c66d2db43210,
+    0xb00327c898fb213f,
+    0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2,
+    0xd5a79147930aa725,
+    0x06ca6351e003826f,
+    0x142929670a0e6e70,
+    0x27b70a8546d22ffc,
+    0x2e1b21385c26c926,
+    0x4d2c6dfc5ac42aed,
+    0x53380d139d95b3df,
+    0x650a73548baf63de,
+    0x766a0abb3c77b2a8,
+    0x81c2c92e47edaee6,
+    0x92722c851482353b,
+    0xa2bfe8a14cf10364,
+    0xa81a664bbc423001,
+    0xc24b8b70d0f89791,
+    0xc76c51a30654be30,
+    0xd192e819d6ef5218,
+    0xd69906245565a910,
+    0xf40e35855771202a,
+    0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8,
+    0x1e376c085141ab53,
+    0x2748774cdf8eeb99,
+    0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63,
+    0x4ed8aa4ae3418acb,
+    0x5b9cca4f7763e373,
+    0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc,
+    0x78a5636f43172f60,
+    0x84c87814a1f0ab72,
+    0x8cc702081a6439ec,
+    0x90befffa23631e28,
+    0xa4506cebde82bde9,
+    0xbef9a3f7b2c67915,
+    0xc67178f2e372532b,
+    0xca273eceea26619c,
+    0xd186b8c721c0c207,
+    0xeada7dd6cde0eb1e,
+    0xf57d4f7fee6ed178,
+    0x06f067aa72176fba,
+    0x0a637dc5a2c898a6,
+    0x113f9804bef90dae,
+    0x1b710b35131c471b,
+    0x28db77f523047d84,
+    0x32caab7b40c72493,
+    0x3c9ebe0a15c9bebc,
+    0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6,
+    0x597f299cfc657e2a,
+    0x5fcb6fab3ad6faec,
+    0x6c44198c4a475817,
+];
+
+/// The chaining value of a hash computation, whose word size depends on
+/// the algorithm.
+#[derive(Copy, Clone)]
+enum State {
+    Sha1([u32; 5]),
+    Sha256([u32; 8]),
+    Sha512([u64; 8]),
+}
+
+/// Process one 64 byte SHA-1 block.
+fn compress_sha1(state: &mut [u32; 5], block: &[u8]) {
+    let mut w = [0u32; 80];
+    for (i, word) in block.chunks_exact(4).enumerate() {
+        w[i] = u32::from_be_bytes(word.try_into().unwrap_or([0; 4]));
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+
+    let mut v = *state;
+    for (i, wi) in w.iter().enumerate() {
+        let (func, constant) = match i {
+            0..=19 => ((v[1] & v[2]) | (!v[1] & v[3]), 0x5a827999),
+            20..=39 => (v[1] ^ v[2] ^ v[3], 0x6ed9eba1),
+            40..=59 => ((v[1] & v[2]) | (v[1] & v[3]) | (v[2] & v[3]), 0x8f1bbcdc),
+            _ => (v[1] ^ v[2] ^ v[3], 0xca62c1d6),
+        };
+        let temp = v[0]
+            .rotate_left(5)
+            .wrapping_add(func)
+            .wrapping_add(v[4])
+            .wrapping_add(constant)
+            .wrapping_add(*wi);
+        v = [temp, v[0], v[1].rotate_left(30), v[2], v[3]];
+    }
+
+    for (s, x) in state.iter_mut().zip(v.iter()) {
+        *s = s.wrapping_add(*x);
+    }
+}
+
+/// Process one 64 byte SHA-224 or SHA-256 block.
+fn compress_sha256(state: &mut [u32; 8], block: &[u8]) {
+    let mut w = [0u32; 64];
+    for (i, word) in block.chunks_exact(4).enumerate() {
+        w[i] = u32::from_be_bytes(word.try_into().unwrap_or([0; 4]));
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let mut v = *state;
+    for i in 0..64 {
+        let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+        let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+        let t1 = v[7]
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(SHA256_K[i])
+            .wrapping_add(w[i]);
+        let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+        let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+        let t2 = s0.wrapping_add(maj);
+        v = [
+            t1.wrapping_add(t2),
+            v[0],
+            v[1],
+            v[2],
+            v[3].wrapping_add(t1),
+            v[4],
+            v[5],
+            v[6],
+        ];
+    }
+
+    for (s, x) in state.iter_mut().zip(v.iter()) {
+        *s = s.wrapping_add(*x);
+    }
+}
+
+/// Process one 128 byte SHA-384 or SHA-512 block.
+fn compress_sha512(state: &mut [u64; 8], block: &[u8]) {
+    let mut w = [0u64; 80];
+    for (i, word) in block.chunks_exact(8).enumerate() {
+        w[i] = u64::from_be_bytes(word.try_into().unwrap_or([0; 8]));
+    }
+    for i in 16..80 {
+        let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+        let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let mut v = *state;
+    for i in 0..80 {
+        let s1 = v[4].rotate_right(14) ^ v[4].rotate_right(18) ^ v[4].rotate_right(41);
+        let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+        let t1 = v[7]
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(SHA512_K[i])
+            .wrapping_add(w[i]);
+        let s0 = v[0].rotate_right(28) ^ v[0].rotate_right(34) ^ v[0].rotate_right(39);
+        let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+        let t2 = s0.wrapping_add(maj);
+        v = [
+            t1.wrapping_add(t2),
+            v[0],
+            v[1],
+            v[2],
+            v[3].wrapping_add(t1),
+            v[4],
+            v[5],
+            v[6],
+        ];
+    }
+
+    for (s, x) in state.iter_mut().zip(v.iter()) {
+        *s = s.wrapping_add(*x);
+    }
+}
+
+/// An incremental hash computation.
+#[derive(Copy, Clone)]
+pub struct Sha {
+    algorithm: Algorithm,
+    state: State,
+    /// Input that does not fill a whole block yet.
+    block: [u8; MAX_BLOCK_LEN],
+    block_used: usize,
+    /// Total length of the input in bytes.
+    length: u64,
+}
+
+impl Sha {
+    pub fn new(algorithm: Algorithm) -> Sha {
+        let state = match algorithm {
+            Algorithm::Sha1 => State::Sha1(SHA1_H0),
+            Algorithm::Sha224 => State::Sha256(SHA224_H0),
+            Algorithm::Sha256 => State::Sha256(SHA256_H0),
+            Algorithm::Sha384 => State::Sha512(SHA384_H0),
+            Algorithm::Sha512 => State::Sha512(SHA512_H0),
+        };
+        Sha {
+            algorithm,
+            state,
+            block: [0; MAX_BLOCK_LEN],
+            block_used: 0,
+            length: 0,
+        }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        match self.state {
+            State::Sha1(ref mut state) => compress_sha1(state, block),
+            State::Sha256(ref mut state) => compress_sha256(state, block),
+            State::Sha512(ref mut state) => compress_sha512(state, block),
+        }
+    }
+
+    /// Add `data` to the input.
+    pub fn update(&mut self, mut data: &[u8]) {
+        let block_len = self.algorithm.block_len();
+        self.length = self.length.wrapping_add(data.len() as u64);
+
+        // Complete a partially filled block first
+        if self.block_used > 0 {
+            let n = core::cmp::min(block_len - self.block_used, data.len());
+            self.block[self.block_used..self.block_used + n].copy_from_slice(&data[..n]);
+            self.block_used += n;
+            data = &data[n..];
+            if self.block_used < block_len {
+                return;
+            }
+            let block = self.block;
+            self.compress(&block[..block_len]);
+            self.block_used = 0;
+        }
+
+        let mut blocks = data.chunks_exact(block_len);
+        for block in &mut blocks {
+            self.compress(block);
+        }
+        let remainder = blocks.remainder();
+        self.block[..remainder.len()].copy_from_slice(remainder);
+        self.block_used = remainder.len();
+    }
+
+    /// Pad the input, and write the digest to the start of `out`, which must
+    /// be at least `output_len()` bytes long. The computation must be
+    /// restarted with `new()` afterwards.
+    pub fn finish(&mut self, out: &mut [u8]) {
+        let block_len = self.algorithm.block_len();
+        // The length field is 8 bytes for SHA-1 and SHA-256, and 16 bytes
+        // for SHA-512. Inputs are never long enough to need the upper half.
+        let length_len = block_len / 8;
+        let bit_len = self.length.wrapping_mul(8);
+
+        // Pad with a single 1 bit, zeros, and the message length in bits.
+        // This takes one or two more blocks.
+        let mut tail = [0u8; 2 * MAX_BLOCK_LEN];
+        tail[..self.block_used].copy_from_slice(&self.block[..self.block_used]);
+        tail[self.block_used] = 0x80;
+        let tail_len = if self.block_used < block_len - length_len {
+            block_len
+        } else {
+            2 * block_len
+        };
+        tail[tail_len - 8..tail_len].copy_from_slice(&bit_len.to_be_bytes());
+        for block in tail[..tail_len].chunks_exact(block_len) {
+            self.compress(block);
+        }
+        self.block_used = 0;
+
+        let out = &mut out[..self.algorithm.output_len()];
+        match self.state {
+            State::Sha1(ref state) => {
+                for (chunk, s) in out.chunks_mut(4).zip(state.iter()) {
+                    chunk.copy_from_slice(&s.to_be_bytes()[..chunk.len()]);
+                }
+            }
+            State::Sha256(ref state) => {
+                for (chunk, s) in out.chunks_mut(4).zip(state.iter()) {
+                    chunk.copy_from_slice(&s.to_be_bytes()[..chunk.len()]);
+                }
+            }
+            State::Sha512(ref state) => {
+                for (chunk, s) in out.chunks_mut(8).zip(state.iter()) {
+                    chunk.copy_from_slice(&s.to_be_bytes()[..chunk.len()]);
+                }
+            }
+        }
+    }
+}
+
+/// An incremental HMAC computation, as described in RFC 2104.
+///
+/// The key is only needed to set up the inner and outer hash computations,
+/// and is not kept.
+#[derive(Copy, Clone)]
+pub struct Hmac {
+    inner: Sha,
+    outer: Sha,
+}
+
+impl Hmac {
+    pub fn new(algorithm: Algorithm, key: &[u8]) -> Hmac {
+        let block_len = algorithm.block_len();
+
+        // Keys longer than a block are replaced by their hash, and all keys
+        // are padded with zeros to the block length.
+        let mut padded_key = [0u8; MAX_BLOCK_LEN];
+        if key.len() > block_len {
+            let mut sha = Sha::new(algorithm);
+            sha.update(key);
+            sha.finish(&mut padded_key);
+        } else {
+            padded_key[..key.len()].copy_from_slice(key);
+        }
+
+        let mut inner = Sha::new(algorithm);
+        let mut outer = Sha::new(algorithm);
+        let mut pad = [0u8; MAX_BLOCK_LEN];
+        for (p, k) in pad.iter_mut().zip(padded_key.iter()) {
+            *p = k ^ 0x36;
+        }
+        inner.update(&pad[..block_len]);
+        for (p, k) in pad.iter_mut().zip(padded_key.iter()) {
+            *p = k ^ 0x5c;
+        }
+        outer.update(&pad[..block_len]);
+
+        Hmac { inner, outer }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.inner.algorithm()
+    }
+
+    /// Add `data` to the authenticated input.
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    /// Write the MAC to the start of `out`, which must be at least
+    /// `output_len()` bytes long.
+    pub fn finish(&mut self, out: &mut [u8]) {
+        let output_len = self.algorithm().output_len();
+        let mut inner_digest = [0u8; MAX_OUTPUT_LEN];
+        self.inner.finish(&mut inner_digest);
+        self.outer.update(&inner_digest[..output_len]);
+        self.outer.finish(out);
+    }
+}
+
+/// Compute the SHA-256 hash of `data`.
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut sha = Sha::new(Algorithm::Sha256);
+    sha.update(data);
+    let mut out = [0u8; 32];
+    sha.finish(&mut out);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use std::vec;
+    use std::vec::Vec;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn digest(algorithm: Algorithm, data: &[u8]) -> Vec<u8> {
+        let mut sha = Sha::new(algorithm);
+        sha.update(data);
+        let mut out = [0; MAX_OUTPUT_LEN];
+        sha.finish(&mut out);
+        out[..algorithm.output_len()].to_vec()
+    }
+
+    fn mac(algorithm: Algorithm, key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut hmac = Hmac::new(algorithm, key);
+        hmac.update(data);
+        let mut out = [0; MAX_OUTPUT_LEN];
+        hmac.finish(&mut out);
+        out[..algorithm.output_len()].to_vec()
+    }
+
+    const MSG_448: &[u8] = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+    const MSG_896: &[u8] = b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmn\
+        hijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu";
+
+    /// The examples of FIPS 180-4, and the empty message.
+    #[test]
+    fn fips_180_4() {
+        let known: &[(Algorithm, &[u8], &str)] = &[
+            (
+                Algorithm::Sha1,
+                b"",
+                "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+            ),
+            (
+                Algorithm::Sha1,
+                b"abc",
+                "a9993e364706816aba3e25717850c26c9cd0d89d",
+            ),
+            (
+                Algorithm::Sha1,
+                MSG_448,
+                "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+            ),
+            (
+                Algorithm::Sha224,
+                b"",
+                "d14a028c2a3a2bc9476102bb288234c415a2b01f828ea62ac5b3e42f",
+            ),
+            (
+                Algorithm::Sha224,
+                b"abc",
+                "23097d223405d8228642a477bda255b32aadbce4bda0b3f7e36c9da7",
+            ),
+            (
+                Algorithm::Sha224,
+                MSG_448,
+                "75388b16512776cc5dba5da1fd890150b0c6455cb4f58b1952522525",
+            ),
+            (
+                Algorithm::Sha256,
+                b"",
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                Algorithm::Sha256,
+                b"abc",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                Algorithm::Sha256,
+                MSG_448,
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+            (
+                Algorithm::Sha384,
+                b"",
+                "38b060a751ac96384cd9327eb1b1e36a21fdb71114be07434c0cc7bf63f6e1da274edebfe76f65fbd51ad2f14898b95b",
+            ),
+            (
+                Algorithm::Sha384,
+                b"abc",
+                "cb00753f45a35e8bb5a03d699ac65007272c32ab0eded1631a8b605a43ff5bed8086072ba1e7cc2358baeca134c825a7",
+            ),
+            (
+                Algorithm::Sha384,
+                MSG_896,
+                "09330c33f71147e83d192fc782cd1b4753111b173b3b05d22fa08086e3b0f712fcc7c71a557e2db966c3e9fa91746039",
+            ),
+            (
+                Algorithm::Sha512,
+                b"",
+                "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e",
+            ),
+            (
+                Algorithm::Sha512,
+                b"abc",
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+            ),
+            (
+                Algorithm::Sha512,
+                MSG_896,
+                "8e959b75dae313da8cf4f72814fc143f8f7779c6eb9f7fa17299aeadb6889018501d289e4900f7e4331b99dec4b5433ac7d329eeb6dd26545e96e55b874be909",
+            ),
+        ];
+        for &(algorithm, data, expected) in known.iter() {
+            assert_eq!(digest(algorithm, data), hex(expected), "{:?}", algorithm);
+        }
+    }
+
+    /// Inputs around the lengths where the padding needs a second block: 55
+    /// and 56 bytes for 64 byte blocks, and 111 and 112 bytes for 128 byte
+    /// blocks.
+    #[test]
+    fn padding_boundaries() {
+        let known: &[(Algorithm, usize, &str)] = &[
+            (
+                Algorithm::Sha1,
+                55,
+                "c1c8bbdc22796e28c0e15163d20899b65621d65a",
+            ),
+            (
+                Algorithm::Sha1,
+                56,
+                "c2db330f6083854c99d4b5bfb6e8f29f201be699",
+            ),
+            (
+                Algorithm::Sha1,
+                63,
+                "03f09f5b158a7a8cdad920bddc29b81c18a551f5",
+            ),
+            (
+                Algorithm::Sha1,
+                64,
+                "0098ba824b5c16427bd7a1122a5a442a25ec644d",
+            ),
+            (
+                Algorithm::Sha1,
+                65,
+                "11655326c708d70319be2610e8a57d9a5b959d3b",
+            ),
+            (
+                Algorithm::Sha224,
+                55,
+                "fb0bd626a70c28541dfa781bb5cc4d7d7f56622a58f01a0b1ddd646f",
+            ),
+            (
+                Algorithm::Sha224,
+                56,
+                "d40854fc9caf172067136f2e29e1380b14626bf6f0dd06779f820dcd",
+            ),
+            (
+                Algorithm::Sha224,
+                63,
+                "1d4e051f4d6fed2a63fd2421e65834cec00d64456553de3496ae8b1d",
+            ),
+            (
+                Algorithm::Sha224,
+                64,
+                "a88cd5cde6d6fe9136a4e58b49167461ea95d388ca2bdb7afdc3cbf4",
+            ),
+            (
+                Algorithm::Sha224,
+                65,
+                "ff8716f600af42959d0efb52e1f21b01bb328733009344d511c299fb",
+            ),
+            (
+                Algorithm::Sha256,
+                55,
+                "9f4390f8d30c2dd92ec9f095b65e2b9ae9b0a925a5258e241c9f1e910f734318",
+            ),
+            (
+                Algorithm::Sha256,
+                56,
+                "b35439a4ac6f0948b6d6f9e3c6af0f5f590ce20f1bde7090ef7970686ec6738a",
+            ),
+            (
+                Algorithm::Sha256,
+                63,
+                "7d3e74a05d7db15bce4ad9ec0658ea98e3f06eeecf16b4c6fff2da457ddc2f34",
+            ),
+            (
+                Algorithm::Sha256,
+                64,
+                "ffe054fe7ae0cb6dc65c3af9b61d5209f439851db43d0ba5997337df154668eb",
+            ),
+            (
+                Algorithm::Sha256,
+                65,
+                "635361c48bb9eab14198e76ea8ab7f1a41685d6ad62aa9146d301d4f17eb0ae0",
+            ),
+            (
+                Algorithm::Sha384,
+                111,
+                "3c37955051cb5c3026f94d551d5b5e2ac38d572ae4e07172085fed81f8466b8f90dc23a8ffcdea0b8d8e58e8fdacc80a",
+            ),
+            (
+                Algorithm::Sha384,
+                112,
+                "187d4e07cb306103c69967bf544d0dfbe9042577599c73c330abc0cb64c61236d5ed565ee19119d8c31779a38f791fcd",
+            ),
+            (
+                Algorithm::Sha384,
+                127,
+                "9bd06b1763c2cf7aef40e795dc65bc96d59c41b537f3ad72ebdefd485476b5717c1aeb37c327fe9c1831b12b9efd08ae",
+            ),
+            (
+                Algorithm::Sha384,
+                128,
+                "edb12730a366098b3b2beac75a3bef1b0969b15c48e2163c23d96994f8d1bef760c7e27f3c464d3829f56c0d53808b0b",
+            ),
+            (
+                Algorithm::Sha384,
+                129,
+                "39b6f5a7b0e781dbc419f72e49b30eaac10f2c98c4403bc610da31067fd1b48f324138c8615d2b496d08d73d5e865326",
+            ),
+            (
+                Algorithm::Sha512,
+                111,
+                "fa9121c7b32b9e01733d034cfc78cbf67f926c7ed83e82200ef86818196921760b4beff48404df811b953828274461673c68d04e297b0eb7b2b4d60fc6b566a2",
+            ),
+            (
+                Algorithm::Sha512,
+                112,
+                "c01d080efd492776a1c43bd23dd99d0a2e626d481e16782e75d54c2503b5dc32bd05f0f1ba33e568b88fd2d970929b719ecbb152f58f130a407c8830604b70ca",
+            ),
+            (
+                Algorithm::Sha512,
+                127,
+                "828613968b501dc00a97e08c73b118aa8876c26b8aac93df128502ab360f91bab50a51e088769a5c1eff4782ace147dce3642554199876374291f5d921629502",
+            ),
+            (
+                Algorithm::Sha512,
+                128,
+                "b73d1929aa615934e61a871596b3f3b33359f42b8175602e89f7e06e5f658a243667807ed300314b95cacdd579f3e33abdfbe351909519a846d465c59582f321",
+            ),
+            (
+                Algorithm::Sha512,
+                129,
+                "4f681e0bd53cda4b5a2041cc8a06f2eabde44fb16c951fbd5b87702f07aeab611565b19c47fde30587177ebb852e3971bbd8d3fd30da18d71037dfbd98420429",
+            ),
+        ];
+        for &(algorithm, len, expected) in known.iter() {
+            let data = vec![b'a'; len];
+            assert_eq!(
+                digest(algorithm, &data),
+                hex(expected),
+                "{:?} {}",
+                algorithm,
+                len
+            );
+        }
+    }
+
+    /// Splitting the input across calls to `update()` gives the same digest.
+    #[test]
+    fn incremental_update() {
+        let data: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        for &algorithm in [
+            Algorithm::Sha1,
+            Algorithm::Sha224,
+            Algorithm::Sha256,
+            Algorithm::Sha384,
+            Algorithm::Sha512,
+        ]
+        .iter()
+        {
+            let expected = digest(algorithm, &data);
+            for split in [1, 55, 56, 64, 111, 128, 129, 299].iter() {
+                let mut sha = Sha::new(algorithm);
+                sha.update(&data[..*split]);
+                sha.update(&data[*split..]);
+                let mut out = [0; MAX_OUTPUT_LEN];
+                sha.finish(&mut out);
+                assert_eq!(&out[..algorithm.output_len()], &expected[..]);
+            }
+        }
+    }
+
+    /// Test cases 1, 2, 3, 4, 6 and 7 of RFC 4231. Test case 5 truncates
+    /// the output, which `Hmac` does not do.
+    #[test]
+    fn rfc_4231() {
+        const CASE_7_DATA: &[u8] =
+            b"This is a test using a larger than block-size key and a larger \
+            than block-size data. The key needs to be hashed before being used by the HMAC \
+            algorithm.";
+        let cases: [(Vec<u8>, Vec<u8>, [&str; 4]); 6] = [
+            (
+                [0x0b; 20].to_vec(),
+                b"Hi There".to_vec(),
+                [
+                    "896fb1128abbdf196832107cd49df33f47b4b1169912ba4f53684b22",
+                    "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+                    "afd03944d84895626b0825f4ab46907f15f9dadbe4101ec682aa034c7cebc59cfaea9ea9076ede7f4af152e8b2fa9cb6",
+                    "87aa7cdea5ef619d4ff0b4241a1d6cb02379f4e2ce4ec2787ad0b30545e17cdedaa833b7d6b8a702038b274eaea3f4e4be9d914eeb61f1702e696c203a126854",
+                ],
+            ),
+            (
+                b"Jefe".to_vec(),
+                b"what do ya want for nothing?".to_vec(),
+                [
+                    "a30e01098bc6dbbf45690f3a7e9e6d0f8bbea2a39e6148008fd05e44",
+                    "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+                    "af45d2e376484031617f78d2b58a6b1b9c7ef464f5a01b47e42ec3736322445e8e2240ca5e69e2c78b3239ecfab21649",
+                    "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737",
+                ],
+            ),
+            (
+                [0xaa; 20].to_vec(),
+                [0xdd; 50].to_vec(),
+                [
+                    "7fb3cb3588c6c1f6ffa9694d7d6ad2649365b0c1f65d69d1ec8333ea",
+                    "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+                    "88062608d3e6ad8a0aa2ace014c8a86f0aa635d947ac9febe83ef4e55966144b2a5ab39dc13814b94e3ab6e101a34f27",
+                    "fa73b0089d56a284efb0f0756c890be9b1b5dbdd8ee81a3655f83e33b2279d39bf3e848279a722c806b485a47e67c807b946a337bee8942674278859e13292fb",
+                ],
+            ),
+            (
+                hex("0102030405060708090a0b0c0d0e0f10111213141516171819"),
+                [0xcd; 50].to_vec(),
+                [
+                    "6c11506874013cac6a2abc1bb382627cec6a90d86efc012de7afec5a",
+                    "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+                    "3e8a69b7783c25851933ab6290af6ca77a9981480850009cc5577c6e1f573b4e6801dd23c4a7d679ccf8a386c674cffb",
+                    "b0ba465637458c6990e5a8c5f61d4af7e576d97ff94b872de76f8050361ee3dba91ca5c11aa25eb4d679275cc5788063a5f19741120c4f2de2adebeb10a298dd",
+                ],
+            ),
+            (
+                [0xaa; 131].to_vec(),
+                b"Test Using Larger Than Block-Size Key - Hash Key First".to_vec(),
+                [
+                    "95e9a0db962095adaebe9b2d6f0dbce2d499f112f2d2b7273fa6870e",
+                    "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+                    "4ece084485813e9088d2c63a041bc5b44f9ef1012a2b588f3cd11f05033ac4c60c2ef6ab4030fe8296248df163f44952",
+                    "80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f3526b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598",
+                ],
+            ),
+            (
+                [0xaa; 131].to_vec(),
+                CASE_7_DATA.to_vec(),
+                [
+                    "3a854166ac5d9f023f54d517d0b39dbd946770db9c2b95c9f6f565d1",
+                    "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+                    "6617178e941f020d351e2f254e8fd32c602420feb0b8fb9adccebb82461e99c5a678cc31e799176d3860e6110c46523e",
+                    "e37b6a775dc87dbaa4dfa9f96e5e3ffddebd71f8867289865df5a32d20cdc944b6022cac3c4982b10d5eeb55c3e4de15134676fb6de0446065c97440fa8c6a58",
+                ],
+            ),
+        ];
+        let algorithms = [
+            Algorithm::Sha224,
+            Algorithm::Sha256,
+            Algorithm::Sha384,
+            Algorithm::Sha512,
+        ];
+        for (key, data, expected) in cases.iter() {
+            for (algorithm, expected) in algorithms.iter().zip(expected.iter()) {
+                assert_eq!(mac(*algorithm, key, data), hex(expected), "{:?}", algorithm);
+            }
+        }
+    }
+}
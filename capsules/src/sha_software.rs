@@ -0,0 +1,223 @@
+//! Software implementation of the `hil::digest` interface.
+//!
+//! `ShaSoftware` computes SHA-1, SHA-2 and HMAC digests on the CPU, for chips
+//! that do not have a hash peripheral. Data passed to `add_data()` is hashed
+//! immediately, and the `add_data_done()` and `hash_done()` callbacks are
+//! issued from a deferred call, so clients see the same behaviour as with a
+//! hardware implementation.
+//!
+//! The algorithms that can be selected depend on the digest type `T`: for
+//! example a `ShaSoftware<[u8; 32]>` implements `Sha256` and `HMACSha256`,
+//! and a `ShaSoftware<[u8; 64]>` implements `Sha512` and `HMACSha512`. If no
+//! mode is set before data is added, the plain hash with a digest of the size
+//! of `T` is computed.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sha = static_init!(
+//!     capsules::sha_software::ShaSoftware<'static, [u8; 32]>,
+//!     capsules::sha_software::ShaSoftware::new(dynamic_deferred_caller)
+//! );
+//! sha.initialize_callback_handle(
+//!     dynamic_deferred_caller
+//!         .register(sha)
+//!         .expect("no deferred call slot available for sha"),
+//! );
+//! ```
+
+use crate::sha::{Algorithm, Hmac, Sha};
+use core::mem;
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::digest;
+use kernel::hil::digest::DigestType;
+use kernel::ReturnCode;
+
+/// The computation selected by the last `set_mode*()` call.
+// The engine is statically allocated, so the size of the smaller variant
+// does not matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Copy, Clone)]
+enum Mode {
+    Hash(Sha),
+    Hmac(Hmac),
+}
+
+pub struct ShaSoftware<'a, T: 'static + DigestType> {
+    client: OptionalCell<&'a dyn digest::Client<'a, T>>,
+    mode: MapCell<Mode>,
+
+    /// Buffers that are returned to the client from the deferred call.
+    data: TakeCell<'static, [u8]>,
+    digest: TakeCell<'static, T>,
+
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<'a, T: 'static + DigestType> ShaSoftware<'a, T> {
+    pub fn new(deferred_caller: &'a DynamicDeferredCall) -> ShaSoftware<'a, T> {
+        ShaSoftware {
+            client: OptionalCell::empty(),
+            mode: MapCell::empty(),
+            data: TakeCell::empty(),
+            digest: TakeCell::empty(),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    /// Initializes a callback handle for deferred callbacks.
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    fn busy(&self) -> bool {
+        self.data.is_some() || self.digest.is_some()
+    }
+
+    /// If no mode was set, use the plain hash whose digest fits `T`.
+    fn default_mode(&self) -> Result<(), ReturnCode> {
+        if self.mode.is_none() {
+            let algorithm =
+                Algorithm::from_output_len(mem::size_of::<T>()).ok_or(ReturnCode::ENOSUPPORT)?;
+            self.mode.replace(Mode::Hash(Sha::new(algorithm)));
+        }
+        Ok(())
+    }
+
+    fn set_mode(&self, mode: Mode) -> Result<(), ReturnCode> {
+        if self.busy() {
+            return Err(ReturnCode::EBUSY);
+        }
+        self.mode.replace(mode);
+        Ok(())
+    }
+}
+
+impl<'a, T: 'static + DigestType> digest::Digest<'a, T> for ShaSoftware<'a, T> {
+    fn set_client(&'a self, client: &'a dyn digest::Client<'a, T>) {
+        self.client.set(client);
+    }
+
+    fn add_data(
+        &self,
+        data: LeasableBuffer<'static, u8>,
+    ) -> Result<usize, (ReturnCode, &'static mut [u8])> {
+        if self.busy() || self.handle.is_none() {
+            return Err((ReturnCode::EBUSY, data.take()));
+        }
+        if let Err(e) = self.default_mode() {
+            return Err((e, data.take()));
+        }
+
+        let len = data.len();
+        self.mode.map(|mode| match mode {
+            Mode::Hash(sha) => sha.update(&data[..len]),
+            Mode::Hmac(hmac) => hmac.update(&data[..len]),
+        });
+        self.data.replace(data.take());
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+        Ok(len)
+    }
+
+    fn run(&'a self, digest: &'static mut T) -> Result<(), (ReturnCode, &'static mut T)> {
+        if self.digest.is_some() || self.handle.is_none() {
+            return Err((ReturnCode::EBUSY, digest));
+        }
+        if let Err(e) = self.default_mode() {
+            return Err((e, digest));
+        }
+
+        self.mode.map(|mode| match mode {
+            Mode::Hash(sha) => sha.finish(digest.as_mut()),
+            Mode::Hmac(hmac) => hmac.finish(digest.as_mut()),
+        });
+        // The computation is complete, a new one has to be started with
+        // `set_mode*()` or `add_data()`.
+        self.mode.take();
+        self.digest.replace(digest);
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+        Ok(())
+    }
+
+    fn clear_data(&self) {
+        // Dropping the state also drops the HMAC key, which is only kept
+        // mixed into the inner and outer hash states.
+        self.mode.take();
+    }
+}
+
+impl<'a, T: 'static + DigestType> DynamicDeferredCallClient for ShaSoftware<'a, T> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        // Data is always returned before the digest, as it was added before
+        // the digest was requested.
+        if let Some(data) = self.data.take() {
+            self.client
+                .map(move |client| client.add_data_done(Ok(()), data));
+        }
+        if let Some(digest) = self.digest.take() {
+            self.client
+                .map(move |client| client.hash_done(Ok(()), digest));
+        }
+    }
+}
+
+impl digest::Sha1 for ShaSoftware<'_, [u8; 20]> {
+    fn set_mode_sha1(&self) -> Result<(), ReturnCode> {
+        self.set_mode(Mode::Hash(Sha::new(Algorithm::Sha1)))
+    }
+}
+
+impl digest::Sha224 for ShaSoftware<'_, [u8; 28]> {
+    fn set_mode_sha224(&self) -> Result<(), ReturnCode> {
+        self.set_mode(Mode::Hash(Sha::new(Algorithm::Sha224)))
+    }
+}
+
+impl digest::Sha256 for ShaSoftware<'_, [u8; 32]> {
+    fn set_mode_sha256(&self) -> Result<(), ReturnCode> {
+        self.set_mode(Mode::Hash(Sha::new(Algorithm::Sha256)))
+    }
+}
+
+impl digest::Sha384 for ShaSoftware<'_, [u8; 48]> {
+    fn set_mode_sha384(&self) -> Result<(), ReturnCode> {
+        self.set_mode(Mode::Hash(Sha::new(Algorithm::Sha384)))
+    }
+}
+
+impl digest::Sha512 for ShaSoftware<'_, [u8; 64]> {
+    fn set_mode_sha512(&self) -> Result<(), ReturnCode> {
+        self.set_mode(Mode::Hash(Sha::new(Algorithm::Sha512)))
+    }
+}
+
+impl digest::HMACSha1 for ShaSoftware<'_, [u8; 20]> {
+    fn set_mode_hmacsha1(&self, key: &[u8]) -> Result<(), ReturnCode> {
+        self.set_mode(Mode::Hmac(Hmac::new(Algorithm::Sha1, key)))
+    }
+}
+
+impl digest::HMACSha256 for ShaSoftware<'_, [u8; 32]> {
+    fn set_mode_hmacsha256(&self, key: &[u8]) -> Result<(), ReturnCode> {
+        self.set_mode(Mode::Hmac(Hmac::new(Algorithm::Sha256, key)))
+    }
+}
+
+impl digest::HMACSha384 for ShaSoftware<'_, [u8; 48]> {
+    fn set_mode_hmacsha384(&self, key: &[u8]) -> Result<(), ReturnCode> {
+        self.set_mode(Mode::Hmac(Hmac::new(Algorithm::Sha384, key)))
+    }
+}
+
+impl digest::HMACSha512 for ShaSoftware<'_, [u8; 64]> {
+    fn set_mode_hmacsha512(&self, key: &[u8]) -> Result<(), ReturnCode> {
+        self.set_mode(Mode::Hmac(Hmac::new(Algorithm::Sha512, key)))
+    }
+}
@@ -0,0 +1,633 @@
+//! Test the BLE link layer, L2CAP and the ATT server against a simulated
+//! central.
+//!
+//! `SimulatedCentral` implements the link layer radio interface: instead of
+//! transmitting, it plays the role of a central, answering the link layer
+//! shortly after it starts listening. It scans the advertisements, connects,
+//! then runs a script of exchanges in the connection: link layer control
+//! procedures, a packet with a CRC error, a missed connection event, a packet
+//! answered too late to replace the prepared response, ATT requests to a
+//! `TestDatabase` and a notification, before terminating the connection. It checks the channel hopping and the acknowledgements along
+//! the way. Depends on a working UART and debug! macro.
+
+use crate::ble::att::{AttServer, Attribute, AttributeDatabase, Uuid};
+use crate::ble::gatt::{self, properties};
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::debug;
+use kernel::hil::ble_advertising::RadioChannel;
+use kernel::hil::ble_connection::{self, LinkLayerClient, LinkLayerRadio};
+use kernel::hil::time::{Alarm, AlarmClient};
+use kernel::ReturnCode;
+
+// Link layer PDU headers
+const ADV_IND: u8 = 0x00;
+const SCAN_REQ: u8 = 0x03;
+const SCAN_RSP: u8 = 0x04;
+const CONNECT_IND: u8 = 0x05;
+const TXADD: u8 = 1 << 6;
+const RXADD: u8 = 1 << 7;
+const LLID_CONTROL: u8 = 0b11;
+const NESN: u8 = 1 << 2;
+const SN: u8 = 1 << 3;
+
+/// The address of the central
+const CENTRAL_ADDRESS: [u8; 6] = [0x01, 0x02, 0x03, 0x04, 0x05, 0xc6];
+
+/// The parameters of the connection
+const ACCESS_ADDRESS: u32 = 0x5065_5a3c;
+const CRC_INIT: u32 = 0x12_3456;
+const HOP: u8 = 7;
+/// LLData of CONNECT_IND: a 2.5 ms transmit window, a 30 ms interval, a 1 s
+/// supervision timeout, all data channels and a 500 ppm sleep clock
+const LL_DATA: [u8; 22] = [
+    0x3c, 0x5a, 0x65, 0x50, // Access address
+    0x56, 0x34, 0x12, // CRC init
+    2,    // Window size
+    0, 0, // Window offset
+    24, 0, // Interval
+    0, 0, // Latency
+    100, 0, // Timeout
+    0xff, 0xff, 0xff, 0xff, 0x1f, // Channel map
+    HOP,  // Hop, SCA
+];
+
+/// The time the central answers after the link layer starts listening
+const ANSWER_DELAY_US: u32 = 100;
+
+/// The attributes of `TestDatabase`
+const LEVEL_HANDLE: u16 = 3;
+const CCCD_HANDLE: u16 = 4;
+const WRITABLE_HANDLE: u16 = 6;
+const BATTERY_LEVEL: u8 = 90;
+
+/// The steps of the test. Data channel PDUs are written with their LLID
+/// followed by their payload.
+#[derive(Copy, Clone)]
+enum Step {
+    /// Send a scan request after an advertisement
+    ScanRequest,
+    /// Connect after an advertisement
+    Connect,
+    /// Send a PDU and wait for it to be acknowledged, then for the response
+    /// starting with the second PDU, if it is not empty
+    Send(&'static [u8], &'static [u8]),
+    /// Send a packet with a CRC error, which must not be acknowledged
+    CrcError,
+    /// Do not answer in a connection event
+    MissEvent,
+    /// Send a PDU that is answered with the prepared response, which must not
+    /// acknowledge it
+    LateResponse(&'static [u8]),
+    /// Wait for a PDU starting with this one
+    Expect(&'static [u8]),
+    /// Terminate the connection
+    Terminate,
+    Done,
+}
+
+const SCRIPT: &[Step] = &[
+    Step::ScanRequest,
+    Step::Connect,
+    // LL_VERSION_IND
+    Step::Send(
+        &[LLID_CONTROL, 0x0c, 0x08, 0x59, 0x00, 0x01, 0x00],
+        &[LLID_CONTROL, 0x0c, 0x08, 0xff, 0xff],
+    ),
+    Step::CrcError,
+    // LL_FEATURE_REQ
+    Step::Send(
+        &[LLID_CONTROL, 0x08, 0, 0, 0, 0, 0, 0, 0, 0],
+        &[LLID_CONTROL, 0x09, 0, 0, 0, 0, 0, 0, 0, 0],
+    ),
+    Step::MissEvent,
+    // Exchange MTU
+    Step::Send(
+        &[0b10, 3, 0, 4, 0, 0x02, 0x17, 0x00],
+        &[0b10, 3, 0, 4, 0, 0x03, 0x17, 0x00],
+    ),
+    // Read By Group Type of primary services
+    Step::Send(
+        &[0b10, 7, 0, 4, 0, 0x10, 0x01, 0x00, 0xff, 0xff, 0x00, 0x28],
+        &[
+            0b10, 8, 0, 4, 0, 0x11, 6, 0x01, 0x00, 0x06, 0x00, 0x0f, 0x18,
+        ],
+    ),
+    // Read the battery level, first too late for the response to it
+    Step::LateResponse(&[0b10, 3, 0, 4, 0, 0x0a, LEVEL_HANDLE as u8, 0]),
+    Step::Send(
+        &[0b10, 3, 0, 4, 0, 0x0a, LEVEL_HANDLE as u8, 0],
+        &[0b10, 2, 0, 4, 0, 0x0b, BATTERY_LEVEL],
+    ),
+    // Write the writable characteristic, and read it back
+    Step::Send(
+        &[0b10, 5, 0, 4, 0, 0x12, WRITABLE_HANDLE as u8, 0, 0xab, 0xcd],
+        &[0b10, 1, 0, 4, 0, 0x13],
+    ),
+    Step::Send(
+        &[0b10, 3, 0, 4, 0, 0x0a, WRITABLE_HANDLE as u8, 0],
+        &[0b10, 3, 0, 4, 0, 0x0b, 0xab, 0xcd],
+    ),
+    // Subscribe to the battery level, which is then notified
+    Step::Send(
+        &[0b10, 5, 0, 4, 0, 0x12, CCCD_HANDLE as u8, 0, 0x01, 0x00],
+        &[0b10, 1, 0, 4, 0, 0x13],
+    ),
+    Step::Expect(&[0b10, 4, 0, 4, 0, 0x1b, LEVEL_HANDLE as u8, 0, BATTERY_LEVEL]),
+    // Read Multiple is not supported
+    Step::Send(
+        &[0b10, 5, 0, 4, 0, 0x0e, 0x03, 0x00, 0x06, 0x00],
+        &[0b10, 5, 0, 4, 0, 0x01, 0x0e, 0x00, 0x00, 0x06],
+    ),
+    // A request fragmented in two data PDUs
+    Step::Send(&[0b10, 3, 0, 4, 0, 0x0a], &[]),
+    Step::Send(
+        &[0b01, LEVEL_HANDLE as u8, 0],
+        &[0b10, 2, 0, 4, 0, 0x0b, BATTERY_LEVEL],
+    ),
+    // Pairing is not supported
+    Step::Send(
+        &[0b10, 7, 0, 6, 0, 0x01, 0x03, 0x00, 0x01, 0x10, 0x07, 0x07],
+        &[0b10, 2, 0, 6, 0, 0x05, 0x05],
+    ),
+    // Neither are connection-oriented channels
+    Step::Send(
+        &[
+            0b10, 14, 0, 5, 0, 0x14, 0x01, 10, 0, 0x80, 0x00, 0x40, 0x00, 23, 0, 23, 0, 1, 0,
+        ],
+        &[
+            0b10, 14, 0, 5, 0, 0x15, 0x01, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02, 0x00,
+        ],
+    ),
+    Step::Terminate,
+    Step::Done,
+];
+
+/// A GATT database with a battery service, whose level can be notified, and
+/// a writable characteristic.
+pub struct TestDatabase<'a> {
+    att: &'a AttServer<'a>,
+    cccd: Cell<u16>,
+    value: Cell<[u8; 4]>,
+    value_len: Cell<usize>,
+    connected: Cell<bool>,
+    disconnected: Cell<bool>,
+}
+
+impl<'a> TestDatabase<'a> {
+    pub fn new(att: &'a AttServer<'a>) -> TestDatabase<'a> {
+        TestDatabase {
+            att,
+            cccd: Cell::new(0),
+            value: Cell::new([0; 4]),
+            value_len: Cell::new(0),
+            connected: Cell::new(false),
+            disconnected: Cell::new(false),
+        }
+    }
+}
+
+impl AttributeDatabase for TestDatabase<'_> {
+    fn for_each_attribute(&self, start: u16, end: u16, f: &mut dyn FnMut(&Attribute) -> bool) {
+        let mut level_declaration = [0; 5];
+        gatt::characteristic_declaration(
+            &mut level_declaration,
+            properties::READ | properties::NOTIFY,
+            LEVEL_HANDLE,
+            &Uuid::Uuid16(0x2a19),
+        );
+        let mut writable_declaration = [0; 5];
+        gatt::characteristic_declaration(
+            &mut writable_declaration,
+            properties::READ | properties::WRITE,
+            WRITABLE_HANDLE,
+            &Uuid::Uuid16(0xff01),
+        );
+        let cccd = self.cccd.get().to_le_bytes();
+        let value = self.value.get();
+        let attributes = [
+            Attribute {
+                handle: 1,
+                uuid: gatt::PRIMARY_SERVICE,
+                value: &[0x0f, 0x18],
+                readable: true,
+                group_end: WRITABLE_HANDLE,
+            },
+            Attribute {
+                handle: 2,
+                uuid: gatt::CHARACTERISTIC,
+                value: &level_declaration,
+                readable: true,
+                group_end: 2,
+            },
+            Attribute {
+                handle: LEVEL_HANDLE,
+                uuid: Uuid::Uuid16(0x2a19),
+                value: &[BATTERY_LEVEL],
+                readable: true,
+                group_end: LEVEL_HANDLE,
+            },
+            Attribute {
+                handle: CCCD_HANDLE,
+                uuid: gatt::CLIENT_CHARACTERISTIC_CONFIGURATION,
+                value: &cccd,
+                readable: true,
+                group_end: CCCD_HANDLE,
+            },
+            Attribute {
+                handle: 5,
+                uuid: gatt::CHARACTERISTIC,
+                value: &writable_declaration,
+                readable: true,
+                group_end: 5,
+            },
+            Attribute {
+                handle: WRITABLE_HANDLE,
+                uuid: Uuid::Uuid16(0xff01),
+                value: &value[..self.value_len.get()],
+                readable: true,
+                group_end: WRITABLE_HANDLE,
+            },
+        ];
+        for attribute in attributes.iter() {
+            if attribute.handle >= start && attribute.handle <= end && !f(attribute) {
+                return;
+            }
+        }
+    }
+
+    fn write_attribute(&self, handle: u16, value: &[u8], _with_response: bool) -> Result<(), u8> {
+        match handle {
+            CCCD_HANDLE if value.len() == 2 => {
+                self.cccd.set(u16::from_le_bytes([value[0], value[1]]));
+                if self.cccd.get() & gatt::CCCD_NOTIFICATIONS != 0 {
+                    self.att.notify(LEVEL_HANDLE, false);
+                }
+                Ok(())
+            }
+            WRITABLE_HANDLE if value.len() <= 4 => {
+                let mut buf = [0; 4];
+                buf[..value.len()].copy_from_slice(value);
+                self.value.set(buf);
+                self.value_len.set(value.len());
+                Ok(())
+            }
+            CCCD_HANDLE | WRITABLE_HANDLE => {
+                Err(crate::ble::att::error::INVALID_ATTRIBUTE_VALUE_LENGTH)
+            }
+            1..=5 => Err(crate::ble::att::error::WRITE_NOT_PERMITTED),
+            _ => Err(crate::ble::att::error::INVALID_HANDLE),
+        }
+    }
+
+    fn connected(&self) {
+        self.connected.set(true);
+    }
+
+    fn disconnected(&self) {
+        self.disconnected.set(true);
+    }
+
+    fn notification_done(&self, _handle: u16) {}
+}
+
+pub struct SimulatedCentral<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    database: &'a TestDatabase<'a>,
+    client: OptionalCell<&'a dyn LinkLayerClient>,
+    access_address: Cell<u32>,
+    crc_init: Cell<u32>,
+    /// The channel listened on, while the link layer listens
+    channel: Cell<Option<RadioChannel>>,
+    /// The address of the advertiser
+    peripheral: Cell<[u8; 6]>,
+    step: Cell<usize>,
+    /// Whether the PDU of the current step was sent
+    sent: Cell<bool>,
+    /// Whether the PDU of the current step was acknowledged
+    acked: Cell<bool>,
+    /// The response the link layer prepared when it started listening
+    prepared: Cell<[u8; ble_connection::MAX_PDU_LEN]>,
+    prepared_len: Cell<usize>,
+    sn: Cell<bool>,
+    nesn: Cell<bool>,
+    unmapped_channel: Cell<u8>,
+    events: Cell<usize>,
+    failed: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>> SimulatedCentral<'a, A> {
+    pub fn new(alarm: &'a A, database: &'a TestDatabase<'a>) -> SimulatedCentral<'a, A> {
+        SimulatedCentral {
+            alarm,
+            database,
+            client: OptionalCell::empty(),
+            access_address: Cell::new(0),
+            crc_init: Cell::new(0),
+            channel: Cell::new(None),
+            peripheral: Cell::new([0; 6]),
+            step: Cell::new(0),
+            sent: Cell::new(false),
+            acked: Cell::new(false),
+            prepared: Cell::new([0; ble_connection::MAX_PDU_LEN]),
+            prepared_len: Cell::new(0),
+            sn: Cell::new(false),
+            nesn: Cell::new(false),
+            unmapped_channel: Cell::new(0),
+            events: Cell::new(0),
+            failed: Cell::new(false),
+        }
+    }
+
+    fn current(&self) -> Step {
+        SCRIPT[self.step.get()]
+    }
+
+    fn next_step(&self) {
+        self.step.set(self.step.get() + 1);
+        self.sent.set(false);
+        self.acked.set(false);
+    }
+
+    fn fail(&self, message: &str) {
+        debug!(
+            "BLE connection test failed at step {}: {}",
+            self.step.get(),
+            message
+        );
+        self.failed.set(true);
+        self.step.set(SCRIPT.len() - 1);
+    }
+
+    fn listen(&self, channel: RadioChannel) {
+        self.channel.set(Some(channel));
+        // Like the radio, ask for the response before any packet arrives
+        let mut response = [0; ble_connection::MAX_PDU_LEN];
+        let len = self
+            .client
+            .map_or(0, |client| client.prepare_response(&mut response));
+        self.prepared.set(response);
+        self.prepared_len.set(len);
+        self.alarm
+            .set_alarm(self.alarm.now(), A::ticks_from_us(ANSWER_DELAY_US));
+    }
+
+    /// Answers an advertisement.
+    fn advertising_event(&self, client: &dyn LinkLayerClient) {
+        let mut request = [0; 2 + 12 + 22];
+        let len = match self.current() {
+            Step::ScanRequest => {
+                request[0] = SCAN_REQ | TXADD | RXADD;
+                request[1] = 12;
+                request[2..8].copy_from_slice(&CENTRAL_ADDRESS);
+                request[8..14].copy_from_slice(&self.peripheral.get());
+                14
+            }
+            Step::Connect => {
+                request[0] = CONNECT_IND | TXADD | RXADD;
+                request[1] = 34;
+                request[2..8].copy_from_slice(&CENTRAL_ADDRESS);
+                request[8..14].copy_from_slice(&self.peripheral.get());
+                request[14..36].copy_from_slice(&LL_DATA);
+                36
+            }
+            _ => {
+                self.fail("advertising while connected");
+                return;
+            }
+        };
+        let mut response = self.prepared.get();
+        let response_len = client.respond(&request[..len], true, Some(&mut response));
+        match self.current() {
+            Step::ScanRequest => {
+                if response_len != 8
+                    || response[0] != SCAN_RSP | TXADD
+                    || response[2..8] != self.peripheral.get()
+                {
+                    self.fail("bad scan response");
+                    return;
+                }
+            }
+            _ => {
+                if response_len != 0 {
+                    self.fail("response to CONNECT_IND");
+                    return;
+                }
+            }
+        }
+        debug!("BLE connection test: step {} passed", self.step.get());
+        self.next_step();
+    }
+
+    /// Exchanges a packet with the peripheral in a connection event.
+    fn connection_event(&self, client: &dyn LinkLayerClient) {
+        let step = self.current();
+        let (llid, payload): (u8, &[u8]) = match step {
+            Step::Send(pdu, _) if !self.acked.get() => (pdu[0], &pdu[1..]),
+            Step::LateResponse(pdu) => (pdu[0], &pdu[1..]),
+            Step::Terminate => (LLID_CONTROL, &[0x02, 0x13]),
+            _ => (0b01, &[]),
+        };
+        let crc_ok = !matches!(step, Step::CrcError);
+
+        let mut pdu = [0; 2 + 27];
+        pdu[0] = llid;
+        if self.sn.get() {
+            pdu[0] |= SN;
+        }
+        if self.nesn.get() {
+            pdu[0] |= NESN;
+        }
+        pdu[1] = payload.len() as u8;
+        pdu[2..2 + payload.len()].copy_from_slice(payload);
+        self.sent.set(true);
+
+        let mut response = self.prepared.get();
+        let len = if let Step::LateResponse(_) = step {
+            client.respond(&pdu[..2 + payload.len()], crc_ok, None);
+            self.prepared_len.get()
+        } else {
+            client.respond(&pdu[..2 + payload.len()], crc_ok, Some(&mut response))
+        };
+        if len < 2 || len != response[1] as usize + 2 {
+            self.fail("bad response length");
+            return;
+        }
+        let header = response[0];
+
+        if let Step::LateResponse(_) = step {
+            // The prepared response was written before the packet arrived
+            if (header & NESN != 0) != self.sn.get() {
+                self.fail("packet answered late acknowledged");
+                return;
+            }
+        }
+
+        if !crc_ok {
+            // The packet must not be acknowledged, and the response is
+            // ignored so that the peripheral sends it again
+            if (header & NESN != 0) != self.sn.get() {
+                self.fail("packet with CRC error acknowledged");
+            } else {
+                debug!("BLE connection test: step {} passed", self.step.get());
+                self.next_step();
+            }
+            return;
+        }
+
+        if (header & NESN != 0) != self.sn.get() {
+            self.sn.set(!self.sn.get());
+            if !payload.is_empty() {
+                self.acked.set(true);
+            }
+        }
+
+        let mut received = None;
+        if (header & SN != 0) == self.nesn.get() {
+            self.nesn.set(!self.nesn.get());
+            if len > 2 {
+                received = Some(len);
+            }
+        }
+
+        let expected = match step {
+            Step::Send(_, expected) if self.acked.get() && !expected.is_empty() => Some(expected),
+            Step::Expect(expected) => Some(expected),
+            _ => None,
+        };
+        if let Some(len) = received {
+            let matches = expected.map_or(false, |expected| {
+                response[0] & 0b11 == expected[0]
+                    && len - 2 >= expected.len() - 1
+                    && response[2..2 + expected.len() - 1] == expected[1..]
+            });
+            if !matches {
+                debug!(
+                    "BLE connection test: unexpected PDU {:02x?}",
+                    &response[..len]
+                );
+                self.fail("unexpected PDU");
+                return;
+            }
+        }
+
+        let done = match step {
+            Step::Send(_, expected) => {
+                self.acked.get() && (expected.is_empty() || received.is_some())
+            }
+            Step::Expect(_) => received.is_some(),
+            Step::LateResponse(_) => true,
+            Step::Terminate => self.acked.get(),
+            _ => false,
+        };
+        if done {
+            debug!("BLE connection test: step {} passed", self.step.get());
+            self.next_step();
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> LinkLayerRadio<'a> for SimulatedCentral<'a, A> {
+    fn set_link_layer_client(&self, client: &'a dyn LinkLayerClient) {
+        self.client.set(client);
+    }
+
+    fn set_access_address(&self, access_address: u32, crc_init: u32) {
+        self.access_address.set(access_address);
+        self.crc_init.set(crc_init);
+    }
+
+    fn transmit_and_listen(&self, pdu: &[u8], channel: RadioChannel) -> ReturnCode {
+        if self.channel.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        if self.access_address.get() != ble_connection::ADVERTISING_ACCESS_ADDRESS {
+            self.fail("advertising with a data channel access address");
+        } else if pdu.len() < 8 || pdu[0] != ADV_IND | TXADD || pdu[1] as usize != pdu.len() - 2 {
+            self.fail("bad advertisement");
+        } else {
+            let mut address = [0; 6];
+            address.copy_from_slice(&pdu[2..8]);
+            self.peripheral.set(address);
+        }
+        self.listen(channel);
+        ReturnCode::SUCCESS
+    }
+
+    fn listen_and_respond(&self, channel: RadioChannel) -> ReturnCode {
+        if self.channel.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        // Channel selection algorithm #1 with all channels used
+        let unmapped = (self.unmapped_channel.get() + HOP) % 37;
+        self.unmapped_channel.set(unmapped);
+        self.events.set(self.events.get() + 1);
+        if self.failed.get() {
+            self.channel.set(Some(channel));
+            return ReturnCode::SUCCESS;
+        }
+        if channel.get_channel_index() != unmapped as u32 {
+            debug!(
+                "BLE connection test: event {} on channel {}, expected {}",
+                self.events.get(),
+                channel.get_channel_index(),
+                unmapped
+            );
+            self.fail("wrong data channel");
+        }
+        if self.access_address.get() != ACCESS_ADDRESS || self.crc_init.get() != CRC_INIT {
+            self.fail("wrong access address");
+        }
+        if let Step::MissEvent = self.current() {
+            debug!("BLE connection test: step {} passed", self.step.get());
+            self.next_step();
+            self.channel.set(Some(channel));
+        } else {
+            self.listen(channel);
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn stop_listening(&self) -> ReturnCode {
+        if self.channel.take().is_none() {
+            return ReturnCode::EALREADY;
+        }
+        self.alarm.disarm();
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for SimulatedCentral<'a, A> {
+    fn alarm(&self) {
+        let channel = match self.channel.take() {
+            Some(channel) => channel,
+            None => return,
+        };
+        if let Step::Done = self.current() {
+            // Let the link layer time out
+            self.channel.set(Some(channel));
+            return;
+        }
+        self.client.map(|client| {
+            if self.access_address.get() == ble_connection::ADVERTISING_ACCESS_ADDRESS {
+                self.advertising_event(*client);
+                if self.database.connected.get() {
+                    self.fail("connected before CONNECT_IND");
+                }
+            } else {
+                self.connection_event(*client);
+            }
+            client.operation_done(ReturnCode::SUCCESS);
+        });
+        if let (Step::Done, false) = (self.current(), self.failed.get()) {
+            if self.database.connected.get() && self.database.disconnected.get() {
+                debug!(
+                    "BLE connection test passed ({} connection events)",
+                    self.events.get()
+                );
+            } else {
+                self.fail("not disconnected after LL_TERMINATE_IND");
+            }
+        }
+    }
+}
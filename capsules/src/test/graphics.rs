@@ -0,0 +1,225 @@
+//! Test the rendering of display lists by the graphics capsule.
+//!
+//! `TestGraphics` renders a display list of every operation into a frame of a
+//! small screen, the way the graphics capsule does, and checks that:
+//!
+//! - rendering the viewport in small tiles gives the same pixels as
+//!   rendering it at once,
+//! - the pixels outside the viewport are left alone,
+//! - the operations draw the expected pixels, text included,
+//! - rotating the viewport rotates its content.
+//!
+//! It runs synchronously and needs no screen. Depends on a working UART and
+//! debug! macro.
+
+use crate::graphics::canvas::{self, op, Canvas, Rect};
+use kernel::common::cells::TakeCell;
+use kernel::debug;
+use kernel::hil::screen::{ScreenPixelFormat, ScreenRotation};
+
+/// The size of the screen
+pub const WIDTH: usize = 48;
+pub const HEIGHT: usize = 24;
+/// The size of the tiles, as the graphics capsule would use with a small
+/// buffer
+pub const TILE_WIDTH: usize = 7;
+pub const TILE_HEIGHT: usize = 5;
+
+const FORMAT: ScreenPixelFormat = ScreenPixelFormat::RGB_233;
+/// The pixels outside the viewport
+const UNTOUCHED: u8 = 0xaa;
+const VIEWPORT: Rect = Rect::new(4, 2, 40, 20);
+/// A square viewport, to compare its rotations
+const SQUARE: Rect = Rect::new(10, 2, 20, 20);
+
+const WHITE: u32 = 0xff_ffff;
+const RED: u32 = 0xff_0000;
+const BLUE: u32 = 0x00_00ff;
+
+#[rustfmt::skip]
+const DISPLAY_LIST: [u8; 92] = [
+    op::CLEAR, 0xff, 0x00, 0x00, 0x00, // Blue
+    op::FILL_RECT, 30, 0, 12, 0, 20, 0, 4, 0, 0x00, 0x00, 0xff, 0x00, // Red, past the edge
+    op::LINE, 0, 0, 0, 0, 19, 0, 19, 0, 0xff, 0xff, 0xff, 0x00, // White
+    op::RECT, 0, 0, 0, 0, 40, 0, 20, 0, 0x00, 0xff, 0x00, 0x00, // Green
+    op::PIXEL, 0xfe, 0xff, 0xfe, 0xff, 0xff, 0xff, 0xff, 0x00, // Outside
+    op::TEXT, 2, 0, 12, 0, 0xff, 0xff, 0xff, 0x00, 0xff, 0xff, 0xff, 0xff, 1, 2, b'H', b'i',
+    op::BITMAP, 24, 0, 12, 0, 3, 0, 2, 0,
+    0x00, 0x00, 0xff, 0x00, 0xff, 0xff, 0xff, 0x00, 2, 0b1010_0000, 0b0100_0000,
+    op::END, op::PIXEL,
+];
+
+pub struct TestGraphics {
+    /// The screen, rendered at once
+    frame: TakeCell<'static, [u8]>,
+    /// The screen, rendered in tiles
+    tiled_frame: TakeCell<'static, [u8]>,
+    tile: TakeCell<'static, [u8]>,
+}
+
+impl TestGraphics {
+    /// `frame` and `tiled_frame` must hold `WIDTH * HEIGHT` bytes, and
+    /// `tile` `TILE_WIDTH * TILE_HEIGHT`.
+    pub fn new(
+        frame: &'static mut [u8],
+        tiled_frame: &'static mut [u8],
+        tile: &'static mut [u8],
+    ) -> TestGraphics {
+        TestGraphics {
+            frame: TakeCell::new(frame),
+            tiled_frame: TakeCell::new(tiled_frame),
+            tile: TakeCell::new(tile),
+        }
+    }
+
+    pub fn run(&self) {
+        self.frame.map(|frame| {
+            self.tiled_frame.map(|tiled_frame| {
+                self.tile.map(|tile| {
+                    Self::test_tiles(frame, tiled_frame, tile);
+                    Self::test_operations(frame);
+                    Self::test_rotation(frame, tiled_frame);
+                })
+            })
+        });
+        debug!("Graphics test passed");
+    }
+
+    /// Renders `viewport` at once into `frame`.
+    fn render(frame: &mut [u8], viewport: Rect, rotation: ScreenRotation) {
+        for pixel in frame.iter_mut() {
+            *pixel = UNTOUCHED;
+        }
+        let mut canvas = Canvas::new(
+            &mut frame[..WIDTH * HEIGHT],
+            FORMAT,
+            Rect::new(0, 0, WIDTH as i32, HEIGHT as i32),
+            viewport,
+            rotation,
+        );
+        canvas::render(&mut canvas, &DISPLAY_LIST);
+    }
+
+    fn pixel(frame: &[u8], x: i32, y: i32) -> u8 {
+        frame[y as usize * WIDTH + x as usize]
+    }
+
+    fn test_tiles(frame: &mut [u8], tiled_frame: &mut [u8], tile: &mut [u8]) {
+        Self::render(frame, VIEWPORT, ScreenRotation::Normal);
+
+        for pixel in tiled_frame.iter_mut() {
+            *pixel = UNTOUCHED;
+        }
+        for tile_y in (VIEWPORT.y..VIEWPORT.y + VIEWPORT.height).step_by(TILE_HEIGHT) {
+            for tile_x in (VIEWPORT.x..VIEWPORT.x + VIEWPORT.width).step_by(TILE_WIDTH) {
+                let area = Rect::new(tile_x, tile_y, TILE_WIDTH as i32, TILE_HEIGHT as i32)
+                    .intersection(&VIEWPORT);
+                let len = (area.width * area.height) as usize;
+                let mut canvas = Canvas::new(
+                    &mut tile[..len],
+                    FORMAT,
+                    area,
+                    VIEWPORT,
+                    ScreenRotation::Normal,
+                );
+                canvas::render(&mut canvas, &DISPLAY_LIST);
+                for y in 0..area.height {
+                    for x in 0..area.width {
+                        tiled_frame[(area.y + y) as usize * WIDTH + (area.x + x) as usize] =
+                            tile[(y * area.width + x) as usize];
+                    }
+                }
+            }
+        }
+
+        for y in 0..HEIGHT as i32 {
+            for x in 0..WIDTH as i32 {
+                let (once, tiled) = (Self::pixel(frame, x, y), Self::pixel(tiled_frame, x, y));
+                if once != tiled {
+                    panic!(
+                        "Graphics test: pixel ({}, {}) is {:#x} rendered at once, {:#x} in tiles",
+                        x, y, once, tiled
+                    );
+                }
+                if !VIEWPORT.contains(x, y) && once != UNTOUCHED {
+                    panic!("Graphics test: pixel ({}, {}) outside the viewport", x, y);
+                }
+            }
+        }
+        debug!("Graphics test: tiles passed");
+    }
+
+    fn test_operations(frame: &mut [u8]) {
+        Self::render(frame, VIEWPORT, ScreenRotation::Normal);
+        let color = |color| canvas::encode_color(color, FORMAT) as u8;
+        let check = |x: i32, y: i32, expected: u32, what: &str| {
+            let pixel = Self::pixel(frame, VIEWPORT.x + x, VIEWPORT.y + y);
+            if pixel != color(expected) {
+                panic!(
+                    "Graphics test: {} at ({}, {}) is {:#x}, expected {:#x}",
+                    what,
+                    x,
+                    y,
+                    pixel,
+                    color(expected)
+                );
+            }
+        };
+
+        check(5, 10, BLUE, "background");
+        check(0, 0, 0x00_ff00, "rectangle");
+        check(39, 19, 0x00_ff00, "rectangle");
+        check(10, 10, WHITE, "line");
+        check(18, 18, WHITE, "line");
+        check(35, 13, RED, "filled rectangle");
+        check(39, 13, 0x00_ff00, "filled rectangle");
+        // 'H' is two columns joined in its middle row
+        check(2, 12, WHITE, "text");
+        check(2, 18, WHITE, "text");
+        check(4, 15, WHITE, "text");
+        check(4, 14, BLUE, "text");
+        // 'i' is dotted
+        check(10, 12, WHITE, "text");
+        check(10, 13, BLUE, "text");
+        check(10, 14, WHITE, "text");
+        // Each bit of the bitmap is 2 by 2 pixels
+        check(24, 12, RED, "bitmap");
+        check(25, 13, RED, "bitmap");
+        check(26, 12, WHITE, "bitmap");
+        check(28, 12, RED, "bitmap");
+        check(26, 14, RED, "bitmap");
+        check(24, 14, WHITE, "bitmap");
+        debug!("Graphics test: operations passed");
+    }
+
+    fn test_rotation(frame: &mut [u8], rotated_frame: &mut [u8]) {
+        Self::render(frame, SQUARE, ScreenRotation::Normal);
+        let size = SQUARE.width;
+        for &rotation in &[
+            ScreenRotation::Rotated90,
+            ScreenRotation::Rotated180,
+            ScreenRotation::Rotated270,
+        ] {
+            Self::render(rotated_frame, SQUARE, rotation);
+            for y in 0..size {
+                for x in 0..size {
+                    let (rotated_x, rotated_y) = match rotation {
+                        ScreenRotation::Rotated90 => (size - 1 - y, x),
+                        ScreenRotation::Rotated180 => (size - 1 - x, size - 1 - y),
+                        _ => (y, size - 1 - x),
+                    };
+                    let pixel = Self::pixel(frame, SQUARE.x + x, SQUARE.y + y);
+                    let rotated =
+                        Self::pixel(rotated_frame, SQUARE.x + rotated_x, SQUARE.y + rotated_y);
+                    if pixel != rotated {
+                        panic!(
+                            "Graphics test: rotation {} moved pixel ({}, {})",
+                            rotation as usize, x, y
+                        );
+                    }
+                }
+            }
+        }
+        debug!("Graphics test: rotation passed");
+    }
+}
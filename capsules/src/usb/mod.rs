@@ -1,6 +1,7 @@
 pub mod cdc;
 pub mod ctap;
 pub mod descriptors;
+pub mod msc;
 pub mod usb_user;
 pub mod usbc_client;
 pub mod usbc_client_ctrl;
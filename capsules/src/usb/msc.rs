@@ -0,0 +1,856 @@
+//! USB Mass Storage Class, Bulk-Only Transport.
+//!
+//! This exposes a block device, such as an SD card or a region of flash (see
+//! `nonvolatile_to_blocks`), to a computer as a SCSI disk, so that the files a
+//! device stored can be copied by plugging it in.
+//!
+//! Based on the "Universal Serial Bus Mass Storage Class Bulk-Only Transport"
+//! specification, revision 1.0, with the subset of SCSI commands that hosts
+//! use for flash drives. The device has a single logical unit, and transfers
+//! one block at a time through a buffer given at creation, which must hold at
+//! least one block of the device.
+//!
+//! `hil::usb` offers no way to clear the halt of an endpoint, so this driver
+//! never stalls its bulk endpoints. Invalid command wrappers are ignored
+//! instead of starting a reset recovery, and data the host expects beyond the
+//! end of a response is padded with zeros.
+//!
+//! The host caches the device and writes to it at any time. The block device
+//! should not be used by anything else, such as `fat::FatFs`, while it is
+//! exposed.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static STRINGS: &'static [&str; 3] = &[
+//!     "XYZ Corp.",      // Manufacturer
+//!     "The Zorpinator", // Product
+//!     "0123456789AB",   // Serial number, at least 12 hexadecimal digits
+//! ];
+//! let msc = components::usb_msc::UsbMscComponent::new(
+//!     &nrf52::usbd::USBD,
+//!     capsules::usb::usbc_client::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915,
+//!     0x503a,
+//!     STRINGS,
+//!     sdcard_block_storage,
+//! )
+//! .finalize(components::usb_msc_component_helper!(
+//!     nrf52::usbd::Usbd,
+//!     capsules::sdcard::SDCardBlockStorage<'static, VirtualMuxAlarm<'static, nrf52::rtc::Rtc>>
+//! ));
+//! msc.enable();
+//! msc.attach();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use super::descriptors;
+use super::descriptors::Buffer64;
+use super::descriptors::EndpointAddress;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::Recipient;
+use super::descriptors::RequestType;
+use super::descriptors::TransferDirection;
+use super::usbc_client_ctrl::ClientCtrl;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::hil::block_storage::{BlockStorage, BlockStorageClient};
+use kernel::hil::usb::TransferType;
+use kernel::ReturnCode;
+
+/// Bulk IN endpoint, from the device to the host.
+const ENDPOINT_IN_NUM: usize = 1;
+/// Bulk OUT endpoint, from the host to the device.
+const ENDPOINT_OUT_NUM: usize = 2;
+
+const IN_BUFFER: usize = 0;
+const OUT_BUFFER: usize = 1;
+
+const N_ENDPOINTS: usize = 2;
+
+static LANGUAGES: &[u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// Size of a packet of the bulk endpoints.
+const PACKET_SIZE: u32 = 64;
+
+/// Buffer size to give to `MassStorage::new()` for devices with 512 byte
+/// blocks.
+pub const BUFFER_SIZE: usize = 512;
+
+/// Class-specific requests.
+const REQUEST_GET_MAX_LUN: u8 = 0xFE;
+const REQUEST_RESET: u8 = 0xFF;
+
+/// Command Block Wrapper, sent by the host to start a command.
+const CBW_SIGNATURE: u32 = 0x43425355;
+const CBW_LENGTH: u32 = 31;
+/// Command Status Wrapper, sent to the host at the end of a command.
+const CSW_SIGNATURE: u32 = 0x53425355;
+const CSW_LENGTH: usize = 13;
+
+const STATUS_PASSED: u8 = 0;
+const STATUS_FAILED: u8 = 1;
+const STATUS_PHASE_ERROR: u8 = 2;
+
+/// SCSI operation codes.
+const TEST_UNIT_READY: u8 = 0x00;
+const REQUEST_SENSE: u8 = 0x03;
+const INQUIRY: u8 = 0x12;
+const MODE_SENSE_6: u8 = 0x1A;
+const START_STOP_UNIT: u8 = 0x1B;
+const PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1E;
+const READ_FORMAT_CAPACITIES: u8 = 0x23;
+const READ_CAPACITY_10: u8 = 0x25;
+const READ_10: u8 = 0x28;
+const WRITE_10: u8 = 0x2A;
+const VERIFY_10: u8 = 0x2F;
+const SYNCHRONIZE_CACHE_10: u8 = 0x35;
+const MODE_SENSE_10: u8 = 0x5A;
+
+/// Vendor, product and revision returned by INQUIRY.
+const INQUIRY_IDENTIFICATION: &[u8; 28] = b"Tock    Mass Storage    1.0 ";
+
+/// Sense key and additional sense code describing why the last command
+/// failed, returned by REQUEST SENSE.
+#[derive(Clone, Copy)]
+struct Sense(u8, u8);
+
+const NO_SENSE: Sense = Sense(0x00, 0x00);
+const MEDIUM_NOT_PRESENT: Sense = Sense(0x02, 0x3A);
+const WRITE_ERROR: Sense = Sense(0x03, 0x0C);
+const UNRECOVERED_READ_ERROR: Sense = Sense(0x03, 0x11);
+const INVALID_COMMAND: Sense = Sense(0x05, 0x20);
+const LBA_OUT_OF_RANGE: Sense = Sense(0x05, 0x21);
+const INVALID_FIELD_IN_CDB: Sense = Sense(0x05, 0x24);
+const LUN_NOT_SUPPORTED: Sense = Sense(0x05, 0x25);
+const WRITE_PROTECTED: Sense = Sense(0x07, 0x27);
+
+/// Phases of a command.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Phase {
+    /// Waiting for a Command Block Wrapper.
+    Command,
+    /// Checking whether the block device has a medium.
+    Probe,
+    /// Sending data to the host.
+    DataIn,
+    /// Receiving data from the host.
+    DataOut,
+    /// Waiting to send the Command Status Wrapper.
+    Status,
+    /// Waiting for the host to receive the Command Status Wrapper.
+    StatusSent,
+}
+
+/// Data a command transfers, as seen by the device.
+#[derive(Clone, Copy)]
+enum Data {
+    None,
+    In(u32),
+    Out(u32),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum CtrlState {
+    Idle,
+    GetMaxLun,
+}
+
+pub struct MassStorage<'a, U: 'a, B: 'a> {
+    /// Helper USB client library for handling many USB operations.
+    client_ctrl: ClientCtrl<'a, 'static, U>,
+
+    /// 64 byte buffers for each endpoint.
+    buffers: [Buffer64; N_ENDPOINTS],
+
+    /// State of a class-specific control request.
+    ctrl_state: Cell<CtrlState>,
+
+    /// The block device.
+    storage: &'a B,
+    /// Buffer for responses and blocks. It is held by the block device while
+    /// a block is read or written.
+    buffer: TakeCell<'static, [u8]>,
+    /// Whether the host is denied writing to the block device.
+    write_protected: Cell<bool>,
+
+    phase: Cell<Phase>,
+    /// Tag of the current command, echoed in its status.
+    tag: Cell<u32>,
+    /// Number of bytes the host expects to transfer for the current command.
+    host_length: Cell<u32>,
+    /// Whether the host expects data from the device.
+    host_in: Cell<bool>,
+    /// Command Descriptor Block of the current command.
+    cdb: Cell<[u8; 16]>,
+    /// Whether the block device was checked for a medium for this command.
+    probed: Cell<bool>,
+    /// Status of the current command.
+    status: Cell<u8>,
+    /// Reason of the last failure.
+    sense: Cell<Sense>,
+
+    /// Number of bytes of data the device transfers for the current command.
+    data_length: Cell<u32>,
+    /// Number of bytes of data transferred so far.
+    data_done: Cell<u32>,
+    /// Number of bytes transferred so far, including padding and discarded
+    /// bytes.
+    transferred: Cell<u32>,
+    /// Position of the next byte to send or receive in the buffer.
+    buffer_pos: Cell<usize>,
+    /// Number of bytes that can be sent from the buffer, or received into it.
+    buffer_len: Cell<usize>,
+    /// Next block to read or write.
+    block: Cell<u32>,
+
+    /// Size of an OUT packet that arrived while it could not be processed.
+    pending_out: OptionalCell<u32>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, B: BlockStorage<'a>> MassStorage<'a, U, B> {
+    pub fn new(
+        controller: &'a U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        storage: &'a B,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        let interfaces: &mut [InterfaceDescriptor] = &mut [InterfaceDescriptor {
+            interface_number: 0,
+            interface_class: 0x08,    // Mass Storage
+            interface_subclass: 0x06, // SCSI transparent command set
+            interface_protocol: 0x50, // Bulk-Only Transport
+            ..InterfaceDescriptor::default()
+        }];
+
+        let endpoints: &[&[EndpointDescriptor]] = &[&[
+            EndpointDescriptor {
+                endpoint_address: EndpointAddress::new_const(
+                    ENDPOINT_IN_NUM,
+                    TransferDirection::DeviceToHost,
+                ),
+                transfer_type: TransferType::Bulk,
+                max_packet_size: PACKET_SIZE as u16,
+                interval: 0,
+            },
+            EndpointDescriptor {
+                endpoint_address: EndpointAddress::new_const(
+                    ENDPOINT_OUT_NUM,
+                    TransferDirection::HostToDevice,
+                ),
+                transfer_type: TransferType::Bulk,
+                max_packet_size: PACKET_SIZE as u16,
+                interval: 0,
+            },
+        ]];
+
+        let (device_descriptor_buffer, other_descriptor_buffer) =
+            descriptors::create_descriptor_buffers(
+                descriptors::DeviceDescriptor {
+                    vendor_id,
+                    product_id,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    class: 0x00, // Class defined by the interface
+                    max_packet_size_ep0: max_ctrl_packet_size,
+                    ..descriptors::DeviceDescriptor::default()
+                },
+                descriptors::ConfigurationDescriptor {
+                    ..descriptors::ConfigurationDescriptor::default()
+                },
+                interfaces,
+                endpoints,
+                None,
+                None,
+            );
+
+        MassStorage {
+            client_ctrl: ClientCtrl::new(
+                controller,
+                device_descriptor_buffer,
+                other_descriptor_buffer,
+                None,
+                None,
+                LANGUAGES,
+                strings,
+            ),
+            buffers: [Buffer64::default(), Buffer64::default()],
+            ctrl_state: Cell::new(CtrlState::Idle),
+            storage,
+            buffer: TakeCell::new(buffer),
+            write_protected: Cell::new(false),
+            phase: Cell::new(Phase::Command),
+            tag: Cell::new(0),
+            host_length: Cell::new(0),
+            host_in: Cell::new(false),
+            cdb: Cell::new([0; 16]),
+            probed: Cell::new(false),
+            status: Cell::new(STATUS_PASSED),
+            sense: Cell::new(NO_SENSE),
+            data_length: Cell::new(0),
+            data_done: Cell::new(0),
+            transferred: Cell::new(0),
+            buffer_pos: Cell::new(0),
+            buffer_len: Cell::new(0),
+            block: Cell::new(0),
+            pending_out: OptionalCell::empty(),
+        }
+    }
+
+    #[inline]
+    fn controller(&self) -> &'a U {
+        self.client_ctrl.controller()
+    }
+
+    /// Deny or allow the host writing to the block device. The host only
+    /// checks this when the device is attached.
+    pub fn set_write_protected(&self, write_protected: bool) {
+        self.write_protected.set(write_protected);
+    }
+
+    /// Abort the current command, and wait for the next one.
+    fn reset(&self) {
+        self.phase.set(Phase::Command);
+        self.ctrl_state.set(CtrlState::Idle);
+    }
+
+    /// Handle a packet of the bulk OUT endpoint, from the OUT buffer.
+    fn receive(&self, packet_bytes: u32) -> hil::usb::OutResult {
+        // The buffer is missing while the block device uses it.
+        if self.buffer.is_none() {
+            self.pending_out.set(packet_bytes);
+            return hil::usb::OutResult::Delay;
+        }
+        match self.phase.get() {
+            Phase::Command => {
+                self.receive_command(packet_bytes);
+                hil::usb::OutResult::Ok
+            }
+            Phase::DataOut => {
+                self.receive_data(packet_bytes);
+                hil::usb::OutResult::Ok
+            }
+            Phase::Probe | Phase::DataIn | Phase::Status | Phase::StatusSent => {
+                self.pending_out.set(packet_bytes);
+                hil::usb::OutResult::Delay
+            }
+        }
+    }
+
+    /// Process a packet that was delayed, and accept the next one.
+    fn resume_out(&self) {
+        if let Some(packet_bytes) = self.pending_out.take() {
+            match self.receive(packet_bytes) {
+                hil::usb::OutResult::Delay => {}
+                _ => self.controller().endpoint_resume_out(ENDPOINT_OUT_NUM),
+            }
+        }
+    }
+
+    fn receive_command(&self, packet_bytes: u32) {
+        let packet = &self.buffers[OUT_BUFFER].buf;
+        let get_u32 = |i: usize| {
+            u32::from_le_bytes([
+                packet[i].get(),
+                packet[i + 1].get(),
+                packet[i + 2].get(),
+                packet[i + 3].get(),
+            ])
+        };
+        let cdb_length = packet[14].get() as usize;
+        if packet_bytes != CBW_LENGTH
+            || get_u32(0) != CBW_SIGNATURE
+            || cdb_length == 0
+            || cdb_length > 16
+        {
+            return;
+        }
+
+        let mut cdb = [0; 16];
+        for (i, byte) in cdb.iter_mut().enumerate().take(cdb_length) {
+            *byte = packet[15 + i].get();
+        }
+        self.tag.set(get_u32(4));
+        self.host_length.set(get_u32(8));
+        self.host_in.set(packet[12].get() & 0x80 != 0);
+        self.cdb.set(cdb);
+        self.probed.set(false);
+        self.status.set(STATUS_PASSED);
+        self.data_done.set(0);
+        self.transferred.set(0);
+        self.buffer_pos.set(0);
+        self.buffer_len.set(0);
+
+        if packet[13].get() & 0x0F != 0 {
+            self.fail(LUN_NOT_SUPPORTED);
+        } else {
+            self.execute();
+        }
+    }
+
+    /// Execute the current command, and start its data phase. The buffer is
+    /// available.
+    fn execute(&self) {
+        let cdb = self.cdb.get();
+        let get_u16 = |i: usize| u16::from_be_bytes([cdb[i], cdb[i + 1]]) as u32;
+        let get_u32 = |i: usize| u32::from_be_bytes([cdb[i], cdb[i + 1], cdb[i + 2], cdb[i + 3]]);
+        let block_size = self.storage.block_size();
+        let num_blocks = self.storage.num_blocks();
+
+        let needs_medium = matches!(
+            cdb[0],
+            TEST_UNIT_READY
+                | READ_FORMAT_CAPACITIES
+                | READ_CAPACITY_10
+                | READ_10
+                | WRITE_10
+                | VERIFY_10
+        );
+        if needs_medium && num_blocks == 0 {
+            // The block device may find out its size on a first operation.
+            if !self.probed.get() {
+                self.probe();
+            } else {
+                self.fail(MEDIUM_NOT_PRESENT);
+            }
+            return;
+        }
+
+        match cdb[0] {
+            TEST_UNIT_READY
+            | START_STOP_UNIT
+            | PREVENT_ALLOW_MEDIUM_REMOVAL
+            | VERIFY_10
+            | SYNCHRONIZE_CACHE_10 => self.start_data(Data::None),
+            REQUEST_SENSE => {
+                let Sense(key, code) = self.sense.replace(NO_SENSE);
+                self.respond(
+                    &[
+                        0x70, 0, key, 0, 0, 0, 0, 10, 0, 0, 0, 0, code, 0, 0, 0, 0, 0,
+                    ],
+                    cdb[4] as u32,
+                );
+            }
+            INQUIRY => {
+                if cdb[1] & 0x01 != 0 {
+                    // Vital product data pages are not supported.
+                    self.fail(INVALID_FIELD_IN_CDB);
+                    return;
+                }
+                let mut response = [0; 36];
+                // Removable direct access device, SPC-2, 31 additional bytes.
+                response[..5].copy_from_slice(&[0x00, 0x80, 0x04, 0x02, 31]);
+                response[8..].copy_from_slice(INQUIRY_IDENTIFICATION);
+                self.respond(&response, get_u16(3));
+            }
+            MODE_SENSE_6 => {
+                let device_parameter = if self.write_protected.get() { 0x80 } else { 0 };
+                self.respond(&[3, 0, device_parameter, 0], cdb[4] as u32);
+            }
+            MODE_SENSE_10 => {
+                let device_parameter = if self.write_protected.get() { 0x80 } else { 0 };
+                self.respond(&[0, 6, 0, device_parameter, 0, 0, 0, 0], get_u16(7));
+            }
+            READ_FORMAT_CAPACITIES => {
+                let mut response = [0; 12];
+                // One formatted media descriptor.
+                response[3] = 8;
+                response[4..8].copy_from_slice(&num_blocks.to_be_bytes());
+                response[8..].copy_from_slice(&(block_size as u32).to_be_bytes());
+                response[8] = 0x02;
+                self.respond(&response, get_u16(7));
+            }
+            READ_CAPACITY_10 => {
+                let mut response = [0; 8];
+                response[..4].copy_from_slice(&(num_blocks - 1).to_be_bytes());
+                response[4..].copy_from_slice(&(block_size as u32).to_be_bytes());
+                self.respond(&response, 8);
+            }
+            READ_10 | WRITE_10 => {
+                let block = get_u32(2);
+                let count = get_u16(7);
+                if block as u64 + count as u64 > num_blocks as u64 {
+                    self.fail(LBA_OUT_OF_RANGE);
+                } else if cdb[0] == WRITE_10 && self.write_protected.get() {
+                    self.fail(WRITE_PROTECTED);
+                } else {
+                    self.block.set(block);
+                    let length = count * block_size as u32;
+                    if cdb[0] == READ_10 {
+                        self.start_data(Data::In(length));
+                    } else {
+                        self.buffer_len.set(block_size);
+                        self.start_data(Data::Out(length));
+                    }
+                }
+            }
+            _ => self.fail(INVALID_COMMAND),
+        }
+    }
+
+    /// Read the first block, to find out whether the block device has a
+    /// medium.
+    fn probe(&self) {
+        self.probed.set(true);
+        if let Some(buffer) = self.buffer.take() {
+            self.phase.set(Phase::Probe);
+            if let Err((_, buffer)) = self.storage.read_blocks(buffer, 0, 1) {
+                self.buffer.replace(buffer);
+                self.execute();
+            }
+        }
+    }
+
+    /// Send the start of `response`, at most `allocation_length` bytes.
+    fn respond(&self, response: &[u8], allocation_length: u32) {
+        let length = cmp::min(response.len(), allocation_length as usize);
+        self.buffer.map(|buffer| {
+            buffer[..length].copy_from_slice(&response[..length]);
+        });
+        self.buffer_len.set(length);
+        self.start_data(Data::In(length as u32));
+    }
+
+    /// Fail the current command without transferring data.
+    fn fail(&self, sense: Sense) {
+        self.status.set(STATUS_FAILED);
+        self.sense.set(sense);
+        self.start_data(Data::None);
+    }
+
+    /// Start the data phase for the data the device transfers. If the host
+    /// expects less data, or data in the other direction, the device only
+    /// transfers what the host expects and reports a phase error.
+    fn start_data(&self, data: Data) {
+        let host_length = self.host_length.get();
+        let host_in = self.host_in.get();
+        let data_length = match data {
+            Data::None | Data::In(0) | Data::Out(0) => 0,
+            Data::In(length) if host_in && length <= host_length => length,
+            Data::Out(length) if !host_in && length <= host_length => length,
+            Data::In(_) | Data::Out(_) => {
+                self.status.set(STATUS_PHASE_ERROR);
+                0
+            }
+        };
+
+        self.data_length.set(data_length);
+        if host_length == 0 {
+            self.phase.set(Phase::Status);
+        } else if host_in {
+            self.phase.set(Phase::DataIn);
+        } else {
+            self.phase.set(Phase::DataOut);
+        }
+
+        if self.phase.get() == Phase::DataOut {
+            self.resume_out();
+        } else {
+            self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+        }
+    }
+
+    /// Stop transferring data after a failure of the block device. The rest
+    /// of the data the host expects is padded or discarded.
+    fn fail_data(&self, sense: Sense) {
+        self.status.set(STATUS_FAILED);
+        self.sense.set(sense);
+        self.data_length.set(self.data_done.get());
+    }
+
+    /// Fill the IN buffer with the next packet of the data phase.
+    fn send_data(&self) -> hil::usb::InResult {
+        let length =
+            cmp::min(PACKET_SIZE, self.host_length.get() - self.transferred.get()) as usize;
+
+        // Read the next block once the buffer was sent.
+        if self.data_done.get() < self.data_length.get()
+            && self.buffer_pos.get() == self.buffer_len.get()
+        {
+            if self.buffer.is_some() {
+                self.read_block();
+            }
+            if self.data_done.get() < self.data_length.get() {
+                return hil::usb::InResult::Delay;
+            }
+        }
+
+        let packet = &self.buffers[IN_BUFFER].buf;
+        let data_length = cmp::min(
+            length,
+            (self.data_length.get() - self.data_done.get()) as usize,
+        );
+        let pos = self.buffer_pos.get();
+        self.buffer.map(|buffer| {
+            for i in 0..data_length {
+                packet[i].set(buffer[pos + i]);
+            }
+        });
+        for byte in packet[data_length..length].iter() {
+            byte.set(0);
+        }
+        self.buffer_pos.set(pos + data_length);
+        self.data_done
+            .set(self.data_done.get() + data_length as u32);
+        self.transferred.set(self.transferred.get() + length as u32);
+        hil::usb::InResult::Packet(length)
+    }
+
+    fn read_block(&self) {
+        if let Some(buffer) = self.buffer.take() {
+            if let Err((rcode, buffer)) = self.storage.read_blocks(buffer, self.block.get(), 1) {
+                self.buffer.replace(buffer);
+                self.fail_data(match rcode {
+                    ReturnCode::EUNINSTALLED => MEDIUM_NOT_PRESENT,
+                    _ => UNRECOVERED_READ_ERROR,
+                });
+            }
+        }
+    }
+
+    /// Copy a packet of the data phase from the OUT buffer.
+    fn receive_data(&self, packet_bytes: u32) {
+        let packet = &self.buffers[OUT_BUFFER].buf;
+        let length = cmp::min(packet_bytes, self.data_length.get() - self.data_done.get()) as usize;
+        let pos = self.buffer_pos.get();
+        let length = cmp::min(length, self.buffer_len.get() - pos);
+        self.buffer.map(|buffer| {
+            for i in 0..length {
+                buffer[pos + i] = packet[i].get();
+            }
+        });
+        self.buffer_pos.set(pos + length);
+        self.data_done.set(self.data_done.get() + length as u32);
+        self.transferred.set(self.transferred.get() + packet_bytes);
+
+        if length > 0 && self.buffer_pos.get() == self.buffer_len.get() {
+            self.write_block();
+        }
+        self.finish_data_out();
+    }
+
+    fn write_block(&self) {
+        if let Some(buffer) = self.buffer.take() {
+            if let Err((rcode, buffer)) = self.storage.write_blocks(buffer, self.block.get(), 1) {
+                self.buffer.replace(buffer);
+                self.write_failed(rcode);
+            }
+        }
+    }
+
+    fn write_failed(&self, rcode: ReturnCode) {
+        // The data of the block that failed was not processed.
+        self.data_done
+            .set(self.data_done.get() - self.buffer_pos.get() as u32);
+        self.fail_data(match rcode {
+            ReturnCode::EUNINSTALLED => MEDIUM_NOT_PRESENT,
+            _ => WRITE_ERROR,
+        });
+    }
+
+    /// Send the status once all data was received and written.
+    fn finish_data_out(&self) {
+        if self.transferred.get() >= self.host_length.get() && self.buffer.is_some() {
+            self.phase.set(Phase::Status);
+            self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+        }
+    }
+
+    /// Fill the IN buffer with the Command Status Wrapper.
+    fn send_status(&self) -> hil::usb::InResult {
+        let residue = self.host_length.get() - self.data_done.get();
+        let mut csw = [0; CSW_LENGTH];
+        csw[0..4].copy_from_slice(&CSW_SIGNATURE.to_le_bytes());
+        csw[4..8].copy_from_slice(&self.tag.get().to_le_bytes());
+        csw[8..12].copy_from_slice(&residue.to_le_bytes());
+        csw[12] = self.status.get();
+
+        let packet = &self.buffers[IN_BUFFER].buf;
+        for (byte, value) in packet.iter().zip(csw.iter()) {
+            byte.set(*value);
+        }
+        self.phase.set(Phase::StatusSent);
+        hil::usb::InResult::Packet(CSW_LENGTH)
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, B: BlockStorage<'a>> hil::usb::Client<'a>
+    for MassStorage<'a, U, B>
+{
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+
+        // Setup buffers for IN and OUT data transfer.
+        self.controller()
+            .endpoint_set_in_buffer(ENDPOINT_IN_NUM, &self.buffers[IN_BUFFER].buf);
+        self.controller()
+            .endpoint_in_enable(TransferType::Bulk, ENDPOINT_IN_NUM);
+
+        self.controller()
+            .endpoint_set_out_buffer(ENDPOINT_OUT_NUM, &self.buffers[OUT_BUFFER].buf);
+        self.controller()
+            .endpoint_out_enable(TransferType::Bulk, ENDPOINT_OUT_NUM);
+    }
+
+    fn attach(&'a self) {
+        self.client_ctrl.attach();
+    }
+
+    fn bus_reset(&'a self) {
+        // The endpoints are reset as well, so delayed packets are lost.
+        self.pending_out.clear();
+        self.reset();
+    }
+
+    /// Handle a Control Setup transaction.
+    ///
+    /// Mass storage uses class-specific requests to find out the number of
+    /// logical units, and to reset the device after an error.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        if let Some(setup_data) = descriptors::SetupData::get(&self.client_ctrl.ctrl_buffer.buf) {
+            let request_type = setup_data.request_type;
+            if matches!(request_type.request_type(), RequestType::Class)
+                && matches!(request_type.recipient(), Recipient::Interface)
+            {
+                match (setup_data.request_code, request_type.transfer_direction()) {
+                    (REQUEST_GET_MAX_LUN, TransferDirection::DeviceToHost) => {
+                        self.ctrl_state.set(CtrlState::GetMaxLun);
+                        return hil::usb::CtrlSetupResult::Ok;
+                    }
+                    (REQUEST_RESET, TransferDirection::HostToDevice) => {
+                        self.reset();
+                        // Drop a delayed command, and accept the next one.
+                        if self.pending_out.take().is_some() {
+                            self.controller().endpoint_resume_out(ENDPOINT_OUT_NUM);
+                        }
+                        return hil::usb::CtrlSetupResult::Ok;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        self.client_ctrl.ctrl_setup(endpoint)
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        if self.ctrl_state.get() == CtrlState::GetMaxLun {
+            // A single logical unit, number 0.
+            self.client_ctrl.ctrl_buffer.buf[0].set(0);
+            return hil::usb::CtrlInResult::Packet(1, true);
+        }
+
+        self.client_ctrl.ctrl_in(endpoint)
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        self.ctrl_state.set(CtrlState::Idle);
+        self.client_ctrl.ctrl_status_complete(endpoint)
+    }
+
+    /// Handle a Bulk IN transaction.
+    fn packet_in(&'a self, transfer_type: TransferType, _endpoint: usize) -> hil::usb::InResult {
+        match transfer_type {
+            TransferType::Bulk => match self.phase.get() {
+                Phase::DataIn if self.transferred.get() < self.host_length.get() => {
+                    self.send_data()
+                }
+                Phase::DataIn | Phase::Status => self.send_status(),
+                Phase::Command | Phase::Probe | Phase::DataOut | Phase::StatusSent => {
+                    hil::usb::InResult::Delay
+                }
+            },
+            TransferType::Control | TransferType::Interrupt | TransferType::Isochronous => {
+                panic!("Transfer protocol not supported by USB MSC");
+            }
+        }
+    }
+
+    /// Handle a Bulk OUT transaction
+    fn packet_out(
+        &'a self,
+        transfer_type: TransferType,
+        _endpoint: usize,
+        packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        match transfer_type {
+            TransferType::Bulk => self.receive(packet_bytes),
+            TransferType::Control | TransferType::Interrupt | TransferType::Isochronous => {
+                panic!("Transfer protocol not supported by USB MSC");
+            }
+        }
+    }
+
+    fn packet_transmitted(&'a self, _endpoint: usize) {
+        match self.phase.get() {
+            Phase::DataIn | Phase::Status => {
+                self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+            }
+            Phase::StatusSent => {
+                self.phase.set(Phase::Command);
+                self.resume_out();
+            }
+            Phase::Command | Phase::Probe | Phase::DataOut => {}
+        }
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, B: BlockStorage<'a>> BlockStorageClient
+    for MassStorage<'a, U, B>
+{
+    fn read_done(&self, result: ReturnCode, buffer: &'static mut [u8]) {
+        self.buffer.replace(buffer);
+        match self.phase.get() {
+            Phase::Probe => self.execute(),
+            Phase::DataIn => {
+                if result == ReturnCode::SUCCESS {
+                    self.buffer_pos.set(0);
+                    self.buffer_len.set(self.storage.block_size());
+                    self.block.set(self.block.get() + 1);
+                } else {
+                    self.fail_data(UNRECOVERED_READ_ERROR);
+                }
+                self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+            }
+            // The command was aborted by a reset.
+            _ => self.resume_out(),
+        }
+    }
+
+    fn write_done(&self, result: ReturnCode, buffer: &'static mut [u8]) {
+        self.buffer.replace(buffer);
+        if self.phase.get() == Phase::DataOut {
+            if result == ReturnCode::SUCCESS {
+                self.block.set(self.block.get() + 1);
+            } else {
+                self.write_failed(result);
+            }
+            self.buffer_pos.set(0);
+            self.finish_data_out();
+        }
+        self.resume_out();
+    }
+}
@@ -5,7 +5,7 @@ use core::cell::Cell;
 use core::marker::PhantomData;
 use kernel::common::cells::OptionalCell;
 use kernel::common::leasable_buffer::LeasableBuffer;
-use kernel::common::{ListLink, ListNode};
+use kernel::common::{List, ListLink, ListNode};
 use kernel::hil::digest;
 use kernel::hil::digest::DigestType;
 use kernel::ReturnCode;
@@ -37,6 +37,31 @@ impl<'a, A: digest::Digest<'a, T>, T: DigestType> VirtualMuxDigest<'a, A, T> {
             id: id,
         }
     }
+
+    /// Register this user with the mux, so that it receives the callbacks
+    /// of the underlying device while it is the running user. Must be called
+    /// once, right after `static_init!()`.
+    pub fn setup(&'a self) {
+        self.mux.users.push_head(self);
+    }
+
+    /// Run `set_mode` on the underlying device, making this the running user
+    /// if no user is running.
+    fn set_mode<F>(&self, set_mode: F) -> Result<(), ReturnCode>
+    where
+        F: FnOnce(&A) -> Result<(), ReturnCode>,
+    {
+        // Check if any mux is enabled. If it isn't we enable it for us.
+        if self.mux.running.get() == false {
+            self.mux.running.set(true);
+            self.mux.running_id.set(self.id);
+            set_mode(self.mux.digest)
+        } else if self.mux.running_id.get() == self.id {
+            set_mode(self.mux.digest)
+        } else {
+            Err(ReturnCode::EBUSY)
+        }
+    }
 }
 
 impl<'a, A: digest::Digest<'a, T>, T: DigestType> digest::Digest<'a, T>
@@ -107,20 +132,75 @@ impl<'a, A: digest::Digest<'a, T>, T: DigestType> digest::Client<'a, T>
     }
 }
 
+impl<'a, A: digest::Digest<'a, T> + digest::Sha1, T: DigestType> digest::Sha1
+    for VirtualMuxDigest<'a, A, T>
+{
+    fn set_mode_sha1(&self) -> Result<(), ReturnCode> {
+        self.set_mode(|digest| digest.set_mode_sha1())
+    }
+}
+
+impl<'a, A: digest::Digest<'a, T> + digest::Sha224, T: DigestType> digest::Sha224
+    for VirtualMuxDigest<'a, A, T>
+{
+    fn set_mode_sha224(&self) -> Result<(), ReturnCode> {
+        self.set_mode(|digest| digest.set_mode_sha224())
+    }
+}
+
+impl<'a, A: digest::Digest<'a, T> + digest::Sha256, T: DigestType> digest::Sha256
+    for VirtualMuxDigest<'a, A, T>
+{
+    fn set_mode_sha256(&self) -> Result<(), ReturnCode> {
+        self.set_mode(|digest| digest.set_mode_sha256())
+    }
+}
+
+impl<'a, A: digest::Digest<'a, T> + digest::Sha384, T: DigestType> digest::Sha384
+    for VirtualMuxDigest<'a, A, T>
+{
+    fn set_mode_sha384(&self) -> Result<(), ReturnCode> {
+        self.set_mode(|digest| digest.set_mode_sha384())
+    }
+}
+
+impl<'a, A: digest::Digest<'a, T> + digest::Sha512, T: DigestType> digest::Sha512
+    for VirtualMuxDigest<'a, A, T>
+{
+    fn set_mode_sha512(&self) -> Result<(), ReturnCode> {
+        self.set_mode(|digest| digest.set_mode_sha512())
+    }
+}
+
+impl<'a, A: digest::Digest<'a, T> + digest::HMACSha1, T: DigestType> digest::HMACSha1
+    for VirtualMuxDigest<'a, A, T>
+{
+    fn set_mode_hmacsha1(&self, key: &[u8]) -> Result<(), ReturnCode> {
+        self.set_mode(|digest| digest.set_mode_hmacsha1(key))
+    }
+}
+
 impl<'a, A: digest::Digest<'a, T> + digest::HMACSha256, T: DigestType> digest::HMACSha256
     for VirtualMuxDigest<'a, A, T>
 {
-    fn set_mode_hmacsha256(&self, key: &[u8; 32]) -> Result<(), ReturnCode> {
-        // Check if any mux is enabled. If it isn't we enable it for us.
-        if self.mux.running.get() == false {
-            self.mux.running.set(true);
-            self.mux.running_id.set(self.id);
-            self.mux.digest.set_mode_hmacsha256(key)
-        } else if self.mux.running_id.get() == self.id {
-            self.mux.digest.set_mode_hmacsha256(key)
-        } else {
-            Err(ReturnCode::EBUSY)
-        }
+    fn set_mode_hmacsha256(&self, key: &[u8]) -> Result<(), ReturnCode> {
+        self.set_mode(|digest| digest.set_mode_hmacsha256(key))
+    }
+}
+
+impl<'a, A: digest::Digest<'a, T> + digest::HMACSha384, T: DigestType> digest::HMACSha384
+    for VirtualMuxDigest<'a, A, T>
+{
+    fn set_mode_hmacsha384(&self, key: &[u8]) -> Result<(), ReturnCode> {
+        self.set_mode(|digest| digest.set_mode_hmacsha384(key))
+    }
+}
+
+impl<'a, A: digest::Digest<'a, T> + digest::HMACSha512, T: DigestType> digest::HMACSha512
+    for VirtualMuxDigest<'a, A, T>
+{
+    fn set_mode_hmacsha512(&self, key: &[u8]) -> Result<(), ReturnCode> {
+        self.set_mode(|digest| digest.set_mode_hmacsha512(key))
     }
 }
 
@@ -128,8 +208,12 @@ impl<'a, A: digest::Digest<'a, T> + digest::HMACSha256, T: DigestType> digest::H
 /// `VirtualMuxDigest` as the one that has been enabled and running. Until that
 /// Mux calls `clear_data()` it will be the only `VirtualMuxDigest` that can
 /// interact with the underlying device.
+///
+/// The `MuxDigest` must be set as the client of the underlying device, and
+/// passes its callbacks on to the running `VirtualMuxDigest`.
 pub struct MuxDigest<'a, A: digest::Digest<'a, T>, T: DigestType> {
     digest: &'a A,
+    users: List<'a, VirtualMuxDigest<'a, A, T>>,
     running: Cell<bool>,
     running_id: Cell<u32>,
     next_id: Cell<u32>,
@@ -140,6 +224,7 @@ impl<'a, A: digest::Digest<'a, T>, T: DigestType> MuxDigest<'a, A, T> {
     pub const fn new(digest: &'a A) -> MuxDigest<'a, A, T> {
         MuxDigest {
             digest: digest,
+            users: List::new(),
             running: Cell::new(false),
             running_id: Cell::new(0),
             next_id: Cell::new(0),
@@ -147,3 +232,19 @@ impl<'a, A: digest::Digest<'a, T>, T: DigestType> MuxDigest<'a, A, T> {
         }
     }
 }
+
+impl<'a, A: digest::Digest<'a, T>, T: DigestType> digest::Client<'a, T> for MuxDigest<'a, A, T> {
+    fn add_data_done(&'a self, result: Result<(), ReturnCode>, data: &'static mut [u8]) {
+        let running_id = self.running_id.get();
+        if let Some(user) = self.users.iter().find(|user| user.id == running_id) {
+            user.add_data_done(result, data);
+        }
+    }
+
+    fn hash_done(&'a self, result: Result<(), ReturnCode>, digest: &'static mut T) {
+        let running_id = self.running_id.get();
+        if let Some(user) = self.users.iter().find(|user| user.id == running_id) {
+            user.hash_done(result, digest);
+        }
+    }
+}
@@ -23,6 +23,7 @@ pub struct E310x<'a, A: 'static + Alarm<'static>, I: InterruptService<()> + 'a>
     scheduler_timer: kernel::VirtualSchedulerTimer<A>,
     timer: &'a rv32i::machine_timer::MachineTimer<'a>,
     plic_interrupt_service: &'a I,
+    watchdog: sifive::watchdog::Watchdog,
 }
 
 pub struct E310xDefaultPeripherals<'a> {
@@ -83,9 +84,15 @@ impl<'a, A: 'static + Alarm<'static>, I: InterruptService<()> + 'a> E310x<'a, A,
             scheduler_timer: kernel::VirtualSchedulerTimer::new(alarm),
             timer,
             plic_interrupt_service,
+            watchdog: sifive::watchdog::Watchdog::new(crate::watchdog::WATCHDOG_BASE),
         }
     }
 
+    /// Start the hardware watchdog when the kernel enters its main loop.
+    pub fn enable_watchdog(&self) {
+        self.watchdog.enable();
+    }
+
     pub unsafe fn enable_plic_interrupts(&self) {
         self.plic.disable_all();
         self.plic.clear_all_pending();
@@ -110,7 +117,7 @@ impl<'a, A: 'static + Alarm<'static>, I: InterruptService<()> + 'a> kernel::Chip
     type MPU = PMP;
     type UserspaceKernelBoundary = rv32i::syscall::SysCall;
     type SchedulerTimer = kernel::VirtualSchedulerTimer<A>;
-    type WatchDog = ();
+    type WatchDog = sifive::watchdog::Watchdog;
 
     fn mpu(&self) -> &Self::MPU {
         &self.pmp
@@ -121,7 +128,7 @@ impl<'a, A: 'static + Alarm<'static>, I: InterruptService<()> + 'a> kernel::Chip
     }
 
     fn watchdog(&self) -> &Self::WatchDog {
-        &()
+        &self.watchdog
     }
 
     fn userspace_kernel_boundary(&self) -> &rv32i::syscall::SysCall {
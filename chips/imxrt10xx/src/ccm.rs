@@ -425,6 +425,19 @@ impl Ccm {
         self.registers.ccgr3.modify(CCGR3::CG6::CLEAR)
     }
 
+    /// WDOG1 clock
+    pub fn is_enabled_wdog1_clock(&self) -> bool {
+        self.registers.ccgr3.is_set(CCGR3::CG8)
+    }
+
+    pub fn enable_wdog1_clock(&self) {
+        self.registers.ccgr3.modify(CCGR3::CG8.val(0b11 as u32))
+    }
+
+    pub fn disable_wdog1_clock(&self) {
+        self.registers.ccgr3.modify(CCGR3::CG8::CLEAR)
+    }
+
     /// GPIO5 clock
     pub fn is_enabled_gpio5_clock(&self) -> bool {
         self.registers.ccgr1.is_set(CCGR1::CG15)
@@ -664,6 +677,7 @@ pub enum HCLK2 {
 
 pub enum HCLK3 {
     GPIO4,
+    WDOG1,
     // and others ...
 }
 
@@ -706,6 +720,7 @@ impl ClockInterface for PeripheralClock<'_> {
             },
             ClockGate::CCGR3(ref v) => match v {
                 HCLK3::GPIO4 => self.ccm.is_enabled_gpio4_clock(),
+                HCLK3::WDOG1 => self.ccm.is_enabled_wdog1_clock(),
             },
             ClockGate::CCGR4(ref v) => match v {
                 HCLK4::IOMUXC => self.ccm.is_enabled_iomuxc_clock(),
@@ -735,6 +750,7 @@ impl ClockInterface for PeripheralClock<'_> {
             },
             ClockGate::CCGR3(ref v) => match v {
                 HCLK3::GPIO4 => self.ccm.enable_gpio4_clock(),
+                HCLK3::WDOG1 => self.ccm.enable_wdog1_clock(),
             },
             ClockGate::CCGR4(ref v) => match v {
                 HCLK4::IOMUXC => self.ccm.enable_iomuxc_clock(),
@@ -764,6 +780,7 @@ impl ClockInterface for PeripheralClock<'_> {
             },
             ClockGate::CCGR3(ref v) => match v {
                 HCLK3::GPIO4 => self.ccm.disable_gpio4_clock(),
+                HCLK3::WDOG1 => self.ccm.disable_wdog1_clock(),
             },
             ClockGate::CCGR4(ref v) => match v {
                 HCLK4::IOMUXC => self.ccm.disable_iomuxc_clock(),
@@ -12,17 +12,25 @@ pub struct Imxrt10xx<I: InterruptService<()> + 'static> {
     userspace_kernel_boundary: cortexm7::syscall::SysCall,
     scheduler_timer: cortexm7::systick::SysTick,
     interrupt_service: &'static I,
+    watchdog: crate::wdog::Wdog<'static>,
 }
 
 impl<I: InterruptService<()> + 'static> Imxrt10xx<I> {
-    pub unsafe fn new(interrupt_service: &'static I) -> Self {
+    pub unsafe fn new(ccm: &'static crate::ccm::Ccm, interrupt_service: &'static I) -> Self {
         Imxrt10xx {
             mpu: cortexm7::mpu::MPU::new(),
             userspace_kernel_boundary: cortexm7::syscall::SysCall::new(),
             scheduler_timer: cortexm7::systick::SysTick::new_with_calibration(792_000_000),
             interrupt_service,
+            watchdog: crate::wdog::Wdog::new_wdog1(ccm),
         }
     }
+
+    /// Start WDOG1 when the kernel enters its main loop. Once started it
+    /// cannot be stopped until the chip resets.
+    pub fn enable_watchdog(&self) {
+        self.watchdog.enable();
+    }
 }
 
 pub struct Imxrt10xxDefaultPeripherals {
@@ -88,7 +96,7 @@ impl<I: InterruptService<()> + 'static> Chip for Imxrt10xx<I> {
     type MPU = cortexm7::mpu::MPU;
     type UserspaceKernelBoundary = cortexm7::syscall::SysCall;
     type SchedulerTimer = cortexm7::systick::SysTick;
-    type WatchDog = ();
+    type WatchDog = crate::wdog::Wdog<'static>;
 
     fn service_pending_interrupts(&self) {
         unsafe {
@@ -119,7 +127,7 @@ impl<I: InterruptService<()> + 'static> Chip for Imxrt10xx<I> {
     }
 
     fn watchdog(&self) -> &Self::WatchDog {
-        &()
+        &self.watchdog
     }
 
     fn userspace_kernel_boundary(&self) -> &cortexm7::syscall::SysCall {
@@ -19,6 +19,7 @@ pub mod iomuxc;
 pub mod iomuxc_snvs;
 pub mod lpi2c;
 pub mod lpuart;
+pub mod wdog;
 
 use cortexm7::{
     generic_isr, hard_fault_handler, svc_handler, systick_handler, unhandled_interrupt,
@@ -0,0 +1,145 @@
+//! Watchdog timer (WDOG1)
+//!
+//! WDOG1 counts down on the 32 kHz low frequency clock and resets the chip
+//! when it reaches zero. Once enabled it cannot be disabled until the next
+//! reset, so it is only started if the board called
+//! `Imxrt10xx::enable_watchdog()`. It is configured to pause in the wait and
+//! stop low power modes, so the kernel does not need to wake up to service
+//! it.
+
+use core::cell::Cell;
+use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite};
+use kernel::common::StaticRef;
+use kernel::ClockInterface;
+
+use crate::ccm;
+
+#[repr(C)]
+struct WdogRegisters {
+    /// Watchdog Control Register
+    wcr: ReadWrite<u16, WCR::Register>,
+    /// Watchdog Service Register
+    wsr: ReadWrite<u16>,
+    /// Watchdog Reset Status Register
+    wrsr: ReadOnly<u16, WRSR::Register>,
+    /// Watchdog Interrupt Control Register
+    wicr: ReadWrite<u16>,
+    /// Watchdog Miscellaneous Control Register
+    wmcr: ReadWrite<u16, WMCR::Register>,
+}
+
+register_bitfields![u16,
+    WCR [
+        /// Watchdog time-out field, in steps of 0.5 s
+        WT OFFSET(8) NUMBITS(8) [],
+        /// Suspend the watchdog in wait mode (write once)
+        WDW OFFSET(7) NUMBITS(1) [],
+        /// Software reset extension
+        SRE OFFSET(6) NUMBITS(1) [],
+        /// WDOG_B assertion, active low
+        WDA OFFSET(5) NUMBITS(1) [],
+        /// Software reset signal, active low
+        SRS OFFSET(4) NUMBITS(1) [],
+        /// Assert WDOG_B on a time-out
+        WDT OFFSET(3) NUMBITS(1) [],
+        /// Watchdog enable (write once)
+        WDE OFFSET(2) NUMBITS(1) [],
+        /// Suspend the watchdog in debug mode (write once)
+        WDBG OFFSET(1) NUMBITS(1) [],
+        /// Suspend the watchdog in stop and doze modes (write once)
+        WDZST OFFSET(0) NUMBITS(1) []
+    ],
+    WRSR [
+        /// Reset due to a power on reset
+        POR OFFSET(4) NUMBITS(1) [],
+        /// Reset due to a watchdog time-out
+        TOUT OFFSET(1) NUMBITS(1) [],
+        /// Reset due to a software reset
+        SFTW OFFSET(0) NUMBITS(1) []
+    ],
+    WMCR [
+        /// Power down counter enable
+        PDE OFFSET(0) NUMBITS(1) []
+    ]
+];
+
+const WDOG1_BASE: StaticRef<WdogRegisters> =
+    unsafe { StaticRef::new(0x400B8000 as *const WdogRegisters) };
+
+/// Time-out of the watchdog: (1 + 1) * 0.5 s = 1 s.
+const TIMEOUT: u16 = 1;
+
+pub struct Wdog<'a> {
+    registers: StaticRef<WdogRegisters>,
+    clock: WdogClock<'a>,
+    enabled: Cell<bool>,
+}
+
+impl<'a> Wdog<'a> {
+    pub const fn new_wdog1(ccm: &'a ccm::Ccm) -> Self {
+        Wdog {
+            registers: WDOG1_BASE,
+            clock: WdogClock(ccm::PeripheralClock::ccgr3(ccm, ccm::HCLK3::WDOG1)),
+            enabled: Cell::new(false),
+        }
+    }
+
+    /// Have the kernel start the watchdog when it enters its main loop.
+    pub fn enable(&self) {
+        self.enabled.set(true);
+    }
+
+    fn start(&self) {
+        self.clock.enable();
+        // Without this, the power down counter asserts WDOG_B 16 s after
+        // reset.
+        self.registers.wmcr.write(WMCR::PDE::CLEAR);
+        // The suspend bits and WDE can only be written once, so they are
+        // all set at once. SRS and WDA are active low and must stay set.
+        self.registers.wcr.write(
+            WCR::WT.val(TIMEOUT)
+                + WCR::WDW::SET
+                + WCR::WDA::SET
+                + WCR::SRS::SET
+                + WCR::WDE::SET
+                + WCR::WDBG::SET
+                + WCR::WDZST::SET,
+        );
+        self.service();
+    }
+
+    fn service(&self) {
+        self.registers.wsr.set(0x5555);
+        self.registers.wsr.set(0xAAAA);
+    }
+}
+
+struct WdogClock<'a>(ccm::PeripheralClock<'a>);
+
+impl ClockInterface for WdogClock<'_> {
+    fn is_enabled(&self) -> bool {
+        self.0.is_enabled()
+    }
+
+    fn enable(&self) {
+        self.0.enable();
+    }
+
+    fn disable(&self) {
+        self.0.disable();
+    }
+}
+
+impl kernel::watchdog::WatchDog for Wdog<'_> {
+    fn setup(&self) {
+        if self.enabled.get() {
+            self.start();
+        }
+    }
+
+    fn tickle(&self) {
+        if self.enabled.get() {
+            self.service();
+        }
+    }
+}
@@ -11,6 +11,7 @@ use core::slice;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::common::StaticRef;
 use kernel::debug;
+use kernel::hil::ethernet::{EthernetAdapter, EthernetAdapterClient};
 use kernel::ReturnCode;
 
 // Both events have the same index since they are located on different
@@ -80,11 +81,6 @@ impl<R: LiteXSoCRegisterConfiguration> LiteEthMacRegisters<R> {
     }
 }
 
-pub trait LiteEthClient {
-    fn tx_done(&self, rc: ReturnCode, packet_buffer: &'static mut [u8]);
-    fn rx_packet(&self, packet: &'static mut [u8], len: usize);
-}
-
 pub struct LiteEth<'a, R: LiteXSoCRegisterConfiguration> {
     mac_regs: StaticRef<LiteEthMacRegisters<R>>,
     mac_memory_base: usize,
@@ -92,9 +88,9 @@ pub struct LiteEth<'a, R: LiteXSoCRegisterConfiguration> {
     slot_size: usize,
     rx_slots: usize,
     tx_slots: usize,
-    client: OptionalCell<&'a dyn LiteEthClient>,
+    client: OptionalCell<&'a dyn EthernetAdapterClient>,
     tx_packet: TakeCell<'static, [u8]>,
-    rx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
     initialized: Cell<bool>,
 }
 
@@ -106,7 +102,6 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
         slot_size: usize,
         rx_slots: usize,
         tx_slots: usize,
-        rx_buffer: &'static mut [u8],
     ) -> LiteEth<'a, R> {
         LiteEth {
             mac_regs,
@@ -117,15 +112,11 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
             tx_slots,
             client: OptionalCell::empty(),
             tx_packet: TakeCell::empty(),
-            rx_buffer: TakeCell::new(rx_buffer),
+            tx_len: Cell::new(0),
             initialized: Cell::new(false),
         }
     }
 
-    pub fn set_client(&self, client: &'a dyn LiteEthClient) {
-        self.client.set(client);
-    }
-
     pub fn initialize(&self) {
         // Sanity check the memory parameters
         //
@@ -173,63 +164,66 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
         ))
     }
 
-    pub fn return_rx_buffer(&self, rx_buffer: &'static mut [u8]) {
-        // Assert that we won't overwrite a buffer
-        assert!(
-            self.rx_buffer.is_none(),
-            "LiteEth: return RX buffer while one is registered"
-        );
+    fn rx_interrupt(&self) {
+        // Get the frame length. If it exceeds the size of a slot,
+        // discard the packet
+        let pkt_len = self.mac_regs.rx_length.get() as usize;
+        if pkt_len > self.slot_size {
+            debug!("LiteEth: discarding ethernet packet with len {}", pkt_len);
+        } else {
+            // Obtain the packet slot id
+            let slot_id: usize = self.mac_regs.rx_slot.get().into();
 
-        // Put the buffer back
-        self.rx_buffer.replace(rx_buffer);
+            // Get the slot buffer reference
+            let slot = unsafe {
+                self.get_slot_buffer(false, slot_id)
+                    .expect("LiteEth: invalid RX slot id")
+            };
 
-        // In case we received a packet RX interrupt but couldn't
-        // handle it due to the missing buffer, reenable RX interrupts
-        self.mac_regs.rx_ev().enable_event(LITEETH_RX_EVENT);
+            // The client reads the packet directly from the slot
+            self.client.map(|client| client.rx_packet(&slot[..pkt_len]));
+        }
+
+        // Acknowledge the interrupt so that the HW may use the slot again
+        self.mac_regs.rx_ev().clear_event(LITEETH_RX_EVENT);
     }
 
-    fn rx_interrupt(&self) {
-        // Check whether we have a buffer to read the packet into. If
-        // not, we must disable, but not clear the event and enable it
-        // again as soon as we get the buffer back from the client
-        if self.rx_buffer.is_none() {
-            self.mac_regs.rx_ev().disable_event(LITEETH_RX_EVENT);
-        } else {
-            // Get the buffer first to be able to check the length
-            let rx_buffer = self.rx_buffer.take().unwrap();
-
-            // Get the frame length. If it exceeds the length of the
-            // rx_buffer, discard the packet, put the buffer back
-            let pkt_len = self.mac_regs.rx_length.get() as usize;
-            if pkt_len > rx_buffer.len() {
-                debug!("LiteEth: discarding ethernet packet with len {}", pkt_len);
-
-                // Acknowledge the interrupt so that the HW may use the slot again
-                self.mac_regs.rx_ev().clear_event(LITEETH_RX_EVENT);
-
-                // Replace the buffer
-                self.rx_buffer.replace(rx_buffer);
-            } else {
-                // Obtain the packet slot id
-                let slot_id: usize = self.mac_regs.rx_slot.get().into();
-
-                // Get the slot buffer reference
-                let slot = unsafe {
-                    self.get_slot_buffer(false, slot_id)
-                        .expect("LiteEth: invalid RX slot id")
-                };
-
-                // Copy the packet into the buffer
-                rx_buffer[..pkt_len].copy_from_slice(&slot[..pkt_len]);
-
-                // Since all data is copied, acknowledge the interrupt
-                // so that the slot is ready for use again
-                self.mac_regs.rx_ev().clear_event(LITEETH_RX_EVENT);
-
-                self.client
-                    .map(move |client| client.rx_packet(rx_buffer, pkt_len));
-            }
+    fn tx_interrupt(&self) {
+        // Deassert the interrupt, but can be left enabled
+        self.mac_regs.tx_ev().clear_event(LITEETH_TX_EVENT);
+
+        if self.tx_packet.is_none() {
+            debug!("LiteEth: tx interrupt called without tx_packet set");
+        }
+
+        // We use only one slot, so this event is unambiguous
+        let packet = self
+            .tx_packet
+            .take()
+            .expect("LiteEth: TakeCell empty in tx callback");
+        let len = self.tx_len.get();
+        self.client
+            .map(move |client| client.tx_done(ReturnCode::SUCCESS, packet, len));
+    }
+
+    pub fn service_interrupt(&self) {
+        // The interrupt could've been generated by both a packet
+        // being received or finished transmitting. Check and handle
+        // both cases
+
+        if self.mac_regs.rx_ev().event_asserted(LITEETH_RX_EVENT) {
+            self.rx_interrupt();
         }
+
+        if self.mac_regs.tx_ev().event_asserted(LITEETH_TX_EVENT) {
+            self.tx_interrupt();
+        }
+    }
+}
+
+impl<'a, R: LiteXSoCRegisterConfiguration> EthernetAdapter<'a> for LiteEth<'a, R> {
+    fn set_client(&self, client: &'a dyn EthernetAdapterClient) {
+        self.client.set(client);
     }
 
     /// Transmit an ethernet packet over the interface
@@ -237,7 +231,7 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
     /// For now this will only use a single slot on the interface and
     /// is therefore blocking. A client must wait until a callback to
     /// `tx_done` prior to sending a new packet.
-    pub fn transmit(
+    fn transmit(
         &self,
         packet: &'static mut [u8],
         len: usize,
@@ -261,6 +255,7 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
         // Put the currently transmitting packet into the designated
         // TakeCell
         self.tx_packet.replace(packet);
+        self.tx_len.set(len);
 
         // Set the slot and packet length
         self.mac_regs.tx_slot.set(0);
@@ -277,35 +272,4 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
 
         Ok(())
     }
-
-    fn tx_interrupt(&self) {
-        // Deassert the interrupt, but can be left enabled
-        self.mac_regs.tx_ev().clear_event(LITEETH_TX_EVENT);
-
-        if self.tx_packet.is_none() {
-            debug!("LiteEth: tx interrupt called without tx_packet set");
-        }
-
-        // We use only one slot, so this event is unambiguous
-        let packet = self
-            .tx_packet
-            .take()
-            .expect("LiteEth: TakeCell empty in tx callback");
-        self.client
-            .map(move |client| client.tx_done(ReturnCode::SUCCESS, packet));
-    }
-
-    pub fn service_interrupt(&self) {
-        // The interrupt could've been generated by both a packet
-        // being received or finished transmitting. Check and handle
-        // both cases
-
-        if self.mac_regs.rx_ev().event_asserted(LITEETH_RX_EVENT) {
-            self.rx_interrupt();
-        }
-
-        if self.mac_regs.tx_ev().event_asserted(LITEETH_TX_EVENT) {
-            self.tx_interrupt();
-        }
-    }
 }
@@ -245,9 +245,19 @@ impl<'a> hil::digest::Digest<'a, [u8; 32]> for Hmac<'a> {
 }
 
 impl hil::digest::HMACSha256 for Hmac<'_> {
-    fn set_mode_hmacsha256(&self, key: &[u8; 32]) -> Result<(), ReturnCode> {
+    fn set_mode_hmacsha256(&self, key: &[u8]) -> Result<(), ReturnCode> {
         let regs = self.registers;
 
+        // The key registers hold 256 bits. Shorter keys are padded with
+        // zeros, which is equivalent for HMAC, but longer keys would have
+        // to be hashed first.
+        if key.len() > 32 {
+            return Err(ReturnCode::ENOSUPPORT);
+        }
+        let mut padded_key = [0; 32];
+        padded_key[..key.len()].copy_from_slice(key);
+        let key = &padded_key;
+
         // Ensure the HMAC is setup
         regs.cfg
             .write(CFG::ENDIAN_SWAP::SET + CFG::SHA_EN::SET + CFG::DIGEST_SWAP::SET);
@@ -32,8 +32,22 @@
 //! * Payload - 2 to 255 bytes
 //!
 //! * CRC - 3 bytes
+//!
+//! ### Link layer
+//!
+//! For connectable advertising and connections, the radio implements
+//! `hil::ble_connection::LinkLayerRadio`. The turnaround between a packet and
+//! its response is done by the hardware, with the `END_DISABLE`,
+//! `DISABLED_TXEN` and `DISABLED_RXEN` shortcuts and the `TIFS` register. The
+//! response is transmitted from `RESPONSE`, which holds the response the link
+//! layer prepared before the packet arrived. The packet pointer is moved to
+//! `RESPONSE` once the receiver has started, so the transmitter never sends
+//! the received packet back. The link layer may replace the prepared response
+//! only while the transmitter is still ramping up, as the transmitter reads
+//! the buffer once it starts, T_IFS (150 µs) after the end of the packet.
 
 use core::cell::Cell;
+use core::cmp;
 use core::convert::TryFrom;
 use kernel::common::cells::OptionalCell;
 use kernel::common::cells::TakeCell;
@@ -41,6 +55,8 @@ use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite, WriteOn
 use kernel::common::StaticRef;
 use kernel::hil::ble_advertising;
 use kernel::hil::ble_advertising::RadioChannel;
+use kernel::hil::ble_connection;
+use kernel::power::{SleepConstraint, SleepState};
 use kernel::ReturnCode;
 use nrf5x::constants::TxPower;
 
@@ -529,12 +545,39 @@ register_bitfields! [u32,
 static mut PAYLOAD: [u8; nrf5x::constants::RADIO_PAYLOAD_LENGTH] =
     [0x00; nrf5x::constants::RADIO_PAYLOAD_LENGTH];
 
+// Responses of the link layer are prepared here, as the received packet is
+// written to `PAYLOAD`
+static mut RESPONSE: [u8; nrf5x::constants::RADIO_PAYLOAD_LENGTH] =
+    [0x00; nrf5x::constants::RADIO_PAYLOAD_LENGTH];
+
+/// The link layer operation in progress. Each state ends with a DISABLED
+/// event, on which the shortcuts have already started the next one.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum LinkLayerState {
+    Idle,
+    /// Transmitting a packet, then listening
+    Transmitting,
+    /// Listening, then responding
+    Listening,
+    /// Transmitting a response
+    Responding,
+}
+
 pub struct Radio<'a> {
     registers: StaticRef<RadioRegisters>,
     tx_power: Cell<TxPower>,
     rx_client: OptionalCell<&'a dyn ble_advertising::RxClient>,
     tx_client: OptionalCell<&'a dyn ble_advertising::TxClient>,
     buffer: TakeCell<'static, [u8]>,
+    link_layer_client: OptionalCell<&'a dyn ble_connection::LinkLayerClient>,
+    link_layer: Cell<LinkLayerState>,
+    stop_requested: Cell<bool>,
+    access_address: Cell<u32>,
+    crc_init: Cell<u32>,
+    /// An advertising operation requested during a link layer operation:
+    /// whether it transmits, and its channel
+    deferred_advertising: Cell<Option<(bool, RadioChannel)>>,
+    sleep_constraint: SleepConstraint<'a>,
 }
 
 impl<'a> Radio<'a> {
@@ -545,9 +588,22 @@ impl<'a> Radio<'a> {
             rx_client: OptionalCell::empty(),
             tx_client: OptionalCell::empty(),
             buffer: TakeCell::empty(),
+            link_layer_client: OptionalCell::empty(),
+            link_layer: Cell::new(LinkLayerState::Idle),
+            stop_requested: Cell::new(false),
+            access_address: Cell::new(ble_connection::ADVERTISING_ACCESS_ADDRESS),
+            crc_init: Cell::new(ble_connection::ADVERTISING_CRC_INIT),
+            deferred_advertising: Cell::new(None),
+            sleep_constraint: SleepConstraint::new("ble_radio"),
         }
     }
 
+    /// The constraint that keeps the chip out of deep sleep while the radio
+    /// is powered.
+    pub fn sleep_constraint(&self) -> &SleepConstraint<'a> {
+        &self.sleep_constraint
+    }
+
     pub fn is_enabled(&self) -> bool {
         self.registers.mode.matches_all(Mode::MODE::BLE_1MBIT)
     }
@@ -575,6 +631,8 @@ impl<'a> Radio<'a> {
     }
 
     fn radio_on(&self) {
+        // The radio needs the crystal oscillator while it is powered.
+        self.sleep_constraint.limit(SleepState::Sleep);
         // reset and enable power
         self.registers.power.write(Task::ENABLE::CLEAR);
         self.registers.power.write(Task::ENABLE::SET);
@@ -582,6 +640,7 @@ impl<'a> Radio<'a> {
 
     fn radio_off(&self) {
         self.registers.power.write(Task::ENABLE::CLEAR);
+        self.sleep_constraint.release();
     }
 
     fn set_tx_power(&self) {
@@ -594,8 +653,19 @@ impl<'a> Radio<'a> {
         }
     }
 
+    fn is_busy(&self) -> bool {
+        self.link_layer.get() != LinkLayerState::Idle
+            || self.buffer.is_some()
+            || self.registers.state.get() != nrf5x::constants::RADIO_STATE_DISABLE
+    }
+
     #[inline(never)]
     pub fn handle_interrupt(&self) {
+        if self.link_layer.get() != LinkLayerState::Idle {
+            self.handle_link_layer_interrupt();
+            return;
+        }
+
         self.disable_all_interrupts();
 
         if self.registers.event_ready.is_set(Event::READY) {
@@ -651,6 +721,132 @@ impl<'a> Radio<'a> {
         self.enable_interrupts();
     }
 
+    fn handle_link_layer_interrupt(&self) {
+        if !self.registers.event_disabled.is_set(Event::READY) {
+            return;
+        }
+        self.registers.event_disabled.write(Event::READY::CLEAR);
+
+        match self.link_layer.get() {
+            LinkLayerState::Transmitting => {
+                // DISABLED_RXEN has started the receiver, which writes to
+                // `PAYLOAD` as the transmitted packet did not need it anymore
+                self.registers.event_address.write(Event::READY::CLEAR);
+                if self.stop_requested.get() {
+                    self.registers.shorts.set(0);
+                    self.registers.task_disable.write(Task::ENABLE::SET);
+                    self.link_layer_done(ReturnCode::SUCCESS);
+                } else {
+                    self.registers
+                        .shorts
+                        .write(Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET);
+                    self.link_layer.set(LinkLayerState::Listening);
+                    self.arm_response();
+                }
+            }
+            LinkLayerState::Listening => {
+                // If the response was armed, DISABLED_TXEN has started the
+                // transmitter, which sends `RESPONSE` T_IFS after the end of
+                // the received packet. It only reads `RESPONSE` once it
+                // starts, so the response can still be replaced while the
+                // transmitter is ramping up.
+                let armed = self.registers.shorts.is_set(Shortcut::DISABLED_TXEN);
+                self.registers
+                    .shorts
+                    .write(Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET);
+                let in_time =
+                    armed && self.registers.state.get() == nrf5x::constants::RADIO_STATE_TXRU;
+
+                let crc_ok = self.registers.crcstatus.is_set(Event::READY);
+                let response_len = self.link_layer_client.map_or(0, |client| unsafe {
+                    let len = cmp::min(PAYLOAD[1] as usize + 2, PAYLOAD.len());
+                    let response = if in_time {
+                        Some(&mut RESPONSE[..])
+                    } else {
+                        None
+                    };
+                    client.respond(&PAYLOAD[..len], crc_ok, response)
+                });
+                if !armed || (in_time && response_len == 0) {
+                    self.registers.task_disable.write(Task::ENABLE::SET);
+                    self.link_layer_done(ReturnCode::SUCCESS);
+                } else {
+                    self.link_layer.set(LinkLayerState::Responding);
+                }
+            }
+            LinkLayerState::Responding => self.link_layer_done(ReturnCode::SUCCESS),
+            LinkLayerState::Idle => (),
+        }
+    }
+
+    /// Prepares the response to the packet being received, and arms the
+    /// shortcut that transmits it T_IFS after the end of the packet. The
+    /// receiver reads the packet pointer when it starts, so the pointer is
+    /// only moved to `RESPONSE` once the receiver has started.
+    fn arm_response(&self) {
+        let len = self.link_layer_client.map_or(0, |client| unsafe {
+            client.prepare_response(&mut RESPONSE)
+        });
+        if len == 0 {
+            return;
+        }
+
+        // The receiver starts after ramping up for 40 µs, or at most T_IFS
+        // after the packet it follows
+        while matches!(
+            self.registers.state.get(),
+            nrf5x::constants::RADIO_STATE_RXRU | nrf5x::constants::RADIO_STATE_RXIDLE
+        ) {}
+        if self.registers.state.get() != nrf5x::constants::RADIO_STATE_RX {
+            // The receiver was stopped
+            return;
+        }
+        unsafe {
+            self.registers.packetptr.set(RESPONSE.as_ptr() as u32);
+        }
+        self.registers.shorts.write(
+            Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET + Shortcut::DISABLED_TXEN::SET,
+        );
+        // If a packet ended before the shortcut was armed, the transmitter
+        // was not started, and never will be
+        if self.registers.state.get() == nrf5x::constants::RADIO_STATE_DISABLE {
+            self.registers
+                .shorts
+                .write(Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET);
+        }
+    }
+
+    fn link_layer_done(&self, result: ReturnCode) {
+        self.registers.intenclr.write(Interrupt::DISABLED::SET);
+        self.link_layer.set(LinkLayerState::Idle);
+        self.stop_requested.set(false);
+        self.radio_off();
+        self.link_layer_client
+            .map(|client| client.operation_done(result));
+        self.start_deferred_advertising();
+    }
+
+    fn start_deferred_advertising(&self) {
+        if self.link_layer.get() != LinkLayerState::Idle {
+            return;
+        }
+        match self.deferred_advertising.take() {
+            Some((true, channel)) => {
+                // The link layer may have used the buffer until now
+                self.buffer.map(|buf| self.copy_to_radio_buffer(buf));
+                self.ble_initialize(channel);
+                self.tx();
+                self.enable_interrupts();
+            }
+            Some((false, channel)) => {
+                self.ble_initialize(channel);
+                self.rx();
+                self.enable_interrupts();
+            }
+            None => (),
+        }
+    }
+
     pub fn enable_interrupts(&self) {
         self.registers.intenset.write(
             Interrupt::READY::SET
@@ -673,14 +869,13 @@ impl<'a> Radio<'a> {
         self.registers.intenclr.set(0xffffffff);
     }
 
-    fn replace_radio_buffer(&self, buf: &'static mut [u8]) -> &'static mut [u8] {
+    fn copy_to_radio_buffer(&self, buf: &[u8]) {
         // set payload
-        for (i, c) in buf.as_ref().iter().enumerate() {
+        for (i, c) in buf.iter().enumerate() {
             unsafe {
                 PAYLOAD[i] = *c;
             }
         }
-        buf
     }
 
     fn ble_initialize(&self, channel: RadioChannel) {
@@ -697,31 +892,65 @@ impl<'a> Radio<'a> {
         self.set_rx_address();
 
         self.ble_set_packet_config();
-        self.ble_set_advertising_access_address();
+        self.ble_set_access_address(ble_connection::ADVERTISING_ACCESS_ADDRESS);
+
+        self.ble_set_crc_config(nrf5x::constants::RADIO_CRCINIT_BLE);
+
+        self.set_dma_ptr();
+    }
+
+    fn link_layer_initialize(&self, channel: RadioChannel) {
+        self.radio_on();
+
+        self.ble_set_tx_power();
+
+        self.ble_set_channel_rate();
+
+        self.ble_set_channel_freq(channel);
+        self.ble_set_data_whitening(channel);
+
+        self.set_tx_address();
+        self.set_rx_address();
+
+        self.ble_set_packet_config();
+        self.ble_set_access_address(self.access_address.get());
 
-        self.ble_set_crc_config();
+        self.ble_set_crc_config(self.crc_init.get());
+
+        // The fast ramp-up (40 µs) lets the receiver start listening well
+        // before the peer responds, T_IFS after our packet
+        self.registers
+            .modecnf0
+            .write(RadioModeConfig::RU::FAST + RadioModeConfig::DTX::CENTER);
+        self.registers
+            .tifs
+            .write(InterFrameSpacing::TIFS.val(ble_connection::T_IFS_US));
 
         self.set_dma_ptr();
+
+        self.registers.event_disabled.write(Event::READY::CLEAR);
+        self.registers.event_address.write(Event::READY::CLEAR);
+        self.registers.event_end.write(Event::READY::CLEAR);
+        self.disable_all_interrupts();
+        self.registers.intenset.write(Interrupt::DISABLED::SET);
     }
 
     // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 3.1.1 CRC Generation
-    fn ble_set_crc_config(&self) {
+    fn ble_set_crc_config(&self, crc_init: u32) {
         self.registers
             .crccnf
             .write(CrcConfiguration::LEN::THREE + CrcConfiguration::SKIPADDR::EXCLUDE);
-        self.registers
-            .crcinit
-            .set(nrf5x::constants::RADIO_CRCINIT_BLE);
+        self.registers.crcinit.set(crc_init);
         self.registers
             .crcpoly
             .set(nrf5x::constants::RADIO_CRCPOLY_BLE);
     }
 
     // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.1.2 Access Address
-    // Set access address to 0x8E89BED6
-    fn ble_set_advertising_access_address(&self) {
-        self.registers.prefix0.set(0x0000008e);
-        self.registers.base0.set(0x89bed600);
+    // The most significant byte is the prefix, the 3 others the base
+    fn ble_set_access_address(&self, access_address: u32) {
+        self.registers.prefix0.set(access_address >> 24);
+        self.registers.base0.set(access_address << 8);
     }
 
     // Packet configuration
@@ -788,17 +1017,14 @@ impl<'a> Radio<'a> {
 
 impl<'a> ble_advertising::BleAdvertisementDriver<'a> for Radio<'a> {
     fn transmit_advertisement(&self, buf: &'static mut [u8], _len: usize, channel: RadioChannel) {
-        let res = self.replace_radio_buffer(buf);
-        self.buffer.replace(res);
-        self.ble_initialize(channel);
-        self.tx();
-        self.enable_interrupts();
+        self.buffer.replace(buf);
+        self.deferred_advertising.set(Some((true, channel)));
+        self.start_deferred_advertising();
     }
 
     fn receive_advertisement(&self, channel: RadioChannel) {
-        self.ble_initialize(channel);
-        self.rx();
-        self.enable_interrupts();
+        self.deferred_advertising.set(Some((false, channel)));
+        self.start_deferred_advertising();
     }
 
     fn set_receive_client(&self, client: &'a dyn ble_advertising::RxClient) {
@@ -810,6 +1036,79 @@ impl<'a> ble_advertising::BleAdvertisementDriver<'a> for Radio<'a> {
     }
 }
 
+impl<'a> ble_connection::LinkLayerRadio<'a> for Radio<'a> {
+    fn set_link_layer_client(&self, client: &'a dyn ble_connection::LinkLayerClient) {
+        self.link_layer_client.set(client);
+    }
+
+    fn set_access_address(&self, access_address: u32, crc_init: u32) {
+        self.access_address.set(access_address);
+        self.crc_init.set(crc_init);
+    }
+
+    fn transmit_and_listen(&self, pdu: &[u8], channel: RadioChannel) -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        if pdu.len() > nrf5x::constants::RADIO_PAYLOAD_LENGTH {
+            return ReturnCode::ESIZE;
+        }
+        self.link_layer_initialize(channel);
+        // The receiver writes to `PAYLOAD` too, so the packet pointer does not
+        // need to change in the 40 µs the receiver takes to start
+        unsafe {
+            PAYLOAD[..pdu.len()].copy_from_slice(pdu);
+        }
+        self.registers.shorts.write(
+            Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET + Shortcut::DISABLED_RXEN::SET,
+        );
+        self.link_layer.set(LinkLayerState::Transmitting);
+        self.tx();
+        ReturnCode::SUCCESS
+    }
+
+    fn listen_and_respond(&self, channel: RadioChannel) -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        self.link_layer_initialize(channel);
+        self.registers
+            .shorts
+            .write(Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET);
+        self.link_layer.set(LinkLayerState::Listening);
+        self.rx();
+        // The receiver starts ramping up as soon as it is enabled
+        while self.registers.state.get() == nrf5x::constants::RADIO_STATE_DISABLE {}
+        self.arm_response();
+        ReturnCode::SUCCESS
+    }
+
+    fn stop_listening(&self) -> ReturnCode {
+        match self.link_layer.get() {
+            LinkLayerState::Idle => ReturnCode::EALREADY,
+            LinkLayerState::Listening => {
+                if self.registers.event_address.is_set(Event::READY) {
+                    // A packet is being received
+                    return ReturnCode::EBUSY;
+                }
+                self.registers.shorts.set(0);
+                self.registers.intenclr.write(Interrupt::DISABLED::SET);
+                self.registers.task_disable.write(Task::ENABLE::SET);
+                self.link_layer.set(LinkLayerState::Idle);
+                self.radio_off();
+                self.start_deferred_advertising();
+                ReturnCode::SUCCESS
+            }
+            LinkLayerState::Transmitting => {
+                // Disable the receiver as soon as the transmitter is done
+                self.stop_requested.set(true);
+                ReturnCode::EBUSY
+            }
+            LinkLayerState::Responding => ReturnCode::EBUSY,
+        }
+    }
+}
+
 impl ble_advertising::BleConfig for Radio<'_> {
     // The BLE Advertising Driver validates that the `tx_power` is between -20 to 10 dBm but then
     // underlying chip must validate if the current `tx_power` is supported as well
@@ -2,6 +2,7 @@ use crate::deferred_call_tasks::DeferredCallTask;
 use core::fmt::Write;
 use cortexm4::{self, nvic};
 use kernel::common::deferred_call;
+use kernel::power::SleepState;
 use kernel::InterruptService;
 
 pub struct NRF52<'a, I: InterruptService<DeferredCallTask> + 'a> {
@@ -9,6 +10,11 @@ pub struct NRF52<'a, I: InterruptService<DeferredCallTask> + 'a> {
     userspace_kernel_boundary: cortexm4::syscall::SysCall,
     scheduler_timer: cortexm4::systick::SysTick,
     interrupt_service: &'a I,
+    // Only used for their tasks when sleeping, the instances in
+    // `Nrf52DefaultPeripherals` handle the interrupts.
+    clock: crate::clock::Clock,
+    power: crate::power::Power<'a>,
+    watchdog: crate::wdt::Wdt,
 }
 
 impl<'a, I: InterruptService<DeferredCallTask> + 'a> NRF52<'a, I> {
@@ -20,8 +26,17 @@ impl<'a, I: InterruptService<DeferredCallTask> + 'a> NRF52<'a, I> {
             // 64Mhz CPU clock.
             scheduler_timer: cortexm4::systick::SysTick::new_with_calibration(64000000),
             interrupt_service,
+            clock: crate::clock::Clock::new(),
+            power: crate::power::Power::new(),
+            watchdog: crate::wdt::Wdt::new(),
         }
     }
+
+    /// Start the hardware watchdog when the kernel enters its main loop.
+    /// Once started it cannot be stopped until the chip resets.
+    pub fn enable_watchdog(&self) {
+        self.watchdog.enable();
+    }
 }
 
 /// This struct, when initialized, instantiates all peripheral drivers for the apollo3.
@@ -67,7 +82,7 @@ impl<'a> Nrf52DefaultPeripherals<'a> {
             timer0: crate::timer::TimerAlarm::new(0),
             timer1: crate::timer::TimerAlarm::new(1),
             timer2: crate::timer::Timer::new(2),
-            uarte0: crate::uart::Uarte::new(),
+            uarte0: crate::uart::Uarte::new("uarte0"),
             spim0: crate::spi::SPIM::new(0),
             twim0: crate::i2c::TWIM::new_twim0(),
             spim1: crate::spi::SPIM::new(1),
@@ -156,7 +171,7 @@ impl<'a, I: InterruptService<DeferredCallTask> + 'a> kernel::Chip for NRF52<'a,
     type MPU = cortexm4::mpu::MPU;
     type UserspaceKernelBoundary = cortexm4::syscall::SysCall;
     type SchedulerTimer = cortexm4::systick::SysTick;
-    type WatchDog = ();
+    type WatchDog = crate::wdt::Wdt;
 
     fn mpu(&self) -> &Self::MPU {
         &self.mpu
@@ -167,7 +182,7 @@ impl<'a, I: InterruptService<DeferredCallTask> + 'a> kernel::Chip for NRF52<'a,
     }
 
     fn watchdog(&self) -> &Self::WatchDog {
-        &()
+        &self.watchdog
     }
 
     fn userspace_kernel_boundary(&self) -> &Self::UserspaceKernelBoundary {
@@ -205,6 +220,47 @@ impl<'a, I: InterruptService<DeferredCallTask> + 'a> kernel::Chip for NRF52<'a,
         }
     }
 
+    fn sleep_in(&self, state: SleepState) -> SleepState {
+        match state {
+            SleepState::Idle => {
+                self.power.set_constant_latency();
+                unsafe {
+                    cortexm4::support::wfi();
+                }
+            }
+            SleepState::Sleep => {
+                self.power.set_low_power();
+                unsafe {
+                    cortexm4::support::wfi();
+                }
+            }
+            SleepState::DeepSleep => {
+                self.power.set_low_power();
+
+                // Boards start the crystal oscillator at boot and leave it
+                // running, which costs more than everything else in System
+                // ON idle. Drivers that need its accuracy (the radios, USB
+                // and UARTE reception) keep the chip out of deep sleep, so
+                // stop it here and start it again before anything runs.
+                let hfxo = self.clock.high_running()
+                    && self.clock.high_source() == crate::clock::HighClockSource::XTAL;
+                if hfxo {
+                    self.clock.high_stop();
+                }
+                unsafe {
+                    cortexm4::scb::set_sleepdeep();
+                    cortexm4::support::wfi();
+                    cortexm4::scb::unset_sleepdeep();
+                }
+                if hfxo {
+                    self.clock.high_start();
+                    while !self.clock.high_started() {}
+                }
+            }
+        }
+        state
+    }
+
     unsafe fn atomic<F, R>(&self, f: F) -> R
     where
         F: FnOnce() -> R,
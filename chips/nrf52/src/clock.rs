@@ -32,7 +32,7 @@ register_structs! {
         (0x014 => tasks_ctstart: WriteOnly<u32, Control::Register>),
         (0x018 => tasks_ctstop: WriteOnly<u32, Control::Register>),
         (0x01C => _reserved1),
-        (0x100 => events_hfclkstarted: ReadOnly<u32, Status::Register>),
+        (0x100 => events_hfclkstarted: ReadWrite<u32, Status::Register>),
         (0x104 => events_lfclkstarted: ReadOnly<u32, Status::Register>),
         (0x108 => _reserved2),
         (0x10C => events_done: ReadOnly<u32, Status::Register>),
@@ -141,6 +141,7 @@ pub enum LowClockSource {
 }
 
 /// High frequency clock source
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum HighClockSource {
     RC = 0,
     XTAL = 1,
@@ -207,6 +208,9 @@ impl Clock {
     /// Start the high frequency clock - specifically HFXO, and sets the high frequency
     /// clock source to HFXO
     pub fn high_start(&self) {
+        self.registers
+            .events_hfclkstarted
+            .write(Status::READY::CLEAR);
         self.registers.tasks_hfclkstart.write(Control::ENABLE::SET);
     }
 
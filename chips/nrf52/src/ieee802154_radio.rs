@@ -8,6 +8,7 @@ use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite, WriteOn
 use kernel::common::StaticRef;
 use kernel::hil::radio::{self, PowerClient};
 use kernel::hil::time::Alarm;
+use kernel::power::{SleepConstraint, SleepState};
 use kernel::ReturnCode;
 
 use crate::ppi;
@@ -675,6 +676,7 @@ pub struct Radio<'p> {
     transmitting: Cell<bool>,
     timer0: OptionalCell<&'p crate::timer::TimerAlarm<'p>>,
     ppi: &'p crate::ppi::Ppi,
+    sleep_constraint: SleepConstraint<'p>,
 }
 
 impl<'p> Radio<'p> {
@@ -696,9 +698,16 @@ impl<'p> Radio<'p> {
             transmitting: Cell::new(false),
             timer0: OptionalCell::empty(),
             ppi,
+            sleep_constraint: SleepConstraint::new("ieee802154_radio"),
         }
     }
 
+    /// The constraint that keeps the chip out of deep sleep while the radio
+    /// is powered.
+    pub fn sleep_constraint(&self) -> &SleepConstraint<'p> {
+        &self.sleep_constraint
+    }
+
     pub fn set_timer_ref(&self, timer: &'p crate::timer::TimerAlarm<'p>) {
         self.timer0.set(timer);
     }
@@ -745,6 +754,8 @@ impl<'p> Radio<'p> {
     }
 
     fn radio_on(&self) {
+        // The radio needs the crystal oscillator while it is powered.
+        self.sleep_constraint.limit(SleepState::Sleep);
         // reset and enable power
         self.registers.power.write(Task::ENABLE::CLEAR);
         self.registers.power.write(Task::ENABLE::SET);
@@ -752,6 +763,7 @@ impl<'p> Radio<'p> {
 
     fn radio_off(&self) {
         self.registers.power.write(Task::ENABLE::CLEAR);
+        self.sleep_constraint.release();
     }
 
     fn set_tx_power(&self) {
@@ -343,6 +343,18 @@ impl<'a> Power<'a> {
         self.registers.usbregstatus.is_set(UsbRegStatus::OUTPUTRDY)
     }
 
+    /// Keep the regulators and clocks of System ON ready while the CPU sleeps,
+    /// so that it wakes up with a short and fixed latency.
+    pub fn set_constant_latency(&self) {
+        self.registers.task_constlat.write(Task::ENABLE::SET);
+    }
+
+    /// Let the chip turn off regulators and clocks that no peripheral uses
+    /// while the CPU sleeps, which makes waking up take longer.
+    pub fn set_low_power(&self) {
+        self.registers.task_lowpwr.write(Task::ENABLE::SET);
+    }
+
     /// Return the contents of the GPREGRET (general purpose retention register)
     /// register.
     ///
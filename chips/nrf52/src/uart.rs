@@ -13,6 +13,7 @@ use kernel::common::cells::OptionalCell;
 use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil::uart;
+use kernel::power::{SleepConstraint, SleepState};
 use kernel::ReturnCode;
 use nrf5x::pinmux;
 
@@ -169,6 +170,7 @@ pub struct Uarte<'a> {
     rx_remaining_bytes: Cell<usize>,
     rx_abort_in_progress: Cell<bool>,
     offset: Cell<usize>,
+    sleep_constraint: SleepConstraint<'a>,
 }
 
 #[derive(Copy, Clone)]
@@ -177,9 +179,10 @@ pub struct UARTParams {
 }
 
 impl<'a> Uarte<'a> {
-    /// Constructor
+    /// Constructor. `name` names the instance in the list of sleep
+    /// constraints.
     // This should only be constructed once
-    pub const fn new() -> Uarte<'a> {
+    pub const fn new(name: &'static str) -> Uarte<'a> {
         Uarte {
             registers: UARTE_BASE,
             tx_client: OptionalCell::empty(),
@@ -191,6 +194,24 @@ impl<'a> Uarte<'a> {
             rx_remaining_bytes: Cell::new(0),
             rx_abort_in_progress: Cell::new(false),
             offset: Cell::new(0),
+            sleep_constraint: SleepConstraint::new(name),
+        }
+    }
+
+    /// The constraint that keeps the chip out of deep sleep while a transmit
+    /// or a receive is in progress, as the baud rate drifts without the
+    /// crystal oscillator.
+    pub fn sleep_constraint(&self) -> &SleepConstraint<'a> {
+        &self.sleep_constraint
+    }
+
+    /// Hold the sleep constraint as long as a transmit or a receive is in
+    /// progress.
+    fn update_sleep_constraint(&self) {
+        if self.tx_buffer.is_some() || self.rx_buffer.is_some() {
+            self.sleep_constraint.limit(SleepState::Sleep);
+        } else {
+            self.sleep_constraint.release();
         }
     }
 
@@ -299,15 +320,17 @@ impl<'a> Uarte<'a> {
             // All bytes have been transmitted
             if rem == 0 {
                 // Signal client write done
-                self.tx_client.map(|client| {
-                    self.tx_buffer.take().map(|tx_buffer| {
+                let tx_buffer = self.tx_buffer.take();
+                self.update_sleep_constraint();
+                if let Some(tx_buffer) = tx_buffer {
+                    self.tx_client.map(move |client| {
                         client.transmitted_buffer(
                             tx_buffer,
                             self.tx_len.get(),
                             ReturnCode::SUCCESS,
                         );
                     });
-                });
+                }
             } else {
                 // Not all bytes have been transmitted then update offset and continue transmitting
                 self.offset.set(self.offset.get() + tx_bytes);
@@ -334,8 +357,10 @@ impl<'a> Uarte<'a> {
             // do the receive callback immediately.
             if self.rx_abort_in_progress.get() {
                 self.rx_abort_in_progress.set(false);
-                self.rx_client.map(|client| {
-                    self.rx_buffer.take().map(|rx_buffer| {
+                let rx_buffer = self.rx_buffer.take();
+                self.update_sleep_constraint();
+                if let Some(rx_buffer) = rx_buffer {
+                    self.rx_client.map(move |client| {
                         client.received_buffer(
                             rx_buffer,
                             self.offset.get() + rx_bytes,
@@ -343,7 +368,7 @@ impl<'a> Uarte<'a> {
                             uart::Error::None,
                         );
                     });
-                });
+                }
             } else {
                 // In the normal case, we need to either pass call the callback
                 // or do another read to get more bytes.
@@ -357,8 +382,10 @@ impl<'a> Uarte<'a> {
                 let rem = self.rx_remaining_bytes.get();
                 if rem == 0 {
                     // Signal client that the read is done
-                    self.rx_client.map(|client| {
-                        self.rx_buffer.take().map(|rx_buffer| {
+                    let rx_buffer = self.rx_buffer.take();
+                    self.update_sleep_constraint();
+                    if let Some(rx_buffer) = rx_buffer {
+                        self.rx_client.map(move |client| {
                             client.received_buffer(
                                 rx_buffer,
                                 self.offset.get(),
@@ -366,7 +393,7 @@ impl<'a> Uarte<'a> {
                                 uart::Error::None,
                             );
                         });
-                    });
+                    }
                 } else {
                     // Setup how much we can read. We already made sure that
                     // this will fit in the buffer.
@@ -428,6 +455,7 @@ impl<'a> Uarte<'a> {
         self.tx_len.set(tx_len);
         self.offset.set(0);
         self.tx_buffer.replace(buf);
+        self.update_sleep_constraint();
         self.set_tx_dma_pointer_to_buffer();
 
         self.registers
@@ -511,6 +539,7 @@ impl<'a> uart::Receive<'a> for Uarte<'a> {
         self.offset.set(0);
         self.rx_buffer.replace(rx_buf);
         self.set_rx_dma_pointer_to_buffer();
+        self.update_sleep_constraint();
 
         let truncated_uart_max_length = core::cmp::min(truncated_length, 255);
 
@@ -10,6 +10,7 @@ use kernel::common::registers::{
 use kernel::common::StaticRef;
 use kernel::hil;
 use kernel::hil::usb::TransferType;
+use kernel::power::{SleepConstraint, SleepState};
 
 use crate::power;
 
@@ -710,6 +711,7 @@ pub struct Usbd<'a> {
     client: OptionalCell<&'a dyn hil::usb::Client<'a>>,
     descriptors: [Endpoint<'a>; NUM_ENDPOINTS],
     power: OptionalCell<&'a power::Power<'a>>,
+    sleep_constraint: SleepConstraint<'a>,
 }
 
 impl<'a> Usbd<'a> {
@@ -730,9 +732,16 @@ impl<'a> Usbd<'a> {
                 Endpoint::new(),
             ],
             power: OptionalCell::empty(),
+            sleep_constraint: SleepConstraint::new("usbd"),
         }
     }
 
+    /// The constraint that keeps the chip out of deep sleep while the USB
+    /// peripheral is enabled.
+    pub fn sleep_constraint(&self) -> &SleepConstraint<'a> {
+        &self.sleep_constraint
+    }
+
     pub fn set_power_ref(&self, power: &'a power::Power<'a>) {
         self.power.set(power);
     }
@@ -819,6 +828,8 @@ impl<'a> Usbd<'a> {
             internal_warn!("USBC is already enabled");
             return;
         }
+        // USB needs the crystal oscillator while the peripheral is enabled.
+        self.sleep_constraint.limit(SleepState::Sleep);
         self.registers.eventcause.modify(EventCause::READY::CLEAR);
         self.apply_errata_187(3);
         self.apply_errata_171(0xc0);
@@ -968,6 +979,7 @@ impl<'a> Usbd<'a> {
         self.registers.enable.write(Usb::ENABLE::OFF);
         self.state.set(UsbState::Initialized);
         self.clear_pending_dma();
+        self.sleep_constraint.release();
     }
 
     fn clear_pending_dma(&self) {
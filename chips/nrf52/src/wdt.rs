@@ -0,0 +1,132 @@
+//! Watchdog timer (WDT)
+//!
+//! The WDT counts down from its reload value on the 32.768 kHz low frequency
+//! clock and resets the chip when it reaches zero. Once started it cannot be
+//! stopped or reconfigured until the next reset, so it is only started if the
+//! board called `NRF52::enable_watchdog()`. It is configured to pause while
+//! the CPU sleeps, so the kernel does not need to wake up to feed it.
+
+use core::cell::Cell;
+use kernel::common::registers::{
+    register_bitfields, register_structs, ReadOnly, ReadWrite, WriteOnly,
+};
+use kernel::common::StaticRef;
+
+const WDT_BASE: StaticRef<WdtRegisters> =
+    unsafe { StaticRef::new(0x40010000 as *const WdtRegisters) };
+
+/// The value written to a reload request register to feed the watchdog.
+const RELOAD_VALUE: u32 = 0x6E524635;
+
+/// Ticks of the 32.768 kHz clock before the watchdog resets the chip: 1 s.
+const TIMEOUT_TICKS: u32 = 32768;
+
+register_structs! {
+    WdtRegisters {
+        /// Start the watchdog
+        (0x000 => task_start: WriteOnly<u32, Task::Register>),
+        (0x004 => _reserved0),
+        /// Watchdog timeout
+        (0x100 => event_timeout: ReadWrite<u32, Event::Register>),
+        (0x104 => _reserved1),
+        /// Enable interrupt
+        (0x304 => intenset: ReadWrite<u32, Interrupt::Register>),
+        /// Disable interrupt
+        (0x308 => intenclr: ReadWrite<u32, Interrupt::Register>),
+        (0x30C => _reserved2),
+        /// Run status
+        (0x400 => runstatus: ReadOnly<u32, RunStatus::Register>),
+        /// Request status
+        (0x404 => reqstatus: ReadOnly<u32>),
+        (0x408 => _reserved3),
+        /// Counter reload value
+        (0x504 => crv: ReadWrite<u32>),
+        /// Enable register for reload request registers
+        (0x508 => rren: ReadWrite<u32>),
+        /// Configuration register
+        (0x50C => config: ReadWrite<u32, Config::Register>),
+        (0x510 => _reserved4),
+        /// Reload request registers
+        (0x600 => rr: [WriteOnly<u32>; 8]),
+        (0x620 => @END),
+    }
+}
+
+register_bitfields![u32,
+    Task [
+        ENABLE OFFSET(0) NUMBITS(1)
+    ],
+    Event [
+        READY OFFSET(0) NUMBITS(1)
+    ],
+    Interrupt [
+        TIMEOUT OFFSET(0) NUMBITS(1)
+    ],
+    RunStatus [
+        RUNNING OFFSET(0) NUMBITS(1)
+    ],
+    Config [
+        /// Keep the watchdog running while the CPU is sleeping
+        SLEEP OFFSET(0) NUMBITS(1) [
+            Pause = 0,
+            Run = 1
+        ],
+        /// Keep the watchdog running while the CPU is halted by the debugger
+        HALT OFFSET(3) NUMBITS(1) [
+            Pause = 0,
+            Run = 1
+        ]
+    ]
+];
+
+pub struct Wdt {
+    registers: StaticRef<WdtRegisters>,
+    enabled: Cell<bool>,
+}
+
+impl Wdt {
+    pub const fn new() -> Wdt {
+        Wdt {
+            registers: WDT_BASE,
+            enabled: Cell::new(false),
+        }
+    }
+
+    /// Have the kernel start the watchdog when it enters its main loop.
+    pub fn enable(&self) {
+        self.enabled.set(true);
+    }
+
+    fn start(&self) {
+        let regs = &*self.registers;
+        // A watchdog started by an earlier boot stage keeps running and can
+        // no longer be configured, so only feed it.
+        if !regs.runstatus.is_set(RunStatus::RUNNING) {
+            regs.config
+                .write(Config::SLEEP::Pause + Config::HALT::Pause);
+            regs.crv.set(TIMEOUT_TICKS - 1);
+            // Only use the first reload request register.
+            regs.rren.set(1);
+            regs.task_start.write(Task::ENABLE::SET);
+        }
+        self.feed();
+    }
+
+    fn feed(&self) {
+        self.registers.rr[0].set(RELOAD_VALUE);
+    }
+}
+
+impl kernel::watchdog::WatchDog for Wdt {
+    fn setup(&self) {
+        if self.enabled.get() {
+            self.start();
+        }
+    }
+
+    fn tickle(&self) {
+        if self.enabled.get() {
+            self.feed();
+        }
+    }
+}
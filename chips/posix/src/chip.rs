@@ -0,0 +1,139 @@
+//! High-level setup and interrupt mapping for the emulated chip.
+
+use core::fmt::Write;
+use kernel::hil::time::Alarm;
+use kernel::{Chip, InterruptService};
+use std::process;
+
+use crate::clock::Clock;
+use crate::disk::Disk;
+use crate::flash::Flash;
+use crate::interrupts::{self, INTERRUPTS};
+use crate::mpu::Mpu;
+use crate::syscall::SysCall;
+use crate::uart::Uart;
+
+pub struct Posix<'a, A: 'static + Alarm<'static>> {
+    userspace_kernel_boundary: SysCall<'a>,
+    scheduler_timer: kernel::VirtualSchedulerTimer<A>,
+    peripherals: &'a PosixDefaultPeripherals<'a>,
+}
+
+pub struct PosixDefaultPeripherals<'a> {
+    pub clock: Clock<'a>,
+    pub uart0: Uart<'a>,
+    pub flash: Flash,
+    pub disk: Disk<'a>,
+    pub mpu: Mpu,
+}
+
+impl<'a> PosixDefaultPeripherals<'a> {
+    pub fn new(flash_pages: usize) -> Self {
+        Self {
+            clock: Clock::new(),
+            uart0: Uart::new(),
+            flash: Flash::new(flash_pages),
+            disk: Disk::new(),
+            mpu: Mpu::new(),
+        }
+    }
+}
+
+impl<'a> InterruptService<()> for PosixDefaultPeripherals<'a> {
+    unsafe fn service_interrupt(&self, interrupt: u32) -> bool {
+        match interrupt {
+            interrupts::CLOCK => self.clock.handle_interrupt(),
+            interrupts::UART0 => self.uart0.handle_interrupt(),
+            interrupts::FLASH => self.flash.handle_interrupt(),
+            interrupts::DISK => self.disk.handle_interrupt(),
+            interrupts::CLOCK_OVERFLOW => self.clock.handle_overflow_interrupt(),
+            _ => return false,
+        }
+        true
+    }
+
+    unsafe fn service_deferred_call(&self, _: ()) -> bool {
+        false
+    }
+}
+
+impl<'a, A: 'static + Alarm<'static>> Posix<'a, A> {
+    pub fn new(alarm: &'static A, peripherals: &'a PosixDefaultPeripherals<'a>) -> Self {
+        Self {
+            userspace_kernel_boundary: SysCall::new(&peripherals.clock, &peripherals.mpu),
+            scheduler_timer: kernel::VirtualSchedulerTimer::new(alarm),
+            peripherals,
+        }
+    }
+}
+
+impl<'a, A: 'static + Alarm<'static>> Chip for Posix<'a, A> {
+    type MPU = Mpu;
+    type UserspaceKernelBoundary = SysCall<'a>;
+    type SchedulerTimer = kernel::VirtualSchedulerTimer<A>;
+    type WatchDog = ();
+
+    fn mpu(&self) -> &Self::MPU {
+        &self.peripherals.mpu
+    }
+
+    fn scheduler_timer(&self) -> &Self::SchedulerTimer {
+        &self.scheduler_timer
+    }
+
+    fn watchdog(&self) -> &Self::WatchDog {
+        &()
+    }
+
+    fn userspace_kernel_boundary(&self) -> &SysCall<'a> {
+        &self.userspace_kernel_boundary
+    }
+
+    fn service_pending_interrupts(&self) {
+        while let Some(interrupt) = INTERRUPTS.take_next_pending() {
+            if unsafe { !self.peripherals.service_interrupt(interrupt) } {
+                panic!("unhandled interrupt {}", interrupt);
+            }
+        }
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        INTERRUPTS.has_pending()
+    }
+
+    /// Waits for the next event. Input is delivered if there is some, and
+    /// otherwise simulated time jumps to the next alarm. If neither can ever
+    /// happen, the system is done and the host process exits.
+    fn sleep(&self) {
+        let clock = &self.peripherals.clock;
+        let uart = &self.peripherals.uart0;
+        if clock.is_armed() {
+            if !uart.receive_input(false) {
+                clock.advance_to_alarm();
+            }
+        } else if !uart.receive_input(true) {
+            eprintln!(
+                "posix: nothing left to do after {} us, exiting",
+                clock.now_us()
+            );
+            process::exit(0);
+        }
+    }
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        // Interrupts are only raised by the kernel thread itself, so there
+        // is nothing that could preempt `f`.
+        f()
+    }
+
+    unsafe fn print_state(&self, writer: &mut dyn Write) {
+        let _ = writer.write_fmt(format_args!(
+            "\r\n---| POSIX Emulation State |---\
+             \r\n Simulated time: {} us\r\n",
+            self.peripherals.clock.now_us()
+        ));
+    }
+}
@@ -0,0 +1,148 @@
+//! Simulated clock with a single alarm.
+//!
+//! The clock is also a 32-bit counter, which raises an interrupt when it
+//! overflows. It is always running and cannot be stopped or reset.
+//!
+//! The clock does not follow the time of the host. It counts microseconds of
+//! simulated time, which pass while processes execute instructions, and when
+//! the chip sleeps until the next alarm. Work done by the kernel takes no
+//! simulated time.
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Counter, Ticks, Ticks32, Time};
+use kernel::ReturnCode;
+
+use crate::interrupts::{self, INTERRUPTS};
+
+pub struct Clock<'a> {
+    /// Simulated time since boot, in ticks.
+    now: Cell<u64>,
+    /// Time at which the alarm expires, if it is armed.
+    deadline: Cell<Option<u64>>,
+    alarm: Cell<Ticks32>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+    overflow_client: OptionalCell<&'a dyn time::OverflowClient>,
+}
+
+impl<'a> Clock<'a> {
+    pub fn new() -> Clock<'a> {
+        Clock {
+            now: Cell::new(0),
+            deadline: Cell::new(None),
+            alarm: Cell::new(Ticks32::from(0)),
+            client: OptionalCell::empty(),
+            overflow_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Advances simulated time by `ticks`, raising the interrupt if the
+    /// alarm expired.
+    pub fn advance(&self, ticks: u64) {
+        let before = self.now.get();
+        let now = before + ticks;
+        self.now.set(now);
+        if before >> 32 != now >> 32 {
+            INTERRUPTS.raise(interrupts::CLOCK_OVERFLOW);
+        }
+        if let Some(deadline) = self.deadline.get() {
+            if now >= deadline {
+                self.deadline.set(None);
+                INTERRUPTS.raise(interrupts::CLOCK);
+            }
+        }
+    }
+
+    /// Advances simulated time to the expiry of the alarm. Returns `false`
+    /// if the alarm is not armed, in which case time cannot advance.
+    pub fn advance_to_alarm(&self) -> bool {
+        match self.deadline.get() {
+            Some(deadline) => {
+                self.advance(deadline.saturating_sub(self.now.get()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Simulated time since boot, in microseconds.
+    pub fn now_us(&self) -> u64 {
+        self.now.get()
+    }
+
+    pub fn handle_interrupt(&self) {
+        self.client.map(|client| client.alarm());
+    }
+
+    pub fn handle_overflow_interrupt(&self) {
+        self.overflow_client.map(|client| client.overflow());
+    }
+}
+
+impl Time for Clock<'_> {
+    type Frequency = time::Freq1MHz;
+    type Ticks = Ticks32;
+
+    fn now(&self) -> Ticks32 {
+        Ticks32::from(self.now.get() as u32)
+    }
+}
+
+impl<'a> Counter<'a> for Clock<'a> {
+    fn set_overflow_client(&'a self, client: &'a dyn time::OverflowClient) {
+        self.overflow_client.set(client);
+    }
+
+    fn start(&self) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn stop(&self) -> ReturnCode {
+        ReturnCode::EBUSY
+    }
+
+    fn reset(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn is_running(&self) -> bool {
+        true
+    }
+}
+
+impl<'a> Alarm<'a> for Clock<'a> {
+    fn set_alarm_client(&'a self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+        let expire = reference.wrapping_add(dt);
+        let now = self.now();
+        // An alarm whose expiry already passed fires right away
+        let remaining = if now.within_range(reference, expire) {
+            expire.wrapping_sub(now).into_u32()
+        } else {
+            0
+        };
+        self.alarm.set(expire);
+        self.deadline.set(Some(self.now.get() + remaining as u64));
+        self.advance(0);
+    }
+
+    fn get_alarm(&self) -> Ticks32 {
+        self.alarm.get()
+    }
+
+    fn disarm(&self) -> ReturnCode {
+        self.deadline.set(None);
+        ReturnCode::SUCCESS
+    }
+
+    fn is_armed(&self) -> bool {
+        self.deadline.get().is_some()
+    }
+
+    fn minimum_dt(&self) -> Ticks32 {
+        Ticks32::from(1)
+    }
+}
@@ -0,0 +1,160 @@
+//! Block storage backed by a disk image file, standing in for an SD card.
+//!
+//! Blocks are read from and written to the file directly, so that images
+//! created on the host, for example with `mkfs.fat`, can be used by capsules
+//! and inspected after a run. Without a file, the disk has no medium.
+//!
+//! Operations complete immediately, and the client is called back when the
+//! interrupt is serviced.
+
+use core::cell::{Cell, RefCell};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::block_storage::{BlockStorage, BlockStorageClient};
+use kernel::ReturnCode;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::interrupts::{self, INTERRUPTS};
+
+pub const BLOCK_SIZE: usize = 512;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    None,
+    Read,
+    Write,
+}
+
+pub struct Disk<'a> {
+    file: RefCell<Option<File>>,
+    num_blocks: Cell<u32>,
+    client: OptionalCell<&'a dyn BlockStorageClient>,
+    buffer: TakeCell<'static, [u8]>,
+    operation: Cell<Operation>,
+    result: Cell<ReturnCode>,
+}
+
+impl<'a> Disk<'a> {
+    pub fn new() -> Disk<'a> {
+        Disk {
+            file: RefCell::new(None),
+            num_blocks: Cell::new(0),
+            client: OptionalCell::empty(),
+            buffer: TakeCell::empty(),
+            operation: Cell::new(Operation::None),
+            result: Cell::new(ReturnCode::SUCCESS),
+        }
+    }
+
+    /// Uses the existing image at `path` as the medium of the disk. Bytes
+    /// after the last whole block of the image are not accessible.
+    pub fn attach_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let num_blocks = file.metadata()?.len() / BLOCK_SIZE as u64;
+        if num_blocks > u32::MAX as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "image has too many blocks",
+            ));
+        }
+
+        self.num_blocks.set(num_blocks as u32);
+        self.file.replace(Some(file));
+        Ok(())
+    }
+
+    /// Checks an operation on `count` blocks at `block` from `buffer`, and
+    /// returns the byte offset and length of the blocks in the image.
+    fn check(&self, buffer: &[u8], block: u32, count: u32) -> Result<(u64, usize), ReturnCode> {
+        if self.operation.get() != Operation::None {
+            return Err(ReturnCode::EBUSY);
+        }
+        if self.file.borrow().is_none() {
+            return Err(ReturnCode::EUNINSTALLED);
+        }
+        let len = count as usize * BLOCK_SIZE;
+        if count == 0
+            || buffer.len() < len
+            || block as u64 + count as u64 > self.num_blocks.get() as u64
+        {
+            return Err(ReturnCode::EINVAL);
+        }
+        Ok((block as u64 * BLOCK_SIZE as u64, len))
+    }
+
+    fn complete(&self, operation: Operation, result: io::Result<()>, buffer: &'static mut [u8]) {
+        self.operation.set(operation);
+        self.result.set(match result {
+            Ok(()) => ReturnCode::SUCCESS,
+            Err(_) => ReturnCode::FAIL,
+        });
+        self.buffer.replace(buffer);
+        INTERRUPTS.raise(interrupts::DISK);
+    }
+
+    pub fn handle_interrupt(&self) {
+        let result = self.result.get();
+        let operation = self.operation.replace(Operation::None);
+        if let Some(buffer) = self.buffer.take() {
+            self.client.map(move |client| match operation {
+                Operation::Read => client.read_done(result, buffer),
+                Operation::Write => client.write_done(result, buffer),
+                Operation::None => {}
+            });
+        }
+    }
+}
+
+impl<'a> BlockStorage<'a> for Disk<'a> {
+    fn set_client(&self, client: &'a dyn BlockStorageClient) {
+        self.client.set(client);
+    }
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn num_blocks(&self) -> u32 {
+        self.num_blocks.get()
+    }
+
+    fn read_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        let (offset, len) = match self.check(buffer, block, count) {
+            Ok(range) => range,
+            Err(rcode) => return Err((rcode, buffer)),
+        };
+
+        let result = self.file.borrow_mut().as_mut().map_or(Ok(()), |file| {
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut buffer[..len])
+        });
+        self.complete(Operation::Read, result, buffer);
+        Ok(())
+    }
+
+    fn write_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        let (offset, len) = match self.check(buffer, block, count) {
+            Ok(range) => range,
+            Err(rcode) => return Err((rcode, buffer)),
+        };
+
+        let result = self.file.borrow_mut().as_mut().map_or(Ok(()), |file| {
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(&buffer[..len])?;
+            file.flush()
+        });
+        self.complete(Operation::Write, result, buffer);
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+//! Flash kept in host memory, optionally backed by a file.
+//!
+//! Without a file, the flash starts out erased on every run. With a file, the
+//! flash is loaded from it, and every write and erase is written through to
+//! it, so the contents persist across runs.
+//!
+//! Operations complete immediately, and the client is called back when the
+//! interrupt is serviced.
+
+use core::cell::{Cell, RefCell};
+use core::cmp;
+use core::ops::{Index, IndexMut};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::ReturnCode;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::interrupts::{self, INTERRUPTS};
+
+pub const PAGE_SIZE: usize = 4096;
+
+/// The value of erased bytes.
+const ERASED: u8 = 0xff;
+
+pub struct PosixPage(pub [u8; PAGE_SIZE]);
+
+impl Default for PosixPage {
+    fn default() -> Self {
+        Self([0; PAGE_SIZE])
+    }
+}
+
+impl Index<usize> for PosixPage {
+    type Output = u8;
+
+    fn index(&self, idx: usize) -> &u8 {
+        &self.0[idx]
+    }
+}
+
+impl IndexMut<usize> for PosixPage {
+    fn index_mut(&mut self, idx: usize) -> &mut u8 {
+        &mut self.0[idx]
+    }
+}
+
+impl AsMut<[u8]> for PosixPage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    None,
+    Read,
+    Write,
+    Erase,
+}
+
+pub struct Flash {
+    contents: RefCell<Vec<u8>>,
+    file: RefCell<Option<File>>,
+    client: OptionalCell<&'static dyn hil::flash::Client<Flash>>,
+    buffer: TakeCell<'static, PosixPage>,
+    operation: Cell<Operation>,
+    error: Cell<hil::flash::Error>,
+}
+
+impl Flash {
+    pub fn new(num_pages: usize) -> Flash {
+        Flash {
+            contents: RefCell::new(vec![ERASED; num_pages * PAGE_SIZE]),
+            file: RefCell::new(None),
+            client: OptionalCell::empty(),
+            buffer: TakeCell::empty(),
+            operation: Cell::new(Operation::None),
+            error: Cell::new(hil::flash::Error::CommandComplete),
+        }
+    }
+
+    /// Backs the flash with the file at `path`, which is created if it does
+    /// not exist. Existing contents of the file are loaded into the flash.
+    pub fn attach_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)?;
+
+        let mut existing = Vec::new();
+        file.read_to_end(&mut existing)?;
+
+        // Bytes the file does not have yet are erased
+        let mut contents = self.contents.borrow_mut();
+        let len = cmp::min(existing.len(), contents.len());
+        contents[..len].copy_from_slice(&existing[..len]);
+        for byte in contents[len..].iter_mut() {
+            *byte = ERASED;
+        }
+        file.set_len(contents.len() as u64)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&contents)?;
+
+        self.file.replace(Some(file));
+        Ok(())
+    }
+
+    /// Returns the size of the flash in pages.
+    pub fn num_pages(&self) -> usize {
+        self.contents.borrow().len() / PAGE_SIZE
+    }
+
+    fn page_range(&self, page_number: usize) -> Option<core::ops::Range<usize>> {
+        if page_number < self.num_pages() {
+            Some(page_number * PAGE_SIZE..(page_number + 1) * PAGE_SIZE)
+        } else {
+            None
+        }
+    }
+
+    /// Writes `data` at `offset` to the backing file, if there is one.
+    fn write_through(&self, offset: usize, data: &[u8]) -> hil::flash::Error {
+        let result = self.file.borrow_mut().as_mut().map_or(Ok(()), |file| {
+            file.seek(SeekFrom::Start(offset as u64))?;
+            file.write_all(data)?;
+            file.flush()
+        });
+        match result {
+            Ok(()) => hil::flash::Error::CommandComplete,
+            Err(_) => hil::flash::Error::FlashError,
+        }
+    }
+
+    fn complete(&self, operation: Operation, error: hil::flash::Error) {
+        self.operation.set(operation);
+        self.error.set(error);
+        INTERRUPTS.raise(interrupts::FLASH);
+    }
+
+    pub fn handle_interrupt(&self) {
+        let error = self.error.get();
+        match self.operation.replace(Operation::None) {
+            Operation::None => {}
+            Operation::Read => {
+                if let Some(buffer) = self.buffer.take() {
+                    self.client
+                        .map(move |client| client.read_complete(buffer, error));
+                }
+            }
+            Operation::Write => {
+                if let Some(buffer) = self.buffer.take() {
+                    self.client
+                        .map(move |client| client.write_complete(buffer, error));
+                }
+            }
+            Operation::Erase => {
+                self.client.map(move |client| client.erase_complete(error));
+            }
+        }
+    }
+}
+
+impl<C: hil::flash::Client<Self>> hil::flash::HasClient<'static, C> for Flash {
+    fn set_client(&self, client: &'static C) {
+        self.client.set(client);
+    }
+}
+
+impl hil::flash::Flash for Flash {
+    type Page = PosixPage;
+
+    fn read_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        if self.operation.get() != Operation::None {
+            return Err((ReturnCode::EBUSY, buf));
+        }
+        let range = match self.page_range(page_number) {
+            Some(range) => range,
+            None => return Err((ReturnCode::EINVAL, buf)),
+        };
+
+        buf.0.copy_from_slice(&self.contents.borrow()[range]);
+        self.buffer.replace(buf);
+        self.complete(Operation::Read, hil::flash::Error::CommandComplete);
+        Ok(())
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        if self.operation.get() != Operation::None {
+            return Err((ReturnCode::EBUSY, buf));
+        }
+        let range = match self.page_range(page_number) {
+            Some(range) => range,
+            None => return Err((ReturnCode::EINVAL, buf)),
+        };
+
+        self.contents.borrow_mut()[range.clone()].copy_from_slice(&buf.0);
+        let error = self.write_through(range.start, &buf.0);
+        self.buffer.replace(buf);
+        self.complete(Operation::Write, error);
+        Ok(())
+    }
+
+    fn erase_page(&self, page_number: usize) -> ReturnCode {
+        if self.operation.get() != Operation::None {
+            return ReturnCode::EBUSY;
+        }
+        let range = match self.page_range(page_number) {
+            Some(range) => range,
+            None => return ReturnCode::EINVAL,
+        };
+
+        for byte in self.contents.borrow_mut()[range.clone()].iter_mut() {
+            *byte = ERASED;
+        }
+        let error = self.write_through(range.start, &[ERASED; PAGE_SIZE]);
+        self.complete(Operation::Erase, error);
+        ReturnCode::SUCCESS
+    }
+}
@@ -0,0 +1,57 @@
+//! Interrupt lines of the emulated peripherals.
+//!
+//! Peripherals raise an interrupt when an operation completes, and the chip
+//! services pending interrupts from the kernel loop, like with an interrupt
+//! controller that records interrupts for the bottom half handlers.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+pub const CLOCK: u32 = 0;
+pub const UART0: u32 = 1;
+pub const FLASH: u32 = 2;
+pub const DISK: u32 = 3;
+pub const CLOCK_OVERFLOW: u32 = 4;
+
+pub struct Interrupts {
+    pending: AtomicU32,
+    /// Set when an interrupt is raised, and cleared when a running process
+    /// is interrupted because of it.
+    raised: AtomicBool,
+}
+
+pub static INTERRUPTS: Interrupts = Interrupts::new();
+
+impl Interrupts {
+    const fn new() -> Interrupts {
+        Interrupts {
+            pending: AtomicU32::new(0),
+            raised: AtomicBool::new(false),
+        }
+    }
+
+    pub fn raise(&self, interrupt: u32) {
+        self.pending.fetch_or(1 << interrupt, Ordering::SeqCst);
+        self.raised.store(true, Ordering::SeqCst);
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.pending.load(Ordering::SeqCst) != 0
+    }
+
+    /// Returns the lowest pending interrupt and marks it as serviced.
+    pub fn take_next_pending(&self) -> Option<u32> {
+        let pending = self.pending.load(Ordering::SeqCst);
+        if pending == 0 {
+            None
+        } else {
+            let interrupt = pending.trailing_zeros();
+            self.pending.fetch_and(!(1 << interrupt), Ordering::SeqCst);
+            Some(interrupt)
+        }
+    }
+
+    /// Returns whether an interrupt was raised since the last call.
+    pub(crate) fn take_raised(&self) -> bool {
+        self.raised.swap(false, Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,36 @@
+//! Chip support for running Tock as a process on a POSIX host.
+//!
+//! This crate emulates a microcontroller on top of the primitives of the
+//! host operating system, so that the kernel, capsules and processes can be
+//! exercised without hardware or an instruction set simulator:
+//!
+//! - Processes are RISC-V (RV32IMAC) binaries, which are executed by an
+//!   interpreter that implements `UserspaceKernelBoundary`. The memory
+//!   protection of the `mpu` module is checked on every access.
+//! - Time is simulated. The clock advances by one tick for every instruction
+//!   a process executes, and jumps to the next alarm when the chip sleeps,
+//!   so runs are deterministic and do not depend on the speed of the host.
+//! - The UART is connected to stdin and stdout.
+//! - The flash is kept in memory, and can be backed by a file.
+//! - A disk image file can serve as block storage, in place of an SD card.
+//!
+//! Process memory is accessed through host pointers, which must fit in the
+//! 32-bit address space of the processes. Boards use `memory::map()` to
+//! place app flash and RAM at low host addresses.
+//!
+//! This crate uses `std` and only builds for Linux hosts.
+
+#![crate_name = "posix"]
+#![crate_type = "rlib"]
+
+mod rv32;
+
+pub mod chip;
+pub mod clock;
+pub mod disk;
+pub mod flash;
+pub mod interrupts;
+pub mod memory;
+pub mod mpu;
+pub mod syscall;
+pub mod uart;
@@ -0,0 +1,63 @@
+//! Placement of process memory at fixed host addresses.
+//!
+//! Processes use 32-bit addresses, and the interpreter accesses their memory
+//! through host pointers with the same value. App flash and RAM therefore
+//! have to be placed below 4 GiB, which `map()` does with `mmap()`. Using the
+//! addresses that the memory has on a real board also lets processes that
+//! were linked for fixed addresses run unmodified.
+
+use core::slice;
+use std::io;
+
+extern "C" {
+    fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut u8;
+    fn munmap(addr: *mut u8, len: usize) -> i32;
+}
+
+const PROT_READ: i32 = 0x1;
+const PROT_WRITE: i32 = 0x2;
+const MAP_PRIVATE: i32 = 0x02;
+const MAP_ANONYMOUS: i32 = 0x20;
+const MAP_FIXED_NOREPLACE: i32 = 0x10_0000;
+
+const HOST_PAGE_SIZE: usize = 4096;
+
+/// Maps `len` bytes of zeroed memory at the host address `address`, which
+/// must be free.
+///
+/// # Safety
+///
+/// The memory is handed out as a `'static` slice and never unmapped, so a
+/// range must only be mapped once.
+pub unsafe fn map(address: usize, len: usize) -> io::Result<&'static mut [u8]> {
+    if address.checked_add(len).map_or(true, |end| end > 1 << 32) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "process memory must be below 4 GiB",
+        ));
+    }
+
+    let start = address & !(HOST_PAGE_SIZE - 1);
+    let end = (address + len + HOST_PAGE_SIZE - 1) & !(HOST_PAGE_SIZE - 1);
+    let mapped = mmap(
+        start as *mut u8,
+        end - start,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED_NOREPLACE,
+        -1,
+        0,
+    );
+    if mapped as isize == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    if mapped as usize != start {
+        // Kernels older than Linux 4.17 treat the address as a hint only
+        munmap(mapped, end - start);
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "requested address is not available",
+        ));
+    }
+
+    Ok(slice::from_raw_parts_mut(address as *mut u8, len))
+}
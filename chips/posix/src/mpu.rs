@@ -0,0 +1,232 @@
+//! Software memory protection for processes.
+//!
+//! The regions are not enforced by hardware, but checked by the interpreter
+//! on every instruction fetch, load and store of a process. Regions can start
+//! and end at any address.
+
+use core::cell::Cell;
+use core::cmp;
+use core::fmt;
+use kernel::mpu::{self, Permissions};
+use kernel::AppId;
+
+/// Number of regions each process can have.
+pub const NUM_REGIONS: usize = 8;
+
+#[derive(Copy, Clone)]
+struct RegionConfig {
+    start: usize,
+    size: usize,
+    permissions: Permissions,
+}
+
+impl RegionConfig {
+    fn contains(&self, start: usize, len: usize) -> bool {
+        start >= self.start && start + len <= self.start + self.size
+    }
+}
+
+/// The kind of a memory access.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+fn permits(permissions: Permissions, access: Access) -> bool {
+    match (permissions, access) {
+        (Permissions::ReadWriteExecute, _) => true,
+        (Permissions::ReadWriteOnly, Access::Read)
+        | (Permissions::ReadWriteOnly, Access::Write) => true,
+        (Permissions::ReadExecuteOnly, Access::Read)
+        | (Permissions::ReadExecuteOnly, Access::Execute) => true,
+        (Permissions::ReadOnly, Access::Read) => true,
+        (Permissions::ExecuteOnly, Access::Execute) => true,
+        _ => false,
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+pub struct MpuConfig {
+    regions: [Option<RegionConfig>; NUM_REGIONS],
+    /// The region covering the part of the process RAM the process can
+    /// access, which grows and shrinks with the app break.
+    app_memory_region: Option<usize>,
+    /// The start and size of the memory below the stack the process may not
+    /// access, even where a region allows it.
+    stack_guard: Option<(usize, usize)>,
+}
+
+impl MpuConfig {
+    fn unused_region(&self) -> Option<usize> {
+        self.regions.iter().position(|region| region.is_none())
+    }
+}
+
+impl fmt::Display for MpuConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\r\n Software MPU")?;
+        if let Some((start, size)) = self.stack_guard {
+            write!(
+                f,
+                "\r\n  Stack guard: [{:#010X}:{:#010X}], length: {} bytes; ---",
+                start,
+                start + size,
+                size
+            )?;
+        }
+        for (i, region) in self.regions.iter().enumerate() {
+            match region {
+                Some(region) => write!(
+                    f,
+                    "\r\n  Region {}: [{:#010X}:{:#010X}], length: {} bytes; {}",
+                    i,
+                    region.start,
+                    region.start + region.size,
+                    region.size,
+                    match region.permissions {
+                        Permissions::ReadWriteExecute => "RWX",
+                        Permissions::ReadWriteOnly => "RW-",
+                        Permissions::ReadExecuteOnly => "R-X",
+                        Permissions::ReadOnly => "R--",
+                        Permissions::ExecuteOnly => "--X",
+                    }
+                )?,
+                None => write!(f, "\r\n  Region {}: Unused", i)?,
+            }
+        }
+        write!(f, "\r\n")
+    }
+}
+
+pub struct Mpu {
+    config: Cell<MpuConfig>,
+    enabled: Cell<bool>,
+}
+
+impl Mpu {
+    pub fn new() -> Mpu {
+        Mpu {
+            config: Cell::new(MpuConfig::default()),
+            enabled: Cell::new(false),
+        }
+    }
+
+    /// Returns whether the running process may access `len` bytes at
+    /// `address`. Nothing may be accessed while the MPU is disabled, nor in
+    /// the stack guard.
+    pub fn allows(&self, address: u32, len: u32, access: Access) -> bool {
+        let config = self.config.get();
+        let in_guard = config.stack_guard.map_or(false, |(start, size)| {
+            (address as usize) < start + size && start < (address as usize) + (len as usize)
+        });
+        self.enabled.get()
+            && !in_guard
+            && config.regions.iter().any(|region| {
+                region.map_or(false, |region| {
+                    region.contains(address as usize, len as usize)
+                        && permits(region.permissions, access)
+                })
+            })
+    }
+}
+
+impl mpu::MPU for Mpu {
+    type MpuConfig = MpuConfig;
+
+    fn clear_mpu(&self) {
+        self.config.set(MpuConfig::default());
+    }
+
+    fn enable_app_mpu(&self) {
+        self.enabled.set(true);
+    }
+
+    fn disable_app_mpu(&self) {
+        self.enabled.set(false);
+    }
+
+    fn number_total_regions(&self) -> usize {
+        NUM_REGIONS
+    }
+
+    fn allocate_region(
+        &self,
+        unallocated_memory_start: *const u8,
+        unallocated_memory_size: usize,
+        min_region_size: usize,
+        permissions: Permissions,
+        config: &mut MpuConfig,
+    ) -> Option<mpu::Region> {
+        if min_region_size > unallocated_memory_size {
+            return None;
+        }
+        let index = config.unused_region()?;
+        config.regions[index] = Some(RegionConfig {
+            start: unallocated_memory_start as usize,
+            size: min_region_size,
+            permissions,
+        });
+        Some(mpu::Region::new(unallocated_memory_start, min_region_size))
+    }
+
+    fn allocate_app_memory_region(
+        &self,
+        unallocated_memory_start: *const u8,
+        unallocated_memory_size: usize,
+        min_memory_size: usize,
+        initial_app_memory_size: usize,
+        initial_kernel_memory_size: usize,
+        permissions: Permissions,
+        config: &mut MpuConfig,
+    ) -> Option<(*const u8, usize)> {
+        let memory_size = cmp::max(
+            min_memory_size,
+            initial_app_memory_size + initial_kernel_memory_size,
+        );
+        if memory_size > unallocated_memory_size {
+            return None;
+        }
+        let index = config.unused_region()?;
+        config.regions[index] = Some(RegionConfig {
+            start: unallocated_memory_start as usize,
+            size: initial_app_memory_size,
+            permissions,
+        });
+        config.app_memory_region = Some(index);
+        Some((unallocated_memory_start, memory_size))
+    }
+
+    fn update_app_memory_region(
+        &self,
+        app_memory_break: *const u8,
+        kernel_memory_break: *const u8,
+        permissions: Permissions,
+        config: &mut MpuConfig,
+    ) -> Result<(), ()> {
+        if (app_memory_break as usize) > (kernel_memory_break as usize) {
+            return Err(());
+        }
+        let index = config.app_memory_region.ok_or(())?;
+        let region = config.regions[index].as_mut().ok_or(())?;
+        region.size = app_memory_break as usize - region.start;
+        region.permissions = permissions;
+        Ok(())
+    }
+
+    fn allocate_stack_guard(
+        &self,
+        stack_bottom: *const u8,
+        max_guard_size: usize,
+        config: &mut MpuConfig,
+    ) -> Option<mpu::Region> {
+        let start = (stack_bottom as usize).checked_sub(max_guard_size)?;
+        config.stack_guard = Some((start, max_guard_size));
+        Some(mpu::Region::new(start as *const u8, max_guard_size))
+    }
+
+    fn configure_mpu(&self, config: &MpuConfig, _app_id: &AppId) {
+        self.config.set(*config);
+    }
+}
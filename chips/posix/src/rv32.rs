@@ -0,0 +1,707 @@
+//! Interpreter for the RV32IMAC instruction set.
+//!
+//! Processes run in user mode, so only the unprivileged instructions are
+//! implemented: the base integer instructions, multiplication and division,
+//! atomics and compressed instructions. CSR instructions are illegal. Every
+//! instruction fetch, load and store is checked against the MPU, and
+//! misaligned loads and stores are allowed.
+//!
+//! Process memory is accessed through host pointers with the value of the
+//! address the process uses.
+
+use core::ptr;
+
+use crate::mpu::{Access, Mpu};
+
+/// Exception codes, as reported in the RISC-V `mcause` register.
+pub(crate) mod cause {
+    pub(crate) const INSTRUCTION_MISALIGNED: u32 = 0;
+    pub(crate) const INSTRUCTION_FAULT: u32 = 1;
+    pub(crate) const ILLEGAL_INSTRUCTION: u32 = 2;
+    pub(crate) const BREAKPOINT: u32 = 3;
+    pub(crate) const LOAD_FAULT: u32 = 5;
+    pub(crate) const STORE_MISALIGNED: u32 = 6;
+    pub(crate) const STORE_FAULT: u32 = 7;
+}
+
+/// Why an instruction did not complete.
+pub(crate) enum Trap {
+    /// The instruction was `ecall`.
+    EnvCall,
+    /// The instruction caused an exception. `tval` is the faulting address,
+    /// or the instruction for illegal instructions.
+    Exception { cause: u32, tval: u32 },
+}
+
+fn exception(cause: u32, tval: u32) -> Trap {
+    Trap::Exception { cause, tval }
+}
+
+#[derive(Copy, Clone)]
+enum AluOp {
+    Add,
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+}
+
+#[derive(Copy, Clone)]
+enum Op {
+    Lui {
+        rd: usize,
+        imm: u32,
+    },
+    Auipc {
+        rd: usize,
+        imm: u32,
+    },
+    Jal {
+        rd: usize,
+        offset: u32,
+    },
+    Jalr {
+        rd: usize,
+        rs1: usize,
+        offset: u32,
+    },
+    Branch {
+        funct3: u32,
+        rs1: usize,
+        rs2: usize,
+        offset: u32,
+    },
+    Load {
+        funct3: u32,
+        rd: usize,
+        rs1: usize,
+        offset: u32,
+    },
+    Store {
+        funct3: u32,
+        rs1: usize,
+        rs2: usize,
+        offset: u32,
+    },
+    AluImm {
+        op: AluOp,
+        rd: usize,
+        rs1: usize,
+        imm: u32,
+    },
+    AluReg {
+        op: AluOp,
+        rd: usize,
+        rs1: usize,
+        rs2: usize,
+    },
+    Amo {
+        funct5: u32,
+        rd: usize,
+        rs1: usize,
+        rs2: usize,
+    },
+    Fence,
+    Ecall,
+    Ebreak,
+    Illegal,
+}
+
+/// Sign-extends the lowest `bits` bits of `value`.
+fn sext(value: u32, bits: u32) -> u32 {
+    let shift = 32 - bits;
+    ((value << shift) as i32 >> shift) as u32
+}
+
+fn decode(inst: u32) -> Op {
+    let rd = ((inst >> 7) & 0x1f) as usize;
+    let rs1 = ((inst >> 15) & 0x1f) as usize;
+    let rs2 = ((inst >> 20) & 0x1f) as usize;
+    let funct3 = (inst >> 12) & 0x7;
+    let funct7 = inst >> 25;
+
+    let imm_i = sext(inst >> 20, 12);
+    let imm_s = sext(((inst >> 20) & 0xfe0) | ((inst >> 7) & 0x1f), 12);
+    let imm_b = sext(
+        ((inst >> 19) & 0x1000)
+            | ((inst << 4) & 0x800)
+            | ((inst >> 20) & 0x7e0)
+            | ((inst >> 7) & 0x1e),
+        13,
+    );
+    let imm_u = inst & 0xffff_f000;
+    let imm_j = sext(
+        ((inst >> 11) & 0x10_0000)
+            | (inst & 0xf_f000)
+            | ((inst >> 9) & 0x800)
+            | ((inst >> 20) & 0x7fe),
+        21,
+    );
+
+    match inst & 0x7f {
+        0x37 => Op::Lui { rd, imm: imm_u },
+        0x17 => Op::Auipc { rd, imm: imm_u },
+        0x6f => Op::Jal { rd, offset: imm_j },
+        0x67 if funct3 == 0 => Op::Jalr {
+            rd,
+            rs1,
+            offset: imm_i,
+        },
+        0x63 if funct3 != 2 && funct3 != 3 => Op::Branch {
+            funct3,
+            rs1,
+            rs2,
+            offset: imm_b,
+        },
+        0x03 if funct3 != 3 && funct3 < 6 => Op::Load {
+            funct3,
+            rd,
+            rs1,
+            offset: imm_i,
+        },
+        0x23 if funct3 < 3 => Op::Store {
+            funct3,
+            rs1,
+            rs2,
+            offset: imm_s,
+        },
+        0x13 => {
+            let op = match (funct3, funct7) {
+                (0, _) => AluOp::Add,
+                (1, 0x00) => AluOp::Sll,
+                (2, _) => AluOp::Slt,
+                (3, _) => AluOp::Sltu,
+                (4, _) => AluOp::Xor,
+                (5, 0x00) => AluOp::Srl,
+                (5, 0x20) => AluOp::Sra,
+                (6, _) => AluOp::Or,
+                (7, _) => AluOp::And,
+                _ => return Op::Illegal,
+            };
+            // Shift amounts are in the low bits of the immediate
+            let imm = if funct3 == 1 || funct3 == 5 {
+                rs2 as u32
+            } else {
+                imm_i
+            };
+            Op::AluImm { op, rd, rs1, imm }
+        }
+        0x33 => {
+            let op = match (funct7, funct3) {
+                (0x00, 0) => AluOp::Add,
+                (0x20, 0) => AluOp::Sub,
+                (0x00, 1) => AluOp::Sll,
+                (0x00, 2) => AluOp::Slt,
+                (0x00, 3) => AluOp::Sltu,
+                (0x00, 4) => AluOp::Xor,
+                (0x00, 5) => AluOp::Srl,
+                (0x20, 5) => AluOp::Sra,
+                (0x00, 6) => AluOp::Or,
+                (0x00, 7) => AluOp::And,
+                (0x01, 0) => AluOp::Mul,
+                (0x01, 1) => AluOp::Mulh,
+                (0x01, 2) => AluOp::Mulhsu,
+                (0x01, 3) => AluOp::Mulhu,
+                (0x01, 4) => AluOp::Div,
+                (0x01, 5) => AluOp::Divu,
+                (0x01, 6) => AluOp::Rem,
+                (0x01, 7) => AluOp::Remu,
+                _ => return Op::Illegal,
+            };
+            Op::AluReg { op, rd, rs1, rs2 }
+        }
+        0x2f if funct3 == 2 => {
+            let funct5 = inst >> 27;
+            match funct5 {
+                0x00 | 0x01 | 0x04 | 0x08 | 0x0c | 0x10 | 0x14 | 0x18 | 0x1c => Op::Amo {
+                    funct5,
+                    rd,
+                    rs1,
+                    rs2,
+                },
+                // lr.w must not have a source register
+                0x02 if rs2 == 0 => Op::Amo {
+                    funct5,
+                    rd,
+                    rs1,
+                    rs2,
+                },
+                0x03 => Op::Amo {
+                    funct5,
+                    rd,
+                    rs1,
+                    rs2,
+                },
+                _ => Op::Illegal,
+            }
+        }
+        0x0f => Op::Fence,
+        0x73 if inst == 0x0000_0073 => Op::Ecall,
+        0x73 if inst == 0x0010_0073 => Op::Ebreak,
+        _ => Op::Illegal,
+    }
+}
+
+/// Decodes a compressed instruction into the instruction it expands to.
+fn decode_compressed(inst: u16) -> Op {
+    let inst = inst as u32;
+    let rd = ((inst >> 7) & 0x1f) as usize;
+    let rs2 = ((inst >> 2) & 0x1f) as usize;
+    // Registers x8-x15, as encoded in three bits
+    let rd_short = (((inst >> 2) & 0x7) + 8) as usize;
+    let rs1_short = (((inst >> 7) & 0x7) + 8) as usize;
+
+    let imm6 = sext(((inst >> 7) & 0x20) | ((inst >> 2) & 0x1f), 6);
+    let shamt = (inst >> 2) & 0x1f;
+    let bit12 = inst & 0x1000 != 0;
+    let offset_j = sext(
+        ((inst >> 1) & 0x800)
+            | ((inst >> 7) & 0x10)
+            | ((inst >> 1) & 0x300)
+            | ((inst << 2) & 0x400)
+            | ((inst >> 1) & 0x40)
+            | ((inst << 1) & 0x80)
+            | ((inst >> 2) & 0xe)
+            | ((inst << 3) & 0x20),
+        12,
+    );
+    let offset_b = sext(
+        ((inst >> 4) & 0x100)
+            | ((inst >> 7) & 0x18)
+            | ((inst << 1) & 0xc0)
+            | ((inst >> 2) & 0x6)
+            | ((inst << 3) & 0x20),
+        9,
+    );
+    // Offset of c.lw and c.sw
+    let offset_w = ((inst >> 7) & 0x38) | ((inst >> 4) & 0x4) | ((inst << 1) & 0x40);
+
+    match (inst & 0x3, (inst >> 13) & 0x7) {
+        // c.addi4spn
+        (0, 0) => {
+            let imm = ((inst >> 7) & 0x30)
+                | ((inst >> 1) & 0x3c0)
+                | ((inst >> 4) & 0x4)
+                | ((inst >> 2) & 0x8);
+            if imm == 0 {
+                Op::Illegal
+            } else {
+                Op::AluImm {
+                    op: AluOp::Add,
+                    rd: rd_short,
+                    rs1: 2,
+                    imm,
+                }
+            }
+        }
+        // c.lw
+        (0, 2) => Op::Load {
+            funct3: 2,
+            rd: rd_short,
+            rs1: rs1_short,
+            offset: offset_w,
+        },
+        // c.sw
+        (0, 6) => Op::Store {
+            funct3: 2,
+            rs1: rs1_short,
+            rs2: rd_short,
+            offset: offset_w,
+        },
+        // c.addi
+        (1, 0) => Op::AluImm {
+            op: AluOp::Add,
+            rd,
+            rs1: rd,
+            imm: imm6,
+        },
+        // c.jal
+        (1, 1) => Op::Jal {
+            rd: 1,
+            offset: offset_j,
+        },
+        // c.li
+        (1, 2) => Op::AluImm {
+            op: AluOp::Add,
+            rd,
+            rs1: 0,
+            imm: imm6,
+        },
+        // c.addi16sp
+        (1, 3) if rd == 2 => {
+            let imm = sext(
+                ((inst >> 3) & 0x200)
+                    | ((inst >> 2) & 0x10)
+                    | ((inst << 1) & 0x40)
+                    | ((inst << 4) & 0x180)
+                    | ((inst << 3) & 0x20),
+                10,
+            );
+            if imm == 0 {
+                Op::Illegal
+            } else {
+                Op::AluImm {
+                    op: AluOp::Add,
+                    rd: 2,
+                    rs1: 2,
+                    imm,
+                }
+            }
+        }
+        // c.lui
+        (1, 3) => {
+            if imm6 == 0 {
+                Op::Illegal
+            } else {
+                Op::Lui {
+                    rd,
+                    imm: imm6 << 12,
+                }
+            }
+        }
+        (1, 4) => match (inst >> 10) & 0x3 {
+            // c.srli and c.srai, with shift amounts above 31 reserved
+            0 | 1 if bit12 => Op::Illegal,
+            0 => Op::AluImm {
+                op: AluOp::Srl,
+                rd: rs1_short,
+                rs1: rs1_short,
+                imm: shamt,
+            },
+            1 => Op::AluImm {
+                op: AluOp::Sra,
+                rd: rs1_short,
+                rs1: rs1_short,
+                imm: shamt,
+            },
+            // c.andi
+            2 => Op::AluImm {
+                op: AluOp::And,
+                rd: rs1_short,
+                rs1: rs1_short,
+                imm: imm6,
+            },
+            // c.sub, c.xor, c.or and c.and
+            _ if bit12 => Op::Illegal,
+            _ => {
+                let op = match (inst >> 5) & 0x3 {
+                    0 => AluOp::Sub,
+                    1 => AluOp::Xor,
+                    2 => AluOp::Or,
+                    _ => AluOp::And,
+                };
+                Op::AluReg {
+                    op,
+                    rd: rs1_short,
+                    rs1: rs1_short,
+                    rs2: rd_short,
+                }
+            }
+        },
+        // c.j
+        (1, 5) => Op::Jal {
+            rd: 0,
+            offset: offset_j,
+        },
+        // c.beqz and c.bnez
+        (1, 6) | (1, 7) => Op::Branch {
+            funct3: (inst >> 13) & 0x1,
+            rs1: rs1_short,
+            rs2: 0,
+            offset: offset_b,
+        },
+        // c.slli
+        (2, 0) if bit12 => Op::Illegal,
+        (2, 0) => Op::AluImm {
+            op: AluOp::Sll,
+            rd,
+            rs1: rd,
+            imm: shamt,
+        },
+        // c.lwsp
+        (2, 2) if rd == 0 => Op::Illegal,
+        (2, 2) => Op::Load {
+            funct3: 2,
+            rd,
+            rs1: 2,
+            offset: ((inst >> 7) & 0x20) | ((inst >> 2) & 0x1c) | ((inst << 4) & 0xc0),
+        },
+        (2, 4) => match (bit12, rd, rs2) {
+            (false, 0, 0) => Op::Illegal,
+            // c.jr
+            (false, _, 0) => Op::Jalr {
+                rd: 0,
+                rs1: rd,
+                offset: 0,
+            },
+            // c.mv
+            (false, _, _) => Op::AluReg {
+                op: AluOp::Add,
+                rd,
+                rs1: 0,
+                rs2,
+            },
+            (true, 0, 0) => Op::Ebreak,
+            // c.jalr
+            (true, _, 0) => Op::Jalr {
+                rd: 1,
+                rs1: rd,
+                offset: 0,
+            },
+            // c.add
+            (true, _, _) => Op::AluReg {
+                op: AluOp::Add,
+                rd,
+                rs1: rd,
+                rs2,
+            },
+        },
+        // c.swsp
+        (2, 6) => Op::Store {
+            funct3: 2,
+            rs1: 2,
+            rs2,
+            offset: ((inst >> 7) & 0x3c) | ((inst >> 1) & 0xc0),
+        },
+        _ => Op::Illegal,
+    }
+}
+
+fn alu(op: AluOp, a: u32, b: u32) -> u32 {
+    match op {
+        AluOp::Add => a.wrapping_add(b),
+        AluOp::Sub => a.wrapping_sub(b),
+        AluOp::Sll => a << (b & 0x1f),
+        AluOp::Slt => ((a as i32) < (b as i32)) as u32,
+        AluOp::Sltu => (a < b) as u32,
+        AluOp::Xor => a ^ b,
+        AluOp::Srl => a >> (b & 0x1f),
+        AluOp::Sra => ((a as i32) >> (b & 0x1f)) as u32,
+        AluOp::Or => a | b,
+        AluOp::And => a & b,
+        AluOp::Mul => a.wrapping_mul(b),
+        AluOp::Mulh => ((a as i32 as i64 * b as i32 as i64) >> 32) as u32,
+        AluOp::Mulhsu => ((a as i32 as i64 * b as i64) >> 32) as u32,
+        AluOp::Mulhu => ((a as u64 * b as u64) >> 32) as u32,
+        // Division by zero does not trap, but has defined results
+        AluOp::Div if b == 0 => u32::MAX,
+        AluOp::Div => (a as i32).wrapping_div(b as i32) as u32,
+        AluOp::Divu if b == 0 => u32::MAX,
+        AluOp::Divu => a / b,
+        AluOp::Rem if b == 0 => a,
+        AluOp::Rem => (a as i32).wrapping_rem(b as i32) as u32,
+        AluOp::Remu if b == 0 => a,
+        AluOp::Remu => a % b,
+    }
+}
+
+/// Reads `len` bytes at `address`, zero-extended.
+fn load(mpu: &Mpu, address: u32, len: u32, access: Access) -> Option<u32> {
+    if !mpu.allows(address, len, access) {
+        return None;
+    }
+    let host = address as usize;
+    // The MPU only allows accesses to process memory, which is mapped at
+    // host addresses equal to the addresses the process uses.
+    unsafe {
+        Some(match len {
+            1 => ptr::read(host as *const u8) as u32,
+            2 => ptr::read_unaligned(host as *const u16) as u32,
+            _ => ptr::read_unaligned(host as *const u32),
+        })
+    }
+}
+
+/// Writes the lowest `len` bytes of `value` to `address`.
+fn store(mpu: &Mpu, address: u32, len: u32, value: u32) -> Option<()> {
+    if !mpu.allows(address, len, Access::Write) {
+        return None;
+    }
+    let host = address as usize;
+    unsafe {
+        match len {
+            1 => ptr::write(host as *mut u8, value as u8),
+            2 => ptr::write_unaligned(host as *mut u16, value as u16),
+            _ => ptr::write_unaligned(host as *mut u32, value),
+        }
+    }
+    Some(())
+}
+
+/// The architectural state of a hart running a process.
+#[derive(Default)]
+pub(crate) struct Cpu {
+    /// The integer registers. `x0` is kept at zero.
+    pub(crate) regs: [u32; 32],
+    pub(crate) pc: u32,
+    /// The address reserved by the last `lr.w`.
+    reservation: Option<u32>,
+}
+
+impl Cpu {
+    fn set(&mut self, rd: usize, value: u32) {
+        if rd != 0 {
+            self.regs[rd] = value;
+        }
+    }
+
+    /// Executes the instruction at `pc`. If it traps, the registers and `pc`
+    /// are left unchanged.
+    pub(crate) fn step(&mut self, mpu: &Mpu) -> Result<(), Trap> {
+        let pc = self.pc;
+        if pc & 0x1 != 0 {
+            return Err(exception(cause::INSTRUCTION_MISALIGNED, pc));
+        }
+        let low = load(mpu, pc, 2, Access::Execute)
+            .ok_or_else(|| exception(cause::INSTRUCTION_FAULT, pc))?;
+        let (inst, op, len) = if low & 0x3 == 0x3 {
+            let inst = load(mpu, pc, 4, Access::Execute)
+                .ok_or_else(|| exception(cause::INSTRUCTION_FAULT, pc))?;
+            (inst, decode(inst), 4)
+        } else {
+            (low, decode_compressed(low as u16), 2)
+        };
+        let mut next = pc.wrapping_add(len);
+
+        match op {
+            Op::Lui { rd, imm } => self.set(rd, imm),
+            Op::Auipc { rd, imm } => self.set(rd, pc.wrapping_add(imm)),
+            Op::Jal { rd, offset } => {
+                self.set(rd, next);
+                next = pc.wrapping_add(offset);
+            }
+            Op::Jalr { rd, rs1, offset } => {
+                let target = self.regs[rs1].wrapping_add(offset) & !0x1;
+                self.set(rd, next);
+                next = target;
+            }
+            Op::Branch {
+                funct3,
+                rs1,
+                rs2,
+                offset,
+            } => {
+                let (a, b) = (self.regs[rs1], self.regs[rs2]);
+                let taken = match funct3 {
+                    0 => a == b,
+                    1 => a != b,
+                    4 => (a as i32) < (b as i32),
+                    5 => (a as i32) >= (b as i32),
+                    6 => a < b,
+                    _ => a >= b,
+                };
+                if taken {
+                    next = pc.wrapping_add(offset);
+                }
+            }
+            Op::Load {
+                funct3,
+                rd,
+                rs1,
+                offset,
+            } => {
+                let address = self.regs[rs1].wrapping_add(offset);
+                let len = 1 << (funct3 & 0x3);
+                let value = load(mpu, address, len, Access::Read)
+                    .ok_or_else(|| exception(cause::LOAD_FAULT, address))?;
+                let value = match funct3 {
+                    0 => sext(value, 8),
+                    1 => sext(value, 16),
+                    _ => value,
+                };
+                self.set(rd, value);
+            }
+            Op::Store {
+                funct3,
+                rs1,
+                rs2,
+                offset,
+            } => {
+                let address = self.regs[rs1].wrapping_add(offset);
+                store(mpu, address, 1 << funct3, self.regs[rs2])
+                    .ok_or_else(|| exception(cause::STORE_FAULT, address))?;
+            }
+            Op::AluImm { op, rd, rs1, imm } => self.set(rd, alu(op, self.regs[rs1], imm)),
+            Op::AluReg { op, rd, rs1, rs2 } => {
+                self.set(rd, alu(op, self.regs[rs1], self.regs[rs2]))
+            }
+            Op::Amo {
+                funct5,
+                rd,
+                rs1,
+                rs2,
+            } => {
+                let address = self.regs[rs1];
+                if address & 0x3 != 0 {
+                    return Err(exception(cause::STORE_MISALIGNED, address));
+                }
+                match funct5 {
+                    // lr.w
+                    0x02 => {
+                        let value = load(mpu, address, 4, Access::Read)
+                            .ok_or_else(|| exception(cause::LOAD_FAULT, address))?;
+                        self.reservation = Some(address);
+                        self.set(rd, value);
+                    }
+                    // sc.w
+                    0x03 => {
+                        if self.reservation.take() == Some(address) {
+                            store(mpu, address, 4, self.regs[rs2])
+                                .ok_or_else(|| exception(cause::STORE_FAULT, address))?;
+                            self.set(rd, 0);
+                        } else {
+                            self.set(rd, 1);
+                        }
+                    }
+                    _ => {
+                        if !mpu.allows(address, 4, Access::Write) {
+                            return Err(exception(cause::STORE_FAULT, address));
+                        }
+                        let old = load(mpu, address, 4, Access::Read)
+                            .ok_or_else(|| exception(cause::STORE_FAULT, address))?;
+                        let src = self.regs[rs2];
+                        let new = match funct5 {
+                            0x00 => old.wrapping_add(src),
+                            0x01 => src,
+                            0x04 => old ^ src,
+                            0x08 => old | src,
+                            0x0c => old & src,
+                            0x10 => (old as i32).min(src as i32) as u32,
+                            0x14 => (old as i32).max(src as i32) as u32,
+                            0x18 => old.min(src),
+                            _ => old.max(src),
+                        };
+                        store(mpu, address, 4, new)
+                            .ok_or_else(|| exception(cause::STORE_FAULT, address))?;
+                        self.set(rd, old);
+                    }
+                }
+            }
+            Op::Fence => {}
+            Op::Ecall => return Err(Trap::EnvCall),
+            Op::Ebreak => return Err(exception(cause::BREAKPOINT, pc)),
+            Op::Illegal => return Err(exception(cause::ILLEGAL_INSTRUCTION, inst)),
+        }
+
+        self.pc = next;
+        Ok(())
+    }
+
+    /// Forgets the reservation of `lr.w`, as happens on a context switch.
+    pub(crate) fn clear_reservation(&mut self) {
+        self.reservation = None;
+    }
+}
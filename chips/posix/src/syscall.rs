@@ -0,0 +1,214 @@
+//! Kernel-userland system call interface for interpreted RISC-V processes.
+//!
+//! Processes follow the calling convention of the `rv32i` architecture
+//! crate, so that apps built for RISC-V boards run unmodified. Instead of
+//! switching to the process, `switch_to_process()` interprets its
+//! instructions until it makes a system call, faults, or an interrupt is
+//! raised.
+
+use core::fmt::Write;
+use kernel::crash_record::{Architecture, Registers};
+use kernel::syscall::ContextSwitchReason;
+
+use crate::clock::Clock;
+use crate::interrupts::INTERRUPTS;
+use crate::mpu::Mpu;
+use crate::rv32::{cause, Cpu, Trap};
+
+/// This holds all of the state that the kernel must keep for the process when
+/// the process is not executing.
+#[derive(Default)]
+pub struct PosixStoredState {
+    /// The registers and PC of the process.
+    cpu: Cpu,
+
+    /// The exception code of the last fault, as in the RISC-V `mcause`
+    /// register.
+    mcause: u32,
+
+    /// The faulting address or instruction of the last fault, as in the
+    /// RISC-V `mtval` register.
+    mtval: u32,
+}
+
+// Register numbers of the RISC-V calling convention.
+const R_RA: usize = 1;
+const R_SP: usize = 2;
+const R_A0: usize = 10;
+const R_A1: usize = 11;
+const R_A2: usize = 12;
+const R_A3: usize = 13;
+const R_A4: usize = 14;
+
+/// Implementation of the `UserspaceKernelBoundary` for interpreted processes.
+pub struct SysCall<'a> {
+    /// Advanced by one tick for every instruction a process executes.
+    clock: &'a Clock<'a>,
+    mpu: &'a Mpu,
+}
+
+impl<'a> SysCall<'a> {
+    pub fn new(clock: &'a Clock<'a>, mpu: &'a Mpu) -> SysCall<'a> {
+        SysCall { clock, mpu }
+    }
+}
+
+impl kernel::syscall::UserspaceKernelBoundary for SysCall<'_> {
+    type StoredState = PosixStoredState;
+
+    unsafe fn initialize_process(
+        &self,
+        stack_pointer: *const usize,
+        _stack_size: usize,
+        state: &mut Self::StoredState,
+    ) -> Result<*const usize, ()> {
+        *state = PosixStoredState::default();
+
+        // The first time the process runs we need to set the initial stack
+        // pointer in the sp register.
+        state.cpu.regs[R_SP] = stack_pointer as u32;
+
+        Ok(stack_pointer)
+    }
+
+    unsafe fn set_syscall_return_value(
+        &self,
+        _stack_pointer: *const usize,
+        state: &mut Self::StoredState,
+        return_value: isize,
+    ) {
+        state.cpu.regs[R_A0] = return_value as u32;
+    }
+
+    unsafe fn set_process_function(
+        &self,
+        stack_pointer: *const usize,
+        _remaining_stack_memory: usize,
+        state: &mut PosixStoredState,
+        callback: kernel::procs::FunctionCall,
+    ) -> Result<*mut usize, *mut usize> {
+        state.cpu.regs[R_A0] = callback.argument0 as u32;
+        state.cpu.regs[R_A1] = callback.argument1 as u32;
+        state.cpu.regs[R_A2] = callback.argument2 as u32;
+        state.cpu.regs[R_A3] = callback.argument3 as u32;
+
+        // The function returns to where the process was executing. As with
+        // `rv32i`, this is meaningless the first time the process runs.
+        state.cpu.regs[R_RA] = state.cpu.pc;
+        state.cpu.pc = callback.pc as u32;
+
+        Ok(stack_pointer as *mut usize)
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        _stack_pointer: *const usize,
+        state: &mut PosixStoredState,
+    ) -> (*mut usize, ContextSwitchReason) {
+        // Interrupts raised while the kernel was running are serviced by the
+        // kernel loop, and must not stop the process right away.
+        INTERRUPTS.take_raised();
+        state.cpu.clear_reservation();
+
+        let reason = loop {
+            if INTERRUPTS.take_raised() {
+                break ContextSwitchReason::Interrupted;
+            }
+
+            let result = state.cpu.step(self.mpu);
+            self.clock.advance(1);
+            match result {
+                Ok(()) => {}
+                Err(Trap::EnvCall) => {
+                    state.cpu.pc = state.cpu.pc.wrapping_add(4);
+
+                    let regs = &state.cpu.regs;
+                    let syscall = kernel::syscall::arguments_to_syscall(
+                        regs[R_A0] as u8,
+                        regs[R_A1] as usize,
+                        regs[R_A2] as usize,
+                        regs[R_A3] as usize,
+                        regs[R_A4] as usize,
+                    );
+                    break match syscall {
+                        Some(s) => ContextSwitchReason::SyscallFired { syscall: s },
+                        None => ContextSwitchReason::Fault,
+                    };
+                }
+                Err(Trap::Exception { cause, tval }) => {
+                    state.mcause = cause;
+                    state.mtval = tval;
+                    break ContextSwitchReason::Fault;
+                }
+            }
+        };
+
+        (state.cpu.regs[R_SP] as usize as *mut usize, reason)
+    }
+
+    unsafe fn print_context(
+        &self,
+        stack_pointer: *const usize,
+        state: &PosixStoredState,
+        writer: &mut dyn Write,
+    ) {
+        let regs = &state.cpu.regs;
+        for i in 0..16 {
+            let _ = writer.write_fmt(format_args!(
+                "\r\n R{:<2}: {:#010X}    R{:<2}: {:#010X}",
+                i,
+                regs[i],
+                i + 16,
+                regs[i + 16],
+            ));
+        }
+        let _ = writer.write_fmt(format_args!(
+            "\
+             \r\n PC : {:#010X}    SP : {:#010X}\
+             \r\n\
+             \r\n mcause: {:#010X} ({})\
+             \r\n mtval:  {:#010X}\
+             \r\n\r\n",
+            state.cpu.pc,
+            stack_pointer as usize,
+            state.mcause,
+            match state.mcause {
+                cause::INSTRUCTION_MISALIGNED => "Instruction access misaligned",
+                cause::INSTRUCTION_FAULT => "Instruction access fault",
+                cause::ILLEGAL_INSTRUCTION => "Illegal instruction",
+                cause::BREAKPOINT => "Breakpoint",
+                cause::LOAD_FAULT => "Load access fault",
+                cause::STORE_MISALIGNED => "Store/AMO address misaligned",
+                cause::STORE_FAULT => "Store/AMO access fault",
+                _ => "No fault",
+            },
+            state.mtval,
+        ));
+    }
+
+    unsafe fn store_context(
+        &self,
+        _stack_pointer: *const usize,
+        state: &PosixStoredState,
+    ) -> Registers {
+        let mut registers = Registers::new(Architecture::RiscV32);
+        for &value in state.cpu.regs[1..].iter() {
+            registers.push(value as usize);
+        }
+        registers.push(state.cpu.pc as usize);
+        registers.push(state.mcause as usize);
+        registers.push(state.mtval as usize);
+        registers
+    }
+
+    unsafe fn fault_address(
+        &self,
+        _stack_pointer: *const usize,
+        state: &PosixStoredState,
+    ) -> Option<usize> {
+        match state.mcause {
+            cause::LOAD_FAULT | cause::STORE_FAULT => Some(state.mtval as usize),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! UART connected to the standard input and output of the host process.
+//!
+//! Transmitted data is written to stdout right away. Input is read from stdin
+//! by a separate thread, but is only handed to the kernel when the chip
+//! sleeps, so that input never interrupts a running process at a point in
+//! simulated time that depends on the speed of the host.
+
+use core::cell::{Cell, RefCell};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::uart;
+use kernel::ReturnCode;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use crate::interrupts::{self, INTERRUPTS};
+
+pub struct Uart<'a> {
+    tx_client: OptionalCell<&'a dyn uart::TransmitClient>,
+    rx_client: OptionalCell<&'a dyn uart::ReceiveClient>,
+
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_index: Cell<usize>,
+    /// Result of a reception that has completed or was aborted, to be
+    /// reported when the interrupt is serviced.
+    rx_result: Cell<Option<ReturnCode>>,
+
+    /// Chunks of input read by the stdin thread, which is started on the
+    /// first reception. `None` once stdin is closed.
+    input: RefCell<Option<Receiver<Vec<u8>>>>,
+    input_started: Cell<bool>,
+    /// Input that was read but not received yet.
+    unread: RefCell<VecDeque<u8>>,
+}
+
+impl<'a> Uart<'a> {
+    pub fn new() -> Uart<'a> {
+        Uart {
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_index: Cell::new(0),
+            rx_result: Cell::new(None),
+            input: RefCell::new(None),
+            input_started: Cell::new(false),
+            unread: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    fn start_input(&self) {
+        if self.input_started.get() {
+            return;
+        }
+        self.input_started.set(true);
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            let mut buf = [0; 64];
+            loop {
+                match stdin.lock().read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(len) => {
+                        if sender.send(buf[..len].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+            // Dropping the sender tells the UART that stdin is closed
+        });
+        self.input.replace(Some(receiver));
+    }
+
+    /// Returns whether a reception is waiting for input.
+    pub fn is_receiving(&self) -> bool {
+        self.rx_buffer.is_some() && self.rx_result.get().is_none()
+    }
+
+    /// Hands input to the outstanding reception. If `wait` is true, this
+    /// blocks until there is some input. Returns `false` if there is no
+    /// input, and if waiting, if stdin was closed.
+    pub fn receive_input(&self, wait: bool) -> bool {
+        if !self.is_receiving() {
+            return false;
+        }
+
+        if self.unread.borrow().is_empty() {
+            let mut input = self.input.borrow_mut();
+            let chunk = match input.as_ref() {
+                Some(receiver) if wait => receiver.recv().ok(),
+                Some(receiver) => match receiver.try_recv() {
+                    Ok(chunk) => Some(chunk),
+                    Err(TryRecvError::Empty) => return false,
+                    Err(TryRecvError::Disconnected) => None,
+                },
+                None => None,
+            };
+            match chunk {
+                Some(chunk) => self.unread.borrow_mut().extend(chunk),
+                None => {
+                    *input = None;
+                    return false;
+                }
+            }
+        }
+
+        let mut unread = self.unread.borrow_mut();
+        self.rx_buffer.map(|buffer| {
+            let mut index = self.rx_index.get();
+            while index < self.rx_len.get() {
+                match unread.pop_front() {
+                    Some(byte) => buffer[index] = byte,
+                    None => break,
+                }
+                index += 1;
+            }
+            self.rx_index.set(index);
+            if index == self.rx_len.get() {
+                self.rx_result.set(Some(ReturnCode::SUCCESS));
+                INTERRUPTS.raise(interrupts::UART0);
+            }
+        });
+        true
+    }
+
+    pub fn handle_interrupt(&self) {
+        if let Some(buffer) = self.tx_buffer.take() {
+            let len = self.tx_len.get();
+            self.tx_client
+                .map(move |client| client.transmitted_buffer(buffer, len, ReturnCode::SUCCESS));
+        }
+
+        if let Some(rval) = self.rx_result.take() {
+            if let Some(buffer) = self.rx_buffer.take() {
+                let error = if rval == ReturnCode::SUCCESS {
+                    uart::Error::None
+                } else {
+                    uart::Error::Aborted
+                };
+                let len = self.rx_index.get();
+                self.rx_client
+                    .map(move |client| client.received_buffer(buffer, len, rval, error));
+            }
+        }
+    }
+}
+
+impl uart::Configure for Uart<'_> {
+    fn configure(&self, _params: uart::Parameters) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a> uart::Transmit<'a> for Uart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.tx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(tx_buffer));
+        }
+        if tx_len > tx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(tx_buffer));
+        }
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        let _ = stdout.write_all(&tx_buffer[..tx_len]);
+        let _ = stdout.flush();
+
+        self.tx_len.set(tx_len);
+        self.tx_buffer.replace(tx_buffer);
+        INTERRUPTS.raise(interrupts::UART0);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn transmit_word(&self, _word: u32) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn transmit_abort(&self) -> ReturnCode {
+        // Transmissions complete immediately, so there is nothing to abort
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a> uart::Receive<'a> for Uart<'a> {
+    fn set_receive_client(&self, client: &'a dyn uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.rx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(rx_buffer));
+        }
+        if rx_len > rx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(rx_buffer));
+        }
+
+        self.start_input();
+        self.rx_len.set(rx_len);
+        self.rx_index.set(0);
+        self.rx_result.set(None);
+        self.rx_buffer.replace(rx_buffer);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn receive_word(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn receive_abort(&self) -> ReturnCode {
+        if !self.is_receiving() {
+            return ReturnCode::SUCCESS;
+        }
+        self.rx_result.set(Some(ReturnCode::ECANCEL));
+        INTERRUPTS.raise(interrupts::UART0);
+        ReturnCode::EBUSY
+    }
+}
+
+impl<'a> uart::Uart<'a> for Uart<'a> {}
+impl<'a> uart::UartData<'a> for Uart<'a> {}
@@ -6,6 +6,7 @@ use crate::pm;
 use core::fmt::Write;
 use cortexm4;
 use kernel::common::deferred_call;
+use kernel::power::SleepState;
 use kernel::{Chip, InterruptService};
 
 pub struct Sam4l<I: InterruptService<Task> + 'static> {
@@ -285,19 +286,30 @@ impl<I: InterruptService<Task> + 'static> Chip for Sam4l<I> {
     }
 
     fn sleep(&self) {
-        if pm::deep_sleep_ready() {
+        self.sleep_in(SleepState::DeepSleep);
+    }
+
+    fn sleep_in(&self, state: SleepState) -> SleepState {
+        // Deep sleep stops every clock that is not in the masks of
+        // `pm::deep_sleep_ready()`, so peripherals that use any other clock
+        // keep the chip out of it in addition to the kernel's constraints.
+        // There is no separate state between idle and deep sleep.
+        let state = if state == SleepState::DeepSleep && pm::deep_sleep_ready() {
             unsafe {
                 cortexm4::scb::set_sleepdeep();
             }
+            SleepState::DeepSleep
         } else {
             unsafe {
                 cortexm4::scb::unset_sleepdeep();
             }
-        }
+            SleepState::Idle
+        };
 
         unsafe {
             cortexm4::support::wfi();
         }
+        state
     }
 
     unsafe fn atomic<F, R>(&self, f: F) -> R
@@ -1,5 +1,10 @@
 //! Watchdog driver.
+//!
+//! The watchdog counts on the always-on low frequency clock and resets the
+//! chip if it is not fed for about a second. The kernel only starts it if the
+//! board called `enable()`, and stops the count while the chip sleeps.
 
+use core::cell::Cell;
 use kernel::common::registers::{register_bitfields, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 
@@ -39,13 +44,28 @@ register_bitfields![u32,
     ]
 ];
 
+/// The counter is compared against `wdogcmp` after being shifted right by
+/// `SCALE`, so with the 32.768 kHz clock the chip resets after
+/// `COMPARE << SCALE` ticks, which is 1 s.
+const SCALE: u32 = 10;
+const COMPARE: u32 = 32;
+
 pub struct Watchdog {
     registers: StaticRef<WatchdogRegisters>,
+    enabled: Cell<bool>,
 }
 
 impl Watchdog {
     pub const fn new(base: StaticRef<WatchdogRegisters>) -> Watchdog {
-        Watchdog { registers: base }
+        Watchdog {
+            registers: base,
+            enabled: Cell::new(false),
+        }
+    }
+
+    /// Have the kernel start the watchdog when it enters its main loop.
+    pub fn enable(&self) {
+        self.enabled.set(true);
     }
 
     fn unlock(&self) {
@@ -68,4 +88,51 @@ impl Watchdog {
         );
         self.feed();
     }
+
+    /// Make the counter run, or stop it while keeping its configuration.
+    fn set_counting(&self, counting: bool) {
+        self.unlock();
+        self.registers.wdogcfg.modify(if counting {
+            cfg::enalways::SET
+        } else {
+            cfg::enalways::CLEAR
+        });
+    }
+}
+
+impl kernel::watchdog::WatchDog for Watchdog {
+    fn setup(&self) {
+        if self.enabled.get() {
+            self.unlock();
+            self.registers.wdogcmp.set(COMPARE);
+            self.unlock();
+            self.registers.wdogcfg.write(
+                cfg::scale.val(SCALE)
+                    + cfg::rsten::SET
+                    + cfg::zerocmp::CLEAR
+                    + cfg::enalways::SET
+                    + cfg::encoreawake::CLEAR,
+            );
+            self.feed();
+        }
+    }
+
+    fn tickle(&self) {
+        if self.enabled.get() {
+            self.feed();
+        }
+    }
+
+    fn suspend(&self) {
+        if self.enabled.get() {
+            self.set_counting(false);
+        }
+    }
+
+    fn resume(&self) {
+        if self.enabled.get() {
+            self.feed();
+            self.set_counting(true);
+        }
+    }
 }
@@ -3,7 +3,7 @@
 use cortexm4::generic_isr;
 
 pub use stm32f4xx::{
-    adc, chip, dbg, dma1, exti, fsmc, gpio, i2c, nvic, rcc, spi, syscfg, tim2, trng, usart,
+    adc, chip, dbg, dma1, exti, fsmc, gpio, i2c, nvic, rcc, rtc, spi, syscfg, tim2, trng, usart,
 };
 
 pub mod interrupt_service;
@@ -2,7 +2,7 @@
 
 use cortexm4::generic_isr;
 
-pub use stm32f4xx::{adc, chip, dbg, dma1, exti, gpio, nvic, rcc, spi, syscfg, tim2, usart};
+pub use stm32f4xx::{adc, chip, dbg, dma1, exti, gpio, nvic, rcc, rtc, spi, syscfg, tim2, usart};
 
 pub mod interrupt_service;
 pub mod stm32f429zi_nvic;
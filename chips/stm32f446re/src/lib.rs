@@ -1,6 +1,6 @@
 #![no_std]
 
-pub use stm32f4xx::{chip, dbg, dma1, exti, gpio, nvic, rcc, spi, syscfg, tim2, usart};
+pub use stm32f4xx::{chip, dbg, dma1, exti, gpio, nvic, rcc, rtc, spi, syscfg, tim2, usart};
 
 pub mod interrupt_service;
 pub mod stm32f446re_nvic;
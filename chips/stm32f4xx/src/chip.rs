@@ -16,6 +16,7 @@ pub struct Stm32f4xx<'a, I: InterruptService<DeferredCallTask> + 'a> {
     userspace_kernel_boundary: cortexm4::syscall::SysCall,
     scheduler_timer: cortexm4::systick::SysTick,
     interrupt_service: &'a I,
+    watchdog: crate::iwdg::Iwdg,
 }
 
 pub struct Stm32f4xxDefaultPeripherals<'a> {
@@ -23,6 +24,7 @@ pub struct Stm32f4xxDefaultPeripherals<'a> {
     pub dma_streams: [crate::dma1::Stream<'a>; 8],
     pub exti: &'a crate::exti::Exti<'a>,
     pub i2c1: crate::i2c::I2C<'a>,
+    pub rtc: crate::rtc::Rtc<'a>,
     pub spi3: crate::spi::Spi<'a>,
     pub tim2: crate::tim2::Tim2<'a>,
     pub usart2: crate::usart::Usart<'a>,
@@ -42,6 +44,7 @@ impl<'a> Stm32f4xxDefaultPeripherals<'a> {
             dma_streams: crate::dma1::new_dma1_stream(dma),
             exti,
             i2c1: crate::i2c::I2C::new(rcc),
+            rtc: crate::rtc::Rtc::new(rcc),
             spi3: crate::spi::Spi::new(
                 crate::spi::SPI3_BASE,
                 crate::spi::SpiClock(crate::rcc::PeripheralClock::new(
@@ -134,15 +137,22 @@ impl<'a, I: InterruptService<DeferredCallTask> + 'a> Stm32f4xx<'a, I> {
             userspace_kernel_boundary: cortexm4::syscall::SysCall::new(),
             scheduler_timer: cortexm4::systick::SysTick::new(),
             interrupt_service,
+            watchdog: crate::iwdg::Iwdg::new(),
         }
     }
+
+    /// Start the independent watchdog when the kernel enters its main loop.
+    /// Once started it cannot be stopped until the chip resets.
+    pub fn enable_watchdog(&self) {
+        self.watchdog.enable();
+    }
 }
 
 impl<'a, I: InterruptService<DeferredCallTask> + 'a> Chip for Stm32f4xx<'a, I> {
     type MPU = cortexm4::mpu::MPU;
     type UserspaceKernelBoundary = cortexm4::syscall::SysCall;
     type SchedulerTimer = cortexm4::systick::SysTick;
-    type WatchDog = ();
+    type WatchDog = crate::iwdg::Iwdg;
 
     fn service_pending_interrupts(&self) {
         unsafe {
@@ -179,7 +189,7 @@ impl<'a, I: InterruptService<DeferredCallTask> + 'a> Chip for Stm32f4xx<'a, I> {
     }
 
     fn watchdog(&self) -> &Self::WatchDog {
-        &()
+        &self.watchdog
     }
 
     fn userspace_kernel_boundary(&self) -> &cortexm4::syscall::SysCall {
@@ -0,0 +1,112 @@
+//! Independent watchdog (IWDG)
+//!
+//! The IWDG counts down on the internal low speed oscillator (LSI, about
+//! 32 kHz) and resets the chip when it reaches zero. Once started it can
+//! neither be stopped nor paused while the chip sleeps, so it is only started
+//! if the board called `Stm32f4xx::enable_watchdog()`, and uses the longest
+//! timeout the hardware supports, about 32 s. Boards that enable it must make
+//! sure the kernel wakes up more often than that, for example with a periodic
+//! alarm.
+
+use core::cell::Cell;
+use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+
+const IWDG_BASE: StaticRef<IwdgRegisters> =
+    unsafe { StaticRef::new(0x4000_3000 as *const IwdgRegisters) };
+
+#[repr(C)]
+struct IwdgRegisters {
+    /// Key register
+    kr: WriteOnly<u32, Key::Register>,
+    /// Prescaler register
+    pr: ReadWrite<u32, Prescaler::Register>,
+    /// Reload register
+    rlr: ReadWrite<u32, Reload::Register>,
+    /// Status register
+    sr: ReadOnly<u32, Status::Register>,
+}
+
+register_bitfields![u32,
+    Key [
+        KEY OFFSET(0) NUMBITS(16) [
+            /// Give write access to the prescaler and reload registers
+            Unlock = 0x5555,
+            /// Reload the counter with the reload value
+            Reload = 0xAAAA,
+            /// Start the watchdog
+            Start = 0xCCCC
+        ]
+    ],
+    Prescaler [
+        PR OFFSET(0) NUMBITS(3) [
+            DivideBy4 = 0,
+            DivideBy8 = 1,
+            DivideBy16 = 2,
+            DivideBy32 = 3,
+            DivideBy64 = 4,
+            DivideBy128 = 5,
+            DivideBy256 = 6
+        ]
+    ],
+    Reload [
+        RL OFFSET(0) NUMBITS(12) []
+    ],
+    Status [
+        /// Watchdog counter reload value update
+        RVU OFFSET(1) NUMBITS(1) [],
+        /// Watchdog prescaler value update
+        PVU OFFSET(0) NUMBITS(1) []
+    ]
+];
+
+pub struct Iwdg {
+    registers: StaticRef<IwdgRegisters>,
+    enabled: Cell<bool>,
+}
+
+impl Iwdg {
+    pub const fn new() -> Iwdg {
+        Iwdg {
+            registers: IWDG_BASE,
+            enabled: Cell::new(false),
+        }
+    }
+
+    /// Have the kernel start the watchdog when it enters its main loop.
+    pub fn enable(&self) {
+        self.enabled.set(true);
+    }
+
+    fn start(&self) {
+        let regs = &*self.registers;
+        // Starting the watchdog also starts the LSI.
+        regs.kr.write(Key::KEY::Start);
+        regs.kr.write(Key::KEY::Unlock);
+        // LSI / 256 = 125 Hz, so 4096 counts take about 32.8 s.
+        regs.pr.write(Prescaler::PR::DivideBy256);
+        regs.rlr.write(Reload::RL.val(0xFFF));
+        // The new values only take effect once they have crossed into the
+        // LSI clock domain.
+        while regs.sr.is_set(Status::PVU) || regs.sr.is_set(Status::RVU) {}
+        self.reload();
+    }
+
+    fn reload(&self) {
+        self.registers.kr.write(Key::KEY::Reload);
+    }
+}
+
+impl kernel::watchdog::WatchDog for Iwdg {
+    fn setup(&self) {
+        if self.enabled.get() {
+            self.start();
+        }
+    }
+
+    fn tickle(&self) {
+        if self.enabled.get() {
+            self.reload();
+        }
+    }
+}
@@ -20,7 +20,9 @@ pub mod exti;
 pub mod fsmc;
 pub mod gpio;
 pub mod i2c;
+pub mod iwdg;
 pub mod rcc;
+pub mod rtc;
 pub mod spi;
 pub mod syscfg;
 pub mod tim2;
@@ -738,6 +738,45 @@ impl Rcc {
         self.registers.cr.modify(CR::PLLON::SET);
     }
 
+    // PWR clock
+
+    fn is_enabled_pwr_clock(&self) -> bool {
+        self.registers.apb1enr.is_set(APB1ENR::PWREN)
+    }
+
+    fn enable_pwr_clock(&self) {
+        self.registers.apb1enr.modify(APB1ENR::PWREN::SET);
+    }
+
+    fn disable_pwr_clock(&self) {
+        self.registers.apb1enr.modify(APB1ENR::PWREN::CLEAR)
+    }
+
+    // RTC clock
+    //
+    // These registers are in the backup domain, which is write protected
+    // until the PWR peripheral gives access to it.
+
+    pub(crate) fn enable_lse(&self) {
+        self.registers.bdcr.modify(BDCR::LSEON::SET);
+    }
+
+    pub(crate) fn is_lse_ready(&self) -> bool {
+        self.registers.bdcr.is_set(BDCR::LSERDY)
+    }
+
+    pub(crate) fn is_enabled_rtc_clock(&self) -> bool {
+        self.registers.bdcr.is_set(BDCR::RTCEN)
+    }
+
+    /// Clock the RTC from the LSE. The source can only be selected once,
+    /// until the backup domain is reset.
+    pub(crate) fn enable_rtc_clock_from_lse(&self) {
+        self.registers
+            .bdcr
+            .modify(BDCR::RTCSEL.val(0b01) + BDCR::RTCEN::SET);
+    }
+
     // I2C1 clock
 
     fn is_enabled_i2c1_clock(&self) -> bool {
@@ -1055,6 +1094,7 @@ pub enum HCLK2 {
 
 /// Peripherals clocked by PCLK1
 pub enum PCLK1 {
+    PWR,
     TIM2,
     USART2,
     USART3,
@@ -1100,6 +1140,7 @@ impl<'a> ClockInterface for PeripheralClock<'a> {
                 HCLK3::FMC => self.rcc.is_enabled_fmc_clock(),
             },
             PeripheralClockType::APB1(ref v) => match v {
+                PCLK1::PWR => self.rcc.is_enabled_pwr_clock(),
                 PCLK1::TIM2 => self.rcc.is_enabled_tim2_clock(),
                 PCLK1::USART2 => self.rcc.is_enabled_usart2_clock(),
                 PCLK1::USART3 => self.rcc.is_enabled_usart3_clock(),
@@ -1156,6 +1197,9 @@ impl<'a> ClockInterface for PeripheralClock<'a> {
                 HCLK3::FMC => self.rcc.enable_fmc_clock(),
             },
             PeripheralClockType::APB1(ref v) => match v {
+                PCLK1::PWR => {
+                    self.rcc.enable_pwr_clock();
+                }
                 PCLK1::TIM2 => {
                     self.rcc.enable_tim2_clock();
                 }
@@ -1226,6 +1270,9 @@ impl<'a> ClockInterface for PeripheralClock<'a> {
                 HCLK3::FMC => self.rcc.disable_fmc_clock(),
             },
             PeripheralClockType::APB1(ref v) => match v {
+                PCLK1::PWR => {
+                    self.rcc.disable_pwr_clock();
+                }
                 PCLK1::TIM2 => {
                     self.rcc.disable_tim2_clock();
                 }
@@ -0,0 +1,261 @@
+//! Real-time clock (RTC)
+//!
+//! The RTC keeps a calendar in BCD, counting on the 32.768 kHz external low
+//! speed oscillator (LSE). Both are in the backup domain, which is powered
+//! from VBAT when the main supply is off, so with a battery on VBAT the date
+//! and time survive resets and power cycles. The RTC implements
+//! `hil::date_time::DateTime` for the years 2000 to 2099, as the calendar
+//! only holds two digits of the year.
+//!
+//! The hardware flag for a set calendar (INITS) only tells whether the year
+//! is not 0, so it cannot tell 2000 from a calendar that was never set.
+//! Instead, setting the clock writes a magic value to the first backup
+//! register, which is in the backup domain as well and is cleared with it.
+//!
+//! The board calls `enable()` at boot, which starts the LSE if the RTC is not
+//! already running from an earlier boot. The LSE takes a while to start, so
+//! setting the clock returns `EOFF` until it has.
+
+use kernel::common::registers::{register_bitfields, ReadWrite};
+use kernel::common::StaticRef;
+use kernel::hil::date_time::{self, DateTimeValues};
+use kernel::ClockInterface;
+use kernel::ReturnCode;
+
+use crate::rcc;
+
+#[repr(C)]
+struct RtcRegisters {
+    /// time register
+    tr: ReadWrite<u32, TR::Register>,
+    /// date register
+    dr: ReadWrite<u32, DR::Register>,
+    /// control register
+    cr: ReadWrite<u32, CR::Register>,
+    /// initialization and status register
+    isr: ReadWrite<u32, ISR::Register>,
+    /// prescaler register
+    prer: ReadWrite<u32, PRER::Register>,
+    /// wakeup timer register
+    wutr: ReadWrite<u32>,
+    /// calibration register
+    calibr: ReadWrite<u32>,
+    /// alarm A register
+    alrmar: ReadWrite<u32>,
+    /// alarm B register
+    alrmbr: ReadWrite<u32>,
+    /// write protection register
+    wpr: ReadWrite<u32, WPR::Register>,
+    _reserved0: [u32; 10],
+    /// backup register 0
+    bkp0r: ReadWrite<u32>,
+}
+
+/// Power controller, which controls write access to the backup domain.
+#[repr(C)]
+struct PwrRegisters {
+    /// power control register
+    cr: ReadWrite<u32, PWR_CR::Register>,
+    /// power control/status register
+    csr: ReadWrite<u32>,
+}
+
+register_bitfields![u32,
+    TR [
+        /// AM/PM notation
+        PM OFFSET(22) NUMBITS(1) [],
+        /// Hour tens in BCD format
+        HT OFFSET(20) NUMBITS(2) [],
+        /// Hour units in BCD format
+        HU OFFSET(16) NUMBITS(4) [],
+        /// Minute tens in BCD format
+        MNT OFFSET(12) NUMBITS(3) [],
+        /// Minute units in BCD format
+        MNU OFFSET(8) NUMBITS(4) [],
+        /// Second tens in BCD format
+        ST OFFSET(4) NUMBITS(3) [],
+        /// Second units in BCD format
+        SU OFFSET(0) NUMBITS(4) []
+    ],
+    DR [
+        /// Year tens in BCD format
+        YT OFFSET(20) NUMBITS(4) [],
+        /// Year units in BCD format
+        YU OFFSET(16) NUMBITS(4) [],
+        /// Week day units, 1 for Monday to 7 for Sunday
+        WDU OFFSET(13) NUMBITS(3) [],
+        /// Month tens in BCD format
+        MT OFFSET(12) NUMBITS(1) [],
+        /// Month units in BCD format
+        MU OFFSET(8) NUMBITS(4) [],
+        /// Date tens in BCD format
+        DT OFFSET(4) NUMBITS(2) [],
+        /// Date units in BCD format
+        DU OFFSET(0) NUMBITS(4) []
+    ],
+    CR [
+        /// Hour format, 12 hours when set
+        FMT OFFSET(6) NUMBITS(1) [],
+        /// Bypass the shadow registers
+        BYPSHAD OFFSET(5) NUMBITS(1) []
+    ],
+    ISR [
+        /// Initialization mode
+        INIT OFFSET(7) NUMBITS(1) [],
+        /// Initialization flag, the calendar can be written
+        INITF OFFSET(6) NUMBITS(1) [],
+        /// Registers synchronization flag
+        RSF OFFSET(5) NUMBITS(1) []
+    ],
+    PRER [
+        /// Asynchronous prescaler factor
+        PREDIV_A OFFSET(16) NUMBITS(7) [],
+        /// Synchronous prescaler factor
+        PREDIV_S OFFSET(0) NUMBITS(15) []
+    ],
+    WPR [
+        KEY OFFSET(0) NUMBITS(8) []
+    ],
+    PWR_CR [
+        /// Disable backup domain write protection
+        DBP OFFSET(8) NUMBITS(1) []
+    ]
+];
+
+/// Value of the first backup register once the calendar was set.
+const CALENDAR_SET: u32 = 0x5443_4b31;
+
+const RTC_BASE: StaticRef<RtcRegisters> =
+    unsafe { StaticRef::new(0x4000_2800 as *const RtcRegisters) };
+
+const PWR_BASE: StaticRef<PwrRegisters> =
+    unsafe { StaticRef::new(0x4000_7000 as *const PwrRegisters) };
+
+pub struct Rtc<'a> {
+    registers: StaticRef<RtcRegisters>,
+    pwr: StaticRef<PwrRegisters>,
+    pwr_clock: rcc::PeripheralClock<'a>,
+    rcc: &'a rcc::Rcc,
+}
+
+impl<'a> Rtc<'a> {
+    pub const fn new(rcc: &'a rcc::Rcc) -> Self {
+        Rtc {
+            registers: RTC_BASE,
+            pwr: PWR_BASE,
+            pwr_clock: rcc::PeripheralClock::new(
+                rcc::PeripheralClockType::APB1(rcc::PCLK1::PWR),
+                rcc,
+            ),
+            rcc,
+        }
+    }
+
+    /// Give the kernel write access to the backup domain, and start the LSE
+    /// unless the RTC is already running.
+    pub fn enable(&self) {
+        self.pwr_clock.enable();
+        self.pwr.cr.modify(PWR_CR::DBP::SET);
+        if !self.rcc.is_enabled_rtc_clock() {
+            self.rcc.enable_lse();
+        }
+    }
+
+    /// Clock the RTC from the LSE once the LSE is ready. Returns whether the
+    /// RTC is running.
+    fn start(&self) -> bool {
+        if self.rcc.is_enabled_rtc_clock() {
+            return true;
+        }
+        if !self.rcc.is_lse_ready() {
+            return false;
+        }
+        self.rcc.enable_rtc_clock_from_lse();
+        true
+    }
+}
+
+/// Splits a value below 100 into its BCD tens and units.
+fn to_bcd(value: u8) -> (u32, u32) {
+    ((value / 10) as u32, (value % 10) as u32)
+}
+
+fn from_bcd(tens: u32, units: u32) -> u8 {
+    (tens * 10 + units) as u8
+}
+
+impl date_time::DateTime for Rtc<'_> {
+    fn get_date_time(&self) -> Result<DateTimeValues, ReturnCode> {
+        let regs = &*self.registers;
+        if !self.rcc.is_enabled_rtc_clock() || regs.bkp0r.get() != CALENDAR_SET {
+            return Err(ReturnCode::EOFF);
+        }
+        // The shadow registers are copied from the calendar every two RTC
+        // clock cycles, and only once RSF is set after a reset or after the
+        // calendar was set.
+        while !regs.isr.is_set(ISR::RSF) {}
+        // Reading TR locks DR until it is read, so both are consistent.
+        let tr = regs.tr.extract();
+        let dr = regs.dr.extract();
+        Ok(DateTimeValues {
+            year: 2000 + from_bcd(dr.read(DR::YT), dr.read(DR::YU)) as u16,
+            month: from_bcd(dr.read(DR::MT), dr.read(DR::MU)),
+            day: from_bcd(dr.read(DR::DT), dr.read(DR::DU)),
+            hour: from_bcd(tr.read(TR::HT), tr.read(TR::HU)),
+            minute: from_bcd(tr.read(TR::MNT), tr.read(TR::MNU)),
+            second: from_bcd(tr.read(TR::ST), tr.read(TR::SU)),
+        })
+    }
+
+    fn set_date_time(&self, date_time: DateTimeValues) -> ReturnCode {
+        if !date_time.is_valid() || date_time.year < 2000 || date_time.year > 2099 {
+            return ReturnCode::EINVAL;
+        }
+        if !self.start() {
+            return ReturnCode::EOFF;
+        }
+        let regs = &*self.registers;
+        let (year_tens, year_units) = to_bcd((date_time.year - 2000) as u8);
+        let (month_tens, month_units) = to_bcd(date_time.month);
+        let (day_tens, day_units) = to_bcd(date_time.day);
+        let (hour_tens, hour_units) = to_bcd(date_time.hour);
+        let (minute_tens, minute_units) = to_bcd(date_time.minute);
+        let (second_tens, second_units) = to_bcd(date_time.second);
+
+        regs.wpr.write(WPR::KEY.val(0xCA));
+        regs.wpr.write(WPR::KEY.val(0x53));
+        regs.isr.modify(ISR::INIT::SET);
+        while !regs.isr.is_set(ISR::INITF) {}
+
+        // 32768 Hz / (127 + 1) / (255 + 1) = 1 Hz. The two prescalers must be
+        // written separately.
+        regs.prer.write(PRER::PREDIV_S.val(255));
+        regs.prer.modify(PRER::PREDIV_A.val(127));
+        regs.cr.modify(CR::FMT::CLEAR);
+        regs.tr.write(
+            TR::HT.val(hour_tens)
+                + TR::HU.val(hour_units)
+                + TR::MNT.val(minute_tens)
+                + TR::MNU.val(minute_units)
+                + TR::ST.val(second_tens)
+                + TR::SU.val(second_units),
+        );
+        regs.dr.write(
+            DR::YT.val(year_tens)
+                + DR::YU.val(year_units)
+                + DR::WDU.val(date_time.weekday() as u32)
+                + DR::MT.val(month_tens)
+                + DR::MU.val(month_units)
+                + DR::DT.val(day_tens)
+                + DR::DU.val(day_units),
+        );
+
+        regs.isr.modify(ISR::INIT::CLEAR);
+        // The shadow registers hold the old date and time until the next
+        // synchronization.
+        regs.isr.modify(ISR::RSF::CLEAR);
+        regs.wpr.write(WPR::KEY.val(0xFF));
+        regs.bkp0r.set(CALENDAR_SET);
+        ReturnCode::SUCCESS
+    }
+}
@@ -151,6 +151,14 @@ impl AppId {
             (start, end)
         })
     }
+
+    /// Returns the package name of the app from its TBF header. This is an
+    /// empty string if the app did not specify a package name, or if the app
+    /// no longer exists.
+    pub fn get_package_name(&self) -> &'static str {
+        self.kernel
+            .process_map_or("", *self, |process| process.get_process_name())
+    }
 }
 
 /// Type to uniquely identify a callback subscription across all drivers.
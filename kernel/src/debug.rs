@@ -59,6 +59,7 @@ use crate::common::cells::{MapCell, TakeCell};
 use crate::common::queue::Queue;
 use crate::common::ring_buffer::RingBuffer;
 use crate::hil;
+use crate::platform::crash_record;
 use crate::process::ProcessType;
 use crate::Chip;
 use crate::ReturnCode;
@@ -105,6 +106,7 @@ pub unsafe fn panic<L: hil::led::Led, W: Write + IoWrite, C: Chip>(
     flush(writer);
     panic_cpu_state(chip, writer);
     panic_process_info(processes, writer);
+    panic_crash_record(chip, writer, panic_info);
     panic_blink_forever(leds)
 }
 
@@ -145,6 +147,29 @@ pub unsafe fn panic_cpu_state<W: Write, C: Chip>(
     });
 }
 
+/// Store a record of the panic with the crash store the board set with
+/// `crash_record::set_crash_store()`, if any, and print whether it was saved.
+///
+/// **NOTE:** The supplied `writer` must be synchronous.
+pub unsafe fn panic_crash_record<W: Write, C: Chip>(
+    chip: &'static Option<&'static C>,
+    writer: &mut W,
+    panic_info: &PanicInfo,
+) {
+    let service = || {
+        chip.map(|c| c.service_pending_interrupts());
+    };
+    match crash_record::store_panic(panic_info, &service) {
+        Some(true) => {
+            let _ = writer.write_fmt(format_args!("\r\nCrash record saved\r\n"));
+        }
+        Some(false) => {
+            let _ = writer.write_fmt(format_args!("\r\nCrash record not saved\r\n"));
+        }
+        None => {}
+    }
+}
+
 /// More detailed prints about all processes.
 ///
 /// **NOTE:** The supplied `writer` must be synchronous.
@@ -0,0 +1,93 @@
+//! Interface for the link layer of Bluetooth Low Energy connections.
+//!
+//! Unlike advertisements, packets of connectable advertising and of
+//! connections are answered by the peer exactly the inter frame space (T_IFS,
+//! 150 µs) after the end of the packet they answer. That is too short for the
+//! link layer to turn the radio around, so the radio does it: it switches
+//! from transmitting to receiving (`transmit_and_listen`) or from receiving
+//! to transmitting (`listen_and_respond`) on its own, and only asks the link
+//! layer for the contents of its response. As even that may come too late,
+//! the link layer prepares a response before the packet arrives, and replaces
+//! it once the packet is received if there is still time.
+//!
+//! Packets are passed as PDUs: the 2 byte header (whose second byte is the
+//! length of the payload) followed by the payload. The radio adds the access
+//! address and the CRC.
+//!
+//! BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.1 Inter
+//! Frame Space
+
+use crate::hil::ble_advertising::RadioChannel;
+use crate::returncode::ReturnCode;
+
+/// The access address of advertising channel packets.
+pub const ADVERTISING_ACCESS_ADDRESS: u32 = 0x8E89BED6;
+
+/// The CRC initial value of advertising channel packets.
+pub const ADVERTISING_CRC_INIT: u32 = 0x555555;
+
+/// The inter frame space, in microseconds.
+pub const T_IFS_US: u32 = 150;
+
+/// The maximum length of a PDU, header included.
+pub const MAX_PDU_LEN: usize = 2 + 255;
+
+pub trait LinkLayerRadio<'a> {
+    fn set_link_layer_client(&self, client: &'a dyn LinkLayerClient);
+
+    /// Sets the access address and the CRC initial value used by the next
+    /// operations.
+    fn set_access_address(&self, access_address: u32, crc_init: u32);
+
+    /// Transmits `pdu` on `channel`, then listens on the same channel until
+    /// `stop_listening` is called. A packet received is answered T_IFS after
+    /// its end with the response of `LinkLayerClient::prepare_response`, or
+    /// the one `LinkLayerClient::respond` replaces it with.
+    ///
+    /// Returns EBUSY if the radio is in use, and ESIZE if `pdu` is too long.
+    fn transmit_and_listen(&self, pdu: &[u8], channel: RadioChannel) -> ReturnCode;
+
+    /// Listens on `channel` until `stop_listening` is called. A packet
+    /// received is answered as with `transmit_and_listen`.
+    ///
+    /// Returns EBUSY if the radio is in use.
+    fn listen_and_respond(&self, channel: RadioChannel) -> ReturnCode;
+
+    /// Stops listening. Returns SUCCESS if the radio stopped, in which case
+    /// `LinkLayerClient::operation_done` is not called. Returns EBUSY if a
+    /// packet is being received: the operation then completes as usual.
+    /// Returns EALREADY if there is no operation in progress.
+    fn stop_listening(&self) -> ReturnCode;
+}
+
+pub trait LinkLayerClient {
+    /// The radio is listening and will respond to the next packet. The client
+    /// writes the response that is transmitted if `respond` cannot replace it
+    /// in time, and returns its length, or returns 0 to not respond to any
+    /// packet.
+    ///
+    /// The response must be valid whatever packet is received, as the radio
+    /// starts transmitting it T_IFS after the end of the packet whether or
+    /// not the client has looked at the packet yet.
+    fn prepare_response(&self, response: &mut [u8]) -> usize;
+
+    /// A packet was received. `pdu` is the packet, and `crc_ok` whether its
+    /// CRC was valid. `response` holds the prepared response: the client may
+    /// replace it and return the length of its response, or return 0 to not
+    /// respond.
+    ///
+    /// `response` is `None` if it is too late to change the response: the
+    /// prepared response, if there was one, is being transmitted, and the
+    /// return value is ignored.
+    ///
+    /// This is called between the end of the packet and the start of the
+    /// response, so it must return within a few tens of microseconds:
+    /// anything longer than copying a prepared response belongs in
+    /// `operation_done`.
+    fn respond(&self, pdu: &[u8], crc_ok: bool, response: Option<&mut [u8]>) -> usize;
+
+    /// The operation completed: a packet was received and answered, if
+    /// there was a response. `result` is SUCCESS, or FAIL if the response
+    /// could not be transmitted.
+    fn operation_done(&self, result: ReturnCode);
+}
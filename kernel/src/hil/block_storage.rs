@@ -0,0 +1,55 @@
+//! Interface for block devices such as SD cards and disk images.
+//!
+//! A `BlockStorage` device reads and writes whole blocks, addressed by their
+//! block number. Only one operation can be outstanding at a time: the client
+//! must wait for the completion callback of an operation before starting
+//! another one.
+
+use crate::returncode::ReturnCode;
+
+pub trait BlockStorage<'a> {
+    /// Set the client to be used for callbacks.
+    fn set_client(&self, client: &'a dyn BlockStorageClient);
+
+    /// Size of a block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// Number of blocks of the device, or 0 if it is not known yet, for
+    /// example because the device was not initialized by a first operation.
+    fn num_blocks(&self) -> u32;
+
+    /// Read `count` blocks starting at block `block` into `buffer`, which
+    /// must hold at least `count` blocks.
+    ///
+    /// On error, `buffer` is returned along with:
+    /// - EBUSY if another operation is outstanding
+    /// - EINVAL if `buffer` is too small or the blocks are out of range
+    /// - EUNINSTALLED if there is no medium in the device
+    fn read_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])>;
+
+    /// Write `count` blocks starting at block `block` from `buffer`, which
+    /// must hold at least `count` blocks.
+    ///
+    /// On error, `buffer` is returned along with the errors of `read_blocks`
+    /// or ENOSUPPORT if the device cannot write `count` blocks at once.
+    fn write_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])>;
+}
+
+pub trait BlockStorageClient {
+    /// Called when a read started with `read_blocks` finished. On success,
+    /// `buffer` holds the blocks that were read.
+    fn read_done(&self, result: ReturnCode, buffer: &'static mut [u8]);
+
+    /// Called when a write started with `write_blocks` finished.
+    fn write_done(&self, result: ReturnCode, buffer: &'static mut [u8]);
+}
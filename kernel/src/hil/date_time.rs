@@ -0,0 +1,231 @@
+//! Interface for clocks that keep the calendar date and time.
+//!
+//! The traits in `hil::time` count ticks from an arbitrary point, usually
+//! boot. A `DateTime` clock instead keeps the current date and time in UTC.
+//! It can be an RTC block with its own calendar, which may keep counting from
+//! a battery while the rest of the chip is off, or a software clock built on
+//! a `Counter`, which has to be set again after every reset.
+//!
+//! Times have a resolution of one second, and leap seconds are not
+//! represented. Converting to local time is left to userspace.
+
+use crate::ReturnCode;
+
+/// Number of days from 0000-03-01 to 1970-01-01 in the proleptic Gregorian
+/// calendar.
+const DAYS_TO_UNIX_EPOCH: u64 = 719_468;
+/// Number of days in a cycle of 400 years.
+const DAYS_PER_ERA: u64 = 146_097;
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// A date and time in UTC, in the proleptic Gregorian calendar.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DateTimeValues {
+    pub year: u16,
+    /// Month of the year, from 1 to 12.
+    pub month: u8,
+    /// Day of the month, from 1 to 31.
+    pub day: u8,
+    /// From 0 to 23.
+    pub hour: u8,
+    /// From 0 to 59.
+    pub minute: u8,
+    /// From 0 to 59.
+    pub second: u8,
+}
+
+impl DateTimeValues {
+    /// Returns the date and time `seconds` seconds after the Unix epoch,
+    /// 1970-01-01 00:00:00 UTC, or `None` if its year does not fit in a
+    /// `u16`.
+    pub fn from_unix_time(seconds: u64) -> Option<DateTimeValues> {
+        let days = seconds / SECONDS_PER_DAY;
+        let seconds_of_day = seconds % SECONDS_PER_DAY;
+
+        // Count years from March, so that leap days fall at the end of
+        // the year.
+        let days = days + DAYS_TO_UNIX_EPOCH;
+        let era = days / DAYS_PER_ERA;
+        let day_of_era = days % DAYS_PER_ERA;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+        let month = if month_from_march < 10 {
+            month_from_march + 3
+        } else {
+            month_from_march - 9
+        };
+        let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+
+        if year > u16::MAX as u64 {
+            return None;
+        }
+        Some(DateTimeValues {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds_of_day / 3600) as u8,
+            minute: (seconds_of_day / 60 % 60) as u8,
+            second: (seconds_of_day % 60) as u8,
+        })
+    }
+
+    /// Returns the number of seconds since the Unix epoch, or `None` if the
+    /// date is invalid or before 1970.
+    pub fn unix_time(&self) -> Option<u64> {
+        if !self.is_valid() || self.year < 1970 {
+            return None;
+        }
+        let seconds_of_day = self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64;
+        Some(self.days_since_epoch() * SECONDS_PER_DAY + seconds_of_day)
+    }
+
+    /// Returns whether all fields are in range, including the day for the
+    /// length of the month.
+    pub fn is_valid(&self) -> bool {
+        self.month >= 1
+            && self.month <= 12
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// Returns the day of the week as in ISO 8601, from 1 for Monday to 7
+    /// for Sunday. Only meaningful for valid dates from 1970 on.
+    pub fn weekday(&self) -> u8 {
+        // 1970-01-01 was a Thursday.
+        ((self.days_since_epoch() + 3) % 7 + 1) as u8
+    }
+
+    fn days_since_epoch(&self) -> u64 {
+        let (year, month) = if self.month > 2 {
+            (self.year as u64, self.month as u64 - 3)
+        } else {
+            (self.year as u64 - 1, self.month as u64 + 9)
+        };
+        let era = year / 400;
+        let year_of_era = year % 400;
+        let day_of_year = (153 * month + 2) / 5 + self.day as u64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * DAYS_PER_ERA + day_of_era - DAYS_TO_UNIX_EPOCH
+    }
+}
+
+fn is_leap_year(year: u16) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// A clock that keeps the current date and time in UTC.
+///
+/// Both operations complete immediately, so implementations must be able to
+/// read and write the clock without waiting on a bus.
+pub trait DateTime {
+    /// Returns the current date and time. Valid errors are:
+    ///   - `ReturnCode::EOFF`: the clock has not been set since it was
+    ///   reset or lost power, so it does not know the time.
+    ///   - `ReturnCode::FAIL`: the clock could not be read.
+    fn get_date_time(&self) -> Result<DateTimeValues, ReturnCode>;
+
+    /// Sets the current date and time. Valid `ReturnCode` values are:
+    ///   - `ReturnCode::SUCCESS`: the clock now counts from `date_time`.
+    ///   - `ReturnCode::EINVAL`: `date_time` is invalid, or outside of the
+    ///   range of dates the clock can hold.
+    ///   - `ReturnCode::EOFF`: the clock is not running, for example because
+    ///   its oscillator has not started.
+    ///   - `ReturnCode::FAIL`: the clock could not be set.
+    fn set_date_time(&self, date_time: DateTimeValues) -> ReturnCode;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn date(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTimeValues {
+        DateTimeValues {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    #[test]
+    fn epoch() {
+        assert_eq!(
+            DateTimeValues::from_unix_time(0),
+            Some(date(1970, 1, 1, 0, 0, 0))
+        );
+        assert_eq!(date(1970, 1, 1, 0, 0, 0).unix_time(), Some(0));
+        assert_eq!(date(1970, 1, 1, 0, 0, 0).weekday(), 4);
+    }
+
+    #[test]
+    fn known_dates() {
+        let known = [
+            (951_782_400, date(2000, 2, 29, 0, 0, 0), 2),
+            (951_868_799, date(2000, 2, 29, 23, 59, 59), 2),
+            (1_234_567_890, date(2009, 2, 13, 23, 31, 30), 5),
+            (2_147_483_648, date(2038, 1, 19, 3, 14, 8), 2),
+            (4_102_444_800, date(2100, 1, 1, 0, 0, 0), 5),
+            (4_107_456_000, date(2100, 2, 28, 0, 0, 0), 7),
+            (4_107_542_400, date(2100, 3, 1, 0, 0, 0), 1),
+        ];
+        for &(seconds, date_time, weekday) in known.iter() {
+            assert_eq!(DateTimeValues::from_unix_time(seconds), Some(date_time));
+            assert_eq!(date_time.unix_time(), Some(seconds));
+            assert_eq!(date_time.weekday(), weekday);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        // Steps of a bit more than a day, so that every day of the month
+        // and many times of day are visited.
+        for seconds in (0..5_000_000_000u64).step_by(86_400 + 3_607) {
+            let date_time = DateTimeValues::from_unix_time(seconds).unwrap();
+            assert!(date_time.is_valid());
+            assert_eq!(date_time.unix_time(), Some(seconds));
+        }
+    }
+
+    #[test]
+    fn invalid_dates() {
+        assert!(!date(1900, 2, 29, 0, 0, 0).is_valid());
+        assert!(date(2000, 2, 29, 0, 0, 0).is_valid());
+        assert!(!date(2021, 2, 29, 0, 0, 0).is_valid());
+        assert!(!date(2021, 4, 31, 0, 0, 0).is_valid());
+        assert!(!date(2021, 0, 1, 0, 0, 0).is_valid());
+        assert!(!date(2021, 13, 1, 0, 0, 0).is_valid());
+        assert!(!date(2021, 1, 0, 0, 0, 0).is_valid());
+        assert!(!date(2021, 1, 1, 24, 0, 0).is_valid());
+        assert!(!date(2021, 1, 1, 0, 60, 0).is_valid());
+        assert!(!date(2021, 1, 1, 0, 0, 60).is_valid());
+        assert_eq!(date(1969, 12, 31, 23, 59, 59).unix_time(), None);
+        assert_eq!(date(2021, 2, 29, 0, 0, 0).unix_time(), None);
+    }
+
+    #[test]
+    fn year_overflow() {
+        let last = date(u16::MAX, 12, 31, 23, 59, 59).unix_time().unwrap();
+        assert_eq!(
+            DateTimeValues::from_unix_time(last),
+            Some(date(u16::MAX, 12, 31, 23, 59, 59))
+        );
+        assert_eq!(DateTimeValues::from_unix_time(last + 1), None);
+    }
+}
@@ -7,7 +7,16 @@ use crate::returncode::ReturnCode;
 /// operations.
 pub trait DigestType: Eq + Copy + Clone + Sized + AsRef<[u8]> + AsMut<[u8]> {}
 
+/// SHA-1
+impl DigestType for [u8; 20] {}
+/// SHA-224
+impl DigestType for [u8; 28] {}
+/// SHA-256
 impl DigestType for [u8; 32] {}
+/// SHA-384
+impl DigestType for [u8; 48] {}
+/// SHA-512
+impl DigestType for [u8; 64] {}
 
 /// Implement this trait and use `set_client()` in order to receive callbacks.
 pub trait Client<'a, T: DigestType> {
@@ -64,9 +73,62 @@ pub trait Digest<'a, T: DigestType> {
     fn clear_data(&self);
 }
 
+/// Implement this trait to support SHA-1. SHA-1 is no longer considered
+/// secure against collisions and should only be used by protocols that
+/// require it.
+pub trait Sha1 {
+    /// Call before `Digest::run()` to perform SHA-1
+    fn set_mode_sha1(&self) -> Result<(), ReturnCode>;
+}
+
+pub trait Sha224 {
+    /// Call before `Digest::run()` to perform SHA-224
+    fn set_mode_sha224(&self) -> Result<(), ReturnCode>;
+}
+
+pub trait Sha256 {
+    /// Call before `Digest::run()` to perform SHA-256
+    fn set_mode_sha256(&self) -> Result<(), ReturnCode>;
+}
+
+pub trait Sha384 {
+    /// Call before `Digest::run()` to perform SHA-384
+    fn set_mode_sha384(&self) -> Result<(), ReturnCode>;
+}
+
+pub trait Sha512 {
+    /// Call before `Digest::run()` to perform SHA-512
+    fn set_mode_sha512(&self) -> Result<(), ReturnCode>;
+}
+
+/// The HMAC traits take keys of any length. As described in RFC 2104, keys
+/// longer than the block size of the hash function are hashed first, and
+/// shorter keys are padded with zeros. Implementations that cannot support a
+/// key length return `ENOSUPPORT`.
+pub trait HMACSha1 {
+    /// Call before `Digest::run()` to perform HMACSha1
+    ///
+    /// The key used for the HMAC is passed to this function.
+    fn set_mode_hmacsha1(&self, key: &[u8]) -> Result<(), ReturnCode>;
+}
+
 pub trait HMACSha256 {
     /// Call before `Digest::run()` to perform HMACSha256
     ///
     /// The key used for the HMAC is passed to this function.
-    fn set_mode_hmacsha256(&self, key: &[u8; 32]) -> Result<(), ReturnCode>;
+    fn set_mode_hmacsha256(&self, key: &[u8]) -> Result<(), ReturnCode>;
+}
+
+pub trait HMACSha384 {
+    /// Call before `Digest::run()` to perform HMACSha384
+    ///
+    /// The key used for the HMAC is passed to this function.
+    fn set_mode_hmacsha384(&self, key: &[u8]) -> Result<(), ReturnCode>;
+}
+
+pub trait HMACSha512 {
+    /// Call before `Digest::run()` to perform HMACSha512
+    ///
+    /// The key used for the HMAC is passed to this function.
+    fn set_mode_hmacsha512(&self, key: &[u8]) -> Result<(), ReturnCode>;
 }
@@ -0,0 +1,36 @@
+//! Interface for Ethernet MAC devices.
+//!
+//! An `EthernetAdapter` sends and receives raw Ethernet frames, starting with
+//! the destination MAC address and ending with the payload. Any preamble and
+//! frame check sequence are handled by the adapter.
+
+use crate::returncode::ReturnCode;
+
+pub trait EthernetAdapter<'a> {
+    /// Set the client to be used for callbacks.
+    fn set_client(&self, client: &'a dyn EthernetAdapterClient);
+
+    /// Transmit the first `len` bytes of `packet` as a single frame. Only
+    /// one frame can be transmitted at a time: the client must wait for the
+    /// `tx_done` callback before transmitting another frame.
+    ///
+    /// On error, `packet` is returned along with:
+    /// - EINVAL if `len` is larger than `packet`
+    /// - EBUSY if a frame is already being transmitted
+    /// - ESIZE if the frame is too large for the adapter
+    fn transmit(
+        &self,
+        packet: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])>;
+}
+
+pub trait EthernetAdapterClient {
+    /// Called when the transmission of a frame started with `transmit`
+    /// finished, returning the buffer and the length of the frame.
+    fn tx_done(&self, result: ReturnCode, packet: &'static mut [u8], len: usize);
+
+    /// Called when a frame was received. The frame is only valid for the
+    /// duration of the callback.
+    fn rx_packet(&self, packet: &[u8]);
+}
@@ -3,12 +3,16 @@
 pub mod adc;
 pub mod analog_comparator;
 pub mod ble_advertising;
+pub mod ble_connection;
+pub mod block_storage;
 pub mod bus8080;
 pub mod crc;
 pub mod dac;
+pub mod date_time;
 pub mod digest;
 pub mod eic;
 pub mod entropy;
+pub mod ethernet;
 pub mod flash;
 pub mod gpio;
 pub mod gpio_async;
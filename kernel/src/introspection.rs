@@ -15,6 +15,7 @@ use core::cell::Cell;
 use crate::callback::AppId;
 use crate::capabilities::ProcessManagementCapability;
 use crate::common::cells::NumericCellExt;
+use crate::platform::power::{SleepResidency, SleepState};
 use crate::process;
 use crate::sched::Kernel;
 
@@ -123,6 +124,53 @@ impl KernelInfo {
             .process_map_or(0, app, |process| process.debug_timeslice_expiration_count())
     }
 
+    /// Returns the number of times this app has not finished its work by its
+    /// deadline. This is only counted by schedulers that support deadlines.
+    pub fn number_app_deadline_misses(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel
+            .process_map_or(0, app, |process| process.debug_deadline_miss_count())
+    }
+
+    /// Returns how many times this app has overflowed its stack.
+    pub fn number_app_stack_overflows(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel
+            .process_map_or(0, app, |process| process.debug_stack_overflow_count())
+    }
+
+    /// Returns why this app faulted the last time it did, or `None` if it
+    /// never faulted.
+    pub fn app_fault_reason(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<process::FaultReason> {
+        self.kernel
+            .process_map_or(None, app, |process| process.get_fault_reason())
+    }
+
+    /// Returns a tuple of (the largest number of bytes the stack of this app
+    /// has used, the size of its stack if the app declared it). Returns `None`
+    /// if the app has not told the kernel where its stack starts.
+    pub fn app_stack_usage(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<(usize, Option<usize>)> {
+        self.kernel.process_map_or(None, app, |process| {
+            process
+                .debug_stack_high_water_mark()
+                .map(|used| (used, process.debug_stack_size()))
+        })
+    }
+
     /// Returns a tuple of the (the number of grants in the grant region this
     /// app has allocated, total number of grants that exist in the system).
     pub fn number_app_grant_uses(
@@ -159,4 +207,34 @@ impl KernelInfo {
         });
         count.get()
     }
+
+    /// Returns the total number of times all processes have missed their
+    /// deadlines.
+    pub fn deadline_misses(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        let count: Cell<usize> = Cell::new(0);
+        self.kernel.process_each(|proc| {
+            count.add(proc.debug_deadline_miss_count());
+        });
+        count.get()
+    }
+
+    /// Returns the total number of times all processes have overflowed their
+    /// stacks.
+    pub fn stack_overflows(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        let count: Cell<usize> = Cell::new(0);
+        self.kernel.process_each(|proc| {
+            count.add(proc.debug_stack_overflow_count());
+        });
+        count.get()
+    }
+
+    /// Returns how often and for how long the chip was in the sleep state
+    /// `state` when the kernel was idle.
+    pub fn sleep_residency(
+        &self,
+        state: SleepState,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> SleepResidency {
+        self.kernel.power_manager().residency(state)
+    }
 }
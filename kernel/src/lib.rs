@@ -106,6 +106,8 @@ mod mem;
 mod memop;
 mod platform;
 mod process;
+mod process_checker;
+mod process_loader;
 mod returncode;
 mod sched;
 mod tbfheader;
@@ -114,12 +116,17 @@ pub use crate::callback::{AppId, Callback};
 pub use crate::driver::Driver;
 pub use crate::grant::Grant;
 pub use crate::mem::{AppSlice, Private, Shared};
+pub use crate::platform::crash_record;
+pub use crate::platform::power;
 pub use crate::platform::scheduler_timer::{SchedulerTimer, VirtualSchedulerTimer};
+pub use crate::platform::syscall_filter;
+pub use crate::platform::syscall_trace;
 pub use crate::platform::watchdog;
 pub use crate::platform::{mpu, Chip, InterruptService, Platform};
 pub use crate::platform::{ClockInterface, NoClockControl, NO_CLOCK_CONTROL};
 pub use crate::returncode::ReturnCode;
 pub use crate::sched::cooperative::{CoopProcessNode, CooperativeSched};
+pub use crate::sched::edf::{EDFProcessNode, EDFSched};
 pub use crate::sched::mlfq::{MLFQProcessNode, MLFQSched};
 pub use crate::sched::priority::PrioritySched;
 pub use crate::sched::round_robin::{RoundRobinProcessNode, RoundRobinSched};
@@ -132,8 +139,14 @@ pub use crate::sched::{Kernel, Scheduler};
 /// Publicly available process-related objects.
 pub mod procs {
     pub use crate::process::{
-        load_processes, AlwaysRestart, Error, FaultResponse, FunctionCall, FunctionCallSource,
-        Process, ProcessLoadError, ProcessRestartPolicy, ProcessType, State, Task,
-        ThresholdRestart, ThresholdRestartThenPanic,
+        load_processes, load_processes_with_checker, AlwaysRestart, Error, FaultReason,
+        FaultResponse, FunctionCall, FunctionCallSource, Process, ProcessAddresses,
+        ProcessLoadError, ProcessRestartPolicy, ProcessType, State, Task, ThresholdRestart,
+        ThresholdRestartThenPanic, SUBSCRIBED_CALLBACKS_LEN, SYSCALL_HISTORY_LEN,
+    };
+    pub use crate::process_checker::{AppCredentialsChecker, CheckResult};
+    pub use crate::process_loader::{AppPlacement, DynamicProcessLoader};
+    pub use crate::tbfheader::{
+        CommandPermissions, TbfFooterV2Credentials, TbfFooterV2CredentialsType, TbfHeaderV2Realtime,
     };
 }
@@ -0,0 +1,469 @@
+//! Persistent records of kernel panics and process faults.
+//!
+//! `debug::panic()` prints the state of the kernel and of the faulting process
+//! to the console, which is lost on reset and of no help for a device in the
+//! field that nobody is watching. A board can also keep a compact record of
+//! every panic in nonvolatile memory, to be read out after the next boot, by
+//! giving the kernel a `CrashStore`:
+//!
+//! ```ignore
+//! let crash_log = components::crash_log::CrashLogComponent::new(...).finalize(...);
+//! kernel::crash_record::set_crash_store(crash_log);
+//! ```
+//!
+//! When a process with `FaultResponse::Panic` faults, the kernel saves its
+//! name, registers and the top of its stack before panicking. `debug::panic()`
+//! then adds the panic message and hands the record to the store, servicing
+//! interrupts until the store is done with it. Kernel panics are recorded the
+//! same way, without process state.
+//!
+//! The records are read back through a `CrashRecordReader`, for example with
+//! the `crash` command of the process console.
+//!
+//! Record format
+//! -------------
+//!
+//! `CrashRecord::to_bytes()` encodes a record as `RECORD_LEN` bytes, with all
+//! fields little endian:
+//!
+//! ```text
+//! 0        4     5     6     7     8        12       16       20    21    22    23    24
+//! +--------+-----+-----+-----+-----+--------+--------+--------+-----+-----+-----+-----+
+//! | "TKCR" | ver | kind| arch| regs| crc    |sequence| sp     | name| msg |stack| 0   |
+//! +--------+-----+-----+-----+-----+--------+--------+--------+-----+-----+-----+-----+
+//!
+//! 24                 40                 56                                        256
+//! +------------------+------------------+------------------------------------------+
+//! | version          | process name     | payload                                  |
+//! +------------------+------------------+------------------------------------------+
+//! ```
+//!
+//! - `ver` is the version of the format, `VERSION`.
+//! - `kind` is 1 for a kernel panic and 2 for a process fault.
+//! - `arch` is the architecture the registers come from: 0 unknown,
+//!   1 Cortex-M, 2 32-bit RISC-V.
+//! - `regs` is the number of registers in the payload.
+//! - `crc` is the CRC-32 (IEEE 802.3) of bytes 12 to the end of the record.
+//! - `sequence` counts the records written to the store.
+//! - `sp` is the stack pointer of the faulting process.
+//! - `name`, `msg` and `stack` are the lengths of the process name, of the
+//!   panic message and of the stack excerpt.
+//! - `version` is the kernel version (`TOCK_KERNEL_VERSION` at build time)
+//!   and `process name` the name of the faulting process, both padded with
+//!   zeros.
+//! - `payload` holds the registers as 32-bit words, then the stack excerpt,
+//!   the words at and above `sp`, then the panic message, truncated to fit.
+//!
+//! The registers of a Cortex-M process are r0 to r12, sp, lr, pc, xpsr, the
+//! pc of its last yield, and the CFSR, HFSR, MMFAR and BFAR fault registers.
+//! Those of a RISC-V process are x1 to x31, pc, mcause and mtval.
+//!
+//! `tools/crash_record_decode.py` decodes records.
+
+use core::fmt;
+use core::panic::PanicInfo;
+use core::str;
+
+use crate::returncode::ReturnCode;
+
+/// Length of an encoded `CrashRecord`.
+pub const RECORD_LEN: usize = 256;
+
+/// The first bytes of every encoded record.
+pub const MAGIC: [u8; 4] = *b"TKCR";
+
+/// Version of the record format.
+pub const VERSION: u8 = 1;
+
+/// The most registers a record holds.
+pub const MAX_REGISTERS: usize = 34;
+
+/// The most bytes of the process stack a record holds.
+pub const MAX_STACK_LEN: usize = 48;
+
+/// Length of the process name and kernel version fields.
+const NAME_LEN: usize = 16;
+const HEADER_LEN: usize = 56;
+const PAYLOAD_LEN: usize = RECORD_LEN - HEADER_LEN;
+/// Room kept for the panic message when the stack excerpt is added.
+const MIN_MESSAGE_LEN: usize = 32;
+
+/// How many times `debug::panic()` services interrupts while waiting for the
+/// store to write a record.
+const STORE_ATTEMPTS: usize = 1_000_000;
+
+/// What caused a crash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrashKind {
+    KernelPanic = 1,
+    ProcessFault = 2,
+}
+
+/// Which architecture the registers of a record come from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Architecture {
+    Unknown = 0,
+    CortexM = 1,
+    RiscV32 = 2,
+}
+
+/// The registers of a process, in the order its architecture documents.
+#[derive(Clone, Copy)]
+pub struct Registers {
+    architecture: Architecture,
+    values: [u32; MAX_REGISTERS],
+    count: usize,
+}
+
+impl Registers {
+    pub fn new(architecture: Architecture) -> Registers {
+        Registers {
+            architecture,
+            values: [0; MAX_REGISTERS],
+            count: 0,
+        }
+    }
+
+    /// Add the next register. Registers past `MAX_REGISTERS` are dropped.
+    pub fn push(&mut self, value: usize) {
+        if self.count < MAX_REGISTERS {
+            self.values[self.count] = value as u32;
+            self.count += 1;
+        }
+    }
+
+    pub fn architecture(&self) -> Architecture {
+        self.architecture
+    }
+
+    pub fn values(&self) -> &[u32] {
+        &self.values[..self.count]
+    }
+}
+
+/// A record of a kernel panic or process fault.
+#[derive(Clone, Copy)]
+pub struct CrashRecord {
+    kind: CrashKind,
+    sequence: u32,
+    registers: Registers,
+    stack_pointer: u32,
+    kernel_version: [u8; NAME_LEN],
+    process_name: [u8; NAME_LEN],
+    name_len: usize,
+    stack: [u8; MAX_STACK_LEN],
+    stack_len: usize,
+    message: [u8; PAYLOAD_LEN],
+    message_len: usize,
+}
+
+impl CrashRecord {
+    /// Create a record of this kind for the running kernel, without process
+    /// state or message.
+    pub fn new(kind: CrashKind) -> CrashRecord {
+        let mut kernel_version = [0; NAME_LEN];
+        let version = option_env!("TOCK_KERNEL_VERSION").unwrap_or("unknown");
+        let len = core::cmp::min(version.len(), NAME_LEN);
+        kernel_version[..len].copy_from_slice(&version.as_bytes()[..len]);
+        CrashRecord {
+            kind,
+            sequence: 0,
+            registers: Registers::new(Architecture::Unknown),
+            stack_pointer: 0,
+            kernel_version,
+            process_name: [0; NAME_LEN],
+            name_len: 0,
+            stack: [0; MAX_STACK_LEN],
+            stack_len: 0,
+            message: [0; PAYLOAD_LEN],
+            message_len: 0,
+        }
+    }
+
+    /// Record the state of the faulting process. The stack excerpt is cut to
+    /// whole words and to `MAX_STACK_LEN`, and to leave room for a message.
+    pub fn set_process(
+        &mut self,
+        name: &str,
+        registers: &Registers,
+        stack_pointer: usize,
+        stack: &[u8],
+    ) {
+        self.kind = CrashKind::ProcessFault;
+        self.name_len = core::cmp::min(name.len(), NAME_LEN);
+        self.process_name = [0; NAME_LEN];
+        self.process_name[..self.name_len].copy_from_slice(&name.as_bytes()[..self.name_len]);
+        self.registers = *registers;
+        self.stack_pointer = stack_pointer as u32;
+
+        let room = PAYLOAD_LEN - 4 * self.registers.count - MIN_MESSAGE_LEN;
+        self.stack_len = core::cmp::min(stack.len(), core::cmp::min(room, MAX_STACK_LEN)) & !0x3;
+        self.stack[..self.stack_len].copy_from_slice(&stack[..self.stack_len]);
+        self.message_len = core::cmp::min(self.message_len, self.message_room());
+    }
+
+    /// Set the message, cut to the room left by the registers and stack.
+    pub fn set_message(&mut self, message: fmt::Arguments) {
+        let room = self.message_room();
+        let mut writer = Truncate {
+            buffer: &mut self.message[..room],
+            len: 0,
+        };
+        let _ = fmt::write(&mut writer, message);
+        self.message_len = writer.len;
+    }
+
+    pub fn set_sequence(&mut self, sequence: u32) {
+        self.sequence = sequence;
+    }
+
+    pub fn kind(&self) -> CrashKind {
+        self.kind
+    }
+
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn stack_pointer(&self) -> usize {
+        self.stack_pointer as usize
+    }
+
+    pub fn kernel_version(&self) -> &str {
+        let len = self
+            .kernel_version
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(NAME_LEN);
+        as_str(&self.kernel_version[..len])
+    }
+
+    /// The name of the faulting process, empty for a kernel panic.
+    pub fn process_name(&self) -> &str {
+        as_str(&self.process_name[..self.name_len])
+    }
+
+    pub fn stack(&self) -> &[u8] {
+        &self.stack[..self.stack_len]
+    }
+
+    pub fn message(&self) -> &str {
+        as_str(&self.message[..self.message_len])
+    }
+
+    fn message_room(&self) -> usize {
+        PAYLOAD_LEN - 4 * self.registers.count - self.stack_len
+    }
+
+    /// Encode the record in the format described in the module documentation.
+    pub fn to_bytes(&self) -> [u8; RECORD_LEN] {
+        let mut bytes = [0; RECORD_LEN];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4] = VERSION;
+        bytes[5] = self.kind as u8;
+        bytes[6] = self.registers.architecture as u8;
+        bytes[7] = self.registers.count as u8;
+        bytes[12..16].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.stack_pointer.to_le_bytes());
+        bytes[20] = self.name_len as u8;
+        bytes[21] = self.message_len as u8;
+        bytes[22] = self.stack_len as u8;
+        bytes[24..40].copy_from_slice(&self.kernel_version);
+        bytes[40..56].copy_from_slice(&self.process_name);
+
+        let mut offset = HEADER_LEN;
+        for value in self.registers.values() {
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            offset += 4;
+        }
+        bytes[offset..offset + self.stack_len].copy_from_slice(self.stack());
+        offset += self.stack_len;
+        bytes[offset..offset + self.message_len].copy_from_slice(&self.message[..self.message_len]);
+
+        let crc = crc32(&bytes[12..]);
+        bytes[8..12].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Decode a record, or return `None` if `bytes` does not hold a valid
+    /// record, for example because the flash is erased.
+    pub fn from_bytes(bytes: &[u8]) -> Option<CrashRecord> {
+        if bytes.len() < RECORD_LEN || bytes[0..4] != MAGIC || bytes[4] != VERSION {
+            return None;
+        }
+        let bytes = &bytes[..RECORD_LEN];
+        if crc32(&bytes[12..]) != read_u32(bytes, 8) {
+            return None;
+        }
+        let kind = match bytes[5] {
+            1 => CrashKind::KernelPanic,
+            2 => CrashKind::ProcessFault,
+            _ => return None,
+        };
+        let architecture = match bytes[6] {
+            1 => Architecture::CortexM,
+            2 => Architecture::RiscV32,
+            _ => Architecture::Unknown,
+        };
+        let (count, name_len, message_len, stack_len) = (
+            bytes[7] as usize,
+            bytes[20] as usize,
+            bytes[21] as usize,
+            bytes[22] as usize,
+        );
+        if count > MAX_REGISTERS
+            || name_len > NAME_LEN
+            || stack_len > MAX_STACK_LEN
+            || 4 * count + stack_len + message_len > PAYLOAD_LEN
+        {
+            return None;
+        }
+
+        let mut record = CrashRecord::new(kind);
+        record.sequence = read_u32(bytes, 12);
+        record.stack_pointer = read_u32(bytes, 16);
+        record.kernel_version.copy_from_slice(&bytes[24..40]);
+        record.process_name.copy_from_slice(&bytes[40..56]);
+        record.name_len = name_len;
+        record.registers = Registers::new(architecture);
+        let mut offset = HEADER_LEN;
+        for _ in 0..count {
+            record.registers.push(read_u32(bytes, offset) as usize);
+            offset += 4;
+        }
+        record.stack[..stack_len].copy_from_slice(&bytes[offset..offset + stack_len]);
+        record.stack_len = stack_len;
+        offset += stack_len;
+        record.message[..message_len].copy_from_slice(&bytes[offset..offset + message_len]);
+        record.message_len = message_len;
+        Some(record)
+    }
+}
+
+/// Nonvolatile memory that keeps crash records across reboots.
+///
+/// `store()` is called from the panic handler: interrupts are disabled and
+/// the kernel loop no longer runs, so the store must make progress only
+/// through the interrupts and deferred calls that
+/// `Chip::service_pending_interrupts()` handles.
+pub trait CrashStore {
+    /// Start writing a record. The store assigns it the next sequence number.
+    /// Returns `EBUSY` if the store must finish another operation first, in
+    /// which case the caller services interrupts and tries again.
+    fn store(&self, record: &CrashRecord) -> ReturnCode;
+
+    /// Returns whether the record passed to the last `store()` is written.
+    fn is_stored(&self) -> bool;
+}
+
+/// Reading out the records of a `CrashStore`.
+pub trait CrashRecordReader {
+    /// Returns how many records are stored.
+    fn count(&self) -> usize;
+
+    /// Print the record with this index, 0 being the most recent, to the
+    /// debug output. The record may be printed after this returns, once it
+    /// is read from the store.
+    fn print(&self, index: usize) -> ReturnCode;
+
+    /// Erase all records.
+    fn clear(&self) -> ReturnCode;
+}
+
+static mut CRASH_STORE: Option<&'static dyn CrashStore> = None;
+
+/// The record of the process fault the kernel is about to panic for.
+static mut PENDING_RECORD: Option<CrashRecord> = None;
+
+/// Store a record of every kernel panic in `store`.
+pub unsafe fn set_crash_store(store: &'static dyn CrashStore) {
+    CRASH_STORE = Some(store);
+}
+
+/// Keep the state of a faulting process for the record of the panic that
+/// follows.
+pub(crate) unsafe fn set_process_fault(
+    name: &str,
+    registers: &Registers,
+    stack_pointer: usize,
+    stack: &[u8],
+) {
+    let mut record = CrashRecord::new(CrashKind::ProcessFault);
+    record.set_process(name, registers, stack_pointer, stack);
+    PENDING_RECORD = Some(record);
+}
+
+/// Hand the record of this panic to the crash store, if the board set one,
+/// calling `service` until the store has written it. Returns `None` without
+/// a store, and whether the record was written otherwise.
+pub(crate) unsafe fn store_panic(panic_info: &PanicInfo, service: &dyn Fn()) -> Option<bool> {
+    let store = CRASH_STORE?;
+    let mut record = PENDING_RECORD
+        .take()
+        .unwrap_or_else(|| CrashRecord::new(CrashKind::KernelPanic));
+    record.set_message(format_args!("{}", panic_info));
+    let mut started = false;
+    for _ in 0..STORE_ATTEMPTS {
+        if !started {
+            match store.store(&record) {
+                ReturnCode::SUCCESS => started = true,
+                ReturnCode::EBUSY => {}
+                _ => return Some(false),
+            }
+        } else if store.is_stored() {
+            return Some(true);
+        }
+        service();
+    }
+    Some(started && store.is_stored())
+}
+
+/// A `fmt::Write` that drops what does not fit in its buffer.
+struct Truncate<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for Truncate<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = core::cmp::min(s.len(), self.buffer.len() - self.len);
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// The longest valid UTF-8 prefix of `bytes`, as truncation may split a
+/// character.
+fn as_str(bytes: &[u8]) -> &str {
+    match str::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(error) => str::from_utf8(&bytes[..error.valid_up_to()]).unwrap_or(""),
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(word)
+}
+
+/// CRC-32 as used by Ethernet and zlib.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
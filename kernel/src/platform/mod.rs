@@ -6,8 +6,12 @@ use crate::returncode;
 use crate::syscall;
 use core::fmt::Write;
 
+pub mod crash_record;
 pub mod mpu;
+pub mod power;
 pub(crate) mod scheduler_timer;
+pub mod syscall_filter;
+pub mod syscall_trace;
 pub mod watchdog;
 
 /// Interface for individual boards.
@@ -53,6 +57,11 @@ pub trait Platform {
     /// returned to the calling application.  The default implementation allows
     /// all system calls. This API should be considered unstable, and is likely
     /// to change in the future.
+    ///
+    /// Boards can implement this by forwarding to a
+    /// `syscall_filter::SyscallFilter`, such as
+    /// `syscall_filter::TbfHeaderFilterDefaultAllow` which enforces the
+    /// permissions processes declare in their TBF headers.
     fn filter_syscall(
         &self,
         _process: &dyn process::ProcessType,
@@ -60,6 +69,32 @@ pub trait Platform {
     ) -> Result<(), returncode::ReturnCode> {
         Ok(())
     }
+
+    /// Called after the kernel handled a system call, with the value it
+    /// returned to the process (`None` for yield, which returns nothing). The
+    /// default implementation does nothing.
+    ///
+    /// Boards can implement this by forwarding to a
+    /// `syscall_trace::SyscallTrace` to record the system calls processes
+    /// make.
+    fn trace_syscall(
+        &self,
+        _process: &dyn process::ProcessType,
+        _syscall: &syscall::Syscall,
+        _result: Option<returncode::ReturnCode>,
+    ) {
+    }
+
+    /// Choose the sleep state the chip enters when the kernel has nothing to
+    /// do. `permitted` is the deepest state that the sleep constraints of the
+    /// drivers allow, and the kernel never sleeps deeper than that, whatever
+    /// this returns. The default implementation sleeps as deeply as permitted.
+    ///
+    /// Boards can use this to trade power for wake-up latency, for example to
+    /// stay in a shallow state while on external power.
+    fn sleep_policy(&self, permitted: power::SleepState) -> power::SleepState {
+        permitted
+    }
 }
 
 /// Interface for individual MCUs.
@@ -119,6 +154,19 @@ pub trait Chip {
     /// chip and resumes the scheduler.
     fn sleep(&self);
 
+    /// Called instead of `sleep()` when the kernel chose a sleep state from
+    /// the constraints of the drivers (see `power`). The chip should enter
+    /// `state`, or a shallower state if it does not support `state` or its
+    /// peripherals need to keep running, and return the state it entered once
+    /// it wakes up.
+    ///
+    /// The default implementation calls `sleep()` and reports
+    /// `SleepState::Idle`, for chips that have a single sleep state.
+    fn sleep_in(&self, _state: power::SleepState) -> power::SleepState {
+        self.sleep();
+        power::SleepState::Idle
+    }
+
     /// Run a function in an atomic state, which means that interrupts are
     /// disabled so that an interrupt will not fire during the passed in
     /// function's execution.
@@ -251,6 +251,37 @@ pub trait MPU {
         }
     }
 
+    /// Allocates a guard region below a process's stack.
+    ///
+    /// An implementation must store a region in `config` that user mode
+    /// cannot access at all, so that a process that grows its stack past
+    /// `stack_bottom` faults. The region must lie completely within the
+    /// `max_guard_size` bytes below `stack_bottom`, and should end as close to
+    /// `stack_bottom` as the hardware allows. The guard takes precedence over
+    /// the app memory region it overlaps. Calling this again replaces the
+    /// previous guard.
+    ///
+    /// # Arguments
+    ///
+    /// - `stack_bottom`:   lowest address of the stack
+    /// - `max_guard_size`: maximum size of the guard region
+    /// - `config`:         MPU region configuration
+    ///
+    /// # Return Value
+    ///
+    /// Returns the start and size of the guard region. If the MPU does not
+    /// support guard regions or it is infeasible to allocate one, returns
+    /// None. If None is returned no changes are made.
+    #[allow(unused_variables)]
+    fn allocate_stack_guard(
+        &self,
+        stack_bottom: *const u8,
+        max_guard_size: usize,
+        config: &mut Self::MpuConfig,
+    ) -> Option<Region> {
+        None
+    }
+
     /// Configures the MPU with the provided region configuration.
     ///
     /// An implementation must ensure that all memory locations not covered by
@@ -0,0 +1,245 @@
+//! Choosing how deeply the chip sleeps when the kernel is idle.
+//!
+//! Deeper sleep states save more power, but turn off more of the chip: clocks
+//! stop, and peripherals that depend on them stop working or lose incoming
+//! data. Which state is safe depends on what the peripherals are doing at the
+//! moment the kernel goes to sleep. A UART that is waiting for input, or a DMA
+//! transfer in flight, needs its clock to keep running, while the same
+//! peripherals idle do not.
+//!
+//! Peripheral drivers describe this with a `SleepConstraint`, which names the
+//! deepest `SleepState` the driver currently tolerates. The driver tightens its
+//! constraint when it starts an operation and releases it once the operation
+//! is done. The board registers the constraints of its drivers with the
+//! kernel's `PowerManager`:
+//!
+//! ```ignore
+//! board_kernel
+//!     .power_manager()
+//!     .register(base_peripherals.uarte0.sleep_constraint());
+//! ```
+//!
+//! Whenever the kernel has nothing left to do it finds the deepest state all
+//! registered constraints tolerate, lets the board's
+//! `Platform::sleep_policy()` pick that state or a shallower one, and asks the
+//! chip to enter it with `Chip::sleep_in()`. The chip may still pick a
+//! shallower state, because it does not support the one asked for or because
+//! its own peripherals require it, and reports the state it entered.
+//!
+//! If the board gives the power manager a clock with `set_clock()`, the
+//! kernel also records how often and for how long the chip was in each state,
+//! which is available through `introspection::KernelInfo`. A single sleep
+//! that lasts longer than the clock takes to wrap around is undercounted, so
+//! the clock should be a slow counter, such as a 32 kHz RTC.
+
+use core::cell::Cell;
+
+use crate::common::cells::OptionalCell;
+use crate::common::{List, ListLink, ListNode};
+use crate::hil::time::{self, Frequency, Ticks};
+
+/// The sleep states, ordered from the shallowest to the deepest.
+///
+/// What exactly each state turns off is up to the chip. The descriptions below
+/// are what the kernel and drivers assume.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SleepState {
+    /// Only the CPU stops. All clocks and peripherals keep running and the
+    /// chip wakes up immediately.
+    Idle = 0,
+    /// The CPU stops and the chip may stop clocks that no peripheral is using,
+    /// at the cost of a longer wake-up time.
+    Sleep = 1,
+    /// The high frequency clocks stop. Only peripherals running from a low
+    /// frequency clock, and interrupts such as GPIO, can wake the chip.
+    DeepSleep = 2,
+}
+
+/// Number of `SleepState`s.
+pub const NUM_SLEEP_STATES: usize = 3;
+
+impl SleepState {
+    /// All sleep states, from the shallowest to the deepest.
+    pub const ALL: [SleepState; NUM_SLEEP_STATES] =
+        [SleepState::Idle, SleepState::Sleep, SleepState::DeepSleep];
+
+    /// The name of the state, for printing.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SleepState::Idle => "idle",
+            SleepState::Sleep => "sleep",
+            SleepState::DeepSleep => "deep sleep",
+        }
+    }
+}
+
+/// The deepest sleep state a peripheral driver currently tolerates.
+///
+/// Drivers hold one of these, tighten it with `limit()` while they need
+/// clocks that deeper states turn off, and `release()` it when they are done.
+/// A constraint only has an effect once the board registers it with the
+/// `PowerManager`.
+pub struct SleepConstraint<'a> {
+    name: &'static str,
+    deepest: Cell<SleepState>,
+    next: ListLink<'a, SleepConstraint<'a>>,
+}
+
+impl<'a> ListNode<'a, SleepConstraint<'a>> for SleepConstraint<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, SleepConstraint<'a>> {
+        &self.next
+    }
+}
+
+impl<'a> SleepConstraint<'a> {
+    /// Create a constraint that does not limit the sleep state. `name` names
+    /// the driver when the constraints are listed.
+    pub const fn new(name: &'static str) -> SleepConstraint<'a> {
+        SleepConstraint {
+            name,
+            deepest: Cell::new(SleepState::DeepSleep),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Allow the chip to sleep no deeper than `state`.
+    pub fn limit(&self, state: SleepState) {
+        self.deepest.set(state);
+    }
+
+    /// Stop limiting the sleep state.
+    pub fn release(&self) {
+        self.deepest.set(SleepState::DeepSleep);
+    }
+
+    /// Returns the deepest sleep state the driver tolerates.
+    pub fn deepest(&self) -> SleepState {
+        self.deepest.get()
+    }
+
+    /// Returns the name of the driver holding the constraint.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// A free running clock for measuring how long the chip sleeps.
+///
+/// Every `hil::time::Time` is one; boards pass their RTC or alarm.
+pub trait ResidencyClock {
+    /// Returns the current value of the clock.
+    fn residency_ticks(&self) -> u32;
+
+    /// Returns how many ticks passed since the clock read `start`, taking the
+    /// width of the clock into account.
+    fn residency_ticks_since(&self, start: u32) -> u32;
+
+    /// Returns the frequency of the clock, in Hz.
+    fn residency_frequency(&self) -> u32;
+}
+
+impl<T: time::Time> ResidencyClock for T {
+    fn residency_ticks(&self) -> u32 {
+        self.now().into_u32()
+    }
+
+    fn residency_ticks_since(&self, start: u32) -> u32 {
+        self.now().wrapping_sub(T::Ticks::from(start)).into_u32()
+    }
+
+    fn residency_frequency(&self) -> u32 {
+        T::Frequency::frequency()
+    }
+}
+
+/// How often and for how long the chip was in one sleep state.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SleepResidency {
+    /// How many times the chip entered the state.
+    pub entries: usize,
+    /// The total time the chip spent in the state, in microseconds, or 0 if
+    /// the board did not give the power manager a clock.
+    pub time_us: u64,
+}
+
+/// The kernel's record of sleep constraints and sleep statistics.
+///
+/// There is one in every `Kernel`, see `Kernel::power_manager()`.
+pub struct PowerManager {
+    constraints: List<'static, SleepConstraint<'static>>,
+    clock: OptionalCell<&'static dyn ResidencyClock>,
+    entries: [Cell<usize>; NUM_SLEEP_STATES],
+    ticks: [Cell<u64>; NUM_SLEEP_STATES],
+}
+
+impl PowerManager {
+    pub(crate) fn new() -> PowerManager {
+        PowerManager {
+            constraints: List::new(),
+            clock: OptionalCell::empty(),
+            entries: Default::default(),
+            ticks: Default::default(),
+        }
+    }
+
+    /// Take `constraint` into account whenever the kernel goes to sleep.
+    pub fn register(&self, constraint: &'static SleepConstraint<'static>) {
+        self.constraints.push_head(constraint);
+    }
+
+    /// Measure how long the chip sleeps with `clock`.
+    pub fn set_clock(&self, clock: &'static dyn ResidencyClock) {
+        self.clock.set(clock);
+    }
+
+    /// Returns the deepest sleep state that all registered constraints
+    /// tolerate.
+    pub fn deepest_permitted(&self) -> SleepState {
+        self.constraints
+            .iter()
+            .map(|constraint| constraint.deepest())
+            .min()
+            .unwrap_or(SleepState::DeepSleep)
+    }
+
+    /// Returns the shallowest registered constraint, if any constraint limits
+    /// the sleep state. This is the driver keeping the chip awake.
+    pub fn limiting_constraint(&self) -> Option<&'static SleepConstraint<'static>> {
+        self.constraints
+            .iter()
+            .filter(|constraint| constraint.deepest() < SleepState::DeepSleep)
+            .min_by_key(|constraint| constraint.deepest())
+    }
+
+    /// Returns the statistics of `state`.
+    pub(crate) fn residency(&self, state: SleepState) -> SleepResidency {
+        let ticks = self.ticks[state as usize].get();
+        SleepResidency {
+            entries: self.entries[state as usize].get(),
+            time_us: self.clock.map_or(0, |clock| {
+                let frequency = clock.residency_frequency() as u64;
+                if frequency == 0 {
+                    0
+                } else {
+                    ticks / frequency * 1_000_000 + ticks % frequency * 1_000_000 / frequency
+                }
+            }),
+        }
+    }
+
+    /// Run `sleep`, which puts the chip to sleep and returns the state it
+    /// entered, and add the sleep to the statistics of that state.
+    pub(crate) fn record_sleep<F>(&self, sleep: F)
+    where
+        F: FnOnce() -> SleepState,
+    {
+        let clock = self.clock.map(|clock| *clock);
+        let start = clock.map(|clock| clock.residency_ticks());
+        let entered = sleep() as usize;
+        self.entries[entered].set(self.entries[entered].get().wrapping_add(1));
+        if let (Some(clock), Some(start)) = (clock, start) {
+            let ticks = clock.residency_ticks_since(start) as u64;
+            self.ticks[entered].set(self.ticks[entered].get() + ticks);
+        }
+    }
+}
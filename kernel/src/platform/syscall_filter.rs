@@ -0,0 +1,98 @@
+//! Reusable system call filters for `Platform::filter_syscall()`.
+
+use crate::process;
+use crate::returncode::ReturnCode;
+use crate::syscall::Syscall;
+use crate::tbfheader::CommandPermissions;
+
+/// A policy that decides whether a process may make a particular system call.
+///
+/// This has the same signature as `Platform::filter_syscall()`, so a board can
+/// hold a filter and forward `filter_syscall()` to it:
+///
+/// ```ignore
+/// impl Platform for Hail {
+///     fn filter_syscall(
+///         &self,
+///         process: &dyn ProcessType,
+///         syscall: &Syscall,
+///     ) -> Result<(), ReturnCode> {
+///         self.syscall_filter.filter_syscall(process, syscall)
+///     }
+/// }
+/// ```
+pub trait SyscallFilter {
+    /// Return `Ok(())` if `process` may make `syscall`, or the `ReturnCode`
+    /// to return to the process otherwise.
+    fn filter_syscall(
+        &self,
+        process: &dyn process::ProcessType,
+        syscall: &Syscall,
+    ) -> Result<(), ReturnCode>;
+}
+
+/// Implement default `SyscallFilter` trait for unit, which allows all system
+/// calls.
+impl SyscallFilter for () {
+    fn filter_syscall(
+        &self,
+        _process: &dyn process::ProcessType,
+        _syscall: &Syscall,
+    ) -> Result<(), ReturnCode> {
+        Ok(())
+    }
+}
+
+/// Filter system calls based on the permissions declared in each process's
+/// TBF header.
+///
+/// Processes that do not include a Permissions entry in their TBF header are
+/// not restricted. Processes that do may only subscribe, allow, and issue
+/// commands to the drivers listed there, and may only issue the commands that
+/// are set in the mask for that driver. Memop and yield are always allowed.
+///
+/// Denied system calls return `ReturnCode::EPERM` to the process.
+pub struct TbfHeaderFilterDefaultAllow {}
+
+impl TbfHeaderFilterDefaultAllow {
+    pub const fn new() -> TbfHeaderFilterDefaultAllow {
+        TbfHeaderFilterDefaultAllow {}
+    }
+}
+
+impl SyscallFilter for TbfHeaderFilterDefaultAllow {
+    fn filter_syscall(
+        &self,
+        process: &dyn process::ProcessType,
+        syscall: &Syscall,
+    ) -> Result<(), ReturnCode> {
+        match *syscall {
+            Syscall::COMMAND {
+                driver_number,
+                subdriver_number,
+                ..
+            } => match process.get_command_permissions(driver_number, subdriver_number / 64) {
+                CommandPermissions::NoPermsAtAll => Ok(()),
+                CommandPermissions::NoPermsThisDriver => Err(ReturnCode::EPERM),
+                CommandPermissions::Mask(allowed) => {
+                    if (allowed >> (subdriver_number % 64)) & 1 == 1 {
+                        Ok(())
+                    } else {
+                        Err(ReturnCode::EPERM)
+                    }
+                }
+            },
+
+            // Subscribe and allow do not have their own permissions. A process
+            // may use them with any driver it has permissions for.
+            Syscall::SUBSCRIBE { driver_number, .. } | Syscall::ALLOW { driver_number, .. } => {
+                match process.get_command_permissions(driver_number, 0) {
+                    CommandPermissions::NoPermsThisDriver => Err(ReturnCode::EPERM),
+                    _ => Ok(()),
+                }
+            }
+
+            Syscall::YIELD | Syscall::MEMOP { .. } => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Recording system calls for `Platform::trace_syscall()`.
+//!
+//! `config::CONFIG.trace_syscalls` prints every system call as it happens,
+//! which is only practical for short debugging sessions: it floods the console
+//! and slows the kernel down enough to change how processes behave.
+//! `SyscallTrace` instead stores a compact record of each system call in a
+//! fixed-size ring buffer, which is read out later, for example through the
+//! process console. When the buffer is full the oldest records are dropped.
+//!
+//! Recording can be turned on and off, and limited to one process or one
+//! driver, while the kernel runs.
+//!
+//! A board records system calls by holding a `SyscallTrace` and forwarding
+//! `trace_syscall()` to it:
+//!
+//! ```ignore
+//! impl Platform for Hail {
+//!     fn trace_syscall(
+//!         &self,
+//!         process: &dyn ProcessType,
+//!         syscall: &Syscall,
+//!         result: Option<ReturnCode>,
+//!     ) {
+//!         self.syscall_trace.record(process, syscall, result);
+//!     }
+//! }
+//! ```
+//!
+//! Record format
+//! -------------
+//!
+//! `SyscallTraceRecord::to_bytes()` encodes a record as `RECORD_LEN` bytes,
+//! with all fields little endian:
+//!
+//! ```text
+//! 0        4        8     9     10        12       16       20       24       28
+//! +--------+--------+-----+-----+---------+--------+--------+--------+--------+
+//! | time   | app id |class| 0   |subdriver| driver | arg0   | arg1   | result |
+//! +--------+--------+-----+-----+---------+--------+--------+--------+--------+
+//! ```
+//!
+//! - `time` is the value of the board's timer when the system call returned,
+//!   in ticks of the frequency given by `SyscallTraceReader::frequency()`.
+//! - `app id` is the identifier of the process (`AppId::id()`).
+//! - `class` is the system call number: 0 yield, 1 subscribe, 2 command,
+//!   3 allow, 4 memop.
+//! - `driver`, `subdriver`, `arg0` and `arg1` are the arguments of the system
+//!   call. For subscribe `arg0` is the callback and `arg1` the app data, for
+//!   allow they are the address and size of the buffer, and for memop the
+//!   operation is in `subdriver` and its argument in `arg0`.
+//! - `result` is the `ReturnCode` returned to the process, or 0 for yield.
+//!
+//! `tools/syscall_trace_decode.py` turns these records into a readable timeline.
+
+use core::cell::Cell;
+
+use crate::callback::AppId;
+use crate::common::cells::{MapCell, OptionalCell};
+use crate::common::{Queue, RingBuffer};
+use crate::hil::time::{self, Frequency, Ticks};
+use crate::process;
+use crate::returncode::ReturnCode;
+use crate::syscall::Syscall;
+
+/// Length of an encoded `SyscallTraceRecord`.
+pub const RECORD_LEN: usize = 28;
+
+/// A single recorded system call.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SyscallTraceRecord {
+    pub timestamp: u32,
+    pub app_id: usize,
+    pub class: u8,
+    pub driver_number: usize,
+    pub subdriver_number: usize,
+    pub arg0: usize,
+    pub arg1: usize,
+    pub result: isize,
+}
+
+impl SyscallTraceRecord {
+    fn new(timestamp: u32, app_id: AppId, syscall: &Syscall, result: Option<ReturnCode>) -> Self {
+        let (class, driver_number, subdriver_number, arg0, arg1) = match *syscall {
+            Syscall::YIELD => (0, 0, 0, 0, 0),
+            Syscall::SUBSCRIBE {
+                driver_number,
+                subdriver_number,
+                callback_ptr,
+                appdata,
+            } => (
+                1,
+                driver_number,
+                subdriver_number,
+                callback_ptr as usize,
+                appdata,
+            ),
+            Syscall::COMMAND {
+                driver_number,
+                subdriver_number,
+                arg0,
+                arg1,
+            } => (2, driver_number, subdriver_number, arg0, arg1),
+            Syscall::ALLOW {
+                driver_number,
+                subdriver_number,
+                allow_address,
+                allow_size,
+            } => (
+                3,
+                driver_number,
+                subdriver_number,
+                allow_address as usize,
+                allow_size,
+            ),
+            Syscall::MEMOP { operand, arg0 } => (4, 0, operand, arg0, 0),
+        };
+        SyscallTraceRecord {
+            timestamp,
+            app_id: app_id.id(),
+            class,
+            driver_number,
+            subdriver_number,
+            arg0,
+            arg1,
+            result: result.map_or(0, isize::from),
+        }
+    }
+
+    /// Encode the record in the format described in the module documentation.
+    pub fn to_bytes(&self) -> [u8; RECORD_LEN] {
+        let mut bytes = [0; RECORD_LEN];
+        bytes[0..4].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[4..8].copy_from_slice(&(self.app_id as u32).to_le_bytes());
+        bytes[8] = self.class;
+        bytes[10..12].copy_from_slice(&(self.subdriver_number as u16).to_le_bytes());
+        bytes[12..16].copy_from_slice(&(self.driver_number as u32).to_le_bytes());
+        bytes[16..20].copy_from_slice(&(self.arg0 as u32).to_le_bytes());
+        bytes[20..24].copy_from_slice(&(self.arg1 as u32).to_le_bytes());
+        bytes[24..28].copy_from_slice(&(self.result as i32).to_le_bytes());
+        bytes
+    }
+}
+
+/// Reading out and controlling a `SyscallTrace`.
+///
+/// This does not depend on the timer the trace uses, so that capsules such as
+/// the process console can hold any `SyscallTrace`.
+pub trait SyscallTraceReader {
+    /// Remove the oldest record from the trace and return it.
+    fn pop(&self) -> Option<SyscallTraceRecord>;
+
+    /// Returns how many records are in the trace.
+    fn len(&self) -> usize;
+
+    /// Returns whether the trace is empty.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns how many records were dropped because the trace was full, and
+    /// resets the count.
+    fn take_dropped_count(&self) -> usize;
+
+    /// Returns the frequency of the record timestamps, in Hz.
+    fn frequency(&self) -> u32;
+
+    /// Start or stop recording.
+    fn set_enabled(&self, enabled: bool);
+
+    /// Returns whether system calls are being recorded.
+    fn is_enabled(&self) -> bool;
+
+    /// Only record system calls of this process, or of all processes if
+    /// `None`.
+    fn set_process_filter(&self, app_id: Option<AppId>);
+
+    /// Only record system calls to this driver, or to all drivers if `None`.
+    /// Yield and memop calls are not recorded while a driver filter is set.
+    fn set_driver_filter(&self, driver_number: Option<usize>);
+}
+
+/// A ring buffer of recorded system calls.
+pub struct SyscallTrace<'a, T: time::Time> {
+    time: &'a T,
+    records: MapCell<RingBuffer<'static, SyscallTraceRecord>>,
+    dropped: Cell<usize>,
+    enabled: Cell<bool>,
+    process_filter: OptionalCell<AppId>,
+    driver_filter: OptionalCell<usize>,
+}
+
+impl<'a, T: time::Time> SyscallTrace<'a, T> {
+    /// Create a trace that stores up to `buffer.len() - 1` records. Recording
+    /// starts disabled.
+    pub fn new(time: &'a T, buffer: &'static mut [SyscallTraceRecord]) -> SyscallTrace<'a, T> {
+        SyscallTrace {
+            time,
+            records: MapCell::new(RingBuffer::new(buffer)),
+            dropped: Cell::new(0),
+            enabled: Cell::new(false),
+            process_filter: OptionalCell::empty(),
+            driver_filter: OptionalCell::empty(),
+        }
+    }
+
+    /// Record a system call a process made, and the value returned to it, if
+    /// it passes the filters.
+    pub fn record(
+        &self,
+        process: &dyn process::ProcessType,
+        syscall: &Syscall,
+        result: Option<ReturnCode>,
+    ) {
+        if !self.enabled.get() {
+            return;
+        }
+        let app_id = process.appid();
+        if self
+            .process_filter
+            .map_or(false, |filter| *filter != app_id)
+        {
+            return;
+        }
+        if let Some(driver) = self.driver_filter.map(|driver| *driver) {
+            let matches = match *syscall {
+                Syscall::SUBSCRIBE { driver_number, .. }
+                | Syscall::COMMAND { driver_number, .. }
+                | Syscall::ALLOW { driver_number, .. } => driver_number == driver,
+                Syscall::YIELD | Syscall::MEMOP { .. } => false,
+            };
+            if !matches {
+                return;
+            }
+        }
+
+        let record = SyscallTraceRecord::new(self.time.now().into_u32(), app_id, syscall, result);
+        self.records.map(|records| {
+            if records.push(record).is_some() {
+                self.dropped.set(self.dropped.get() + 1);
+            }
+        });
+    }
+}
+
+impl<'a, T: time::Time> SyscallTraceReader for SyscallTrace<'a, T> {
+    fn pop(&self) -> Option<SyscallTraceRecord> {
+        self.records.map_or(None, |records| records.dequeue())
+    }
+
+    fn len(&self) -> usize {
+        self.records.map_or(0, |records| records.len())
+    }
+
+    fn take_dropped_count(&self) -> usize {
+        self.dropped.replace(0)
+    }
+
+    fn frequency(&self) -> u32 {
+        T::Frequency::frequency()
+    }
+
+    fn set_enabled(&self, enabled: bool) {
+        self.enabled.set(enabled);
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    fn set_process_filter(&self, app_id: Option<AppId>) {
+        self.process_filter.insert(app_id);
+    }
+
+    fn set_driver_filter(&self, driver_number: Option<usize>) {
+        self.driver_filter.insert(driver_number);
+    }
+}
@@ -4,7 +4,7 @@ use core::cell::Cell;
 use core::convert::TryInto;
 use core::fmt;
 use core::fmt::Write;
-use core::ptr::{write_volatile, NonNull};
+use core::ptr::{read_volatile, write_volatile, NonNull};
 use core::{mem, ptr, slice, str};
 
 use crate::callback::{AppId, CallbackId};
@@ -15,13 +15,15 @@ use crate::config;
 use crate::debug;
 use crate::ipc;
 use crate::mem::{AppSlice, Shared};
+use crate::platform::crash_record;
 use crate::platform::mpu::{self, MPU};
 use crate::platform::Chip;
+use crate::process_checker::{self, AppCredentialsChecker};
 use crate::returncode::ReturnCode;
 use crate::sched::Kernel;
 use crate::syscall::{self, Syscall, UserspaceKernelBoundary};
-use crate::tbfheader;
-use core::cmp::max;
+use crate::tbfheader::{self, CommandPermissions, TbfHeaderV2Realtime};
+use core::cmp::{self, max};
 
 /// Errors that can occur when trying to load and create processes.
 pub enum ProcessLoadError {
@@ -56,6 +58,15 @@ pub enum ProcessLoadError {
         expected_address: u32,
     },
 
+    /// The board's `AppCredentialsChecker` did not accept the credentials in
+    /// the TBF footers of a process (or the process had none and the checker
+    /// requires them, or its footers were malformed). The process was not
+    /// loaded.
+    CredentialsNotAccepted,
+
+    /// There is no empty slot in the processes array to put a new process in.
+    NoFreeProcessSlot,
+
     /// Process loading error due (likely) to a bug in the kernel. If you get
     /// this error please open a bug report.
     InternalError,
@@ -109,6 +120,12 @@ impl fmt::Debug for ProcessLoadError {
                 actual_address, expected_address
             ),
 
+            ProcessLoadError::CredentialsNotAccepted => {
+                write!(f, "App credentials were not accepted")
+            }
+
+            ProcessLoadError::NoFreeProcessSlot => write!(f, "No free slot in the processes array"),
+
             ProcessLoadError::InternalError => write!(f, "Error in kernel. Likely a bug."),
         }
     }
@@ -139,6 +156,59 @@ pub fn load_processes<C: Chip>(
     app_memory: &'static mut [u8],
     procs: &'static mut [Option<&'static dyn ProcessType>],
     fault_response: FaultResponse,
+    capability: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    load_processes_inner(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        procs,
+        fault_response,
+        None,
+        capability,
+    )
+}
+
+/// Load processes from flash like `load_processes()`, but only load processes
+/// whose TBF credentials are accepted by `checker`.
+///
+/// A process whose credentials are not accepted is skipped in the same way as
+/// a disabled process: it is given no memory and its entry in `procs` is left
+/// as `None`. Loading continues with the next process in flash, and each
+/// skipped process is reported on the debug output. Skipping a process is not
+/// an error: this function only returns an error if loading fails in the same
+/// way as in `load_processes()`.
+pub fn load_processes_with_checker<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &'static mut [u8],
+    procs: &'static mut [Option<&'static dyn ProcessType>],
+    fault_response: FaultResponse,
+    checker: &'static dyn AppCredentialsChecker,
+    capability: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    load_processes_inner(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        procs,
+        fault_response,
+        Some(checker),
+        capability,
+    )
+}
+
+fn load_processes_inner<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &'static mut [u8],
+    procs: &'static mut [Option<&'static dyn ProcessType>],
+    fault_response: FaultResponse,
+    checker: Option<&'static dyn AppCredentialsChecker>,
     _capability: &dyn ProcessManagementCapability,
 ) -> Result<(), ProcessLoadError> {
     if config::CONFIG.debug_load_processes {
@@ -164,7 +234,7 @@ pub fn load_processes<C: Chip>(
                 // Not enough flash to test for another app. This just means
                 // we are at the end of flash, and there are no more apps to
                 // load.
-                return Ok(());
+                break;
             }
         };
 
@@ -187,7 +257,7 @@ pub fn load_processes<C: Chip>(
                 // header we started to parse is intentionally invalid to signal
                 // the end of apps. This is ok and just means we have finished
                 // loading apps.
-                return Ok(());
+                break;
             }
         };
 
@@ -205,6 +275,22 @@ pub fn load_processes<C: Chip>(
             .get(entry_flash.len()..)
             .ok_or(ProcessLoadError::NotEnoughFlash)?;
 
+        // If the board asked us to check credentials, do so before creating
+        // the process. A process whose credentials are not accepted is treated
+        // like a disabled process: it is skipped and does not use any memory.
+        if let Some(checker) = checker {
+            if header_length > 0
+                && !credentials_accepted(checker, entry_flash, header_length, version)?
+            {
+                debug!(
+                    "[!] flash={:#010X}-{:#010X} - credentials not accepted, skipping",
+                    entry_flash.as_ptr() as usize,
+                    entry_flash.as_ptr() as usize + entry_flash.len() - 1,
+                );
+                continue;
+            }
+        }
+
         // Need to reassign remaining_memory in every iteration so the compiler
         // knows it will not be re-borrowed.
         remaining_memory = if header_length > 0 {
@@ -226,7 +312,8 @@ pub fn load_processes<C: Chip>(
                     remaining_memory,
                     fault_response,
                     i,
-                )?
+                )
+                .map_err(|(error, _)| error)?
             };
             process_option.map(|process| {
                 if config::CONFIG.debug_load_processes {
@@ -251,10 +338,34 @@ pub fn load_processes<C: Chip>(
             remaining_memory
         };
     }
-
     Ok(())
 }
 
+/// Check the credentials of the TBF object in `entry_flash` with `checker`.
+///
+/// Returns `Ok(true)` if the process may be loaded. Padding and disabled
+/// processes are never loaded, so they are not checked and always pass.
+fn credentials_accepted(
+    checker: &dyn AppCredentialsChecker,
+    entry_flash: &'static [u8],
+    header_length: u16,
+    version: u16,
+) -> Result<bool, ProcessLoadError> {
+    let header_flash = entry_flash
+        .get(0..header_length as usize)
+        .ok_or(ProcessLoadError::NotEnoughFlash)?;
+    let tbf_header = tbfheader::parse_tbf_header(header_flash, version)?;
+    if !tbf_header.is_app() || !tbf_header.enabled() {
+        return Ok(true);
+    }
+
+    match process_checker::check_app_credentials(checker, entry_flash, &tbf_header) {
+        Ok(()) => Ok(true),
+        Err(ProcessLoadError::CredentialsNotAccepted) => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
 /// This trait is implemented by process structs.
 pub trait ProcessType {
     /// Returns the process's identifier
@@ -312,12 +423,29 @@ pub trait ProcessType {
     /// `FaultResponse` for this process to occur.
     fn set_fault_state(&self);
 
+    /// Put this process in the fault state like `set_fault_state()`, but
+    /// record `reason` as the cause of the fault.
+    fn set_fault_state_with_reason(&self, reason: FaultReason);
+
     /// Returns how many times this process has been restarted.
     fn get_restart_count(&self) -> usize;
 
+    /// Returns why this process faulted the last time it did, or `None` if it
+    /// never faulted.
+    fn get_fault_reason(&self) -> Option<FaultReason>;
+
     /// Get the name of the process. Used for IPC.
     fn get_process_name(&self) -> &'static str;
 
+    /// Get the permissions this process has for the commands of driver
+    /// `driver_num`, as declared in its TBF header. `offset` selects which
+    /// block of 64 command numbers the returned mask covers.
+    fn get_command_permissions(&self, driver_num: usize, offset: usize) -> CommandPermissions;
+
+    /// Get the timing requirements of this process from its TBF header, if it
+    /// declared any.
+    fn get_realtime_parameters(&self) -> Option<TbfHeaderV2Realtime>;
+
     // memop operations
 
     /// Change the location of the program break and reallocate the MPU region
@@ -363,6 +491,10 @@ pub trait ProcessType {
     /// Debug function to update the kernel on where the stack starts for this
     /// process. Processes are not required to call this through the memop
     /// system call, but it aids in debugging the process.
+    ///
+    /// If the process declared the size of its stack in its TBF header, this
+    /// also protects the memory below the stack and fills the unused part of
+    /// the stack with a pattern to measure its high-water mark.
     fn update_stack_start_pointer(&self, stack_pointer: *const u8);
 
     /// Debug function to update the kernel on where the process heap starts.
@@ -471,6 +603,24 @@ pub trait ProcessType {
     /// memory, BSS, and data sections) of this process.
     unsafe fn print_memory_map(&self, writer: &mut dyn Write);
 
+    /// Print out the configuration of the memory protection unit (MPU) that
+    /// the process runs under.
+    fn print_mpu_config(&self, writer: &mut dyn Write);
+
+    /// Get the addresses of the regions of flash and RAM that make up this
+    /// process.
+    fn get_addresses(&self) -> ProcessAddresses;
+
+    /// Returns how many bytes of the grant region are taken up by the grant
+    /// with this grant number, or `None` if the grant has not been allocated
+    /// for this process (or the process is inactive).
+    ///
+    /// This is the distance to the next allocation above the grant, so it
+    /// also counts any alignment padding, and any memory a capsule allocated
+    /// for this process between this grant and the grant that was allocated
+    /// before it.
+    fn get_grant_size(&self, grant_num: usize) -> Option<usize>;
+
     /// Print out the full state of the process: its memory map, its
     /// context, and the state of the memory protection unit (MPU).
     unsafe fn print_full_process(&self, writer: &mut dyn Write);
@@ -489,9 +639,82 @@ pub trait ProcessType {
     /// Increment the number of times the process has exceeded its timeslice.
     fn debug_timeslice_expired(&self);
 
+    /// Returns how many times this process has missed a deadline.
+    fn debug_deadline_miss_count(&self) -> usize;
+
+    /// Increment the number of times the process has missed a deadline.
+    fn debug_deadline_missed(&self);
+
+    /// Returns how many times this process has overflowed its stack.
+    fn debug_stack_overflow_count(&self) -> usize;
+
+    /// Returns the size of the stack in bytes, if the process declared it in
+    /// its TBF header.
+    fn debug_stack_size(&self) -> Option<usize>;
+
+    /// Returns the largest number of bytes the stack of this process has
+    /// used, or `None` if the process has not told the kernel where its stack
+    /// starts. If the process declared the size of its stack this is measured
+    /// by how much of the stack the process overwrote, otherwise it is the
+    /// lowest stack pointer the kernel saw when the process stopped running.
+    fn debug_stack_high_water_mark(&self) -> Option<usize>;
+
     /// Increment the number of times the process called a syscall and record
     /// the last syscall that was called.
     fn debug_syscall_called(&self, last_syscall: Syscall);
+
+    /// Returns one of the last syscalls the process called. Index 0 is the
+    /// most recent one. Only the last `SYSCALL_HISTORY_LEN` syscalls are
+    /// kept.
+    fn debug_syscall_history(&self, index: usize) -> Option<Syscall>;
+
+    /// Record that the process subscribed a callback, or unsubscribed it if
+    /// `callback_ptr` is null.
+    fn debug_callback_subscribed(&self, callback_id: CallbackId, callback_ptr: *mut ());
+
+    /// Returns one of the callbacks the process has subscribed, and the
+    /// address of the function it subscribed. At most
+    /// `SUBSCRIBED_CALLBACKS_LEN` callbacks are recorded; callbacks subscribed
+    /// once the record is full are not listed.
+    fn debug_subscribed_callback(&self, index: usize) -> Option<(CallbackId, usize)>;
+}
+
+/// How many of the last syscalls of a process are kept for debugging.
+pub const SYSCALL_HISTORY_LEN: usize = 8;
+
+/// How many subscribed callbacks of a process are recorded for debugging.
+pub const SUBSCRIBED_CALLBACKS_LEN: usize = 8;
+
+/// The addresses of the regions of flash and RAM that make up a process.
+///
+/// RAM grows from `sram_start` upwards: stack, data, and heap up to
+/// `sram_app_brk`. The kernel's grant region grows down from `sram_end` to
+/// `sram_grant_start`.
+#[derive(Clone, Copy, Debug)]
+pub struct ProcessAddresses {
+    /// Start of the process in flash, where its TBF header is.
+    pub flash_start: usize,
+    /// Start of the part of flash the process may write, after its TBF header
+    /// and any other protected region.
+    pub flash_non_protected_start: usize,
+    /// End of the process in flash.
+    pub flash_end: usize,
+    /// Start of the RAM of the process.
+    pub sram_start: usize,
+    /// End of the RAM the process can access (its `brk`).
+    pub sram_app_brk: usize,
+    /// Start of the grant region, the lowest address the kernel has used.
+    pub sram_grant_start: usize,
+    /// Start of the kernel-owned memory of the process, above the grants.
+    pub sram_kernel_start: usize,
+    /// End of the RAM of the process.
+    pub sram_end: usize,
+    /// Start of the heap, if the process told the kernel where it is.
+    pub sram_heap_start: Option<usize>,
+    /// Top of the stack, if the process told the kernel where it is.
+    pub sram_stack_top: Option<usize>,
+    /// Lowest stack pointer the kernel has seen.
+    pub sram_stack_bottom: usize,
 }
 
 /// Generic trait for implementing process restart policies.
@@ -632,6 +855,22 @@ pub enum State {
     Unstarted,
 }
 
+/// Why a process faulted, as far as the kernel can tell.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FaultReason {
+    /// The process grew its stack past its declared size, or past the start
+    /// of its memory if it did not declare a size.
+    StackOverflow,
+
+    /// The process did not send a heartbeat to the application watchdog
+    /// before its deadline.
+    MissedHeartbeat,
+
+    /// Any other fault, such as an invalid memory access or instruction, or
+    /// the kernel forcing the process to fault.
+    Other,
+}
+
 /// A wrapper around `Cell<State>` is used by `Process` to prevent bugs arising from
 /// the state duplication in the kernel work tracking and process state tracking.
 struct ProcessStateCell<'a> {
@@ -761,8 +1000,13 @@ struct ProcessDebug {
     /// How many syscalls have occurred since the process started.
     syscall_count: usize,
 
-    /// What was the most recent syscall.
-    last_syscall: Option<Syscall>,
+    /// The most recent syscalls. The syscall with count `n` is at index
+    /// `n % SYSCALL_HISTORY_LEN`.
+    syscall_history: [Option<Syscall>; SYSCALL_HISTORY_LEN],
+
+    /// The callbacks the process has subscribed, with the address of the
+    /// function subscribed.
+    subscribed_callbacks: [Option<(CallbackId, usize)>; SUBSCRIBED_CALLBACKS_LEN],
 
     /// How many callbacks were dropped because the queue was insufficiently
     /// long.
@@ -771,6 +1015,12 @@ struct ProcessDebug {
     /// How many times this process has been paused because it exceeded its
     /// timeslice.
     timeslice_expiration_count: usize,
+
+    /// How many times this process had not finished its work by its deadline.
+    deadline_miss_count: usize,
+
+    /// How many times this process faulted because it overflowed its stack.
+    stack_overflow_count: usize,
 }
 
 /// A type for userspace processes in Tock.
@@ -881,6 +1131,18 @@ pub struct Process<'a, C: 'static + Chip> {
     /// determine if the process should be restarted or not.
     restart_count: Cell<usize>,
 
+    /// The lowest address of the stack, if the process declared the size of
+    /// its stack and told the kernel where its stack starts. The unused part
+    /// of the stack above it is painted with `STACK_PAINT`.
+    stack_bottom: Cell<Option<*const u8>>,
+
+    /// Why the process stopped running, if it faulted and the kernel could
+    /// tell why. This is taken when the process is put in the fault state.
+    pending_fault_reason: Cell<Option<FaultReason>>,
+
+    /// Why the process faulted the last time it did.
+    fault_reason: Cell<Option<FaultReason>>,
+
     /// Name of the app.
     process_name: &'static str,
 
@@ -981,10 +1243,28 @@ impl<C: Chip> ProcessType for Process<'_, C> {
     fn set_fault_state(&self) {
         self.state.update(State::Fault);
 
+        let fault_reason = self
+            .pending_fault_reason
+            .take()
+            .unwrap_or(FaultReason::Other);
+        self.fault_reason.set(Some(fault_reason));
+        if fault_reason == FaultReason::StackOverflow {
+            self.debug.map(|debug| debug.stack_overflow_count += 1);
+        }
+
         match self.fault_response {
             FaultResponse::Panic => {
                 // process faulted. Panic and print status
-                panic!("Process {} had a fault", self.process_name);
+                self.record_fault();
+                match fault_reason {
+                    FaultReason::StackOverflow => {
+                        panic!("Process {} overflowed its stack", self.process_name)
+                    }
+                    FaultReason::MissedHeartbeat => {
+                        panic!("Process {} missed its watchdog deadline", self.process_name)
+                    }
+                    FaultReason::Other => panic!("Process {} had a fault", self.process_name),
+                }
             }
             FaultResponse::Restart(_) => {
                 self.restart(State::StoppedFaulted);
@@ -1000,10 +1280,19 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         }
     }
 
+    fn set_fault_state_with_reason(&self, reason: FaultReason) {
+        self.pending_fault_reason.set(Some(reason));
+        self.set_fault_state();
+    }
+
     fn get_restart_count(&self) -> usize {
         self.restart_count.get()
     }
 
+    fn get_fault_reason(&self) -> Option<FaultReason> {
+        self.fault_reason.get()
+    }
+
     fn dequeue_task(&self) -> Option<Task> {
         self.tasks.map_or(None, |tasks| {
             tasks.dequeue().map(|cb| {
@@ -1054,6 +1343,10 @@ impl<C: Chip> ProcessType for Process<'_, C> {
                 // we had could be entirely wrong by now.
                 debug.min_stack_pointer = stack_pointer;
             });
+
+            if let Some(stack_size) = self.header.get_stack_size() {
+                self.protect_stack(stack_pointer, stack_size as usize);
+            }
         }
     }
 
@@ -1265,6 +1558,14 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         self.process_name
     }
 
+    fn get_command_permissions(&self, driver_num: usize, offset: usize) -> CommandPermissions {
+        self.header.get_command_permissions(driver_num, offset)
+    }
+
+    fn get_realtime_parameters(&self) -> Option<TbfHeaderV2Realtime> {
+        self.header.get_realtime_parameters()
+    }
+
     unsafe fn set_syscall_return_value(&self, return_value: isize) {
         self.stored_state.map(|stored_state| {
             self.chip
@@ -1319,6 +1620,8 @@ impl<C: Chip> ProcessType for Process<'_, C> {
                         debug.min_stack_pointer = bad_stack_bottom;
                     }
                 });
+                self.pending_fault_reason
+                    .set(Some(FaultReason::StackOverflow));
                 self.set_fault_state();
             }
 
@@ -1341,6 +1644,21 @@ impl<C: Chip> ProcessType for Process<'_, C> {
                 .userspace_kernel_boundary()
                 .switch_to_process(self.sp(), stored_state);
             self.current_stack_pointer.set(stack_pointer as *const u8);
+
+            // Tell a stack overflow apart from other faults by where the
+            // faulting access went.
+            if switch_reason == syscall::ContextSwitchReason::Fault {
+                let fault_address = self
+                    .chip
+                    .userspace_kernel_boundary()
+                    .fault_address(self.sp(), stored_state);
+                let fault_reason = match fault_address {
+                    Some(address) if self.is_below_stack(address) => FaultReason::StackOverflow,
+                    _ => FaultReason::Other,
+                };
+                self.pending_fault_reason.set(Some(fault_reason));
+            }
+
             switch_reason
         });
 
@@ -1373,13 +1691,142 @@ impl<C: Chip> ProcessType for Process<'_, C> {
             .map(|debug| debug.timeslice_expiration_count += 1);
     }
 
+    fn debug_deadline_miss_count(&self) -> usize {
+        self.debug.map_or(0, |debug| debug.deadline_miss_count)
+    }
+
+    fn debug_deadline_missed(&self) {
+        self.debug.map(|debug| debug.deadline_miss_count += 1);
+    }
+
+    fn debug_stack_overflow_count(&self) -> usize {
+        self.debug.map_or(0, |debug| debug.stack_overflow_count)
+    }
+
+    fn debug_stack_size(&self) -> Option<usize> {
+        self.header.get_stack_size().map(|size| size as usize)
+    }
+
+    fn debug_stack_high_water_mark(&self) -> Option<usize> {
+        let stack_top = self
+            .debug
+            .map_or(None, |debug| debug.app_stack_start_pointer)? as usize;
+        let min_stack_pointer = self
+            .debug
+            .map_or(stack_top, |debug| debug.min_stack_pointer as usize);
+        let mut deepest = cmp::min(min_stack_pointer, stack_top);
+
+        // The stack reached as deep as the first word that no longer holds
+        // the paint. Words between that and the stack pointer at the time the
+        // stack was painted were never painted, so the search stops there.
+        if let Some(stack_bottom) = self.stack_bottom.get() {
+            let mut word = (stack_bottom as usize + 3) & !3;
+            while word + 4 <= deepest
+                && unsafe { read_volatile(word as *const u32) } == Self::STACK_PAINT
+            {
+                word += 4;
+            }
+            deepest = cmp::min(deepest, word);
+        }
+
+        Some(stack_top - deepest)
+    }
+
     fn debug_syscall_called(&self, last_syscall: Syscall) {
         self.debug.map(|debug| {
+            debug.syscall_history[debug.syscall_count % SYSCALL_HISTORY_LEN] = Some(last_syscall);
             debug.syscall_count += 1;
-            debug.last_syscall = Some(last_syscall);
         });
     }
 
+    fn debug_syscall_history(&self, index: usize) -> Option<Syscall> {
+        self.debug.map_or(None, |debug| {
+            if index >= SYSCALL_HISTORY_LEN || index >= debug.syscall_count {
+                None
+            } else {
+                debug.syscall_history[(debug.syscall_count - 1 - index) % SYSCALL_HISTORY_LEN]
+            }
+        })
+    }
+
+    fn debug_callback_subscribed(&self, callback_id: CallbackId, callback_ptr: *mut ()) {
+        self.debug.map(|debug| {
+            let existing = debug
+                .subscribed_callbacks
+                .iter_mut()
+                .find(|entry| entry.map_or(false, |(id, _)| id == callback_id));
+            match existing {
+                Some(entry) if callback_ptr.is_null() => *entry = None,
+                Some(entry) => *entry = Some((callback_id, callback_ptr as usize)),
+                None if callback_ptr.is_null() => {}
+                None => {
+                    if let Some(entry) = debug
+                        .subscribed_callbacks
+                        .iter_mut()
+                        .find(|entry| entry.is_none())
+                    {
+                        *entry = Some((callback_id, callback_ptr as usize));
+                    }
+                }
+            }
+        });
+    }
+
+    fn debug_subscribed_callback(&self, index: usize) -> Option<(CallbackId, usize)> {
+        self.debug.map_or(None, |debug| {
+            debug
+                .subscribed_callbacks
+                .iter()
+                .filter_map(|entry| *entry)
+                .nth(index)
+        })
+    }
+
+    fn print_mpu_config(&self, writer: &mut dyn Write) {
+        self.mpu_config.map(|config| {
+            let _ = writer.write_fmt(format_args!("{}", config));
+        });
+    }
+
+    fn get_addresses(&self) -> ProcessAddresses {
+        ProcessAddresses {
+            flash_start: self.flash_start() as usize,
+            flash_non_protected_start: self.flash_non_protected_start() as usize,
+            flash_end: self.flash_end() as usize,
+            sram_start: self.mem_start() as usize,
+            sram_app_brk: self.app_break.get() as usize,
+            sram_grant_start: self.kernel_memory_break.get() as usize,
+            sram_kernel_start: self.original_kernel_memory_break as usize,
+            sram_end: self.mem_end() as usize,
+            sram_heap_start: self.debug.map_or(None, |debug| {
+                debug.app_heap_start_pointer.map(|p| p as usize)
+            }),
+            sram_stack_top: self.debug.map_or(None, |debug| {
+                debug.app_stack_start_pointer.map(|p| p as usize)
+            }),
+            sram_stack_bottom: self
+                .debug
+                .map_or(ptr::null(), |debug| debug.min_stack_pointer)
+                as usize,
+        }
+    }
+
+    fn get_grant_size(&self, grant_num: usize) -> Option<usize> {
+        let grant_ptr = self
+            .get_grant_ptr(grant_num)
+            .filter(|grant_ptr| !grant_ptr.is_null())? as usize;
+
+        // Grants are allocated downwards from the start of the kernel-owned
+        // memory, so this grant ends where the closest allocation above it
+        // starts.
+        let grant_end = (0..self.kernel.get_grant_count_and_finalize())
+            .filter_map(|other| self.get_grant_ptr(other))
+            .map(|other_ptr| other_ptr as usize)
+            .filter(|other_ptr| *other_ptr > grant_ptr)
+            .fold(self.original_kernel_memory_break as usize, cmp::min);
+        Some(grant_end - grant_ptr)
+    }
+
     unsafe fn print_memory_map(&self, writer: &mut dyn Write) {
         // Flash
         let flash_end = self.flash.as_ptr().add(self.flash.len()) as usize;
@@ -1410,7 +1857,7 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         // application statistics
         let events_queued = self.tasks.map_or(0, |tasks| tasks.len());
         let syscall_count = self.debug.map_or(0, |debug| debug.syscall_count);
-        let last_syscall = self.debug.map(|debug| debug.last_syscall);
+        let last_syscall = self.debug_syscall_history(0);
         let dropped_callback_count = self.debug.map_or(0, |debug| debug.dropped_callback_count);
         let restart_count = self.restart_count.get();
 
@@ -1432,6 +1879,10 @@ impl<C: Chip> ProcessType for Process<'_, C> {
             None => writer.write_str(" Last Syscall: None\r\n"),
         };
 
+        if let Some(fault_reason) = self.fault_reason.get() {
+            let _ = writer.write_fmt(format_args!(" Last Fault: {:?}\r\n", fault_reason));
+        }
+
         let _ = writer.write_fmt(format_args!(
             "\
              \r\n\
@@ -1495,8 +1946,15 @@ impl<C: Chip> ProcessType for Process<'_, C> {
 
         match sram_stack_start {
             Some(sram_stack_start) => {
-                let sram_stack_size = sram_stack_start - sram_stack_bottom;
-                let sram_stack_allocated = sram_stack_start - sram_start;
+                // Use the measured high-water mark and the declared size of
+                // the stack if the process declared it.
+                let sram_stack_size = self
+                    .debug_stack_high_water_mark()
+                    .unwrap_or(sram_stack_start - sram_stack_bottom);
+                let sram_stack_allocated = match self.stack_bottom.get() {
+                    Some(stack_bottom) => sram_stack_start - stack_bottom as usize,
+                    None => sram_stack_start - sram_start,
+                };
 
                 let _ = writer.write_fmt(format_args!(
                     "\
@@ -1549,9 +2007,7 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         });
 
         // Display the current state of the MPU for this process.
-        self.mpu_config.map(|config| {
-            let _ = writer.write_fmt(format_args!("{}", config));
-        });
+        self.print_mpu_config(writer);
 
         // Print a helpful message on how to re-compile a process to view the
         // listing file. If a process is PIC, then we also need to print the
@@ -1594,6 +2050,13 @@ fn exceeded_check(size: usize, allocated: usize) -> &'static str {
     }
 }
 
+/// The result of `Process::create()`: the process, if one was created, and
+/// the memory it does not use, or the error and all of the memory it was given.
+type CreateResult = Result<
+    (Option<&'static dyn ProcessType>, &'static mut [u8]),
+    (ProcessLoadError, &'static mut [u8]),
+>;
+
 impl<C: 'static + Chip> Process<'_, C> {
     const INITIAL_APP_MEMORY_SIZE: usize = 3 * 1024;
 
@@ -1604,6 +2067,20 @@ impl<C: 'static + Chip> Process<'_, C> {
     // Memory offset to make room for this process's metadata.
     const PROCESS_STRUCT_OFFSET: usize = mem::size_of::<Process<C>>();
 
+    // How far below the stack a faulting access counts as a stack overflow.
+    // This is also the largest guard region the MPU is asked to protect below
+    // the stack, as a stack frame larger than the guard can skip over it.
+    const STACK_GUARD_SIZE: usize = 256;
+
+    // Pattern the unused part of the stack is filled with, to find how deep
+    // the stack has grown.
+    const STACK_PAINT: u32 = 0x57AC_C0DE;
+
+    /// Create a process for the app in `app_flash`, with its memory taken
+    /// from `remaining_memory`.
+    ///
+    /// If creating the process fails, the memory is handed back with the error
+    /// so that it can still be given to other processes.
     pub(crate) unsafe fn create(
         kernel: &'static Kernel,
         chip: &'static C,
@@ -1613,15 +2090,19 @@ impl<C: 'static + Chip> Process<'_, C> {
         remaining_memory: &'static mut [u8],
         fault_response: FaultResponse,
         index: usize,
-    ) -> Result<(Option<&'static dyn ProcessType>, &'static mut [u8]), ProcessLoadError> {
+    ) -> CreateResult {
         // Get a slice for just the app header.
-        let header_flash = app_flash
-            .get(0..header_length as usize)
-            .ok_or(ProcessLoadError::NotEnoughFlash)?;
+        let header_flash = match app_flash.get(0..header_length as usize) {
+            Some(header_flash) => header_flash,
+            None => return Err((ProcessLoadError::NotEnoughFlash, remaining_memory)),
+        };
 
         // Parse the full TBF header to see if this is a valid app. If the
         // header can't parse, we will error right here.
-        let tbf_header = tbfheader::parse_tbf_header(header_flash, app_version)?;
+        let tbf_header = match tbfheader::parse_tbf_header(header_flash, app_version) {
+            Ok(tbf_header) => tbf_header,
+            Err(error) => return Err((error.into(), remaining_memory)),
+        };
 
         // First thing: check that the process is at the correct location in
         // flash if the TBF header specified a fixed address. If there is a
@@ -1632,10 +2113,13 @@ impl<C: 'static + Chip> Process<'_, C> {
             let actual_address = app_flash.as_ptr() as u32 + tbf_header.get_protected_size();
             let expected_address = fixed_flash_start;
             if actual_address != expected_address {
-                return Err(ProcessLoadError::IncorrectFlashAddress {
-                    actual_address,
-                    expected_address,
-                });
+                return Err((
+                    ProcessLoadError::IncorrectFlashAddress {
+                        actual_address,
+                        expected_address,
+                    },
+                    remaining_memory,
+                ));
             }
         }
 
@@ -1695,7 +2179,7 @@ impl<C: 'static + Chip> Process<'_, C> {
                     process_name
                 );
             }
-            return Err(ProcessLoadError::MpuInvalidFlashLength);
+            return Err((ProcessLoadError::MpuInvalidFlashLength, remaining_memory));
         }
 
         // Determine how much space we need in the application's
@@ -1726,12 +2210,11 @@ impl<C: 'static + Chip> Process<'_, C> {
         // Right now, we only support skipping some RAM and leaving a chunk
         // unused so that the memory region starts where the process needs it
         // to.
-        let remaining_memory = if let Some(fixed_memory_start) = tbf_header.get_fixed_address_ram()
-        {
+        let memory_offset = if let Some(fixed_memory_start) = tbf_header.get_fixed_address_ram() {
             // The process does have a fixed address.
             if fixed_memory_start == remaining_memory.as_ptr() as u32 {
                 // Address already matches.
-                remaining_memory
+                0
             } else if fixed_memory_start > remaining_memory.as_ptr() as u32 {
                 // Process wants a memory address farther in memory. Try to
                 // advance the memory region to make the address match.
@@ -1741,35 +2224,38 @@ impl<C: 'static + Chip> Process<'_, C> {
                     let actual_address =
                         remaining_memory.as_ptr() as u32 + remaining_memory.len() as u32 - 1;
                     let expected_address = fixed_memory_start;
-                    return Err(ProcessLoadError::MemoryAddressMismatch {
-                        actual_address,
-                        expected_address,
-                    });
+                    return Err((
+                        ProcessLoadError::MemoryAddressMismatch {
+                            actual_address,
+                            expected_address,
+                        },
+                        remaining_memory,
+                    ));
                 } else {
-                    // Change the memory range to start where the process
-                    // requested it.
-                    remaining_memory
-                        .get_mut(diff..)
-                        .ok_or(ProcessLoadError::InternalError)?
+                    // Start the memory range where the process requested it.
+                    diff
                 }
             } else {
                 // Address is earlier in memory, nothing we can do.
                 let actual_address = remaining_memory.as_ptr() as u32;
                 let expected_address = fixed_memory_start;
-                return Err(ProcessLoadError::MemoryAddressMismatch {
-                    actual_address,
-                    expected_address,
-                });
+                return Err((
+                    ProcessLoadError::MemoryAddressMismatch {
+                        actual_address,
+                        expected_address,
+                    },
+                    remaining_memory,
+                ));
             }
         } else {
-            remaining_memory
+            0
         };
 
         // Determine where process memory will go and allocate MPU region for
         // app-owned memory.
         let (app_memory_start, app_memory_size) = match chip.mpu().allocate_app_memory_region(
-            remaining_memory.as_ptr() as *const u8,
-            remaining_memory.len(),
+            remaining_memory.as_ptr().add(memory_offset),
+            remaining_memory.len() - memory_offset,
             min_total_memory_size,
             Self::INITIAL_APP_MEMORY_SIZE,
             initial_kernel_memory_size,
@@ -1788,24 +2274,20 @@ impl<C: 'static + Chip> Process<'_, C> {
                         min_total_memory_size
                     );
                 }
-                return Err(ProcessLoadError::NotEnoughMemory);
+                return Err((ProcessLoadError::NotEnoughMemory, remaining_memory));
             }
         };
 
-        // Get a slice for the memory dedicated to the process. This can fail if
-        // the MPU returns a region of memory that is not inside of the
+        // The MPU must return a region of memory that is inside of the
         // `remaining_memory` slice passed to `create()` to allocate the
         // process's memory out of.
-        let memory_start_offset = app_memory_start as usize - remaining_memory.as_ptr() as usize;
-        // First split the remaining memory into a slice that contains the
-        // process memory and a slice that will not be used by this process.
-        let (app_memory_oversize, unused_memory) =
-            remaining_memory.split_at_mut(memory_start_offset + app_memory_size);
-        // Then since the process's memory need not start at the beginning of
-        // the remaining slice given to create(), get a smaller slice as needed.
-        let app_memory = app_memory_oversize
-            .get_mut(memory_start_offset..)
-            .ok_or(ProcessLoadError::InternalError)?;
+        let memory_start_offset =
+            (app_memory_start as usize).wrapping_sub(remaining_memory.as_ptr() as usize);
+        if memory_start_offset < memory_offset
+            || memory_start_offset + app_memory_size > remaining_memory.len()
+        {
+            return Err((ProcessLoadError::InternalError, remaining_memory));
+        }
 
         // Check if the memory region is valid for the process. If a process
         // included a fixed address for the start of RAM in its TBF header (this
@@ -1813,16 +2295,27 @@ impl<C: 'static + Chip> Process<'_, C> {
         // need a fixed address) then we check that we used the same address
         // when we allocated it in RAM.
         if let Some(fixed_memory_start) = tbf_header.get_fixed_address_ram() {
-            let actual_address = app_memory.as_ptr() as u32;
+            let actual_address = app_memory_start as u32;
             let expected_address = fixed_memory_start;
             if actual_address != expected_address {
-                return Err(ProcessLoadError::MemoryAddressMismatch {
-                    actual_address,
-                    expected_address,
-                });
+                return Err((
+                    ProcessLoadError::MemoryAddressMismatch {
+                        actual_address,
+                        expected_address,
+                    },
+                    remaining_memory,
+                ));
             }
         }
 
+        // First split the remaining memory into a slice that contains the
+        // process memory and a slice that will not be used by this process.
+        let (app_memory_oversize, unused_memory) =
+            remaining_memory.split_at_mut(memory_start_offset + app_memory_size);
+        // Then since the process's memory need not start at the beginning of
+        // the remaining slice given to create(), get a smaller slice as needed.
+        let app_memory = &mut app_memory_oversize[memory_start_offset..];
+
         // Set the initial process stack and memory to 3072 bytes.
         let initial_stack_pointer = app_memory.as_ptr().add(Self::INITIAL_APP_MEMORY_SIZE);
         let initial_sbrk_pointer = app_memory.as_ptr().add(Self::INITIAL_APP_MEMORY_SIZE);
@@ -1916,6 +2409,9 @@ impl<C: 'static + Chip> Process<'_, C> {
         process.state = ProcessStateCell::new(process.kernel);
         process.fault_response = fault_response;
         process.restart_count = Cell::new(0);
+        process.stack_bottom = Cell::new(None);
+        process.pending_fault_reason = Cell::new(None);
+        process.fault_reason = Cell::new(None);
 
         process.mpu_config = MapCell::new(mpu_config);
         process.mpu_regions = [
@@ -1936,9 +2432,12 @@ impl<C: 'static + Chip> Process<'_, C> {
             app_stack_start_pointer: app_stack_start_pointer,
             min_stack_pointer: initial_stack_pointer,
             syscall_count: 0,
-            last_syscall: None,
+            syscall_history: [None; SYSCALL_HISTORY_LEN],
+            subscribed_callbacks: [None; SUBSCRIBED_CALLBACKS_LEN],
             dropped_callback_count: 0,
             timeslice_expiration_count: 0,
+            deadline_miss_count: 0,
+            stack_overflow_count: 0,
         });
 
         let flash_protected_size = process.header.get_protected_size() as usize;
@@ -1978,7 +2477,9 @@ impl<C: 'static + Chip> Process<'_, C> {
                         process_name
                     );
                 }
-                return Err(ProcessLoadError::InternalError);
+                // The memory given to the process cannot be handed back, as
+                // the process structure is already in it.
+                return Err((ProcessLoadError::InternalError, unused_memory));
             }
         };
 
@@ -2052,9 +2553,11 @@ impl<C: 'static + Chip> Process<'_, C> {
         // Reset debug information that is per-execution and not per-process.
         self.debug.map(|debug| {
             debug.syscall_count = 0;
-            debug.last_syscall = None;
+            debug.syscall_history = [None; SYSCALL_HISTORY_LEN];
+            debug.subscribed_callbacks = [None; SUBSCRIBED_CALLBACKS_LEN];
             debug.dropped_callback_count = 0;
             debug.timeslice_expiration_count = 0;
+            debug.deadline_miss_count = 0;
         });
 
         // We are going to start this process over again, so need the init_fn
@@ -2075,6 +2578,9 @@ impl<C: 'static + Chip> Process<'_, C> {
         self.allow_high_water_mark
             .set(self.original_allow_high_water_mark);
 
+        // The process has to tell the kernel where its stack is again.
+        self.stack_bottom.set(None);
+
         // Reset MPU region configuration.
         // TODO: ideally, this would be moved into a helper function used by both
         // create() and reset(), but process load debugging complicates this.
@@ -2221,6 +2727,82 @@ impl<C: 'static + Chip> Process<'_, C> {
         self.current_stack_pointer.get() as *const usize
     }
 
+    /// Keep the name, registers and top of the stack of the process for the
+    /// crash record of the panic its fault causes.
+    fn record_fault(&self) {
+        let sp = self.sp();
+        // The registers the hardware pushed and the stack excerpt can only be
+        // read while the stack pointer is in process memory.
+        let stack_valid = self.in_app_owned_memory(sp as *const u8, 8 * mem::size_of::<usize>());
+        let (registers, stack) = if stack_valid {
+            let registers = self.stored_state.map(|stored_state| unsafe {
+                self.chip
+                    .userspace_kernel_boundary()
+                    .store_context(sp, stored_state)
+            });
+            let len = cmp::min(
+                crash_record::MAX_STACK_LEN,
+                self.app_break.get() as usize - sp as usize,
+            );
+            (registers, unsafe {
+                slice::from_raw_parts(sp as *const u8, len)
+            })
+        } else {
+            (None, &[][..])
+        };
+        let registers = registers
+            .unwrap_or_else(|| crash_record::Registers::new(crash_record::Architecture::Unknown));
+        unsafe {
+            crash_record::set_process_fault(self.process_name, &registers, sp as usize, stack);
+        }
+    }
+
+    /// Protect the memory below a stack of `stack_size` bytes that starts at
+    /// `stack_top`, and paint the part of the stack the process is not using.
+    fn protect_stack(&self, stack_top: *const u8, stack_size: usize) {
+        let mem_start = self.mem_start() as usize;
+        let stack_bottom = cmp::max((stack_top as usize).saturating_sub(stack_size), mem_start);
+
+        // The process cannot access memory below its memory anyway, so a guard
+        // is only needed if the stack does not start at the bottom of process
+        // memory. Not all MPUs support a guard; faults below the stack are
+        // still recognized as stack overflows without one.
+        if stack_bottom > mem_start && stack_top <= self.app_break.get() {
+            let max_guard_size = cmp::min(Self::STACK_GUARD_SIZE, stack_bottom - mem_start);
+            self.mpu_config.map(|config| {
+                self.chip.mpu().allocate_stack_guard(
+                    stack_bottom as *const u8,
+                    max_guard_size,
+                    config,
+                )
+            });
+        }
+        self.stack_bottom.set(Some(stack_bottom as *const u8));
+
+        // Everything below the current stack pointer is unused, as the process
+        // is in a system call.
+        let paint_start = (stack_bottom + 3) & !3;
+        let paint_end = cmp::min(self.sp() as usize, stack_top as usize) & !3;
+        if paint_start < paint_end
+            && self.in_app_owned_memory(paint_start as *const u8, paint_end - paint_start)
+        {
+            for word in (paint_start..paint_end).step_by(4) {
+                unsafe {
+                    write_volatile(word as *mut u32, Self::STACK_PAINT);
+                }
+            }
+        }
+    }
+
+    /// Checks if `address` is just below the stack of the process, where an
+    /// access is most likely caused by the stack overflowing. Without a
+    /// declared stack size the stack is assumed to extend to the start of
+    /// process memory.
+    fn is_below_stack(&self, address: usize) -> bool {
+        let stack_bottom = self.stack_bottom.get().unwrap_or_else(|| self.mem_start()) as usize;
+        address < stack_bottom && address >= stack_bottom.saturating_sub(Self::STACK_GUARD_SIZE)
+    }
+
     /// Checks if the buffer represented by the passed in base pointer and size
     /// are within the memory bounds currently exposed to the processes (i.e.
     /// ending at `app_break`. If this method returns true, the buffer
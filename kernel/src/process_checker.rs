@@ -0,0 +1,100 @@
+//! Checking the credentials of applications before they are loaded.
+//!
+//! A TBF object may carry one or more credentials footers after the
+//! application binary. Each credential is a hash or signature covering the TBF
+//! header and binary. Before a `Process` is created for an application, the
+//! kernel passes each credential to a board-provided `AppCredentialsChecker`,
+//! which decides whether the application may run.
+//!
+//! The checker sees the credentials in the order they appear in flash. The
+//! first credential that is accepted allows the process to load, and the first
+//! credential that is rejected prevents it from loading. Credentials the
+//! checker passes on (for example because it does not support that format) are
+//! ignored. If no credential is accepted, `require_credentials()` decides
+//! whether the process is loaded anyway.
+
+use crate::process::ProcessLoadError;
+use crate::tbfheader::{self, TbfFooterV2Credentials, TbfHeader};
+
+/// The result of checking a single credential.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckResult {
+    /// The credential is valid. The process may be loaded and no further
+    /// credentials are checked.
+    Accept,
+
+    /// The checker does not have an opinion on this credential, for example
+    /// because it does not support its format. The next credential is checked.
+    Pass,
+
+    /// The credential is invalid. The process is not loaded and no further
+    /// credentials are checked.
+    Reject,
+}
+
+/// A policy for deciding which applications the kernel will load, based on the
+/// credentials stored in their TBF footers.
+///
+/// Boards that want to restrict which applications run provide an
+/// implementation of this trait to `load_processes_with_checker()`.
+pub trait AppCredentialsChecker {
+    /// Whether a process that has no accepted credentials (either because it
+    /// has no credentials at all, or because the checker passed on all of
+    /// them) may still be loaded. Return `true` to refuse such processes.
+    fn require_credentials(&self) -> bool;
+
+    /// Check a single credential.
+    ///
+    /// `binary` is the region of flash the credential covers: the TBF header
+    /// followed by the application binary, up to but not including the
+    /// footers.
+    fn check_credentials(
+        &self,
+        credentials: TbfFooterV2Credentials,
+        binary: &'static [u8],
+    ) -> CheckResult;
+}
+
+/// Run `checker` over all credentials in the footers of the TBF object in
+/// `app_flash`.
+///
+/// Returns `Ok(())` if the process may be loaded, and
+/// `ProcessLoadError::CredentialsNotAccepted` if it may not. Footers that
+/// cannot be parsed also make this return `CredentialsNotAccepted`, so that
+/// one corrupt TBF object only causes that process to be skipped.
+pub(crate) fn check_app_credentials(
+    checker: &dyn AppCredentialsChecker,
+    app_flash: &'static [u8],
+    header: &TbfHeader,
+) -> Result<(), ProcessLoadError> {
+    let binary_end = header.get_binary_end() as usize;
+    let binary = app_flash
+        .get(0..binary_end)
+        .ok_or(ProcessLoadError::CredentialsNotAccepted)?;
+    let mut footers = app_flash
+        .get(binary_end..)
+        .ok_or(ProcessLoadError::CredentialsNotAccepted)?;
+
+    while !footers.is_empty() {
+        let (credentials, entry_len) = tbfheader::parse_tbf_footer(footers)
+            .or(Err(ProcessLoadError::CredentialsNotAccepted))?;
+
+        if let Some(credentials) = credentials {
+            match checker.check_credentials(credentials, binary) {
+                CheckResult::Accept => return Ok(()),
+                CheckResult::Reject => return Err(ProcessLoadError::CredentialsNotAccepted),
+                CheckResult::Pass => {}
+            }
+        }
+
+        footers = footers
+            .get(entry_len as usize..)
+            .ok_or(ProcessLoadError::CredentialsNotAccepted)?;
+    }
+
+    if checker.require_credentials() {
+        Err(ProcessLoadError::CredentialsNotAccepted)
+    } else {
+        Ok(())
+    }
+}
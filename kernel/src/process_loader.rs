@@ -0,0 +1,340 @@
+//! Loading processes while the kernel is running.
+//!
+//! Normally all processes are loaded once, when the board boots, by
+//! `load_processes()`. A `DynamicProcessLoader` lets the board add processes
+//! after that without reflashing the kernel. A new TBF object is written into
+//! app flash right after the existing apps, and a process is then created for
+//! it in an empty slot of the processes array. Because the new app is appended
+//! to the app linked list, `load_processes()` also finds it when the board next
+//! boots.
+//!
+//! The loader does not write flash itself, as that depends on the flash
+//! controller and on where the TBF object comes from (see
+//! `capsules::app_loader`). Loading an app takes three steps:
+//!
+//! 1. `check_header()` checks the start of the TBF header and decides where
+//!    in app flash the app goes.
+//! 2. The caller writes the app (and a padding entry, if there is one) to
+//!    flash.
+//! 3. `load()` parses the full TBF header from flash and creates the process.
+//!
+//! Processes loaded at runtime get their memory from a region of RAM that is
+//! set aside for them when the loader is created. That memory is not reclaimed,
+//! so the board should size the region for the apps it expects to load.
+//!
+//! If the board loads processes with `load_processes_with_checker()`, it should
+//! give the loader the same `AppCredentialsChecker`, so that apps loaded at
+//! runtime must pass the same checks as apps loaded at boot. `load()` then
+//! refuses to create a process for an app whose credentials are not accepted.
+//!
+//! If loading an app fails after it was written, for example because its
+//! header does not parse or its credentials are not accepted, the next app
+//! that is loaded is written over it.
+
+use core::convert::TryInto;
+
+use crate::capabilities::ProcessManagementCapability;
+use crate::common::cells::{OptionalCell, TakeCell};
+use crate::config;
+use crate::debug;
+use crate::platform::Chip;
+use crate::process::{FaultResponse, Process, ProcessLoadError, ProcessType};
+use crate::process_checker::{self, AppCredentialsChecker};
+use crate::sched::Kernel;
+use crate::tbfheader::{self, InitialTbfParseError, TbfParseError};
+
+/// Where in app flash a new app is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AppPlacement {
+    padding_address: usize,
+    address: usize,
+    length: usize,
+}
+
+impl AppPlacement {
+    /// Address of the first byte that must be written. This is the start of
+    /// the padding entry if there is one, and the start of the app otherwise.
+    pub fn write_start(&self) -> usize {
+        self.padding_address
+    }
+
+    /// Address the app's TBF object starts at.
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    /// Length of the app's TBF object, from its header.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// Address of the first byte after the app.
+    pub fn end(&self) -> usize {
+        self.address + self.length
+    }
+
+    /// If the app cannot start right after the existing apps, because the MPU
+    /// needs it to be aligned, the space in between must be covered by a
+    /// padding entry to keep the app linked list intact. Returns the TBF
+    /// header of that entry, which must be written at `write_start()`.
+    pub fn padding_header(&self) -> Option<[u8; 16]> {
+        if self.padding_address == self.address {
+            return None;
+        }
+
+        let version_and_size: u32 = 2 | (16 << 16);
+        let total_size = (self.address - self.padding_address) as u32;
+        let flags: u32 = 0;
+        let checksum = version_and_size ^ total_size ^ flags;
+
+        let mut header = [0; 16];
+        header[0..4].copy_from_slice(&version_and_size.to_le_bytes());
+        header[4..8].copy_from_slice(&total_size.to_le_bytes());
+        header[8..12].copy_from_slice(&flags.to_le_bytes());
+        header[12..16].copy_from_slice(&checksum.to_le_bytes());
+        Some(header)
+    }
+}
+
+/// Adds processes to a running kernel from TBF objects written to app flash.
+pub struct DynamicProcessLoader<C: 'static + Chip> {
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: TakeCell<'static, [u8]>,
+    procs: TakeCell<'static, [Option<&'static dyn ProcessType>]>,
+    fault_response: FaultResponse,
+    checker: Option<&'static dyn AppCredentialsChecker>,
+    /// The app that `check_header()` last found a place for.
+    placement: OptionalCell<AppPlacement>,
+    /// The last app whose credentials `load()` did not accept. Its header is
+    /// valid, so it is still in the app linked list, but the next app can be
+    /// written over it.
+    rejected: OptionalCell<AppPlacement>,
+}
+
+impl<C: 'static + Chip> DynamicProcessLoader<C> {
+    /// Create a loader for apps in `app_flash`.
+    ///
+    /// `app_flash` and `procs` must be the same app flash region and processes
+    /// array the board passed to `load_processes()`. `app_memory` is the RAM
+    /// that processes loaded at runtime are allocated from; it must not
+    /// overlap the memory given to `load_processes()`. If `checker` is given,
+    /// only apps whose credentials it accepts are loaded.
+    pub fn new(
+        kernel: &'static Kernel,
+        chip: &'static C,
+        app_flash: &'static [u8],
+        app_memory: &'static mut [u8],
+        procs: &'static mut [Option<&'static dyn ProcessType>],
+        fault_response: FaultResponse,
+        checker: Option<&'static dyn AppCredentialsChecker>,
+    ) -> DynamicProcessLoader<C> {
+        DynamicProcessLoader {
+            kernel,
+            chip,
+            app_flash,
+            app_memory: TakeCell::new(app_memory),
+            procs: TakeCell::new(procs),
+            fault_response,
+            checker,
+            placement: OptionalCell::empty(),
+            rejected: OptionalCell::empty(),
+        }
+    }
+
+    /// The app flash region new apps are written to.
+    pub fn app_flash(&self) -> &'static [u8] {
+        self.app_flash
+    }
+
+    /// Returns the offset into app flash just past the last valid entry in
+    /// the app linked list. Entries whose header does not parse are treated
+    /// as free space.
+    fn end_of_apps(&self) -> usize {
+        let mut offset = 0;
+        while let Some(entry) = self.app_flash.get(offset..) {
+            let lengths = match entry.get(0..8).and_then(|start| start.try_into().ok()) {
+                Some(start) => tbfheader::parse_tbf_header_lengths(start),
+                None => break,
+            };
+            let (version, header_length, entry_length) = match lengths {
+                Ok(lengths) => lengths,
+                Err(_) => break,
+            };
+            let valid = entry
+                .get(0..entry_length as usize)
+                .and_then(|entry| entry.get(0..header_length as usize))
+                .map_or(false, |header| {
+                    tbfheader::parse_tbf_header(header, version).is_ok()
+                });
+            if !valid {
+                break;
+            }
+            offset += entry_length as usize;
+        }
+        offset
+    }
+
+    /// Check the start of the TBF header of a new app and find where in app
+    /// flash it should be written.
+    ///
+    /// `header` must contain at least the first eight bytes of the TBF header,
+    /// which hold its version and lengths. The rest of the header is checked
+    /// by `load()` once the app is in flash.
+    pub fn check_header(
+        &self,
+        header: &[u8],
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Result<AppPlacement, ProcessLoadError> {
+        let start: &[u8; 8] = header
+            .get(0..8)
+            .ok_or(TbfParseError::NotEnoughFlash)?
+            .try_into()
+            .or(Err(ProcessLoadError::InternalError))?;
+        let length = match tbfheader::parse_tbf_header_lengths(start) {
+            Ok((_, _, length)) => length as usize,
+            Err(InitialTbfParseError::InvalidHeader(_)) => {
+                return Err(TbfParseError::NotEnoughFlash.into());
+            }
+            Err(InitialTbfParseError::UnableToParse) => {
+                let version = u16::from_le_bytes([start[0], start[1]]);
+                return Err(TbfParseError::UnsupportedVersion(version).into());
+            }
+        };
+
+        let free_slot = self
+            .procs
+            .map_or(false, |procs| procs.iter().any(|p| p.is_none()));
+        if !free_slot {
+            return Err(ProcessLoadError::NoFreeProcessSlot);
+        }
+
+        // MPUs generally need a process's flash region to be aligned to its
+        // size, so place the app at the next multiple of its size rounded up
+        // to a power of two. Any gap before it needs room for a padding
+        // header.
+        let flash_start = self.app_flash.as_ptr() as usize;
+        let mut padding_address = flash_start + self.end_of_apps();
+        if let Some(rejected) = self.rejected.take() {
+            if rejected.end() == padding_address {
+                padding_address = rejected.write_start();
+            }
+        }
+        let align = length.next_power_of_two();
+        let mut address = (padding_address + align - 1) / align * align;
+        if address != padding_address && address - padding_address < 16 {
+            address += align;
+        }
+
+        if address + length > flash_start + self.app_flash.len() {
+            return Err(ProcessLoadError::NotEnoughFlash);
+        }
+
+        let placement = AppPlacement {
+            padding_address,
+            address,
+            length,
+        };
+        self.placement.set(placement);
+        Ok(placement)
+    }
+
+    /// Create a process for the app placed by the last call to
+    /// `check_header()`, which must now be written to flash.
+    ///
+    /// Returns `Ok(None)` if the app was loaded but no process was created for
+    /// it, because it is disabled.
+    pub fn load(
+        &self,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Result<Option<&'static dyn ProcessType>, ProcessLoadError> {
+        let placement = self
+            .placement
+            .take()
+            .ok_or(ProcessLoadError::InternalError)?;
+        let offset = placement.address - self.app_flash.as_ptr() as usize;
+        let entry_flash = self
+            .app_flash
+            .get(offset..offset + placement.length)
+            .ok_or(ProcessLoadError::NotEnoughFlash)?;
+
+        // Check the header again now that it is in flash. It may not be what
+        // `check_header()` was shown.
+        let start: &[u8; 8] = entry_flash
+            .get(0..8)
+            .ok_or(ProcessLoadError::NotEnoughFlash)?
+            .try_into()
+            .or(Err(ProcessLoadError::InternalError))?;
+        let (version, header_length) = match tbfheader::parse_tbf_header_lengths(start) {
+            Ok((version, header_length, length)) if length as usize == placement.length => {
+                (version, header_length)
+            }
+            _ => return Err(TbfParseError::NotEnoughFlash.into()),
+        };
+        let header_flash = entry_flash
+            .get(0..header_length as usize)
+            .ok_or(ProcessLoadError::NotEnoughFlash)?;
+        let tbf_header = tbfheader::parse_tbf_header(header_flash, version)?;
+
+        if let Some(checker) = self.checker {
+            if tbf_header.is_app() && tbf_header.enabled() {
+                if let Err(error) =
+                    process_checker::check_app_credentials(checker, entry_flash, &tbf_header)
+                {
+                    self.rejected.set(placement);
+                    return Err(error);
+                }
+            }
+        }
+
+        self.procs
+            .map_or(Err(ProcessLoadError::InternalError), |procs| {
+                let index = procs
+                    .iter()
+                    .position(|p| p.is_none())
+                    .ok_or(ProcessLoadError::NoFreeProcessSlot)?;
+                let memory = self
+                    .app_memory
+                    .take()
+                    .ok_or(ProcessLoadError::NotEnoughMemory)?;
+
+                let result = unsafe {
+                    Process::create(
+                        self.kernel,
+                        self.chip,
+                        entry_flash,
+                        header_length as usize,
+                        version,
+                        memory,
+                        self.fault_response,
+                        index,
+                    )
+                };
+
+                // If creating the process failed, keep the memory it was
+                // offered for the next app.
+                let (process, unused_memory) = result.map_err(|(error, memory)| {
+                    self.app_memory.replace(memory);
+                    error
+                })?;
+                self.app_memory.replace(unused_memory);
+
+                if let Some(process) = process {
+                    if config::CONFIG.debug_load_processes {
+                        debug!(
+                            "Loaded process[{}] from flash={:#010X}-{:#010X} into sram={:#010X}-{:#010X} = {:?}",
+                            index,
+                            entry_flash.as_ptr() as usize,
+                            entry_flash.as_ptr() as usize + entry_flash.len() - 1,
+                            process.mem_start() as usize,
+                            process.mem_end() as usize - 1,
+                            process.get_process_name()
+                        );
+                    }
+                    procs[index] = Some(process);
+                }
+                Ok(process)
+            })
+    }
+}
@@ -4,6 +4,7 @@
 //! different scheduler implementations.
 
 pub(crate) mod cooperative;
+pub(crate) mod edf;
 pub(crate) mod mlfq;
 pub(crate) mod priority;
 pub(crate) mod round_robin;
@@ -21,6 +22,7 @@ use crate::grant::Grant;
 use crate::ipc;
 use crate::memop;
 use crate::platform::mpu::MPU;
+use crate::platform::power::PowerManager;
 use crate::platform::scheduler_timer::SchedulerTimer;
 use crate::platform::watchdog::WatchDog;
 use crate::platform::{Chip, Platform};
@@ -141,6 +143,9 @@ pub struct Kernel {
     /// created and the data structures for grants have already been
     /// established.
     grants_finalized: Cell<bool>,
+
+    /// The sleep constraints of the drivers and the sleep statistics.
+    power_manager: PowerManager,
 }
 
 /// Enum used to inform scheduler why a process stopped executing (aka why
@@ -176,9 +181,16 @@ impl Kernel {
             process_identifier_max: Cell::new(0),
             grant_counter: Cell::new(0),
             grants_finalized: Cell::new(false),
+            power_manager: PowerManager::new(),
         }
     }
 
+    /// Returns the power manager, with which boards register the sleep
+    /// constraints of their drivers.
+    pub fn power_manager(&self) -> &PowerManager {
+        &self.power_manager
+    }
+
     /// Something was scheduled for a process, so there is more work to do.
     ///
     /// This is only exposed in the core kernel crate.
@@ -507,8 +519,13 @@ impl Kernel {
                                         && !DynamicDeferredCall::global_instance_calls_pending()
                                             .unwrap_or(false)
                                     {
+                                        let permitted = self.power_manager.deepest_permitted();
+                                        let state = core::cmp::min(
+                                            platform.sleep_policy(permitted),
+                                            permitted,
+                                        );
                                         chip.watchdog().suspend();
-                                        chip.sleep();
+                                        self.power_manager.record_sleep(|| chip.sleep_in(state));
                                         chip.watchdog().resume();
                                     }
                                 });
@@ -645,6 +662,7 @@ impl Kernel {
                             // decide how to handle the error.
                             if syscall != Syscall::YIELD {
                                 if let Err(response) = platform.filter_syscall(process, &syscall) {
+                                    platform.trace_syscall(process, &syscall, Some(response));
                                     process.set_syscall_return_value(response.into());
                                     continue;
                                 }
@@ -664,12 +682,14 @@ impl Kernel {
                                             res
                                         );
                                     }
+                                    platform.trace_syscall(process, &syscall, Some(res));
                                     process.set_syscall_return_value(res.into());
                                 }
                                 Syscall::YIELD => {
                                     if config::CONFIG.trace_syscalls {
                                         debug!("[{:?}] yield", process.appid());
                                     }
+                                    platform.trace_syscall(process, &syscall, None);
                                     process.set_yielded_state();
 
                                     // There might be already enqueued callbacks
@@ -716,6 +736,10 @@ impl Kernel {
                                                 None => ReturnCode::ENODEVICE,
                                             },
                                         );
+                                    if res == ReturnCode::SUCCESS {
+                                        process
+                                            .debug_callback_subscribed(callback_id, callback_ptr);
+                                    }
                                     if config::CONFIG.trace_syscalls {
                                         debug!(
                                             "[{:?}] subscribe({:#x}, {}, @{:#x}, {:#x}) = {:#x} = {:?}",
@@ -728,6 +752,7 @@ impl Kernel {
                                             res
                                         );
                                     }
+                                    platform.trace_syscall(process, &syscall, Some(res));
                                     process.set_syscall_return_value(res.into());
                                 }
                                 Syscall::COMMAND {
@@ -761,6 +786,7 @@ impl Kernel {
                                             res
                                         );
                                     }
+                                    platform.trace_syscall(process, &syscall, Some(res));
                                     process.set_syscall_return_value(res.into());
                                 }
                                 Syscall::ALLOW {
@@ -796,6 +822,7 @@ impl Kernel {
                                             res
                                         );
                                     }
+                                    platform.trace_syscall(process, &syscall, Some(res));
                                     process.set_syscall_return_value(res.into());
                                 }
                             }
@@ -0,0 +1,282 @@
+//! Earliest deadline first scheduler for Tock
+//!
+//! This scheduler is meant for boards that run processes with real-time
+//! requirements, such as a control loop that must run every few milliseconds,
+//! next to processes without them.
+//!
+//! A process declares its timing requirements in the Realtime entry of its TBF
+//! header: a period, a relative deadline, and a budget. Every period a new job
+//! of the process is released. The job may use up to the budget of CPU time,
+//! and should be finished by the deadline. This scheduler follows these rules:
+//!
+//! - Rule 1: Of the real-time processes that are ready, have a released job,
+//!           and have budget left, the one whose job has the earliest
+//!           deadline runs.
+//! - Rule 2: A real-time process that has used up its budget does not run
+//!           again until its next job is released, even if it is ready.
+//! - Rule 3: Processes without timing requirements only run when no
+//!           real-time process can, in round-robin fashion.
+//! - Rule 4: If a real-time process still has work to do (it is ready) when
+//!           its deadline passes, the deadline is counted as missed. Missed
+//!           deadlines can be read through `introspection::KernelInfo`.
+//!
+//! Budgets are enforced with the scheduler timer, and timeslices are cut short
+//! when another job is released so that the new job can preempt the running
+//! process if its deadline is earlier. Because the kernel will not start a
+//! process with less than `MIN_QUANTA_THRESHOLD_US` of its timeslice left,
+//! budgets and releases are only enforced to about that granularity.
+
+use crate::common::list::{List, ListLink, ListNode};
+use crate::hil::time::{self, Frequency, Ticks};
+use crate::platform::Chip;
+use crate::process::ProcessType;
+use crate::sched::{
+    Kernel, Scheduler, SchedulingDecision, StoppedExecutingReason, MIN_QUANTA_THRESHOLD_US,
+};
+use core::cell::Cell;
+
+/// State of the current job of a real-time process. All times are in
+/// scheduler ticks since the scheduler started.
+#[derive(Default)]
+struct EdfProcState {
+    /// Whether this process has been seen by the scheduler yet. Its first job
+    /// is released the first time it is.
+    started: Cell<bool>,
+    /// When the next job of this process will be released.
+    next_release: Cell<u64>,
+    /// Deadline of the current job, if one is released and its deadline has
+    /// not passed.
+    deadline: Cell<Option<u64>>,
+    /// CPU time the current job may still use, in microseconds.
+    budget_remaining_us: Cell<u32>,
+}
+
+/// Nodes store per-process state
+pub struct EDFProcessNode<'a> {
+    proc: &'static Option<&'static dyn ProcessType>,
+    state: EdfProcState,
+    next: ListLink<'a, EDFProcessNode<'a>>,
+}
+
+impl<'a> EDFProcessNode<'a> {
+    pub fn new(proc: &'static Option<&'static dyn ProcessType>) -> EDFProcessNode<'a> {
+        EDFProcessNode {
+            proc,
+            state: EdfProcState::default(),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Whether this node holds a real-time process whose current job may run.
+    fn can_run_realtime(&self) -> bool {
+        self.state.deadline.get().is_some()
+            && self.state.budget_remaining_us.get() > 0
+            && self.proc.map_or(false, |proc| proc.ready())
+    }
+}
+
+impl<'a> ListNode<'a, EDFProcessNode<'a>> for EDFProcessNode<'a> {
+    fn next(&'a self) -> &'static ListLink<'a, EDFProcessNode<'a>> {
+        &self.next
+    }
+}
+
+pub struct EDFSched<'a, A: 'static + time::Alarm<'static>> {
+    alarm: &'static A,
+    pub processes: List<'a, EDFProcessNode<'a>>,
+    /// Ticks since the scheduler started, extended to 64 bits so that job
+    /// times do not wrap.
+    time: Cell<u64>,
+    /// Value of the alarm's counter when `time` was last updated.
+    last_now: Cell<A::Ticks>,
+    /// Index of the process that was last run, and whether it was run as a
+    /// real-time process.
+    last_run: Cell<Option<(usize, bool)>>,
+    /// Index of the process without timing requirements that was last run.
+    last_best_effort: Cell<usize>,
+}
+
+impl<'a, A: 'static + time::Alarm<'static>> EDFSched<'a, A> {
+    /// How long a process without timing requirements can run before being
+    /// pre-empted
+    pub const DEFAULT_TIMESLICE_US: u32 = 10000;
+
+    pub fn new(alarm: &'static A) -> Self {
+        Self {
+            alarm,
+            processes: List::new(),
+            time: Cell::new(0),
+            last_now: Cell::new(A::Ticks::from(0)),
+            last_run: Cell::new(None),
+            last_best_effort: Cell::new(0),
+        }
+    }
+
+    fn us_to_ticks(us: u32) -> u64 {
+        us as u64 * A::Frequency::frequency() as u64 / 1_000_000
+    }
+
+    fn ticks_to_us(ticks: u64) -> u32 {
+        let us = ticks * 1_000_000 / A::Frequency::frequency() as u64;
+        if us > u32::MAX as u64 {
+            u32::MAX
+        } else {
+            us as u32
+        }
+    }
+
+    /// Advance the scheduler's clock and return the current time.
+    ///
+    /// This must be called at least once per wraparound of the alarm's
+    /// counter to keep time accurately. If it is not, for example because the
+    /// board slept for a long time, jobs are released late.
+    fn update_time(&self) -> u64 {
+        let now = self.alarm.now();
+        let elapsed = now.wrapping_sub(self.last_now.get()).into_u32() as u64;
+        self.last_now.set(now);
+        self.time.set(self.time.get() + elapsed);
+        self.time.get()
+    }
+
+    /// Check whether the current job of a process missed its deadline, and
+    /// release its next job if it is due.
+    fn update_job(&self, node: &EDFProcessNode<'a>, proc: &dyn ProcessType, now: u64) {
+        let realtime = match proc.get_realtime_parameters() {
+            Some(realtime) => realtime,
+            None => {
+                node.state.deadline.set(None);
+                return;
+            }
+        };
+        let state = &node.state;
+
+        if !state.started.get() {
+            state.started.set(true);
+            state.next_release.set(now);
+        }
+
+        if let Some(deadline) = state.deadline.get() {
+            if now >= deadline {
+                if proc.ready() {
+                    proc.debug_deadline_missed();
+                }
+                state.deadline.set(None);
+            }
+        }
+
+        if now >= state.next_release.get() {
+            // If releases were skipped, for example because the process was
+            // stopped, release the job for the period we are in now rather
+            // than all of the skipped jobs.
+            let period = Self::us_to_ticks(realtime.period_us()).max(1);
+            let periods_late = (now - state.next_release.get()) / period;
+            let release = state.next_release.get() + periods_late * period;
+
+            state
+                .deadline
+                .set(Some(release + Self::us_to_ticks(realtime.deadline_us())));
+            state.budget_remaining_us.set(realtime.budget_us());
+            state.next_release.set(release + period);
+        }
+    }
+
+    /// Returns the time until the next job of any process is released, if
+    /// there are any real-time processes.
+    fn ticks_until_next_release(&self, now: u64) -> Option<u64> {
+        self.processes
+            .iter()
+            .filter(|node| {
+                node.proc
+                    .map_or(false, |proc| proc.get_realtime_parameters().is_some())
+            })
+            .map(|node| node.state.next_release.get().saturating_sub(now))
+            .min()
+    }
+
+    /// Limit a timeslice so that it ends when the next job is released, which
+    /// may need to preempt the running process.
+    fn limit_timeslice(&self, timeslice_us: u32, now: u64) -> u32 {
+        let limit = self
+            .ticks_until_next_release(now)
+            .map_or(u32::MAX, |ticks| {
+                Self::ticks_to_us(ticks).saturating_add(MIN_QUANTA_THRESHOLD_US)
+            });
+        timeslice_us.min(limit).max(2 * MIN_QUANTA_THRESHOLD_US)
+    }
+}
+
+impl<'a, A: 'static + time::Alarm<'static>, C: Chip> Scheduler<C> for EDFSched<'a, A> {
+    fn next(&self, kernel: &Kernel) -> SchedulingDecision {
+        let now = self.update_time();
+        for node in self.processes.iter() {
+            if let Some(proc) = node.proc {
+                self.update_job(node, *proc, now);
+            }
+        }
+
+        if kernel.processes_blocked() {
+            // No processes ready
+            return SchedulingDecision::TrySleep;
+        }
+
+        // Run the real-time process whose job has the earliest deadline.
+        let earliest = self
+            .processes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.can_run_realtime())
+            .min_by_key(|(_, node)| node.state.deadline.get());
+        if let Some((index, node)) = earliest {
+            let timeslice = self.limit_timeslice(node.state.budget_remaining_us.get(), now);
+            self.last_run.set(Some((index, true)));
+            // `can_run_realtime()` checked that the process exists.
+            let next = node.proc.unwrap().appid();
+            return SchedulingDecision::RunProcess((next, Some(timeslice)));
+        }
+
+        // Otherwise, run the next ready process without timing requirements.
+        let count = self.processes.iter().count();
+        let start = self.last_best_effort.get() + 1;
+        for i in 0..count {
+            let index = (start + i) % count;
+            let next = self.processes.iter().nth(index).and_then(|node| {
+                node.proc
+                    .filter(|proc| proc.get_realtime_parameters().is_none() && proc.ready())
+            });
+            if let Some(proc) = next {
+                let timeslice = self.limit_timeslice(Self::DEFAULT_TIMESLICE_US, now);
+                self.last_run.set(Some((index, false)));
+                self.last_best_effort.set(index);
+                return SchedulingDecision::RunProcess((proc.appid(), Some(timeslice)));
+            }
+        }
+
+        // The only ready processes are real-time processes waiting for their
+        // next job. Wake up when it is released.
+        if let Some(ticks) = self.ticks_until_next_release(now) {
+            let max_dt = A::Ticks::max_value().into_u32() / 2;
+            let dt = if ticks > max_dt as u64 {
+                max_dt
+            } else {
+                ticks as u32
+            };
+            self.alarm
+                .set_alarm(self.last_now.get(), A::Ticks::from(dt));
+        }
+        self.last_run.set(None);
+        SchedulingDecision::TrySleep
+    }
+
+    fn result(&self, _result: StoppedExecutingReason, execution_time_us: Option<u32>) {
+        let execution_time_us = execution_time_us.unwrap(); // should never fail as we never run cooperatively
+        if let Some((index, true)) = self.last_run.get() {
+            if let Some(node) = self.processes.iter().nth(index) {
+                let remaining = node.state.budget_remaining_us.get();
+                node.state
+                    .budget_remaining_us
+                    .set(remaining.saturating_sub(execution_time_us));
+            }
+        }
+        self.last_run.set(None);
+    }
+}
@@ -2,6 +2,7 @@
 
 use core::fmt::Write;
 
+use crate::platform::crash_record::Registers;
 use crate::process;
 
 /// The syscall number assignments.
@@ -158,6 +159,27 @@ pub trait UserspaceKernelBoundary {
         state: &Self::StoredState,
         writer: &mut dyn Write,
     );
+
+    /// Collect the registers `print_context()` displays for a process into a
+    /// crash record. `stack_pointer` must be valid as for `print_context()`.
+    unsafe fn store_context(
+        &self,
+        stack_pointer: *const usize,
+        state: &Self::StoredState,
+    ) -> Registers;
+
+    /// Returns the address of the memory access that made the process fault,
+    /// if the fault was caused by a memory access and the hardware recorded
+    /// the address. This is only meaningful right after `switch_to_process()`
+    /// returned `ContextSwitchReason::Fault`.
+    #[allow(unused_variables)]
+    unsafe fn fault_address(
+        &self,
+        stack_pointer: *const usize,
+        state: &Self::StoredState,
+    ) -> Option<usize> {
+        None
+    }
 }
 
 /// Helper function for converting raw values passed back from an application
@@ -117,6 +117,10 @@ pub(crate) enum TbfHeaderTypes {
     TbfHeaderWriteableFlashRegions = 2,
     TbfHeaderPackageName = 3,
     TbfHeaderFixedAddresses = 5,
+    TbfHeaderPermissions = 6,
+    TbfHeaderRealtime = 7,
+    TbfHeaderStack = 8,
+    TbfHeaderProgram = 9,
 
     /// Some field in the header that we do not understand. Since the TLV format
     /// specifies the length of each section, if we get a field we do not
@@ -142,6 +146,22 @@ pub(crate) struct TbfHeaderV2Main {
     minimum_ram_size: u32,
 }
 
+/// The v2 program section for apps.
+///
+/// This is a superset of the main section that additionally records where the
+/// application binary ends. Anything after the end of the binary and before the
+/// end of the TBF object (`total_size`) is the footer region, which holds
+/// entries such as credentials. If both a Main and a Program section are
+/// present, the Program section is used.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TbfHeaderV2Program {
+    init_fn_offset: u32,
+    protected_size: u32,
+    minimum_ram_size: u32,
+    binary_end_offset: u32,
+    version: u32,
+}
+
 /// Writeable flash regions only need an offset and size.
 ///
 /// There can be multiple (or zero) flash regions defined, so this is its own
@@ -176,6 +196,90 @@ pub(crate) struct TbfHeaderV2FixedAddresses {
     start_process_flash: u32,
 }
 
+/// Permission for a process to use a particular driver.
+///
+/// `allowed_commands` is a bitmask of the command numbers the process may
+/// call: bit `i` allows command number `offset * 64 + i`. A driver can be
+/// listed more than once with different offsets to allow command numbers above
+/// 63.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct TbfHeaderDriverPermission {
+    driver_number: u32,
+    offset: u32,
+    allowed_commands: u64,
+}
+
+/// The set of drivers a process may use.
+///
+/// If this header is present, the process may only use the drivers listed in
+/// it. If it is omitted, the process may use every driver the board provides.
+///
+/// To enable a static buffer, we only store up to eight driver permissions.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct TbfHeaderV2Permissions {
+    perms: [Option<TbfHeaderDriverPermission>; 8],
+}
+
+/// Timing requirements of a process with real-time constraints.
+///
+/// The process does periodic work: every `period_us` it is released to do at
+/// most `budget_us` of work, which must be finished within `deadline_us` of
+/// being released. Schedulers that support deadlines use this to decide when
+/// to run the process; other schedulers ignore it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TbfHeaderV2Realtime {
+    period_us: u32,
+    deadline_us: u32,
+    budget_us: u32,
+}
+
+impl TbfHeaderV2Realtime {
+    /// How often the process is released, in microseconds.
+    pub fn period_us(&self) -> u32 {
+        self.period_us
+    }
+
+    /// How long after being released the process must finish its work, in
+    /// microseconds. This is at most the period.
+    pub fn deadline_us(&self) -> u32 {
+        self.deadline_us
+    }
+
+    /// How long the process may run each period, in microseconds. This is at
+    /// most the deadline.
+    pub fn budget_us(&self) -> u32 {
+        self.budget_us
+    }
+}
+
+/// The size of the stack the process uses.
+///
+/// The stack grows down from the stack pointer the process reports with the
+/// `memop` system call, and must fit in `stack_size` bytes below it. The kernel
+/// protects the memory below the stack so that a process that overflows its
+/// stack faults instead of overwriting it.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TbfHeaderV2Stack {
+    stack_size: u32,
+}
+
+/// The permissions a process has for the commands of a particular driver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandPermissions {
+    /// The process did not specify any permissions in its TBF header, so it
+    /// is not restricted.
+    NoPermsAtAll,
+
+    /// The process specified permissions, but none for this driver. The
+    /// process may not use this driver at all.
+    NoPermsThisDriver,
+
+    /// The process may use this driver. Bit `i` of the mask is set if the
+    /// process may call command number `offset * 64 + i`, where `offset` is
+    /// the offset that was asked for.
+    Mask(u64),
+}
+
 // Conversion functions from slices to the various TBF fields.
 
 impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Base {
@@ -212,6 +316,44 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Base {
     }
 }
 
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Realtime {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2Realtime, Self::Error> {
+        Ok(TbfHeaderV2Realtime {
+            period_us: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            deadline_us: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            budget_us: u32::from_le_bytes(
+                b.get(8..12)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Stack {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2Stack, Self::Error> {
+        Ok(TbfHeaderV2Stack {
+            stack_size: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
 impl core::convert::TryFrom<u16> for TbfHeaderTypes {
     type Error = TbfParseError;
 
@@ -221,6 +363,10 @@ impl core::convert::TryFrom<u16> for TbfHeaderTypes {
             2 => Ok(TbfHeaderTypes::TbfHeaderWriteableFlashRegions),
             3 => Ok(TbfHeaderTypes::TbfHeaderPackageName),
             5 => Ok(TbfHeaderTypes::TbfHeaderFixedAddresses),
+            6 => Ok(TbfHeaderTypes::TbfHeaderPermissions),
+            7 => Ok(TbfHeaderTypes::TbfHeaderRealtime),
+            8 => Ok(TbfHeaderTypes::TbfHeaderStack),
+            9 => Ok(TbfHeaderTypes::TbfHeaderProgram),
             _ => Ok(TbfHeaderTypes::Unknown),
         }
     }
@@ -270,6 +416,40 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Main {
     }
 }
 
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Program {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2Program, Self::Error> {
+        Ok(TbfHeaderV2Program {
+            init_fn_offset: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            protected_size: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            minimum_ram_size: u32::from_le_bytes(
+                b.get(8..12)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            binary_end_offset: u32::from_le_bytes(
+                b.get(12..16)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            version: u32::from_le_bytes(
+                b.get(16..20)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
 impl core::convert::TryFrom<&[u8]> for TbfHeaderV2WriteableFlashRegion {
     type Error = TbfParseError;
 
@@ -289,6 +469,30 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2WriteableFlashRegion {
     }
 }
 
+impl core::convert::TryFrom<&[u8]> for TbfHeaderDriverPermission {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderDriverPermission, Self::Error> {
+        Ok(TbfHeaderDriverPermission {
+            driver_number: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            offset: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            allowed_commands: u64::from_le_bytes(
+                b.get(8..16)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
 impl core::convert::TryFrom<&[u8]> for TbfHeaderV2FixedAddresses {
     type Error = TbfParseError;
 
@@ -317,9 +521,13 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2FixedAddresses {
 pub(crate) struct TbfHeaderV2 {
     base: TbfHeaderV2Base,
     main: Option<TbfHeaderV2Main>,
+    program: Option<TbfHeaderV2Program>,
     package_name: Option<&'static str>,
     writeable_regions: Option<[Option<TbfHeaderV2WriteableFlashRegion>; 4]>,
     fixed_addresses: Option<TbfHeaderV2FixedAddresses>,
+    permissions: Option<TbfHeaderV2Permissions>,
+    realtime: Option<TbfHeaderV2Realtime>,
+    stack: Option<TbfHeaderV2Stack>,
 }
 
 /// Type that represents the fields of the Tock Binary Format header.
@@ -360,7 +568,10 @@ impl TbfHeader {
     /// needed for this app.
     pub(crate) fn get_minimum_app_ram_size(&self) -> u32 {
         match *self {
-            TbfHeader::TbfHeaderV2(hd) => hd.main.map_or(0, |m| m.minimum_ram_size),
+            TbfHeader::TbfHeaderV2(hd) => match hd.program {
+                Some(p) => p.minimum_ram_size,
+                None => hd.main.map_or(0, |m| m.minimum_ram_size),
+            },
             _ => 0,
         }
     }
@@ -370,7 +581,11 @@ impl TbfHeader {
     pub(crate) fn get_protected_size(&self) -> u32 {
         match *self {
             TbfHeader::TbfHeaderV2(hd) => {
-                hd.main.map_or(0, |m| m.protected_size) + (hd.base.header_size as u32)
+                let protected_size = match hd.program {
+                    Some(p) => p.protected_size,
+                    None => hd.main.map_or(0, |m| m.protected_size),
+                };
+                protected_size + (hd.base.header_size as u32)
             }
             _ => 0,
         }
@@ -381,12 +596,31 @@ impl TbfHeader {
     pub(crate) fn get_init_function_offset(&self) -> u32 {
         match *self {
             TbfHeader::TbfHeaderV2(hd) => {
-                hd.main.map_or(0, |m| m.init_fn_offset) + (hd.base.header_size as u32)
+                let init_fn_offset = match hd.program {
+                    Some(p) => p.init_fn_offset,
+                    None => hd.main.map_or(0, |m| m.init_fn_offset),
+                };
+                init_fn_offset + (hd.base.header_size as u32)
             }
             _ => 0,
         }
     }
 
+    /// Get the offset from the beginning of the app's flash region of the
+    /// first byte after the application binary. Everything between this
+    /// offset and the total size of the TBF object is the footer region.
+    ///
+    /// If the header does not contain a Program section there are no footers
+    /// and the binary extends to the end of the TBF object.
+    pub(crate) fn get_binary_end(&self) -> u32 {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .program
+                .map_or(hd.base.total_size, |p| p.binary_end_offset),
+            TbfHeader::Padding(base) => base.total_size,
+        }
+    }
+
     /// Get the name of the app.
     pub(crate) fn get_package_name(&self) -> Option<&'static str> {
         match *self {
@@ -434,6 +668,57 @@ impl TbfHeader {
         }
     }
 
+    /// Get the permissions this process has for the commands of driver
+    /// `driver_num`, for command numbers `offset * 64` through
+    /// `offset * 64 + 63`.
+    pub(crate) fn get_command_permissions(
+        &self,
+        driver_num: usize,
+        offset: usize,
+    ) -> CommandPermissions {
+        let permissions = match self {
+            TbfHeader::TbfHeaderV2(hd) => match hd.permissions {
+                Some(permissions) => permissions,
+                None => return CommandPermissions::NoPermsAtAll,
+            },
+            _ => return CommandPermissions::NoPermsAtAll,
+        };
+
+        let mut found_driver = false;
+        for perm in permissions.perms.iter().flatten() {
+            if perm.driver_number as usize == driver_num {
+                if perm.offset as usize == offset {
+                    return CommandPermissions::Mask(perm.allowed_commands);
+                }
+                found_driver = true;
+            }
+        }
+
+        if found_driver {
+            // The driver is allowed, but none of these commands are.
+            CommandPermissions::Mask(0)
+        } else {
+            CommandPermissions::NoPermsThisDriver
+        }
+    }
+
+    /// Get the timing requirements of this process, if it has any.
+    pub(crate) fn get_realtime_parameters(&self) -> Option<TbfHeaderV2Realtime> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.realtime,
+            _ => None,
+        }
+    }
+
+    /// Get the size of the stack of this process in bytes, if the process
+    /// specified it.
+    pub(crate) fn get_stack_size(&self) -> Option<u32> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.stack.map(|stack| stack.stack_size),
+            _ => None,
+        }
+    }
+
     /// Get the address in flash this process was specifically compiled for. If
     /// the process is position independent, return `None`.
     pub(crate) fn get_fixed_address_flash(&self) -> Option<u32> {
@@ -464,7 +749,7 @@ impl TbfHeader {
 /// we can skip over it and check for the next app.
 /// - Err(InitialTbfParseError::InvalidHeader(app_length))
 pub(crate) fn parse_tbf_header_lengths(
-    app: &'static [u8; 8],
+    app: &[u8; 8],
 ) -> Result<(u16, u16, u32), InitialTbfParseError> {
     // Version is the first 16 bits of the app TBF contents. We need this to
     // correctly parse the other lengths.
@@ -559,10 +844,14 @@ pub(crate) fn parse_tbf_header(
                 // Places to save fields that we parse out of the header
                 // options.
                 let mut main_pointer: Option<TbfHeaderV2Main> = None;
+                let mut program_pointer: Option<TbfHeaderV2Program> = None;
                 let mut wfr_pointer: [Option<TbfHeaderV2WriteableFlashRegion>; 4] =
                     Default::default();
                 let mut app_name_str = "";
                 let mut fixed_address_pointer: Option<TbfHeaderV2FixedAddresses> = None;
+                let mut permissions_pointer: Option<TbfHeaderV2Permissions> = None;
+                let mut realtime_pointer: Option<TbfHeaderV2Realtime> = None;
+                let mut stack_pointer: Option<TbfHeaderV2Stack> = None;
 
                 // Iterate the remainder of the header looking for TLV entries.
                 while remaining.len() > 0 {
@@ -586,6 +875,30 @@ pub(crate) fn parse_tbf_header(
                             }
                         }
 
+                        TbfHeaderTypes::TbfHeaderProgram => {
+                            let entry_len = mem::size_of::<TbfHeaderV2Program>();
+
+                            // Like Main, the Program TLV has a fixed size. In
+                            // addition, the end of the binary must lie
+                            // between the end of the header and the end of
+                            // the TBF object, otherwise the footer region
+                            // would be nonsensical.
+                            if tlv_header.length as usize == entry_len {
+                                let program: TbfHeaderV2Program = remaining.try_into()?;
+                                if program.binary_end_offset
+                                    < u32::from(tbf_header_base.header_size)
+                                    || program.binary_end_offset > tbf_header_base.total_size
+                                {
+                                    return Err(TbfParseError::BadTlvEntry(
+                                        tlv_header.tipe as usize,
+                                    ));
+                                }
+                                program_pointer = Some(program);
+                            } else {
+                                return Err(TbfParseError::BadTlvEntry(tlv_header.tipe as usize));
+                            }
+                        }
+
                         TbfHeaderTypes::TbfHeaderWriteableFlashRegions => {
                             // Length must be a multiple of the size of a region definition.
                             if tlv_header.length as usize
@@ -643,6 +956,89 @@ pub(crate) fn parse_tbf_header(
                             }
                         }
 
+                        TbfHeaderTypes::TbfHeaderPermissions => {
+                            // The entry starts with a 16 bit count of driver
+                            // permissions, followed by the permissions
+                            // themselves.
+                            let perm_len = mem::size_of::<TbfHeaderDriverPermission>();
+                            let perms_slice = remaining
+                                .get(0..tlv_header.length as usize)
+                                .ok_or(TbfParseError::NotEnoughFlash)?;
+                            let number_perms = u16::from_le_bytes(
+                                perms_slice
+                                    .get(0..2)
+                                    .ok_or(TbfParseError::BadTlvEntry(tlv_header.tipe as usize))?
+                                    .try_into()?,
+                            ) as usize;
+
+                            if tlv_header.length as usize != 2 + number_perms * perm_len {
+                                return Err(TbfParseError::BadTlvEntry(tlv_header.tipe as usize));
+                            }
+
+                            // Silently dropping permissions would leave the
+                            // process unable to use drivers it expects to
+                            // have, which is hard to debug. Refuse headers with
+                            // more permissions than we can store instead.
+                            let mut permissions = TbfHeaderV2Permissions::default();
+                            if number_perms > permissions.perms.len() {
+                                return Err(TbfParseError::BadTlvEntry(tlv_header.tipe as usize));
+                            }
+
+                            for (i, perm) in
+                                permissions.perms.iter_mut().enumerate().take(number_perms)
+                            {
+                                let start = 2 + i * perm_len;
+                                *perm = Some(
+                                    perms_slice
+                                        .get(start..start + perm_len)
+                                        .ok_or(TbfParseError::NotEnoughFlash)?
+                                        .try_into()?,
+                                );
+                            }
+
+                            permissions_pointer = Some(permissions);
+                        }
+
+                        TbfHeaderTypes::TbfHeaderRealtime => {
+                            let entry_len = mem::size_of::<TbfHeaderV2Realtime>();
+
+                            // The timing requirements must be consistent: the
+                            // budget has to fit before the deadline, and the
+                            // deadline before the next release.
+                            if tlv_header.length as usize == entry_len {
+                                let realtime: TbfHeaderV2Realtime = remaining.try_into()?;
+                                if realtime.budget_us == 0
+                                    || realtime.budget_us > realtime.deadline_us
+                                    || realtime.deadline_us > realtime.period_us
+                                {
+                                    return Err(TbfParseError::BadTlvEntry(
+                                        tlv_header.tipe as usize,
+                                    ));
+                                }
+                                realtime_pointer = Some(realtime);
+                            } else {
+                                return Err(TbfParseError::BadTlvEntry(tlv_header.tipe as usize));
+                            }
+                        }
+
+                        TbfHeaderTypes::TbfHeaderStack => {
+                            let entry_len = mem::size_of::<TbfHeaderV2Stack>();
+
+                            // A process without any stack cannot run, so an
+                            // empty stack must be a mistake.
+                            if tlv_header.length as usize == entry_len {
+                                let stack: TbfHeaderV2Stack = remaining.try_into()?;
+                                if stack.stack_size == 0 {
+                                    return Err(TbfParseError::BadTlvEntry(
+                                        tlv_header.tipe as usize,
+                                    ));
+                                }
+                                stack_pointer = Some(stack);
+                            } else {
+                                return Err(TbfParseError::BadTlvEntry(tlv_header.tipe as usize));
+                            }
+                        }
+
                         _ => {}
                     }
 
@@ -657,9 +1053,13 @@ pub(crate) fn parse_tbf_header(
                 let tbf_header = TbfHeaderV2 {
                     base: tbf_header_base,
                     main: main_pointer,
+                    program: program_pointer,
                     package_name: Some(app_name_str),
                     writeable_regions: Some(wfr_pointer),
                     fixed_addresses: fixed_address_pointer,
+                    permissions: permissions_pointer,
+                    realtime: realtime_pointer,
+                    stack: stack_pointer,
                 };
 
                 Ok(TbfHeader::TbfHeaderV2(tbf_header))
@@ -668,3 +1068,170 @@ pub(crate) fn parse_tbf_header(
         _ => Err(TbfParseError::UnsupportedVersion(version)),
     }
 }
+
+// TBF footer structure
+
+/// Types in TLV structures for each entry in the footer region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TbfFooterTypes {
+    TbfFooterCredentials = 128,
+
+    /// A footer entry we do not understand. Like unknown header entries, these
+    /// are skipped.
+    Unknown,
+}
+
+impl core::convert::From<u16> for TbfFooterTypes {
+    fn from(f: u16) -> TbfFooterTypes {
+        match f {
+            128 => TbfFooterTypes::TbfFooterCredentials,
+            _ => TbfFooterTypes::Unknown,
+        }
+    }
+}
+
+/// The format of the data stored in a credentials footer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TbfFooterV2CredentialsType {
+    /// Reserved space that holds no credential. Tools use this to leave room
+    /// for credentials that are added after the TBF object is created.
+    Reserved = 0,
+    /// A 384 byte RSA-3072 public key followed by a 384 byte PKCS#1 v1.5
+    /// signature over a SHA-256 hash of the binary.
+    Rsa3072Key = 1,
+    /// A 512 byte RSA-4096 public key followed by a 512 byte PKCS#1 v1.5
+    /// signature over a SHA-256 hash of the binary.
+    Rsa4096Key = 2,
+    /// A 32 byte SHA-256 hash of the binary.
+    SHA256 = 3,
+    /// A 48 byte SHA-384 hash of the binary.
+    SHA384 = 4,
+    /// A 64 byte SHA-512 hash of the binary.
+    SHA512 = 5,
+    /// A 64 byte ECDSA NIST P-256 signature (`r` followed by `s`) over a
+    /// SHA-256 hash of the binary.
+    EcdsaNistP256 = 6,
+}
+
+impl TbfFooterV2CredentialsType {
+    /// The number of bytes of credential data this format requires, or `None`
+    /// if the length is not fixed.
+    fn data_length(&self) -> Option<usize> {
+        match self {
+            TbfFooterV2CredentialsType::Reserved => None,
+            TbfFooterV2CredentialsType::Rsa3072Key => Some(768),
+            TbfFooterV2CredentialsType::Rsa4096Key => Some(1024),
+            TbfFooterV2CredentialsType::SHA256 => Some(32),
+            TbfFooterV2CredentialsType::SHA384 => Some(48),
+            TbfFooterV2CredentialsType::SHA512 => Some(64),
+            TbfFooterV2CredentialsType::EcdsaNistP256 => Some(64),
+        }
+    }
+}
+
+impl core::convert::TryFrom<u32> for TbfFooterV2CredentialsType {
+    type Error = TbfParseError;
+
+    fn try_from(f: u32) -> Result<TbfFooterV2CredentialsType, Self::Error> {
+        match f {
+            0 => Ok(TbfFooterV2CredentialsType::Reserved),
+            1 => Ok(TbfFooterV2CredentialsType::Rsa3072Key),
+            2 => Ok(TbfFooterV2CredentialsType::Rsa4096Key),
+            3 => Ok(TbfFooterV2CredentialsType::SHA256),
+            4 => Ok(TbfFooterV2CredentialsType::SHA384),
+            5 => Ok(TbfFooterV2CredentialsType::SHA512),
+            6 => Ok(TbfFooterV2CredentialsType::EcdsaNistP256),
+            _ => Err(TbfParseError::BadTlvEntry(
+                TbfFooterTypes::TbfFooterCredentials as usize,
+            )),
+        }
+    }
+}
+
+/// A credentials entry from the footer region of a TBF object.
+///
+/// Credentials (hashes or signatures) cover the TBF object from its start up
+/// to the end of the binary, i.e. the header and the application binary but
+/// not the footers themselves.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfFooterV2Credentials {
+    format: TbfFooterV2CredentialsType,
+    data: &'static [u8],
+}
+
+impl TbfFooterV2Credentials {
+    /// The format of the credential.
+    pub fn format(&self) -> TbfFooterV2CredentialsType {
+        self.format
+    }
+
+    /// The credential itself, without the format identifier.
+    pub fn data(&self) -> &'static [u8] {
+        self.data
+    }
+}
+
+/// Parse a single entry from the footer region of a TBF object.
+///
+/// The `footers` slice must start at a footer TLV entry and extend at most to
+/// the end of the TBF object.
+///
+/// ## Return
+///
+/// On success, returns the credentials if the entry is a credentials footer
+/// (or `None` if it is a footer type or credentials format the kernel does not
+/// understand) and the total number of bytes the entry occupies, including its
+/// TLV header and padding. The caller can skip that many bytes to reach the
+/// next footer.
+pub(crate) fn parse_tbf_footer(
+    footers: &'static [u8],
+) -> Result<(Option<TbfFooterV2Credentials>, u32), TbfParseError> {
+    let tipe = u16::from_le_bytes(
+        footers
+            .get(0..2)
+            .ok_or(TbfParseError::NotEnoughFlash)?
+            .try_into()?,
+    );
+    let length = u16::from_le_bytes(
+        footers
+            .get(2..4)
+            .ok_or(TbfParseError::NotEnoughFlash)?
+            .try_into()?,
+    ) as usize;
+    let entry_len = 4 + align4!(length);
+    let value = footers
+        .get(4..4 + length)
+        .ok_or(TbfParseError::NotEnoughFlash)?;
+
+    match TbfFooterTypes::from(tipe) {
+        TbfFooterTypes::TbfFooterCredentials => {
+            let format = u32::from_le_bytes(
+                value
+                    .get(0..4)
+                    .ok_or(TbfParseError::BadTlvEntry(tipe as usize))?
+                    .try_into()?,
+            );
+            // Credentials in a format this kernel does not know, for example
+            // from a newer toolchain, are skipped like unknown footers.
+            let format: TbfFooterV2CredentialsType = match format.try_into() {
+                Ok(format) => format,
+                Err(_) => return Ok((None, entry_len as u32)),
+            };
+            let data = value.get(4..).ok_or(TbfParseError::InternalError)?;
+
+            // Formats with fixed-length credentials must match that length
+            // exactly, otherwise we may compare against garbage.
+            if let Some(expected) = format.data_length() {
+                if data.len() != expected {
+                    return Err(TbfParseError::BadTlvEntry(tipe as usize));
+                }
+            }
+
+            Ok((
+                Some(TbfFooterV2Credentials { format, data }),
+                entry_len as u32,
+            ))
+        }
+        TbfFooterTypes::Unknown => Ok((None, entry_len as u32)),
+    }
+}
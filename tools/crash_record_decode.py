@@ -0,0 +1,251 @@
+#!/usr/bin/env python3
+
+# Decodes the crash records the kernel keeps in flash.
+#
+# Usage: crash_record_decode.py [FILE ...]
+
+'''
+Decode the crash records of a `capsules::crash_log::CrashLog`.
+
+The kernel writes a `kernel::crash_record::CrashRecord` to flash when it
+panics, and the process console's `crash <n>` command prints a record as
+`crash:` lines holding it in hex. This script reads the output of the console
+(for example saved from `tockloader listen`) from the files given, or from
+stdin, ignores everything else, and prints each record:
+
+    Crash record 3: process blink faulted
+      Kernel:   1.6.0
+      Message:  panicked at 'Process blink had a fault', kernel/src/process.rs
+      Registers (Cortex-M):
+        r0   0x00000000  r1   0x20008000  ...
+
+With --binary, the files are raw dumps of the flash pages of the log instead,
+for example read with `nrfjprog --readcode`, and every record in them is
+printed.
+
+Usage: crash_record_decode.py [options] [FILE ...]
+Options:
+  -b, --binary  The files are raw flash dumps.
+  -h, --help    Print this message.
+'''
+
+import getopt
+import re
+import struct
+import sys
+import zlib
+
+# Layout of a record, see kernel/src/platform/crash_record.rs.
+MAGIC = b'TKCR'
+VERSION = 1
+RECORD_LEN = 256
+HEADER_FORMAT = '<4sBBBBIIIBBBx16s16s'
+HEADER_LEN = struct.calcsize(HEADER_FORMAT)
+
+KINDS = {1: 'kernel panic', 2: 'process fault'}
+
+REGISTER_NAMES = {
+    1: ('Cortex-M', ['r0', 'r1', 'r2', 'r3', 'r4', 'r5', 'r6', 'r7', 'r8',
+                     'r9', 'r10', 'r11', 'r12', 'sp', 'lr', 'pc', 'xpsr',
+                     'ypc', 'cfsr', 'hfsr', 'mmfar', 'bfar']),
+    2: ('RISC-V', ['ra', 'sp', 'gp', 'tp', 't0', 't1', 't2', 's0', 's1',
+                   'a0', 'a1', 'a2', 'a3', 'a4', 'a5', 'a6', 'a7', 's2',
+                   's3', 's4', 's5', 's6', 's7', 's8', 's9', 's10', 's11',
+                   't3', 't4', 't5', 't6', 'pc', 'mcause', 'mtval']),
+}
+
+# The Configurable Fault Status Register bits of Cortex-M.
+CFSR_BITS = {
+    0: 'IACCVIOL: instruction access violation',
+    1: 'DACCVIOL: data access violation',
+    3: 'MUNSTKERR: memory fault on unstacking',
+    4: 'MSTKERR: memory fault on stacking',
+    5: 'MLSPERR: memory fault on floating point lazy state preservation',
+    7: 'MMARVALID: MMFAR holds the faulting address',
+    8: 'IBUSERR: instruction bus error',
+    9: 'PRECISERR: precise data bus error',
+    10: 'IMPRECISERR: imprecise data bus error',
+    11: 'UNSTKERR: bus fault on unstacking',
+    12: 'STKERR: bus fault on stacking',
+    13: 'LSPERR: bus fault on floating point lazy state preservation',
+    15: 'BFARVALID: BFAR holds the faulting address',
+    16: 'UNDEFINSTR: undefined instruction',
+    17: 'INVSTATE: invalid state',
+    18: 'INVPC: invalid PC load',
+    19: 'NOCP: no coprocessor',
+    24: 'UNALIGNED: unaligned access',
+    25: 'DIVBYZERO: divide by zero',
+}
+
+# The RISC-V exception codes of mcause.
+MCAUSE_EXCEPTIONS = {
+    0: 'instruction address misaligned',
+    1: 'instruction access fault',
+    2: 'illegal instruction',
+    3: 'breakpoint',
+    4: 'load address misaligned',
+    5: 'load access fault',
+    6: 'store/AMO address misaligned',
+    7: 'store/AMO access fault',
+    8: 'environment call from U-mode',
+    11: 'environment call from M-mode',
+    12: 'instruction page fault',
+    13: 'load page fault',
+    15: 'store/AMO page fault',
+}
+
+
+def usage(message=None):
+    '''Print the usage message, with an error if there is one, and exit.'''
+    if message:
+        print('Error: ' + message)
+    print(__doc__)
+    sys.exit(1 if message else 0)
+
+
+def text(data):
+    '''Decode a zero-padded string field.'''
+    return data.split(b'\0', 1)[0].decode('utf-8', 'replace')
+
+
+def fault_reasons(architecture, registers):
+    '''Explain the fault registers of a record.'''
+    values = dict(zip(REGISTER_NAMES[architecture][1], registers))
+    reasons = []
+    if architecture == 1 and 'cfsr' in values:
+        cfsr = values['cfsr']
+        for bit, reason in sorted(CFSR_BITS.items()):
+            if cfsr & (1 << bit):
+                reasons.append(reason)
+        if cfsr & (1 << 7) and 'mmfar' in values:
+            reasons.append('memory fault address {:#010x}'.format(values['mmfar']))
+        if cfsr & (1 << 15) and 'bfar' in values:
+            reasons.append('bus fault address {:#010x}'.format(values['bfar']))
+        if values.get('hfsr', 0) & (1 << 30):
+            reasons.append('FORCED: escalated to a hard fault')
+    elif architecture == 2 and 'mcause' in values:
+        mcause = values['mcause']
+        if mcause & 0x80000000:
+            reasons.append('interrupt {}'.format(mcause & 0x7fffffff))
+        else:
+            reasons.append(MCAUSE_EXCEPTIONS.get(
+                mcause, 'exception {}'.format(mcause)))
+        reasons.append('mtval {:#010x}'.format(values.get('mtval', 0)))
+    return reasons
+
+
+def decode_record(record):
+    '''Format a record, or return None if it is not a valid record.'''
+    if len(record) < RECORD_LEN:
+        return None
+    record = record[:RECORD_LEN]
+    (magic, version, kind, architecture, count, crc, sequence, sp, name_len,
+     message_len, stack_len, kernel_version, name) = struct.unpack(
+         HEADER_FORMAT, record[:HEADER_LEN])
+    if magic != MAGIC or version != VERSION:
+        return None
+    if zlib.crc32(record[12:]) & 0xffffffff != crc:
+        return None
+    if 4 * count + stack_len + message_len > RECORD_LEN - HEADER_LEN:
+        return None
+
+    offset = HEADER_LEN
+    registers = struct.unpack_from('<{}I'.format(count), record, offset)
+    offset += 4 * count
+    stack = record[offset:offset + stack_len]
+    offset += stack_len
+    message = record[offset:offset + message_len].decode('utf-8', 'replace')
+
+    name = name[:name_len].decode('utf-8', 'replace')
+    if kind == 2:
+        title = 'process {} faulted'.format(name)
+    else:
+        title = KINDS.get(kind, 'unknown kind {}'.format(kind))
+    lines = ['Crash record {}: {}'.format(sequence, title),
+             '  Kernel:   {}'.format(text(kernel_version)),
+             '  Message:  {}'.format(message)]
+
+    if registers:
+        arch_name, names = REGISTER_NAMES.get(architecture, ('unknown', []))
+        lines.append('  Registers ({}):'.format(arch_name))
+        cells = []
+        for i, value in enumerate(registers):
+            register = names[i] if i < len(names) else 'x{}'.format(i)
+            cells.append('{:6} {:#010x}'.format(register, value))
+        for i in range(0, len(cells), 4):
+            lines.append('    ' + '  '.join(cells[i:i + 4]))
+        if architecture in REGISTER_NAMES:
+            for reason in fault_reasons(architecture, registers):
+                lines.append('  Fault:    {}'.format(reason))
+
+    if stack:
+        lines.append('  Stack at {:#010x}:'.format(sp))
+        for i in range(0, len(stack), 16):
+            words = struct.unpack_from('<{}I'.format(min(4, (len(stack) - i) // 4)),
+                                       stack, i)
+            lines.append('    {:#010x}: {}'.format(
+                sp + i, ' '.join('{:08x}'.format(word) for word in words)))
+    return '\n'.join(lines)
+
+
+def decode_console(lines):
+    '''Decode the records printed by the process console.'''
+    data = b''
+    for line in lines:
+        match = re.search(r'crash: ([0-9a-fA-F]+)', line)
+        if not match:
+            continue
+        data += bytes.fromhex(match.group(1))
+        if len(data) >= RECORD_LEN:
+            decoded = decode_record(data[:RECORD_LEN])
+            print(decoded if decoded else '--- malformed record ---')
+            data = data[RECORD_LEN:]
+    if data:
+        print('--- incomplete record ---')
+
+
+def decode_binary(data):
+    '''Decode all records in a flash dump.'''
+    found = False
+    offset = data.find(MAGIC)
+    while offset >= 0:
+        decoded = decode_record(data[offset:offset + RECORD_LEN])
+        if decoded:
+            print(decoded)
+            found = True
+        offset = data.find(MAGIC, offset + 1)
+    if not found:
+        print('--- no crash records ---')
+
+
+def main():
+    '''Parse the arguments and decode the input.'''
+    try:
+        opts, args = getopt.getopt(sys.argv[1:], 'bh', ['binary', 'help'])
+    except getopt.GetoptError as err:
+        usage(str(err))
+
+    binary = False
+    for opt, _ in opts:
+        if opt in ('-b', '--binary'):
+            binary = True
+        elif opt in ('-h', '--help'):
+            usage()
+
+    if binary:
+        if args:
+            for path in args:
+                with open(path, 'rb') as dump:
+                    decode_binary(dump.read())
+        else:
+            decode_binary(sys.stdin.buffer.read())
+    elif args:
+        for path in args:
+            with open(path) as console:
+                decode_console(console)
+    else:
+        decode_console(sys.stdin)
+
+
+if __name__ == '__main__':
+    main()
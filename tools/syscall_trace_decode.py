@@ -0,0 +1,181 @@
+#!/usr/bin/env python3
+
+# Turns a syscall trace dumped by the process console into a timeline.
+#
+# Usage: syscall_trace_decode.py [FILE ...]
+
+'''
+Decode the syscall trace records printed by the process console's
+`trace dump` command.
+
+The kernel records syscalls in a `kernel::syscall_trace::SyscallTrace`, and
+`trace dump` prints them as `trace:` lines holding each record in hex. This
+script reads the output of the console (for example saved from
+`tockloader listen`) from the files given, or from stdin, ignores everything
+that is not part of a trace dump, and prints one line per syscall:
+
+        0.500000  app 2  command   Led(0x2) 1 0x0 0x0  -> SUCCESS
+
+Usage: syscall_trace_decode.py [options] [FILE ...]
+Options:
+  -d, --drivers=FILE   Take driver names from this driver.rs instead of
+                       capsules/src/driver.rs in this repository.
+  -h, --help           Print this message.
+'''
+
+import getopt
+import os
+import re
+import struct
+import sys
+
+# Layout of a record, see kernel/src/platform/syscall_trace.rs.
+RECORD_FORMAT = '<IIBxHIIIi'
+RECORD_LEN = struct.calcsize(RECORD_FORMAT)
+
+CLASSES = ['yield', 'subscribe', 'command', 'allow', 'memop']
+
+RETURN_CODES = {
+    0: 'SUCCESS',
+    -1: 'FAIL',
+    -2: 'EBUSY',
+    -3: 'EALREADY',
+    -4: 'EOFF',
+    -5: 'ERESERVE',
+    -6: 'EINVAL',
+    -7: 'ESIZE',
+    -8: 'ECANCEL',
+    -9: 'ENOMEM',
+    -10: 'ENOSUPPORT',
+    -11: 'ENODEVICE',
+    -12: 'EUNINSTALLED',
+    -13: 'ENOACK',
+    -14: 'EPERM',
+}
+
+DEFAULT_DRIVERS = os.path.join(os.path.dirname(os.path.abspath(__file__)),
+                               '..', 'capsules', 'src', 'driver.rs')
+
+
+def usage(message=None):
+    '''Print the usage message, with an error if there is one, and exit.'''
+    if message:
+        print('Error: ' + message)
+    print(__doc__)
+    sys.exit(1 if message else 0)
+
+
+def read_driver_names(path):
+    '''Map driver numbers to the names given to them in driver.rs.'''
+    names = {}
+    try:
+        with open(path) as driver_file:
+            for line in driver_file:
+                match = re.match(r'\s*(\w+)\s*=\s*(0x[0-9a-fA-F]+|\d+)\s*,', line)
+                if match:
+                    names[int(match.group(2), 0)] = match.group(1)
+    except IOError:
+        pass
+    # The IPC driver is part of the kernel and not listed in driver.rs.
+    names.setdefault(0x10000, 'Ipc')
+    return names
+
+
+def driver_name(names, number):
+    '''Name a driver number, falling back to the number alone.'''
+    if number in names:
+        return '{}({:#x})'.format(names[number], number)
+    return '{:#x}'.format(number)
+
+
+def return_code_name(class_name, result):
+    '''Name the value a syscall returned.'''
+    if class_name == 'yield':
+        return ''
+    if result > 0:
+        return 'SuccessWithValue({})'.format(result)
+    return RETURN_CODES.get(result, str(result))
+
+
+def decode_record(record, frequency, names):
+    '''Format a single record as a line of the timeline.'''
+    (timestamp, app_id, class_number, subdriver, driver, arg0, arg1,
+     result) = struct.unpack(RECORD_FORMAT, record)
+
+    if class_number < len(CLASSES):
+        class_name = CLASSES[class_number]
+    else:
+        class_name = 'unknown({})'.format(class_number)
+
+    if class_name == 'yield':
+        arguments = ''
+    elif class_name == 'memop':
+        arguments = '{} {:#x}'.format(subdriver, arg0)
+    elif class_name == 'subscribe':
+        arguments = '{} {} @{:#x} {:#x}'.format(
+            driver_name(names, driver), subdriver, arg0, arg1)
+    elif class_name == 'allow':
+        arguments = '{} {} @{:#x} {}'.format(
+            driver_name(names, driver), subdriver, arg0, arg1)
+    else:
+        arguments = '{} {} {:#x} {:#x}'.format(
+            driver_name(names, driver), subdriver, arg0, arg1)
+
+    if frequency:
+        time = '{:12.6f}'.format(timestamp / frequency)
+    else:
+        time = '{:12}'.format(timestamp)
+
+    line = '{}  app {}  {:9} {}'.format(time, app_id, class_name, arguments)
+    code = return_code_name(class_name, result)
+    if code:
+        line += '  -> ' + code
+    return line.rstrip()
+
+
+def decode(lines, names):
+    '''Decode all trace dumps in the console output.'''
+    frequency = 0
+    for line in lines:
+        start = re.search(r'trace-start frequency=(\d+) dropped=(\d+)', line)
+        if start:
+            frequency = int(start.group(1))
+            dropped = int(start.group(2))
+            if dropped:
+                print('--- {} records dropped ---'.format(dropped))
+            continue
+
+        record = re.search(r'trace: ([0-9a-fA-F]+)', line)
+        if record:
+            data = bytes.fromhex(record.group(1))
+            if len(data) != RECORD_LEN:
+                print('--- malformed record: {} ---'.format(record.group(1)))
+                continue
+            print(decode_record(data, frequency, names))
+
+
+def main():
+    '''Parse the arguments and decode the input.'''
+    try:
+        opts, args = getopt.getopt(sys.argv[1:], 'd:h', ['drivers=', 'help'])
+    except getopt.GetoptError as err:
+        usage(str(err))
+
+    drivers = DEFAULT_DRIVERS
+    for opt, val in opts:
+        if opt in ('-d', '--drivers'):
+            drivers = val
+        elif opt in ('-h', '--help'):
+            usage()
+
+    names = read_driver_names(drivers)
+    if args:
+        for path in args:
+            with open(path) as trace_file:
+                decode(trace_file, names)
+    else:
+        decode(sys.stdin, names)
+
+
+if __name__ == '__main__':
+    main()